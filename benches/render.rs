@@ -0,0 +1,84 @@
+// Per-print rendering cost for a typical receipt and a long report, and
+// template cache lookups
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nexora_printer_manager::{
+    load_template, ReceiptData, ReceiptItem, TemplateCache, TemplateRenderer,
+};
+
+/// The full receipt fixture with its items repeated to 60 lines, on 80mm paper
+fn receipt_80mm(c: &mut Criterion) {
+    let template = load_template(include_str!("../testdata/full_receipt.template.json")).unwrap();
+    let mut data: ReceiptData =
+        serde_json::from_str(include_str!("../testdata/full_receipt.data.json")).unwrap();
+    let items: Vec<ReceiptItem> = data.items.iter().cycle().take(60).cloned().collect();
+    data.items = items;
+
+    let renderer = TemplateRenderer::new(template.paper_width.unwrap_or(48));
+    let compiled = renderer.compile(&template);
+
+    let mut group = c.benchmark_group("receipt_80mm");
+    group.bench_function("compile_and_render", |b| {
+        b.iter(|| renderer.render_template(black_box(&template), black_box(&data)))
+    });
+    group.bench_function("render_compiled", |b| {
+        b.iter(|| renderer.render_to_commands(black_box(&compiled), black_box(&data)))
+    });
+    group.finish();
+}
+
+/// The sales report fixture with 1000 rows in its table, chart and leaderboard
+fn report_1000_rows(c: &mut Criterion) {
+    let template =
+        load_template(include_str!("../testdata/sales_report.template.json")).unwrap();
+    let mut data: ReceiptData =
+        serde_json::from_str(include_str!("../testdata/sales_report.data.json")).unwrap();
+    let rows: Vec<serde_json::Value> = (0..1000)
+        .map(|i| {
+            serde_json::json!({
+                "name": format!("Product {}", i),
+                "qty": i % 40,
+                "amount": (i % 97) as f64 * 1.25,
+                "note": if i % 3 == 0 { "promo" } else { "" },
+                "sku": format!("SKU-{:04}", i),
+            })
+        })
+        .collect();
+    data.custom
+        .insert("sales".to_string(), serde_json::Value::Array(rows));
+
+    let renderer = TemplateRenderer::new(template.paper_width.unwrap_or(48));
+    let compiled = renderer.compile(&template);
+
+    c.bench_function("report_1000_rows", |b| {
+        b.iter(|| renderer.render_to_commands(black_box(&compiled), black_box(&data)))
+    });
+}
+
+/// Looking up a cached template carrying a ~200 KB inline base64 logo
+fn cached_logo_template(c: &mut Criterion) {
+    let logo = "iVBORw0KGgo".repeat(200_000 / 11);
+    let template = load_template(
+        &serde_json::json!({
+            "id": "logo", "name": "Logo", "version": "1.0",
+            "layout": { "sections": [{ "type": "header", "elements": [
+                { "type": "logo", "source": logo, "align": "center" }
+            ] }] }
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let mut cache = TemplateCache::default();
+    cache.insert(template, None);
+
+    let mut group = c.benchmark_group("cached_logo_template");
+    group.bench_function("get", |b| b.iter(|| cache.get(black_box("logo"))));
+    group.bench_function("deep_clone", |b| {
+        let template = cache.get("logo").unwrap();
+        b.iter(|| black_box(&template).as_ref().clone())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, receipt_80mm, report_1000_rows, cached_logo_template);
+criterion_main!(benches);
@@ -1,4 +1,11 @@
 fn main() {
+    // The UI and executable icon only matter for the desktop binary
+    #[cfg(feature = "gui")]
+    gui();
+}
+
+#[cfg(feature = "gui")]
+fn gui() {
     slint_build::compile("ui/main.slint").unwrap();
 
     // Add Windows icon to executable (Windows only)
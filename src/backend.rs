@@ -0,0 +1,2277 @@
+// src/backend.rs
+// Print backends: how encoded jobs reach the printer for each connection type
+
+use serde::Serialize;
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::{SerialSettings, Timeouts, WritePacing};
+use crate::encoder::encode_commands;
+use crate::ipp::IppBackend;
+use crate::pdf::PdfBackend;
+use crate::realtime_status::{self, StatusLink};
+use crate::serial;
+use crate::snmp;
+use crate::template_render::PrintCommand;
+#[cfg(feature = "usb")]
+use crate::usb::UsbBackend;
+
+/// One way of getting bytes to a printer.
+///
+/// `PrinterManager` and `BarcodePrinterManager` pick a backend in `connect`
+/// from the configured connection type (see [`connect`]) and every job is
+/// written through it, so a new connection type is one more implementation.
+pub trait PrintBackend: Debug + Send + Sync {
+    /// Open the printer for incremental writes, used by jobs that are
+    /// written while they render. `None` if this backend only takes whole
+    /// jobs (the Windows spooler, the console).
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String>;
+
+    /// Write a complete job. This performs blocking I/O.
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String>;
+
+    /// Write a job that isn't text, such as an image or a label. `kind`
+    /// names it for backends that describe jobs rather than print them.
+    fn write_binary(&self, bytes: &[u8], kind: &str) -> Result<(), String> {
+        let _ = kind;
+        self.write_all(bytes)
+    }
+
+    /// Lays jobs out itself from print commands instead of taking ESC/POS
+    /// (the PDF backend), so rendered jobs are handed to
+    /// [`write_commands`](Self::write_commands) unencoded
+    fn takes_commands(&self) -> bool {
+        false
+    }
+
+    /// Write a complete rendered job. Backends that don't take commands get
+    /// it encoded as ESC/POS.
+    fn write_commands(&self, commands: &[PrintCommand]) -> Result<(), String> {
+        self.write_all(&encode_commands(commands))
+    }
+
+    /// Check that the printer can be reached, without printing anything
+    fn query_status(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Ask the printer what state it is in. `None` if this backend has no
+    /// way to ask (IPP, or SNMP for network printers) or the printer didn't
+    /// answer. This performs blocking I/O.
+    fn printer_state(&self) -> Result<Option<PrinterState>, String> {
+        Ok(None)
+    }
+
+    /// Release the printer on disconnect
+    fn close(&self) {}
+}
+
+/// What a printer reports about itself, over IPP or SNMP
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PrinterState {
+    /// "idle", "processing", "stopped" or "unknown"
+    pub state: String,
+    /// Why, as IPP keywords, e.g. "media-empty"
+    pub reasons: Vec<String>,
+    pub message: Option<String>,
+    pub model: Option<String>,
+    pub serial_number: Option<String>,
+    /// "ok", "low" or "empty", if the printer reports it
+    pub paper: Option<String>,
+}
+
+/// Pick the backend for a configured connection type and device path and
+/// check that the printer can be reached, so a wrong port or address fails
+/// here rather than on the first job. `serial` applies when the device turns
+/// out to be a serial port, `timeouts` to every backend that can hang, and
+/// `document` names jobs in the Windows spooler queue.
+pub fn connect(
+    connection_type: &str,
+    device_path: &str,
+    serial: &SerialSettings,
+    timeouts: &Timeouts,
+    document: &'static str,
+) -> Result<Arc<dyn PrintBackend>, String> {
+    let backend = backend_for(connection_type, device_path, serial, timeouts, document)?;
+    backend.query_status()?;
+    Ok(backend)
+}
+
+/// The backend [`connect`] would use, without touching the printer
+pub fn backend_for(
+    connection_type: &str,
+    device_path: &str,
+    serial: &SerialSettings,
+    timeouts: &Timeouts,
+    document: &'static str,
+) -> Result<Arc<dyn PrintBackend>, String> {
+    timeouts.validate()?;
+    match connection_type {
+        "USB" => {
+            // A printer-class device by vendor and product id (usb:04b8:0e15)
+            if device_path.starts_with("usb:") {
+                #[cfg(feature = "usb")]
+                return Ok(Arc::new(
+                    UsbBackend::new(device_path)?.with_timeouts(*timeouts),
+                ));
+                #[cfg(not(feature = "usb"))]
+                return Err("Direct USB printing needs the usb feature".to_string());
+            }
+            if is_shared_printer(device_path) {
+                return shared_printer(device_path);
+            }
+            // Check if this looks like a port (COM3, \\.\USB001, /dev/usb/lp0)
+            // or a printer name
+            if device_path.starts_with(r"\\.\")
+                || device_path.starts_with("COM")
+                || device_path.starts_with('/')
+            {
+                serial.validate()?;
+                Ok(usb_port(device_path, serial, timeouts, document))
+            } else {
+                // It's likely a Windows printer name (e.g. "POS-80")
+                Ok(Arc::new(SpoolerBackend::new(device_path, document)))
+            }
+        }
+        "Network" => Ok(Arc::new(TcpBackend {
+            // The raw printing port unless the path has one
+            addr: with_default_port(device_path, 9100),
+            timeouts: *timeouts,
+        })),
+        "Serial over TCP" => {
+            serial.validate()?;
+            Ok(Arc::new(SerialTcpBackend::new(
+                device_path,
+                serial,
+                timeouts,
+            )))
+        }
+        "LPT" => {
+            #[cfg(target_os = "windows")]
+            {
+                Ok(Arc::new(ParallelBackend::new(device_path, timeouts)?))
+            }
+            #[cfg(not(target_os = "windows"))]
+            {
+                Err("LPT ports are only supported on Windows.".to_string())
+            }
+        }
+        "Windows Printer" => {
+            if is_shared_printer(device_path) {
+                return shared_printer(device_path);
+            }
+            #[cfg(target_os = "windows")]
+            {
+                Ok(Arc::new(SpoolerBackend::new(device_path, document)))
+            }
+            #[cfg(not(target_os = "windows"))]
+            {
+                Err("Windows printers are only supported on Windows.".to_string())
+            }
+        }
+        "IPP" => Ok(Arc::new(
+            IppBackend::new(device_path, document)?.with_timeouts(*timeouts),
+        )),
+        "Console" => Ok(Arc::new(ConsoleBackend)),
+        "File" => Ok(Arc::new(FileBackend::new(device_path)?)),
+        "PDF" => Ok(Arc::new(PdfBackend::new(device_path)?)),
+        _ => Err(format!("Unsupported connection type: {}", connection_type)),
+    }
+}
+
+/// `host:port` for `address`, adding `port` when it has none. A bare IPv6
+/// address is bracketed first, so `fe80::1` becomes `[fe80::1]:9100`.
+pub(crate) fn with_default_port(address: &str, port: u16) -> String {
+    if address.starts_with('[') {
+        // [fe80::1] or [fe80::1]:9100
+        return match address.ends_with(']') {
+            true => format!("{}:{}", address, port),
+            false => address.to_string(),
+        };
+    }
+    match address.matches(':').count() {
+        0 => format!("{}:{}", address, port),
+        1 => address.to_string(),
+        _ => format!("[{}]:{}", address, port),
+    }
+}
+
+/// Why an open, connect or write failed, in the words a user would look
+/// for: "port busy", "host unreachable", "timed out", ...
+pub(crate) fn io_error_reason(error: &std::io::Error) -> String {
+    use std::io::ErrorKind;
+
+    #[cfg(unix)]
+    match error.raw_os_error() {
+        Some(libc::EBUSY) => return "port busy".to_string(),
+        Some(libc::EHOSTUNREACH | libc::ENETUNREACH) => return "host unreachable".to_string(),
+        Some(libc::ENXIO | libc::ENODEV) => return "no such device".to_string(),
+        _ => {}
+    }
+    #[cfg(target_os = "windows")]
+    match error.raw_os_error() {
+        // ERROR_SHARING_VIOLATION
+        Some(32) => return "port busy".to_string(),
+        // ERROR_ACCESS_DENIED, which is what a COM port open elsewhere gives
+        Some(5) => return "port busy or access denied".to_string(),
+        // WSAENETUNREACH, WSAEHOSTUNREACH
+        Some(10051 | 10065) => return "host unreachable".to_string(),
+        // ERROR_BAD_NETPATH
+        Some(53) => return "computer not found".to_string(),
+        // ERROR_BAD_NET_NAME
+        Some(67) => return "share not found".to_string(),
+        _ => {}
+    }
+    match error.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => "timed out".to_string(),
+        ErrorKind::ConnectionRefused => "connection refused".to_string(),
+        ErrorKind::NotFound => "not found".to_string(),
+        ErrorKind::PermissionDenied => "permission denied".to_string(),
+        _ => error.to_string(),
+    }
+}
+
+/// A port path opens directly; if it can't be opened it may actually be a
+/// system printer name
+fn usb_port(
+    path: &str,
+    serial: &SerialSettings,
+    timeouts: &Timeouts,
+    document: &'static str,
+) -> Arc<dyn PrintBackend> {
+    #[cfg(target_os = "windows")]
+    {
+        let mut wide: Vec<u16> = path.encode_utf16().collect();
+        wide.push(0);
+
+        const GENERIC_WRITE: u32 = 0x40000000;
+        use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+        use windows_sys::Win32::Storage::FileSystem::{
+            CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+        };
+
+        let handle = unsafe {
+            CreateFileW(
+                wide.as_ptr(),
+                GENERIC_WRITE,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                std::ptr::null(),
+                OPEN_EXISTING,
+                FILE_ATTRIBUTE_NORMAL,
+                std::ptr::null_mut(),
+            )
+        };
+
+        if handle == INVALID_HANDLE_VALUE {
+            return Arc::new(SpoolerBackend::new(path, document));
+        }
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(handle) };
+    }
+    #[cfg(not(target_os = "windows"))]
+    let _ = document;
+
+    Arc::new(DeviceBackend {
+        path: path.to_string(),
+        serial: serial.clone(),
+        timeouts: *timeouts,
+    })
+}
+
+// ==================== Paced Writes ====================
+
+/// Wrap `backend` so jobs go out `pacing.chunk_size` bytes at a time with a
+/// pause after each, for printers with a small buffer
+pub fn paced(backend: Arc<dyn PrintBackend>, pacing: WritePacing) -> Arc<dyn PrintBackend> {
+    Arc::new(PacedBackend {
+        inner: backend,
+        pacing,
+    })
+}
+
+/// A backend whose writes are paced (see [`paced`]). Backends that only take
+/// whole jobs, such as the Windows spooler, are written to unpaced.
+#[derive(Debug)]
+pub struct PacedBackend {
+    inner: Arc<dyn PrintBackend>,
+    pacing: WritePacing,
+}
+
+impl PacedBackend {
+    fn write_paced(
+        &self,
+        bytes: &[u8],
+        whole_job: impl FnOnce() -> Result<(), String>,
+    ) -> Result<(), String> {
+        let Some(mut writer) = self.open()? else {
+            return whole_job();
+        };
+        writer
+            .write_all(bytes)
+            .and_then(|()| writer.flush())
+            .map_err(|e| format!("Failed to write to printer: {}", io_error_reason(&e)))
+    }
+}
+
+impl PrintBackend for PacedBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        Ok(self
+            .inner
+            .open()?
+            .map(|writer| Box::new(PacedWriter::new(writer, self.pacing)) as Box<dyn Write + Send>))
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        self.write_paced(bytes, || self.inner.write_all(bytes))
+    }
+
+    fn write_binary(&self, bytes: &[u8], kind: &str) -> Result<(), String> {
+        self.write_paced(bytes, || self.inner.write_binary(bytes, kind))
+    }
+
+    fn takes_commands(&self) -> bool {
+        self.inner.takes_commands()
+    }
+
+    fn write_commands(&self, commands: &[PrintCommand]) -> Result<(), String> {
+        if self.inner.takes_commands() {
+            self.inner.write_commands(commands)
+        } else {
+            self.write_all(&encode_commands(commands))
+        }
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        self.inner.query_status()
+    }
+
+    fn printer_state(&self) -> Result<Option<PrinterState>, String> {
+        self.inner.printer_state()
+    }
+
+    fn close(&self) {
+        self.inner.close();
+    }
+}
+
+/// Passes at most `chunk_size` bytes on at a time, then flushes and waits
+/// before the next chunk
+struct PacedWriter<W: Write> {
+    inner: W,
+    pacing: WritePacing,
+    /// Bytes written since the last pause
+    sent: usize,
+}
+
+impl<W: Write> PacedWriter<W> {
+    fn new(inner: W, pacing: WritePacing) -> Self {
+        Self {
+            inner,
+            pacing,
+            sent: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for PacedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let chunk_size = self.pacing.chunk_size.max(1);
+        if self.sent >= chunk_size {
+            self.inner.flush()?;
+            std::thread::sleep(self.pacing.delay());
+            self.sent = 0;
+        }
+        let end = buf.len().min(chunk_size - self.sent);
+        let n = self.inner.write(&buf[..end])?;
+        self.sent += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// ==================== Console ====================
+
+/// Prints jobs to stdout, for trying things out without a printer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsoleBackend;
+
+impl PrintBackend for ConsoleBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        Ok(None)
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        if let Ok(s) = std::str::from_utf8(bytes) {
+            println!("{}", s);
+        }
+        Ok(())
+    }
+
+    fn write_binary(&self, bytes: &[u8], kind: &str) -> Result<(), String> {
+        println!("[{} data: {} bytes]", kind, bytes.len());
+        Ok(())
+    }
+}
+
+// ==================== File ====================
+
+/// A folder that jobs are saved into, each to a new file
+#[derive(Debug)]
+pub struct JobFolder {
+    pub dir: PathBuf,
+    /// Numbers the files, so jobs saved in the same millisecond don't clash
+    jobs: AtomicUsize,
+}
+
+impl JobFolder {
+    /// `output` names the connection type in the error for an empty path
+    pub(crate) fn new(dir: &str, output: &str) -> Result<Self, String> {
+        if dir.trim().is_empty() {
+            return Err(format!("{} output needs a folder to save jobs in", output));
+        }
+        Ok(Self {
+            dir: PathBuf::from(dir.trim()),
+            jobs: AtomicUsize::new(0),
+        })
+    }
+
+    /// Create the file for the next job, named after when it was printed,
+    /// e.g. `job-20240131-094512-042-1.bin`
+    pub(crate) fn create(
+        &self,
+        prefix: &str,
+        extension: &str,
+    ) -> Result<(std::fs::File, PathBuf), String> {
+        let job = self.jobs.fetch_add(1, Ordering::Relaxed) + 1;
+        let name = format!(
+            "{}-{}-{}.{}",
+            prefix,
+            chrono::Local::now().format("%Y%m%d-%H%M%S-%3f"),
+            job,
+            extension
+        );
+        let path = self.dir.join(name);
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| format!("Cannot create {}: {}", path.display(), io_error_reason(&e)))?;
+        Ok((file, path))
+    }
+
+    /// Write one job to a new file, returning its path
+    pub(crate) fn save(
+        &self,
+        prefix: &str,
+        extension: &str,
+        bytes: &[u8],
+    ) -> Result<PathBuf, String> {
+        let (mut file, path) = self.create(prefix, extension)?;
+        file.write_all(bytes)
+            .and_then(|()| file.flush())
+            .map_err(|e| {
+                format!(
+                    "Failed to write to {}: {}",
+                    path.display(),
+                    io_error_reason(&e)
+                )
+            })?;
+        Ok(path)
+    }
+
+    /// Make sure the folder exists
+    pub(crate) fn check(&self) -> Result<(), String> {
+        std::fs::create_dir_all(&self.dir).map_err(|e| {
+            format!(
+                "Cannot create folder {}: {}",
+                self.dir.display(),
+                io_error_reason(&e)
+            )
+        })
+    }
+}
+
+/// Saves the exact bytes of every job to a new file in a folder, for
+/// checking what a printer would have been sent
+#[derive(Debug)]
+pub struct FileBackend {
+    pub folder: JobFolder,
+}
+
+impl FileBackend {
+    pub fn new(dir: &str) -> Result<Self, String> {
+        Ok(Self {
+            folder: JobFolder::new(dir, "File")?,
+        })
+    }
+}
+
+impl PrintBackend for FileBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        let (file, _) = self.folder.create("job", "bin")?;
+        Ok(Some(Box::new(file)))
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        let path = self.folder.save("job", "bin", bytes)?;
+        log::debug!("Saved {} byte job to {}", bytes.len(), path.display());
+        Ok(())
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        self.folder.check()
+    }
+}
+
+// ==================== Device ====================
+
+/// A USB/serial port or file written directly
+#[derive(Debug, Clone)]
+pub struct DeviceBackend {
+    pub path: String,
+    /// Applied each time the device is opened, if it is a serial port
+    pub serial: SerialSettings,
+    pub timeouts: Timeouts,
+}
+
+impl DeviceBackend {
+    #[cfg(not(target_os = "windows"))]
+    fn open_device(&self) -> Result<Box<dyn Write + Send>, String> {
+        // Never created: an unplugged printer or a mistyped path fails
+        // instead of jobs quietly going into a new file
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true);
+        #[cfg(unix)]
+        {
+            // Writes wait in poll() instead, so they can time out
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY);
+        }
+        let file = options
+            .open(&self.path)
+            .map_err(|e| format!("Cannot open {}: {}", self.path, io_error_reason(&e)))?;
+        serial::configure(&file, &self.serial)?;
+        // Held until the job is written, so status requests skip the device
+        #[cfg(unix)]
+        lock_device(&file, &self.path, self.timeouts.write())?;
+        #[cfg(unix)]
+        return Ok(Box::new(TimedWriter {
+            file,
+            timeout: self.timeouts.write(),
+            dsr_flow: self.serial.flow_control == "dsr",
+        }));
+        #[cfg(not(unix))]
+        Ok(Box::new(file))
+    }
+}
+
+/// Lock `file` for one job, waiting up to `timeout` for a job already
+/// writing to it, so a stuck job can't hold up every later one
+#[cfg(unix)]
+fn lock_device(file: &std::fs::File, path: &str, timeout: Duration) -> Result<(), String> {
+    use std::os::unix::io::AsRawFd;
+
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(());
+        }
+        let error = std::io::Error::last_os_error();
+        match error.raw_os_error() {
+            Some(libc::EWOULDBLOCK | libc::EINTR) => {}
+            _ => return Err(format!("Cannot lock {}: {}", path, io_error_reason(&error))),
+        }
+        if std::time::Instant::now() >= deadline {
+            return Err(format!("Cannot open {}: port busy", path));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+impl PrintBackend for DeviceBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        #[cfg(target_os = "windows")]
+        {
+            // Same as write_to_device_windows: read/write first, then write only
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&self.path)
+                .or_else(|_| std::fs::OpenOptions::new().write(true).open(&self.path))
+                .map_err(|e| format!("Cannot open {}: {}", self.path, io_error_reason(&e)))?;
+            serial::configure(&file, &self.serial)?;
+            {
+                use std::os::windows::io::AsRawHandle;
+                // Only COM ports take timeouts
+                let _ = serial::set_timeouts_handle(file.as_raw_handle() as _, &self.timeouts);
+            }
+            Ok(Some(Box::new(file)))
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            self.open_device().map(Some)
+        }
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        #[cfg(target_os = "windows")]
+        {
+            write_to_device_windows(&self.path, &self.serial, &self.timeouts, bytes)
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            let mut device = self.open_device()?;
+            device
+                .write_all(bytes)
+                .and_then(|()| device.flush())
+                .map_err(|e| format!("Failed to write to {}: {}", self.path, io_error_reason(&e)))
+        }
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        // Opening without creating or truncating leaves the device as it was
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true);
+        #[cfg(unix)]
+        {
+            // Don't wait for a modem carrier or adopt the port as a terminal
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY);
+        }
+        options
+            .open(&self.path)
+            .map(|_| ())
+            .map_err(|e| format!("Cannot open {}: {}", self.path, io_error_reason(&e)))
+    }
+
+    /// Real-time status (DLE EOT), skipped while a job is being written so a
+    /// request can't land in the middle of its data
+    fn printer_state(&self) -> Result<Option<PrinterState>, String> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+            use std::os::unix::io::AsRawFd;
+
+            // Requests written to a plain file would end up in it
+            let is_device = std::fs::metadata(&self.path)
+                .is_ok_and(|metadata| metadata.file_type().is_char_device());
+            if !is_device {
+                return Ok(None);
+            }
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+                .open(&self.path)
+                .map_err(|e| format!("Cannot open {}: {}", self.path, io_error_reason(&e)))?;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                return Ok(None);
+            }
+            serial::configure(&file, &self.serial)?;
+            let mut port = TimedWriter {
+                file,
+                timeout: self.timeouts.write(),
+                dsr_flow: false,
+            };
+            realtime_status::query(&mut port).map_err(|e| {
+                format!(
+                    "Failed to read status from {}: {}",
+                    self.path,
+                    io_error_reason(&e)
+                )
+            })
+        }
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::io::AsRawHandle;
+
+            // COM ports open exclusively, so this fails while a job prints
+            let Ok(mut file) = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&self.path)
+            else {
+                return Ok(None);
+            };
+            let timeouts = Timeouts {
+                read_ms: realtime_status::STATUS_TIMEOUT.as_millis() as u64,
+                ..self.timeouts
+            };
+            // Only COM ports take timeouts; USB printer ports can't be read
+            if serial::set_timeouts_handle(file.as_raw_handle() as _, &timeouts).is_err() {
+                return Ok(None);
+            }
+            serial::configure(&file, &self.serial)?;
+            realtime_status::query(&mut file).map_err(|e| {
+                format!(
+                    "Failed to read status from {}: {}",
+                    self.path,
+                    io_error_reason(&e)
+                )
+            })
+        }
+        #[cfg(not(any(unix, target_os = "windows")))]
+        Ok(None)
+    }
+}
+
+/// A device opened non-blocking, whose writes fail once it has taken
+/// nothing for `timeout`, e.g. a serial printer holding off with flow control
+#[cfg(unix)]
+struct TimedWriter {
+    file: std::fs::File,
+    timeout: Duration,
+    /// Write only while the printer holds DSR up. The kernel has no DTR/DSR
+    /// flow control, so it is done here.
+    dsr_flow: bool,
+}
+
+#[cfg(unix)]
+impl TimedWriter {
+    fn wait_for_dsr(&self) -> std::io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let deadline = std::time::Instant::now() + self.timeout;
+        loop {
+            let mut lines: libc::c_int = 0;
+            // A port without modem lines has nothing to wait for
+            if unsafe { libc::ioctl(self.file.as_raw_fd(), libc::TIOCMGET, &mut lines) } != 0
+                || lines & libc::TIOCM_DSR != 0
+            {
+                return Ok(());
+            }
+            if std::time::Instant::now() >= deadline {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "printer is not accepting data",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Write for TimedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use std::os::unix::io::AsRawFd;
+
+        if self.dsr_flow {
+            self.wait_for_dsr()?;
+        }
+        loop {
+            match self.file.write(buf) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            let mut ready = libc::pollfd {
+                fd: self.file.as_raw_fd(),
+                events: libc::POLLOUT,
+                revents: 0,
+            };
+            let timeout_ms = self.timeout.as_millis().min(i32::MAX as u128) as i32;
+            match unsafe { libc::poll(&mut ready, 1, timeout_ms) } {
+                0 => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "printer is not accepting data",
+                    ))
+                }
+                n if n < 0 => {
+                    let error = std::io::Error::last_os_error();
+                    if error.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(unix)]
+impl StatusLink for TimedWriter {
+    fn send(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.write_all(bytes)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> std::io::Result<usize> {
+        use std::os::unix::io::AsRawFd;
+
+        let mut ready = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        match unsafe { libc::poll(&mut ready, 1, timeout_ms) } {
+            0 => return Ok(0),
+            n if n < 0 => {
+                let error = std::io::Error::last_os_error();
+                return match error.kind() {
+                    std::io::ErrorKind::Interrupted => Ok(0),
+                    _ => Err(error),
+                };
+            }
+            _ => {}
+        }
+        match self.file.read(buf) {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(0),
+            result => result,
+        }
+    }
+}
+
+/// A COM port, whose reads give up after the port's read timeout
+#[cfg(target_os = "windows")]
+impl StatusLink for std::fs::File {
+    fn send(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.write_all(bytes)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], _timeout: Duration) -> std::io::Result<usize> {
+        self.read(buf)
+    }
+}
+
+// ==================== Parallel Port ====================
+
+/// `LPT1` → `\\.\LPT1`; a device path already in that form is kept
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn parallel_port_path(device_path: &str) -> Result<String, String> {
+    let name = device_path.strip_prefix(r"\\.\").unwrap_or(device_path);
+    let number = name
+        .get(..3)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("LPT"))
+        .and_then(|_| name[3..].parse::<u8>().ok())
+        .filter(|n| (1..=9).contains(n))
+        .ok_or_else(|| {
+            format!(
+                "Invalid parallel port: {} (expected LPT1-LPT9)",
+                device_path
+            )
+        })?;
+    Ok(format!(r"\\.\LPT{}", number))
+}
+
+/// A legacy parallel port printer, written through the port device.
+///
+/// The port stops taking data while the printer is offline or out of paper,
+/// so writes give up after the write timeout like network jobs do.
+#[cfg(target_os = "windows")]
+#[derive(Debug, Clone)]
+pub struct ParallelBackend {
+    /// Device path, e.g. `\\.\LPT1`
+    pub path: String,
+    pub timeouts: Timeouts,
+}
+
+#[cfg(target_os = "windows")]
+impl ParallelBackend {
+    pub fn new(device_path: &str, timeouts: &Timeouts) -> Result<Self, String> {
+        Ok(Self {
+            path: parallel_port_path(device_path)?,
+            timeouts: *timeouts,
+        })
+    }
+
+    fn open_port(&self) -> Result<ParallelPort, String> {
+        use windows_sys::Win32::Foundation::{GetLastError, INVALID_HANDLE_VALUE};
+        use windows_sys::Win32::Storage::FileSystem::{
+            CreateFileW, FILE_ATTRIBUTE_NORMAL, OPEN_EXISTING,
+        };
+
+        const GENERIC_WRITE: u32 = 0x40000000;
+
+        let mut wide: Vec<u16> = self.path.encode_utf16().collect();
+        wide.push(0);
+
+        // Parallel ports are write-only and can't be shared
+        let handle = unsafe {
+            CreateFileW(
+                wide.as_ptr(),
+                GENERIC_WRITE,
+                0,
+                std::ptr::null(),
+                OPEN_EXISTING,
+                FILE_ATTRIBUTE_NORMAL,
+                std::ptr::null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let err = unsafe { GetLastError() };
+            return Err(format!(
+                "Cannot open {}: Windows error code {}",
+                self.path, err
+            ));
+        }
+        let port = ParallelPort {
+            handle,
+            path: self.path.clone(),
+        };
+
+        if let Err(e) = serial::set_timeouts_handle(port.handle, &self.timeouts) {
+            // Not fatal: the write just can't time out
+            log::warn!("{} on {}", e, self.path);
+        }
+        Ok(port)
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl PrintBackend for ParallelBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        Ok(Some(Box::new(self.open_port()?)))
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        let mut port = self.open_port()?;
+        port
+            .write_all(bytes)
+            .and_then(|()| port.flush())
+            .map_err(|e| format!("Failed to write to {}: {}", self.path, e))
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        self.open_port().map(|_| ())
+    }
+}
+
+/// An open parallel port handle, closed on drop
+#[cfg(target_os = "windows")]
+struct ParallelPort {
+    handle: windows_sys::Win32::Foundation::HANDLE,
+    path: String,
+}
+
+// The handle is only used by whichever thread owns the port
+#[cfg(target_os = "windows")]
+unsafe impl Send for ParallelPort {}
+
+#[cfg(target_os = "windows")]
+impl Write for ParallelPort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use windows_sys::Win32::Storage::FileSystem::WriteFile;
+
+        let mut written: u32 = 0;
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let ok = unsafe {
+            WriteFile(
+                self.handle,
+                buf.as_ptr(),
+                len,
+                &mut written,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if written == 0 && len > 0 {
+            // The write timeout ran out with nothing taken
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("printer on {} is not accepting data", self.path),
+            ));
+        }
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl Drop for ParallelPort {
+    fn drop(&mut self) {
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(self.handle) };
+    }
+}
+
+// ==================== Shared Printer ====================
+
+/// `\\COMPUTER\Printer`, as opposed to a device path such as `\\.\USB001`
+fn is_shared_printer(device_path: &str) -> bool {
+    device_path.starts_with(r"\\")
+        && !device_path.starts_with(r"\\.\")
+        && !device_path.starts_with(r"\\?\")
+}
+
+/// Check a share path has a computer and a printer name, dropping any
+/// trailing backslash
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn shared_printer_path(device_path: &str) -> Result<String, String> {
+    let invalid = || {
+        format!(
+            r"Invalid shared printer: {} (expected \\COMPUTER\Printer)",
+            device_path
+        )
+    };
+    let (computer, printer) = device_path
+        .strip_prefix(r"\\")
+        .and_then(|rest| rest.trim_end_matches('\\').split_once('\\'))
+        .ok_or_else(invalid)?;
+    if computer.is_empty() || printer.is_empty() || printer.contains('\\') {
+        return Err(invalid());
+    }
+    Ok(format!(r"\\{}\{}", computer, printer))
+}
+
+fn shared_printer(device_path: &str) -> Result<Arc<dyn PrintBackend>, String> {
+    #[cfg(target_os = "windows")]
+    {
+        Ok(Arc::new(SharedPrinterBackend::new(device_path)?))
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        shared_printer_path(device_path)?;
+        Err("Shared printers are only supported on Windows.".to_string())
+    }
+}
+
+/// A printer shared from another Windows machine, written raw through the
+/// share the way `copy /b receipt.bin \\BACKOFFICE\EpsonTM` does. The
+/// sharing machine's spooler queues each job, so the job isn't held up by a
+/// printer that is out of paper.
+#[cfg(target_os = "windows")]
+#[derive(Debug, Clone)]
+pub struct SharedPrinterBackend {
+    /// Share path, e.g. `\\BACKOFFICE\EpsonTM`
+    pub path: String,
+}
+
+#[cfg(target_os = "windows")]
+impl SharedPrinterBackend {
+    pub fn new(device_path: &str) -> Result<Self, String> {
+        Ok(Self {
+            path: shared_printer_path(device_path)?,
+        })
+    }
+
+    /// Every handle opened on the share becomes one print job when closed
+    fn open_share(&self) -> Result<std::fs::File, String> {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .map_err(|e| format!("Cannot open {}: {}", self.path, io_error_reason(&e)))
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl PrintBackend for SharedPrinterBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        Ok(Some(Box::new(self.open_share()?)))
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        let mut share = self.open_share()?;
+        share
+            .write_all(bytes)
+            .and_then(|()| share.flush())
+            .map_err(|e| format!("Failed to write to {}: {}", self.path, io_error_reason(&e)))
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        // Opening the share would start an empty job; ask the spooler instead
+        query_system_printer_windows(&self.path)
+    }
+}
+
+// ==================== Network ====================
+
+/// A network printer taking raw jobs over TCP (usually port 9100)
+#[derive(Debug, Clone)]
+pub struct TcpBackend {
+    /// `host:port`
+    pub addr: String,
+    pub timeouts: Timeouts,
+}
+
+/// Connect to the first address `addr` (`host:port`) resolves to that
+/// answers, with reads and writes limited by `timeouts`
+pub(crate) fn connect_tcp(addr: &str, timeouts: &Timeouts) -> Result<TcpStream, String> {
+    let addrs = addr
+        .to_socket_addrs()
+        .map_err(|e| format!("Cannot resolve {}: {}", addr, e))?;
+
+    let mut last_error = None;
+    for resolved in addrs {
+        match TcpStream::connect_timeout(&resolved, timeouts.connect()) {
+            Ok(stream) => {
+                stream
+                    .set_write_timeout(Some(timeouts.write()))
+                    .and_then(|()| stream.set_read_timeout(Some(timeouts.read())))
+                    .map_err(|e| format!("Cannot configure {}: {}", addr, e))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) => format!("Cannot reach {}: {}", addr, io_error_reason(&e)),
+        None => format!("Cannot resolve {}", addr),
+    })
+}
+
+impl PrintBackend for TcpBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        Ok(Some(Box::new(connect_tcp(&self.addr, &self.timeouts)?)))
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        let mut stream = connect_tcp(&self.addr, &self.timeouts)?;
+        stream
+            .write_all(bytes)
+            .and_then(|()| stream.flush())
+            .map_err(|e| format!("Failed to write to {}: {}", self.addr, io_error_reason(&e)))
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        connect_tcp(&self.addr, &self.timeouts).map(|_| ())
+    }
+
+    /// Receipt printers answer DLE EOT on the raw port; other network
+    /// printers may run an SNMP agent. A printer busy with a job doesn't take
+    /// a second connection, so it answers over SNMP or not at all.
+    fn printer_state(&self) -> Result<Option<PrinterState>, String> {
+        if let Ok(mut stream) = connect_tcp(&self.addr, &self.timeouts) {
+            match realtime_status::query(&mut stream) {
+                Ok(Some(state)) => return Ok(Some(state)),
+                Ok(None) => {}
+                Err(e) => log::debug!("No real-time status from {}: {}", self.addr, e),
+            }
+        }
+        snmp::query_printer(&self.addr, snmp::SNMP_TIMEOUT)
+    }
+}
+
+// ==================== Serial over TCP ====================
+
+/// Moxa NPort's default port for raw TCP server mode
+const SERIAL_TCP_PORT: u16 = 4001;
+/// Bytes written to the device server at a time
+const SERIAL_TCP_CHUNK: usize = 256;
+/// How far ahead of the serial line writes may get. Device servers buffer
+/// a few KB per port; staying well inside that keeps them from dropping data.
+const SERIAL_TCP_AHEAD: usize = 1024;
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+/// A serial printer behind a device server (ser2net, Moxa NPort) in raw TCP
+/// mode.
+///
+/// Unlike a 9100 printer, the far end of the socket is a serial line: writes
+/// are paced to its baud rate and, with software flow control, stop while
+/// the printer has sent XOFF. The line settings themselves are configured on
+/// the device server; `serial` only has to match them.
+#[derive(Debug, Clone)]
+pub struct SerialTcpBackend {
+    /// `host:port`
+    pub addr: String,
+    pub serial: SerialSettings,
+    pub timeouts: Timeouts,
+}
+
+impl SerialTcpBackend {
+    pub fn new(device_path: &str, serial: &SerialSettings, timeouts: &Timeouts) -> Self {
+        Self {
+            addr: with_default_port(device_path, SERIAL_TCP_PORT),
+            serial: serial.clone(),
+            timeouts: *timeouts,
+        }
+    }
+
+    fn open_line(&self) -> Result<SerialTcpWriter, String> {
+        Ok(SerialTcpWriter::new(
+            connect_tcp(&self.addr, &self.timeouts)?,
+            &self.serial,
+            self.timeouts.write(),
+        ))
+    }
+}
+
+impl PrintBackend for SerialTcpBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        Ok(Some(Box::new(self.open_line()?)))
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        let mut line = self.open_line()?;
+        line.write_all(bytes)
+            .and_then(|()| line.flush())
+            .map_err(|e| format!("Failed to write to {}: {}", self.addr, io_error_reason(&e)))
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        connect_tcp(&self.addr, &self.timeouts).map(|_| ())
+    }
+
+    /// Real-time status (DLE EOT). Device servers take one connection per
+    /// port, so there's no answer while a job is printing.
+    fn printer_state(&self) -> Result<Option<PrinterState>, String> {
+        let mut stream = connect_tcp(&self.addr, &self.timeouts)?;
+        realtime_status::query(&mut stream).map_err(|e| {
+            format!(
+                "Failed to read status from {}: {}",
+                self.addr,
+                io_error_reason(&e)
+            )
+        })
+    }
+}
+
+/// Writes to a device server no faster than its serial line drains
+struct SerialTcpWriter {
+    stream: TcpStream,
+    /// Time one character takes on the line, start and stop bits included
+    char_time: Duration,
+    xon_xoff: bool,
+    /// The printer sent XOFF and hasn't sent XON yet
+    paused: bool,
+    /// When the line will have sent everything written so far
+    drained_at: std::time::Instant,
+    timeout: Duration,
+}
+
+impl SerialTcpWriter {
+    fn new(stream: TcpStream, serial: &SerialSettings, timeout: Duration) -> Self {
+        let bits = 1
+            + serial.data_bits as u32
+            + u32::from(serial.parity != "none")
+            + serial.stop_bits as u32;
+        Self {
+            stream,
+            char_time: Duration::from_secs_f64(bits as f64 / serial.baud_rate as f64),
+            xon_xoff: serial.flow_control == "software",
+            paused: false,
+            drained_at: std::time::Instant::now(),
+            timeout,
+        }
+    }
+
+    /// Take in XON/XOFF from the printer, waiting while it is paused
+    fn wait_for_xon(&mut self) -> std::io::Result<()> {
+        let mut incoming = [0u8; 64];
+        self.stream.set_nonblocking(true)?;
+        let read = self.stream.read(&mut incoming);
+        self.stream.set_nonblocking(false)?;
+        match read {
+            Ok(n) => self.take_flow_control(&incoming[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+
+        let deadline = std::time::Instant::now() + self.timeout;
+        while self.paused {
+            let left = deadline.saturating_duration_since(std::time::Instant::now());
+            if left.is_zero() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "printer is not accepting data",
+                ));
+            }
+            self.stream.set_read_timeout(Some(left))?;
+            match self.stream.read(&mut incoming) {
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.take_flow_control(&incoming[..n]),
+                Err(e) => match e.kind() {
+                    // The deadline check above reports running out of time
+                    std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::WouldBlock
+                    | std::io::ErrorKind::TimedOut => {}
+                    _ => return Err(e),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// The last of XON or XOFF wins; anything else the printer sends is
+    /// ignored
+    fn take_flow_control(&mut self, incoming: &[u8]) {
+        if let Some(&byte) = incoming.iter().rev().find(|&&b| b == XON || b == XOFF) {
+            self.paused = byte == XOFF;
+        }
+    }
+}
+
+impl Write for SerialTcpWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.xon_xoff {
+            self.wait_for_xon()?;
+        }
+        let ahead = self.char_time * SERIAL_TCP_AHEAD as u32;
+        if let Some(wait) = self
+            .drained_at
+            .checked_sub(ahead)
+            .and_then(|t| t.checked_duration_since(std::time::Instant::now()))
+        {
+            std::thread::sleep(wait);
+        }
+
+        let chunk = &buf[..buf.len().min(SERIAL_TCP_CHUNK)];
+        self.stream.write_all(chunk)?;
+        self.drained_at =
+            self.drained_at.max(std::time::Instant::now()) + self.char_time * chunk.len() as u32;
+        Ok(chunk.len())
+    }
+
+    /// Returns once the line has sent everything, so closing the connection
+    /// can't cut off the end of a job still in the device server's buffer
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()?;
+        if let Some(wait) = self
+            .drained_at
+            .checked_duration_since(std::time::Instant::now())
+        {
+            std::thread::sleep(wait);
+        }
+        Ok(())
+    }
+}
+
+// ==================== Windows Spooler ====================
+
+/// A printer installed in Windows, written through the spooler as a RAW job
+#[derive(Debug, Clone)]
+pub struct SpoolerBackend {
+    /// Printer name as shown in Devices and Printers (e.g. "POS-80")
+    pub name: String,
+    /// Document name shown in the print queue
+    pub document: &'static str,
+}
+
+impl SpoolerBackend {
+    pub fn new(name: &str, document: &'static str) -> Self {
+        Self {
+            name: name.to_string(),
+            document,
+        }
+    }
+}
+
+/// A printer installed in Windows, as listed by [`installed_printers`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledPrinter {
+    /// Name to connect to with the "Windows Printer" connection type
+    pub name: String,
+    /// Port it prints through, e.g. "USB001"
+    pub port: String,
+}
+
+/// Local and connected printers installed in Windows; empty elsewhere
+pub fn installed_printers() -> Result<Vec<InstalledPrinter>, String> {
+    #[cfg(target_os = "windows")]
+    {
+        installed_printers_windows()
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn installed_printers_windows() -> Result<Vec<InstalledPrinter>, String> {
+    use windows_sys::Win32::Graphics::Printing::{
+        EnumPrintersW, PRINTER_ENUM_CONNECTIONS, PRINTER_ENUM_LOCAL, PRINTER_INFO_5W,
+    };
+
+    let flags = PRINTER_ENUM_LOCAL | PRINTER_ENUM_CONNECTIONS;
+    let mut needed: u32 = 0;
+    let mut count: u32 = 0;
+    // The first call only reports how big the buffer has to be
+    unsafe {
+        EnumPrintersW(
+            flags,
+            std::ptr::null(),
+            5,
+            std::ptr::null_mut(),
+            0,
+            &mut needed,
+            &mut count,
+        )
+    };
+    if needed == 0 {
+        return Ok(Vec::new());
+    }
+
+    // u64s keep the buffer aligned for the pointers in PRINTER_INFO_5W
+    let mut buffer = vec![0u64; (needed as usize).div_ceil(8)];
+    let success = unsafe {
+        EnumPrintersW(
+            flags,
+            std::ptr::null(),
+            5,
+            buffer.as_mut_ptr() as *mut u8,
+            needed,
+            &mut needed,
+            &mut count,
+        )
+    };
+    if success == 0 {
+        return Err(format!(
+            "Failed to list installed printers: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    let infos = unsafe {
+        std::slice::from_raw_parts(buffer.as_ptr() as *const PRINTER_INFO_5W, count as usize)
+    };
+    Ok(infos
+        .iter()
+        .map(|info| InstalledPrinter {
+            name: unsafe { wide_to_string(info.pPrinterName) },
+            port: unsafe { wide_to_string(info.pPortName) },
+        })
+        .collect())
+}
+
+/// Read a NUL-terminated UTF-16 string; null reads as empty
+#[cfg(target_os = "windows")]
+unsafe fn wide_to_string(ptr: *const u16) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len))
+}
+
+impl PrintBackend for SpoolerBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        Ok(None)
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        #[cfg(target_os = "windows")]
+        {
+            write_to_system_printer_windows(&self.name, self.document, bytes)
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            let _ = bytes;
+            Err("System printer printing is only supported on Windows.".to_string())
+        }
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        #[cfg(target_os = "windows")]
+        {
+            query_system_printer_windows(&self.name)
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            Err("System printer printing is only supported on Windows.".to_string())
+        }
+    }
+}
+
+/// Check that the spooler knows the printer and doesn't have it offline
+#[cfg(target_os = "windows")]
+fn query_system_printer_windows(name: &str) -> Result<(), String> {
+    use windows_sys::Win32::Foundation::{GetLastError, ERROR_INVALID_PRINTER_NAME};
+    use windows_sys::Win32::Graphics::Printing::{
+        ClosePrinter, GetPrinterW, OpenPrinterW, PRINTER_HANDLE, PRINTER_INFO_6,
+        PRINTER_STATUS_OFFLINE,
+    };
+
+    let mut wide_name: Vec<u16> = name.encode_utf16().collect();
+    wide_name.push(0);
+
+    let mut h_printer: PRINTER_HANDLE = unsafe { std::mem::zeroed() };
+    if unsafe { OpenPrinterW(wide_name.as_ptr(), &mut h_printer, std::ptr::null()) } == 0 {
+        let err = unsafe { GetLastError() };
+        return Err(match err {
+            ERROR_INVALID_PRINTER_NAME if is_shared_printer(name) => {
+                format!("No printer is shared as {}", name)
+            }
+            ERROR_INVALID_PRINTER_NAME => format!("Printer '{}' is not installed", name),
+            _ => format!(
+                "Cannot open printer '{}': {}",
+                name,
+                io_error_reason(&std::io::Error::from_raw_os_error(err as i32))
+            ),
+        });
+    }
+
+    let mut info = PRINTER_INFO_6::default();
+    let mut needed: u32 = 0;
+    let success = unsafe {
+        GetPrinterW(
+            h_printer,
+            6,
+            &mut info as *mut PRINTER_INFO_6 as *mut u8,
+            std::mem::size_of::<PRINTER_INFO_6>() as u32,
+            &mut needed,
+        )
+    };
+    unsafe { ClosePrinter(h_printer) };
+
+    if success != 0 && info.dwStatus & PRINTER_STATUS_OFFLINE != 0 {
+        return Err(format!("Printer '{}' is offline", name));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn write_to_system_printer_windows(name: &str, document: &str, data: &[u8]) -> Result<(), String> {
+    use windows_sys::Win32::Graphics::Printing::{
+        ClosePrinter, EndDocPrinter, EndPagePrinter, OpenPrinterW, StartDocPrinterW,
+        StartPagePrinter, WritePrinter, DOC_INFO_1W, PRINTER_HANDLE,
+    };
+
+    let mut wide_name: Vec<u16> = name.encode_utf16().collect();
+    wide_name.push(0);
+
+    let mut h_printer: PRINTER_HANDLE = unsafe { std::mem::zeroed() };
+    let success = unsafe {
+        OpenPrinterW(
+            wide_name.as_ptr() as *mut u16,
+            &mut h_printer,
+            std::ptr::null_mut(),
+        )
+    };
+
+    if success == 0 {
+        return Err(format!("Could not open system printer '{}'. Please check the name in Devices and Printers.", name));
+    }
+
+    let doc_name = format!("{}\0", document).encode_utf16().collect::<Vec<u16>>();
+    let data_type = "RAW\0".encode_utf16().collect::<Vec<u16>>();
+
+    let doc_info = DOC_INFO_1W {
+        pDocName: doc_name.as_ptr() as *mut u16,
+        pOutputFile: std::ptr::null_mut(),
+        pDatatype: data_type.as_ptr() as *mut u16,
+    };
+
+    let job_id = unsafe { StartDocPrinterW(h_printer, 1, &doc_info as *const DOC_INFO_1W) };
+
+    if job_id == 0 {
+        let error = spooler_error(name, "start a print job");
+        unsafe { ClosePrinter(h_printer) };
+        return Err(error);
+    }
+
+    let mut result = Ok(());
+    if unsafe { StartPagePrinter(h_printer) } == 0 {
+        result = Err(spooler_error(name, "start a page"));
+    } else {
+        let mut written = 0;
+        let wrote = unsafe {
+            WritePrinter(
+                h_printer,
+                data.as_ptr() as *const _,
+                data.len() as u32,
+                &mut written,
+            )
+        };
+        if wrote == 0 {
+            result = Err(spooler_error(name, "write"));
+        } else if written as usize != data.len() {
+            result = Err(format!(
+                "Printer '{}' took {} of {} bytes",
+                name,
+                written,
+                data.len()
+            ));
+        }
+        if unsafe { EndPagePrinter(h_printer) } == 0 && result.is_ok() {
+            result = Err(spooler_error(name, "end the page"));
+        }
+    }
+
+    // The job is ended and the printer closed even when a step failed
+    if unsafe { EndDocPrinter(h_printer) } == 0 && result.is_ok() {
+        result = Err(spooler_error(name, "end the print job"));
+    }
+    unsafe { ClosePrinter(h_printer) };
+    result
+}
+
+/// The spooler's error for the step of printing to `name` that just failed
+#[cfg(target_os = "windows")]
+fn spooler_error(name: &str, step: &str) -> String {
+    let err = unsafe { windows_sys::Win32::Foundation::GetLastError() };
+    format!(
+        "Cannot {} on printer '{}': {}",
+        step,
+        name,
+        std::io::Error::from_raw_os_error(err as i32)
+    )
+}
+
+#[cfg(target_os = "windows")]
+fn write_to_device_windows(
+    path: &str,
+    serial: &SerialSettings,
+    timeouts: &Timeouts,
+    data: &[u8],
+) -> Result<(), String> {
+    use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+    use windows_sys::Win32::Storage::FileSystem::{
+        CreateFileW, WriteFile, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE,
+        OPEN_EXISTING,
+    };
+
+    // Standard generic access rights
+    const GENERIC_READ: u32 = 0x80000000;
+    const GENERIC_WRITE: u32 = 0x40000000;
+
+    let mut wide: Vec<u16> = path.encode_utf16().collect();
+    wide.push(0);
+
+    let mut handle = unsafe {
+        CreateFileW(
+            wide.as_ptr(),
+            GENERIC_READ | GENERIC_WRITE,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            FILE_ATTRIBUTE_NORMAL,
+            std::ptr::null_mut(),
+        )
+    };
+
+    if handle == INVALID_HANDLE_VALUE {
+        // Try just WRITE if BOTH fails
+        handle = unsafe {
+            CreateFileW(
+                wide.as_ptr(),
+                GENERIC_WRITE,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                std::ptr::null(),
+                OPEN_EXISTING,
+                FILE_ATTRIBUTE_NORMAL,
+                std::ptr::null_mut(),
+            )
+        };
+    }
+
+    if handle == INVALID_HANDLE_VALUE {
+        let err = unsafe { windows_sys::Win32::Foundation::GetLastError() };
+        return Err(format!("Cannot open {}: Windows error code {}", path, err));
+    }
+    if let Err(e) = serial::configure_handle(handle, serial) {
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(handle) };
+        return Err(e);
+    }
+    // Only COM ports take timeouts
+    let _ = serial::set_timeouts_handle(handle, timeouts);
+
+    let mut written: u32 = 0;
+    let success = unsafe {
+        WriteFile(
+            handle,
+            data.as_ptr(),
+            data.len() as u32,
+            &mut written,
+            std::ptr::null_mut(),
+        )
+    };
+
+    unsafe { windows_sys::Win32::Foundation::CloseHandle(handle) };
+
+    if success == 0 {
+        let err = unsafe { windows_sys::Win32::Foundation::GetLastError() };
+        return Err(format!(
+            "Failed to write to {}: Windows error code {}",
+            path, err
+        ));
+    }
+    if (written as usize) < data.len() {
+        // The write timeout ran out part way
+        return Err(format!("Failed to write to {}: timed out", path));
+    }
+
+    Ok(())
+}
+
+// ==================== Mock ====================
+
+/// A printer for tests: records everything written and fails on request.
+///
+/// Clones share their state, so a test can keep one and give the manager
+/// another.
+#[derive(Debug, Clone, Default)]
+pub struct MockBackend {
+    state: Arc<MockState>,
+    /// Sleep before each write, to simulate a slow printer
+    delay: Duration,
+    /// Take whole jobs only, like the spooler and the console
+    whole_jobs: bool,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    written: Mutex<Vec<u8>>,
+    writes: AtomicUsize,
+    jobs: AtomicUsize,
+    fail_writes: AtomicUsize,
+    offline: AtomicBool,
+    closed: AtomicBool,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Don't support incremental writes, so streamed jobs are buffered
+    pub fn whole_jobs(mut self) -> Self {
+        self.whole_jobs = true;
+        self
+    }
+
+    /// Make the next `count` writes fail
+    pub fn fail_next_writes(&self, count: usize) {
+        self.state.fail_writes.store(count, Ordering::SeqCst);
+    }
+
+    /// Take the printer offline: status checks and writes fail until it is
+    /// brought back
+    pub fn set_offline(&self, offline: bool) {
+        self.state.offline.store(offline, Ordering::SeqCst);
+    }
+
+    /// All bytes written so far
+    pub fn written(&self) -> Vec<u8> {
+        self.state.written.lock().unwrap().clone()
+    }
+
+    /// Forget what was written
+    pub fn clear(&self) {
+        self.state.written.lock().unwrap().clear();
+    }
+
+    /// Successful write calls, whole jobs and incremental writes alike
+    pub fn writes(&self) -> usize {
+        self.state.writes.load(Ordering::SeqCst)
+    }
+
+    /// Jobs started: whole-job writes plus opened streams
+    pub fn jobs(&self) -> usize {
+        self.state.jobs.load(Ordering::SeqCst)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.closed.load(Ordering::SeqCst)
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) -> Result<(), String> {
+        std::thread::sleep(self.delay);
+        if self.state.offline.load(Ordering::SeqCst) {
+            return Err("Mock printer is offline".to_string());
+        }
+        let failing = self
+            .state
+            .fail_writes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if failing {
+            return Err("Mock printer write failed".to_string());
+        }
+        self.state.written.lock().unwrap().extend_from_slice(bytes);
+        self.state.writes.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl PrintBackend for MockBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        if self.whole_jobs {
+            return Ok(None);
+        }
+        self.query_status()?;
+        self.state.jobs.fetch_add(1, Ordering::SeqCst);
+        Ok(Some(Box::new(MockWriter(self.clone()))))
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        self.state.jobs.fetch_add(1, Ordering::SeqCst);
+        self.write_bytes(bytes)
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        if self.state.offline.load(Ordering::SeqCst) {
+            Err("Mock printer is offline".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn close(&self) {
+        self.state.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+struct MockWriter(MockBackend);
+
+impl Write for MockWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .write_bytes(buf)
+            .map(|_| buf.len())
+            .map_err(std::io::Error::other)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_for_picks_backend_for_connection_type() {
+        let serial = SerialSettings::default();
+        let timeouts = Timeouts {
+            connect_ms: 1_000,
+            write_ms: 2_000,
+            read_ms: 3_000,
+        };
+        let timeouts_debug = "Timeouts { connect_ms: 1000, write_ms: 2000, read_ms: 3000 }";
+        let backend = backend_for("Network", "192.168.1.50", &serial, &timeouts, "Test").unwrap();
+        assert_eq!(
+            format!("{:?}", backend),
+            format!(
+                r#"TcpBackend {{ addr: "192.168.1.50:9100", timeouts: {} }}"#,
+                timeouts_debug
+            )
+        );
+
+        let backend = backend_for("USB", "POS-80", &serial, &timeouts, "Test").unwrap();
+        assert!(format!("{:?}", backend).starts_with("SpoolerBackend"));
+
+        #[cfg(not(target_os = "windows"))]
+        {
+            let backend = backend_for("USB", "/dev/usb/lp0", &serial, &timeouts, "Test").unwrap();
+            assert!(format!("{:?}", backend).starts_with(r#"DeviceBackend { path: "/dev/usb/lp0""#));
+        }
+
+        let backend = backend_for("USB", "usb:04b8:0e15", &serial, &timeouts, "Test");
+        #[cfg(feature = "usb")]
+        assert_eq!(
+            format!("{:?}", backend.unwrap()),
+            format!(
+                "UsbBackend {{ vendor_id: 1208, product_id: 3605, timeouts: {} }}",
+                timeouts_debug
+            )
+        );
+        #[cfg(not(feature = "usb"))]
+        assert_eq!(backend.unwrap_err(), "Direct USB printing needs the usb feature");
+
+        assert_eq!(format!("{:?}", backend_for("Console", "", &serial, &timeouts, "Test").unwrap()), "ConsoleBackend");
+
+        let backend = backend_for("File", "captures", &serial, &timeouts, "Test").unwrap();
+        assert!(format!("{:?}", backend).starts_with(r#"FileBackend { folder: JobFolder { dir: "captures""#));
+        assert_eq!(
+            backend_for("File", " ", &serial, &timeouts, "Test").unwrap_err(),
+            "File output needs a folder to save jobs in"
+        );
+        let backend = backend_for("PDF", "receipts", &serial, &timeouts, "Test").unwrap();
+        assert!(backend.takes_commands());
+
+        let backend = backend_for("LPT", "LPT1", &serial, &timeouts, "Test");
+        #[cfg(target_os = "windows")]
+        assert_eq!(
+            format!("{:?}", backend.unwrap()),
+            format!(
+                r#"ParallelBackend {{ path: "\\\\.\\LPT1", timeouts: {} }}"#,
+                timeouts_debug
+            )
+        );
+        #[cfg(not(target_os = "windows"))]
+        assert_eq!(backend.unwrap_err(), "LPT ports are only supported on Windows.");
+
+        let backend = backend_for("Windows Printer", "POS-80", &serial, &timeouts, "Test");
+        #[cfg(target_os = "windows")]
+        assert!(format!("{:?}", backend.unwrap()).starts_with("SpoolerBackend"));
+        #[cfg(not(target_os = "windows"))]
+        assert_eq!(
+            backend.unwrap_err(),
+            "Windows printers are only supported on Windows."
+        );
+
+        let backend = backend_for("IPP", "ipp://192.168.1.60", &serial, &timeouts, "Test").unwrap();
+        assert_eq!(
+            format!("{:?}", backend),
+            format!(
+                r#"IppBackend {{ addr: "192.168.1.60:631", path: "/ipp/print", document: "Test", timeouts: {} }}"#,
+                timeouts_debug
+            )
+        );
+
+        let backend =
+            backend_for("Serial over TCP", "10.0.0.7", &serial, &timeouts, "Test").unwrap();
+        assert!(format!("{:?}", backend).starts_with(r#"SerialTcpBackend { addr: "10.0.0.7:4001""#));
+
+        let bad_serial = SerialSettings {
+            baud_rate: 12345,
+            ..SerialSettings::default()
+        };
+        assert_eq!(
+            backend_for("USB", "COM3", &bad_serial, &timeouts, "Test").unwrap_err(),
+            "Unsupported baud rate: 12345"
+        );
+        assert_eq!(
+            backend_for("Serial over TCP", "", &bad_serial, &timeouts, "Test").unwrap_err(),
+            "Unsupported baud rate: 12345"
+        );
+
+        assert_eq!(
+            backend_for("Bluetooth", "", &serial, &timeouts, "Test").unwrap_err(),
+            "Unsupported connection type: Bluetooth"
+        );
+
+        let no_timeout = Timeouts {
+            write_ms: 0,
+            ..Timeouts::default()
+        };
+        assert_eq!(
+            backend_for("Console", "", &serial, &no_timeout, "Test").unwrap_err(),
+            "The write timeout must be more than 0 ms"
+        );
+    }
+
+    #[test]
+    fn test_default_port_is_added_to_ipv6_addresses_in_brackets() {
+        assert_eq!(with_default_port("192.168.1.50", 9100), "192.168.1.50:9100");
+        assert_eq!(
+            with_default_port("printer.local:9101", 9100),
+            "printer.local:9101"
+        );
+        assert_eq!(with_default_port("fe80::1", 9100), "[fe80::1]:9100");
+        assert_eq!(
+            with_default_port("fe80::1%eth0", 9100),
+            "[fe80::1%eth0]:9100"
+        );
+        assert_eq!(with_default_port("[fe80::1]", 9100), "[fe80::1]:9100");
+        assert_eq!(with_default_port("[fe80::1]:9101", 9100), "[fe80::1]:9101");
+
+        // Connects to the port it was given, IPv6 or not
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        connect_tcp(&with_default_port("127.0.0.1", port), &Timeouts::default()).unwrap();
+        if let Ok(listener) = std::net::TcpListener::bind("[::1]:0") {
+            let port = listener.local_addr().unwrap().port();
+            connect_tcp(&with_default_port("::1", port), &Timeouts::default()).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_missing_device_is_not_created() {
+        let path = std::env::temp_dir().join(format!("nexora-unplugged-{}", std::process::id()));
+        let printer = DeviceBackend {
+            path: path.to_string_lossy().into_owned(),
+            serial: SerialSettings::default(),
+            timeouts: Timeouts::default(),
+        };
+        assert_eq!(
+            printer.write_all(b"job").unwrap_err(),
+            format!("Cannot open {}: not found", printer.path)
+        );
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_device_in_use_fails_after_write_timeout() {
+        use std::os::unix::io::AsRawFd;
+
+        let path = std::env::temp_dir().join(format!("nexora-locked-{}", std::process::id()));
+        // Another job holding the device
+        let job = std::fs::File::create(&path).unwrap();
+        assert_eq!(unsafe { libc::flock(job.as_raw_fd(), libc::LOCK_EX) }, 0);
+        let printer = DeviceBackend {
+            path: path.to_string_lossy().into_owned(),
+            serial: SerialSettings::default(),
+            timeouts: Timeouts {
+                write_ms: 100,
+                ..Timeouts::default()
+            },
+        };
+
+        let started = std::time::Instant::now();
+        assert_eq!(
+            printer.write_all(b"job").unwrap_err(),
+            format!("Cannot open {}: port busy", printer.path)
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        drop(job);
+        printer.write_all(b"job").unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_device_write_times_out_when_printer_stops_taking_data() {
+        // Nothing reads the other end of the pty, so its buffer fills up
+        let (mut master, mut slave) = (0, 0);
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, 0, "openpty failed");
+        let path = unsafe { std::ffi::CStr::from_ptr(libc::ttyname(slave)) }
+            .to_string_lossy()
+            .into_owned();
+        let printer = DeviceBackend {
+            path: path.clone(),
+            serial: SerialSettings::default(),
+            timeouts: Timeouts {
+                write_ms: 100,
+                ..Timeouts::default()
+            },
+        };
+
+        let started = std::time::Instant::now();
+        let error = printer.write_all(&vec![b'x'; 1 << 20]).unwrap_err();
+        assert_eq!(error, format!("Failed to write to {}: timed out", path));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        unsafe {
+            libc::close(master);
+            libc::close(slave);
+        }
+    }
+
+    #[test]
+    fn test_connect_checks_printer_is_reachable() {
+        let serial = SerialSettings::default();
+        let timeouts = Timeouts::default();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        connect("Network", &addr, &serial, &timeouts, "Test").unwrap();
+
+        drop(listener);
+        assert_eq!(
+            connect("Network", &addr, &serial, &timeouts, "Test").unwrap_err(),
+            format!("Cannot reach {}: connection refused", addr)
+        );
+
+        #[cfg(unix)]
+        assert_eq!(
+            connect("USB", "/dev/nexora-missing", &serial, &timeouts, "Test").unwrap_err(),
+            "Cannot open /dev/nexora-missing: not found"
+        );
+        #[cfg(not(target_os = "windows"))]
+        assert_eq!(
+            connect("USB", "POS-80", &serial, &timeouts, "Test").unwrap_err(),
+            "System printer printing is only supported on Windows."
+        );
+    }
+
+    #[test]
+    fn test_serial_over_tcp_paces_writes_and_honours_xoff() {
+        let serial = SerialSettings {
+            baud_rate: 115200,
+            ..SerialSettings::default()
+        };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        // 8N1 at 115200 sends 11520 bytes a second, and the write returns
+        // once the line has drained: 4096 / 11520 = 355 ms
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            (received, listener)
+        });
+        let job: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+        let printer = SerialTcpBackend::new(&addr, &serial, &Timeouts::default());
+        let started = std::time::Instant::now();
+        printer.write_all(&job).unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(350), "{:?}", elapsed);
+        let (received, listener) = server.join().unwrap();
+        assert_eq!(received, job);
+
+        // A printer that sends XOFF and never XON times the job out
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&[XOFF]).unwrap();
+            let _ = stream.read_to_end(&mut Vec::new());
+        });
+        let xon_xoff = SerialSettings {
+            flow_control: "software".to_string(),
+            ..serial
+        };
+        let timeouts = Timeouts {
+            write_ms: 300,
+            ..Timeouts::default()
+        };
+        let printer = SerialTcpBackend::new(&addr, &xon_xoff, &timeouts);
+        assert_eq!(
+            printer.write_all(&job).unwrap_err(),
+            format!("Failed to write to {}: timed out", addr)
+        );
+        drop(printer);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_network_printer_answers_real_time_status() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Out of paper: offline, stopped by paper end, roll sensor empty
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 3];
+            for reply in [0x1A, 0x32, 0x12, 0x72] {
+                stream.read_exact(&mut request).unwrap();
+                assert_eq!(request[..2], [0x10, 0x04]);
+                stream.write_all(&[reply]).unwrap();
+            }
+        });
+
+        let printer = TcpBackend {
+            addr,
+            timeouts: Timeouts::default(),
+        };
+        let state = printer.printer_state().unwrap().unwrap();
+        server.join().unwrap();
+        assert_eq!(state.state, "stopped");
+        assert_eq!(state.reasons, ["offline", "media-empty"]);
+        assert_eq!(state.paper.as_deref(), Some("empty"));
+    }
+
+    #[test]
+    fn test_file_backend_saves_each_job() {
+        let dir = std::env::temp_dir().join(format!("nexora-file-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let printer = FileBackend::new(dir.to_str().unwrap()).unwrap();
+        printer.query_status().unwrap();
+
+        printer.write_all(b"\x1b@first\n").unwrap();
+        let mut writer = printer.open().unwrap().unwrap();
+        writer.write_all(b"\x1b@sec").unwrap();
+        writer.write_all(b"ond\n").unwrap();
+        drop(writer);
+
+        let mut jobs: Vec<(String, Vec<u8>)> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                (name, std::fs::read(&path).unwrap())
+            })
+            .collect();
+        jobs.sort_by_key(|(name, _)| name.rsplit('-').next().unwrap().to_string());
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(jobs.len(), 2);
+        assert!(jobs[0].0.starts_with("job-") && jobs[0].0.ends_with("-1.bin"));
+        assert_eq!(jobs[0].1, b"\x1b@first\n");
+        assert_eq!(jobs[1].1, b"\x1b@second\n");
+    }
+
+    #[test]
+    fn test_parallel_port_path() {
+        assert_eq!(parallel_port_path("LPT1").unwrap(), r"\\.\LPT1");
+        assert_eq!(parallel_port_path("lpt2").unwrap(), r"\\.\LPT2");
+        assert_eq!(parallel_port_path(r"\\.\LPT3").unwrap(), r"\\.\LPT3");
+        assert_eq!(
+            parallel_port_path("COM1").unwrap_err(),
+            "Invalid parallel port: COM1 (expected LPT1-LPT9)"
+        );
+        assert!(parallel_port_path("LPT0").is_err());
+        assert!(parallel_port_path("LPT").is_err());
+    }
+
+    #[test]
+    fn test_shared_printer_path() {
+        assert!(is_shared_printer(r"\\BACKOFFICE\EpsonTM"));
+        assert!(!is_shared_printer(r"\\.\USB001"));
+        assert!(!is_shared_printer("POS-80"));
+        assert_eq!(
+            shared_printer_path(r"\\BACKOFFICE\EpsonTM\").unwrap(),
+            r"\\BACKOFFICE\EpsonTM"
+        );
+        assert_eq!(
+            shared_printer_path(r"\\BACKOFFICE").unwrap_err(),
+            r"Invalid shared printer: \\BACKOFFICE (expected \\COMPUTER\Printer)"
+        );
+        assert!(shared_printer_path(r"\\BACKOFFICE\Printers\EpsonTM").is_err());
+        assert!(shared_printer_path(r"\\\EpsonTM").is_err());
+
+        #[cfg(not(target_os = "windows"))]
+        {
+            let serial = SerialSettings::default();
+            let timeouts = Timeouts::default();
+            let error = backend_for("USB", r"\\BACKOFFICE\EpsonTM", &serial, &timeouts, "Test")
+                .unwrap_err();
+            assert_eq!(error, "Shared printers are only supported on Windows.");
+        }
+    }
+
+    #[test]
+    fn test_mock_records_writes_and_injects_failures() {
+        let mock = MockBackend::new();
+        mock.write_all(b"one").unwrap();
+
+        mock.fail_next_writes(1);
+        assert!(mock.write_all(b"two").is_err());
+
+        let mut writer = mock.open().unwrap().unwrap();
+        writer.write_all(b"three").unwrap();
+        assert_eq!(mock.written(), b"onethree");
+        assert_eq!((mock.jobs(), mock.writes()), (3, 2));
+
+        mock.set_offline(true);
+        assert!(mock.query_status().is_err());
+        assert!(mock.open().is_err());
+        assert!(writer.write_all(b"four").is_err());
+    }
+
+    #[test]
+    fn test_paced_backend_writes_in_chunks() {
+        let pacing = WritePacing {
+            chunk_size: 4,
+            delay_ms: 0,
+        };
+        let mock = MockBackend::new();
+        let backend = paced(Arc::new(mock.clone()), pacing);
+        backend.write_all(b"0123456789").unwrap();
+        assert_eq!(mock.written(), b"0123456789");
+        assert_eq!(mock.writes(), 3);
+        assert_eq!(mock.jobs(), 1);
+
+        // Backends that only take whole jobs get the job in one write
+        let spooler = MockBackend::new().whole_jobs();
+        let backend = paced(Arc::new(spooler.clone()), pacing);
+        backend.write_all(b"0123456789").unwrap();
+        assert_eq!(spooler.written(), b"0123456789");
+        assert_eq!(spooler.writes(), 1);
+    }
+}
@@ -0,0 +1,253 @@
+// src/barcode.rs
+// Barcode symbologies for receipt printers: content checks and ESC/POS GS k
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A barcode format receipt printers can print
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symbology {
+    Code128,
+    Code39,
+    Code93,
+    Ean13,
+    Ean8,
+    UpcA,
+    UpcE,
+    Itf,
+    Codabar,
+}
+
+impl Symbology {
+    /// The format named in a template, e.g. `"CODE128"` or `"EAN-13"`.
+    /// Case and dashes don't matter.
+    pub fn parse(format: &str) -> Option<Self> {
+        let name = format.to_uppercase().replace(['-', '_'], "");
+        Some(match name.as_str() {
+            "CODE128" | "128" => Symbology::Code128,
+            "CODE39" | "39" => Symbology::Code39,
+            "CODE93" | "93" => Symbology::Code93,
+            "EAN13" | "JAN13" => Symbology::Ean13,
+            "EAN8" | "JAN8" => Symbology::Ean8,
+            "UPCA" | "UPC" => Symbology::UpcA,
+            "UPCE" => Symbology::UpcE,
+            "ITF" | "I25" => Symbology::Itf,
+            "CODABAR" | "NW7" => Symbology::Codabar,
+            _ => return None,
+        })
+    }
+
+    /// `m` of the ESC/POS `GS k m n d1...dn` form
+    fn escpos_code(self) -> u8 {
+        match self {
+            Symbology::UpcA => 65,
+            Symbology::UpcE => 66,
+            Symbology::Ean13 => 67,
+            Symbology::Ean8 => 68,
+            Symbology::Code39 => 69,
+            Symbology::Itf => 70,
+            Symbology::Codabar => 71,
+            Symbology::Code93 => 72,
+            Symbology::Code128 => 73,
+        }
+    }
+
+    /// Check `content` against the format and return what to encode. EAN
+    /// and UPC-A content without its check digit gets it appended.
+    pub fn check(self, content: &str) -> Result<String, String> {
+        if content.is_empty() {
+            return Err("no content".to_string());
+        }
+        match self {
+            Symbology::Ean13 => check_digits(content, 12),
+            Symbology::Ean8 => check_digits(content, 7),
+            Symbology::UpcA => check_digits(content, 11),
+            Symbology::UpcE => {
+                all_digits(content)?;
+                if !(6..=8).contains(&content.len()) {
+                    return Err(format!("needs 6 to 8 digits, not {}", content.len()));
+                }
+                Ok(content.to_string())
+            }
+            Symbology::Itf => {
+                all_digits(content)?;
+                if !content.len().is_multiple_of(2) {
+                    return Err("needs an even number of digits".to_string());
+                }
+                Ok(content.to_string())
+            }
+            Symbology::Code39 => {
+                let content = content.to_uppercase();
+                match content
+                    .chars()
+                    .find(|c| !c.is_ascii_alphanumeric() && !" -.$/+%".contains(*c))
+                {
+                    Some(c) => Err(format!("'{}' is not a Code 39 character", c)),
+                    None => Ok(content),
+                }
+            }
+            Symbology::Codabar => match content
+                .chars()
+                .find(|c| !c.is_ascii_digit() && !"-$:/.+ABCDabcd".contains(*c))
+            {
+                Some(c) => Err(format!("'{}' is not a Codabar character", c)),
+                None => Ok(content.to_string()),
+            },
+            Symbology::Code128 | Symbology::Code93 => {
+                if let Some(c) = content.chars().find(|c| !c.is_ascii()) {
+                    return Err(format!("'{}' is not an ASCII character", c));
+                }
+                Ok(content.to_string())
+            }
+        }
+    }
+}
+
+/// Where the human readable text goes relative to a barcode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HriPosition {
+    None,
+    Above,
+    #[default]
+    Below,
+    Both,
+}
+
+/// Longest content `GS k` takes
+const MAX_LEN: usize = 253;
+
+/// ESC/POS `GS h`, `GS w`, `GS H` and `GS k` for a checked barcode.
+/// `width` is the narrowest bar in dots, 2 to 6.
+pub fn escpos_bytes(
+    symbology: Symbology,
+    content: &str,
+    height: u8,
+    width: u8,
+    hri: HriPosition,
+    bytes: &mut Vec<u8>,
+) {
+    let mut data = Vec::with_capacity(content.len() + 2);
+    if symbology == Symbology::Code128 {
+        // Code set B, with a literal '{' doubled
+        data.extend_from_slice(b"{B");
+        for byte in content.bytes() {
+            if byte == b'{' {
+                data.push(b'{');
+            }
+            data.push(byte);
+        }
+    } else {
+        data.extend_from_slice(content.as_bytes());
+    }
+    data.truncate(MAX_LEN);
+
+    bytes.extend_from_slice(&[0x1D, 0x68, height.max(1)]);
+    bytes.extend_from_slice(&[0x1D, 0x77, width.clamp(2, 6)]);
+    bytes.extend_from_slice(&[0x1D, 0x48, hri as u8]);
+    bytes.extend_from_slice(&[0x1D, 0x6B, symbology.escpos_code(), data.len() as u8]);
+    bytes.extend_from_slice(&data);
+}
+
+fn all_digits(content: &str) -> Result<(), String> {
+    match content.chars().find(|c| !c.is_ascii_digit()) {
+        Some(c) => Err(format!("'{}' is not a digit", c)),
+        None => Ok(()),
+    }
+}
+
+/// `content` of `len` digits plus the check digit, or `len + 1` digits
+/// whose last is the right check digit
+fn check_digits(content: &str, len: usize) -> Result<String, String> {
+    all_digits(content)?;
+    let digits = &content[..len.min(content.len())];
+    if content.len() != len && content.len() != len + 1 {
+        return Err(format!(
+            "needs {} digits, or {} with the check digit, not {}",
+            len,
+            len + 1,
+            content.len()
+        ));
+    }
+    let check = check_digit(digits);
+    match content.as_bytes().get(len) {
+        Some(&given) if given != check => Err(format!(
+            "check digit is {}, not {}",
+            check as char, given as char
+        )),
+        _ => Ok(format!("{}{}", digits, check as char)),
+    }
+}
+
+/// The EAN/UPC check digit: digits weighted 3 and 1 from the right
+fn check_digit(digits: &str) -> u8 {
+    let sum: u32 = digits
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(i, b)| (b - b'0') as u32 * if i % 2 == 0 { 3 } else { 1 })
+        .sum();
+    b'0' + ((10 - sum % 10) % 10) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_digits_are_added_or_verified() {
+        assert_eq!(
+            Symbology::Ean13.check("400638133393").unwrap(),
+            "4006381333931"
+        );
+        assert_eq!(
+            Symbology::Ean13.check("4006381333931").unwrap(),
+            "4006381333931"
+        );
+        assert_eq!(
+            Symbology::Ean13.check("4006381333932").unwrap_err(),
+            "check digit is 1, not 2"
+        );
+        assert_eq!(Symbology::Ean8.check("9638507").unwrap(), "96385074");
+        assert_eq!(
+            Symbology::UpcA.check("03600029145").unwrap(),
+            "036000291452"
+        );
+        assert!(Symbology::Ean13.check("40063813").is_err());
+        assert!(Symbology::UpcA.check("0360002914A").is_err());
+    }
+
+    #[test]
+    fn test_content_is_checked_per_format() {
+        assert_eq!(Symbology::Code39.check("order-42").unwrap(), "ORDER-42");
+        assert!(Symbology::Code39.check("A*B").is_err());
+        assert!(Symbology::Itf.check("12345").is_err());
+        assert!(Symbology::Itf.check("123456").is_ok());
+        assert!(Symbology::Code128.check("Café").is_err());
+        assert!(Symbology::Code128.check("").is_err());
+        assert_eq!(Symbology::parse("ean-13"), Some(Symbology::Ean13));
+        assert_eq!(Symbology::parse("PDF417"), None);
+    }
+
+    #[test]
+    fn test_escpos_bytes() {
+        let mut bytes = Vec::new();
+        escpos_bytes(
+            Symbology::Code128,
+            "a{1",
+            80,
+            9,
+            HriPosition::Both,
+            &mut bytes,
+        );
+        assert_eq!(
+            bytes,
+            [
+                &[0x1D, 0x68, 80, 0x1D, 0x77, 6, 0x1D, 0x48, 3][..],
+                &[0x1D, 0x6B, 73, 6],
+                b"{Ba{{1"
+            ]
+            .concat()
+        );
+    }
+}
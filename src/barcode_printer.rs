@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
+use crate::config::{SerialSettings, Timeouts};
+
 // ---------------------------------------------------------------------------
 // BarcodeType
 // ---------------------------------------------------------------------------
@@ -48,6 +50,11 @@ pub struct BarcodePrinterConfig {
     pub label_width_mm: u32,
     pub label_height_mm: u32,
     pub dpi: u32,
+    /// Only used when the device is a serial port
+    #[serde(default)]
+    pub serial: SerialSettings,
+    #[serde(default)]
+    pub timeouts: Timeouts,
 }
 
 // ---------------------------------------------------------------------------
@@ -117,7 +124,7 @@ fn estimate_modules(barcode_type: &BarcodeType, data_len: usize) -> u32 {
     let n = data_len as u32;
     match barcode_type {
         // CODE128: START(11) + ceil(N/2) code words × 11 + CHECK(11) + STOP(13) + 2×quiet(10)
-        BarcodeType::Code128 => 11 * ((n + 1) / 2) + 55,
+        BarcodeType::Code128 => 11 * n.div_ceil(2) + 55,
         // CODE39: each char = 10 modules, plus inter-char gap, plus start/stop + quiet
         BarcodeType::Code39  => 10 * (n + 2) + (n + 1) + 20,
         // Fixed-width symbologies (quiet zones included)
@@ -145,8 +152,6 @@ struct LabelLayout {
     total_w: u32,
     /// Usable width inside margins in dots.
     printable_w: u32,
-    /// Usable height inside margins in dots.
-    printable_h: u32,
     // ── barcode position ─────────────────────────────────────────────────────
     /// Left edge of the barcode, horizontally centred in the printable area.
     barcode_x: u32,
@@ -291,10 +296,9 @@ impl LabelLayout {
 
         // ── narrow bar width ─────────────────────────────────────────────
         let modules = estimate_modules(barcode_type, data_len);
-        let narrow = if modules == 0 {
-            1 // QR — handled via qr_cell below
-        } else {
-            (printable_w / modules).max(1).min(3)
+        let narrow = match printable_w.checked_div(modules) {
+            Some(w) => w.clamp(1, 3),
+            None => 1, // QR — handled via qr_cell below
         };
         let wide = (narrow * 2).max(2);
 
@@ -325,12 +329,11 @@ impl LabelLayout {
         // ── QR cell size ──────────────────────────────────────────────────
         // Assume ~29 modules per side for common short data.
         let qr_area = printable_w.min(barcode_h);
-        let qr_cell = (qr_area / 29).max(1).min(10);
+        let qr_cell = (qr_area / 29).clamp(1, 10);
 
         LabelLayout {
             total_w,
             printable_w,
-            printable_h,
             barcode_x,
             barcode_y,
             barcode_h,
@@ -401,7 +404,7 @@ fn build_tspl(config: &BarcodePrinterConfig, req: &BarcodeLabelRequest) -> Vec<u
 }
 
 /// Map a [`BarcodeType`] to its TSPL barcode identifier string.
-fn tspl_barcode_type(barcode_type: &BarcodeType) -> &'static str {
+pub(crate) fn tspl_barcode_type(barcode_type: &BarcodeType) -> &'static str {
     match barcode_type {
         BarcodeType::Code128 => "128",
         BarcodeType::Code39  => "39",
@@ -644,6 +647,8 @@ mod tests {
             label_width_mm: 100,
             label_height_mm: 50,
             dpi: 203,
+            serial: SerialSettings::default(),
+            timeouts: Timeouts::default(),
         }
     }
 
@@ -655,6 +660,8 @@ mod tests {
             label_width_mm: 32,
             label_height_mm: 25,
             dpi: 203,
+            serial: SerialSettings::default(),
+            timeouts: Timeouts::default(),
         }
     }
 
@@ -731,7 +738,7 @@ mod tests {
     #[test]
     fn test_layout_no_text_barcode_capped_at_55pct() {
         let l = LabelLayout::compute(32, 25, 203, &BarcodeType::Code128, 12, None);
-        let printable_h = mm_to_dots(25, 203).saturating_sub(2 * 6_u32.max(3));
+        let printable_h = mm_to_dots(25, 203).saturating_sub(2 * 6);
         let max_h = ((printable_h as f64 * 0.55).round() as u32).max(20);
         assert_eq!(l.barcode_h, max_h,
             "without text, barcode height should be capped at 55% of printable height");
@@ -0,0 +1,308 @@
+// src/bidi.rs
+// Right-to-left text put in the order printers print it, left to right
+
+use std::mem;
+use std::ops::Range;
+
+use unicode_bidi::{bidi_class, BidiClass, BidiInfo, Level};
+
+use crate::template_render::{CommandSink, Direction, Font, PrintCommand};
+
+/// What the style commands have set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    underline: bool,
+    reverse: bool,
+    double_strike: bool,
+    font: Font,
+    size: (u8, u8),
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            bold: false,
+            underline: false,
+            reverse: false,
+            double_strike: false,
+            font: Font::A,
+            size: (1, 1),
+        }
+    }
+}
+
+impl Style {
+    /// Take in a style command; `false` if `command` isn't one
+    fn apply(&mut self, command: &PrintCommand) -> bool {
+        match *command {
+            PrintCommand::Bold(on) => self.bold = on,
+            PrintCommand::Underline(on) => self.underline = on,
+            PrintCommand::Reverse(on) => self.reverse = on,
+            PrintCommand::DoubleStrike(on) => self.double_strike = on,
+            PrintCommand::Font(font) => self.font = font,
+            PrintCommand::Size(width, height) => self.size = (width, height),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The commands that change `self` into `to`
+    fn changes(&self, to: &Style) -> Vec<PrintCommand> {
+        let mut commands = Vec::new();
+        if self.bold != to.bold {
+            commands.push(PrintCommand::Bold(to.bold));
+        }
+        if self.underline != to.underline {
+            commands.push(PrintCommand::Underline(to.underline));
+        }
+        if self.reverse != to.reverse {
+            commands.push(PrintCommand::Reverse(to.reverse));
+        }
+        if self.double_strike != to.double_strike {
+            commands.push(PrintCommand::DoubleStrike(to.double_strike));
+        }
+        if self.font != to.font {
+            commands.push(PrintCommand::Font(to.font));
+        }
+        if self.size != to.size {
+            commands.push(PrintCommand::Size(to.size.0, to.size.1));
+        }
+        commands
+    }
+}
+
+/// Passes one element's commands on with its text in visual order.
+///
+/// Left-to-right elements only have text with Arabic or Hebrew in it
+/// reordered. Right-to-left elements are put together a line at a time and
+/// the whole line reordered, so a row's label prints on the right and a
+/// table's first column rightmost; `left` and `right` alignment swap.
+pub(crate) struct BidiSink<'a> {
+    inner: &'a mut dyn CommandSink,
+    direction: Direction,
+    /// The style as the element has set it
+    style: Style,
+    /// The style the printer has been sent
+    printed: Style,
+    /// Text of the unfinished line, each piece with its style
+    line: Vec<(Style, String)>,
+}
+
+impl<'a> BidiSink<'a> {
+    pub(crate) fn new(inner: &'a mut dyn CommandSink, direction: Direction) -> Self {
+        // Right-to-left lines start at the right unless the element aligns them
+        if direction == Direction::Rtl {
+            inner.push(PrintCommand::Align("right".to_string()));
+        }
+        Self {
+            inner,
+            direction,
+            style: Style::default(),
+            printed: Style::default(),
+            line: Vec::new(),
+        }
+    }
+
+    /// Send any unfinished line and put the alignment back for the next
+    /// element
+    pub(crate) fn finish(mut self) {
+        if self.direction == Direction::Rtl {
+            self.flush(false);
+            self.inner.push(PrintCommand::Align("left".to_string()));
+        }
+    }
+
+    /// Add text to the line, which ends at each newline
+    fn text(&mut self, text: &str, newline: bool) {
+        let mut lines = text.split('\n').peekable();
+        while let Some(piece) = lines.next() {
+            if !piece.is_empty() {
+                self.line.push((self.style, piece.to_string()));
+            }
+            if lines.peek().is_some() || newline {
+                self.flush(true);
+            }
+        }
+    }
+
+    /// Send the line in visual order, the last piece with a newline if
+    /// `newline`
+    fn flush(&mut self, newline: bool) {
+        let line = mem::take(&mut self.line);
+        let mut pieces = reorder(&line, Level::rtl()).into_iter().peekable();
+        while let Some((style, text)) = pieces.next() {
+            self.print_in(style);
+            if newline && pieces.peek().is_none() {
+                self.inner.push(PrintCommand::WriteLine(text));
+                self.print_in(self.style);
+                return;
+            }
+            self.inner.push(PrintCommand::Write(text));
+        }
+        self.print_in(self.style);
+        if newline {
+            self.inner.push(PrintCommand::WriteLine(String::new()));
+        }
+    }
+
+    fn print_in(&mut self, style: Style) {
+        for command in self.printed.changes(&style) {
+            self.inner.push(command);
+        }
+        self.printed = style;
+    }
+}
+
+impl CommandSink for BidiSink<'_> {
+    fn push(&mut self, command: PrintCommand) {
+        if self.direction == Direction::Ltr {
+            let command = match command {
+                PrintCommand::Write(text) => PrintCommand::Write(visual_order(text)),
+                PrintCommand::WriteLine(text) => PrintCommand::WriteLine(visual_order(text)),
+                command => command,
+            };
+            self.inner.push(command);
+            return;
+        }
+
+        match command {
+            PrintCommand::Write(text) => self.text(&text, false),
+            PrintCommand::WriteLine(text) => self.text(&text, true),
+            // Sent with the text they apply to, once the line is in order
+            command if self.style.apply(&command) => {}
+            PrintCommand::Align(align) => {
+                self.flush(false);
+                let align = match align.as_str() {
+                    "left" => "right".to_string(),
+                    "right" => "left".to_string(),
+                    _ => align,
+                };
+                self.inner.push(PrintCommand::Align(align));
+            }
+            command => {
+                self.flush(false);
+                self.inner.push(command);
+            }
+        }
+    }
+
+    fn check(&mut self) -> Result<(), String> {
+        self.inner.check()
+    }
+}
+
+/// Left-to-right text with any right-to-left runs in it turned around
+fn visual_order(text: String) -> String {
+    if !has_rtl(&text) {
+        return text;
+    }
+    reorder(&[(Style::default(), text)], Level::ltr())
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect()
+}
+
+fn has_rtl(text: &str) -> bool {
+    text.chars()
+        .any(|c| matches!(bidi_class(c), BidiClass::R | BidiClass::AL))
+}
+
+/// A line's pieces in visual order with right-to-left runs reversed, for a
+/// paragraph at `level`. Pieces are split where runs change direction.
+fn reorder(line: &[(Style, String)], level: Level) -> Vec<(Style, String)> {
+    let text: String = line.iter().map(|(_, piece)| piece.as_str()).collect();
+    let mut ranges: Vec<(Style, Range<usize>)> = Vec::with_capacity(line.len());
+    let mut start = 0;
+    for (style, piece) in line {
+        ranges.push((*style, start..start + piece.len()));
+        start += piece.len();
+    }
+
+    let info = BidiInfo::new(&text, Some(level));
+    let mut visual: Vec<(Style, String)> = Vec::new();
+    for paragraph in &info.paragraphs {
+        let (levels, runs) = info.visual_runs(paragraph, paragraph.range.clone());
+        for run in runs {
+            let rtl = levels[run.start].is_rtl();
+            let mut parts: Vec<(Style, &str)> = ranges
+                .iter()
+                .filter(|(_, range)| range.start < run.end && run.start < range.end)
+                .map(|(style, range)| {
+                    let part = range.start.max(run.start)..range.end.min(run.end);
+                    (*style, &text[part])
+                })
+                .collect();
+            if rtl {
+                parts.reverse();
+            }
+            for (style, part) in parts {
+                let part: String = if rtl {
+                    part.chars().rev().map(mirror).collect()
+                } else {
+                    part.to_string()
+                };
+                match visual.last_mut() {
+                    Some((last, text)) if *last == style => text.push_str(&part),
+                    _ => visual.push((style, part)),
+                }
+            }
+        }
+    }
+    visual
+}
+
+/// Brackets face the other way in right-to-left text
+fn mirror(c: char) -> char {
+    match c {
+        '(' => ')',
+        ')' => '(',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        '}' => '{',
+        '<' => '>',
+        '>' => '<',
+        '«' => '»',
+        '»' => '«',
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hebrew_in_left_to_right_text_is_turned_around() {
+        assert_eq!(visual_order("Table שלום 5".to_string()), "Table 5 םולש");
+        assert_eq!(visual_order("Latte 4.50".to_string()), "Latte 4.50");
+    }
+
+    #[test]
+    fn test_right_to_left_lines_are_mirrored() {
+        let mut commands = Vec::new();
+        let mut sink = BidiSink::new(&mut commands, Direction::Rtl);
+        sink.push(PrintCommand::WriteLine("סה״כ (כולל)    12.50".to_string()));
+        sink.push(PrintCommand::Bold(true));
+        sink.push(PrintCommand::Write("Latte".to_string()));
+        sink.push(PrintCommand::Bold(false));
+        sink.push(PrintCommand::WriteLine(" קפה".to_string()));
+        sink.push(PrintCommand::Align("left".to_string()));
+        sink.finish();
+
+        assert_eq!(
+            commands,
+            vec![
+                PrintCommand::Align("right".to_string()),
+                PrintCommand::WriteLine("12.50    (ללוכ) כ״הס".to_string()),
+                PrintCommand::Write("הפק ".to_string()),
+                PrintCommand::Bold(true),
+                PrintCommand::WriteLine("Latte".to_string()),
+                PrintCommand::Bold(false),
+                PrintCommand::Align("right".to_string()),
+                PrintCommand::Align("left".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,607 @@
+// src/codepage.rs
+// Printer code pages and the conversion of rendered UTF-8 text into them
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoder::Protocol;
+
+/// A single-byte character set the printer can switch to. The lower half is
+/// ASCII on all of them; the upper half differs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodePage {
+    /// US, the power-on default of most printers
+    Cp437,
+    /// Western Europe
+    Cp850,
+    /// Central Europe
+    Cp852,
+    /// Turkish
+    Cp857,
+    /// Western Europe with the euro sign
+    Cp858,
+    /// Portuguese
+    Cp860,
+    /// Canadian French
+    Cp863,
+    /// Nordic
+    Cp865,
+    /// Cyrillic
+    Cp866,
+    /// Windows Latin 1
+    Cp1252,
+}
+
+/// Code pages tried, in order, for printers whose code pages aren't
+/// configured or known from their profile. Every ESC/POS printer has these.
+pub const DEFAULT_CODE_PAGES: &[CodePage] = &[
+    CodePage::Cp437,
+    CodePage::Cp858,
+    CodePage::Cp852,
+    CodePage::Cp866,
+    CodePage::Cp1252,
+];
+
+impl CodePage {
+    pub const ALL: [CodePage; 10] = [
+        CodePage::Cp437,
+        CodePage::Cp850,
+        CodePage::Cp852,
+        CodePage::Cp857,
+        CodePage::Cp858,
+        CodePage::Cp860,
+        CodePage::Cp863,
+        CodePage::Cp865,
+        CodePage::Cp866,
+        CodePage::Cp1252,
+    ];
+
+    /// The name used in config files and profiles, e.g. "cp858"
+    pub fn name(self) -> &'static str {
+        match self {
+            CodePage::Cp437 => "cp437",
+            CodePage::Cp850 => "cp850",
+            CodePage::Cp852 => "cp852",
+            CodePage::Cp857 => "cp857",
+            CodePage::Cp858 => "cp858",
+            CodePage::Cp860 => "cp860",
+            CodePage::Cp863 => "cp863",
+            CodePage::Cp865 => "cp865",
+            CodePage::Cp866 => "cp866",
+            CodePage::Cp1252 => "cp1252",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CodePage> {
+        let name = name.trim();
+        Self::ALL
+            .into_iter()
+            .find(|page| page.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The table number that selects this page: `ESC t n` on ESC/POS
+    /// printers, `ESC GS t n` on Star printers. Star has no plain CP850.
+    pub fn table_number(self, protocol: Protocol) -> Option<u8> {
+        match protocol {
+            Protocol::EscPos => Some(match self {
+                CodePage::Cp437 => 0,
+                CodePage::Cp850 => 2,
+                CodePage::Cp860 => 3,
+                CodePage::Cp863 => 4,
+                CodePage::Cp865 => 5,
+                CodePage::Cp857 => 13,
+                CodePage::Cp1252 => 16,
+                CodePage::Cp866 => 17,
+                CodePage::Cp852 => 18,
+                CodePage::Cp858 => 19,
+            }),
+            Protocol::Star => match self {
+                CodePage::Cp437 => Some(1),
+                CodePage::Cp850 => None,
+                CodePage::Cp858 => Some(4),
+                CodePage::Cp852 => Some(5),
+                CodePage::Cp860 => Some(6),
+                CodePage::Cp863 => Some(8),
+                CodePage::Cp865 => Some(9),
+                CodePage::Cp866 => Some(10),
+                CodePage::Cp857 => Some(12),
+                CodePage::Cp1252 => Some(32),
+            },
+        }
+    }
+
+    /// The byte for `c` on this page, if it has it
+    pub fn byte(self, c: char) -> Option<u8> {
+        if c.is_ascii() {
+            return Some(c as u8);
+        }
+        self.upper_half()
+            .iter()
+            .position(|&upper| upper == c)
+            .map(|i| 0x80 + i as u8)
+    }
+
+    fn upper_half(self) -> &'static [char; 128] {
+        match self {
+            CodePage::Cp437 => &CP437,
+            CodePage::Cp850 => &CP850,
+            CodePage::Cp852 => &CP852,
+            CodePage::Cp857 => &CP857,
+            CodePage::Cp858 => &CP858,
+            CodePage::Cp860 => &CP860,
+            CodePage::Cp863 => &CP863,
+            CodePage::Cp865 => &CP865,
+            CodePage::Cp866 => &CP866,
+            CodePage::Cp1252 => &CP1252,
+        }
+    }
+}
+
+/// The code pages of a built-in profile, leaving out any this module
+/// doesn't have a table for
+pub fn from_names(names: &[&str]) -> Vec<CodePage> {
+    names
+        .iter()
+        .filter_map(|name| CodePage::from_name(name))
+        .collect()
+}
+
+/// A double-byte character set for Chinese, Japanese or Korean text, printed
+/// in the printer's Kanji mode. Only CJK models have the fonts for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CjkEncoding {
+    /// Simplified Chinese
+    Gbk,
+    /// Japanese
+    ShiftJis,
+    /// Korean
+    EucKr,
+}
+
+impl CjkEncoding {
+    /// The two bytes for `c`, if the character set has it
+    pub fn bytes(self, c: char) -> Option<[u8; 2]> {
+        let encoding = match self {
+            CjkEncoding::Gbk => encoding_rs::GBK,
+            CjkEncoding::ShiftJis => encoding_rs::SHIFT_JIS,
+            CjkEncoding::EucKr => encoding_rs::EUC_KR,
+        };
+        let mut utf8 = [0; 4];
+        let (bytes, _, unmappable) = encoding.encode(c.encode_utf8(&mut utf8));
+        match *bytes {
+            [first, second] if !unmappable => Some([first, second]),
+            _ => None,
+        }
+    }
+
+    /// Turn Kanji mode on: `FS &` on ESC/POS printers, after `FS C 1` to
+    /// read Shift-JIS; `ESC $ 1` for Shift-JIS on Star printers, whose
+    /// Chinese and Korean models take double-byte text as is
+    fn enter(self, protocol: Protocol, bytes: &mut Vec<u8>) {
+        match (protocol, self) {
+            (Protocol::EscPos, CjkEncoding::ShiftJis) => {
+                bytes.extend_from_slice(&[0x1C, 0x43, 0x01, 0x1C, 0x26])
+            }
+            (Protocol::EscPos, _) => bytes.extend_from_slice(&[0x1C, 0x26]),
+            (Protocol::Star, CjkEncoding::ShiftJis) => bytes.extend_from_slice(&[0x1B, 0x24, 0x01]),
+            (Protocol::Star, _) => {}
+        }
+    }
+
+    /// Turn Kanji mode off again, so code page bytes print as single
+    /// characters
+    fn leave(self, protocol: Protocol, bytes: &mut Vec<u8>) {
+        match (protocol, self) {
+            (Protocol::EscPos, CjkEncoding::ShiftJis) => {
+                bytes.extend_from_slice(&[0x1C, 0x2E, 0x1C, 0x43, 0x00])
+            }
+            (Protocol::EscPos, _) => bytes.extend_from_slice(&[0x1C, 0x2E]),
+            (Protocol::Star, CjkEncoding::ShiftJis) => bytes.extend_from_slice(&[0x1B, 0x24, 0x00]),
+            (Protocol::Star, _) => {}
+        }
+    }
+}
+
+// ==================== Encoding ====================
+
+/// Append `text` in the printer's code pages.
+///
+/// ASCII goes out as is. Other characters are looked up in the page last
+/// selected, then in `pages` in order, and the printer is switched to the
+/// first page that has them. Characters no page has go out in `cjk`, in
+/// Kanji mode, if the printer has a CJK character set. What is left is
+/// replaced by a look-alike (curly quotes, dashes) or `?`, one byte per
+/// character so columns stay aligned. Every call starts without a selected
+/// page and ends outside Kanji mode, so commands can be encoded on their own.
+pub fn encode_text(
+    text: &str,
+    pages: &[CodePage],
+    cjk: Option<CjkEncoding>,
+    protocol: Protocol,
+    bytes: &mut Vec<u8>,
+) {
+    let mut current: Option<CodePage> = None;
+    let mut kanji = false;
+    for c in text.chars() {
+        if c.is_ascii() {
+            bytes.push(c as u8);
+            continue;
+        }
+        let on_page = match current.and_then(|page| page.byte(c)) {
+            Some(byte) => Some((None, byte)),
+            None => pages.iter().find_map(|&page| {
+                let number = page.table_number(protocol)?;
+                Some((Some((page, number)), page.byte(c)?))
+            }),
+        };
+        if let Some((switch, byte)) = on_page {
+            if let (true, Some(cjk)) = (kanji, cjk) {
+                cjk.leave(protocol, bytes);
+                kanji = false;
+            }
+            if let Some((page, number)) = switch {
+                match protocol {
+                    Protocol::EscPos => bytes.extend_from_slice(&[0x1B, 0x74, number]),
+                    Protocol::Star => bytes.extend_from_slice(&[0x1B, 0x1D, 0x74, number]),
+                }
+                current = Some(page);
+            }
+            bytes.push(byte);
+            continue;
+        }
+        match cjk.and_then(|cjk| Some((cjk, cjk.bytes(c)?))) {
+            Some((cjk, pair)) => {
+                if !kanji {
+                    cjk.enter(protocol, bytes);
+                    kanji = true;
+                }
+                bytes.extend_from_slice(&pair);
+            }
+            None => bytes.push(substitute(c)),
+        }
+    }
+    if let (true, Some(cjk)) = (kanji, cjk) {
+        cjk.leave(protocol, bytes);
+    }
+}
+
+/// Whether `text` prints without any `?`: every character is on one of
+/// `pages`, in `cjk`, or has a look-alike
+pub fn can_encode(
+    text: &str,
+    pages: &[CodePage],
+    cjk: Option<CjkEncoding>,
+    protocol: Protocol,
+) -> bool {
+    text.chars().all(|c| {
+        c.is_ascii()
+            || pages
+                .iter()
+                .any(|page| page.table_number(protocol).is_some() && page.byte(c).is_some())
+            || cjk.is_some_and(|cjk| cjk.bytes(c).is_some())
+            || substitute(c) != b'?'
+    })
+}
+
+/// An ASCII stand-in for a character no code page has
+fn substitute(c: char) -> u8 {
+    match c {
+        '‘' | '’' | '‚' | '′' => b'\'',
+        '“' | '”' | '„' | '″' => b'"',
+        '‐' | '‑' | '‒' | '–' | '—' | '−' => b'-',
+        '•' | '‣' | '∗' => b'*',
+        '\u{2002}'..='\u{200a}' | '\u{202f}' => b' ',
+        _ => b'?',
+    }
+}
+
+// ==================== Tables ====================
+
+// The characters for bytes 0x80 to 0xFF; '\0' where a page has none
+#[rustfmt::skip]
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù',
+    'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º',
+    '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖',
+    '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫',
+    '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ',
+    'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈',
+    '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+#[rustfmt::skip]
+const CP850: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù',
+    'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º',
+    '¿', '®', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À',
+    '©', '╣', '║', '╗', '╝', '¢', '¥', '┐',
+    '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤',
+    'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î',
+    'Ï', '┘', '┌', '█', '▄', '¦', 'Ì', '▀',
+    'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ',
+    'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´',
+    '\u{ad}', '±', '‗', '¾', '¶', '§', '÷', '¸',
+    '°', '¨', '·', '¹', '³', '²', '■', '\u{a0}',
+];
+
+#[rustfmt::skip]
+const CP852: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'ů', 'ć', 'ç',
+    'ł', 'ë', 'Ő', 'ő', 'î', 'Ź', 'Ä', 'Ć',
+    'É', 'Ĺ', 'ĺ', 'ô', 'ö', 'Ľ', 'ľ', 'Ś',
+    'ś', 'Ö', 'Ü', 'Ť', 'ť', 'Ł', '×', 'č',
+    'á', 'í', 'ó', 'ú', 'Ą', 'ą', 'Ž', 'ž',
+    'Ę', 'ę', '¬', 'ź', 'Č', 'ş', '«', '»',
+    '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'Ě',
+    'Ş', '╣', '║', '╗', '╝', 'Ż', 'ż', '┐',
+    '└', '┴', '┬', '├', '─', '┼', 'Ă', 'ă',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤',
+    'đ', 'Đ', 'Ď', 'Ë', 'ď', 'Ň', 'Í', 'Î',
+    'ě', '┘', '┌', '█', '▄', 'Ţ', 'Ů', '▀',
+    'Ó', 'ß', 'Ô', 'Ń', 'ń', 'ň', 'Š', 'š',
+    'Ŕ', 'Ú', 'ŕ', 'Ű', 'ý', 'Ý', 'ţ', '´',
+    '\u{ad}', '˝', '˛', 'ˇ', '˘', '§', '÷', '¸',
+    '°', '¨', '˙', 'ű', 'Ř', 'ř', '■', '\u{a0}',
+];
+
+#[rustfmt::skip]
+const CP857: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', 'ı', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù',
+    'İ', 'Ö', 'Ü', 'ø', '£', 'Ø', 'Ş', 'ş',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'Ğ', 'ğ',
+    '¿', '®', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À',
+    '©', '╣', '║', '╗', '╝', '¢', '¥', '┐',
+    '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤',
+    'º', 'ª', 'Ê', 'Ë', 'È', '\0', 'Í', 'Î',
+    'Ï', '┘', '┌', '█', '▄', '¦', 'Ì', '▀',
+    'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', '\0',
+    '×', 'Ú', 'Û', 'Ù', 'ì', 'ÿ', '¯', '´',
+    '\u{ad}', '±', '\0', '¾', '¶', '§', '÷', '¸',
+    '°', '¨', '·', '¹', '³', '²', '■', '\u{a0}',
+];
+
+#[rustfmt::skip]
+const CP858: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù',
+    'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º',
+    '¿', '®', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À',
+    '©', '╣', '║', '╗', '╝', '¢', '¥', '┐',
+    '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤',
+    'ð', 'Ð', 'Ê', 'Ë', 'È', '€', 'Í', 'Î',
+    'Ï', '┘', '┌', '█', '▄', '¦', 'Ì', '▀',
+    'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ',
+    'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´',
+    '\u{ad}', '±', '‗', '¾', '¶', '§', '÷', '¸',
+    '°', '¨', '·', '¹', '³', '²', '■', '\u{a0}',
+];
+
+#[rustfmt::skip]
+const CP860: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ã', 'à', 'Á', 'ç',
+    'ê', 'Ê', 'è', 'Í', 'Ô', 'ì', 'Ã', 'Â',
+    'É', 'À', 'È', 'ô', 'õ', 'ò', 'Ú', 'ù',
+    'Ì', 'Õ', 'Ü', '¢', '£', 'Ù', '₧', 'Ó',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º',
+    '¿', 'Ò', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖',
+    '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫',
+    '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ',
+    'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈',
+    '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+#[rustfmt::skip]
+const CP863: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'Â', 'à', '¶', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', '‗', 'À', '§',
+    'É', 'È', 'Ê', 'ô', 'Ë', 'Ï', 'û', 'ù',
+    '¤', 'Ô', 'Ü', '¢', '£', 'Ù', 'Û', 'ƒ',
+    '¦', '´', 'ó', 'ú', '¨', '¸', '³', '¯',
+    'Î', '⌐', '¬', '½', '¼', '¾', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖',
+    '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫',
+    '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ',
+    'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈',
+    '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+#[rustfmt::skip]
+const CP865: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù',
+    'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º',
+    '¿', '⌐', '¬', '½', '¼', '¡', '«', '¤',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖',
+    '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫',
+    '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ',
+    'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈',
+    '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+#[rustfmt::skip]
+const CP866: [char; 128] = [
+    'А', 'Б', 'В', 'Г', 'Д', 'Е', 'Ж', 'З',
+    'И', 'Й', 'К', 'Л', 'М', 'Н', 'О', 'П',
+    'Р', 'С', 'Т', 'У', 'Ф', 'Х', 'Ц', 'Ч',
+    'Ш', 'Щ', 'Ъ', 'Ы', 'Ь', 'Э', 'Ю', 'Я',
+    'а', 'б', 'в', 'г', 'д', 'е', 'ж', 'з',
+    'и', 'й', 'к', 'л', 'м', 'н', 'о', 'п',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖',
+    '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫',
+    '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'р', 'с', 'т', 'у', 'ф', 'х', 'ц', 'ч',
+    'ш', 'щ', 'ъ', 'ы', 'ь', 'э', 'ю', 'я',
+    'Ё', 'ё', 'Є', 'є', 'Ї', 'ї', 'Ў', 'ў',
+    '°', '∙', '·', '√', '№', '¤', '■', '\u{a0}',
+];
+
+#[rustfmt::skip]
+const CP1252: [char; 128] = [
+    '€', '\0', '‚', 'ƒ', '„', '…', '†', '‡',
+    'ˆ', '‰', 'Š', '‹', 'Œ', '\0', 'Ž', '\0',
+    '\0', '‘', '’', '“', '”', '•', '–', '—',
+    '˜', '™', 'š', '›', 'œ', '\0', 'ž', 'Ÿ',
+    '\u{a0}', '¡', '¢', '£', '¤', '¥', '¦', '§',
+    '¨', '©', 'ª', '«', '¬', '\u{ad}', '®', '¯',
+    '°', '±', '²', '³', '´', 'µ', '¶', '·',
+    '¸', '¹', 'º', '»', '¼', '½', '¾', '¿',
+    'À', 'Á', 'Â', 'Ã', 'Ä', 'Å', 'Æ', 'Ç',
+    'È', 'É', 'Ê', 'Ë', 'Ì', 'Í', 'Î', 'Ï',
+    'Ð', 'Ñ', 'Ò', 'Ó', 'Ô', 'Õ', 'Ö', '×',
+    'Ø', 'Ù', 'Ú', 'Û', 'Ü', 'Ý', 'Þ', 'ß',
+    'à', 'á', 'â', 'ã', 'ä', 'å', 'æ', 'ç',
+    'è', 'é', 'ê', 'ë', 'ì', 'í', 'î', 'ï',
+    'ð', 'ñ', 'ò', 'ó', 'ô', 'õ', 'ö', '÷',
+    'ø', 'ù', 'ú', 'û', 'ü', 'ý', 'þ', 'ÿ',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(text: &str, pages: &[CodePage], protocol: Protocol) -> Vec<u8> {
+        encode_cjk(text, pages, None, protocol)
+    }
+
+    fn encode_cjk(
+        text: &str,
+        pages: &[CodePage],
+        cjk: Option<CjkEncoding>,
+        protocol: Protocol,
+    ) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        encode_text(text, pages, cjk, protocol, &mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn test_switches_code_page_only_when_needed() {
+        assert_eq!(
+            encode("Total 12.50", DEFAULT_CODE_PAGES, Protocol::EscPos),
+            b"Total 12.50"
+        );
+        // é and ñ are on CP437; € needs CP858
+        assert_eq!(
+            encode("Café Niño 5€", DEFAULT_CODE_PAGES, Protocol::EscPos),
+            b"Caf\x1bt\x00\x82 Ni\xa4o 5\x1bt\x13\xd5"
+        );
+        // ñ is on CP858 too, so it stays there
+        assert_eq!(
+            encode("€ñ", DEFAULT_CODE_PAGES, Protocol::EscPos),
+            b"\x1bt\x13\xd5\xa4"
+        );
+        assert_eq!(
+            encode("Привет", &[CodePage::Cp866], Protocol::Star),
+            b"\x1b\x1dt\x0a\x8f\xe0\xa8\xa2\xa5\xe2"
+        );
+    }
+
+    #[test]
+    fn test_unknown_characters_keep_their_column() {
+        assert_eq!(
+            encode("“Çay” – ₺5", &[CodePage::Cp1252], Protocol::EscPos),
+            b"\x1bt\x10\x93\xc7ay\x94 \x96 ?5"
+        );
+        // Without CP1252 the quotes and dash have stand-ins
+        assert_eq!(
+            encode("“Çay” – ₺5", &[CodePage::Cp857], Protocol::EscPos),
+            b"\"\x1bt\x0d\x80ay\" - ?5"
+        );
+        // Star has no CP850, so the page is skipped
+        assert_eq!(encode("é", &[CodePage::Cp850], Protocol::Star), b"?");
+    }
+
+    #[test]
+    fn test_cjk_text_uses_kanji_mode() {
+        let gbk = Some(CjkEncoding::Gbk);
+        assert_eq!(
+            encode_cjk("合计 12元", DEFAULT_CODE_PAGES, gbk, Protocol::EscPos),
+            b"\x1c&\xba\xcf\xbc\xc6 12\xd4\xaa\x1c."
+        );
+        // Code pages come first, so é stays a single column
+        assert_eq!(
+            encode_cjk("é中", DEFAULT_CODE_PAGES, gbk, Protocol::EscPos),
+            b"\x1bt\x00\x82\x1c&\xd6\xd0\x1c."
+        );
+        assert_eq!(
+            encode_cjk("日本", &[], Some(CjkEncoding::ShiftJis), Protocol::EscPos),
+            b"\x1cC\x01\x1c&\x93\xfa\x96\x7b\x1c.\x1cC\x00"
+        );
+        assert_eq!(
+            encode_cjk("日本", &[], Some(CjkEncoding::ShiftJis), Protocol::Star),
+            b"\x1b$\x01\x93\xfa\x96\x7b\x1b$\x00"
+        );
+        assert_eq!(
+            encode_cjk("한", &[], Some(CjkEncoding::EucKr), Protocol::EscPos),
+            b"\x1c&\xc7\xd1\x1c."
+        );
+        // Without a CJK character set there is nothing to print them with
+        assert_eq!(encode("中", DEFAULT_CODE_PAGES, Protocol::EscPos), b"?");
+    }
+
+    #[test]
+    fn test_tables_round_trip() {
+        for page in CodePage::ALL {
+            assert_eq!(CodePage::from_name(page.name()), Some(page));
+            for (i, &c) in page.upper_half().iter().enumerate() {
+                if c != '\0' {
+                    assert_eq!(
+                        page.byte(c),
+                        Some(0x80 + i as u8),
+                        "{} {:?}",
+                        page.name(),
+                        c
+                    );
+                }
+            }
+        }
+        assert_eq!(
+            from_names(&["cp437", "katakana", "CP1252"]),
+            [CodePage::Cp437, CodePage::Cp1252]
+        );
+    }
+}
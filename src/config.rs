@@ -0,0 +1,362 @@
+// src/config.rs
+// Printer configuration models and on-disk storage
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::barcode_printer::BarcodePrinterConfig;
+use crate::codepage::{CjkEncoding, CodePage};
+use crate::encoder::{PrintSpeed, Protocol};
+use crate::routing::RoutingConfig;
+use crate::template_cache::TemplateCacheLimits;
+use crate::template_compile::TemplateValidation;
+use crate::template_render::PrintArea;
+
+// ==================== Configuration Models ====================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrinterConfig {
+    pub connection_type: String,
+    pub device_path: String,
+    pub store_name: String,
+    pub store_address: String,
+    pub footer_message: String,
+    /// Only used when the device is a serial port, or a serial device server
+    /// with "Serial over TCP"
+    #[serde(default)]
+    pub serial: SerialSettings,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    /// What the printer is used for, such as "receipt", "kitchen", "bar" or
+    /// "label"; jobs and routing rules can target a role instead of an id
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// ESC/POS for most printers; Star printers such as the TSP143 need
+    /// "star". Unset uses the printer's profile.
+    #[serde(default)]
+    pub protocol: Option<Protocol>,
+    /// Built-in model profile, such as "epson-tm-t20"; unset detects it from
+    /// a `usb:VID:PID` device path (see [`crate::profiles`])
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Code pages the printer has, such as "cp858", tried in order for
+    /// non-ASCII text; empty uses the printer's profile
+    #[serde(default)]
+    pub code_pages: Vec<CodePage>,
+    /// "gbk", "shift_jis" or "euc_kr" on Chinese, Japanese and Korean models,
+    /// for text no code page has
+    #[serde(default)]
+    pub cjk_encoding: Option<CjkEncoding>,
+    /// Print lines with characters the printer can't (Arabic, emoji) as
+    /// bitmaps instead of `?`
+    #[serde(default)]
+    pub raster_text: bool,
+    /// The printer is mounted upside down, e.g. under a counter: lines are
+    /// rotated and printed last first
+    #[serde(default)]
+    pub upside_down: bool,
+    /// Darkness from -6 (lightest) to 6 (darkest), 0 the printer's
+    /// standard; raise it when prints come out faded. Unset leaves the
+    /// printer's own setting.
+    #[serde(default)]
+    pub density: Option<i8>,
+    /// "slow" prints darker on cheap paper; unset leaves the printer's own
+    /// setting
+    #[serde(default)]
+    pub print_speed: Option<PrintSpeed>,
+    /// Where on the paper receipts print, e.g. `{}` to centre 58mm
+    /// templates on 80mm paper; a template's own `print_area` wins
+    #[serde(default)]
+    pub print_area: Option<PrintArea>,
+    /// Send jobs in chunks with pauses between, for printers that drop data
+    /// on long receipts and logos; unset writes as fast as the printer takes
+    #[serde(default)]
+    pub write_pacing: Option<WritePacing>,
+}
+
+/// Line settings for a printer on a serial port (e.g. `COM3`,
+/// `/dev/ttyUSB0`). Many receipt printers ship at 9600 8N1, some at 19200 or
+/// 38400; it has to match the DIP switches or the printer prints garbage.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SerialSettings {
+    pub baud_rate: u32,
+    /// 5 to 8
+    pub data_bits: u8,
+    /// "none", "odd" or "even"
+    pub parity: String,
+    /// 1 or 2
+    pub stop_bits: u8,
+    /// "none", "software" (XON/XOFF), "hardware" (RTS/CTS) or "dsr"
+    /// (DTR/DSR, which many Epson serial printers use)
+    pub flow_control: String,
+}
+
+/// Baud rates every platform can set
+pub const BAUD_RATES: [u32; 9] = [1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400];
+
+impl Default for SerialSettings {
+    fn default() -> Self {
+        Self {
+            baud_rate: 9600,
+            data_bits: 8,
+            parity: "none".to_string(),
+            stop_bits: 1,
+            flow_control: "none".to_string(),
+        }
+    }
+}
+
+impl SerialSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !BAUD_RATES.contains(&self.baud_rate) {
+            return Err(format!("Unsupported baud rate: {}", self.baud_rate));
+        }
+        if !(5..=8).contains(&self.data_bits) {
+            return Err(format!("Data bits must be 5 to 8, got {}", self.data_bits));
+        }
+        if !matches!(self.parity.as_str(), "none" | "odd" | "even") {
+            return Err(format!("Unknown parity: {}", self.parity));
+        }
+        if !matches!(self.stop_bits, 1 | 2) {
+            return Err(format!("Stop bits must be 1 or 2, got {}", self.stop_bits));
+        }
+        if !matches!(
+            self.flow_control.as_str(),
+            "none" | "software" | "hardware" | "dsr"
+        ) {
+            return Err(format!("Unknown flow control: {}", self.flow_control));
+        }
+        Ok(())
+    }
+}
+
+/// Writes in small chunks with a pause after each, for printers whose buffer
+/// overflows on long jobs and logos and that drop what doesn't fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WritePacing {
+    /// Bytes written before each pause
+    pub chunk_size: usize,
+    pub delay_ms: u64,
+}
+
+impl Default for WritePacing {
+    fn default() -> Self {
+        Self {
+            chunk_size: 512,
+            delay_ms: 50,
+        }
+    }
+}
+
+impl WritePacing {
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.chunk_size == 0 {
+            return Err("The write chunk size must be more than 0 bytes".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// How long a printer gets before a job fails, so a hung printer can't hold
+/// up printing (or the HTTP request waiting on it) indefinitely
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// Opening a network or IPP connection
+    pub connect_ms: u64,
+    /// How long the printer may stop taking data (e.g. out of paper)
+    pub write_ms: u64,
+    /// How long to wait for the printer to answer, e.g. an IPP response
+    pub read_ms: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect_ms: 5_000,
+            write_ms: 30_000,
+            read_ms: 30_000,
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn connect(&self) -> Duration {
+        Duration::from_millis(self.connect_ms)
+    }
+
+    pub fn write(&self) -> Duration {
+        Duration::from_millis(self.write_ms)
+    }
+
+    pub fn read(&self) -> Duration {
+        Duration::from_millis(self.read_ms)
+    }
+
+    /// A zero timeout would mean waiting forever on some platforms
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, ms) in [
+            ("connect", self.connect_ms),
+            ("write", self.write_ms),
+            ("read", self.read_ms),
+        ] {
+            if ms == 0 {
+                return Err(format!("The {} timeout must be more than 0 ms", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How the app winds down on exit, from `shutdown.json`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShutdownSettings {
+    /// How long the job being printed may take to finish before it is
+    /// aborted
+    pub drain_timeout_secs: u64,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        Self {
+            drain_timeout_secs: 10,
+        }
+    }
+}
+
+impl ShutdownSettings {
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_secs)
+    }
+}
+
+// ==================== Configuration Storage ====================
+
+pub fn get_config_path() -> Result<std::path::PathBuf, String> {
+    let config_dir = directories::ProjectDirs::from("com", "nexora", "printer-manager")
+        .ok_or("Failed to determine config directory")?;
+
+    std::fs::create_dir_all(config_dir.config_dir())
+        .map_err(|e| format!("Failed to create config directory: {}", e))?;
+
+    Ok(config_dir.config_dir().join("config.json"))
+}
+
+pub fn save_config(config: &PrinterConfig) -> Result<(), String> {
+    let path = get_config_path()?;
+    let json = serde_json::to_string_pretty(config)
+        .map_err(|e| format!("Failed to serialize config: {}", e))?;
+
+    std::fs::write(path, json).map_err(|e| format!("Failed to write config: {}", e))?;
+
+    log::info!("Configuration saved");
+    Ok(())
+}
+
+pub fn load_config() -> Result<Option<PrinterConfig>, String> {
+    let path = get_config_path()?;
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let json =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read config: {}", e))?;
+
+    let config: PrinterConfig =
+        serde_json::from_str(&json).map_err(|e| format!("Failed to parse config: {}", e))?;
+
+    log::info!("Configuration loaded");
+    Ok(Some(config))
+}
+
+pub fn save_barcode_config(config: &BarcodePrinterConfig) -> Result<(), String> {
+    let path = get_config_path()?.with_file_name("barcode_config.json");
+    let json = serde_json::to_string_pretty(config)
+        .map_err(|e| format!("Failed to serialize barcode config: {}", e))?;
+    std::fs::write(path, json).map_err(|e| format!("Failed to write barcode config: {}", e))?;
+    log::info!("Barcode configuration saved");
+    Ok(())
+}
+
+pub fn load_barcode_config() -> Result<Option<BarcodePrinterConfig>, String> {
+    let path = get_config_path()?.with_file_name("barcode_config.json");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read barcode config: {}", e))?;
+    let config: BarcodePrinterConfig = serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to parse barcode config: {}", e))?;
+    log::info!("Barcode configuration loaded");
+    Ok(Some(config))
+}
+
+
+/// Template cache limits from `template_cache.json`, if the file exists
+pub fn load_template_cache_limits() -> Result<Option<TemplateCacheLimits>, String> {
+    let path = get_config_path()?.with_file_name("template_cache.json");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read template cache config: {}", e))?;
+    let limits: TemplateCacheLimits = serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to parse template cache config: {}", e))?;
+    log::info!("Template cache limits loaded");
+    Ok(Some(limits))
+}
+
+/// Folder of template files, loaded at startup and reloaded as they change
+pub fn get_templates_dir() -> Result<std::path::PathBuf, String> {
+    Ok(get_config_path()?.with_file_name("templates"))
+}
+
+/// Template validation settings from `template_validation.json`, if the file exists
+pub fn load_template_validation() -> Result<Option<TemplateValidation>, String> {
+    let path = get_config_path()?.with_file_name("template_validation.json");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read template validation config: {}", e))?;
+    let validation: TemplateValidation = serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to parse template validation config: {}", e))?;
+    log::info!("Template validation settings loaded");
+    Ok(Some(validation))
+}
+
+/// Item routing rules from `routing.json`, if the file exists
+pub fn load_routing_config() -> Result<Option<RoutingConfig>, String> {
+    let path = get_config_path()?.with_file_name("routing.json");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read routing config: {}", e))?;
+    let routing: RoutingConfig = serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to parse routing config: {}", e))?;
+    log::info!("{} routing rule(s) loaded", routing.rules.len());
+    Ok(Some(routing))
+}
+
+/// Shutdown settings from `shutdown.json`, if the file exists
+pub fn load_shutdown_settings() -> Result<Option<ShutdownSettings>, String> {
+    let path = get_config_path()?.with_file_name("shutdown.json");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read shutdown config: {}", e))?;
+    let settings: ShutdownSettings = serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to parse shutdown config: {}", e))?;
+    log::info!("Shutdown settings loaded");
+    Ok(Some(settings))
+}
@@ -0,0 +1,184 @@
+// src/discovery.rs
+// Find raw network printers by probing the local /24 for an open port 9100
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::backend::PrinterState;
+use crate::snmp;
+
+/// ESC/POS real-time status request: DLE EOT 1 (printer status)
+const STATUS_REQUEST: [u8; 3] = [0x10, 0x04, 0x01];
+
+/// Settings for [`scan_subnet`]
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub port: u16,
+    /// How long each address gets to accept the connection
+    pub connect_timeout: Duration,
+    /// Ask each printer found for its ESC/POS status
+    pub query_status: bool,
+    /// Ask each printer found for its model, serial number and paper status
+    /// over SNMP
+    pub query_snmp: bool,
+    /// Probes in flight at once
+    pub concurrency: usize,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            port: 9100,
+            connect_timeout: Duration::from_millis(300),
+            query_status: true,
+            query_snmp: true,
+            concurrency: 64,
+        }
+    }
+}
+
+/// A host that accepted a connection on the probed port
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiscoveredPrinter {
+    /// `ip:port`, ready to use as a "Network" device path
+    pub addr: String,
+    /// Answered the ESC/POS status request; `None` if it wasn't asked or
+    /// didn't answer in time
+    pub escpos: Option<bool>,
+    /// Reported itself offline (cover open, out of paper, error)
+    pub offline: Option<bool>,
+    /// What the printer told SNMP, if it has an agent
+    pub details: Option<PrinterState>,
+}
+
+/// Probe every other host on `local`'s /24, returning those that accepted a
+/// connection, in address order
+pub async fn scan_subnet(local: Ipv4Addr, options: &ScanOptions) -> Vec<DiscoveredPrinter> {
+    let [a, b, c, own] = local.octets();
+    let limit = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let mut probes = JoinSet::new();
+    for host in (1..=254u8).filter(|&host| host != own) {
+        let addr = SocketAddr::from((Ipv4Addr::new(a, b, c, host), options.port));
+        let limit = Arc::clone(&limit);
+        let options = options.clone();
+        probes.spawn(async move {
+            let _permit = limit.acquire_owned().await.ok()?;
+            probe(addr, &options).await
+        });
+    }
+
+    let mut found = Vec::new();
+    while let Some(result) = probes.join_next().await {
+        if let Ok(Some(printer)) = result {
+            found.push(printer);
+        }
+    }
+    found.sort_by_key(|printer| printer.addr.parse::<SocketAddr>().ok());
+    found
+}
+
+/// Connect to one address and, if asked, query its status
+pub async fn probe(addr: SocketAddr, options: &ScanOptions) -> Option<DiscoveredPrinter> {
+    let mut stream = tokio::time::timeout(options.connect_timeout, TcpStream::connect(addr))
+        .await
+        .ok()?
+        .ok()?;
+
+    let mut printer = DiscoveredPrinter {
+        addr: addr.to_string(),
+        escpos: None,
+        offline: None,
+        details: None,
+    };
+    if options.query_status {
+        // Printers that don't speak ESC/POS just stay silent
+        let status = tokio::time::timeout(options.connect_timeout, async {
+            stream.write_all(&STATUS_REQUEST).await?;
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await?;
+            Ok::<u8, std::io::Error>(byte[0])
+        })
+        .await;
+        if let Ok(Ok(status)) = status {
+            // Bits 1 and 4 are always set and bits 0 and 7 always clear
+            let escpos = status & 0x93 == 0x12;
+            printer.escpos = Some(escpos);
+            printer.offline = escpos.then_some(status & 0x08 != 0);
+        }
+    }
+    if options.query_snmp {
+        let host = addr.ip().to_string();
+        printer.details =
+            tokio::task::spawn_blocking(move || snmp::query_printer(&host, snmp::SNMP_TIMEOUT))
+                .await
+                .ok()
+                .and_then(Result::ok)
+                .flatten();
+    }
+    Some(printer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_probe_reports_open_port_and_escpos_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let printer = tokio::spawn(async move {
+            // An ESC/POS printer that is online, then one with its cover open
+            for status in [0x12u8, 0x1A] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 3];
+                socket.read_exact(&mut request).await.unwrap();
+                assert_eq!(request, STATUS_REQUEST);
+                socket.write_all(&[status]).await.unwrap();
+            }
+        });
+
+        let options = ScanOptions {
+            query_snmp: false,
+            ..ScanOptions::default()
+        };
+        let online = probe(addr, &options).await.unwrap();
+        assert_eq!(
+            online,
+            DiscoveredPrinter {
+                addr: addr.to_string(),
+                escpos: Some(true),
+                offline: Some(false),
+                details: None,
+            }
+        );
+        assert_eq!(probe(addr, &options).await.unwrap().offline, Some(true));
+
+        // Nothing listening any more
+        printer.await.unwrap();
+        assert!(probe(addr, &options).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_probe_without_status_reply() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = ScanOptions {
+            connect_timeout: Duration::from_millis(100),
+            query_snmp: false,
+            ..ScanOptions::default()
+        };
+
+        // Accepted by the backlog but never answered
+        let printer = probe(addr, &options).await.unwrap();
+        assert_eq!((printer.escpos, printer.offline), (None, None));
+        drop(listener);
+    }
+}
@@ -0,0 +1,747 @@
+// src/encoder.rs
+// Command encoders that turn rendered print commands into printer bytes
+
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::barcode::{self, HriPosition, Symbology};
+use crate::codepage::{self, CjkEncoding, CodePage, DEFAULT_CODE_PAGES};
+use crate::image_print;
+use crate::template_render::{CommandSink, Font, PrintCommand};
+
+/// Bytes buffered by `StreamEncoder` before they are written out
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/// Most data an ESC/POS QR code holds; longer content is cut off
+const QR_MAX_BYTES: usize = 7089;
+
+/// A printer command set. The renderer only produces [`PrintCommand`]s, so
+/// supporting another printer language means adding an encoder, not
+/// touching `TemplateRenderer`.
+///
+/// [`PrintCommand::Image`] carries an ESC/POS `GS v 0` raster, which other
+/// encoders convert to their own image command. Text is UTF-8 and goes out
+/// in the printer's code pages (see [`codepage::encode_text`]).
+pub trait CommandEncoder: Send + Sync {
+    /// Append the bytes for one command
+    fn encode_into(&self, command: &PrintCommand, bytes: &mut Vec<u8>);
+
+    /// The bytes for one command
+    fn encode(&self, command: &PrintCommand) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.encode_into(command, &mut bytes);
+        bytes
+    }
+
+    /// The bytes for a whole job
+    fn encode_all(&self, commands: &[PrintCommand]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for command in commands {
+            self.encode_into(command, &mut bytes);
+        }
+        bytes
+    }
+}
+
+// ==================== ESC/POS ====================
+
+/// Epson ESC/POS, spoken by most receipt printers
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EscPosEncoder;
+
+impl CommandEncoder for EscPosEncoder {
+    fn encode_into(&self, command: &PrintCommand, bytes: &mut Vec<u8>) {
+        encode_command(command, bytes);
+    }
+}
+
+/// Convert print commands to raw ESC/POS bytes.
+///
+/// ```
+/// use nexora_printer_manager::encoder::encode_commands;
+/// use nexora_printer_manager::PrintCommand;
+///
+/// let bytes = encode_commands(&[
+///     PrintCommand::Init,
+///     PrintCommand::Bold(true),
+///     PrintCommand::WriteLine("TOTAL".to_string()),
+///     PrintCommand::Cut,
+/// ]);
+/// assert_eq!(bytes, b"\x1b@\x1bE\x01TOTAL\n\x1dV\x01");
+/// ```
+pub fn encode_commands(commands: &[PrintCommand]) -> Vec<u8> {
+    EscPosEncoder.encode_all(commands)
+}
+
+/// Append the ESC/POS bytes for one command
+pub fn encode_command(cmd: &PrintCommand, bytes: &mut Vec<u8>) {
+    encode_escpos_command(cmd, DEFAULT_CODE_PAGES, None, bytes);
+}
+
+fn encode_escpos_command(
+    cmd: &PrintCommand,
+    code_pages: &[CodePage],
+    cjk: Option<CjkEncoding>,
+    bytes: &mut Vec<u8>,
+) {
+    match cmd {
+        PrintCommand::Init => bytes.extend_from_slice(&[0x1B, 0x40]),
+        PrintCommand::Write(s) => {
+            codepage::encode_text(s, code_pages, cjk, Protocol::EscPos, bytes);
+        }
+        PrintCommand::WriteLine(s) => {
+            codepage::encode_text(s, code_pages, cjk, Protocol::EscPos, bytes);
+            bytes.push(b'\n');
+        }
+        PrintCommand::Feed(n) => {
+            bytes.resize(bytes.len() + *n as usize, b'\n');
+        }
+        PrintCommand::Cut => {
+            bytes.extend_from_slice(&[0x1D, 0x56, 0x01]);
+        }
+        PrintCommand::Bold(on) => {
+            bytes.extend_from_slice(&[0x1B, 0x45, if *on { 1 } else { 0 }]);
+        }
+        PrintCommand::Underline(on) => {
+            bytes.extend_from_slice(&[0x1B, 0x2D, if *on { 1 } else { 0 }]);
+        }
+        PrintCommand::Reverse(on) => {
+            bytes.extend_from_slice(&[0x1D, 0x42, if *on { 1 } else { 0 }]);
+        }
+        PrintCommand::DoubleStrike(on) => {
+            bytes.extend_from_slice(&[0x1B, 0x47, if *on { 1 } else { 0 }]);
+        }
+        PrintCommand::Font(font) => {
+            let n = match font {
+                Font::A => 0,
+                Font::B => 1,
+                Font::C => 2,
+            };
+            bytes.extend_from_slice(&[0x1B, 0x4D, n]);
+        }
+        PrintCommand::Size(w, h) => {
+            // GS ! takes 0-7 per nibble for 1x-8x; clamp instead of wrapping around
+            let size = (((*w).clamp(1, 8) - 1) << 4) | ((*h).clamp(1, 8) - 1);
+            bytes.extend_from_slice(&[0x1D, 0x21, size]);
+        }
+        PrintCommand::Align(align) => {
+            let n = match align.to_lowercase().as_str() {
+                "center" => 1,
+                "right" => 2,
+                _ => 0,
+            };
+            bytes.extend_from_slice(&[0x1B, 0x61, n]);
+        }
+        PrintCommand::QRCode {
+            content,
+            size,
+            error_correction,
+        } => {
+            // GS ( k: select model 2, the module size in dots and the error
+            // correction level, store the data, then print it
+            let data = &content.as_bytes()[..content.len().min(QR_MAX_BYTES)];
+            let level = 0x30 + *error_correction as u8;
+            bytes.extend_from_slice(&[0x1D, 0x28, 0x6B, 0x04, 0x00, 0x31, 0x41, 0x32, 0x00]);
+            bytes.extend_from_slice(&[0x1D, 0x28, 0x6B, 0x03, 0x00, 0x31, 0x43]);
+            bytes.push((*size).clamp(1, 16));
+            bytes.extend_from_slice(&[0x1D, 0x28, 0x6B, 0x03, 0x00, 0x31, 0x45, level]);
+            bytes.extend_from_slice(&[0x1D, 0x28, 0x6B]);
+            bytes.extend_from_slice(&(data.len() as u16 + 3).to_le_bytes());
+            bytes.extend_from_slice(&[0x31, 0x50, 0x30]);
+            bytes.extend_from_slice(data);
+            bytes.extend_from_slice(&[0x1D, 0x28, 0x6B, 0x03, 0x00, 0x31, 0x51, 0x30]);
+        }
+        PrintCommand::Barcode {
+            content,
+            format,
+            height,
+            width,
+            hri,
+        } => {
+            let symbology = Symbology::parse(format).unwrap_or(Symbology::Code128);
+            barcode::escpos_bytes(symbology, content, *height, *width, *hri, bytes);
+        }
+        PrintCommand::Image(img_bytes) => {
+            bytes.extend_from_slice(img_bytes);
+        }
+        PrintCommand::Buzzer { times, duration_ms } => {
+            // ESC B n t: n beeps of t x 50 ms, both 1-9
+            let t = (*duration_ms / 50).clamp(1, 9) as u8;
+            bytes.extend_from_slice(&[0x1B, 0x42, (*times).clamp(1, 9), t]);
+        }
+        PrintCommand::NvLogo(slot) => {
+            // GS ( L fn 69: print NV graphics kc1 kc2 at normal size
+            let [kc1, kc2] = image_print::nv_key(*slot);
+            bytes.extend_from_slice(&[0x1D, 0x28, 0x4C, 0x06, 0x00, 0x30, 0x45, kc1, kc2, 1, 1]);
+        }
+        PrintCommand::UpsideDown(on) => {
+            bytes.extend_from_slice(&[0x1B, 0x7B, if *on { 1 } else { 0 }]);
+        }
+        PrintCommand::PrintArea { left, width } => {
+            // GS L left margin, GS W print area width
+            let [ll, lh] = left.to_le_bytes();
+            let [wl, wh] = width.to_le_bytes();
+            bytes.extend_from_slice(&[0x1D, 0x4C, ll, lh, 0x1D, 0x57, wl, wh]);
+        }
+    }
+}
+
+// ==================== Star ====================
+
+/// Star Line Mode, for Star printers such as the TSP100/TSP143.
+/// StarPRNT printers accept it as well.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StarEncoder;
+
+impl CommandEncoder for StarEncoder {
+    fn encode_into(&self, command: &PrintCommand, bytes: &mut Vec<u8>) {
+        encode_star_command(command, DEFAULT_CODE_PAGES, None, bytes);
+    }
+}
+
+/// Append the Star Line Mode bytes for one command
+fn encode_star_command(
+    cmd: &PrintCommand,
+    code_pages: &[CodePage],
+    cjk: Option<CjkEncoding>,
+    bytes: &mut Vec<u8>,
+) {
+    match cmd {
+        PrintCommand::Init => bytes.extend_from_slice(&[0x1B, 0x40]),
+        PrintCommand::Write(s) => codepage::encode_text(s, code_pages, cjk, Protocol::Star, bytes),
+        PrintCommand::WriteLine(s) => {
+            codepage::encode_text(s, code_pages, cjk, Protocol::Star, bytes);
+            bytes.push(b'\n');
+        }
+        PrintCommand::Feed(n) => {
+            bytes.resize(bytes.len() + *n as usize, b'\n');
+        }
+        PrintCommand::Cut => {
+            // ESC d 1: partial cut at the current position
+            bytes.extend_from_slice(&[0x1B, 0x64, 0x01]);
+        }
+        PrintCommand::Bold(on) => {
+            bytes.extend_from_slice(&[0x1B, if *on { 0x45 } else { 0x46 }]);
+        }
+        PrintCommand::Underline(on) => {
+            bytes.extend_from_slice(&[0x1B, 0x2D, if *on { 1 } else { 0 }]);
+        }
+        PrintCommand::Reverse(on) => {
+            bytes.extend_from_slice(&[0x1B, if *on { 0x34 } else { 0x35 }]);
+        }
+        // Star has no double-strike; emphasis is the nearest
+        PrintCommand::DoubleStrike(on) => {
+            bytes.extend_from_slice(&[0x1B, if *on { 0x45 } else { 0x46 }]);
+        }
+        PrintCommand::Font(font) => {
+            // ESC RS F n: 0 font A, 1 font B; Star has no font C
+            let n = if *font == Font::A { 0 } else { 1 };
+            bytes.extend_from_slice(&[0x1B, 0x1E, 0x46, n]);
+        }
+        PrintCommand::Size(w, h) => {
+            // ESC i takes height then width, 0-5 for 1x-6x
+            let h = (*h).clamp(1, 6) - 1;
+            let w = (*w).clamp(1, 6) - 1;
+            bytes.extend_from_slice(&[0x1B, 0x69, h, w]);
+        }
+        PrintCommand::Align(align) => {
+            let n = match align.to_lowercase().as_str() {
+                "center" => 1,
+                "right" => 2,
+                _ => 0,
+            };
+            bytes.extend_from_slice(&[0x1B, 0x1D, 0x61, n]);
+        }
+        PrintCommand::QRCode {
+            content,
+            size,
+            error_correction,
+        } => {
+            let data = content.as_bytes();
+            let len = data.len().min(u16::MAX as usize);
+            // Model 2, error correction L/M/Q/H as 0-3, cell size 1-8
+            bytes.extend_from_slice(&[0x1B, 0x1D, 0x79, 0x53, 0x30, 0x02]);
+            bytes.extend_from_slice(&[0x1B, 0x1D, 0x79, 0x53, 0x31, *error_correction as u8]);
+            bytes.extend_from_slice(&[0x1B, 0x1D, 0x79, 0x53, 0x32, (*size).clamp(1, 8)]);
+            bytes.extend_from_slice(&[0x1B, 0x1D, 0x79, 0x44, 0x31, 0x00]);
+            bytes.extend_from_slice(&(len as u16).to_le_bytes());
+            bytes.extend_from_slice(&data[..len]);
+            bytes.extend_from_slice(&[0x1B, 0x1D, 0x79, 0x50]);
+            bytes.push(b'\n');
+        }
+        PrintCommand::Barcode {
+            content,
+            format,
+            width,
+            height,
+            hri,
+        } => {
+            let kind = match Symbology::parse(format) {
+                Some(Symbology::UpcE) => b'0',
+                Some(Symbology::UpcA) => b'1',
+                Some(Symbology::Ean8) => b'2',
+                Some(Symbology::Ean13) => b'3',
+                Some(Symbology::Code39) => b'4',
+                Some(Symbology::Itf) => b'5',
+                Some(Symbology::Code93) => b'7',
+                Some(Symbology::Codabar) => b'8',
+                Some(Symbology::Code128) | None => b'6',
+            };
+            // With or without the text underneath, then a line feed; Star
+            // prints no text above
+            let text = if *hri == HriPosition::None {
+                b'1'
+            } else {
+                b'2'
+            };
+            // Narrowest bar of 2, 3 or 4 dots
+            let mode = (*width).clamp(2, 4) - 1;
+            bytes.extend_from_slice(&[0x1B, 0x62, kind, text, mode, (*height).max(1)]);
+            bytes.extend_from_slice(content.as_bytes());
+            bytes.push(0x1E);
+        }
+        PrintCommand::Image(img_bytes) => {
+            // Images are stored as ESC/POS GS v 0 rasters; Star takes the
+            // same bitmap after ESC GS S
+            match img_bytes.strip_prefix(&[0x1D, 0x76, 0x30]) {
+                Some(raster) if raster.len() >= 5 => {
+                    bytes.extend_from_slice(&[0x1B, 0x1D, 0x53, 0x01]);
+                    bytes.extend_from_slice(&raster[1..5]);
+                    bytes.push(0x00);
+                    bytes.extend_from_slice(&raster[5..]);
+                }
+                _ => {
+                    log::warn!("Image is not an ESC/POS raster, sending it unchanged");
+                    bytes.extend_from_slice(img_bytes);
+                }
+            }
+        }
+        PrintCommand::Buzzer { times, duration_ms } => {
+            // ESC GS EM DC1 sets the on and off time in 20 ms steps, ESC GS
+            // EM DC2 sounds it n1 + n2 * 256 times
+            let t = (*duration_ms / 20).clamp(1, 255) as u8;
+            bytes.extend_from_slice(&[0x1B, 0x1D, 0x19, 0x11, 0x01, t, t]);
+            bytes.extend_from_slice(&[0x1B, 0x1D, 0x19, 0x12, 0x01, (*times).max(1), 0x00]);
+        }
+        PrintCommand::NvLogo(slot) => {
+            // ESC FS p n m: print NV logo n at normal size
+            bytes.extend_from_slice(&[0x1B, 0x1C, 0x70, *slot, 0x00]);
+        }
+        PrintCommand::UpsideDown(on) => {
+            // ESC SI turns upside-down printing on, ESC DC2 off
+            bytes.extend_from_slice(&[0x1B, if *on { 0x0F } else { 0x12 }]);
+        }
+        PrintCommand::PrintArea { left, width } => {
+            // ESC l n and ESC Q n set the left and right margins in
+            // characters of 12 dots from the left edge
+            let left_chars = (*left / 12).min(255) as u8;
+            let right_chars = ((u32::from(*left) + u32::from(*width)) / 12).min(255) as u8;
+            bytes.extend_from_slice(&[0x1B, 0x6C, left_chars, 0x1B, 0x51, right_chars]);
+        }
+    }
+}
+
+// ==================== Protocols ====================
+
+/// The command set a printer is configured to speak, selecting its encoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// [`EscPosEncoder`]
+    #[default]
+    EscPos,
+    /// [`StarEncoder`]
+    Star,
+}
+
+impl Protocol {
+    pub fn encoder(self) -> &'static dyn CommandEncoder {
+        match self {
+            Protocol::EscPos => &EscPosEncoder,
+            Protocol::Star => &StarEncoder,
+        }
+    }
+}
+
+impl CommandEncoder for Protocol {
+    fn encode_into(&self, command: &PrintCommand, bytes: &mut Vec<u8>) {
+        self.encoder().encode_into(command, bytes);
+    }
+}
+
+/// How fast the paper moves; slower prints darker on cheap paper
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintSpeed {
+    Slow,
+    Medium,
+    Fast,
+}
+
+/// Lightest and darkest print density, from 70% to 130% of the standard
+pub const DENSITY_RANGE: std::ops::RangeInclusive<i8> = -6..=6;
+
+/// The commands setting density and speed, sent after every `ESC @`
+fn print_settings(
+    protocol: Protocol,
+    density: Option<i8>,
+    speed: Option<PrintSpeed>,
+    bytes: &mut Vec<u8>,
+) {
+    let density = density.map(|d| d.clamp(*DENSITY_RANGE.start(), *DENSITY_RANGE.end()));
+    match protocol {
+        Protocol::EscPos => {
+            // GS ( K fn 49: density m is -6 to 6 as a byte, 0 the standard
+            if let Some(density) = density {
+                bytes.extend_from_slice(&[0x1D, 0x28, 0x4B, 0x02, 0x00, 0x31, density as u8]);
+            }
+            // GS ( K fn 50: speed level 1 (slowest) to 13
+            if let Some(speed) = speed {
+                let level = match speed {
+                    PrintSpeed::Slow => 1,
+                    PrintSpeed::Medium => 7,
+                    PrintSpeed::Fast => 13,
+                };
+                bytes.extend_from_slice(&[0x1D, 0x28, 0x4B, 0x02, 0x00, 0x32, level]);
+            }
+        }
+        Protocol::Star => {
+            // ESC RS d n: n 0 (darkest) to 6, 3 the standard; one Star step
+            // is two of ours
+            if let Some(density) = density {
+                let n = 3 - density.div_euclid(2).clamp(-3, 3);
+                bytes.extend_from_slice(&[0x1B, 0x1E, 0x64, n as u8]);
+            }
+            // ESC RS r n: 0 fast, 1 medium, 2 slow
+            if let Some(speed) = speed {
+                let n = match speed {
+                    PrintSpeed::Fast => 0,
+                    PrintSpeed::Medium => 1,
+                    PrintSpeed::Slow => 2,
+                };
+                bytes.extend_from_slice(&[0x1B, 0x1E, 0x72, n]);
+            }
+        }
+    }
+}
+
+/// One printer's protocol together with the code pages it has, in the order
+/// they are tried, and its CJK character set if it is a CJK model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrinterEncoder {
+    pub protocol: Protocol,
+    pub code_pages: Vec<CodePage>,
+    pub cjk: Option<CjkEncoding>,
+    /// Print lines with characters none of the character sets have as
+    /// bitmaps (needs the `raster-text` feature)
+    pub raster_text: bool,
+    /// Whether the printer draws QR codes itself; if not they are sent as
+    /// images
+    pub native_qr: bool,
+    /// Print density in [`DENSITY_RANGE`], 0 the standard; unset leaves the
+    /// printer's own setting
+    pub density: Option<i8>,
+    pub speed: Option<PrintSpeed>,
+}
+
+impl PrinterEncoder {
+    /// No code pages means [`DEFAULT_CODE_PAGES`]
+    pub fn new(protocol: Protocol, code_pages: Vec<CodePage>) -> Self {
+        Self {
+            protocol,
+            code_pages,
+            cjk: None,
+            raster_text: false,
+            native_qr: true,
+            density: None,
+            speed: None,
+        }
+    }
+
+    pub fn with_cjk(mut self, cjk: Option<CjkEncoding>) -> Self {
+        self.cjk = cjk;
+        self
+    }
+
+    pub fn with_raster_text(mut self, raster_text: bool) -> Self {
+        self.raster_text = raster_text;
+        self
+    }
+
+    pub fn with_native_qr(mut self, native_qr: bool) -> Self {
+        self.native_qr = native_qr;
+        self
+    }
+
+    pub fn with_print_settings(mut self, density: Option<i8>, speed: Option<PrintSpeed>) -> Self {
+        self.density = density;
+        self.speed = speed;
+        self
+    }
+
+    fn code_pages(&self) -> &[CodePage] {
+        if self.code_pages.is_empty() {
+            DEFAULT_CODE_PAGES
+        } else {
+            &self.code_pages
+        }
+    }
+}
+
+impl CommandEncoder for PrinterEncoder {
+    fn encode_into(&self, command: &PrintCommand, bytes: &mut Vec<u8>) {
+        #[cfg(feature = "raster-text")]
+        if let PrintCommand::WriteLine(text) = command {
+            if self.raster_text
+                && !codepage::can_encode(text, self.code_pages(), self.cjk, self.protocol)
+            {
+                let image = PrintCommand::Image(crate::raster_text::render_line(text));
+                self.encode_into(&image, bytes);
+                return;
+            }
+        }
+        if let PrintCommand::QRCode {
+            content,
+            size,
+            error_correction,
+        } = command
+        {
+            if !self.native_qr {
+                let fallback = match image_print::qr_raster(content, *size, *error_correction) {
+                    Ok(raster) => PrintCommand::Image(raster),
+                    Err(e) => {
+                        log::warn!("Cannot draw QR code ({}), printing its text instead", e);
+                        PrintCommand::WriteLine(content.clone())
+                    }
+                };
+                self.encode_into(&fallback, bytes);
+                return;
+            }
+        }
+        match self.protocol {
+            Protocol::EscPos => encode_escpos_command(command, self.code_pages(), self.cjk, bytes),
+            Protocol::Star => encode_star_command(command, self.code_pages(), self.cjk, bytes),
+        }
+        // Initializing may reset them, so they follow every ESC @
+        if *command == PrintCommand::Init {
+            print_settings(self.protocol, self.density, self.speed, bytes);
+        }
+    }
+}
+
+// ==================== Streaming ====================
+
+/// Encodes commands as they are rendered and writes them out in chunks.
+///
+/// At most one chunk plus the largest single command (usually a logo image)
+/// is held in memory, however long the job is. A write error is kept and
+/// reported from [`CommandSink::check`], which stops the render at the next
+/// element instead of rendering the rest of the job into a dead connection.
+pub struct StreamEncoder<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+    chunk_size: usize,
+    encoder: Box<dyn CommandEncoder>,
+    error: Option<String>,
+}
+
+impl<W: Write> StreamEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_chunk_size(writer, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(writer: W, chunk_size: usize) -> Self {
+        Self {
+            writer,
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_size: chunk_size.max(1),
+            encoder: Box::new(EscPosEncoder),
+            error: None,
+        }
+    }
+
+    /// Encode with `encoder` instead of ESC/POS
+    pub fn with_encoder(mut self, encoder: impl CommandEncoder + 'static) -> Self {
+        self.encoder = Box::new(encoder);
+        self
+    }
+
+    /// Write out anything still buffered and flush the writer
+    pub fn finish(mut self) -> Result<W, String> {
+        self.write_buffer();
+        if self.error.is_none() {
+            if let Err(e) = self.writer.flush() {
+                self.error = Some(e.to_string());
+            }
+        }
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.writer),
+        }
+    }
+
+    fn write_buffer(&mut self) {
+        if self.error.is_none() && !self.buffer.is_empty() {
+            if let Err(e) = self.writer.write_all(&self.buffer) {
+                self.error = Some(e.to_string());
+            }
+        }
+        self.buffer.clear();
+    }
+}
+
+impl<W: Write> CommandSink for StreamEncoder<W> {
+    fn push(&mut self, command: PrintCommand) {
+        self.encoder.encode_into(&command, &mut self.buffer);
+        if self.buffer.len() >= self.chunk_size {
+            self.write_buffer();
+        }
+    }
+
+    fn check(&mut self) -> Result<(), String> {
+        match &self.error {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template_render::QrErrorCorrection;
+
+    #[test]
+    fn test_star_encodes_text_styles() {
+        let bytes = StarEncoder.encode_all(&[
+            PrintCommand::Init,
+            PrintCommand::Align("center".to_string()),
+            PrintCommand::Bold(true),
+            PrintCommand::Size(2, 2),
+            PrintCommand::WriteLine("TOTAL".to_string()),
+            PrintCommand::Bold(false),
+            PrintCommand::Cut,
+        ]);
+        assert_eq!(
+            bytes,
+            b"\x1b@\x1b\x1da\x01\x1bE\x1bi\x01\x01TOTAL\n\x1bF\x1bd\x01"
+        );
+    }
+
+    #[test]
+    fn test_star_encodes_codes_and_images() {
+        let bytes = StarEncoder.encode_all(&[PrintCommand::Barcode {
+            content: "123".to_string(),
+            format: "CODE39".to_string(),
+            height: 80,
+            width: 3,
+            hri: HriPosition::Below,
+        }]);
+        assert_eq!(bytes, b"\x1bb42\x02\x50123\x1e");
+
+        let bytes = StarEncoder.encode_all(&[PrintCommand::QRCode {
+            content: "hi".to_string(),
+            size: 4,
+            error_correction: QrErrorCorrection::M,
+        }]);
+        assert!(bytes.starts_with(b"\x1b\x1dyS0\x02\x1b\x1dyS1\x01\x1b\x1dyS2\x04"));
+        assert!(bytes.ends_with(b"\x1b\x1dyD1\x00\x02\x00hi\x1b\x1dyP\n"));
+
+        let raster = vec![0x1D, 0x76, 0x30, 0x00, 1, 0, 2, 0, 0xF0, 0x0F];
+        let bytes = StarEncoder.encode_all(&[PrintCommand::Image(raster)]);
+        assert_eq!(bytes, [0x1B, 0x1D, 0x53, 0x01, 1, 0, 2, 0, 0, 0xF0, 0x0F]);
+    }
+
+    /// Text only, as a stand-in for a command set added outside this module
+    struct TextEncoder;
+
+    impl CommandEncoder for TextEncoder {
+        fn encode_into(&self, command: &PrintCommand, bytes: &mut Vec<u8>) {
+            if let PrintCommand::WriteLine(line) = command {
+                bytes.extend_from_slice(line.as_bytes());
+                bytes.push(b'\n');
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_encoder_takes_any_encoder() {
+        let commands = [
+            PrintCommand::Bold(true),
+            PrintCommand::WriteLine("TOTAL".to_string()),
+            PrintCommand::Cut,
+        ];
+        let mut encoder = StreamEncoder::new(Vec::new()).with_encoder(TextEncoder);
+        for command in &commands {
+            encoder.push(command.clone());
+        }
+        assert_eq!(encoder.finish().unwrap(), b"TOTAL\n");
+
+        let mut encoder = StreamEncoder::new(Vec::new()).with_encoder(Protocol::Star);
+        encoder.push(PrintCommand::Cut);
+        assert_eq!(encoder.finish().unwrap(), b"\x1bd\x01");
+        assert_eq!(
+            Protocol::EscPos.encode_all(&commands),
+            encode_commands(&commands)
+        );
+    }
+
+    #[test]
+    fn test_qr_codes_are_native_or_rasters() {
+        let qr = PrintCommand::QRCode {
+            content: "hi".to_string(),
+            size: 20,
+            error_correction: QrErrorCorrection::Q,
+        };
+        let bytes = encode_commands(std::slice::from_ref(&qr));
+        assert!(bytes.starts_with(&[
+            0x1D, 0x28, 0x6B, 4, 0, 0x31, 0x41, 0x32, 0x00, // model 2
+            0x1D, 0x28, 0x6B, 3, 0, 0x31, 0x43, 16, // size clamped to 16
+            0x1D, 0x28, 0x6B, 3, 0, 0x31, 0x45, b'2', // level Q
+            0x1D, 0x28, 0x6B, 5, 0, 0x31, 0x50, 0x30, b'h', b'i',
+        ]));
+        assert!(bytes.ends_with(&[0x1D, 0x28, 0x6B, 3, 0, 0x31, 0x51, 0x30]));
+
+        // Without native QR the code goes out as an image
+        let encoder = PrinterEncoder::new(Protocol::EscPos, Vec::new()).with_native_qr(false);
+        assert!(encoder.encode(&qr).starts_with(&[0x1D, 0x76, 0x30, 0x00]));
+        let star = PrinterEncoder::new(Protocol::Star, Vec::new()).with_native_qr(false);
+        assert!(star.encode(&qr).starts_with(&[0x1B, 0x1D, 0x53, 0x01]));
+    }
+
+    #[test]
+    fn test_density_and_speed_follow_init() {
+        let encoder = PrinterEncoder::new(Protocol::EscPos, Vec::new())
+            .with_print_settings(Some(-2), Some(PrintSpeed::Slow));
+        assert_eq!(
+            encoder.encode(&PrintCommand::Init),
+            b"\x1b@\x1d(K\x02\x001\xfe\x1d(K\x02\x002\x01"
+        );
+        let star = PrinterEncoder::new(Protocol::Star, Vec::new())
+            .with_print_settings(Some(9), Some(PrintSpeed::Fast));
+        assert_eq!(
+            star.encode(&PrintCommand::Init),
+            b"\x1b@\x1b\x1ed\x00\x1b\x1er\x00"
+        );
+        // Unset leaves the printer's own settings
+        let plain = PrinterEncoder::new(Protocol::EscPos, Vec::new());
+        assert_eq!(plain.encode(&PrintCommand::Init), b"\x1b@");
+    }
+
+    #[cfg(feature = "raster-text")]
+    #[test]
+    fn test_lines_without_characters_print_as_bitmaps() {
+        let encoder = PrinterEncoder::new(Protocol::EscPos, Vec::new()).with_raster_text(true);
+        assert_eq!(
+            encoder.encode(&PrintCommand::WriteLine("Café".to_string())),
+            b"Caf\x1bt\x00\x82\n"
+        );
+        let bytes = encoder.encode(&PrintCommand::WriteLine("شكرا".to_string()));
+        assert!(bytes.starts_with(&[0x1D, 0x76, 0x30, 0x00, 6, 0, 24, 0]));
+
+        let star = PrinterEncoder::new(Protocol::Star, Vec::new()).with_raster_text(true);
+        let bytes = star.encode(&PrintCommand::WriteLine("😀".to_string()));
+        assert!(bytes.starts_with(&[0x1B, 0x1D, 0x53, 0x01]));
+    }
+}
@@ -0,0 +1,144 @@
+// src/golden.rs
+// Golden-file tests: fixture receipts rendered to text and ESC/POS bytes,
+// compared with the output recorded in testdata/golden
+
+use std::path::{Path, PathBuf};
+
+use crate::encoder::encode_commands;
+use crate::template_render::{load_template, ReceiptData, TemplateRenderer};
+use crate::text_preview::render_text;
+
+/// Each `<name>.template.json` here with a `<name>.data.json` is a fixture
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata");
+/// `<name>.txt` and `<name>.hex` for each fixture
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/golden");
+
+/// The fixtures' names and template paths, sorted by name
+fn fixtures() -> Vec<(String, PathBuf)> {
+    let mut fixtures: Vec<_> = std::fs::read_dir(FIXTURES)
+        .unwrap()
+        .filter_map(|entry| {
+            let path = entry.unwrap().path();
+            let file_name = path.file_name()?.to_str()?;
+            let name = file_name.strip_suffix(".template.json")?.to_string();
+            Some((name, path))
+        })
+        .filter(|(name, _)| {
+            Path::new(FIXTURES)
+                .join(format!("{}.data.json", name))
+                .is_file()
+        })
+        .collect();
+    fixtures.sort();
+    fixtures
+}
+
+/// The fixture as the text preview and as a hex dump of its ESC/POS bytes
+fn render_fixture(name: &str, template: &Path) -> Result<[(&'static str, String); 2], String> {
+    let read = |path: &Path| std::fs::read_to_string(path).map_err(|e| e.to_string());
+    let template = load_template(&read(template)?).map_err(|e| e.to_string())?;
+    let data_path = Path::new(FIXTURES).join(format!("{}.data.json", name));
+    let data: ReceiptData = serde_json::from_str(&read(&data_path)?).map_err(|e| e.to_string())?;
+
+    let paper_width = template.paper_width.unwrap_or(48);
+    let commands = TemplateRenderer::new(paper_width).render_template(&template, &data)?;
+    Ok([
+        ("txt", render_text(&commands, paper_width)),
+        ("hex", hex_dump(&encode_commands(&commands))),
+    ])
+}
+
+/// 16 bytes a line: offset, hex and the printable ASCII characters
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        dump.push_str(&format!(
+            "{:08x}  {:<47}  |{}|\n",
+            i * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    dump
+}
+
+/// Where `rendered` first departs from `golden`, line by line
+fn first_difference(golden: &str, rendered: &str) -> Option<String> {
+    let golden = golden.replace("\r\n", "\n");
+    let mut expected = golden.lines();
+    let mut actual = rendered.lines();
+    for line in 1.. {
+        match (expected.next(), actual.next()) {
+            (None, None) => return None,
+            (e, a) if e == a => {}
+            (e, a) => {
+                return Some(format!(
+                    "line {}\n  golden:   {}\n  rendered: {}",
+                    line,
+                    e.unwrap_or("(end of file)"),
+                    a.unwrap_or("(end of file)")
+                ))
+            }
+        }
+    }
+    None
+}
+
+#[test]
+fn test_fixtures_match_golden_files() {
+    let fixtures = fixtures();
+    assert!(!fixtures.is_empty(), "no fixtures in {}", FIXTURES);
+
+    let mut failures = Vec::new();
+    for (name, template) in &fixtures {
+        let dumps = match render_fixture(name, template) {
+            Ok(dumps) => dumps,
+            Err(e) => {
+                failures.push(format!("{}: {}", name, e));
+                continue;
+            }
+        };
+        for (extension, rendered) in dumps {
+            let path = Path::new(GOLDEN).join(format!("{}.{}", name, extension));
+            if cfg!(feature = "update-golden") {
+                std::fs::create_dir_all(GOLDEN).unwrap();
+                std::fs::write(&path, rendered).unwrap();
+                continue;
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(golden) => {
+                    if let Some(difference) = first_difference(&golden, &rendered) {
+                        failures.push(format!("{} differs at {}", path.display(), difference));
+                    }
+                }
+                Err(e) => failures.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\n\nIf the new output is right, record it with \
+         `cargo test --features update-golden golden` and review the diff",
+        failures.join("\n\n")
+    );
+}
+
+#[test]
+fn test_hex_dump_shows_offsets_and_text() {
+    assert_eq!(
+        hex_dump(b"\x1b@Hello, receipt!\n\x1dV\x01"),
+        "00000000  1b 40 48 65 6c 6c 6f 2c 20 72 65 63 65 69 70 74  |.@Hello, receipt|\n\
+         00000010  21 0a 1d 56 01                                   |!..V.|\n"
+    );
+}
@@ -0,0 +1,337 @@
+// src/hotplug.rs
+// Notice USB printers and serial adapters being plugged in or removed
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Quiet time after the last device event before it is reported. Plugging
+/// in a printer raises a burst of events, and its device node only appears
+/// once the driver has bound.
+pub const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Called once USB devices have been plugged in or removed and settled
+pub type HotplugListener = Box<dyn Fn() + Send>;
+
+/// Watches for USB devices coming and going: kernel uevents on Linux,
+/// device interface notifications on Windows.
+///
+/// Events don't say which printer changed, so the listener is expected to
+/// check them all, e.g. through [`CheckTrigger`](crate::reconnect::CheckTrigger).
+/// The watcher stops when dropped.
+pub struct HotplugWatcher {
+    /// Feeds the settle thread; dropping it ends that thread
+    source: Option<platform::Source>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HotplugWatcher {
+    /// Start watching. Fails where the OS has no device notifications, in
+    /// which case printers are only checked on the reconnect interval.
+    pub fn spawn(on_change: HotplugListener) -> Result<Self, String> {
+        let (events, received) = mpsc::channel::<()>();
+        let source = platform::watch(events)?;
+        let thread = std::thread::Builder::new()
+            .name("usb-hotplug".to_string())
+            .spawn(move || settle(received, on_change))
+            .map_err(|e| format!("Cannot start USB hotplug thread: {}", e))?;
+        Ok(Self {
+            source: Some(source),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for HotplugWatcher {
+    fn drop(&mut self) {
+        self.source.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Report each burst of events once it has been quiet for [`SETTLE_TIME`]
+fn settle(events: mpsc::Receiver<()>, on_change: HotplugListener) {
+    while events.recv().is_ok() {
+        loop {
+            match events.recv_timeout(SETTLE_TIME) {
+                Ok(()) => {}
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        on_change();
+    }
+}
+
+/// Whether a kernel uevent (`add@/devices/...\0ACTION=add\0SUBSYSTEM=usb...`)
+/// is a USB device, USB printer port or serial port being added or removed
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn is_device_event(message: &[u8]) -> bool {
+    let mut action = None;
+    let mut subsystem = None;
+    for field in message.split(|&b| b == 0) {
+        if let Some(value) = field.strip_prefix(b"ACTION=") {
+            action = Some(value);
+        } else if let Some(value) = field.strip_prefix(b"SUBSYSTEM=") {
+            subsystem = Some(value);
+        }
+    }
+    matches!(action, Some(b"add" | b"remove"))
+        && matches!(subsystem, Some(b"usb" | b"usbmisc" | b"tty"))
+}
+
+// ==================== Linux ====================
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread::JoinHandle;
+
+    use super::is_device_event;
+
+    /// How often the reader looks up from the socket to see if it should stop
+    const POLL_MS: i32 = 250;
+    /// The kernel's uevent multicast group, as opposed to udev's
+    const KERNEL_EVENTS: u32 = 1;
+
+    /// A thread reading the kernel's uevent socket
+    pub(super) struct Source {
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    pub(super) fn watch(events: mpsc::Sender<()>) -> Result<Source, String> {
+        let error = || {
+            format!(
+                "Cannot watch for USB devices: {}",
+                std::io::Error::last_os_error()
+            )
+        };
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = KERNEL_EVENTS;
+        let bound = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if bound != 0 {
+            return Err(error());
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = std::thread::Builder::new()
+            .name("usb-uevents".to_string())
+            .spawn(move || read_events(socket, events, &stopped))
+            .map_err(|e| format!("Cannot start USB hotplug thread: {}", e))?;
+        Ok(Source {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    fn read_events(socket: OwnedFd, events: mpsc::Sender<()>, stop: &AtomicBool) {
+        let mut buffer = vec![0u8; 8192];
+        while !stop.load(Ordering::Relaxed) {
+            let mut ready = libc::pollfd {
+                fd: socket.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut ready, 1, POLL_MS) } <= 0 {
+                continue;
+            }
+            let n = unsafe {
+                libc::recv(
+                    socket.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            let changed = if n >= 0 {
+                is_device_event(&buffer[..n as usize])
+            } else {
+                let error = std::io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(libc::EINTR) => false,
+                    // Events were dropped while the socket was full
+                    Some(libc::ENOBUFS) => true,
+                    _ => {
+                        log::warn!("Stopped watching for USB devices: {}", error);
+                        return;
+                    }
+                }
+            };
+            if changed && events.send(()).is_err() {
+                return;
+            }
+        }
+    }
+
+    impl Drop for Source {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+// ==================== Windows ====================
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use std::sync::mpsc;
+
+    use windows_sys::Win32::Devices::DeviceAndDriverInstallation::{
+        CM_Register_Notification, CM_Unregister_Notification, CM_NOTIFY_ACTION,
+        CM_NOTIFY_ACTION_DEVICEINTERFACEARRIVAL, CM_NOTIFY_ACTION_DEVICEINTERFACEREMOVAL,
+        CM_NOTIFY_EVENT_DATA, CM_NOTIFY_FILTER, CM_NOTIFY_FILTER_FLAG_ALL_INTERFACE_CLASSES,
+        CM_NOTIFY_FILTER_TYPE_DEVICEINTERFACE, CR_SUCCESS, HCMNOTIFICATION,
+    };
+
+    /// A device interface notification registered with the configuration
+    /// manager, which calls back on its own threads
+    pub(super) struct Source {
+        handle: HCMNOTIFICATION,
+        events: *mut mpsc::Sender<()>,
+    }
+
+    // The handle and sender are only released by whoever owns the source
+    unsafe impl Send for Source {}
+
+    pub(super) fn watch(events: mpsc::Sender<()>) -> Result<Source, String> {
+        let filter = CM_NOTIFY_FILTER {
+            cbSize: std::mem::size_of::<CM_NOTIFY_FILTER>() as u32,
+            Flags: CM_NOTIFY_FILTER_FLAG_ALL_INTERFACE_CLASSES,
+            FilterType: CM_NOTIFY_FILTER_TYPE_DEVICEINTERFACE,
+            ..Default::default()
+        };
+
+        let events = Box::into_raw(Box::new(events));
+        let mut handle: HCMNOTIFICATION = std::ptr::null_mut();
+        let result = unsafe {
+            CM_Register_Notification(
+                &filter,
+                events as *const std::ffi::c_void,
+                Some(on_notification),
+                &mut handle,
+            )
+        };
+        if result != CR_SUCCESS {
+            drop(unsafe { Box::from_raw(events) });
+            return Err(format!(
+                "Cannot watch for USB devices: configuration manager error {}",
+                result
+            ));
+        }
+        Ok(Source { handle, events })
+    }
+
+    /// Every interface class is watched, so this fires for more than
+    /// printers; a spare check is cheap
+    unsafe extern "system" fn on_notification(
+        _notify: HCMNOTIFICATION,
+        context: *const std::ffi::c_void,
+        action: CM_NOTIFY_ACTION,
+        _data: *const CM_NOTIFY_EVENT_DATA,
+        _size: u32,
+    ) -> u32 {
+        if action == CM_NOTIFY_ACTION_DEVICEINTERFACEARRIVAL
+            || action == CM_NOTIFY_ACTION_DEVICEINTERFACEREMOVAL
+        {
+            let events = &*(context as *const mpsc::Sender<()>);
+            let _ = events.send(());
+        }
+        0
+    }
+
+    impl Drop for Source {
+        fn drop(&mut self) {
+            // Waits for callbacks in progress, so the sender can go after
+            unsafe {
+                CM_Unregister_Notification(self.handle);
+                drop(Box::from_raw(self.events));
+            }
+        }
+    }
+}
+
+// ==================== Other Platforms ====================
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+mod platform {
+    use std::sync::mpsc;
+
+    pub(super) struct Source;
+
+    pub(super) fn watch(_events: mpsc::Sender<()>) -> Result<Source, String> {
+        Err("USB hotplug detection is not supported on this platform".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_is_device_event() {
+        let event = |action: &str, subsystem: &str| {
+            format!(
+                "{}@/devices/pci0000:00/usb1/1-1\0ACTION={}\0DEVPATH=/devices/pci0000:00/usb1/1-1\0SUBSYSTEM={}\0SEQNUM=4242",
+                action, action, subsystem
+            )
+        };
+        assert!(is_device_event(event("add", "usb").as_bytes()));
+        assert!(is_device_event(event("remove", "usbmisc").as_bytes()));
+        assert!(is_device_event(event("add", "tty").as_bytes()));
+        assert!(!is_device_event(event("bind", "usb").as_bytes()));
+        assert!(!is_device_event(event("add", "block").as_bytes()));
+        assert!(!is_device_event(b"libudev\0garbage"));
+    }
+
+    #[test]
+    fn test_settle_reports_each_burst_once() {
+        let (events, received) = mpsc::channel();
+        let calls = Arc::new(Mutex::new(0));
+        let counted = Arc::clone(&calls);
+        let thread = std::thread::spawn(move || {
+            settle(received, Box::new(move || *counted.lock().unwrap() += 1));
+        });
+
+        for _ in 0..5 {
+            events.send(()).unwrap();
+        }
+        std::thread::sleep(SETTLE_TIME * 3);
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        events.send(()).unwrap();
+        drop(events);
+        thread.join().unwrap();
+        // A burst cut short by shutdown isn't reported
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,232 @@
+// src/html_preview.rs
+// Print jobs as HTML pages, for previews in the web app and receipts that
+// can be printed from a browser
+
+use std::fmt::Write;
+use std::io::Cursor;
+
+use base64::Engine;
+use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+
+use crate::pdf::{layout, Align, Block, Run, CHAR_WIDTH};
+use crate::template_render::{Font, PrintCommand};
+
+/// Lay rendered print commands out as an HTML page, each receipt a column
+/// of `paper_width` monospace characters.
+///
+/// Styles are inline on each run of text, so the receipts can be copied
+/// into another page as they are. QR codes and barcodes are drawn as SVG and
+/// images as PNG, at their printed size.
+pub fn render_html(commands: &[PrintCommand], paper_width: u32) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Receipt</title>\n</head>\n<body style=\"margin:16px;background:#eee\">\n",
+    );
+    for blocks in layout(commands) {
+        // No white space between the lines, which `pre` would show
+        let _ = write!(
+            html,
+            "<div style=\"width:{}ch;margin:0 auto 16px;padding:1ch;background:#fff;color:#000;font-family:monospace;line-height:1.25;white-space:pre;overflow:hidden\">",
+            paper_width
+        );
+        for block in &blocks {
+            block_html(&mut html, block);
+        }
+        html.push_str("</div>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn block_html(html: &mut String, block: &Block) {
+    match block {
+        Block::Line { runs, align } => {
+            let _ = write!(html, "<div style=\"text-align:{}\">", align_css(*align));
+            if runs.is_empty() {
+                // Keeps the height of a blank line
+                html.push(' ');
+            }
+            for run in runs {
+                run_html(html, run);
+            }
+            html.push_str("</div>");
+        }
+        Block::Raster {
+            bytes_per_row,
+            rows,
+            data,
+        } => {
+            let width = bytes_per_row * 8;
+            let _ = write!(
+                html,
+                "<div><img alt=\"\" src=\"data:image/png;base64,{}\" style=\"display:block;width:{:.2}ch;image-rendering:pixelated\"></div>",
+                raster_png(width, *rows, data),
+                columns(width)
+            );
+        }
+        Block::Shapes {
+            width,
+            height,
+            rects,
+            align,
+        } => {
+            let _ = write!(
+                html,
+                "<div style=\"text-align:{}\"><svg viewBox=\"0 0 {} {}\" style=\"width:{:.2}ch;vertical-align:top\" shape-rendering=\"crispEdges\">",
+                align_css(*align),
+                width,
+                height,
+                columns(*width)
+            );
+            for (x, y, w, h) in rects {
+                let _ = write!(
+                    html,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>",
+                    x, y, w, h
+                );
+            }
+            html.push_str("</svg></div>");
+        }
+    }
+}
+
+/// One run of text as a span with its style inline
+fn run_html(html: &mut String, run: &Run) {
+    let style = run.style;
+    let mut css = String::new();
+    if style.bold || style.double_strike {
+        css.push_str("font-weight:bold;");
+    }
+    if style.underline {
+        css.push_str("text-decoration:underline;");
+    }
+    if style.reverse {
+        css.push_str("background:#000;color:#fff;");
+    }
+    // Characters are `width` columns wide and `height` lines tall; a
+    // narrower font keeps its height but takes less of each column
+    let font_width = style.font.char_width() as f32 / Font::A.char_width() as f32;
+    let advance = style.width as f32 * font_width;
+    if style.height != 1 {
+        let _ = write!(css, "font-size:{}em;", style.height);
+    }
+    if advance != style.height as f32 {
+        let spacing = (advance - style.height as f32) / style.height as f32;
+        let _ = write!(css, "letter-spacing:{}ch;", spacing);
+    }
+
+    if css.is_empty() {
+        escape_into(html, &run.text);
+    } else {
+        let _ = write!(html, "<span style=\"{}\">", css);
+        escape_into(html, &run.text);
+        html.push_str("</span>");
+    }
+}
+
+fn align_css(align: Align) -> &'static str {
+    match align {
+        Align::Left => "left",
+        Align::Center => "center",
+        Align::Right => "right",
+    }
+}
+
+/// Characters of font A as wide as `dots`
+fn columns(dots: u32) -> f32 {
+    dots as f32 / CHAR_WIDTH as f32
+}
+
+/// 1-bit rows as a base64 PNG
+fn raster_png(width: u32, rows: u32, data: &[u8]) -> String {
+    let bytes_per_row = width / 8;
+    let image = GrayImage::from_fn(width, rows, |x, y| {
+        let byte = data[(y * bytes_per_row + x / 8) as usize];
+        if byte & (0x80 >> (x % 8)) != 0 {
+            Luma([0])
+        } else {
+            Luma([255])
+        }
+    });
+    let mut png = Vec::new();
+    DynamicImage::ImageLuma8(image)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .expect("a PNG can be written to memory");
+    base64::engine::general_purpose::STANDARD.encode(png)
+}
+
+fn escape_into(html: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            _ => html.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receipt_becomes_styled_html() {
+        let commands = [
+            PrintCommand::Init,
+            PrintCommand::Align("center".to_string()),
+            PrintCommand::Bold(true),
+            PrintCommand::Size(2, 2),
+            PrintCommand::WriteLine("Fish & Chips".to_string()),
+            PrintCommand::Size(1, 1),
+            PrintCommand::Bold(false),
+            PrintCommand::Align("left".to_string()),
+            PrintCommand::Reverse(true),
+            PrintCommand::Write("<TOTAL>".to_string()),
+            PrintCommand::Reverse(false),
+            PrintCommand::WriteLine("  9.50".to_string()),
+            PrintCommand::Feed(1),
+            PrintCommand::Barcode {
+                content: "1001".to_string(),
+                format: "CODE128".to_string(),
+                height: 60,
+                width: 2,
+                hri: Default::default(),
+            },
+            PrintCommand::Cut,
+            PrintCommand::WriteLine("Copy".to_string()),
+        ];
+        let html = render_html(&commands, 32);
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert_eq!(html.matches("width:32ch").count(), 2, "one column per cut");
+        assert!(html.contains(
+            "<div style=\"text-align:center\"><span style=\"font-weight:bold;font-size:2em;\">Fish &amp; Chips</span></div>"
+        ));
+        assert!(html.contains(
+            "<div style=\"text-align:left\"><span style=\"background:#000;color:#fff;\">&lt;TOTAL&gt;</span>  9.50</div>"
+        ));
+        assert!(html.contains("<div style=\"text-align:left\"> </div>"));
+        assert!(html.contains("<svg viewBox=\"0 0 "));
+        assert!(html.contains("<rect "));
+    }
+
+    #[test]
+    fn test_images_are_embedded_as_png() {
+        let mut raster = vec![0x1D, 0x76, 0x30, 0x00, 2, 0, 2, 0];
+        raster.extend([0xFF, 0x00, 0x00, 0xFF]);
+        let html = render_html(&[PrintCommand::Image(raster)], 48);
+
+        let start = html.find("base64,").unwrap() + "base64,".len();
+        let end = start + html[start..].find('"').unwrap();
+        let png = base64::engine::general_purpose::STANDARD
+            .decode(&html[start..end])
+            .unwrap();
+        let decoded = image::load_from_memory(&png).unwrap().to_luma8();
+        assert_eq!(decoded.dimensions(), (16, 2));
+        assert_eq!(decoded.get_pixel(0, 0), &Luma([0]));
+        assert_eq!(decoded.get_pixel(0, 1), &Luma([255]));
+        assert_eq!(decoded.get_pixel(15, 1), &Luma([0]));
+        assert!(html.contains("width:1.33ch"));
+    }
+}
@@ -2,20 +2,42 @@
 // HTTP server for integration with Nexora POS web app using Axum
 
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    extract::{FromRequest, Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{delete, get, post},
     Json, Router,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use tower_http::cors::{Any, CorsLayer};
 
+use crate::backend::{PrintBackend, PrinterState};
+use crate::codepage::{CjkEncoding, CodePage};
+use crate::encoder::{PrintSpeed, Protocol, DENSITY_RANGE};
+use crate::image_print::{Dithering, ImageOptions};
+use crate::lock::LockExt;
+use crate::print_worker::PrintWorker;
+use crate::printer::{
+    PrintOutput, PrintSnapshot, PrinterInfo, DEFAULT_PRINTER, STREAMING_ROW_THRESHOLD,
+};
+use crate::profiles::{PrinterProfile, PROFILES};
+use crate::reconnect::ConnectionState;
+use crate::routing::{route_items, RoutingRule};
+use crate::telemetry::PrintStats;
+use crate::template_parse::{parse_template_json, TemplateParseError};
+use crate::template_compile::{check_conditions, check_layout};
 use crate::{
-    PrinterManager, ReceiptData, ReceiptTemplate, TemplateRenderer,
-    BarcodePrinterManager, BarcodePrinterConfig, BarcodeType, BarcodeLabelRequest,
+    ConditionIssue, Diagnostic, PrinterConfig, PrinterManager, ReceiptData, ReceiptTemplate, SetTemplateError,
+    PrintArea, TemplateCacheStats, TemplateRenderer, TemplateReplacement, VersionConflict,
+    BarcodePrinterManager, BarcodePrinterConfig, BarcodeType, BarcodeLabelRequest, SerialSettings,
+    Severity, Timeouts, WritePacing,
 };
 
+/// How long `/status` waits for the printer to report its state
+const PRINTER_STATE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
 // ==================== Request/Response Types ====================
 
 #[derive(Debug, Deserialize)]
@@ -34,6 +56,18 @@ pub struct PrintItem {
     pub name: String,
     pub quantity: u32,
     pub price: f64,
+    #[serde(default)]
+    pub sku: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub discount: Option<f64>,
+    #[serde(default)]
+    pub tax: Option<f64>,
+    #[serde(default)]
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub note: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -45,19 +79,165 @@ pub struct ApiResponse {
 #[derive(Debug, Deserialize)]
 pub struct SetTemplateRequest {
     pub template: ReceiptTemplate,
+    /// Replace the cached template even if it has a newer version
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ValidateTemplateRequest {
+    /// Kept as JSON so fields the template doesn't have can be reported
+    pub template: serde_json::Value,
+    /// Characters per line to check widths for; the template's own if unset
+    #[serde(default)]
+    pub paper_width: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateValidationResponse {
+    pub success: bool,
+    pub valid: bool,
+    pub message: String,
+    /// Every problem found, errors and warnings, with its path
+    pub diagnostics: Vec<Diagnostic>,
+    pub issues: Vec<ConditionIssue>,
+    /// Problems that don't make the template invalid, such as absurd spacing
+    pub warnings: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PrintTemplateRequest {
     pub template_id: Option<String>,
     pub template: Option<ReceiptTemplate>,
+    #[serde(default)]
+    pub force: bool,
+    /// Print on every connected printer with this role instead of the
+    /// default printer
+    #[serde(default)]
+    pub role: Option<String>,
     pub data: ReceiptData,
 }
 
+/// Response to `/print-template`. `warnings` lists print data that doesn't
+/// match the template's declared variables.
+#[derive(Debug, Serialize)]
+pub struct PrintTemplateResponse {
+    pub success: bool,
+    pub message: String,
+    pub warnings: Vec<String>,
+    /// Tickets sent to other printers by the routing rules
+    pub routed: Vec<RoutedPrintResult>,
+}
+
+impl PrintTemplateResponse {
+    fn failed(message: String, warnings: Vec<String>) -> Self {
+        Self {
+            success: false,
+            message,
+            warnings,
+            routed: Vec::new(),
+        }
+    }
+}
+
+/// Outcome of one routed ticket on one printer
+#[derive(Debug, Serialize)]
+pub struct RoutedPrintResult {
+    pub role: String,
+    /// `None` when no connected printer has the role
+    pub printer_id: Option<String>,
+    pub items: usize,
+    pub success: bool,
+    pub message: String,
+}
+
+/// 409 body when a template would replace a newer cached version
+#[derive(Debug, Serialize)]
+pub struct TemplateConflictResponse {
+    pub success: bool,
+    pub message: String,
+    #[serde(flatten)]
+    pub conflict: VersionConflict,
+}
+
+/// 422 body when a template's conditions don't parse
+#[derive(Debug, Serialize)]
+pub struct TemplateConditionsResponse {
+    pub success: bool,
+    pub message: String,
+    pub issues: Vec<ConditionIssue>,
+}
+
+fn set_template_error_response(error: SetTemplateError) -> Response {
+    let message = error.to_string();
+    match error {
+        SetTemplateError::VersionConflict(conflict) => (
+            StatusCode::CONFLICT,
+            Json(TemplateConflictResponse {
+                success: false,
+                message,
+                conflict,
+            }),
+        )
+            .into_response(),
+        SetTemplateError::InvalidConditions { issues, .. } => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(TemplateConditionsResponse {
+                success: false,
+                message,
+                issues,
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// 400 body for a request whose template (or data) doesn't parse
+#[derive(Debug, Serialize)]
+pub struct TemplateParseErrorResponse {
+    pub success: bool,
+    pub message: String,
+    pub error: TemplateParseError,
+}
+
+/// JSON body extractor for requests carrying a template. Unlike `Json`, a
+/// parse failure answers 400 with the path, line and column of the problem
+/// so the template editor can jump to it.
+pub struct TemplateJson<T>(pub T);
+
+#[axum::async_trait]
+impl<T, S> FromRequest<S> for TemplateJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let body = String::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        parse_template_json(&body).map(TemplateJson).map_err(|error| {
+            log::warn!("Rejected template request: {}", error);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(TemplateParseErrorResponse {
+                    success: false,
+                    message: format!("Failed to parse template: {}", error),
+                    error,
+                }),
+            )
+                .into_response()
+        })
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct TemplateCacheResponse {
     pub templates: Vec<TemplateInfoResponse>,
     pub active_template_id: Option<String>,
+    pub cache: TemplateCacheStats,
 }
 
 #[derive(Debug, Serialize)]
@@ -71,9 +251,19 @@ pub struct TemplateInfoResponse {
 #[derive(Debug, Serialize)]
 pub struct StatusResponse {
     pub connected: bool,
+    /// Tells a dropped printer that is being retried apart from one that
+    /// was never connected
+    pub connection_state: ConnectionState,
     pub active_template: Option<String>,
     pub cached_templates: usize,
+    pub template_cache_info: TemplateCacheStats,
     pub logo_cache_info: LogoCacheStatsResponse,
+    pub print_stats: PrintStats,
+    /// What the printer reports about itself, over IPP, SNMP or ESC/POS
+    /// real-time status
+    pub printer_state: Option<PrinterState>,
+    /// Every registered printer; the fields above are about `default`
+    pub printers: Vec<PrinterInfo>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -82,6 +272,14 @@ pub struct PreviewTemplateRequest {
     pub data: ReceiptData,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PreviewQuery {
+    /// A cached template, for `GET`; the active one if not given
+    pub template_id: Option<String>,
+    /// `text` (the default), `html` or `png`
+    pub format: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PrintImageRequest {
     /// Base64 image, with or without `data:image/...;base64,` prefix.
@@ -95,6 +293,8 @@ pub struct PreviewResponse {
     pub success: bool,
     pub commands: Vec<String>,
     pub text_preview: String,
+    /// Parts of the template left out at this paper width
+    pub warnings: Vec<String>,
 }
 
 // ==================== Logo Cache Types ====================
@@ -125,6 +325,27 @@ pub struct LogoCacheStatsResponse {
     pub disk_usage_bytes: u64,
 }
 
+/// A logo to store in a printer's NV memory
+#[derive(Debug, Deserialize)]
+pub struct NvLogoRequest {
+    /// 1 to 99; templates print it with `"nv_slot"`
+    pub slot: u8,
+    /// A cached logo id, base64 image data, a file path or a URL
+    pub source: String,
+    #[serde(default)]
+    pub max_width: Option<u32>,
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    #[serde(default)]
+    pub dither: Option<Dithering>,
+    #[serde(default)]
+    pub threshold: Option<u8>,
+    #[serde(default)]
+    pub brightness: Option<i32>,
+    #[serde(default)]
+    pub contrast: Option<i32>,
+}
+
 // ==================== Barcode Printer Types ====================
 
 #[derive(Debug, Deserialize)]
@@ -135,6 +356,73 @@ pub struct BarcodePrinterConnectRequest {
     pub label_width_mm: u32,
     pub label_height_mm: u32,
     pub dpi: u32,
+    /// Line settings for a printer on a serial port; 9600 8N1 if omitted
+    #[serde(default)]
+    pub serial: SerialSettings,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PrinterConnectRequest {
+    pub connection_type: String,
+    pub device_path: String,
+    /// Line settings for a printer on a serial port; 9600 8N1 if omitted
+    #[serde(default)]
+    pub serial: SerialSettings,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    /// Roles such as "kitchen" or "bar" for role-targeted prints and routing
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// "escpos" or "star"; unset uses the printer's profile, else ESC/POS
+    #[serde(default)]
+    pub protocol: Option<Protocol>,
+    /// Built-in model profile such as "epson-tm-t20", see `GET /profiles`;
+    /// unset detects it from a `usb:VID:PID` device path
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Code pages such as "cp858", tried in order for accented text; unset
+    /// uses the profile's
+    #[serde(default)]
+    pub code_pages: Vec<CodePage>,
+    /// "gbk", "shift_jis" or "euc_kr" for a Chinese, Japanese or Korean model
+    #[serde(default)]
+    pub cjk_encoding: Option<CjkEncoding>,
+    /// Print lines the printer has no characters for as bitmaps
+    #[serde(default)]
+    pub raster_text: bool,
+    /// The printer is mounted upside down
+    #[serde(default)]
+    pub upside_down: bool,
+    /// -6 (lightest) to 6 (darkest); unset keeps the printer's setting
+    #[serde(default)]
+    pub density: Option<i8>,
+    /// "slow", "medium" or "fast"; unset keeps the printer's setting
+    #[serde(default)]
+    pub print_speed: Option<PrintSpeed>,
+    /// Left margin and width in dots; `{}` centres narrower templates
+    #[serde(default)]
+    pub print_area: Option<PrintArea>,
+    /// `{ "chunk_size": 512, "delay_ms": 50 }` for printers that drop data
+    /// on long jobs
+    #[serde(default)]
+    pub write_pacing: Option<WritePacing>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PrintersResponse {
+    pub printers: Vec<PrinterInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfilesResponse {
+    pub profiles: &'static [PrinterProfile],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoutingRulesBody {
+    pub rules: Vec<RoutingRule>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -149,6 +437,16 @@ pub struct PrintBarcodeRequest {
     pub label_height_mm: Option<u32>,
 }
 
+/// A label template printed on the barcode printer, inline or cached by id
+#[derive(Debug, Deserialize)]
+pub struct PrintLabelRequest {
+    pub template_id: Option<String>,
+    pub template: Option<ReceiptTemplate>,
+    pub data: ReceiptData,
+    /// Defaults to 1
+    pub copies: Option<u32>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct BarcodeStatusResponse {
     pub connected: bool,
@@ -156,6 +454,7 @@ pub struct BarcodeStatusResponse {
     pub label_width_mm: Option<u32>,
     pub label_height_mm: Option<u32>,
     pub dpi: Option<u32>,
+    pub serial: Option<SerialSettings>,
 }
 
 // ==================== App State ====================
@@ -163,10 +462,30 @@ pub struct BarcodeStatusResponse {
 pub struct AppState {
     pub printer_manager: Arc<Mutex<PrinterManager>>,
     pub barcode_manager: Arc<Mutex<BarcodePrinterManager>>,
+    pub print_worker: PrintWorker,
 }
 
 // ==================== Route Handlers ====================
 
+/// Turn print jobs away once shutdown has started, so they aren't lost
+async fn reject_while_shutting_down(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.print_worker.is_accepting() {
+        return next.run(request).await;
+    }
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse {
+            success: false,
+            message: "Print server is shutting down".to_string(),
+        }),
+    )
+        .into_response()
+}
+
 /// Health check endpoint
 async fn health() -> Json<serde_json::Value> {
     Json(serde_json::json!({"status": "healthy"}))
@@ -174,34 +493,57 @@ async fn health() -> Json<serde_json::Value> {
 
 /// Get printer and server status
 async fn status(State(state): State<Arc<AppState>>) -> Json<StatusResponse> {
-    let manager = state.printer_manager.lock().unwrap();
+    let connection = state.printer_manager.lock_or_recover().connection(DEFAULT_PRINTER);
+    let printer_state = match connection {
+        Some(connection) => query_printer_state(connection).await,
+        None => None,
+    };
+
+    let manager = state.printer_manager.lock_or_recover();
     let (count, total_size, disk_usage) = crate::logo_cache::get_cache_stats(&manager);
     Json(StatusResponse {
         connected: manager.is_connected(),
+        connection_state: manager.connection_state(),
         active_template: manager.active_template_id.clone(),
         cached_templates: manager.template_cache.len(),
+        template_cache_info: manager.template_cache.stats(),
         logo_cache_info: LogoCacheStatsResponse {
             count,
             total_size_bytes: total_size,
             disk_usage_bytes: disk_usage,
         },
+        print_stats: manager.print_stats(),
+        printer_state,
+        printers: manager.printers(),
     })
 }
 
+/// Ask the printer for its state without holding the manager lock, giving up
+/// after `PRINTER_STATE_TIMEOUT` so a dead printer can't stall `/status`
+async fn query_printer_state(connection: Arc<dyn PrintBackend>) -> Option<PrinterState> {
+    let query = tokio::task::spawn_blocking(move || connection.printer_state());
+    match tokio::time::timeout(PRINTER_STATE_TIMEOUT, query).await {
+        Ok(Ok(Ok(state))) => state,
+        Ok(Ok(Err(e))) => {
+            log::warn!("Printer state query failed: {}", e);
+            None
+        }
+        Ok(Err(e)) => {
+            log::warn!("Printer state query task failed: {}", e);
+            None
+        }
+        Err(_) => {
+            log::warn!("Printer state query timed out");
+            None
+        }
+    }
+}
+
 /// Legacy print endpoint (uses Receipt struct format)
 async fn print_legacy(
     State(state): State<Arc<AppState>>,
     Json(request): Json<PrintRequest>,
 ) -> Result<Json<ApiResponse>, StatusCode> {
-    let mut manager = state.printer_manager.lock().unwrap();
-
-    if !manager.is_connected() {
-        return Ok(Json(ApiResponse {
-            success: false,
-            message: "Printer not connected".to_string(),
-        }));
-    }
-
     // Convert to ReceiptData format for template printing
     let data = ReceiptData {
         store_name: None,
@@ -222,6 +564,14 @@ async fn print_legacy(
                 price: item.price,
                 total: item.quantity as f64 * item.price,
                 modifiers: None,
+                sku: item.sku,
+                category: item.category,
+                discount: item.discount,
+                tax: item.tax,
+                unit: item.unit,
+                course: None,
+                note: item.note,
+                seat: None,
             })
             .collect(),
         subtotal: request.subtotal,
@@ -238,7 +588,26 @@ async fn print_legacy(
         ..Default::default()
     };
 
-    match manager.print_with_template(&data) {
+    // Snapshot the template under the lock, then render and print without it
+    let snapshot = {
+        let mut manager = state.printer_manager.lock_or_recover();
+
+        if !manager.is_connected() {
+            return Ok(Json(ApiResponse {
+                success: false,
+                message: "Printer not connected".to_string(),
+            }));
+        }
+
+        manager.snapshot()
+    };
+
+    let result = match snapshot.and_then(|snapshot| snapshot.render(&data)) {
+        Ok(output) => state.print_worker.submit(output).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
         Ok(_) => Ok(Json(ApiResponse {
             success: true,
             message: format!("Receipt printed (Order #{})", request.order_id),
@@ -253,9 +622,9 @@ async fn print_legacy(
 /// Set/cache a template
 async fn set_template(
     State(state): State<Arc<AppState>>,
-    Json(request): Json<SetTemplateRequest>,
-) -> Result<Json<ApiResponse>, StatusCode> {
-    let mut manager = state.printer_manager.lock().unwrap();
+    TemplateJson(request): TemplateJson<SetTemplateRequest>,
+) -> Result<Json<ApiResponse>, Response> {
+    let mut manager = state.printer_manager.lock_or_recover();
     let template_id = request.template.id.clone();
     let mut template = request.template;
 
@@ -268,9 +637,9 @@ async fn set_template(
         }
     };
 
-    match manager.set_template(template) {
-        Ok(_) => {
-            let message = if auto_cached > 0 {
+    match manager.set_template(template, request.force) {
+        Ok(replacement) => {
+            let mut message = if auto_cached > 0 {
                 format!("Template '{}' cached and set as active (auto-cached {} logo{})", 
                     template_id, 
                     auto_cached, 
@@ -279,78 +648,431 @@ async fn set_template(
             } else {
                 format!("Template '{}' cached and set as active", template_id)
             };
+            match replacement {
+                TemplateReplacement::Upgrade { previous_version } => {
+                    message.push_str(&format!(", upgraded from version {}", previous_version));
+                }
+                TemplateReplacement::Downgrade { previous_version } => {
+                    message.push_str(&format!(", downgraded from version {}", previous_version));
+                }
+                TemplateReplacement::Overwrite { .. } => {
+                    message.push_str(", replacing different content with the same version");
+                }
+                TemplateReplacement::New | TemplateReplacement::Unchanged => {}
+            }
             Ok(Json(ApiResponse {
                 success: true,
                 message,
             }))
         }
-        Err(e) => {
-            log::error!("Failed to set template: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        }
+        Err(error) => Err(set_template_error_response(error)),
     }
 }
 
-/// Print using template
+/// Check a template without caching it
+async fn validate_template(
+    TemplateJson(request): TemplateJson<ValidateTemplateRequest>,
+) -> Json<TemplateValidationResponse> {
+    let diagnostics = crate::validate_template(&request.template, request.paper_width);
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    let id = request.template["id"].as_str().unwrap_or_default();
+    let message = if errors == 0 {
+        format!("Template '{}' is valid", id)
+    } else {
+        format!(
+            "Template '{}' has {} error{}",
+            id,
+            errors,
+            if errors == 1 { "" } else { "s" }
+        )
+    };
+
+    // A template that doesn't load only has its structure error
+    let template = serde_json::from_value::<ReceiptTemplate>(request.template).ok();
+    Json(TemplateValidationResponse {
+        success: true,
+        valid: errors == 0,
+        message,
+        diagnostics,
+        issues: template.as_ref().map(check_conditions).unwrap_or_default(),
+        warnings: template.as_ref().map(check_layout).unwrap_or_default(),
+    })
+}
+
+/// Print using template, on the default printer or every printer with the
+/// requested role, then send routed items to their printers
 async fn print_with_template(
     State(state): State<Arc<AppState>>,
-    Json(request): Json<PrintTemplateRequest>,
-) -> Result<Json<ApiResponse>, StatusCode> {
-    let mut manager = state.printer_manager.lock().unwrap();
+    TemplateJson(request): TemplateJson<PrintTemplateRequest>,
+) -> Result<Json<PrintTemplateResponse>, Response> {
+    print_template_on(&state, None, request).await
+}
 
-    // Handle inline template if provided
-    if let Some(template) = request.template {
-        if let Err(e) = manager.set_template(template) {
-            log::error!("Failed to set inline template: {}", e);
-            return Err(StatusCode::BAD_REQUEST);
+/// `/print-template` for one registered printer, without routing
+async fn print_template_on_printer(
+    State(state): State<Arc<AppState>>,
+    Path(printer_id): Path<String>,
+    TemplateJson(request): TemplateJson<PrintTemplateRequest>,
+) -> Result<Json<PrintTemplateResponse>, Response> {
+    print_template_on(&state, Some(printer_id), request).await
+}
+
+async fn print_template_on(
+    state: &AppState,
+    printer_id: Option<String>,
+    request: PrintTemplateRequest,
+) -> Result<Json<PrintTemplateResponse>, Response> {
+    let order_id = request.data.order_id.clone();
+    let data = request.data;
+
+    // Snapshot the templates under the lock, then render and print without
+    // it; template changes from here on only affect later jobs
+    let (snapshots, routed) = {
+        let mut manager = state.printer_manager.lock_or_recover();
+
+        // Handle inline template if provided
+        if let Some(template) = request.template {
+            if let Err(error) = manager.set_template(template, request.force) {
+                return Err(set_template_error_response(error));
+            }
+        } else if let Some(template_id) = &request.template_id {
+            // Verify template is cached
+            if !manager.template_cache.contains_key(template_id) {
+                return Ok(Json(PrintTemplateResponse::failed(
+                    format!(
+                        "Template '{}' not found in cache. Please set it first.",
+                        template_id
+                    ),
+                    Vec::new(),
+                )));
+            }
+
+            // Set as active if not already
+            if manager.active_template_id.as_ref() != Some(template_id) {
+                manager.active_template_id = Some(template_id.clone());
+            }
+        } else if manager.active_template_id.is_none() {
+            return Ok(Json(PrintTemplateResponse::failed(
+                "No template specified and no active template set".to_string(),
+                Vec::new(),
+            )));
         }
-    } else if let Some(template_id) = &request.template_id {
-        // Verify template is cached
-        if !manager.template_cache.contains_key(template_id) {
-            return Ok(Json(ApiResponse {
-                success: false,
-                message: format!(
-                    "Template '{}' not found in cache. Please set it first.",
-                    template_id
+
+        let targets = match (&printer_id, &request.role) {
+            (Some(printer_id), _) => vec![printer_id.clone()],
+            (None, Some(role)) => manager.printers_with_role(role),
+            (None, None) => vec![DEFAULT_PRINTER.to_string()],
+        };
+        if targets.is_empty() {
+            return Ok(Json(PrintTemplateResponse::failed(
+                format!(
+                    "No connected printer has role '{}'",
+                    request.role.unwrap_or_default()
                 ),
-            }));
+                Vec::new(),
+            )));
         }
 
-        // Set as active if not already
-        if manager.active_template_id.as_ref() != Some(template_id) {
-            manager.active_template_id = Some(template_id.clone());
+        // Check printer connection
+        if let Some(target) = targets.iter().find(|t| !manager.is_printer_connected(t)) {
+            let message = if target == DEFAULT_PRINTER {
+                "Printer not connected".to_string()
+            } else {
+                format!("Printer '{}' not connected", target)
+            };
+            return Ok(Json(PrintTemplateResponse::failed(message, Vec::new())));
         }
-    } else if manager.active_template_id.is_none() {
-        return Ok(Json(ApiResponse {
-            success: false,
-            message: "No template specified and no active template set".to_string(),
-        }));
+
+        let snapshots: Vec<_> = targets
+            .into_iter()
+            .map(|target| {
+                let snapshot = manager.snapshot_for(&target);
+                (target, snapshot)
+            })
+            .collect();
+        let routed = if printer_id.is_none() {
+            route_snapshots(&mut manager, &data)
+        } else {
+            Vec::new()
+        };
+        (snapshots, routed)
+    };
+
+    let single = snapshots.len() == 1;
+    let mut warnings = Vec::new();
+    let mut failures = Vec::new();
+    for (target, snapshot) in snapshots {
+        let (output, job_warnings) = render_job(snapshot, data.clone());
+        // Every target renders the same data, so one set of warnings will do
+        if warnings.is_empty() {
+            warnings = job_warnings;
+        }
+        let result = match output {
+            Ok(output) => state.print_worker.submit(output).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("Print failed on '{}': {}", target, e);
+            failures.push(if single {
+                format!("Print failed: {}", e)
+            } else {
+                format!("Print failed on '{}': {}", target, e)
+            });
+        }
+    }
+    if !failures.is_empty() {
+        return Ok(Json(PrintTemplateResponse::failed(
+            failures.join("; "),
+            warnings,
+        )));
     }
 
-    // Check printer connection
-    if !manager.is_connected() {
-        return Ok(Json(ApiResponse {
+    // Routed tickets only go out once the order itself has printed, so a
+    // retried order doesn't reach the kitchen twice
+    let mut routed_results = Vec::new();
+    for job in routed {
+        let items = job.data.items.len();
+        let result = match render_job(job.snapshot, job.data).0 {
+            Ok(output) => state.print_worker.submit(output).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            log::error!("Routed ticket for role '{}' failed: {}", job.role, e);
+        }
+        routed_results.push(RoutedPrintResult {
+            role: job.role,
+            printer_id: job.printer_id,
+            items,
+            success: result.is_ok(),
+            message: match result {
+                Ok(()) => format!("{} item(s) printed", items),
+                Err(e) => format!("Print failed: {}", e),
+            },
+        });
+    }
+
+    Ok(Json(PrintTemplateResponse {
+        success: true,
+        message: format!("Receipt printed successfully (Order #{})", order_id),
+        warnings,
+        routed: routed_results,
+    }))
+}
+
+/// A routed ticket snapshotted for one printer
+struct RoutedJob {
+    role: String,
+    printer_id: Option<String>,
+    snapshot: Result<PrintSnapshot, String>,
+    data: ReceiptData,
+}
+
+/// Apply the manager's routing rules to `data` and snapshot each ticket's
+/// template for every printer with the ticket's role
+fn route_snapshots(manager: &mut PrinterManager, data: &ReceiptData) -> Vec<RoutedJob> {
+    let tickets = route_items(data, &manager.routing_rules);
+    let mut jobs = Vec::new();
+    for ticket in tickets {
+        let printers = manager.printers_with_role(&ticket.role);
+        if printers.is_empty() {
+            jobs.push(RoutedJob {
+                snapshot: Err(format!("No connected printer has role '{}'", ticket.role)),
+                role: ticket.role,
+                printer_id: None,
+                data: ticket.data,
+            });
+            continue;
+        }
+        for printer_id in printers {
+            let snapshot = match &ticket.template_id {
+                Some(template_id) => manager.snapshot_template(&printer_id, template_id),
+                None => manager.snapshot_for(&printer_id),
+            };
+            jobs.push(RoutedJob {
+                role: ticket.role.clone(),
+                printer_id: Some(printer_id),
+                snapshot,
+                data: ticket.data.clone(),
+            });
+        }
+    }
+    jobs
+}
+
+/// Check `data` against the snapshot's template and render it, streaming
+/// very large jobs. Returns the output and any data warnings.
+fn render_job(
+    snapshot: Result<PrintSnapshot, String>,
+    mut data: ReceiptData,
+) -> (Result<PrintOutput, String>, Vec<String>) {
+    let snapshot = match snapshot {
+        Ok(snapshot) => snapshot,
+        Err(e) => return (Err(e), Vec::new()),
+    };
+    let mut warnings = snapshot.dropped().to_vec();
+    match snapshot.check_data(&mut data) {
+        Ok(data_warnings) => warnings.extend(data_warnings),
+        Err(e) => return (Err(e), warnings),
+    }
+
+    // Very large jobs are rendered by the print worker as they are written
+    let output = if data.row_count() > STREAMING_ROW_THRESHOLD {
+        Ok(snapshot.stream(data))
+    } else {
+        snapshot.render(&data)
+    };
+    (output, warnings)
+}
+
+/// Routing rules applied to `/print-template` orders
+async fn get_routing_rules(State(state): State<Arc<AppState>>) -> Json<RoutingRulesBody> {
+    let rules = state
+        .printer_manager
+        .lock_or_recover()
+        .routing_rules
+        .clone();
+    Json(RoutingRulesBody { rules })
+}
+
+/// Replace the routing rules until the app restarts
+async fn set_routing_rules(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<RoutingRulesBody>,
+) -> Json<ApiResponse> {
+    let count = body.rules.len();
+    state.printer_manager.lock_or_recover().routing_rules = body.rules;
+    Json(ApiResponse {
+        success: true,
+        message: format!("{} routing rule(s) set", count),
+    })
+}
+
+/// Every registered printer
+async fn list_printers(State(state): State<Arc<AppState>>) -> Json<PrintersResponse> {
+    let printers = state.printer_manager.lock_or_recover().printers();
+    Json(PrintersResponse { printers })
+}
+
+/// The built-in printer model profiles
+async fn list_profiles() -> Json<ProfilesResponse> {
+    Json(ProfilesResponse { profiles: PROFILES })
+}
+
+/// Connect a printer under `printer_id`, registering it if it is new
+async fn connect_printer(
+    State(state): State<Arc<AppState>>,
+    Path(printer_id): Path<String>,
+    Json(request): Json<PrinterConnectRequest>,
+) -> Json<ApiResponse> {
+    if let Some(density) = request.density.filter(|d| !DENSITY_RANGE.contains(d)) {
+        return Json(ApiResponse {
+            success: false,
+            message: format!("density must be -6 to 6, not {}", density),
+        });
+    }
+    let mut manager = state.printer_manager.lock_or_recover();
+    // Legacy receipts on any printer show the store from the main settings
+    let store = manager.config().cloned();
+    let config = PrinterConfig {
+        connection_type: request.connection_type,
+        device_path: request.device_path,
+        store_name: store
+            .as_ref()
+            .map(|c| c.store_name.clone())
+            .unwrap_or_default(),
+        store_address: store
+            .as_ref()
+            .map(|c| c.store_address.clone())
+            .unwrap_or_default(),
+        footer_message: store.map(|c| c.footer_message).unwrap_or_default(),
+        serial: request.serial,
+        timeouts: request.timeouts,
+        roles: request.roles,
+        protocol: request.protocol,
+        profile: request.profile,
+        code_pages: request.code_pages,
+        cjk_encoding: request.cjk_encoding,
+        raster_text: request.raster_text,
+        upside_down: request.upside_down,
+        density: request.density,
+        print_speed: request.print_speed,
+        print_area: request.print_area,
+        write_pacing: request.write_pacing,
+    };
+    match manager.connect_printer(&printer_id, config) {
+        Ok(()) => {
+            drop(manager);
+            replay_spooled_jobs(&state);
+            Json(ApiResponse {
+                success: true,
+                message: format!("Printer '{}' connected", printer_id),
+            })
+        }
+        Err(e) => Json(ApiResponse {
             success: false,
-            message: "Printer not connected".to_string(),
-        }));
+            message: format!("Printer connection failed: {}", e),
+        }),
     }
+}
 
-    // Print
-    match manager.print_with_template(&request.data) {
-        Ok(_) => Ok(Json(ApiResponse {
+/// Print jobs saved at the last shutdown for printers connected since, in
+/// the background
+fn replay_spooled_jobs(state: &AppState) {
+    let Some(spool) = state.print_worker.spool().cloned() else {
+        return;
+    };
+    if spool.pending().is_empty() {
+        return;
+    }
+    let manager = Arc::clone(&state.printer_manager);
+    std::thread::spawn(move || match spool.replay(&manager) {
+        Ok(printed) => log::info!("Printed {} spooled job(s)", printed),
+        Err(e) => log::warn!("Failed to print spooled jobs: {}", e),
+    });
+}
+
+async fn disconnect_printer(
+    State(state): State<Arc<AppState>>,
+    Path(printer_id): Path<String>,
+) -> Result<Json<ApiResponse>, StatusCode> {
+    if !state
+        .printer_manager
+        .lock_or_recover()
+        .disconnect_printer(&printer_id)
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(ApiResponse {
+        success: true,
+        message: format!("Printer '{}' disconnected", printer_id),
+    }))
+}
+
+/// Print the connection test page on one printer
+async fn test_print_on_printer(
+    State(state): State<Arc<AppState>>,
+    Path(printer_id): Path<String>,
+) -> Json<ApiResponse> {
+    let output = state
+        .printer_manager
+        .lock_or_recover()
+        .prepare_test_for(&printer_id);
+    let result = match output {
+        Ok(output) => state.print_worker.submit(output).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => Json(ApiResponse {
             success: true,
-            message: format!(
-                "Receipt printed successfully (Order #{})",
-                request.data.order_id
-            ),
-        })),
-        Err(e) => {
-            log::error!("Print failed: {}", e);
-            Ok(Json(ApiResponse {
-                success: false,
-                message: format!("Print failed: {}", e),
-            }))
-        }
+            message: format!("Test page printed on '{}'", printer_id),
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            message: format!("Test print failed: {}", e),
+        }),
     }
 }
 
@@ -358,7 +1080,7 @@ async fn print_with_template(
 async fn get_cached_templates(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<TemplateCacheResponse>, StatusCode> {
-    let manager = state.printer_manager.lock().unwrap();
+    let manager = state.printer_manager.lock_or_recover();
 
     let templates: Vec<TemplateInfoResponse> = manager
         .template_cache
@@ -374,21 +1096,23 @@ async fn get_cached_templates(
     Ok(Json(TemplateCacheResponse {
         templates,
         active_template_id: manager.active_template_id.clone(),
+        cache: manager.template_cache.stats(),
     }))
 }
 
+/// JSON Schema of the template format this build understands
+async fn template_schema() -> Json<schemars::schema::RootSchema> {
+    Json(crate::template_parse::template_schema())
+}
+
 /// Get specific template
 async fn get_template(
     State(state): State<Arc<AppState>>,
     Path(template_id): Path<String>,
-) -> Result<Json<ReceiptTemplate>, StatusCode> {
-    let manager = state.printer_manager.lock().unwrap();
-
-    if let Some(template) = manager.template_cache.get(&template_id) {
-        Ok(Json(template.clone()))
-    } else {
-        Err(StatusCode::NOT_FOUND)
-    }
+) -> Result<Json<Arc<ReceiptTemplate>>, StatusCode> {
+    // Serialized after the lock is released
+    let template = state.printer_manager.lock_or_recover().template_cache.get(&template_id);
+    template.map(Json).ok_or(StatusCode::NOT_FOUND)
 }
 
 /// Clear template cache (optionally include logos)
@@ -396,11 +1120,10 @@ async fn clear_cache(
     State(state): State<Arc<AppState>>,
     Query(params): Query<std::collections::HashMap<String, String>>,
 ) -> Result<Json<ApiResponse>, StatusCode> {
-    let mut manager = state.printer_manager.lock().unwrap();
-    let include_logos = params.get("include_logos").map_or(false, |v| v == "true");
+    let mut manager = state.printer_manager.lock_or_recover();
+    let include_logos = params.get("include_logos").is_some_and(|v| v == "true");
 
-    manager.template_cache.clear();
-    manager.active_template_id = None;
+    manager.clear_templates();
 
     let mut message = "Template cache cleared".to_string();
 
@@ -420,22 +1143,6 @@ async fn clear_cache(
 
 /// Test print with active template
 async fn test_print(State(state): State<Arc<AppState>>) -> Result<Json<ApiResponse>, StatusCode> {
-    let mut manager = state.printer_manager.lock().unwrap();
-
-    if !manager.is_connected() {
-        return Ok(Json(ApiResponse {
-            success: false,
-            message: "Printer not connected".to_string(),
-        }));
-    }
-
-    if manager.active_template_id.is_none() {
-        return Ok(Json(ApiResponse {
-            success: false,
-            message: "No active template set".to_string(),
-        }));
-    }
-
     // Create test data
     let test_data = ReceiptData {
         store_name: Some("Test Store".to_string()),
@@ -453,14 +1160,14 @@ async fn test_print(State(state): State<Arc<AppState>>) -> Result<Json<ApiRespon
                 quantity: 2,
                 price: 10.00,
                 total: 20.00,
-                modifiers: None,
+                ..Default::default()
             },
             crate::ReceiptItem {
                 name: "Test Item 2".to_string(),
                 quantity: 1,
                 price: 15.50,
                 total: 15.50,
-                modifiers: None,
+                ..Default::default()
             },
         ],
         subtotal: 35.50,
@@ -477,7 +1184,33 @@ async fn test_print(State(state): State<Arc<AppState>>) -> Result<Json<ApiRespon
         ..Default::default()
     };
 
-    match manager.print_with_template(&test_data) {
+    // Snapshot the template under the lock, then render and print without it
+    let snapshot = {
+        let mut manager = state.printer_manager.lock_or_recover();
+
+        if !manager.is_connected() {
+            return Ok(Json(ApiResponse {
+                success: false,
+                message: "Printer not connected".to_string(),
+            }));
+        }
+
+        if manager.active_template_id.is_none() {
+            return Ok(Json(ApiResponse {
+                success: false,
+                message: "No active template set".to_string(),
+            }));
+        }
+
+        manager.snapshot()
+    };
+
+    let result = match snapshot.and_then(|snapshot| snapshot.render(&test_data)) {
+        Ok(output) => state.print_worker.submit(output).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
         Ok(_) => Ok(Json(ApiResponse {
             success: true,
             message: "Test receipt printed successfully".to_string(),
@@ -496,12 +1229,13 @@ async fn test_print(State(state): State<Arc<AppState>>) -> Result<Json<ApiRespon
 /// This endpoint renders a template with data and returns the print commands
 /// and a text preview - useful for testing templates
 async fn preview_template(
-    Json(request): Json<PreviewTemplateRequest>,
+    TemplateJson(request): TemplateJson<PreviewTemplateRequest>,
 ) -> Result<Json<PreviewResponse>, StatusCode> {
     let paper_width = request.template.paper_width.unwrap_or(48);
     let renderer = TemplateRenderer::new(paper_width);
+    let compiled = renderer.compile(&request.template);
 
-    match renderer.render_to_commands(&request.template, &request.data) {
+    match renderer.render_to_commands(&compiled, &request.data) {
         Ok(commands) => {
             // Convert commands to string representations
             let command_strings: Vec<String> =
@@ -528,6 +1262,7 @@ async fn preview_template(
                 success: true,
                 commands: command_strings,
                 text_preview,
+                warnings: compiled.dropped().to_vec(),
             }))
         }
         Err(e) => {
@@ -536,11 +1271,133 @@ async fn preview_template(
                 success: false,
                 commands: vec![],
                 text_preview: format!("Error: {}", e),
+                warnings: compiled.dropped().to_vec(),
             }))
         }
     }
 }
 
+/// Render a template with data to a receipt-width PDF, e.g. to email it
+async fn preview_template_pdf(
+    TemplateJson(request): TemplateJson<PreviewTemplateRequest>,
+) -> Response {
+    let paper_width = request.template.paper_width.unwrap_or(48);
+    let renderer = TemplateRenderer::new(paper_width);
+    let compiled = renderer.compile(&request.template);
+
+    match renderer.render_to_commands(&compiled, &request.data) {
+        Ok(commands) => (
+            [(header::CONTENT_TYPE, "application/pdf")],
+            crate::pdf::render_pdf(&commands),
+        )
+            .into_response(),
+        Err(e) => {
+            log::error!("PDF preview failed: {}", e);
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ApiResponse {
+                    success: false,
+                    message: e,
+                }),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Render a template as plain text framed to the paper's width, or as an
+/// HTML page or a PNG of the receipt
+fn preview_response(
+    template: &ReceiptTemplate,
+    data: &ReceiptData,
+    format: Option<&str>,
+) -> Response {
+    let paper_width = template.paper_width.unwrap_or(48);
+    let renderer = TemplateRenderer::new(paper_width);
+    let compiled = renderer.compile(template);
+
+    let commands = match renderer.render_to_commands(&compiled, data) {
+        Ok(commands) => commands,
+        Err(e) => {
+            log::error!("Preview failed: {}", e);
+            return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+        }
+    };
+    match format.unwrap_or("text") {
+        "text" => (
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            crate::text_preview::render_text(&commands, paper_width),
+        )
+            .into_response(),
+        "html" => (
+            [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+            crate::html_preview::render_html(&commands, paper_width),
+        )
+            .into_response(),
+        #[cfg(feature = "raster-text")]
+        "png" => (
+            [(header::CONTENT_TYPE, "image/png")],
+            crate::png_preview::render_png(&commands, paper_width),
+        )
+            .into_response(),
+        #[cfg(not(feature = "raster-text"))]
+        "png" => (
+            StatusCode::NOT_IMPLEMENTED,
+            "PNG previews need the raster-text feature".to_string(),
+        )
+            .into_response(),
+        other => (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Unknown preview format '{}', expected text, html or png",
+                other
+            ),
+        )
+            .into_response(),
+    }
+}
+
+/// Preview a template and data as text, HTML or PNG
+async fn preview(
+    Query(query): Query<PreviewQuery>,
+    TemplateJson(request): TemplateJson<PreviewTemplateRequest>,
+) -> Response {
+    preview_response(&request.template, &request.data, query.format.as_deref())
+}
+
+/// Preview a cached template as text, HTML or PNG, with each declared variable at
+/// its default
+async fn preview_cached(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PreviewQuery>,
+) -> Response {
+    let template = {
+        let manager = state.printer_manager.lock_or_recover();
+        let template_id = query
+            .template_id
+            .or_else(|| manager.active_template_id.clone());
+        match template_id {
+            Some(id) => manager
+                .template_cache
+                .get(&id)
+                .ok_or(format!("Template '{}' not found", id)),
+            None => Err("No template specified and no active template set".to_string()),
+        }
+    };
+    let template = match template {
+        Ok(template) => template,
+        Err(message) => return (StatusCode::NOT_FOUND, message).into_response(),
+    };
+
+    let mut data = ReceiptData::default();
+    for (name, variable) in template.variables.iter().flatten() {
+        if let Some(default) = &variable.default {
+            data.custom.insert(name.clone(), default.clone());
+        }
+    }
+    preview_response(&template, &data, query.format.as_deref())
+}
+
 // ==================== Logo Cache Handlers ====================
 
 /// Cache a logo for fast printing
@@ -548,7 +1405,7 @@ async fn cache_logo(
     State(state): State<Arc<AppState>>,
     Json(request): Json<CacheLogoRequest>,
 ) -> Result<Json<CacheLogoResponse>, StatusCode> {
-    let mut manager = state.printer_manager.lock().unwrap();
+    let mut manager = state.printer_manager.lock_or_recover();
 
     match crate::logo_cache::cache_logo(&mut manager, request.id, &request.base64) {
         Ok((id, content_hash, cached)) => {
@@ -569,7 +1426,7 @@ async fn cache_logo(
 
 /// Get all cached logos
 async fn get_logos(State(state): State<Arc<AppState>>) -> Json<LogoCacheListResponse> {
-    let manager = state.printer_manager.lock().unwrap();
+    let manager = state.printer_manager.lock_or_recover();
     let logos = crate::logo_cache::get_all_logos(&manager);
     Json(LogoCacheListResponse { logos })
 }
@@ -579,7 +1436,7 @@ async fn delete_logo(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
 ) -> Result<Json<ApiResponse>, StatusCode> {
-    let mut manager = state.printer_manager.lock().unwrap();
+    let mut manager = state.printer_manager.lock_or_recover();
 
     match crate::logo_cache::delete_logo(&mut manager, &id) {
         Ok(()) => Ok(Json(ApiResponse {
@@ -593,12 +1450,49 @@ async fn delete_logo(
     }
 }
 
-/// Print a base64-encoded image (PNG/JPEG), scaled to fit paper width.
+/// Store a logo in a printer's NV memory for templates to print by slot
+async fn store_nv_logo(
+    State(state): State<Arc<AppState>>,
+    Path(printer_id): Path<String>,
+    Json(request): Json<NvLogoRequest>,
+) -> Json<ApiResponse> {
+    let defaults = ImageOptions::default();
+    let options = ImageOptions {
+        dithering: request.dither.unwrap_or_default(),
+        threshold: request.threshold.unwrap_or(defaults.threshold),
+        brightness: request.brightness.unwrap_or(defaults.brightness),
+        contrast: request.contrast.unwrap_or(defaults.contrast),
+    };
+    let output = state.printer_manager.lock_or_recover().prepare_nv_logo(
+        &printer_id,
+        request.slot,
+        &request.source,
+        request.max_width,
+        request.max_height,
+        &options,
+    );
+    let result = match output {
+        Ok(output) => state.print_worker.submit(output).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => Json(ApiResponse {
+            success: true,
+            message: format!("Logo stored in slot {} on '{}'", request.slot, printer_id),
+        }),
+        Err(e) => Json(ApiResponse {
+            success: false,
+            message: format!("Storing the logo failed: {}", e),
+        }),
+    }
+}
+
+// Print a base64-encoded image (PNG/JPEG), scaled to fit paper width.
 // async fn print_image(
 //     State(state): State<Arc<AppState>>,
 //     Json(request): Json<PrintImageRequest>,
 // ) -> Result<Json<ApiResponse>, StatusCode> {
-//     let mut manager = state.printer_manager.lock().unwrap();
+//     let mut manager = state.printer_manager.lock_or_recover();
 
 //     if !manager.is_connected() {
 //         return Ok(Json(ApiResponse {
@@ -676,7 +1570,7 @@ async fn delete_logo(
 async fn barcode_status(
     State(state): State<Arc<AppState>>,
 ) -> Json<BarcodeStatusResponse> {
-    let manager = state.barcode_manager.lock().unwrap();
+    let manager = state.barcode_manager.lock_or_recover();
     let (protocol, width, height, dpi) = if let Some(config) = &manager.config {
         (
             Some(config.protocol.clone()),
@@ -693,6 +1587,7 @@ async fn barcode_status(
         label_width_mm: width,
         label_height_mm: height,
         dpi,
+        serial: manager.config.as_ref().map(|config| config.serial.clone()),
     })
 }
 
@@ -707,8 +1602,10 @@ async fn barcode_connect(
         label_width_mm: request.label_width_mm,
         label_height_mm: request.label_height_mm,
         dpi: request.dpi,
+        serial: request.serial,
+        timeouts: request.timeouts,
     };
-    let mut manager = state.barcode_manager.lock().unwrap();
+    let mut manager = state.barcode_manager.lock_or_recover();
     match manager.connect(config) {
         Ok(_) => Json(ApiResponse {
             success: true,
@@ -724,7 +1621,7 @@ async fn barcode_connect(
 async fn barcode_disconnect(
     State(state): State<Arc<AppState>>,
 ) -> Json<ApiResponse> {
-    let mut manager = state.barcode_manager.lock().unwrap();
+    let mut manager = state.barcode_manager.lock_or_recover();
     manager.disconnect();
     Json(ApiResponse {
         success: true,
@@ -736,9 +1633,7 @@ async fn print_barcode(
     State(state): State<Arc<AppState>>,
     Json(request): Json<PrintBarcodeRequest>,
 ) -> Json<ApiResponse> {
-    let mut manager = state.barcode_manager.lock().unwrap();
-
-    if !manager.is_connected() {
+    if !state.barcode_manager.lock_or_recover().is_connected() {
         return Json(ApiResponse {
             success: false,
             message: "Barcode printer not connected".to_string(),
@@ -763,7 +1658,13 @@ async fn print_barcode(
         label_height_mm: request.label_height_mm,
     };
 
-    match manager.print_label(&req) {
+    // Label printing is blocking I/O; keep it off the async workers
+    let manager = Arc::clone(&state.barcode_manager);
+    let result = tokio::task::spawn_blocking(move || manager.lock_or_recover().print_label(&req))
+        .await
+        .unwrap_or_else(|e| Err(format!("Barcode print task failed: {}", e)));
+
+    match result {
         Ok(_) => Json(ApiResponse {
             success: true,
             message: format!("Barcode label printed: {}", request.barcode_data),
@@ -775,17 +1676,74 @@ async fn print_barcode(
     }
 }
 
+/// Print a label template's label with the request's data
+async fn print_label(
+    State(state): State<Arc<AppState>>,
+    TemplateJson(request): TemplateJson<PrintLabelRequest>,
+) -> Json<ApiResponse> {
+    let failed = |message: String| {
+        Json(ApiResponse {
+            success: false,
+            message,
+        })
+    };
+    if !state.barcode_manager.lock_or_recover().is_connected() {
+        return failed("Barcode printer not connected".to_string());
+    }
+
+    let template = match (request.template, request.template_id) {
+        (Some(template), _) => Arc::new(template),
+        (None, Some(id)) => {
+            let cached = state
+                .printer_manager
+                .lock_or_recover()
+                .template_cache
+                .get(&id);
+            match cached {
+                Some(template) => template,
+                None => return failed(format!("Template '{}' not found in cache", id)),
+            }
+        }
+        (None, None) => return failed("Send a template or a template_id".to_string()),
+    };
+    let Some(label) = template.label.clone() else {
+        return failed(format!("Template '{}' has no label", template.id));
+    };
+
+    let data = request.data;
+    let copies = request.copies.unwrap_or(1);
+    let manager = Arc::clone(&state.barcode_manager);
+    let result = tokio::task::spawn_blocking(move || {
+        manager
+            .lock_or_recover()
+            .print_template_label(&label, &data, copies)
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Barcode print task failed: {}", e)));
+
+    match result {
+        Ok(_) => Json(ApiResponse {
+            success: true,
+            message: format!("Label '{}' printed", template.id),
+        }),
+        Err(e) => failed(format!("Label print failed: {}", e)),
+    }
+}
+
 async fn barcode_test_print(
     State(state): State<Arc<AppState>>,
 ) -> Json<ApiResponse> {
-    let mut manager = state.barcode_manager.lock().unwrap();
-    if !manager.is_connected() {
+    if !state.barcode_manager.lock_or_recover().is_connected() {
         return Json(ApiResponse {
             success: false,
             message: "Barcode printer not connected".to_string(),
         });
     }
-    match manager.print_test_label() {
+    let manager = Arc::clone(&state.barcode_manager);
+    let result = tokio::task::spawn_blocking(move || manager.lock_or_recover().print_test_label())
+        .await
+        .unwrap_or_else(|e| Err(format!("Barcode print task failed: {}", e)));
+    match result {
         Ok(_) => Json(ApiResponse {
             success: true,
             message: "Barcode test label printed".to_string(),
@@ -799,38 +1757,57 @@ async fn barcode_test_print(
 
 // ==================== Server Setup ====================
 
-/// Start HTTP server in background
-pub async fn start_server(
-    printer_manager: Arc<Mutex<PrinterManager>>,
-    barcode_manager: Arc<Mutex<BarcodePrinterManager>>,
-    port: u16,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let state = Arc::new(AppState { printer_manager, barcode_manager });
-
+/// Build the router with all routes and CORS configured
+pub fn build_router(state: Arc<AppState>) -> Router {
     // Configure CORS for web app integration
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
+    // Routes that print; these answer 503 during shutdown
+    let printing = Router::new()
+        // Legacy print
+        .route("/print", post(print_legacy))
+        // Template-based printing
+        .route("/print-template", post(print_with_template))
+        // Image printing
+        // .route("/print-image", post(print_image))
+        .route("/test-print", post(test_print))
+        .route("/printers/:id/print-template", post(print_template_on_printer))
+        .route("/printers/:id/test-print", post(test_print_on_printer))
+        .route("/printers/:id/nv-logo", post(store_nv_logo))
+        // Barcode printer
+        .route("/print-barcode",       post(print_barcode))
+        .route("/barcode/test-print",  post(barcode_test_print))
+        .route("/print-label",         post(print_label))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&state),
+            reject_while_shutting_down,
+        ));
+
     // Build router with all routes
-    let app = Router::new()
+    Router::new()
+        .merge(printing)
         // Health & status
         .route("/health", get(health))
         .route("/status", get(status))
-        // Legacy print
-        .route("/print", post(print_legacy))
+        // Printer registry
+        .route("/printers", get(list_printers))
+        .route("/profiles", get(list_profiles))
+        .route("/printers/:id/connect", post(connect_printer))
+        .route("/printers/:id/disconnect", post(disconnect_printer))
+        .route("/routing", get(get_routing_rules).put(set_routing_rules))
         // Template management
         .route("/template", post(set_template))
+        .route("/template/validate", post(validate_template))
+        .route("/template/schema", get(template_schema))
         .route("/templates", get(get_cached_templates))
         .route("/template/{id}", get(get_template))
-        // Template-based printing
-        .route("/print-template", post(print_with_template))
-        // Image printing
-        // .route("/print-image", post(print_image))
-        .route("/test-print", post(test_print))
         // Preview (no printer needed)
         .route("/preview-template", post(preview_template))
+        .route("/preview-template/pdf", post(preview_template_pdf))
+        .route("/preview", get(preview_cached).post(preview))
         // .route("/preview-image", post(preview_image))
         // Cache management
         .route("/cache", delete(clear_cache))
@@ -842,16 +1819,791 @@ pub async fn start_server(
         .route("/barcode/status",      get(barcode_status))
         .route("/barcode/connect",     post(barcode_connect))
         .route("/barcode/disconnect",  post(barcode_disconnect))
-        .route("/print-barcode",       post(print_barcode))
-        .route("/barcode/test-print",  post(barcode_test_print))
         .layer(cors)
-        .with_state(state);
+        .with_state(state)
+}
+
+/// Start HTTP server in background.
+///
+/// Jobs go to `print_worker`; shut it down to start turning print requests
+/// away. The server stops accepting connections once `shutdown` resolves
+/// and returns when in-flight requests are answered.
+pub async fn start_server(
+    printer_manager: Arc<Mutex<PrinterManager>>,
+    barcode_manager: Arc<Mutex<BarcodePrinterManager>>,
+    print_worker: PrintWorker,
+    port: u16,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = Arc::new(AppState {
+        printer_manager,
+        barcode_manager,
+        print_worker,
+    });
+    let app = build_router(state);
 
     let addr = format!("127.0.0.1:{}", port);
     log::info!("HTTP print server listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use std::time::{Duration, Instant};
+    use tower::ServiceExt;
+
+    fn test_state(print_delay: Duration) -> Arc<AppState> {
+        let mut manager = PrinterManager::new();
+        let printer = crate::MockBackend::new().with_delay(print_delay).whole_jobs();
+        manager.connect_backend(DEFAULT_PRINTER, None, Arc::new(printer));
+        let template: ReceiptTemplate = serde_json::from_value(serde_json::json!({
+            "id": "test",
+            "name": "Test",
+            "version": "1.0.0",
+            "layout": {
+                "sections": [
+                    { "type": "body", "elements": [{ "type": "text", "content": "Order {{order_id}}" }] }
+                ]
+            }
+        }))
+        .unwrap();
+        manager.set_template(template, false).unwrap();
+
+        Arc::new(AppState {
+            printer_manager: Arc::new(Mutex::new(manager)),
+            barcode_manager: Arc::new(Mutex::new(BarcodePrinterManager::new())),
+            print_worker: PrintWorker::spawn(),
+        })
+    }
+
+    fn print_request() -> Request<Body> {
+        print_request_for("42")
+    }
+
+    fn print_request_for(order_id: &str) -> Request<Body> {
+        let body = serde_json::json!({
+            "data": { "order_id": order_id, "timestamp": "2024-01-15 14:30:00" }
+        });
+        Request::post("/print-template")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn json_body(response: axum::response::Response) -> serde_json::Value {
+        use http_body_util::BodyExt;
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_status_responds_while_slow_print_in_flight() {
+        let app = build_router(test_state(Duration::from_millis(1500)));
+
+        let print = tokio::spawn(app.clone().oneshot(print_request()));
+        // Give the print job time to reach the (slow) printer
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let started = Instant::now();
+        let response = app
+            .clone()
+            .oneshot(Request::get("/status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(elapsed < Duration::from_millis(100), "status took {:?}", elapsed);
+        assert!(!print.is_finished(), "mock print should still be in flight");
+
+        let body = json_body(print.await.unwrap().unwrap()).await;
+        assert_eq!(body["success"], true);
+    }
+
+    #[tokio::test]
+    async fn test_render_panic_does_not_take_down_server() {
+        let app = build_router(test_state(Duration::ZERO));
+
+        let panic_order = crate::template_render::tests::PANIC_ORDER_ID;
+        let response = app.clone().oneshot(print_request_for(panic_order)).await.unwrap();
+        let body = json_body(response).await;
+        assert_eq!(body["success"], false);
+        assert!(body["message"].as_str().unwrap().contains("forced render panic"));
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let status = json_body(response).await;
+        assert_eq!(status["connected"], true);
+        assert_eq!(status["connection_state"]["state"], "connected");
+        assert_eq!(status["print_stats"]["failures"]["render"], 1);
+
+        let body = json_body(app.oneshot(print_request()).await.unwrap()).await;
+        assert_eq!(body["success"], true);
+    }
+
+    #[tokio::test]
+    async fn test_print_routes_refuse_jobs_during_shutdown() {
+        let state = test_state(Duration::ZERO);
+        let app = build_router(Arc::clone(&state));
+        state.print_worker.shutdown(Duration::from_secs(1));
+
+        let response = app.clone().oneshot(print_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(json_body(response).await["success"], false);
+
+        let response = app
+            .oneshot(Request::get("/status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    fn set_template_request(version: &str, content: &str, force: bool) -> Request<Body> {
+        let body = serde_json::json!({
+            "template": {
+                "id": "test",
+                "name": "Test",
+                "version": version,
+                "layout": {
+                    "sections": [
+                        { "type": "body", "elements": [{ "type": "text", "content": content }] }
+                    ]
+                }
+            },
+            "force": force
+        });
+        Request::post("/template")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn cached_version(state: &AppState) -> String {
+        let manager = state.printer_manager.lock_or_recover();
+        manager.template_cache.get("test").unwrap().version.clone()
+    }
+
+    #[tokio::test]
+    async fn test_template_upgrade_replaces_cached_version() {
+        let state = test_state(Duration::ZERO);
+        let app = build_router(Arc::clone(&state));
+
+        let response = app.oneshot(set_template_request("1.1.0", "v1.1", false)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert!(body["message"].as_str().unwrap().contains("upgraded from version 1.0.0"));
+        assert_eq!(cached_version(&state), "1.1.0");
+    }
+
+    #[tokio::test]
+    async fn test_template_downgrade_rejected_with_conflict() {
+        let state = test_state(Duration::ZERO);
+        let app = build_router(Arc::clone(&state));
+
+        let response = app.oneshot(set_template_request("0.9", "old", false)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = json_body(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["template_id"], "test");
+        assert_eq!(body["cached_version"], "1.0.0");
+        assert_eq!(body["incoming_version"], "0.9");
+        assert_eq!(cached_version(&state), "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_template_downgrade_forced() {
+        let state = test_state(Duration::ZERO);
+        let app = build_router(Arc::clone(&state));
+
+        let response = app.oneshot(set_template_request("0.9", "old", true)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert!(body["message"].as_str().unwrap().contains("downgraded from version 1.0.0"));
+        assert_eq!(cached_version(&state), "0.9");
+    }
+
+    #[tokio::test]
+    async fn test_template_same_version_different_content_overwrites() {
+        let state = test_state(Duration::ZERO);
+        let app = build_router(Arc::clone(&state));
+
+        let response = app
+            .clone()
+            .oneshot(set_template_request("1.0.0", "Changed {{order_id}}", false))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert!(body["message"].as_str().unwrap().contains("replacing different content"));
+
+        {
+            let manager = state.printer_manager.lock_or_recover();
+            let template = manager.template_cache.get("test").unwrap();
+            let json = serde_json::to_string(&template).unwrap();
+            assert!(json.contains("Changed {{order_id}}"));
+        }
+
+        // Re-sending the same content is a plain no-op overwrite
+        let response = app
+            .oneshot(set_template_request("1.0.0", "Changed {{order_id}}", false))
+            .await
+            .unwrap();
+        let body = json_body(response).await;
+        assert_eq!(body["message"], "Template 'test' cached and set as active");
+    }
+
+    #[tokio::test]
+    async fn test_inline_template_downgrade_rejected_on_print() {
+        let app = build_router(test_state(Duration::ZERO));
+        let body = serde_json::json!({
+            "template": {
+                "id": "test",
+                "name": "Test",
+                "version": "0.1",
+                "layout": { "sections": [] }
+            },
+            "data": { "order_id": "1", "timestamp": "2024-01-15 14:30:00" }
+        });
+        let request = Request::post("/print-template")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_broken_template_reports_location() {
+        let app = build_router(test_state(Duration::ZERO));
+        let body = r#"{
+  "template": {
+    "id": "test", "name": "Test", "version": "1.0",
+    "layout": { "sections": [
+      { "type": "body", "elements": [
+        { "type": "text", "content": "Total", "font_size": "2" }
+      ] }
+    ] }
+  }
+}"#;
+        let request = Request::post("/template")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = json_body(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(
+            body["error"]["path"],
+            "template.layout.sections[0].elements[0].font_size"
+        );
+        assert_eq!(body["error"]["line"], 6);
+        assert_eq!(body["error"]["column"], 60);
+        assert!(body["error"]["hint"].as_str().unwrap().contains("remove the quotes"));
+    }
+
+    fn bad_conditions_template() -> serde_json::Value {
+        serde_json::json!({
+            "id": "bad", "name": "Bad", "version": "1.0",
+            "layout": { "sections": [
+                { "type": "body", "condition": "total >= 10 &&", "elements": [
+                    { "type": "text", "content": "A", "condition": "(items.length > 0" },
+                    { "type": "box", "elements": [
+                        { "type": "text", "content": "B", "condition": "status = 'paid'" }
+                    ] }
+                ] }
+            ] }
+        })
+    }
+
+    fn json_request(uri: &str, body: serde_json::Value) -> Request<Body> {
+        Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_printer_routes_drive_named_printers() {
+        let state = test_state(Duration::ZERO);
+        let kitchen = crate::MockBackend::new();
+        state
+            .printer_manager
+            .lock_or_recover()
+            .connect_backend("kitchen", None, Arc::new(kitchen.clone()));
+        let app = build_router(Arc::clone(&state));
+
+        let print = json_request(
+            "/printers/kitchen/print-template",
+            serde_json::json!({ "data": { "order_id": "7", "timestamp": "" } }),
+        );
+        let body = json_body(app.clone().oneshot(print).await.unwrap()).await;
+        assert_eq!(body["success"], true);
+        assert!(String::from_utf8_lossy(&kitchen.written()).contains("Order 7"));
+
+        let connect = json_request(
+            "/printers/bar/connect",
+            serde_json::json!({ "connection_type": "Console", "device_path": "" }),
+        );
+        let body = json_body(app.clone().oneshot(connect).await.unwrap()).await;
+        assert_eq!(body["success"], true);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/printers").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let ids: Vec<_> = json_body(response).await["printers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|printer| printer["id"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(ids, ["bar", "default", "kitchen"]);
+
+        let disconnect = Request::post("/printers/kitchen/disconnect").body(Body::empty());
+        let response = app.clone().oneshot(disconnect.unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(kitchen.is_closed());
+        let disconnect = Request::post("/printers/nowhere/disconnect").body(Body::empty());
+        let response = app.oneshot(disconnect.unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_print_by_role_and_routed_items() {
+        let state = test_state(Duration::ZERO);
+        let (kitchen, bar) = (crate::MockBackend::new(), crate::MockBackend::new());
+        {
+            let mut manager = state.printer_manager.lock_or_recover();
+            for (id, printer) in [("kitchen", &kitchen), ("bar", &bar)] {
+                let config = PrinterConfig {
+                    connection_type: "Console".to_string(),
+                    device_path: String::new(),
+                    store_name: String::new(),
+                    store_address: String::new(),
+                    footer_message: String::new(),
+                    serial: SerialSettings::default(),
+                    timeouts: Timeouts::default(),
+                    roles: vec![id.to_string()],
+                    protocol: None,
+                    profile: None,
+                    code_pages: Vec::new(),
+                    cjk_encoding: None,
+                    raster_text: false,
+                    upside_down: false,
+                    density: None,
+                    print_speed: None,
+                    print_area: None,
+                    write_pacing: None,
+                };
+                manager.connect_backend(id, Some(config), Arc::new(printer.clone()));
+            }
+            manager.routing_rules = serde_json::from_value(serde_json::json!([
+                { "field": "category", "equals": "Drinks", "role": "bar" },
+                { "field": "category", "equals": "dessert", "role": "pastry" }
+            ]))
+            .unwrap();
+        }
+        let app = build_router(Arc::clone(&state));
+
+        let print = json_request(
+            "/print-template",
+            serde_json::json!({
+                "role": "KITCHEN",
+                "data": { "order_id": "8", "timestamp": "", "items": [
+                    { "name": "Cola", "category": "drinks" },
+                    { "name": "Lemonade", "category": "drinks" },
+                    { "name": "Cake", "category": "dessert" },
+                    { "name": "Soup", "category": "food" }
+                ] }
+            }),
+        );
+        let body = json_body(app.clone().oneshot(print).await.unwrap()).await;
+        assert_eq!(body["success"], true);
+        assert!(String::from_utf8_lossy(&kitchen.written()).contains("Order 8"));
+        assert!(String::from_utf8_lossy(&bar.written()).contains("Order 8"));
+        let routed = body["routed"].as_array().unwrap();
+        assert_eq!(routed.len(), 2);
+        assert_eq!(
+            (&routed[0]["printer_id"], &routed[0]["items"], &routed[0]["success"]),
+            (&serde_json::json!("bar"), &serde_json::json!(2), &serde_json::json!(true))
+        );
+        assert_eq!(routed[1]["success"], false);
+        assert!(routed[1]["printer_id"].is_null());
+
+        let print = json_request(
+            "/print-template",
+            serde_json::json!({ "role": "label", "data": { "order_id": "9", "timestamp": "" } }),
+        );
+        let body = json_body(app.oneshot(print).await.unwrap()).await;
+        assert_eq!(body["message"], "No connected printer has role 'label'");
+    }
+
+    #[tokio::test]
+    async fn test_preview_template_as_pdf() {
+        use http_body_util::BodyExt;
+
+        let app = build_router(test_state(Duration::ZERO));
+        let body = serde_json::json!({
+            "template": {
+                "id": "pdf", "name": "PDF", "version": "1.0",
+                "layout": { "sections": [
+                    { "type": "body", "elements": [
+                        { "type": "text", "content": "Order {{order_id}}" },
+                        { "type": "qr", "content": "{{order_id}}" }
+                    ] }
+                ] }
+            },
+            "data": { "order_id": "1001", "timestamp": "2024-01-15 14:30:00" }
+        });
+        let response = app
+            .oneshot(json_request("/preview-template/pdf", body))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/pdf");
+
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let pdf = String::from_utf8_lossy(&bytes);
+        assert!(pdf.starts_with("%PDF-"));
+        assert!(pdf.contains("(Order 1001) Tj"));
+    }
+
+    #[tokio::test]
+    async fn test_preview_template_as_text() {
+        use http_body_util::BodyExt;
+
+        let state = test_state(Duration::ZERO);
+        let body = serde_json::json!({
+            "template": {
+                "id": "text", "name": "Text", "version": "1.0",
+                "layout": { "sections": [
+                    { "type": "body", "elements": [
+                        { "type": "text", "content": "Order {{order_id}}" },
+                        { "type": "qr", "content": "{{order_id}}" }
+                    ] }
+                ] }
+            },
+            "data": { "order_id": "1001", "timestamp": "2024-01-15 14:30:00" }
+        });
+        let response = build_router(Arc::clone(&state))
+            .oneshot(json_request("/preview", body))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(text.starts_with(&format!("+{}+\n", "-".repeat(48))));
+        assert!(text.contains("Order 1001"));
+        assert!(text.contains("[QR code: 1001]"));
+
+        let response = build_router(state)
+            .oneshot(
+                Request::get("/preview?template_id=missing")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_preview_active_template_as_html() {
+        use http_body_util::BodyExt;
+
+        let response = build_router(test_state(Duration::ZERO))
+            .oneshot(
+                Request::get("/preview?format=html")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let html = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(">Order"));
+    }
+
+    #[cfg(feature = "raster-text")]
+    #[tokio::test]
+    async fn test_preview_template_as_png() {
+        use http_body_util::BodyExt;
+
+        let body = serde_json::json!({
+            "template": {
+                "id": "png", "name": "PNG", "version": "1.0", "paper_width": 32,
+                "layout": { "sections": [
+                    { "type": "body", "elements": [
+                        { "type": "text", "content": "Order {{order_id}}" }
+                    ] }
+                ] }
+            },
+            "data": { "order_id": "1001", "timestamp": "2024-01-15 14:30:00" }
+        });
+        let response = build_router(test_state(Duration::ZERO))
+            .oneshot(json_request("/preview?format=png", body))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/png");
+
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let image = image::load_from_memory(&bytes).unwrap();
+        assert!(image.width() >= 32 * 12);
+    }
+
+    #[tokio::test]
+    async fn test_template_with_bad_conditions_rejected() {
+        let state = test_state(Duration::ZERO);
+        let app = build_router(Arc::clone(&state));
+
+        let request =
+            json_request("/template", serde_json::json!({ "template": bad_conditions_template() }));
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = json_body(response).await;
+        assert_eq!(body["success"], false);
+        let issues = body["issues"].as_array().unwrap();
+        assert_eq!(issues.len(), 3);
+        assert_eq!(issues[0]["path"], "layout.sections[0].condition");
+        assert_eq!(issues[2]["path"], "layout.sections[0].elements[1].elements[0].condition");
+        assert!(!state.printer_manager.lock_or_recover().template_cache.contains_key("bad"));
+    }
+
+    #[tokio::test]
+    async fn test_template_with_bad_conditions_accepted_when_warning() {
+        let state = test_state(Duration::ZERO);
+        state.printer_manager.lock_or_recover().template_validation.conditions =
+            crate::template_compile::Strictness::Warn;
+        let app = build_router(Arc::clone(&state));
+
+        let request =
+            json_request("/template", serde_json::json!({ "template": bad_conditions_template() }));
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state.printer_manager.lock_or_recover().template_cache.contains_key("bad"));
+    }
+
+    #[tokio::test]
+    async fn test_validate_template_reports_all_issues() {
+        let app = build_router(test_state(Duration::ZERO));
+
+        let request = json_request(
+            "/template/validate",
+            serde_json::json!({ "template": bad_conditions_template() }),
+        );
+        let body = json_body(app.oneshot(request).await.unwrap()).await;
+        assert_eq!(body["valid"], false);
+        let issues = body["issues"].as_array().unwrap();
+        assert_eq!(issues.len(), 3);
+        assert_eq!(issues[1]["condition"], "(items.length > 0");
+        assert!(issues[1]["error"].as_str().unwrap().contains("missing `)`"));
+        let diagnostics = body["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics[1]["severity"], "error");
+        assert_eq!(
+            diagnostics[1]["path"],
+            "layout.sections[0].elements[0].condition"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_template_schema_is_served() {
+        let app = build_router(test_state(Duration::ZERO));
+
+        let response = app
+            .oneshot(Request::get("/template/schema").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let schema = json_body(response).await;
+        assert_eq!(schema["title"], "ReceiptTemplate");
+        let element = &schema["definitions"]["Element"];
+        let variants = element["oneOf"].as_array().unwrap();
+        assert!(variants
+            .iter()
+            .any(|variant| variant["properties"]["type"]["enum"][0] == "barcode"));
+    }
+
+    #[tokio::test]
+    async fn test_validate_template_reports_structure_errors() {
+        let app = build_router(test_state(Duration::ZERO));
+
+        let request = json_request(
+            "/template/validate",
+            serde_json::json!({ "template": { "id": "t", "name": "T", "version": "1" } }),
+        );
+        let body = json_body(app.oneshot(request).await.unwrap()).await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["message"], "Template 't' has 1 error");
+        assert_eq!(body["diagnostics"][0]["severity"], "error");
+        assert!(body["diagnostics"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("missing field `layout`"));
+    }
+
+    fn typed_print_request() -> Request<Body> {
+        json_request(
+            "/print-template",
+            serde_json::json!({
+                "template": {
+                    "id": "typed", "name": "Typed", "version": "1.0",
+                    "variables": { "points": { "type": "number" } },
+                    "layout": { "sections": [
+                        { "type": "body", "elements": [
+                            { "type": "text", "content": "Points: {{points}}" }
+                        ] }
+                    ] }
+                },
+                "data": { "order_id": "7", "timestamp": "", "points": "lots" }
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_print_reports_mistyped_data_as_warnings() {
+        let app = build_router(test_state(Duration::ZERO));
+
+        let body = json_body(app.oneshot(typed_print_request()).await.unwrap()).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(
+            body["warnings"],
+            serde_json::json!(["`points` is declared as number but is the string \"lots\""])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_print_rejects_mistyped_data_when_strict() {
+        let state = test_state(Duration::ZERO);
+        state.printer_manager.lock_or_recover().template_validation.data =
+            crate::template_compile::Strictness::Reject;
+        let app = build_router(state);
+
+        let body = json_body(app.oneshot(typed_print_request()).await.unwrap()).await;
+        assert_eq!(body["success"], false);
+        assert!(body["message"].as_str().unwrap().contains("`points` is declared as number"));
+    }
+
+    #[tokio::test]
+    async fn test_get_template_returns_shared_template_unchanged() {
+        let state = test_state(Duration::ZERO);
+        let template = json_body(
+            get_template(State(Arc::clone(&state)), Path("test".to_string()))
+                .await
+                .unwrap()
+                .into_response(),
+        )
+        .await;
+        assert_eq!(template["id"], "test");
+        assert_eq!(template["version"], "1.0.0");
+        assert_eq!(
+            template["layout"]["sections"][0]["elements"][0]["content"],
+            "Order {{order_id}}"
+        );
+
+        let missing = get_template(State(Arc::clone(&state)), Path("nope".to_string())).await;
+        assert_eq!(missing.unwrap_err(), StatusCode::NOT_FOUND);
+
+        let manager = state.printer_manager.lock_or_recover();
+        let first = manager.template_cache.get("test").unwrap();
+        let second = manager.template_cache.get("test").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(template, serde_json::to_value(&*first).unwrap());
+    }
+
+    fn report_template(version: &str, heading: &str) -> serde_json::Value {
+        serde_json::json!({
+            "template": {
+                "id": "report", "name": "Report", "version": version,
+                "layout": { "sections": [
+                    { "type": "body", "elements": [
+                        { "type": "text", "content": heading },
+                        { "type": "table", "data_source": "rows",
+                          "columns": [{ "field": "name", "width": 20 }] }
+                    ] }
+                ] }
+            }
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_template_swap_during_print_only_affects_later_jobs() {
+        let printer = crate::MockBackend::new().with_delay(Duration::from_millis(50));
+        let state = test_state(Duration::ZERO);
+        state
+            .printer_manager
+            .lock_or_recover()
+            .connect_backend(DEFAULT_PRINTER, None, Arc::new(printer.clone()));
+        let app = build_router(Arc::clone(&state));
+        app.clone()
+            .oneshot(json_request("/template", report_template("1.0", "OLD REPORT")))
+            .await
+            .unwrap();
+
+        // Enough rows to stream, so the job renders while it is slowly written
+        let rows: Vec<_> = (0..1500)
+            .map(|i| serde_json::json!({ "name": format!("row {}", i) }))
+            .collect();
+        let print = tokio::spawn(app.clone().oneshot(json_request(
+            "/print-template",
+            serde_json::json!({
+                "template_id": "report",
+                "data": { "order_id": "1", "timestamp": "", "rows": rows }
+            }),
+        )));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response = app
+            .clone()
+            .oneshot(json_request("/template", report_template("2.0", "NEW REPORT")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!print.is_finished(), "the swap should land mid-print");
+
+        let body = json_body(print.await.unwrap().unwrap()).await;
+        assert_eq!(body["success"], true);
+        let output = String::from_utf8_lossy(&printer.written()).into_owned();
+        assert!(output.contains("OLD REPORT"));
+        assert!(output.contains("row 1499"));
+        assert!(!output.contains("NEW REPORT"));
+
+        // The next job picks up the new template
+        printer.clear();
+        let body = json_body(app.oneshot(print_request()).await.unwrap()).await;
+        assert_eq!(body["success"], true);
+        assert!(String::from_utf8_lossy(&printer.written()).contains("NEW REPORT"));
+    }
+}
@@ -1,73 +1,340 @@
 // src/image_print.rs
 
 use base64::{engine::general_purpose, Engine as _};
-use image::{imageops::FilterType, GenericImageView, ImageReader};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, GrayImage, ImageReader, Luma};
+use qrcode::{Color, QrCode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::io::Cursor;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-/// Converts a base64-encoded PNG/JPEG into ESC/POS raster bitmap bytes (GS v 0).
+use crate::lock::LockExt;
+use crate::template_render::QrErrorCorrection;
+
+/// Largest image read from a file or URL
+const MAX_IMAGE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// How long fetching an image from a URL may take
+#[cfg(feature = "image-urls")]
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long an image fetched for an image element is reused
+const URL_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// Most bytes of fetched images kept; the oldest fetch goes first
+const URL_CACHE_BYTES: usize = 32 * 1024 * 1024;
+
+/// Images fetched from URLs by image elements: the URL, when it was
+/// fetched and the image, oldest first
+type UrlCache = Vec<(String, Instant, Arc<Vec<u8>>)>;
+static URL_CACHE: Mutex<UrlCache> = Mutex::new(Vec::new());
+
+/// How grey levels become black and white dots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Dithering {
+    /// Error diffusion to four neighbours; smooth shading, the default
+    #[default]
+    FloydSteinberg,
+    /// Diffuses only 3/4 of the error, keeping more contrast and cleaner
+    /// whites; suits logos and line art
+    Atkinson,
+    /// Every pixel darker than the threshold is black; for pure black and
+    /// white images
+    Threshold,
+}
+
+/// Adjustments applied to an image before it is converted to dots
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageOptions {
+    pub dithering: Dithering,
+    /// Grey level (0-255) below which a dot is black
+    pub threshold: u8,
+    /// -100 (black) to 100 (white), added to every pixel
+    pub brightness: i32,
+    /// -100 (flat grey) to 100, stretching levels away from mid grey
+    pub contrast: i32,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            dithering: Dithering::default(),
+            threshold: 128,
+            brightness: 0,
+            contrast: 0,
+        }
+    }
+}
+
+impl ImageOptions {
+    /// A grey level after brightness and contrast
+    fn adjust(&self, level: f32) -> f32 {
+        let level = level + self.brightness.clamp(-100, 100) as f32 * 2.55;
+        let factor = (100 + self.contrast.clamp(-100, 100)) as f32 / 100.0;
+        let factor = factor * factor;
+        (level - 128.0) * factor + 128.0
+    }
+}
+
+/// Whether `source` names an image to read (an `http(s)://` URL, a
+/// `file://` URL or an existing file) rather than holding base64 data
+pub fn is_image_location(source: &str) -> bool {
+    let source = source.trim();
+    source.starts_with("http://")
+        || source.starts_with("https://")
+        || source.starts_with("file://")
+        || (source.len() < 4096 && Path::new(source).is_file())
+}
+
+/// The image bytes `source` refers to: fetched from an `http(s)://` URL,
+/// read from a file path (optionally `file://`), or decoded from base64
+/// with or without a `data:image/...;base64,` prefix.
+pub fn read_image_source(source: &str) -> Result<Vec<u8>, String> {
+    let source = source.trim();
+    if source.starts_with("http://") || source.starts_with("https://") {
+        return fetch_image(source);
+    }
+    if is_image_location(source) {
+        let path = source.strip_prefix("file://").unwrap_or(source);
+        let size = std::fs::metadata(path)
+            .map_err(|e| format!("Failed to read image {}: {}", path, e))?
+            .len();
+        if size > MAX_IMAGE_BYTES {
+            return Err(format!("Image {} is larger than 10 MB", path));
+        }
+        return std::fs::read(path).map_err(|e| format!("Failed to read image {}: {}", path, e));
+    }
+    let b64 = match source.find(',') {
+        Some(pos) => &source[pos + 1..],
+        None => source,
+    };
+    general_purpose::STANDARD
+        .decode(b64.trim())
+        .map_err(|e| format!("Image is not a file, URL or base64 data: {}", e))
+}
+
+#[cfg(feature = "image-urls")]
+fn fetch_image(url: &str) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let response = ureq::get(url)
+        .timeout(FETCH_TIMEOUT)
+        .call()
+        .map_err(|e| format!("Failed to fetch image {}: {}", url, e))?;
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_IMAGE_BYTES)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to fetch image {}: {}", url, e))?;
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "image-urls"))]
+fn fetch_image(url: &str) -> Result<Vec<u8>, String> {
+    Err(format!(
+        "Cannot fetch {}: needs the image-urls feature",
+        url
+    ))
+}
+
+/// [`read_image_source`], reusing an image fetched from the same URL in
+/// the last hour, so a product photo on every ticket is downloaded once
+/// rather than on every print
+pub fn read_image_source_cached(source: &str) -> Result<Arc<Vec<u8>>, String> {
+    let source = source.trim();
+    if !(source.starts_with("http://") || source.starts_with("https://")) {
+        return read_image_source(source).map(Arc::new);
+    }
+    {
+        let mut cache = URL_CACHE.lock_or_recover();
+        cache.retain(|(_, fetched, _)| fetched.elapsed() < URL_CACHE_TTL);
+        if let Some((_, _, bytes)) = cache.iter().find(|(url, ..)| url == source) {
+            return Ok(Arc::clone(bytes));
+        }
+    }
+
+    // Fetched without the lock, so a slow server holds up only its own prints
+    let bytes = Arc::new(read_image_source(source)?);
+    let mut cache = URL_CACHE.lock_or_recover();
+    let mut total: usize = cache.iter().map(|(_, _, bytes)| bytes.len()).sum();
+    while !cache.is_empty() && total + bytes.len() > URL_CACHE_BYTES {
+        total -= cache.remove(0).2.len();
+    }
+    cache.push((source.to_string(), Instant::now(), Arc::clone(&bytes)));
+    Ok(bytes)
+}
+
+/// Decode the PNG/JPEG `source` refers to (see [`read_image_source`])
+pub fn load_image(source: &str) -> Result<DynamicImage, Box<dyn std::error::Error + Send + Sync>> {
+    decode_image(&read_image_source(source)?)
+}
+
+/// Decode PNG or JPEG bytes
+pub fn decode_image(
+    bytes: &[u8],
+) -> Result<DynamicImage, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .decode()?)
+}
+
+/// Converts a PNG/JPEG into ESC/POS raster bitmap bytes (GS v 0).
 ///
 /// # Arguments
-/// * `base64_data`      – Base64 string, with or without a `data:image/...;base64,` prefix.
+/// * `source`           – Base64 data (with or without a `data:image/...;base64,`
+///   prefix), a file path or an `http(s)://` URL; see [`read_image_source`].
 /// * `paper_width_dots` – Full printable width of the paper in dots (e.g. 576 for 80mm).
 /// * `max_width_dots`   – Optional max image width in dots. Defaults to full paper width.
-///                        Use this to print logos smaller than the full paper (e.g. 288 = half).
+///   Use this to print logos smaller than the full paper (e.g. 288 = half).
+/// * `max_height_dots`  – Optional max image height in dots; the image is scaled
+///   down further to fit, keeping its aspect ratio.
 /// * `align`            – Horizontal position: "left" | "center" | "right".
-///                        Achieved by padding empty dot columns — ESC/POS ignores text alignment
-///                        commands for bitmap data, so we handle it in the row bytes directly.
+///   Achieved by padding empty dot columns — ESC/POS ignores text alignment
+///   commands for bitmap data, so we handle it in the row bytes directly.
+/// * `options`          – Dithering, threshold, brightness and contrast.
 ///
 /// # Returns
 /// Raw ESC/POS bytes you can write directly to the printer.
 pub fn image_to_escpos(
-    base64_data: &str,
+    source: &str,
     paper_width_dots: u32,
     max_width_dots: Option<u32>,
+    max_height_dots: Option<u32>,
     align: &str,
+    options: &ImageOptions,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
-    // ── 1. Strip optional data-URI prefix ────────────────────────────────────
-    let b64 = match base64_data.find(',') {
-        Some(pos) => &base64_data[pos + 1..],
-        None => base64_data,
-    };
-
-    // ── 2. Decode base64 → raw image bytes ───────────────────────────────────
-    let img_bytes = general_purpose::STANDARD.decode(b64.trim())?;
-
-    // ── 3. Decode image ───────────────────────────────────────────────────────
-    let img = ImageReader::new(Cursor::new(img_bytes))
-        .with_guessed_format()?
-        .decode()?;
+    // ── 1. Load and decode the image ─────────────────────────────────────────
+    let img = load_image(source)?;
+    Ok(decoded_image_to_escpos(
+        &img,
+        paper_width_dots,
+        max_width_dots,
+        max_height_dots,
+        align,
+        options,
+    ))
+}
 
-    // ── 4. Determine target image width ──────────────────────────────────────
+/// [`image_to_escpos`] for an image that is already decoded
+pub fn decoded_image_to_escpos(
+    img: &DynamicImage,
+    paper_width_dots: u32,
+    max_width_dots: Option<u32>,
+    max_height_dots: Option<u32>,
+    align: &str,
+    options: &ImageOptions,
+) -> Vec<u8> {
+    // ── 2. Determine target image size ───────────────────────────────────────
     // Both paper_width and image_width must be multiples of 8.
     let paper_w = (paper_width_dots / 8) * 8;
+    let (target_w, target_h) =
+        target_size(img.dimensions(), paper_w, max_width_dots, max_height_dots);
+
+    // ── 3. Scale and convert to black and white ──────────────────────────────
+    let mono = to_monochrome(img, target_w, target_h, options);
+    raster_bytes(&mono, paper_w, align)
+}
 
+/// Image size in dots: scaled to the maximum width (a multiple of 8,
+/// at most the paper width), then down to the maximum height
+fn target_size(
+    (orig_w, orig_h): (u32, u32),
+    paper_w: u32,
+    max_width_dots: Option<u32>,
+    max_height_dots: Option<u32>,
+) -> (u32, u32) {
     let image_max_w = match max_width_dots {
         Some(mw) => ((mw / 8) * 8).min(paper_w), // clamp to paper width
         None => paper_w,
     };
-    let image_max_w = image_max_w.max(8);
+    let mut target_w = image_max_w.max(8);
+    let mut target_h = (orig_h as f32 * target_w as f32 / orig_w.max(1) as f32).round() as u32;
 
-    // ── 5. Scale image to image_max_w, preserving aspect ratio ───────────────
-    let (orig_w, orig_h) = img.dimensions();
-    let scale = image_max_w as f32 / orig_w as f32;
-    let target_w = image_max_w;
-    let target_h = (orig_h as f32 * scale).round() as u32;
+    if let Some(max_h) = max_height_dots.filter(|&max_h| max_h > 0 && target_h > max_h) {
+        let fitted_w = (orig_w as f32 * max_h as f32 / orig_h.max(1) as f32) as u32;
+        target_w = ((fitted_w / 8) * 8).max(8);
+        target_h = max_h;
+    }
+    (target_w, target_h.max(1))
+}
 
-    let target_w = target_w.max(8);
-    let target_h = target_h.max(1);
+/// Scale `img` to `width` × `height`, adjust it and dither it to black and
+/// white, so greys print as dot patterns instead of turning solid black or
+/// vanishing. Transparent pixels print white. Returns a grey image holding
+/// only 0 (black) and 255 (white).
+pub fn to_monochrome(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    options: &ImageOptions,
+) -> GrayImage {
+    let rgba = img
+        .resize_exact(width, height, FilterType::Lanczos3)
+        .to_rgba8();
 
-    let gray = img
-        .resize_exact(target_w, target_h, FilterType::Lanczos3)
-        .to_luma8();
+    // Luminance over a white background, as error-diffusion floats
+    let mut levels: Vec<f32> = rgba
+        .pixels()
+        .map(|p| {
+            let [r, g, b, a] = p.0;
+            let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+            let alpha = a as f32 / 255.0;
+            options.adjust(luma * alpha + 255.0 * (1.0 - alpha))
+        })
+        .collect();
+
+    let (w, h) = (width as usize, height as usize);
+    let mut mono = GrayImage::new(width, height);
+    for y in 0..h {
+        for x in 0..w {
+            let old = levels[y * w + x];
+            let new = if old < options.threshold as f32 {
+                0.0
+            } else {
+                255.0
+            };
+            mono.put_pixel(x as u32, y as u32, image::Luma([new as u8]));
+            let error = old - new;
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let nx = x as isize + dx;
+                let ny = y + dy;
+                if nx >= 0 && (nx as usize) < w && ny < h {
+                    levels[ny * w + nx as usize] += error * weight;
+                }
+            };
+            match options.dithering {
+                Dithering::FloydSteinberg => {
+                    spread(1, 0, 7.0 / 16.0);
+                    spread(-1, 1, 3.0 / 16.0);
+                    spread(0, 1, 5.0 / 16.0);
+                    spread(1, 1, 1.0 / 16.0);
+                }
+                Dithering::Atkinson => {
+                    for (dx, dy) in [(1, 0), (2, 0), (-1, 1), (0, 1), (1, 1), (0, 2)] {
+                        spread(dx, dy, 1.0 / 8.0);
+                    }
+                }
+                Dithering::Threshold => {}
+            }
+        }
+    }
+    mono
+}
 
-    let (img_w, height) = gray.dimensions();
-    let img_bytes_per_row = img_w / 8;
+/// Build the GS v 0 command for a black and white image, padded with
+/// empty dots to the full paper width for alignment
+fn raster_bytes(mono: &GrayImage, paper_w: u32, align: &str) -> Vec<u8> {
+    let (img_w, height) = mono.dimensions();
+    let img_bytes_per_row = img_w.div_ceil(8);
 
-    // ── 6. Calculate padding for alignment ───────────────────────────────────
+    // ── Calculate padding for alignment ──────────────────────────────────────
     // Total dots per row in the ESC/POS command = paper_w (we always fill the
     // full paper width with a mix of image dots and empty padding dots).
-    let total_bytes_per_row = paper_w / 8;
-    let pad_total_bytes = total_bytes_per_row.saturating_sub(img_bytes_per_row);
+    let total_bytes_per_row = (paper_w / 8).max(img_bytes_per_row);
+    let pad_total_bytes = total_bytes_per_row - img_bytes_per_row;
 
     let (pad_left_bytes, pad_right_bytes) = match align.to_lowercase().as_str() {
         "right" => (pad_total_bytes, 0),
@@ -79,15 +346,17 @@ pub fn image_to_escpos(
         _ => (0, pad_total_bytes), // "left" default
     };
 
-    // ── 7. Build GS v 0 raster bitmap command ────────────────────────────────
+    // ── Build GS v 0 raster bitmap command ───────────────────────────────────
     // Header: 1D 76 30 <mode> <xL> <xH> <yL> <yH>
     // xL/xH = bytes per row (full paper width, including padding)
     // yL/yH = number of rows (image height)
-    let mut out =
-        Vec::with_capacity(8 + (total_bytes_per_row * height) as usize);
+    let mut out = Vec::with_capacity(8 + (total_bytes_per_row * height) as usize);
 
     out.extend_from_slice(&[
-        0x1D, 0x76, 0x30, 0x00, // GS v 0, normal density
+        0x1D,
+        0x76,
+        0x30,
+        0x00, // GS v 0, normal density
         (total_bytes_per_row & 0xFF) as u8,
         ((total_bytes_per_row >> 8) & 0xFF) as u8,
         (height & 0xFF) as u8,
@@ -96,15 +365,14 @@ pub fn image_to_escpos(
 
     for y in 0..height {
         // Left padding — empty dots (white)
-        for _ in 0..pad_left_bytes {
-            out.push(0x00);
-        }
+        out.resize(out.len() + pad_left_bytes as usize, 0x00);
 
-        // Image pixels — dark pixel (< 128) → 1 (printed dot)
+        // Image pixels — black pixel → 1 (printed dot)
         for bx in 0..img_bytes_per_row {
             let mut byte = 0u8;
             for bit in 0..8u32 {
-                if gray.get_pixel(bx * 8 + bit, y).0[0] < 128 {
+                let x = bx * 8 + bit;
+                if x < img_w && mono.get_pixel(x, y).0[0] == 0 {
                     byte |= 1 << (7 - bit);
                 }
             }
@@ -112,11 +380,91 @@ pub fn image_to_escpos(
         }
 
         // Right padding — empty dots (white)
-        for _ in 0..pad_right_bytes {
-            out.push(0x00);
+        out.resize(out.len() + pad_right_bytes as usize, 0x00);
+    }
+
+    out
+}
+
+/// A QR code as a `GS v 0` raster of `module` dots (1-16) per module, for
+/// printers that can't draw QR codes themselves
+pub fn qr_raster(
+    content: &str,
+    module: u8,
+    error_correction: QrErrorCorrection,
+) -> Result<Vec<u8>, String> {
+    let code = QrCode::with_error_correction_level(content.as_bytes(), error_correction.ec_level())
+        .map_err(|e| e.to_string())?;
+    let module = module.clamp(1, 16) as u32;
+    let modules = code.width() as u32;
+    let colors = code.to_colors();
+    let mono = GrayImage::from_fn(modules * module, modules * module, |x, y| {
+        match colors[((y / module) * modules + x / module) as usize] {
+            Color::Dark => Luma([0]),
+            Color::Light => Luma([255]),
         }
+    });
+    Ok(raster_bytes(&mono, 0, "left"))
+}
+
+/// Slots a logo can be stored in in the printer's non-volatile memory
+pub const NV_LOGO_SLOTS: std::ops::RangeInclusive<u8> = 1..=99;
+
+/// Tallest image NV graphics memory takes, in dots
+const NV_MAX_HEIGHT: u32 = 2304;
+
+/// The `GS ( L` key code of NV logo `slot`: its two digits, "01" to "99"
+pub fn nv_key(slot: u8) -> [u8; 2] {
+    let slot = slot % 100;
+    [b'0' + slot / 10, b'0' + slot % 10]
+}
+
+/// ESC/POS commands storing an image in NV graphics memory under `slot`,
+/// replacing what was there. Once stored, `GS ( L` fn 69 prints it without
+/// sending the bitmap again. The image is scaled and dithered as for
+/// [`image_to_escpos`], and at most 2304 dots tall.
+pub fn nv_logo_store(
+    source: &str,
+    slot: u8,
+    paper_width_dots: u32,
+    max_width_dots: Option<u32>,
+    max_height_dots: Option<u32>,
+    options: &ImageOptions,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    if !NV_LOGO_SLOTS.contains(&slot) {
+        return Err(format!("NV logo slot must be 1 to 99, not {}", slot).into());
     }
+    let img = load_image(source)?;
+    let paper_w = (paper_width_dots / 8) * 8;
+    let max_h = max_height_dots.map_or(NV_MAX_HEIGHT, |h| h.min(NV_MAX_HEIGHT));
+    let (width, height) = target_size(img.dimensions(), paper_w, max_width_dots, Some(max_h));
+    let mono = to_monochrome(&img, width, height, options);
+    // A raster exactly as wide as the image, without its GS v 0 header
+    let raster = raster_bytes(&mono, 0, "left");
+    let dots = &raster[8..];
+    let [kc1, kc2] = nv_key(slot);
 
+    // GS ( L fn 66: delete the slot's old image
+    let mut out = vec![0x1D, 0x28, 0x4C, 0x04, 0x00, 0x30, 0x42, kc1, kc2];
+    // fn 67: define a monochrome raster; the parameter count covers
+    // m fn a kc1 kc2 b xL xH yL yH c and the dots
+    let mut define = vec![0x30, 0x43, 0x30, kc1, kc2, 0x01];
+    define.extend_from_slice(&(width as u16).to_le_bytes());
+    define.extend_from_slice(&(height as u16).to_le_bytes());
+    define.push(0x31);
+    define.extend_from_slice(dots);
+    match u16::try_from(define.len()) {
+        Ok(len) => {
+            out.extend_from_slice(&[0x1D, 0x28, 0x4C]);
+            out.extend_from_slice(&len.to_le_bytes());
+        }
+        // GS 8 L takes a four byte count for larger images
+        Err(_) => {
+            out.extend_from_slice(&[0x1D, 0x38, 0x4C]);
+            out.extend_from_slice(&(define.len() as u32).to_le_bytes());
+        }
+    }
+    out.extend_from_slice(&define);
     Ok(out)
 }
 
@@ -129,29 +477,11 @@ pub fn generate_image_preview(
     max_width_dots: Option<u32>,
     align: &str,
 ) -> Result<(String, u32, u32, usize), Box<dyn std::error::Error + Send + Sync>> {
-    let b64 = match base64_data.find(',') {
-        Some(pos) => &base64_data[pos + 1..],
-        None => base64_data,
-    };
-    let img_bytes = general_purpose::STANDARD.decode(b64.trim())?;
-    let img = ImageReader::new(Cursor::new(img_bytes))
-        .with_guessed_format()?
-        .decode()?;
+    let img = load_image(base64_data)?;
 
     let paper_w = (paper_width_dots / 8) * 8;
-    let image_max_w = match max_width_dots {
-        Some(mw) => ((mw / 8) * 8).min(paper_w),
-        None => paper_w,
-    }
-    .max(8);
-
     let (orig_w, orig_h) = img.dimensions();
-    let scale = image_max_w as f32 / orig_w as f32;
-    let real_w = image_max_w;
-    let real_h = (orig_h as f32 * scale).round() as u32;
-
-    let real_w = real_w.max(8);
-    let real_h = real_h.max(1);
+    let (real_w, real_h) = target_size((orig_w, orig_h), paper_w, max_width_dots, None);
     let total_bytes_per_row = paper_w / 8;
 
     // Header (8) + full-width raster rows + feed/cut footer (7)
@@ -180,8 +510,7 @@ pub fn generate_image_preview(
     };
     let pad_right = pad_total - pad_left;
 
-    let mut ascii_art =
-        String::with_capacity(((preview_max_w + 1) * preview_img_h) as usize);
+    let mut ascii_art = String::with_capacity(((preview_max_w + 1) * preview_img_h) as usize);
 
     for y in 0..preview_img_h {
         for _ in 0..pad_left {
@@ -201,4 +530,148 @@ pub fn generate_image_preview(
     }
 
     Ok((ascii_art, real_w, real_h, estimated_bytes))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgba, RgbaImage};
+
+    fn png(image: RgbaImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_grey_dithers_to_a_dot_pattern() {
+        let grey =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([128, 128, 128, 255])));
+        let mono = to_monochrome(&grey, 16, 16, &ImageOptions::default());
+        let black = mono.pixels().filter(|p| p.0[0] == 0).count();
+        assert!((96..=160).contains(&black), "{} of 256 dots black", black);
+
+        // Transparent pixels are paper, not ink
+        let clear = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([0, 0, 0, 0])));
+        assert!(to_monochrome(&clear, 8, 8, &ImageOptions::default())
+            .pixels()
+            .all(|p| p.0[0] == 255));
+    }
+
+    #[test]
+    fn test_dithering_and_adjustments() {
+        let grey =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([100, 100, 100, 255])));
+        let black_dots = |options: ImageOptions| {
+            to_monochrome(&grey, 16, 16, &options)
+                .pixels()
+                .filter(|p| p.0[0] == 0)
+                .count()
+        };
+        let threshold = ImageOptions {
+            dithering: Dithering::Threshold,
+            ..Default::default()
+        };
+        assert_eq!(black_dots(threshold), 256);
+        assert_eq!(
+            black_dots(ImageOptions {
+                threshold: 90,
+                ..threshold
+            }),
+            0
+        );
+        // Brightening and flattening contrast both lift 100 above 128
+        assert_eq!(
+            black_dots(ImageOptions {
+                brightness: 20,
+                ..threshold
+            }),
+            0
+        );
+        assert_eq!(
+            black_dots(ImageOptions {
+                contrast: -100,
+                ..threshold
+            }),
+            0
+        );
+
+        let floyd = black_dots(ImageOptions::default());
+        let atkinson = black_dots(ImageOptions {
+            dithering: Dithering::Atkinson,
+            ..Default::default()
+        });
+        // Both leave a pattern, mostly black for a dark grey
+        assert!((129..256).contains(&floyd), "{}", floyd);
+        assert!((129..256).contains(&atkinson), "{}", atkinson);
+    }
+
+    #[test]
+    fn test_logo_from_file_is_scaled_and_aligned() {
+        let path = std::env::temp_dir().join(format!("nexora-logo-{}.png", std::process::id()));
+        std::fs::write(
+            &path,
+            png(RgbaImage::from_pixel(100, 50, Rgba([0, 0, 0, 255]))),
+        )
+        .unwrap();
+        let source = path.to_str().unwrap();
+        assert!(is_image_location(source));
+
+        // 64 dots wide would be 32 tall; max_height brings it to 16 x 8
+        let bytes = image_to_escpos(
+            source,
+            96,
+            Some(64),
+            Some(8),
+            "right",
+            &ImageOptions::default(),
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&bytes[..8], &[0x1D, 0x76, 0x30, 0x00, 12, 0, 8, 0]);
+        assert_eq!(&bytes[8..20], &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF]);
+
+        let base64 = general_purpose::STANDARD.encode(png(RgbaImage::new(8, 8)));
+        assert!(!is_image_location(&base64));
+        let data_uri = format!("data:image/png;base64,{}", base64);
+        assert_eq!(
+            read_image_source(&data_uri).unwrap(),
+            read_image_source(&base64).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_nv_logo_replaces_slot() {
+        let base64 = general_purpose::STANDARD.encode(png(RgbaImage::from_pixel(
+            16,
+            4,
+            Rgba([0, 0, 0, 255]),
+        )));
+        let options = ImageOptions::default();
+        let bytes = nv_logo_store(&base64, 7, 576, Some(16), None, &options).unwrap();
+        // Delete "07", then define a 16 x 4 raster of 2 bytes a row
+        assert_eq!(
+            &bytes[..9],
+            &[0x1D, 0x28, 0x4C, 4, 0, 0x30, 0x42, b'0', b'7']
+        );
+        assert_eq!(
+            &bytes[9..25],
+            &[0x1D, 0x28, 0x4C, 19, 0, 0x30, 0x43, 0x30, b'0', b'7', 1, 16, 0, 4, 0, 0x31]
+        );
+        assert!(bytes[25..].iter().all(|&b| b == 0xFF));
+        assert_eq!(bytes.len(), 25 + 8);
+
+        assert!(nv_logo_store(&base64, 0, 576, None, None, &ImageOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_qr_raster_is_module_sized() {
+        let raster = qr_raster("42", 4, QrErrorCorrection::H).unwrap();
+        // Version 1 is 21 modules of 4 dots: 84 dots, 11 bytes a row
+        assert_eq!(&raster[..8], &[0x1D, 0x76, 0x30, 0x00, 11, 0, 84, 0]);
+        // The finder pattern's top row is 7 dark modules
+        assert_eq!(&raster[8..12], &[0xFF, 0xFF, 0xFF, 0xF0]);
+    }
+}
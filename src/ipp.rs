@@ -0,0 +1,514 @@
+// src/ipp.rs
+// Minimal IPP/1.1 client: Print-Job and Get-Printer-Attributes over plain HTTP
+
+use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::backend::{connect_tcp, io_error_reason, with_default_port, PrintBackend, PrinterState};
+use crate::config::Timeouts;
+
+pub const DEFAULT_PORT: u16 = 631;
+/// Where most printers (and CUPS queues via `/printers/<name>`) take jobs
+const DEFAULT_PATH: &str = "/ipp/print";
+
+const PRINT_JOB: u16 = 0x0002;
+const GET_PRINTER_ATTRIBUTES: u16 = 0x000B;
+
+// Delimiter and value tags (RFC 8010, section 3.5)
+const OPERATION_ATTRIBUTES: u8 = 0x01;
+const END_OF_ATTRIBUTES: u8 = 0x03;
+const TAG_ENUM: u8 = 0x23;
+const TAG_NAME: u8 = 0x42;
+const TAG_KEYWORD: u8 = 0x44;
+const TAG_URI: u8 = 0x45;
+const TAG_CHARSET: u8 = 0x47;
+const TAG_LANGUAGE: u8 = 0x48;
+const TAG_MIME_TYPE: u8 = 0x49;
+
+static REQUEST_ID: AtomicU32 = AtomicU32::new(1);
+
+// ==================== Backend ====================
+
+/// A printer that takes jobs over IPP instead of raw port 9100.
+///
+/// Jobs are sent whole as `application/octet-stream`, so the printer passes
+/// the ESC/POS bytes through untouched. Only plain `ipp://` is supported.
+#[derive(Debug, Clone)]
+pub struct IppBackend {
+    /// `host:port`
+    pub addr: String,
+    /// Printer resource, e.g. "/ipp/print"
+    pub path: String,
+    /// Job name shown in the printer's queue
+    pub document: &'static str,
+    /// The read timeout is how long the printer may take to answer a
+    /// request once it has the job
+    pub timeouts: Timeouts,
+}
+
+impl IppBackend {
+    /// Accepts `ipp://host[:port][/path]`, `http://...` or a bare host; the
+    /// port defaults to 631 and the path to `/ipp/print`
+    pub fn new(device_path: &str, document: &'static str) -> Result<Self, String> {
+        if device_path.starts_with("ipps://") || device_path.starts_with("https://") {
+            return Err("Encrypted IPP (ipps://) is not supported, use ipp://".to_string());
+        }
+        let rest = device_path
+            .strip_prefix("ipp://")
+            .or_else(|| device_path.strip_prefix("http://"))
+            .unwrap_or(device_path);
+        let (host, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, DEFAULT_PATH),
+        };
+        if host.is_empty() {
+            return Err(format!("Invalid IPP printer address: {}", device_path));
+        }
+
+        Ok(Self {
+            addr: with_default_port(host, DEFAULT_PORT),
+            path: path.to_string(),
+            document,
+            timeouts: Timeouts::default(),
+        })
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    fn printer_uri(&self) -> String {
+        format!("ipp://{}{}", self.addr, self.path)
+    }
+
+    /// Send one IPP request and return the parsed response, failing on an
+    /// unsuccessful IPP status
+    fn send(&self, request: Vec<u8>) -> Result<Response, String> {
+        let body = self.post(&request)?;
+        let response = Response::parse(&body)?;
+        if response.status > 0x00FF {
+            let message = response
+                .text("status-message")
+                .unwrap_or_else(|| status_name(response.status).to_string());
+            return Err(format!(
+                "IPP printer at {} refused the request: {} (0x{:04x})",
+                self.addr, message, response.status
+            ));
+        }
+        Ok(response)
+    }
+
+    /// POST an `application/ipp` body and return the response body
+    fn post(&self, body: &[u8]) -> Result<Vec<u8>, String> {
+        let mut stream = connect_tcp(&self.addr, &self.timeouts)?;
+
+        let header = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/ipp\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.addr,
+            body.len()
+        );
+        stream
+            .write_all(header.as_bytes())
+            .and_then(|()| stream.write_all(body))
+            .and_then(|()| stream.flush())
+            .map_err(|e| format!("Failed to write to {}: {}", self.addr, io_error_reason(&e)))?;
+
+        read_http_response(BufReader::new(stream))
+            .map_err(|e| format!("Bad response from IPP printer at {}: {}", self.addr, e))
+    }
+}
+
+impl PrintBackend for IppBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        // Print-Job needs the whole document in the request
+        Ok(None)
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        let mut request = Request::new(PRINT_JOB, &self.printer_uri());
+        request
+            .attribute(TAG_NAME, "requesting-user-name", "nexora")
+            .attribute(TAG_NAME, "job-name", self.document)
+            .attribute(TAG_MIME_TYPE, "document-format", "application/octet-stream");
+        self.send(request.finish(bytes)).map(|_| ())
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        match self.printer_state()? {
+            Some(state) if state.state == "stopped" => Err(format!(
+                "Printer is stopped: {}",
+                state.message.unwrap_or_else(|| state.reasons.join(", "))
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn printer_state(&self) -> Result<Option<PrinterState>, String> {
+        let mut request = Request::new(GET_PRINTER_ATTRIBUTES, &self.printer_uri());
+        request
+            .attribute(TAG_KEYWORD, "requested-attributes", "printer-state")
+            .value(TAG_KEYWORD, "printer-state-reasons")
+            .value(TAG_KEYWORD, "printer-state-message")
+            .value(TAG_KEYWORD, "printer-make-and-model");
+        let response = self.send(request.finish(&[]))?;
+
+        let state = match response.integer("printer-state") {
+            Some(3) => "idle",
+            Some(4) => "processing",
+            Some(5) => "stopped",
+            _ => "unknown",
+        };
+        let reasons = response.texts("printer-state-reasons");
+        let paper = if reasons.iter().any(|r| r.starts_with("media-empty")) {
+            "empty"
+        } else if reasons.iter().any(|r| r.starts_with("media-low")) {
+            "low"
+        } else {
+            "ok"
+        };
+        Ok(Some(PrinterState {
+            state: state.to_string(),
+            message: response.text("printer-state-message").filter(|m| !m.is_empty()),
+            model: response.text("printer-make-and-model"),
+            serial_number: None,
+            paper: Some(paper.to_string()),
+            reasons,
+        }))
+    }
+}
+
+// ==================== Encoding ====================
+
+/// An IPP request with its operation attributes
+struct Request {
+    bytes: Vec<u8>,
+}
+
+impl Request {
+    fn new(operation: u16, printer_uri: &str) -> Self {
+        let mut bytes = vec![1, 1];
+        bytes.extend_from_slice(&operation.to_be_bytes());
+        bytes.extend_from_slice(&REQUEST_ID.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+        bytes.push(OPERATION_ATTRIBUTES);
+
+        let mut request = Self { bytes };
+        // These three must come first, in this order
+        request
+            .attribute(TAG_CHARSET, "attributes-charset", "utf-8")
+            .attribute(TAG_LANGUAGE, "attributes-natural-language", "en")
+            .attribute(TAG_URI, "printer-uri", printer_uri);
+        request
+    }
+
+    fn attribute(&mut self, tag: u8, name: &str, value: &str) -> &mut Self {
+        self.bytes.push(tag);
+        self.bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        self.bytes.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    /// Another value for the attribute added last
+    fn value(&mut self, tag: u8, value: &str) -> &mut Self {
+        self.attribute(tag, "", value)
+    }
+
+    /// End the attributes and append the document, if any
+    fn finish(mut self, document: &[u8]) -> Vec<u8> {
+        self.bytes.push(END_OF_ATTRIBUTES);
+        self.bytes.extend_from_slice(document);
+        self.bytes
+    }
+}
+
+// ==================== Decoding ====================
+
+/// The status and attributes of an IPP response, all groups together
+struct Response {
+    status: u16,
+    attributes: Vec<(String, u8, Vec<u8>)>,
+}
+
+impl Response {
+    fn parse(body: &[u8]) -> Result<Self, String> {
+        let truncated = || "Truncated IPP response".to_string();
+        if body.len() < 8 {
+            return Err(truncated());
+        }
+        let status = u16::from_be_bytes([body[2], body[3]]);
+
+        let mut attributes: Vec<(String, u8, Vec<u8>)> = Vec::new();
+        let mut pos = 8;
+        while pos < body.len() {
+            let tag = body[pos];
+            pos += 1;
+            if tag == END_OF_ATTRIBUTES {
+                break;
+            }
+            if tag < 0x10 {
+                // Start of the next attribute group
+                continue;
+            }
+
+            let mut field = || -> Result<&[u8], String> {
+                let len_bytes = body.get(pos..pos + 2).ok_or_else(truncated)?;
+                let len = u16::from_be_bytes([len_bytes[0], len_bytes[1]]) as usize;
+                let field = body.get(pos + 2..pos + 2 + len).ok_or_else(truncated)?;
+                pos += 2 + len;
+                Ok(field)
+            };
+            let name = String::from_utf8_lossy(field()?).into_owned();
+            let value = field()?.to_vec();
+
+            // An empty name is another value of the previous attribute
+            let name = if name.is_empty() {
+                attributes.last().map(|(n, _, _)| n.clone()).unwrap_or_default()
+            } else {
+                name
+            };
+            attributes.push((name, tag, value));
+        }
+
+        Ok(Self { status, attributes })
+    }
+
+    fn texts(&self, name: &str) -> Vec<String> {
+        self.attributes
+            .iter()
+            .filter(|(n, _, _)| n == name)
+            .map(|(_, _, value)| String::from_utf8_lossy(value).into_owned())
+            .collect()
+    }
+
+    fn text(&self, name: &str) -> Option<String> {
+        self.texts(name).into_iter().next()
+    }
+
+    fn integer(&self, name: &str) -> Option<i32> {
+        self.attributes
+            .iter()
+            .find(|(n, tag, value)| n == name && *tag == TAG_ENUM && value.len() == 4)
+            .map(|(_, _, v)| i32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+    }
+}
+
+/// Names for the status codes a receipt printer is likely to send
+fn status_name(status: u16) -> &'static str {
+    match status {
+        0x0400 => "bad request",
+        0x0401 => "forbidden",
+        0x0402 => "not authenticated",
+        0x0406 => "not found",
+        0x040A => "document format not supported",
+        0x0500 => "internal error",
+        0x0501 => "operation not supported",
+        0x0506 => "printer not accepting jobs",
+        0x0507 => "printer busy",
+        _ => "error",
+    }
+}
+
+// ==================== HTTP ====================
+
+/// Read an HTTP/1.1 response to a `Connection: close` request and return its
+/// body, which may be chunked
+fn read_http_response(mut reader: impl BufRead) -> Result<Vec<u8>, String> {
+    let mut line = String::new();
+    read_line(&mut reader, &mut line)?;
+    let code: u16 = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("not an HTTP response: {}", line.trim()))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        read_line(&mut reader, &mut line)?;
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+    if code != 200 {
+        return Err(format!("HTTP {}", code));
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            read_line(&mut reader, &mut line)?;
+            let size = line.trim().split(';').next().unwrap_or("");
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| format!("bad chunk size: {}", line.trim()))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader
+                .read_exact(&mut body[start..])
+                .map_err(|e| e.to_string())?;
+            // CRLF after each chunk
+            read_line(&mut reader, &mut line)?;
+        }
+    } else if let Some(length) = content_length {
+        body.resize(length, 0);
+        reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    } else {
+        reader.read_to_end(&mut body).map_err(|e| e.to_string())?;
+    }
+    Ok(body)
+}
+
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> Result<(), String> {
+    line.clear();
+    match reader.read_line(line) {
+        Ok(0) => Err("connection closed early".to_string()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    /// An attribute as the printer would encode it
+    fn attribute(tag: u8, name: &str, value: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![tag];
+        bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    /// Answer one request with `body`, returning the request's IPP body
+    fn serve_once(listener: TcpListener, body: Vec<u8>, chunked: bool) -> Vec<u8> {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        let mut length = 0;
+        loop {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut request = vec![0; length];
+        reader.read_exact(&mut request).unwrap();
+
+        let mut stream = stream;
+        if chunked {
+            let (first, second) = body.split_at(body.len() / 2);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/ipp\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+                first.len()
+            )
+            .unwrap();
+            stream.write_all(first).unwrap();
+            write!(stream, "\r\n{:x}\r\n", second.len()).unwrap();
+            stream.write_all(second).unwrap();
+            stream.write_all(b"\r\n0\r\n\r\n").unwrap();
+        } else {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/ipp\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        }
+        request
+    }
+
+    fn response(status: u16, attributes: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![1, 1];
+        bytes.extend_from_slice(&status.to_be_bytes());
+        bytes.extend_from_slice(&7u32.to_be_bytes());
+        bytes.push(OPERATION_ATTRIBUTES);
+        bytes.extend(attribute(TAG_CHARSET, "attributes-charset", b"utf-8"));
+        bytes.push(0x04); // printer attributes group
+        for attr in attributes {
+            bytes.extend_from_slice(attr);
+        }
+        bytes.push(END_OF_ATTRIBUTES);
+        bytes
+    }
+
+    #[test]
+    fn test_device_path_forms() {
+        let printer = IppBackend::new("192.168.1.60", "Test").unwrap();
+        assert_eq!(printer.printer_uri(), "ipp://192.168.1.60:631/ipp/print");
+
+        let printer = IppBackend::new("ipp://cups.local:8631/printers/POS-80", "Test").unwrap();
+        assert_eq!(printer.addr, "cups.local:8631");
+        assert_eq!(printer.path, "/printers/POS-80");
+
+        assert!(IppBackend::new("ipps://printer", "Test").is_err());
+        assert!(IppBackend::new("ipp:///ipp/print", "Test").is_err());
+    }
+
+    #[test]
+    fn test_print_job_and_state_against_fake_printer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let printer = IppBackend::new(&addr, "Receipt").unwrap();
+        let job = b"\x1b@Hello\n\x1dV\x01";
+
+        let server = std::thread::spawn(move || {
+            let print = serve_once(listener.try_clone().unwrap(), response(0x0000, &[]), false);
+            let state = response(
+                0x0000,
+                &[
+                    attribute(TAG_ENUM, "printer-state", &5i32.to_be_bytes()),
+                    attribute(TAG_KEYWORD, "printer-state-reasons", b"media-empty"),
+                    attribute(TAG_KEYWORD, "", b"cover-open"),
+                ],
+            );
+            let query = serve_once(listener.try_clone().unwrap(), state, true);
+            let refused = response(0x040A, &[]);
+            serve_once(listener, refused, false);
+            (print, query)
+        });
+
+        printer.write_all(job).unwrap();
+        let state = printer.printer_state().unwrap().unwrap();
+        assert_eq!(state.state, "stopped");
+        assert_eq!(state.reasons, ["media-empty", "cover-open"]);
+        assert_eq!(state.paper.as_deref(), Some("empty"));
+        assert_eq!(
+            printer.write_all(job).unwrap_err(),
+            format!(
+                "IPP printer at {} refused the request: document format not supported (0x040a)",
+                printer.addr
+            )
+        );
+
+        let (print, query) = server.join().unwrap();
+        assert_eq!(&print[..4], &[1, 1, 0x00, 0x02]);
+        assert!(print.ends_with(&[&[END_OF_ATTRIBUTES][..], job].concat()));
+        let text = String::from_utf8_lossy(&print);
+        assert!(text.contains("application/octet-stream") && text.contains("Receipt"));
+        assert_eq!(&query[2..4], &[0x00, 0x0B]);
+    }
+}
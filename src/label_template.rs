@@ -0,0 +1,482 @@
+// src/label_template.rs
+// Label templates: fixed-size labels with elements placed in dots, printed
+// on TSPL, ZPL and EPL label printers
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::barcode_printer::{tspl_barcode_type, BarcodePrinterConfig, BarcodeType};
+use crate::template_compile::TextPattern;
+use crate::template_render::{ReceiptData, TemplateRenderer};
+
+/// Size, media and contents of a label, in printer dots from its top left
+/// corner
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LabelSpec {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub media: LabelMedia,
+    pub elements: Vec<LabelElement>,
+}
+
+/// How the printer finds where each label starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LabelMedia {
+    /// Labels on a liner with a gap of `gap` dots between them
+    Gap {
+        #[serde(default = "default_gap")]
+        gap: u32,
+    },
+    /// A black mark `mark` dots long on the back of the liner; the label
+    /// starts `offset` dots after it
+    BlackMark {
+        #[serde(default = "default_gap")]
+        mark: u32,
+        #[serde(default)]
+        offset: u32,
+    },
+    /// Plain paper, `height` dots per label
+    Continuous,
+}
+
+impl Default for LabelMedia {
+    fn default() -> Self {
+        LabelMedia::Gap { gap: default_gap() }
+    }
+}
+
+/// 2 mm at 203 dpi, the usual gap between labels
+fn default_gap() -> u32 {
+    16
+}
+
+/// Something printed on a label, `x` and `y` dots from its top left corner.
+/// Content can use placeholders such as `{{item.name}}`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LabelElement {
+    Text {
+        x: u32,
+        y: u32,
+        content: String,
+        /// 1 to 8 (default 1), each step 16 by 24 dots a character
+        #[serde(default)]
+        font_size: Option<u8>,
+    },
+    Barcode {
+        x: u32,
+        y: u32,
+        content: String,
+        /// CODE128 (default), CODE39, EAN13, EAN8 or UPCA
+        #[serde(default)]
+        format: Option<String>,
+        /// Height of the bars in dots (default 80)
+        #[serde(default)]
+        height: Option<u32>,
+        /// Width of the narrowest bar in dots (default 2)
+        #[serde(default)]
+        width: Option<u32>,
+        /// Print the content under the bars, on by default
+        #[serde(default)]
+        hri: Option<bool>,
+    },
+    Qr {
+        x: u32,
+        y: u32,
+        content: String,
+        /// Dots per module, 1 to 10 (default 4)
+        #[serde(default)]
+        size: Option<u32>,
+    },
+    /// A rectangle outline, `thickness` dots wide (default 2)
+    Box {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        thickness: Option<u32>,
+    },
+}
+
+impl LabelElement {
+    /// Where the element's top left corner is
+    pub fn origin(&self) -> (u32, u32) {
+        match *self {
+            LabelElement::Text { x, y, .. }
+            | LabelElement::Barcode { x, y, .. }
+            | LabelElement::Qr { x, y, .. }
+            | LabelElement::Box { x, y, .. } => (x, y),
+        }
+    }
+}
+
+/// The printer's command language
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Language {
+    Tspl,
+    Zpl,
+    Epl,
+}
+
+/// Build the bytes that print `copies` of a label for `data` in the
+/// printer's protocol, TSPL unless it is ZPL or EPL.
+pub fn build_template_label(
+    config: &BarcodePrinterConfig,
+    spec: &LabelSpec,
+    data: &ReceiptData,
+    copies: u32,
+) -> Result<Vec<u8>, String> {
+    let language = match config.protocol.to_uppercase().as_str() {
+        "ZPL" => Language::Zpl,
+        "EPL" => Language::Epl,
+        _ => Language::Tspl,
+    };
+    // Placeholders are filled in as on receipts, for a label as wide as
+    // this many font A characters
+    let renderer = TemplateRenderer::new(spec.width / 12);
+    let render = |content: &str| {
+        let pattern = TextPattern::parse(content);
+        renderer.render_text(&pattern, data).into_owned()
+    };
+
+    let mut out = String::new();
+    header(&mut out, language, spec, config.dpi);
+    for element in &spec.elements {
+        match element {
+            LabelElement::Text {
+                x,
+                y,
+                content,
+                font_size,
+            } => {
+                let size = font_size.unwrap_or(1).clamp(1, 8) as u32;
+                let text = render(content);
+                let _ = match language {
+                    Language::Tspl => writeln!(
+                        out,
+                        "TEXT {},{},\"3\",0,{},{},\"{}\"\r",
+                        x,
+                        y,
+                        size,
+                        size,
+                        text.replace('"', "\\[\"]")
+                    ),
+                    Language::Zpl => writeln!(
+                        out,
+                        "^FO{},{}^A0N,{},{}^FH^FD{}^FS",
+                        x,
+                        y,
+                        24 * size,
+                        16 * size,
+                        zpl_field(&text)
+                    ),
+                    Language::Epl => writeln!(
+                        out,
+                        "A{},{},0,4,{},{},N,\"{}\"",
+                        x,
+                        y,
+                        size,
+                        size,
+                        text.replace('"', "\\\"")
+                    ),
+                };
+            }
+            LabelElement::Barcode {
+                x,
+                y,
+                content,
+                format,
+                height,
+                width,
+                hri,
+            } => {
+                let format = format.as_deref().unwrap_or("CODE128");
+                let barcode_type = BarcodeType::from_str(format).unwrap_or(BarcodeType::Code128);
+                if matches!(barcode_type, BarcodeType::Qr) {
+                    return Err("QR codes on labels are `qr` elements".to_string());
+                }
+                let height = height.unwrap_or(80);
+                let narrow = width.unwrap_or(2).clamp(1, 10);
+                let hri = hri.unwrap_or(true);
+                let content = render(content);
+                let _ = match language {
+                    Language::Tspl => writeln!(
+                        out,
+                        "BARCODE {},{},\"{}\",{},{},0,{},{},\"{}\"\r",
+                        x,
+                        y,
+                        tspl_barcode_type(&barcode_type),
+                        height,
+                        u8::from(hri),
+                        narrow,
+                        narrow * 2,
+                        content
+                    ),
+                    Language::Zpl => writeln!(
+                        out,
+                        "^FO{},{}^BY{}{}^FD{}^FS",
+                        x,
+                        y,
+                        narrow,
+                        zpl_barcode(&barcode_type, height, hri),
+                        content
+                    ),
+                    Language::Epl => writeln!(
+                        out,
+                        "B{},{},0,{},{},{},{},{},\"{}\"",
+                        x,
+                        y,
+                        epl_barcode_type(&barcode_type),
+                        narrow,
+                        narrow * 2,
+                        height,
+                        if hri { "B" } else { "N" },
+                        content
+                    ),
+                };
+            }
+            LabelElement::Qr {
+                x,
+                y,
+                content,
+                size,
+            } => {
+                let size = size.unwrap_or(4).clamp(1, 10);
+                let content = render(content);
+                let _ = match language {
+                    Language::Tspl => writeln!(
+                        out,
+                        "QRCODE {},{},M,{},A,0,M2,S3,\"{}\"\r",
+                        x,
+                        y,
+                        size,
+                        content.replace('"', "\\[\"]")
+                    ),
+                    Language::Zpl => writeln!(
+                        out,
+                        "^FO{},{}^BQN,2,{}^FH^FDMA,{}^FS",
+                        x,
+                        y,
+                        size,
+                        zpl_field(&content)
+                    ),
+                    Language::Epl => {
+                        return Err("EPL printers can't print QR codes".to_string());
+                    }
+                };
+            }
+            LabelElement::Box {
+                x,
+                y,
+                width,
+                height,
+                thickness,
+            } => {
+                let thickness = thickness.unwrap_or(2);
+                let _ = match language {
+                    Language::Tspl => writeln!(
+                        out,
+                        "BOX {},{},{},{},{}\r",
+                        x,
+                        y,
+                        x + width,
+                        y + height,
+                        thickness
+                    ),
+                    Language::Zpl => writeln!(
+                        out,
+                        "^FO{},{}^GB{},{},{}^FS",
+                        x, y, width, height, thickness
+                    ),
+                    Language::Epl => writeln!(
+                        out,
+                        "X{},{},{},{},{}",
+                        x,
+                        y,
+                        thickness,
+                        x + width,
+                        y + height
+                    ),
+                };
+            }
+        }
+    }
+
+    let copies = copies.max(1);
+    let _ = match language {
+        Language::Tspl => writeln!(out, "PRINT 1,{}\r", copies),
+        Language::Zpl => writeln!(out, "^PQ{}\n^XZ", copies),
+        Language::Epl => writeln!(out, "P{}", copies),
+    };
+    Ok(out.into_bytes())
+}
+
+/// Label size and media sensing, and a cleared label to draw on
+fn header(out: &mut String, language: Language, spec: &LabelSpec, dpi: u32) {
+    let mm = |dots: u32| format!("{:.1} mm", dots as f64 * 25.4 / dpi.max(1) as f64);
+    let _ = match language {
+        Language::Tspl => {
+            let _ = writeln!(out, "SIZE {}, {}\r", mm(spec.width), mm(spec.height));
+            let _ = match spec.media {
+                LabelMedia::Gap { gap } => writeln!(out, "GAP {}, 0 mm\r", mm(gap)),
+                LabelMedia::BlackMark { mark, offset } => {
+                    writeln!(out, "BLINE {}, {}\r", mm(mark), mm(offset))
+                }
+                LabelMedia::Continuous => writeln!(out, "GAP 0 mm, 0 mm\r"),
+            };
+            writeln!(out, "DIRECTION 0\r\nCLS\r")
+        }
+        Language::Zpl => {
+            let sensing = match spec.media {
+                LabelMedia::Gap { .. } => "Y",
+                LabelMedia::BlackMark { .. } => "M",
+                LabelMedia::Continuous => "N",
+            };
+            writeln!(
+                out,
+                "^XA\n^PW{}\n^LL{}\n^MN{}",
+                spec.width, spec.height, sensing
+            )
+        }
+        Language::Epl => {
+            let media = match spec.media {
+                LabelMedia::Gap { gap } => gap.to_string(),
+                LabelMedia::BlackMark { mark, offset } => format!("B{}+{}", mark, offset),
+                LabelMedia::Continuous => "0".to_string(),
+            };
+            writeln!(out, "N\nq{}\nQ{},{}", spec.width, spec.height, media)
+        }
+    };
+}
+
+/// The ZPL barcode command after `^BY`, without the data
+fn zpl_barcode(barcode_type: &BarcodeType, height: u32, hri: bool) -> String {
+    let hri = if hri { "Y" } else { "N" };
+    match barcode_type {
+        BarcodeType::Code39 => format!("^B3N,N,{},{},N", height, hri),
+        BarcodeType::Ean13 => format!("^BEN,{},{},N", height, hri),
+        BarcodeType::Ean8 => format!("^B8N,{},{},N", height, hri),
+        BarcodeType::Upca => format!("^BUN,{},{},N,Y", height, hri),
+        BarcodeType::Code128 | BarcodeType::Qr => format!("^BCN,{},{},N,N", height, hri),
+    }
+}
+
+fn epl_barcode_type(barcode_type: &BarcodeType) -> &'static str {
+    match barcode_type {
+        BarcodeType::Code39 => "3",
+        BarcodeType::Ean13 => "E30",
+        BarcodeType::Ean8 => "E80",
+        BarcodeType::Upca => "UA0",
+        BarcodeType::Code128 | BarcodeType::Qr => "1",
+    }
+}
+
+/// Field data for `^FH`, with the characters ZPL reads as commands in hex
+fn zpl_field(text: &str) -> String {
+    let mut field = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '^' => field.push_str("_5E"),
+            '~' => field.push_str("_7E"),
+            '_' => field.push_str("_5F"),
+            _ => field.push(c),
+        }
+    }
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{SerialSettings, Timeouts};
+
+    fn config(protocol: &str) -> BarcodePrinterConfig {
+        BarcodePrinterConfig {
+            connection_type: "USB".to_string(),
+            device_path: "/dev/usb/lp0".to_string(),
+            protocol: protocol.to_string(),
+            label_width_mm: 50,
+            label_height_mm: 30,
+            dpi: 203,
+            serial: SerialSettings::default(),
+            timeouts: Timeouts::default(),
+        }
+    }
+
+    fn price_tag(media: serde_json::Value) -> LabelSpec {
+        serde_json::from_value(serde_json::json!({
+            "width": 400, "height": 240, "media": media,
+            "elements": [
+                { "type": "text", "x": 16, "y": 16, "content": "{{store_name}}" },
+                { "type": "text", "x": 16, "y": 56, "content": "{{total}}", "font_size": 2 },
+                { "type": "barcode", "x": 16, "y": 120, "content": "{{order_id}}", "height": 60 },
+                { "type": "box", "x": 0, "y": 0, "width": 400, "height": 240 }
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn data() -> ReceiptData {
+        ReceiptData {
+            store_name: Some("Corner \"Shop\"".to_string()),
+            order_id: "1001".to_string(),
+            total: 3.5,
+            ..Default::default()
+        }
+    }
+
+    fn build(protocol: &str, spec: &LabelSpec) -> String {
+        let bytes = build_template_label(&config(protocol), spec, &data(), 2).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_tspl_places_elements_in_dots() {
+        let tspl = build("TSPL", &price_tag(serde_json::json!({ "type": "gap" })));
+        assert_eq!(
+            tspl,
+            "SIZE 50.0 mm, 30.0 mm\r\n\
+             GAP 2.0 mm, 0 mm\r\n\
+             DIRECTION 0\r\n\
+             CLS\r\n\
+             TEXT 16,16,\"3\",0,1,1,\"Corner \\[\"]Shop\\[\"]\"\r\n\
+             TEXT 16,56,\"3\",0,2,2,\"3.50\"\r\n\
+             BARCODE 16,120,\"128\",60,1,0,2,4,\"1001\"\r\n\
+             BOX 0,0,400,240,2\r\n\
+             PRINT 1,2\r\n"
+        );
+    }
+
+    #[test]
+    fn test_media_sensing_per_protocol() {
+        let mark = price_tag(serde_json::json!({ "type": "black_mark", "mark": 24, "offset": 8 }));
+        assert!(build("TSPL", &mark).contains("BLINE 3.0 mm, 1.0 mm\r\n"));
+        assert!(build("EPL", &mark).starts_with("N\nq400\nQ240,B24+8\n"));
+        assert!(build("ZPL", &mark).starts_with("^XA\n^PW400\n^LL240\n^MNM\n"));
+
+        let continuous = price_tag(serde_json::json!({ "type": "continuous" }));
+        assert!(build("ZPL", &continuous).contains("^MNN\n"));
+        assert!(build("EPL", &continuous).contains("\nQ240,0\n"));
+
+        let zpl = build("ZPL", &price_tag(serde_json::json!({ "type": "gap" })));
+        assert!(zpl.contains("^FO16,56^A0N,48,32^FH^FD3.50^FS\n"));
+        assert!(zpl.contains("^FO16,120^BY2^BCN,60,Y,N,N^FD1001^FS\n"));
+        assert!(zpl.ends_with("^PQ2\n^XZ\n"));
+    }
+
+    #[test]
+    fn test_epl_has_no_qr_codes() {
+        let spec: LabelSpec = serde_json::from_value(serde_json::json!({
+            "width": 400, "height": 240,
+            "elements": [{ "type": "qr", "x": 0, "y": 0, "content": "1001" }]
+        }))
+        .unwrap();
+        assert!(build_template_label(&config("EPL"), &spec, &data(), 1).is_err());
+        assert!(build("ZPL", &spec).contains("^FO0,0^BQN,2,4^FH^FDMA,1001^FS\n"));
+    }
+}
@@ -0,0 +1,193 @@
+//! Nexora POS Printer Manager core.
+//!
+//! JSON receipt templates, the renderer that turns them into
+//! [`PrintCommand`]s, the command encoders that turn them into printer bytes
+//! (ESC/POS, Star; see [`CommandEncoder`]), and the printer connection layer.
+//! The desktop app and the HTTP API are built on top of this crate and can be
+//! left out entirely when embedding it in another service.
+//!
+//! # Features
+//!
+//! - `http-server` — the Axum HTTP API ([`http_server`]), the print worker
+//!   it hands jobs to and network printer discovery. Pulls in tokio and axum.
+//! - `gui` *(default)* — the Slint desktop app with tray icon and autostart.
+//!   Implies `http-server`, `usb` and `raster-text`.
+//! - `usb` — printing to printer-class USB devices over libusb ([`usb`]), for
+//!   printers that don't show up as a serial port. Builds libusb from source
+//!   when it isn't installed.
+//! - `raster-text` — printing lines the printer's character sets can't hold
+//!   as bitmaps ([`raster_text`]). Embeds a 750 KB font.
+//!
+//! To use only the renderer, encoder and printer layer:
+//!
+//! ```toml
+//! [dependencies]
+//! nexora-printer-manager = { version = "1.6", default-features = false }
+//! ```
+//!
+//! # Rendering a receipt
+//!
+//! ```
+//! use nexora_printer_manager::{encoder, load_template, PrintCommand, ReceiptData, TemplateRenderer};
+//!
+//! let template = load_template(r#"{
+//!     "id": "simple",
+//!     "name": "Simple Receipt",
+//!     "version": "1.0.0",
+//!     "paper_width": 32,
+//!     "layout": {
+//!         "sections": [{
+//!             "type": "header",
+//!             "elements": [
+//!                 { "type": "text", "content": "{{store_name}}", "align": "center", "bold": true },
+//!                 { "type": "row", "left": "TOTAL", "right": "${{total}}" }
+//!             ]
+//!         }]
+//!     }
+//! }"#).unwrap();
+//!
+//! let data = ReceiptData {
+//!     store_name: Some("Corner Cafe".to_string()),
+//!     order_id: "1001".to_string(),
+//!     timestamp: "2024-01-15 14:30:00".to_string(),
+//!     total: 12.5,
+//!     ..Default::default()
+//! };
+//!
+//! // Compile once, render for every order
+//! let renderer = TemplateRenderer::new(template.paper_width.unwrap_or(48));
+//! let compiled = renderer.compile(&template);
+//! let commands = renderer.render_to_commands(&compiled, &data).unwrap();
+//! assert!(commands
+//!     .iter()
+//!     .any(|c| matches!(c, PrintCommand::WriteLine(line) if line == "Corner Cafe")));
+//!
+//! // Raw ESC/POS bytes, ready for any transport
+//! let bytes = encoder::encode_commands(&commands);
+//! assert!(bytes.starts_with(&[0x1B, 0x40]));
+//!
+//! // The same receipt for a Star printer
+//! use nexora_printer_manager::{CommandEncoder, StarEncoder};
+//! let bytes = StarEncoder.encode_all(&commands);
+//! assert!(bytes.starts_with(&[0x1B, 0x40]));
+//! ```
+//!
+//! # Printing through a connection
+//!
+//! ```no_run
+//! use nexora_printer_manager::{PrinterConfig, PrinterManager, ReceiptData};
+//!
+//! let mut manager = PrinterManager::new();
+//! manager.connect(PrinterConfig {
+//!     connection_type: "Network".to_string(),
+//!     device_path: "192.168.1.50:9100".to_string(),
+//!     store_name: "Corner Cafe".to_string(),
+//!     store_address: "1 Main St".to_string(),
+//!     footer_message: "Thank you!".to_string(),
+//!     serial: Default::default(),
+//!     timeouts: Default::default(),
+//!     roles: vec!["receipt".to_string()],
+//!     protocol: None,
+//!     profile: None,
+//!     code_pages: Vec::new(),
+//!     cjk_encoding: None,
+//!     raster_text: false,
+//!     upside_down: false,
+//!     density: None,
+//!     print_speed: None,
+//!     print_area: None,
+//!     write_pacing: None,
+//! })?;
+//!
+//! // Render while holding the manager, then write without it
+//! let data = ReceiptData { order_id: "1001".to_string(), ..Default::default() };
+//! let job = manager.prepare_template_print(&data)?;
+//! job.send()?;
+//! # Ok::<(), String>(())
+//! ```
+
+pub mod backend;
+pub mod barcode;
+pub mod barcode_printer;
+mod bidi;
+pub mod codepage;
+pub mod config;
+pub mod encoder;
+pub mod hotplug;
+pub mod html_preview;
+pub mod image_print;
+pub mod ipp;
+pub mod label_template;
+pub mod lock;
+pub mod logo_cache;
+pub mod optimizer;
+pub mod pdf;
+pub mod printer;
+pub mod profiles;
+pub mod realtime_status;
+pub mod reconnect;
+pub mod routing;
+mod serial;
+pub mod snmp;
+pub mod spool;
+pub mod telemetry;
+pub mod template_cache;
+pub mod template_compile;
+pub mod template_dir;
+pub mod template_data;
+pub mod template_expr;
+pub mod template_filter;
+pub mod template_gift;
+pub mod template_import;
+pub mod template_markup;
+pub mod template_migrate;
+pub mod template_parse;
+pub mod template_render;
+pub mod template_validate;
+pub mod text_preview;
+
+#[cfg(feature = "http-server")]
+pub mod discovery;
+#[cfg(feature = "http-server")]
+pub mod http_server;
+#[cfg(feature = "http-server")]
+pub mod print_worker;
+#[cfg(feature = "http-server")]
+pub mod shutdown;
+#[cfg(feature = "raster-text")]
+pub mod png_preview;
+#[cfg(feature = "raster-text")]
+pub mod raster_text;
+#[cfg(feature = "usb")]
+pub mod usb;
+#[cfg(test)]
+mod golden;
+
+pub use backend::{MockBackend, PrintBackend, PrinterState};
+pub use barcode_printer::{BarcodeLabelRequest, BarcodePrinterConfig, BarcodeType};
+pub use codepage::{CjkEncoding, CodePage};
+pub use config::{PrinterConfig, SerialSettings, ShutdownSettings, Timeouts, WritePacing};
+pub use encoder::{CommandEncoder, EscPosEncoder, PrinterEncoder, Protocol, StarEncoder};
+pub use label_template::{LabelElement, LabelMedia, LabelSpec};
+pub use logo_cache::{CachedDimensions, LogoCacheEntry, LogoMetadata};
+pub use printer::{
+    BarcodePrinterManager, LineItem, PrintOutput, PrintSnapshot, PrinterInfo, PrinterManager,
+    Receipt, SetTemplateError, StateQuery, DEFAULT_PRINTER,
+};
+pub use reconnect::{ConnectionState, ReconnectPolicy, ReconnectSupervisor};
+pub use routing::{RoutedTicket, RoutingField, RoutingRule};
+pub use spool::Spool;
+pub use telemetry::{FailureCounts, PrintStats};
+pub use template_cache::{
+    TemplateCache, TemplateCacheLimits, TemplateCacheStats, TemplateReplacement, VersionConflict,
+};
+pub use template_compile::{CompiledTemplate, ConditionIssue, TemplateValidation};
+pub use template_gift::GiftReceipt;
+pub use template_migrate::TEMPLATE_SCHEMA_VERSION;
+pub use template_parse::TemplateParseError;
+pub use template_render::{
+    load_template, CommandSink, Element, Font, PrintArea, PrintCommand, QrErrorCorrection,
+    ReceiptData, ReceiptItem, ReceiptTemplate, ReceiptType, Section, TaxLine, TemplateLayout,
+    TemplateRenderer,
+};
+pub use template_validate::{validate_template, Diagnostic, Severity};
@@ -0,0 +1,46 @@
+// src/lock.rs
+// Mutex helpers that survive poisoning
+
+use std::sync::{Mutex, MutexGuard};
+
+/// Lock a mutex, recovering the inner state if a previous holder panicked.
+///
+/// The printer and barcode managers hold no invariants that a panic mid-job
+/// can break (connection and caches are only replaced wholesale), so taking
+/// the inner state is always safe and keeps one bad print from taking the
+/// HTTP server and UI down with it.
+pub trait LockExt<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockExt<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(|poisoned| {
+            log::warn!("Recovered from a poisoned lock after a panic in a previous holder");
+            self.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_lock_or_recover_after_panic() {
+        let mutex = Arc::new(Mutex::new(1));
+        let clone = Arc::clone(&mutex);
+        let _ = std::thread::spawn(move || {
+            let mut guard = clone.lock().unwrap();
+            *guard = 2;
+            panic!("poison the lock");
+        })
+        .join();
+
+        assert!(mutex.is_poisoned());
+        assert_eq!(*mutex.lock_or_recover(), 2);
+        assert!(!mutex.is_poisoned());
+    }
+}
@@ -1,13 +1,46 @@
 // src/logo_cache.rs
 // Logo caching functionality with in-memory and disk persistence
 
-use crate::{LogoCacheEntry, LogoMetadata, PrinterManager};
+use crate::template_compile::CompiledTemplate;
+use crate::template_render::LogoElement;
+use crate::PrinterManager;
 use chrono::Utc;
+use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
+// ==================== Logo Cache Models ====================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedDimensions {
+    pub align: String,                  // left, center, right
+    pub max_width_dots: Option<u32>,
+    pub max_height_dots: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogoMetadata {
+    pub file_size_bytes: usize,
+    pub original_width: u32,
+    pub original_height: u32,
+    pub mime_type: Option<String>,
+    pub usage_count: u32,
+    pub cached_dimensions: Option<CachedDimensions>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogoCacheEntry {
+    pub id: String,                      // Named ID (user-provided or auto-generated)
+    pub content_hash: String,            // SHA256 of base64 (for dedup)
+    pub base64_data: String,             // Original base64 image
+    pub file_path: Option<String>,       // Path if persisted to disk
+    pub metadata: LogoMetadata,          // Stats and rendering prefs
+    pub created_at: String,              // ISO 8601 timestamp
+    pub last_used: Option<String>,       // ISO 8601 timestamp
+}
+
 /// Compute SHA256 hash of base64 string
 pub fn compute_content_hash(base64_data: &str) -> String {
     let mut hasher = Sha256::new();
@@ -119,7 +152,11 @@ pub fn save_logo_to_disk(manager: &mut PrinterManager, logo: &LogoCacheEntry) ->
 
     // Determine filename: use user-provided ID if not auto-generated, else use hash
     let filename = if logo.id.starts_with("logo-") {
-        format!("{}.b64", &logo.id[6..]) // Remove "logo-" prefix for auto IDs
+        // Remove "logo-" prefix for auto IDs; `get` avoids panicking on short or non-ASCII IDs
+        match logo.id.get(6..).filter(|rest| !rest.is_empty()) {
+            Some(rest) => format!("{}.b64", rest),
+            None => format!("{}.b64", logo.id),
+        }
     } else {
         format!("{}.b64", logo.id)
     };
@@ -368,8 +405,16 @@ pub fn auto_cache_template_logos(manager: &mut PrinterManager, template: &mut cr
                     if let Some(source) = &logo_elem.source {
                         // Check if source looks like base64 (contains base64 markers or is long data-uri)
                         if source.contains("base64,") || source.len() > 100 || source.contains("/") || source.contains("\\") {
-                            // This looks like base64, cache it
-                            match cache_logo(manager, None, source) {
+                            // A file or URL is read once and cached as base64;
+                            // anything else looks like base64 already
+                            let data = match location_to_base64(source) {
+                                Ok(data) => data,
+                                Err(e) => {
+                                    log::warn!("Failed to auto-cache logo: {}", e);
+                                    continue;
+                                }
+                            };
+                            match cache_logo(manager, None, &data) {
                                 Ok((logo_id, _, newly_cached)) => {
                                     logo_elem.logo_id = Some(logo_id);
                                     if newly_cached {
@@ -392,6 +437,18 @@ pub fn auto_cache_template_logos(manager: &mut PrinterManager, template: &mut cr
     Ok(auto_cached_count)
 }
 
+/// The base64 data of a logo given as a file path or URL, or `source`
+/// unchanged if it is already image data
+fn location_to_base64(source: &str) -> Result<String, String> {
+    use base64::{engine::general_purpose, Engine as _};
+
+    if !crate::image_print::is_image_location(source) {
+        return Ok(source.to_string());
+    }
+    let bytes = crate::image_print::read_image_source(source)?;
+    Ok(general_purpose::STANDARD.encode(bytes))
+}
+
 /// Resolve all logo references in a template for rendering
 /// Replaces logo_id and smart-detected source strings with actual base64 data
 /// Also updates usage statistics for resolved logos
@@ -399,32 +456,8 @@ pub fn resolve_template_logos(manager: &mut PrinterManager, template: &mut crate
     for section in &mut template.layout.sections {
         for element in &mut section.elements {
             if let crate::Element::Logo(logo_elem) = element {
-                if !logo_elem.source.is_some() || logo_elem.logo_id.is_some() {
-                    // Try to resolve logo_id first
-                    if let Some(logo_id) = &logo_elem.logo_id.clone() {
-                        if let Some(entry) = get_logo(manager, logo_id) {
-                            logo_elem.source = Some(entry.base64_data);
-                            // Update usage stats
-                            let _ = update_logo_usage(manager, logo_id);
-                            log::debug!("Resolved logo_id to base64: {}", logo_id);
-                        } else {
-                            log::warn!("Logo not found in cache: {}", logo_id);
-                        }
-                    } else if let Some(source) = logo_elem.source.clone() {
-                        // Check if source might be a logo ID (not base64-looking)
-                        // Base64 typically has: data-uri prefix, long length, or specific markers
-                        if !source.contains("base64,") && !source.contains("/") && !source.contains("\\") && source.len() < 100 {
-                            // Looks like it might be an ID, try to resolve it
-                            if let Some(entry) = get_logo(manager, &source) {
-                                logo_elem.source = Some(entry.base64_data);
-                                // Update usage stats
-                                let _ = update_logo_usage(manager, &source);
-                                log::debug!("Resolved source string to cached logo: {}", source);
-                            }
-                            // If not found in cache, keep original source (might fail during rendering, which is OK)
-                        }
-                        // Otherwise keep source as-is (it's likely base64)
-                    }
+                if let Some(base64_data) = resolve_logo_source(manager, logo_elem) {
+                    logo_elem.source = Some(base64_data);
                 }
             }
         }
@@ -432,4 +465,53 @@ pub fn resolve_template_logos(manager: &mut PrinterManager, template: &mut crate
     Ok(())
 }
 
+/// Resolve the logos of a compiled template without modifying it
+/// Returns base64 data keyed by each element's logo_id (or source), ready for
+/// `TemplateRenderer::with_resolved_logos`. Also updates usage statistics
+pub fn resolve_compiled_logos(
+    manager: &mut PrinterManager,
+    template: &CompiledTemplate,
+) -> HashMap<String, String> {
+    let mut resolved = HashMap::new();
+    for logo_elem in template.logos() {
+        let key = logo_elem.logo_id.as_ref().or(logo_elem.source.as_ref());
+        if let Some(key) = key {
+            if let Some(base64_data) = resolve_logo_source(manager, logo_elem) {
+                resolved.insert(key.clone(), base64_data);
+            }
+        }
+    }
+    resolved
+}
 
+/// Look up the cached image data a logo element refers to, if any
+fn resolve_logo_source(manager: &mut PrinterManager, logo_elem: &LogoElement) -> Option<String> {
+    if logo_elem.source.is_none() || logo_elem.logo_id.is_some() {
+        // Try to resolve logo_id first
+        if let Some(logo_id) = &logo_elem.logo_id {
+            if let Some(entry) = get_logo(manager, logo_id) {
+                // Update usage stats
+                let _ = update_logo_usage(manager, logo_id);
+                log::debug!("Resolved logo_id to base64: {}", logo_id);
+                return Some(entry.base64_data);
+            } else {
+                log::warn!("Logo not found in cache: {}", logo_id);
+            }
+        } else if let Some(source) = &logo_elem.source {
+            // Check if source might be a logo ID (not base64-looking)
+            // Base64 typically has: data-uri prefix, long length, or specific markers
+            if !source.contains("base64,") && !source.contains("/") && !source.contains("\\") && source.len() < 100 {
+                // Looks like it might be an ID, try to resolve it
+                if let Some(entry) = get_logo(manager, source) {
+                    // Update usage stats
+                    let _ = update_logo_usage(manager, source);
+                    log::debug!("Resolved source string to cached logo: {}", source);
+                    return Some(entry.base64_data);
+                }
+                // If not found in cache, keep original source (might fail during rendering, which is OK)
+            }
+            // Otherwise keep source as-is (it's likely base64)
+        }
+    }
+    None
+}
@@ -3,9 +3,28 @@
 
 #![windows_subsystem = "windows"]
 
-use serde::{Deserialize, Serialize};
+use nexora_printer_manager::config::{
+    get_templates_dir, load_barcode_config, load_config, load_routing_config,
+    load_shutdown_settings, load_template_cache_limits, load_template_validation,
+    save_barcode_config, save_config,
+};
+use nexora_printer_manager::discovery::{self, ScanOptions};
+use nexora_printer_manager::encoder::PrintSpeed;
+use nexora_printer_manager::hotplug::HotplugWatcher;
+#[cfg(target_os = "windows")]
+use nexora_printer_manager::backend::installed_printers;
+use nexora_printer_manager::lock::LockExt;
+use nexora_printer_manager::print_worker::PrintWorker;
+use nexora_printer_manager::template_dir::{TemplateDir, TemplateDirWatcher};
+use nexora_printer_manager::usb::usb_printers;
+use nexora_printer_manager::{
+    http_server, logo_cache, shutdown, BarcodePrinterConfig, BarcodePrinterManager,
+    ConnectionState, PrinterConfig, PrinterManager, PrinterState, ReconnectPolicy,
+    ReconnectSupervisor, SerialSettings, ShutdownSettings, Spool, DEFAULT_PRINTER,
+};
 use slint::{CloseRequestResponse, Model};
 use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use tray_icon::{
     menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
@@ -13,896 +32,9 @@ use tray_icon::{
 };
 
 mod autostart;
-mod http_server;
-mod image_print;
-mod template_render;
-mod logo_cache;
-mod barcode_printer;
-pub use barcode_printer::{BarcodePrinterConfig, BarcodeType, BarcodeLabelRequest};
-
-pub use template_render::{
-    Element, ReceiptData, ReceiptItem, ReceiptTemplate, Section, TemplateLayout, TemplateRenderer,
-};
 
 slint::include_modules!();
 
-// ==================== Configuration Models ====================
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PrinterConfig {
-    pub connection_type: String,
-    pub device_path: String,
-    pub store_name: String,
-    pub store_address: String,
-    pub footer_message: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[allow(dead_code)]
-pub struct LineItem {
-    pub name: String,
-    pub quantity: u32,
-    pub price: f64,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[allow(dead_code)]
-pub struct Receipt {
-    pub order_id: String,
-    pub timestamp: String,
-    pub items: Vec<LineItem>,
-    pub subtotal: f64,
-    pub tax: f64,
-    pub total: f64,
-    pub payment_method: String,
-}
-
-// ==================== Logo Cache Models ====================
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CachedDimensions {
-    pub align: String,                  // left, center, right
-    pub max_width_dots: Option<u32>,
-    pub max_height_dots: Option<u32>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LogoMetadata {
-    pub file_size_bytes: usize,
-    pub original_width: u32,
-    pub original_height: u32,
-    pub mime_type: Option<String>,
-    pub usage_count: u32,
-    pub cached_dimensions: Option<CachedDimensions>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LogoCacheEntry {
-    pub id: String,                      // Named ID (user-provided or auto-generated)
-    pub content_hash: String,            // SHA256 of base64 (for dedup)
-    pub base64_data: String,             // Original base64 image
-    pub file_path: Option<String>,       // Path if persisted to disk
-    pub metadata: LogoMetadata,          // Stats and rendering prefs
-    pub created_at: String,              // ISO 8601 timestamp
-    pub last_used: Option<String>,       // ISO 8601 timestamp
-}
-
-// ==================== Printer Manager ====================
-
-#[derive(Debug, Clone)]
-#[allow(dead_code)]
-enum PrinterConnection {
-    USB(String),     // port name (COMx or \\.\usbxxx)
-    Network(String), // IP:port
-    LPT(String),     // LPTx
-    System(String),  // Windows Printer Name (e.g., "POS-80")
-    Console,
-}
-
-pub struct PrinterManager {
-    connection: Option<PrinterConnection>,
-    pub config: Option<PrinterConfig>,
-    pub template_cache: std::collections::HashMap<String, ReceiptTemplate>,
-    pub active_template_id: Option<String>,
-    pub logo_cache: std::collections::HashMap<String, LogoCacheEntry>,
-    pub logo_cache_path: String,
-}
-
-impl PrinterManager {
-    pub fn new() -> Self {
-        Self {
-            connection: None,
-            config: None,
-            template_cache: std::collections::HashMap::new(),
-            active_template_id: None,
-            logo_cache: std::collections::HashMap::new(),
-            logo_cache_path: directories::ProjectDirs::from("com", "nexora", "printer-manager")
-                .map(|d| d.data_local_dir().join("cache").join("logos"))
-                .unwrap_or_else(|| std::path::PathBuf::from("cache").join("logos"))
-                .to_string_lossy()
-                .into_owned(),
-        }
-    }
-
-    pub fn connect(&mut self, config: PrinterConfig) -> Result<(), String> {
-        log::info!(
-            "Connecting to {} printer at {}",
-            config.connection_type,
-            config.device_path
-        );
-
-        match config.connection_type.as_str() {
-            "USB" => {
-                // Check if this looks like a port or a printer name
-                if config.device_path.starts_with(r"\\.\") || config.device_path.starts_with("COM")
-                {
-                    // It's a port path
-                    #[cfg(target_os = "windows")]
-                    {
-                        // ... existing port opening logic ...
-                        let mut wide: Vec<u16> = config.device_path.encode_utf16().collect();
-                        wide.push(0);
-
-                        const GENERIC_WRITE: u32 = 0x40000000;
-                        use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
-                        use windows_sys::Win32::Storage::FileSystem::{
-                            CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE,
-                            OPEN_EXISTING,
-                        };
-
-                        let handle = unsafe {
-                            CreateFileW(
-                                wide.as_ptr(),
-                                GENERIC_WRITE,
-                                FILE_SHARE_READ | FILE_SHARE_WRITE,
-                                std::ptr::null(),
-                                OPEN_EXISTING,
-                                FILE_ATTRIBUTE_NORMAL,
-                                std::ptr::null_mut(),
-                            )
-                        };
-
-                        if handle == INVALID_HANDLE_VALUE {
-                            let _err = unsafe { windows_sys::Win32::Foundation::GetLastError() };
-                            // If port fails, try to see if it's actually a system printer name
-                            self.connection =
-                                Some(PrinterConnection::System(config.device_path.clone()));
-                        } else {
-                            unsafe { windows_sys::Win32::Foundation::CloseHandle(handle) };
-                            self.connection =
-                                Some(PrinterConnection::USB(config.device_path.clone()));
-                        }
-                    }
-                    #[cfg(not(target_os = "windows"))]
-                    {
-                        self.connection = Some(PrinterConnection::USB(config.device_path.clone()));
-                    }
-                } else {
-                    // It's likely a Windows printer name (e.g. "POS-80")
-                    self.connection = Some(PrinterConnection::System(config.device_path.clone()));
-                }
-            }
-            "Network" => {
-                // Validate IP:Port format
-                if !config.device_path.contains(':') {
-                    // Try to append default port if missing
-                    let mut path = config.device_path.clone();
-                    path.push_str(":9100");
-                    self.connection = Some(PrinterConnection::Network(path));
-                } else {
-                    self.connection = Some(PrinterConnection::Network(config.device_path.clone()));
-                }
-            }
-            "LPT" => {
-                #[cfg(target_os = "windows")]
-                {
-                    self.connection = Some(PrinterConnection::LPT(config.device_path.clone()));
-                }
-                #[cfg(not(target_os = "windows"))]
-                {
-                    return Err("LPT ports are only supported on Windows.".to_string());
-                }
-            }
-            "Console" => {
-                self.connection = Some(PrinterConnection::Console);
-            }
-            _ => {
-                return Err(format!(
-                    "Unsupported connection type: {}",
-                    config.connection_type
-                ))
-            }
-        };
-
-        self.config = Some(config);
-        log::info!("Printer connected successfully");
-        Ok(())
-    }
-
-    pub fn disconnect(&mut self) {
-        self.connection = None;
-        log::info!("Printer disconnected");
-    }
-
-    pub fn is_connected(&self) -> bool {
-        self.connection.is_some()
-    }
-
-    pub fn set_template(&mut self, template: ReceiptTemplate) -> Result<(), String> {
-        let id = template.id.clone();
-        self.template_cache.insert(id.clone(), template);
-        self.active_template_id = Some(id);
-        Ok(())
-    }
-
-    pub fn print_raw(&mut self, bytes: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        let connection = self.connection.as_ref().ok_or("Printer not connected")?;
-
-        match connection {
-            PrinterConnection::Console => {
-                println!("[Image data: {} bytes]", bytes.len());
-            }
-            PrinterConnection::USB(path) | PrinterConnection::LPT(path) => {
-                let path = path.clone();
-                #[cfg(target_os = "windows")]
-                self.write_to_device_windows(&path, bytes).map_err(|e| e)?;
-                #[cfg(not(target_os = "windows"))]
-                {
-                    use std::io::Write;
-                    let mut file = std::fs::File::create(&path)?;
-                    file.write_all(bytes)?;
-                }
-            }
-            PrinterConnection::Network(addr) => {
-                use std::io::Write;
-                let addr = addr.clone();
-                let mut stream = std::net::TcpStream::connect(&addr)?;
-                stream.write_all(bytes)?;
-            }
-            PrinterConnection::System(name) => {
-                let name = name.clone();
-                #[cfg(target_os = "windows")]
-                self.write_to_system_printer_windows(&name, bytes)
-                    .map_err(|e| e)?;
-                #[cfg(not(target_os = "windows"))]
-                return Err("System printer only supported on Windows".into());
-            }
-        }
-
-        Ok(())
-    }
-
-    pub fn print_with_template(&mut self, data: &ReceiptData) -> Result<(), String> {
-        let template_id = self
-            .active_template_id
-            .as_ref()
-            .ok_or("No active template set")?;
-        let mut template = self
-            .template_cache
-            .get(template_id)
-            .ok_or("Template not found in cache")?
-            .clone();
-
-        // Resolve any logo references using the logo cache
-        logo_cache::resolve_template_logos(self, &mut template)?;
-
-        let paper_width = template.paper_width.unwrap_or(48);
-        let renderer = TemplateRenderer::new(paper_width);
-        let commands = renderer.render_to_commands(&template, data)?;
-
-        self.execute_commands(commands)
-    }
-
-    fn execute_commands(&self, commands: Vec<template_render::PrintCommand>) -> Result<(), String> {
-        let connection = self.connection.as_ref().ok_or("Printer not connected")?;
-
-        // Convert commands to raw ESC/POS bytes
-        let mut bytes = Vec::new();
-        for cmd in commands {
-            match cmd {
-                template_render::PrintCommand::Init => bytes.extend_from_slice(&[0x1B, 0x40]),
-                template_render::PrintCommand::Write(s) => {
-                    bytes.extend_from_slice(s.as_bytes());
-                }
-                template_render::PrintCommand::WriteLine(s) => {
-                    bytes.extend_from_slice(s.as_bytes());
-                    bytes.push(b'\n');
-                }
-                template_render::PrintCommand::Feed(n) => {
-                    for _ in 0..n {
-                        bytes.push(b'\n');
-                    }
-                }
-                template_render::PrintCommand::Cut => {
-                    bytes.extend_from_slice(&[0x1D, 0x56, 0x01]);
-                }
-                template_render::PrintCommand::Bold(on) => {
-                    bytes.extend_from_slice(&[0x1B, 0x45, if on { 1 } else { 0 }]);
-                }
-                template_render::PrintCommand::Underline(on) => {
-                    bytes.extend_from_slice(&[0x1B, 0x2D, if on { 1 } else { 0 }]);
-                }
-                template_render::PrintCommand::Reverse(on) => {
-                    bytes.extend_from_slice(&[0x1D, 0x42, if on { 1 } else { 0 }]);
-                }
-                template_render::PrintCommand::Size(w, h) => {
-                    let size = ((w.saturating_sub(1) & 0x07) << 4) | (h.saturating_sub(1) & 0x07);
-                    bytes.extend_from_slice(&[0x1D, 0x21, size]);
-                }
-                template_render::PrintCommand::Align(align) => {
-                    let n = match align.to_lowercase().as_str() {
-                        "center" => 1,
-                        "right" => 2,
-                        _ => 0,
-                    };
-                    bytes.extend_from_slice(&[0x1B, 0x61, n]);
-                }
-                template_render::PrintCommand::QRCode { content, size: _ } => {
-                    // Simplified QR code (requires actual implementation for different printers)
-                    log::warn!("QR Code not fully implemented in raw bytes");
-                    bytes.extend_from_slice(format!("[QR: {}]", content).as_bytes());
-                    bytes.push(b'\n');
-                }
-                template_render::PrintCommand::Barcode { content, .. } => {
-                    log::warn!("Barcode not fully implemented in raw bytes");
-                    bytes.extend_from_slice(format!("[Barcode: {}]", content).as_bytes());
-                    bytes.push(b'\n');
-                }
-                template_render::PrintCommand::Image(img_bytes) => {
-                    bytes.extend_from_slice(&img_bytes);
-                }
-            }
-        }
-
-        match connection {
-            PrinterConnection::Console => {
-                if let Ok(s) = String::from_utf8(bytes) {
-                    println!("{}", s);
-                }
-                Ok(())
-            }
-            PrinterConnection::USB(path) | PrinterConnection::LPT(path) => {
-                #[cfg(target_os = "windows")]
-                {
-                    self.write_to_device_windows(path, &bytes)
-                }
-                #[cfg(not(target_os = "windows"))]
-                {
-                    use std::io::Write;
-                    let mut file = std::fs::File::create(path).map_err(|e| e.to_string())?;
-                    file.write_all(&bytes).map_err(|e| e.to_string())?;
-                    Ok(())
-                }
-            }
-            PrinterConnection::Network(addr) => {
-                use std::io::Write;
-                let mut stream = std::net::TcpStream::connect(addr).map_err(|e| e.to_string())?;
-                stream.write_all(&bytes).map_err(|e| e.to_string())?;
-                Ok(())
-            }
-            PrinterConnection::System(name) => {
-                #[cfg(target_os = "windows")]
-                {
-                    self.write_to_system_printer_windows(name, &bytes)
-                }
-                #[cfg(not(target_os = "windows"))]
-                {
-                    Err("System printer printing is only supported on Windows.".to_string())
-                }
-            }
-        }
-    }
-
-    #[cfg(target_os = "windows")]
-    fn write_to_system_printer_windows(&self, name: &str, data: &[u8]) -> Result<(), String> {
-        use windows_sys::Win32::Graphics::Printing::{
-            ClosePrinter, EndDocPrinter, EndPagePrinter, OpenPrinterW, StartDocPrinterW,
-            StartPagePrinter, WritePrinter, DOC_INFO_1W, PRINTER_HANDLE,
-        };
-
-        let mut wide_name: Vec<u16> = name.encode_utf16().collect();
-        wide_name.push(0);
-
-        let mut h_printer: PRINTER_HANDLE = unsafe { std::mem::zeroed() };
-        let success = unsafe {
-            OpenPrinterW(
-                wide_name.as_ptr() as *mut u16,
-                &mut h_printer,
-                std::ptr::null_mut(),
-            )
-        };
-
-        if success == 0 {
-            return Err(format!("Could not open system printer '{}'. Please check the name in Devices and Printers.", name));
-        }
-
-        let doc_name = "Nexora Receipt\0".encode_utf16().collect::<Vec<u16>>();
-        let data_type = "RAW\0".encode_utf16().collect::<Vec<u16>>();
-
-        let doc_info = DOC_INFO_1W {
-            pDocName: doc_name.as_ptr() as *mut u16,
-            pOutputFile: std::ptr::null_mut(),
-            pDatatype: data_type.as_ptr() as *mut u16,
-        };
-
-        let job_id = unsafe { StartDocPrinterW(h_printer, 1, &doc_info as *const DOC_INFO_1W) };
-
-        if job_id == 0 {
-            unsafe { ClosePrinter(h_printer) };
-            return Err("Could not start print job via Windows Spooler.".to_string());
-        }
-
-        unsafe {
-            StartPagePrinter(h_printer);
-            let mut written = 0;
-            WritePrinter(
-                h_printer,
-                data.as_ptr() as *const _,
-                data.len() as u32,
-                &mut written,
-            );
-            EndPagePrinter(h_printer);
-            EndDocPrinter(h_printer);
-            ClosePrinter(h_printer);
-        }
-
-        Ok(())
-    }
-
-    #[cfg(target_os = "windows")]
-    fn write_to_device_windows(&self, path: &str, data: &[u8]) -> Result<(), String> {
-        use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
-        use windows_sys::Win32::Storage::FileSystem::{
-            CreateFileW, WriteFile, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE,
-            OPEN_EXISTING,
-        };
-
-        // Standard generic access rights
-        const GENERIC_READ: u32 = 0x80000000;
-        const GENERIC_WRITE: u32 = 0x40000000;
-
-        let mut wide: Vec<u16> = path.encode_utf16().collect();
-        wide.push(0);
-
-        let mut handle = unsafe {
-            CreateFileW(
-                wide.as_ptr(),
-                GENERIC_READ | GENERIC_WRITE,
-                FILE_SHARE_READ | FILE_SHARE_WRITE,
-                std::ptr::null(),
-                OPEN_EXISTING,
-                FILE_ATTRIBUTE_NORMAL,
-                std::ptr::null_mut(),
-            )
-        };
-
-        if handle == INVALID_HANDLE_VALUE {
-            // Try just WRITE if BOTH fails
-            handle = unsafe {
-                CreateFileW(
-                    wide.as_ptr(),
-                    GENERIC_WRITE,
-                    FILE_SHARE_READ | FILE_SHARE_WRITE,
-                    std::ptr::null(),
-                    OPEN_EXISTING,
-                    FILE_ATTRIBUTE_NORMAL,
-                    std::ptr::null_mut(),
-                )
-            };
-        }
-
-        if handle == INVALID_HANDLE_VALUE {
-            let err = unsafe { windows_sys::Win32::Foundation::GetLastError() };
-            return Err(format!("Cannot open {}: Windows error code {}", path, err));
-        }
-
-        let mut written: u32 = 0;
-        let success = unsafe {
-            WriteFile(
-                handle,
-                data.as_ptr(),
-                data.len() as u32,
-                &mut written,
-                std::ptr::null_mut(),
-            )
-        };
-
-        unsafe { windows_sys::Win32::Foundation::CloseHandle(handle) };
-
-        if success == 0 {
-            let err = unsafe { windows_sys::Win32::Foundation::GetLastError() };
-            return Err(format!(
-                "Failed to write to {}: Windows error code {}",
-                path, err
-            ));
-        }
-
-        Ok(())
-    }
-
-    pub fn print_test(&mut self) -> Result<(), String> {
-        let config = self.config.as_ref().ok_or("No configuration found")?;
-
-        let commands = vec![
-            template_render::PrintCommand::Init,
-            template_render::PrintCommand::Align("center".to_string()),
-            template_render::PrintCommand::Size(2, 2),
-            template_render::PrintCommand::Bold(true),
-            template_render::PrintCommand::WriteLine("NEXORA POS".to_string()),
-            template_render::PrintCommand::Size(1, 1),
-            template_render::PrintCommand::Bold(false),
-            template_render::PrintCommand::WriteLine("Test Print".to_string()),
-            template_render::PrintCommand::Feed(1),
-            template_render::PrintCommand::Align("left".to_string()),
-            template_render::PrintCommand::WriteLine(
-                "================================".to_string(),
-            ),
-            template_render::PrintCommand::WriteLine(format!(
-                "Connection: {}",
-                config.connection_type
-            )),
-            template_render::PrintCommand::WriteLine(format!("Device: {}", config.device_path)),
-            template_render::PrintCommand::WriteLine(format!("Store: {}", config.store_name)),
-            template_render::PrintCommand::WriteLine(
-                "================================".to_string(),
-            ),
-            template_render::PrintCommand::Feed(1),
-            template_render::PrintCommand::WriteLine("[OK] Connection Successful".to_string()),
-            template_render::PrintCommand::WriteLine(format!(
-                "Date: {}",
-                chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
-            )),
-            template_render::PrintCommand::Feed(1),
-            template_render::PrintCommand::WriteLine("Testing text output:".to_string()),
-            template_render::PrintCommand::WriteLine("Regular Text".to_string()),
-            template_render::PrintCommand::Bold(true),
-            template_render::PrintCommand::WriteLine("Bold Text".to_string()),
-            template_render::PrintCommand::Bold(false),
-            template_render::PrintCommand::Reverse(true),
-            template_render::PrintCommand::WriteLine("Inverted Text".to_string()),
-            template_render::PrintCommand::Reverse(false),
-            template_render::PrintCommand::Feed(1),
-            template_render::PrintCommand::WriteLine("ESC/POS Compatible [OK]".to_string()),
-            template_render::PrintCommand::Feed(3),
-            template_render::PrintCommand::Feed(1),
-            template_render::PrintCommand::Feed(1),
-            template_render::PrintCommand::Cut,
-        ];
-
-        self.execute_commands(commands)
-    }
-
-    pub fn print_receipt(&mut self, receipt: &Receipt) -> Result<(), String> {
-        // Convert legacy Receipt to ReceiptData and use print_with_template if possible
-        // Or just build commands manually for legacy support
-        let data = ReceiptData {
-            store_name: Some(
-                self.config
-                    .as_ref()
-                    .map(|c| c.store_name.clone())
-                    .unwrap_or_default(),
-            ),
-            store_address: Some(
-                self.config
-                    .as_ref()
-                    .map(|c| c.store_address.clone())
-                    .unwrap_or_default(),
-            ),
-            order_id: receipt.order_id.clone(),
-            timestamp: receipt.timestamp.clone(),
-            items: receipt
-                .items
-                .iter()
-                .map(|item| ReceiptItem {
-                    name: item.name.clone(),
-                    quantity: item.quantity,
-                    price: item.price,
-                    total: item.quantity as f64 * item.price,
-                    modifiers: None,
-                })
-                .collect(),
-            subtotal: receipt.subtotal,
-            tax: receipt.tax,
-            total: receipt.total,
-            payment_method: receipt.payment_method.clone(),
-            footer_message: Some(
-                self.config
-                    .as_ref()
-                    .map(|c| c.footer_message.clone())
-                    .unwrap_or_default(),
-            ),
-            ..Default::default()
-        };
-
-        // If we have an active template, use it. Otherwise, build a simple receipt.
-        if self.active_template_id.is_some() {
-            return self.print_with_template(&data);
-        }
-
-        let mut commands = vec![
-            template_render::PrintCommand::Init,
-            template_render::PrintCommand::Align("center".to_string()),
-            template_render::PrintCommand::Bold(true),
-            template_render::PrintCommand::WriteLine(data.store_name.unwrap_or_default()),
-            template_render::PrintCommand::Bold(false),
-            template_render::PrintCommand::WriteLine(data.store_address.unwrap_or_default()),
-            template_render::PrintCommand::Feed(1),
-            template_render::PrintCommand::Align("left".to_string()),
-            template_render::PrintCommand::WriteLine(format!("Order #{}", data.order_id)),
-            template_render::PrintCommand::WriteLine(data.timestamp),
-            template_render::PrintCommand::WriteLine("-".repeat(32)),
-        ];
-
-        for item in &data.items {
-            commands.push(template_render::PrintCommand::WriteLine(item.name.clone()));
-            let qty_price = format!("{}x ${:.2}", item.quantity, item.price);
-            let total = format!("${:.2}", item.total);
-            let spaces = 32_usize.saturating_sub(qty_price.len() + total.len());
-            commands.push(template_render::PrintCommand::WriteLine(format!(
-                "{}{}{}",
-                qty_price,
-                " ".repeat(spaces),
-                total
-            )));
-        }
-
-        commands.extend_from_slice(&[
-            template_render::PrintCommand::WriteLine("-".repeat(32)),
-            template_render::PrintCommand::WriteLine(format!(
-                "Subtotal:                ${:.2}",
-                data.subtotal
-            )),
-            template_render::PrintCommand::WriteLine(format!(
-                "Tax:                     ${:.2}",
-                data.tax
-            )),
-            template_render::PrintCommand::Bold(true),
-            template_render::PrintCommand::WriteLine(format!(
-                "TOTAL:                   ${:.2}",
-                data.total
-            )),
-            template_render::PrintCommand::Bold(false),
-            template_render::PrintCommand::WriteLine("-".repeat(32)),
-            template_render::PrintCommand::WriteLine(format!("Payment: {}", data.payment_method)),
-            template_render::PrintCommand::Feed(1),
-            template_render::PrintCommand::Align("center".to_string()),
-            template_render::PrintCommand::WriteLine(data.footer_message.unwrap_or_default()),
-            template_render::PrintCommand::WriteLine("Powered by Nexora POS".to_string()),
-            template_render::PrintCommand::Feed(3),
-            template_render::PrintCommand::Cut,
-        ]);
-
-        self.execute_commands(commands)
-    }
-}
-
-// ==================== Barcode Printer Manager ====================
-
-pub struct BarcodePrinterManager {
-    pub connection: Option<PrinterConnection>,
-    pub config: Option<BarcodePrinterConfig>,
-}
-
-impl BarcodePrinterManager {
-    pub fn new() -> Self {
-        Self {
-            connection: None,
-            config: None,
-        }
-    }
-
-    pub fn connect(&mut self, config: BarcodePrinterConfig) -> Result<(), String> {
-        log::info!(
-            "Connecting to barcode printer via {} at {}",
-            config.connection_type,
-            config.device_path
-        );
-
-        match config.connection_type.as_str() {
-            "USB" => {
-                if config.device_path.starts_with(r"\\.\") || config.device_path.starts_with("COM") {
-                    #[cfg(target_os = "windows")]
-                    {
-                        let mut wide: Vec<u16> = config.device_path.encode_utf16().collect();
-                        wide.push(0);
-                        const GENERIC_WRITE: u32 = 0x40000000;
-                        use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
-                        use windows_sys::Win32::Storage::FileSystem::{
-                            CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE,
-                            OPEN_EXISTING,
-                        };
-                        let handle = unsafe {
-                            CreateFileW(
-                                wide.as_ptr(),
-                                GENERIC_WRITE,
-                                FILE_SHARE_READ | FILE_SHARE_WRITE,
-                                std::ptr::null(),
-                                OPEN_EXISTING,
-                                FILE_ATTRIBUTE_NORMAL,
-                                std::ptr::null_mut(),
-                            )
-                        };
-                        if handle == INVALID_HANDLE_VALUE {
-                            self.connection = Some(PrinterConnection::System(config.device_path.clone()));
-                        } else {
-                            unsafe { windows_sys::Win32::Foundation::CloseHandle(handle) };
-                            self.connection = Some(PrinterConnection::USB(config.device_path.clone()));
-                        }
-                    }
-                    #[cfg(not(target_os = "windows"))]
-                    {
-                        self.connection = Some(PrinterConnection::USB(config.device_path.clone()));
-                    }
-                } else {
-                    self.connection = Some(PrinterConnection::System(config.device_path.clone()));
-                }
-            }
-            "Network" => {
-                if !config.device_path.contains(':') {
-                    let mut path = config.device_path.clone();
-                    path.push_str(":9100");
-                    self.connection = Some(PrinterConnection::Network(path));
-                } else {
-                    self.connection = Some(PrinterConnection::Network(config.device_path.clone()));
-                }
-            }
-            "LPT" => {
-                #[cfg(target_os = "windows")]
-                {
-                    self.connection = Some(PrinterConnection::LPT(config.device_path.clone()));
-                }
-                #[cfg(not(target_os = "windows"))]
-                {
-                    return Err("LPT ports are only supported on Windows.".to_string());
-                }
-            }
-            _ => {
-                return Err(format!("Unsupported connection type: {}", config.connection_type))
-            }
-        };
-
-        self.config = Some(config);
-        log::info!("Barcode printer connected successfully");
-        Ok(())
-    }
-
-    pub fn disconnect(&mut self) {
-        self.connection = None;
-        log::info!("Barcode printer disconnected");
-    }
-
-    pub fn is_connected(&self) -> bool {
-        self.connection.is_some()
-    }
-
-    pub fn print_label(&mut self, req: &BarcodeLabelRequest) -> Result<(), String> {
-        let config = self.config.as_ref().ok_or("Barcode printer not configured")?;
-        let bytes = barcode_printer::build_label(config, req);
-        self.print_raw(&bytes).map_err(|e| e.to_string())
-    }
-
-    pub fn print_test_label(&mut self) -> Result<(), String> {
-        let config = self.config.as_ref().ok_or("Barcode printer not configured")?.clone();
-        let bytes = barcode_printer::build_test_label(&config);
-        self.print_raw(&bytes).map_err(|e| e.to_string())
-    }
-
-    pub fn print_raw(&mut self, bytes: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        let connection = self.connection.as_ref().ok_or("Barcode printer not connected")?;
-        match connection {
-            PrinterConnection::Console => {
-                println!("[Barcode label data: {} bytes]", bytes.len());
-            }
-            PrinterConnection::USB(path) | PrinterConnection::LPT(path) => {
-                let path = path.clone();
-                #[cfg(target_os = "windows")]
-                self.write_to_device_windows(&path, bytes)?;
-                #[cfg(not(target_os = "windows"))]
-                {
-                    use std::io::Write;
-                    let mut file = std::fs::File::create(&path)?;
-                    file.write_all(bytes)?;
-                }
-            }
-            PrinterConnection::Network(addr) => {
-                use std::io::Write;
-                let addr = addr.clone();
-                let mut stream = std::net::TcpStream::connect(&addr)?;
-                stream.write_all(bytes)?;
-            }
-            PrinterConnection::System(name) => {
-                let name = name.clone();
-                #[cfg(target_os = "windows")]
-                self.write_to_system_printer_windows(&name, bytes)?;
-                #[cfg(not(target_os = "windows"))]
-                return Err("System printer only supported on Windows".into());
-            }
-        }
-        Ok(())
-    }
-
-    #[cfg(target_os = "windows")]
-    fn write_to_device_windows(&self, path: &str, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
-        use windows_sys::Win32::Storage::FileSystem::{
-            CreateFileW, WriteFile, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE,
-            OPEN_EXISTING,
-        };
-        const GENERIC_WRITE: u32 = 0x40000000;
-        let mut wide: Vec<u16> = path.encode_utf16().collect();
-        wide.push(0);
-        let handle = unsafe {
-            CreateFileW(
-                wide.as_ptr(),
-                GENERIC_WRITE,
-                FILE_SHARE_READ | FILE_SHARE_WRITE,
-                std::ptr::null(),
-                OPEN_EXISTING,
-                FILE_ATTRIBUTE_NORMAL,
-                std::ptr::null_mut(),
-            )
-        };
-        if handle == INVALID_HANDLE_VALUE {
-            let err = unsafe { windows_sys::Win32::Foundation::GetLastError() };
-            return Err(format!("Cannot open {}: Windows error {}", path, err).into());
-        }
-        let mut written: u32 = 0;
-        let success = unsafe {
-            WriteFile(
-                handle,
-                data.as_ptr(),
-                data.len() as u32,
-                &mut written,
-                std::ptr::null_mut(),
-            )
-        };
-        unsafe { windows_sys::Win32::Foundation::CloseHandle(handle) };
-        if success == 0 {
-            let err = unsafe { windows_sys::Win32::Foundation::GetLastError() };
-            return Err(format!("Write failed on {}: Windows error {}", path, err).into());
-        }
-        Ok(())
-    }
-
-    #[cfg(target_os = "windows")]
-    fn write_to_system_printer_windows(&self, name: &str, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        use windows_sys::Win32::Graphics::Printing::{
-            ClosePrinter, EndDocPrinter, EndPagePrinter, OpenPrinterW, StartDocPrinterW,
-            StartPagePrinter, WritePrinter, DOC_INFO_1W, PRINTER_HANDLE,
-        };
-        let mut wide_name: Vec<u16> = name.encode_utf16().collect();
-        wide_name.push(0);
-        let mut h_printer: PRINTER_HANDLE = unsafe { std::mem::zeroed() };
-        let success = unsafe {
-            OpenPrinterW(wide_name.as_ptr() as *mut u16, &mut h_printer, std::ptr::null_mut())
-        };
-        if success == 0 {
-            return Err(format!("Could not open barcode printer '{}'", name).into());
-        }
-        let doc_name = "Nexora Barcode\0".encode_utf16().collect::<Vec<u16>>();
-        let data_type = "RAW\0".encode_utf16().collect::<Vec<u16>>();
-        let doc_info = DOC_INFO_1W {
-            pDocName: doc_name.as_ptr() as *mut u16,
-            pOutputFile: std::ptr::null_mut(),
-            pDatatype: data_type.as_ptr() as *mut u16,
-        };
-        let job_id = unsafe { StartDocPrinterW(h_printer, 1, &doc_info as *const DOC_INFO_1W) };
-        if job_id == 0 {
-            unsafe { ClosePrinter(h_printer) };
-            return Err("Could not start barcode print job".into());
-        }
-        unsafe {
-            StartPagePrinter(h_printer);
-            let mut written = 0;
-            WritePrinter(h_printer, data.as_ptr() as *const _, data.len() as u32, &mut written);
-            EndPagePrinter(h_printer);
-            EndDocPrinter(h_printer);
-            ClosePrinter(h_printer);
-        }
-        Ok(())
-    }
-}
-
 // ==================== Device Detection ====================
 
 fn scan_available_devices() -> Vec<Device> {
@@ -931,25 +63,40 @@ fn scan_available_devices() -> Vec<Device> {
         }
     }
 
+    // Printer-class USB devices that don't show up as a serial port
+    match usb_printers() {
+        Ok(printers) => {
+            for printer in printers {
+                devices.push(Device {
+                    description: format!("{} ({})", printer.name, printer.path).into(),
+                    path: printer.path.into(),
+                    r#type: "USB".into(),
+                });
+            }
+        }
+        Err(e) => {
+            log::warn!("Failed to scan USB printers: {}", e);
+        }
+    }
+
     #[cfg(target_os = "windows")]
     {
         use winreg::enums::*;
         use winreg::RegKey;
 
-        // 1. Find all installed printers from Registry (Most reliable for usb00X)
-        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-        if let Ok(printers_key) =
-            hkcu.open_subkey("Software\\Microsoft\\Windows NT\\CurrentVersion\\Devices")
-        {
-            for (name, value) in printers_key.enum_values().flatten() {
-                let value_str = value.to_string();
-                let port = value_str.split(',').nth(1).unwrap_or("").trim();
-
-                devices.push(Device {
-                    path: name.clone().into(),
-                    description: format!("Printer on port: {}", port).into(),
-                    r#type: "USB".into(),
-                });
+        // 1. Installed printers, printed to through the spooler
+        match installed_printers() {
+            Ok(printers) => {
+                for printer in printers {
+                    devices.push(Device {
+                        path: printer.name.into(),
+                        description: format!("Printer on port: {}", printer.port).into(),
+                        r#type: "Windows Printer".into(),
+                    });
+                }
+            }
+            Err(e) => {
+                log::warn!("Failed to list installed printers: {}", e);
             }
         }
 
@@ -1028,66 +175,93 @@ fn scan_available_devices() -> Vec<Device> {
     devices
 }
 
-// ==================== Configuration Storage ====================
-
-fn get_config_path() -> Result<std::path::PathBuf, String> {
-    let config_dir = directories::ProjectDirs::from("com", "nexora", "printer-manager")
-        .ok_or("Failed to determine config directory")?;
-
-    std::fs::create_dir_all(config_dir.config_dir())
-        .map_err(|e| format!("Failed to create config directory: {}", e))?;
-
-    Ok(config_dir.config_dir().join("config.json"))
+// ==================== Serial Settings ====================
+
+/// Serial settings chosen in a panel; anything unparsable keeps its default
+fn serial_settings(
+    baud_rate: &str,
+    data_bits: &str,
+    parity: &str,
+    stop_bits: &str,
+    flow_control: &str,
+) -> SerialSettings {
+    let defaults = SerialSettings::default();
+    SerialSettings {
+        baud_rate: baud_rate.parse().unwrap_or(defaults.baud_rate),
+        data_bits: data_bits.parse().unwrap_or(defaults.data_bits),
+        parity: parity.to_string(),
+        stop_bits: stop_bits.parse().unwrap_or(defaults.stop_bits),
+        flow_control: flow_control.to_string(),
+    }
 }
 
-fn save_config(config: &PrinterConfig) -> Result<(), String> {
-    let path = get_config_path()?;
-    let json = serde_json::to_string_pretty(config)
-        .map_err(|e| format!("Failed to serialize config: {}", e))?;
-
-    std::fs::write(path, json).map_err(|e| format!("Failed to write config: {}", e))?;
-
-    log::info!("Configuration saved");
-    Ok(())
+fn receipt_serial_settings(ui: &MainWindow) -> SerialSettings {
+    serial_settings(
+        &ui.get_serial_baud_rate(),
+        &ui.get_serial_data_bits(),
+        &ui.get_serial_parity(),
+        &ui.get_serial_stop_bits(),
+        &ui.get_serial_flow_control(),
+    )
 }
 
-fn load_config() -> Result<Option<PrinterConfig>, String> {
-    let path = get_config_path()?;
+fn show_receipt_serial_settings(ui: &MainWindow, serial: &SerialSettings) {
+    ui.set_serial_baud_rate(serial.baud_rate.to_string().into());
+    ui.set_serial_data_bits(serial.data_bits.to_string().into());
+    ui.set_serial_parity(serial.parity.clone().into());
+    ui.set_serial_stop_bits(serial.stop_bits.to_string().into());
+    ui.set_serial_flow_control(serial.flow_control.clone().into());
+}
 
-    if !path.exists() {
-        return Ok(None);
-    }
+fn barcode_serial_settings(ui: &MainWindow) -> SerialSettings {
+    serial_settings(
+        &ui.get_barcode_serial_baud_rate(),
+        &ui.get_barcode_serial_data_bits(),
+        &ui.get_barcode_serial_parity(),
+        &ui.get_barcode_serial_stop_bits(),
+        &ui.get_barcode_serial_flow_control(),
+    )
+}
 
-    let json =
-        std::fs::read_to_string(path).map_err(|e| format!("Failed to read config: {}", e))?;
+fn show_barcode_serial_settings(ui: &MainWindow, serial: &SerialSettings) {
+    ui.set_barcode_serial_baud_rate(serial.baud_rate.to_string().into());
+    ui.set_barcode_serial_data_bits(serial.data_bits.to_string().into());
+    ui.set_barcode_serial_parity(serial.parity.clone().into());
+    ui.set_barcode_serial_stop_bits(serial.stop_bits.to_string().into());
+    ui.set_barcode_serial_flow_control(serial.flow_control.clone().into());
+}
 
-    let config: PrinterConfig =
-        serde_json::from_str(&json).map_err(|e| format!("Failed to parse config: {}", e))?;
+// ==================== Print Settings ====================
 
-    log::info!("Configuration loaded");
-    Ok(Some(config))
+/// Density chosen in the receipt panel, e.g. "+2"; "default" is unset
+fn print_density(ui: &MainWindow) -> Option<i8> {
+    ui.get_print_density().trim_start_matches('+').parse().ok()
 }
 
-fn save_barcode_config(config: &BarcodePrinterConfig) -> Result<(), String> {
-    let path = get_config_path()?.with_file_name("barcode_config.json");
-    let json = serde_json::to_string_pretty(config)
-        .map_err(|e| format!("Failed to serialize barcode config: {}", e))?;
-    std::fs::write(path, json).map_err(|e| format!("Failed to write barcode config: {}", e))?;
-    log::info!("Barcode configuration saved");
-    Ok(())
+/// Speed chosen in the receipt panel; "default" is unset
+fn print_speed(ui: &MainWindow) -> Option<PrintSpeed> {
+    match ui.get_print_speed().as_str() {
+        "slow" => Some(PrintSpeed::Slow),
+        "medium" => Some(PrintSpeed::Medium),
+        "fast" => Some(PrintSpeed::Fast),
+        _ => None,
+    }
 }
 
-fn load_barcode_config() -> Result<Option<BarcodePrinterConfig>, String> {
-    let path = get_config_path()?.with_file_name("barcode_config.json");
-    if !path.exists() {
-        return Ok(None);
-    }
-    let json = std::fs::read_to_string(path)
-        .map_err(|e| format!("Failed to read barcode config: {}", e))?;
-    let config: BarcodePrinterConfig = serde_json::from_str(&json)
-        .map_err(|e| format!("Failed to parse barcode config: {}", e))?;
-    log::info!("Barcode configuration loaded");
-    Ok(Some(config))
+fn show_print_settings(ui: &MainWindow, config: &PrinterConfig) {
+    let density = match config.density {
+        Some(density) if density > 0 => format!("+{}", density),
+        Some(density) => density.to_string(),
+        None => "default".to_string(),
+    };
+    let speed = match config.print_speed {
+        Some(PrintSpeed::Slow) => "slow",
+        Some(PrintSpeed::Medium) => "medium",
+        Some(PrintSpeed::Fast) => "fast",
+        None => "default",
+    };
+    ui.set_print_density(density.into());
+    ui.set_print_speed(speed.into());
 }
 
 // ==================== Main Application ====================
@@ -1146,12 +320,65 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // Load logos from disk cache
     {
-        let mut manager = printer_manager.lock().unwrap();
+        let mut manager = printer_manager.lock_or_recover();
         if let Err(e) = logo_cache::load_logos_from_disk(&mut manager) {
             log::warn!("Failed to load logo cache: {}", e);
         }
+        match load_template_cache_limits() {
+            Ok(Some(limits)) => {
+                manager.template_cache.set_limits(limits, None);
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to load template cache limits: {}", e),
+        }
+        if env::args().any(|arg| arg == "--no-optimize") {
+            log::info!("Command optimization disabled");
+            manager.optimize_commands = false;
+        }
+        match load_template_validation() {
+            Ok(Some(validation)) => manager.template_validation = validation,
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to load template validation settings: {}", e),
+        }
+        match load_routing_config() {
+            Ok(Some(routing)) => manager.routing_rules = routing.rules,
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to load routing rules: {}", e),
+        }
     }
 
+    // Template files edited on disk are cached and reloaded as they're saved
+    let _template_dir = match get_templates_dir() {
+        Ok(dir) => {
+            let manager = Arc::clone(&printer_manager);
+            match TemplateDirWatcher::spawn(TemplateDir::new(dir), manager) {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    log::warn!("{}; template files are not reloaded", e);
+                    None
+                }
+            }
+        }
+        Err(e) => {
+            log::warn!("No template folder: {}", e);
+            None
+        }
+    };
+
+    // Jobs still queued at exit are saved here and printed on the next start
+    let spool = Spool::new(
+        Spool::default_dir().unwrap_or_else(|_| std::path::PathBuf::from("spool")),
+    );
+    let print_worker = PrintWorker::spawn_with_spool(spool.clone());
+    let shutdown_settings = match load_shutdown_settings() {
+        Ok(settings) => settings.unwrap_or_default(),
+        Err(e) => {
+            log::warn!("Failed to load shutdown settings: {}", e);
+            ShutdownSettings::default()
+        }
+    };
+    let server_stop = Arc::new(tokio::sync::Notify::new());
+
     // Keep the tray icon alive
     let mut _tray_icon_handle = None;
 
@@ -1202,11 +429,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         // Start HTTP server
         let printer_manager_clone = Arc::clone(&printer_manager);
         let barcode_manager_clone = Arc::clone(&barcode_manager);
+        let print_worker_clone = print_worker.clone();
+        let server_stop_clone = Arc::clone(&server_stop);
         tokio::spawn(async move {
-            if let Err(e) = http_server::start_server(printer_manager_clone, barcode_manager_clone, 8080).await {
+            let stopped = async move { server_stop_clone.notified().await };
+            if let Err(e) = http_server::start_server(
+                printer_manager_clone,
+                barcode_manager_clone,
+                print_worker_clone,
+                8080,
+                stopped,
+            )
+            .await
+            {
                 log::error!("HTTP server error: {}", e);
             } else {
-                log::info!("HTTP server started on port 8080");
+                log::info!("HTTP server stopped");
             }
         });
 
@@ -1289,12 +527,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         if let Ok(Some(config)) = load_config() {
             ui.set_selected_connection_type(config.connection_type.clone().into());
             ui.set_selected_device(config.device_path.clone().into());
+            show_receipt_serial_settings(&ui, &config.serial);
+            show_print_settings(&ui, &config);
             ui.set_status_message("Configuration loaded, attempting auto-connect...".into());
             log::info!("Loaded saved configuration: {} at {}", config.connection_type, config.device_path);
             
             // Attempt auto-connect with saved configuration
             {
-                let mut manager = printer_manager.lock().unwrap();
+                let mut manager = printer_manager.lock_or_recover();
                 match manager.connect(config.clone()) {
                     Ok(_) => {
                         ui.set_is_connected(true);
@@ -1312,9 +552,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             log::debug!("No saved configuration found at startup");
         }
 
+        // Print jobs left over from the last run on the printers now connected
+        if !spool.pending().is_empty() {
+            let spool = spool.clone();
+            let manager = Arc::clone(&printer_manager);
+            std::thread::spawn(move || match spool.replay(&manager) {
+                Ok(printed) => log::info!("Printed {} spooled job(s) from the last run", printed),
+                Err(e) => log::warn!("Failed to print spooled jobs: {}", e),
+            });
+        }
+
         // Load barcode printer config and auto-connect
         if let Ok(Some(bc_config)) = load_barcode_config() {
-            let mut bc_manager = barcode_manager.lock().unwrap();
+            show_barcode_serial_settings(&ui, &bc_config.serial);
+            let mut bc_manager = barcode_manager.lock_or_recover();
             match bc_manager.connect(bc_config) {
                 Ok(_) => {
                     ui.set_barcode_is_connected(true);
@@ -1356,6 +607,73 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             });
         }
 
+        // Network scan callback: probe the local /24 for port 9100
+        {
+            let ui_handle = ui.as_weak();
+            ui.on_scan_network(move || {
+                let ui = ui_handle.unwrap();
+                let local_ip = match local_ip_address::local_ip() {
+                    Ok(std::net::IpAddr::V4(ip)) => ip,
+                    _ => {
+                        ui.set_status_message("No local IPv4 network to scan".into());
+                        return;
+                    }
+                };
+                let [a, b, c, _] = local_ip.octets();
+                ui.set_is_loading(true);
+                ui.set_status_message(
+                    format!("Scanning {}.{}.{}.0/24 for printers...", a, b, c).into(),
+                );
+
+                let ui_handle = ui.as_weak();
+                tokio::spawn(async move {
+                    let found = discovery::scan_subnet(local_ip, &ScanOptions::default()).await;
+                    log::info!("Network scan found {} printer(s)", found.len());
+                    let _ = slint::invoke_from_event_loop(move || {
+                        let Some(ui) = ui_handle.upgrade() else {
+                            return;
+                        };
+                        // Found printers replace the suggested addresses
+                        let mut devices = scan_available_devices();
+                        if !found.is_empty() {
+                            devices.retain(|device| device.r#type != "Network");
+                        }
+                        for printer in &found {
+                            let mut description = match &printer.details {
+                                Some(PrinterState { model: Some(model), .. }) => model.clone(),
+                                _ if printer.escpos == Some(true) => "ESC/POS printer".to_string(),
+                                _ => "Open port 9100".to_string(),
+                            };
+                            if printer.offline == Some(true) {
+                                description.push_str(" (offline)");
+                            }
+                            if let Some(details) = &printer.details {
+                                if let Some(serial) = &details.serial_number {
+                                    description.push_str(&format!(", S/N {}", serial));
+                                }
+                                match details.paper.as_deref() {
+                                    Some("low") => description.push_str(", paper low"),
+                                    Some("empty") => description.push_str(", out of paper"),
+                                    _ => {}
+                                }
+                            }
+                            devices.push(Device {
+                                path: printer.addr.clone().into(),
+                                description: description.into(),
+                                r#type: "Network".into(),
+                            });
+                        }
+                        let model_array = std::rc::Rc::new(slint::VecModel::from(devices));
+                        ui.set_available_devices(model_array.into());
+                        ui.set_is_loading(false);
+                        ui.set_status_message(
+                            format!("Found {} network printer(s)", found.len()).into(),
+                        );
+                    });
+                });
+            });
+        }
+
         // Connect printer callback
         {
             let ui_handle = ui.as_weak();
@@ -1368,6 +686,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                 // Load current config to keep store name, etc. if they exist
                 let current_config = load_config().ok().flatten();
+                // Protocol and profile belong to the printer connected before
+                let same_printer = current_config
+                    .as_ref()
+                    .filter(|c| c.device_path == device.as_str());
 
                 let config = PrinterConfig {
                     connection_type: conn_type.to_string(),
@@ -1384,9 +706,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .as_ref()
                         .map(|c| c.footer_message.clone())
                         .unwrap_or_else(|| "Thank you for your visit!".to_string()),
+                    serial: receipt_serial_settings(&ui),
+                    timeouts: current_config.as_ref().map(|c| c.timeouts).unwrap_or_default(),
+                    protocol: same_printer.and_then(|c| c.protocol),
+                    profile: same_printer.and_then(|c| c.profile.clone()),
+                    code_pages: same_printer
+                        .map(|c| c.code_pages.clone())
+                        .unwrap_or_default(),
+                    cjk_encoding: same_printer.and_then(|c| c.cjk_encoding),
+                    raster_text: same_printer.is_some_and(|c| c.raster_text),
+                    upside_down: same_printer.is_some_and(|c| c.upside_down),
+                    density: print_density(&ui),
+                    print_speed: print_speed(&ui),
+                    print_area: same_printer.and_then(|c| c.print_area),
+                    write_pacing: same_printer.and_then(|c| c.write_pacing),
+                    roles: current_config.map(|c| c.roles).unwrap_or_default(),
                 };
 
-                let mut manager = manager.lock().unwrap();
+                let mut manager = manager.lock_or_recover();
 
                 if let Err(e) = manager.connect(config.clone()) {
                     ui.set_is_connected(false);
@@ -1394,7 +731,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     log::error!("Connection failed: {}", e);
                 } else {
                     ui.set_is_connected(true);
-                    ui.set_status_message("✓ Printer connected successfully!".into());
+                    let message = match manager.profile(DEFAULT_PRINTER) {
+                        Some(profile) => format!("✓ Connected to {}", profile.name),
+                        None => "✓ Printer connected successfully!".to_string(),
+                    };
+                    ui.set_status_message(message.into());
 
                     // Save configuration
                     if let Err(e) = save_config(&config) {
@@ -1413,7 +754,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             ui.on_disconnect_printer(move || {
                 let ui = ui_handle.unwrap();
-                let mut manager = manager.lock().unwrap();
+                let mut manager = manager.lock_or_recover();
                 manager.disconnect();
                 ui.set_is_connected(false);
                 ui.set_status_message("Printer disconnected".into());
@@ -1430,9 +771,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 ui.set_is_loading(true);
                 ui.set_status_message("Printing test page...".into());
 
-                let mut manager = manager.lock().unwrap();
+                // Release the lock before writing so status queries aren't blocked
+                let output = manager.lock_or_recover().prepare_test();
 
-                if let Err(e) = manager.print_test() {
+                if let Err(e) = output.and_then(|output| output.send()) {
                     ui.set_status_message(format!("✗ Print failed: {}", e).into());
                     log::error!("Test print failed: {}", e);
                 } else {
@@ -1443,6 +785,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             });
         }
 
+        // Store NV logo callback
+        {
+            let ui_handle = ui.as_weak();
+            let manager = Arc::clone(&printer_manager);
+
+            ui.on_store_nv_logo(move |source| {
+                let ui = ui_handle.unwrap();
+                ui.set_is_loading(true);
+                ui.set_status_message("Storing logo in the printer...".into());
+
+                let output = manager.lock_or_recover().prepare_nv_logo(
+                    DEFAULT_PRINTER,
+                    1,
+                    source.trim(),
+                    None,
+                    None,
+                    &Default::default(),
+                );
+
+                if let Err(e) = output.and_then(|output| output.send()) {
+                    ui.set_status_message(format!("✗ Storing logo failed: {}", e).into());
+                    log::error!("Storing NV logo failed: {}", e);
+                } else {
+                    ui.set_status_message("✓ Logo stored in slot 1".into());
+                }
+
+                ui.set_is_loading(false);
+            });
+        }
+
         // Save settings callback
         {
             let ui_handle = ui.as_weak();
@@ -1452,10 +824,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                 // Load current config to keep store name, etc. if they exist
                 let current_config = load_config().ok().flatten();
+                let device = ui.get_selected_device();
+                // Protocol and profile belong to the printer saved before
+                let same_printer = current_config
+                    .as_ref()
+                    .filter(|c| c.device_path == device.as_str());
 
                 let config = PrinterConfig {
                     connection_type: ui.get_selected_connection_type().to_string(),
-                    device_path: ui.get_selected_device().to_string(),
+                    device_path: device.to_string(),
                     store_name: current_config
                         .as_ref()
                         .map(|c| c.store_name.clone())
@@ -1468,6 +845,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .as_ref()
                         .map(|c| c.footer_message.clone())
                         .unwrap_or_else(|| "Thank you for your visit!".to_string()),
+                    serial: receipt_serial_settings(&ui),
+                    timeouts: current_config.as_ref().map(|c| c.timeouts).unwrap_or_default(),
+                    protocol: same_printer.and_then(|c| c.protocol),
+                    profile: same_printer.and_then(|c| c.profile.clone()),
+                    code_pages: same_printer
+                        .map(|c| c.code_pages.clone())
+                        .unwrap_or_default(),
+                    cjk_encoding: same_printer.and_then(|c| c.cjk_encoding),
+                    raster_text: same_printer.is_some_and(|c| c.raster_text),
+                    upside_down: same_printer.is_some_and(|c| c.upside_down),
+                    density: print_density(&ui),
+                    print_speed: print_speed(&ui),
+                    print_area: same_printer.and_then(|c| c.print_area),
+                    write_pacing: same_printer.and_then(|c| c.write_pacing),
+                    roles: current_config.map(|c| c.roles).unwrap_or_default(),
                 };
 
                 if let Err(e) = save_config(&config) {
@@ -1496,9 +888,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     label_width_mm: width_mm as u32,
                     label_height_mm: height_mm as u32,
                     dpi: dpi as u32,
+                    serial: barcode_serial_settings(&ui),
+                    // Only set in barcode_config.json
+                    timeouts: load_barcode_config()
+                        .ok()
+                        .flatten()
+                        .map(|c| c.timeouts)
+                        .unwrap_or_default(),
                 };
 
-                let mut manager = bc_manager.lock().unwrap();
+                let mut manager = bc_manager.lock_or_recover();
                 if let Err(e) = manager.connect(config.clone()) {
                     ui.set_barcode_is_connected(false);
                     ui.set_barcode_status_message(format!("\u{2717} Connection failed: {}", e).into());
@@ -1521,7 +920,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             ui.on_barcode_disconnect_printer(move || {
                 let ui = ui_handle.unwrap();
-                let mut manager = bc_manager.lock().unwrap();
+                let mut manager = bc_manager.lock_or_recover();
                 manager.disconnect();
                 ui.set_barcode_is_connected(false);
                 ui.set_barcode_status_message("Barcode printer disconnected".into());
@@ -1538,7 +937,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 ui.set_barcode_is_loading(true);
                 ui.set_barcode_status_message("Printing barcode test label...".into());
 
-                let mut manager = bc_manager.lock().unwrap();
+                let mut manager = bc_manager.lock_or_recover();
                 match manager.print_test_label() {
                     Ok(_) => {
                         ui.set_barcode_status_message("\u{2713} Test label printed successfully!".into());
@@ -1552,6 +951,160 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             });
         }
 
+        // Refresh the job counters in the status panel
+        let stats_timer = slint::Timer::default();
+        {
+            let ui_handle = ui.as_weak();
+            let manager = Arc::clone(&printer_manager);
+
+            stats_timer.start(
+                slint::TimerMode::Repeated,
+                std::time::Duration::from_secs(2),
+                move || {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        let stats = manager.lock_or_recover().print_stats();
+                        ui.set_print_stats(
+                            format!(
+                                "{} printed, {} failed",
+                                stats.total_prints,
+                                stats.failures.total()
+                            )
+                            .into(),
+                        );
+                    }
+                },
+            );
+        }
+
+        // Show what the receipt printer reports about itself: paper out,
+        // cover open, ...
+        let state_timer = slint::Timer::default();
+        {
+            let ui_handle = ui.as_weak();
+            let manager = Arc::clone(&printer_manager);
+            let querying = Arc::new(AtomicBool::new(false));
+
+            state_timer.start(
+                slint::TimerMode::Repeated,
+                std::time::Duration::from_secs(5),
+                move || {
+                    let Some(ui) = ui_handle.upgrade() else {
+                        return;
+                    };
+                    let Some(query) = manager.lock_or_recover().state_query(DEFAULT_PRINTER) else {
+                        ui.set_printer_state("".into());
+                        return;
+                    };
+                    // A printer slow to answer mustn't pile up queries
+                    if querying.swap(true, Ordering::AcqRel) {
+                        return;
+                    }
+                    let ui_handle = ui_handle.clone();
+                    let querying = Arc::clone(&querying);
+                    std::thread::spawn(move || {
+                        let state = query.run();
+                        querying.store(false, Ordering::Release);
+                        // Keep the last state while the printer is busy with a job
+                        let text = match state {
+                            Ok(Some(state)) => match state.message {
+                                Some(message) => message,
+                                None if state.reasons.is_empty() => "Ready".to_string(),
+                                None => state.reasons.join(", "),
+                            },
+                            Ok(None) => return,
+                            Err(e) => {
+                                log::debug!("Printer state query failed: {}", e);
+                                return;
+                            }
+                        };
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(ui) = ui_handle.upgrade() {
+                                ui.set_printer_state(text.into());
+                            }
+                        });
+                    });
+                },
+            );
+        }
+
+        // Notice an unplugged or powered-off receipt printer and reconnect it
+        let reconnect = {
+            let ui_handle = ui.as_weak();
+            let spool = spool.clone();
+            let manager = Arc::clone(&printer_manager);
+            ReconnectSupervisor::spawn(
+                Arc::clone(&printer_manager),
+                ReconnectPolicy::default(),
+                Box::new(move |printer_id, state| {
+                    // Print jobs left over from the last run once the printer is back
+                    if state == ConnectionState::Connected && !spool.pending().is_empty() {
+                        let spool = spool.clone();
+                        let manager = Arc::clone(&manager);
+                        std::thread::spawn(move || match spool.replay(&manager) {
+                            Ok(printed) => {
+                                log::info!("Printed {} spooled job(s) after reconnecting", printed)
+                            }
+                            Err(e) => log::warn!("Failed to print spooled jobs: {}", e),
+                        });
+                    }
+                    // The window only shows the default printer
+                    if printer_id != DEFAULT_PRINTER {
+                        return;
+                    }
+                    let ui_handle = ui_handle.clone();
+                    let _ = slint::invoke_from_event_loop(move || {
+                        let Some(ui) = ui_handle.upgrade() else {
+                            return;
+                        };
+                        match state {
+                            ConnectionState::Connected => {
+                                ui.set_is_connected(true);
+                                ui.set_status_message("✓ Printer reconnected".into());
+                            }
+                            ConnectionState::Reconnecting {
+                                attempt,
+                                retry_in_ms,
+                                error,
+                            } => {
+                                ui.set_is_connected(false);
+                                ui.set_status_message(
+                                    format!(
+                                        "Printer lost ({}), retry {} in {}s...",
+                                        error,
+                                        attempt,
+                                        retry_in_ms.div_ceil(1000)
+                                    )
+                                    .into(),
+                                );
+                            }
+                            ConnectionState::Disconnected => ui.set_is_connected(false),
+                        }
+                    });
+                }),
+            )
+        };
+
+        // Check the printer as soon as a USB device is plugged in or removed
+        // instead of on the next periodic check
+        let _hotplug = {
+            let trigger = reconnect.trigger();
+            match HotplugWatcher::spawn(Box::new(move || trigger.check_now())) {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    log::warn!("{}; printers are only checked periodically", e);
+                    None
+                }
+            }
+        };
+
+        // Ctrl-C, SIGTERM and system shutdown exit like the tray's Exit item
+        tokio::spawn(async {
+            shutdown::signal().await;
+            let _ = slint::invoke_from_event_loop(|| {
+                let _ = slint::quit_event_loop();
+            });
+        });
+
         // Run the application
         let _dummy_timer = slint::Timer::default();
         _dummy_timer.start(
@@ -1567,6 +1120,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     .await;
 
+    // Turn new jobs away, finish or abort the current one and release the
+    // printers, then let the HTTP server go
+    shutdown::shutdown(&print_worker, &printer_manager, &barcode_manager, &shutdown_settings);
+    server_stop.notify_one();
+
     if let Err(e) = result {
         log::error!("Application error: {}", e);
         return Err(e);
@@ -0,0 +1,381 @@
+// src/optimizer.rs
+// Removes redundant style commands from a rendered command stream
+
+use crate::template_render::{CommandSink, PrintCommand};
+
+/// Text style as the printer sees it; `None` where it isn't known
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Style {
+    bold: Option<bool>,
+    underline: Option<bool>,
+    reverse: Option<bool>,
+    /// Width and height multipliers, clamped to what `GS !` can express
+    size: Option<(u8, u8)>,
+    /// `ESC a` value: 0 left, 1 center, 2 right
+    align: Option<u8>,
+}
+
+impl Style {
+    const UNKNOWN: Style = Style {
+        bold: None,
+        underline: None,
+        reverse: None,
+        size: None,
+        align: None,
+    };
+
+    /// State after `ESC @`
+    const RESET: Style = Style {
+        bold: Some(false),
+        underline: Some(false),
+        reverse: Some(false),
+        size: Some((1, 1)),
+        align: Some(0),
+    };
+}
+
+/// Filters a command stream so the printer only receives style changes that
+/// matter, then passes it on to another sink.
+///
+/// Style commands are held back until something is printed, and only the
+/// ones that change the printer's state are sent. Resets that are undone
+/// before the next line, repeated `Align`s and styles that are already active
+/// all disappear, and consecutive feeds are merged. The printer is in the same
+/// state for every line, feed, code and image as with the original stream,
+/// and at the end of the job.
+///
+/// Call [`Optimizer::finish`] once the stream is complete.
+pub struct Optimizer<S: CommandSink> {
+    inner: S,
+    /// What the commands sent so far have set
+    printer: Style,
+    /// What the stream has asked for
+    wanted: Style,
+    /// Feed lines waiting to be merged with the next feed
+    feed: u8,
+}
+
+impl<S: CommandSink> Optimizer<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            printer: Style::UNKNOWN,
+            wanted: Style::UNKNOWN,
+            feed: 0,
+        }
+    }
+
+    /// Send any pending feed and style changes and return the inner sink
+    pub fn finish(mut self) -> S {
+        self.flush_feed();
+        self.sync();
+        self.inner
+    }
+
+    fn flush_feed(&mut self) {
+        if self.feed > 0 {
+            self.inner.push(PrintCommand::Feed(self.feed));
+            self.feed = 0;
+        }
+    }
+
+    /// Send the style changes needed for the printer to match `wanted`
+    fn sync(&mut self) {
+        let (wanted, printer) = (self.wanted, &mut self.printer);
+        if wanted.align.is_some() && wanted.align != printer.align {
+            let align = match wanted.align {
+                Some(1) => "center",
+                Some(2) => "right",
+                _ => "left",
+            };
+            self.inner.push(PrintCommand::Align(align.to_string()));
+            printer.align = wanted.align;
+        }
+        if let Some((w, h)) = wanted.size.filter(|_| wanted.size != printer.size) {
+            self.inner.push(PrintCommand::Size(w, h));
+            printer.size = wanted.size;
+        }
+        if let Some(on) = wanted.bold.filter(|_| wanted.bold != printer.bold) {
+            self.inner.push(PrintCommand::Bold(on));
+            printer.bold = wanted.bold;
+        }
+        if let Some(on) = wanted.underline.filter(|_| wanted.underline != printer.underline) {
+            self.inner.push(PrintCommand::Underline(on));
+            printer.underline = wanted.underline;
+        }
+        if let Some(on) = wanted.reverse.filter(|_| wanted.reverse != printer.reverse) {
+            self.inner.push(PrintCommand::Reverse(on));
+            printer.reverse = wanted.reverse;
+        }
+    }
+}
+
+impl<S: CommandSink> CommandSink for Optimizer<S> {
+    fn push(&mut self, command: PrintCommand) {
+        match command {
+            PrintCommand::Bold(on) => self.wanted.bold = Some(on),
+            PrintCommand::Underline(on) => self.wanted.underline = Some(on),
+            PrintCommand::Reverse(on) => self.wanted.reverse = Some(on),
+            PrintCommand::Size(w, h) => self.wanted.size = Some((w.clamp(1, 8), h.clamp(1, 8))),
+            PrintCommand::Align(align) => {
+                // Same mapping as the encoder, so "CENTER" and "center" match
+                self.wanted.align = Some(match align.to_lowercase().as_str() {
+                    "center" => 1,
+                    "right" => 2,
+                    _ => 0,
+                });
+            }
+            PrintCommand::Init => {
+                // Style set since the last output is reset before it shows
+                self.flush_feed();
+                self.inner.push(PrintCommand::Init);
+                self.printer = Style::RESET;
+                self.wanted = Style::RESET;
+            }
+            PrintCommand::Feed(lines) => {
+                if self.wanted != self.printer {
+                    self.flush_feed();
+                    self.sync();
+                }
+                match self.feed.checked_add(lines) {
+                    Some(total) => self.feed = total,
+                    None => {
+                        self.flush_feed();
+                        self.feed = lines;
+                    }
+                }
+            }
+            PrintCommand::Image(bytes) => {
+                self.flush_feed();
+                self.sync();
+                self.inner.push(PrintCommand::Image(bytes));
+                // Raw image data may change any setting
+                self.printer = Style::UNKNOWN;
+                self.wanted = Style::UNKNOWN;
+            }
+            command => {
+                self.flush_feed();
+                self.sync();
+                self.inner.push(command);
+            }
+        }
+    }
+
+    fn check(&mut self) -> Result<(), String> {
+        self.inner.check()
+    }
+}
+
+/// Optimize a complete command list (see [`Optimizer`])
+pub fn optimize(commands: Vec<PrintCommand>) -> Vec<PrintCommand> {
+    let mut optimizer = Optimizer::new(Vec::with_capacity(commands.len()));
+    for command in commands {
+        optimizer.push(command);
+    }
+    optimizer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{encode_command, encode_commands};
+    use crate::{load_template, ReceiptData, ReceiptItem, TemplateRenderer};
+    use std::collections::BTreeMap;
+
+    /// Style command prefix to its parameter byte; missing when unknown
+    type Settings = BTreeMap<[u8; 2], u8>;
+
+    /// What the printer does with a command stream: every output command
+    /// with the style settings in effect when it printed, then the final
+    /// settings. Settings are read back from the encoded ESC/POS bytes.
+    fn simulate(commands: &[PrintCommand]) -> (Vec<(String, Settings)>, Settings) {
+        let reset: Settings = [
+            [0x1B, 0x45], // ESC E bold
+            [0x1B, 0x2D], // ESC - underline
+            [0x1D, 0x42], // GS B reverse
+            [0x1D, 0x21], // GS ! size
+            [0x1B, 0x61], // ESC a align
+        ]
+        .into_iter()
+        .map(|prefix| (prefix, 0))
+        .collect();
+
+        let mut state = BTreeMap::new();
+        let mut trace = Vec::new();
+        for command in commands {
+            match command {
+                PrintCommand::Bold(_)
+                | PrintCommand::Underline(_)
+                | PrintCommand::Reverse(_)
+                | PrintCommand::Size(..)
+                | PrintCommand::Align(_) => {
+                    let mut bytes = Vec::new();
+                    encode_command(command, &mut bytes);
+                    state.insert([bytes[0], bytes[1]], bytes[2]);
+                }
+                PrintCommand::Init => {
+                    state = reset.clone();
+                    trace.push(("init".to_string(), state.clone()));
+                }
+                PrintCommand::Feed(lines) => {
+                    for _ in 0..*lines {
+                        trace.push(("feed".to_string(), state.clone()));
+                    }
+                }
+                PrintCommand::Image(_) => {
+                    trace.push((format!("{:?}", command), state.clone()));
+                    state.clear();
+                }
+                _ => trace.push((format!("{:?}", command), state.clone())),
+            }
+        }
+        (trace, state)
+    }
+
+    /// xorshift64, so the random cases are the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn flag(&mut self) -> bool {
+            self.below(2) == 1
+        }
+    }
+
+    fn random_command(rng: &mut Rng) -> PrintCommand {
+        match rng.below(12) {
+            0 => PrintCommand::Init,
+            1 => PrintCommand::Write("w".to_string()),
+            2 | 3 => PrintCommand::WriteLine(format!("line {}", rng.below(100))),
+            4 => PrintCommand::Feed([0, 1, 2, 200][rng.below(4)]),
+            5 => PrintCommand::Cut,
+            6 => PrintCommand::Bold(rng.flag()),
+            7 => PrintCommand::Underline(rng.flag()),
+            8 => PrintCommand::Reverse(rng.flag()),
+            9 => PrintCommand::Size(rng.below(10) as u8, rng.below(10) as u8),
+            10 => PrintCommand::Align(["left", "CENTER", "right", "justify"][rng.below(4)].to_string()),
+            _ => PrintCommand::Image(vec![0x1B, 0x45, 0x01]),
+        }
+    }
+
+    fn random_element(rng: &mut Rng, depth: usize) -> serde_json::Value {
+        let align = ["left", "center", "right"][rng.below(3)];
+        let divider = ["solid", "dashed", "double"][rng.below(3)];
+        let box_style = ["filled", "bordered", "shaded"][rng.below(3)];
+        match rng.below(if depth > 0 { 6 } else { 7 }) {
+            0 | 1 => serde_json::json!({
+                "type": "text", "content": "Order {{order_id}}", "align": align,
+                "bold": rng.flag(), "underline": rng.flag(), "invert": rng.flag(),
+                "font_size": rng.below(3) + 1
+            }),
+            2 => serde_json::json!({
+                "type": "row", "left": "Total", "right": "{{total}}",
+                "bold": rng.flag(), "font_size": rng.below(2) + 1
+            }),
+            3 => serde_json::json!({ "type": "divider", "style": divider }),
+            4 => serde_json::json!({ "type": "space", "lines": rng.below(3) }),
+            5 => serde_json::json!({
+                "type": "table", "data_source": "items", "show_header": rng.flag(),
+                "header_bold": rng.flag(), "alternating_rows": rng.flag(),
+                "columns": [
+                    { "field": "name", "width": 20 },
+                    { "field": "total", "width": 10, "align": "right", "format": "currency" }
+                ]
+            }),
+            _ => serde_json::json!({
+                "type": "box", "style": box_style,
+                "elements": (0..rng.below(3) + 1)
+                    .map(|_| random_element(rng, depth + 1))
+                    .collect::<Vec<_>>()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_drops_redundant_style_changes() {
+        let commands = vec![
+            PrintCommand::Init,
+            PrintCommand::Align("left".to_string()),
+            PrintCommand::Bold(false),
+            PrintCommand::Size(1, 1),
+            PrintCommand::WriteLine("A".to_string()),
+            PrintCommand::Bold(true),
+            PrintCommand::WriteLine("B".to_string()),
+            PrintCommand::Bold(false),
+            PrintCommand::Align("center".to_string()),
+            PrintCommand::Align("center".to_string()),
+            PrintCommand::Bold(true),
+            PrintCommand::WriteLine("C".to_string()),
+            PrintCommand::Feed(1),
+            PrintCommand::Feed(2),
+            PrintCommand::Bold(false),
+            PrintCommand::Cut,
+        ];
+        assert_eq!(
+            optimize(commands),
+            vec![
+                PrintCommand::Init,
+                PrintCommand::WriteLine("A".to_string()),
+                PrintCommand::Bold(true),
+                PrintCommand::WriteLine("B".to_string()),
+                PrintCommand::Align("center".to_string()),
+                PrintCommand::WriteLine("C".to_string()),
+                PrintCommand::Feed(3),
+                PrintCommand::Bold(false),
+                PrintCommand::Cut,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_random_streams_print_the_same() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for case in 0..500 {
+            let commands: Vec<PrintCommand> =
+                (0..rng.below(60)).map(|_| random_command(&mut rng)).collect();
+            let optimized = optimize(commands.clone());
+            assert_eq!(simulate(&optimized), simulate(&commands), "case {}: {:?}", case, commands);
+        }
+    }
+
+    #[test]
+    fn test_random_templates_print_the_same() {
+        let mut rng = Rng(42);
+        let data = ReceiptData {
+            order_id: "1001".to_string(),
+            total: 12.5,
+            items: vec![
+                ReceiptItem { name: "Latte".to_string(), total: 4.5, ..Default::default() },
+                ReceiptItem { name: "Muffin".to_string(), total: 3.0, ..Default::default() },
+                ReceiptItem { name: "Tea".to_string(), total: 5.0, ..Default::default() },
+            ],
+            ..Default::default()
+        };
+
+        let mut total_before = 0;
+        let mut total_after = 0;
+        for case in 0..200 {
+            let elements: Vec<_> =
+                (0..rng.below(8) + 1).map(|_| random_element(&mut rng, 0)).collect();
+            let template = serde_json::json!({
+                "id": "random", "name": "Random", "version": "1.0", "paper_width": 48,
+                "layout": { "sections": [{ "type": "body", "elements": elements }] }
+            });
+            let template = load_template(&template.to_string()).unwrap();
+            let renderer = TemplateRenderer::new(48);
+            let commands = renderer.render_template(&template, &data).unwrap();
+            let optimized = optimize(commands.clone());
+
+            assert_eq!(simulate(&optimized), simulate(&commands), "case {}: {:?}", case, template);
+            total_before += encode_commands(&commands).len();
+            total_after += encode_commands(&optimized).len();
+        }
+        assert!(total_after < total_before);
+    }
+}
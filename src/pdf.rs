@@ -0,0 +1,1124 @@
+// src/pdf.rs
+// Lay print jobs out as receipt-width PDFs, for stores without a printer
+
+use std::io::Write;
+
+use qrcode::{Color, QrCode};
+
+use crate::backend::{JobFolder, PrintBackend};
+use crate::barcode::HriPosition;
+use crate::template_render::{text_width, Font, PrintCommand, QrErrorCorrection};
+
+/// The layout is measured in the dots of a typical 203 dpi thermal printer
+const DOTS_PER_INCH: f32 = 203.0;
+/// Font A character cell, in dots (font B is narrower)
+pub(crate) const CHAR_WIDTH: u32 = 12;
+pub(crate) const CHAR_HEIGHT: u32 = 24;
+/// From one line of text to the next at normal size, in dots
+pub(crate) const LINE_HEIGHT: u32 = 30;
+/// Below the baseline of normal size text, in dots
+const DESCENT: u32 = 5;
+/// Blank paper around the printed area, in dots
+const MARGIN: u32 = 32;
+/// Printable widths of 58 mm and 80 mm paper, in dots
+const NARROW_WIDTH: u32 = 384;
+const WIDE_WIDTH: u32 = 576;
+/// PDF viewers don't open pages taller than 200 inches, so a longer receipt
+/// carries on over more pages
+const MAX_PAGE_HEIGHT: u32 = 200 * 203 - 2 * MARGIN;
+/// Blank modules left around a QR code and a barcode
+const QR_QUIET_ZONE: u32 = 4;
+const BARCODE_QUIET_ZONE: u32 = 10;
+
+// ==================== Backend ====================
+
+/// Saves every job as a PDF in a folder, laid out the way a thermal printer
+/// would print it, so receipts can be archived or emailed
+#[derive(Debug)]
+pub struct PdfBackend {
+    pub folder: JobFolder,
+}
+
+impl PdfBackend {
+    pub fn new(dir: &str) -> Result<Self, String> {
+        Ok(Self {
+            folder: JobFolder::new(dir, "PDF")?,
+        })
+    }
+
+    fn save(&self, pdf: Vec<u8>) -> Result<(), String> {
+        let path = self.folder.save("receipt", "pdf", &pdf)?;
+        log::debug!("Saved receipt to {}", path.display());
+        Ok(())
+    }
+}
+
+impl PrintBackend for PdfBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        // A page can't be laid out until its height is known
+        Ok(None)
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        self.save(escpos_to_pdf(bytes))
+    }
+
+    fn takes_commands(&self) -> bool {
+        true
+    }
+
+    fn write_commands(&self, commands: &[PrintCommand]) -> Result<(), String> {
+        self.save(render_pdf(commands))
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        self.folder.check()
+    }
+}
+
+/// Lay rendered print commands out as a PDF, one page per cut
+pub fn render_pdf(commands: &[PrintCommand]) -> Vec<u8> {
+    write_pdf(&layout(commands))
+}
+
+/// [`render_pdf`] for a job that has already been encoded, such as a spooled
+/// job or a raw image. Understands the ESC/POS that
+/// [`encode_commands`](crate::encoder::encode_commands) produces.
+pub fn escpos_to_pdf(bytes: &[u8]) -> Vec<u8> {
+    let mut layout = Layout::default();
+    layout.escpos(bytes);
+    write_pdf(&layout.finish())
+}
+
+// ==================== Layout ====================
+
+/// The blocks of each page, as the printer would place them
+pub(crate) fn layout(commands: &[PrintCommand]) -> Vec<Vec<Block>> {
+    let mut layout = Layout::default();
+    for command in commands {
+        layout.command(command);
+    }
+    layout.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    fn parse(align: &str) -> Self {
+        match align.to_lowercase().as_str() {
+            "center" => Align::Center,
+            "right" => Align::Right,
+            _ => Align::Left,
+        }
+    }
+
+    /// Space to leave on the left of something `width` wide
+    pub(crate) fn offset(self, width: u32, printable: u32) -> u32 {
+        let spare = printable.saturating_sub(width);
+        match self {
+            Align::Left => 0,
+            Align::Center => spare / 2,
+            Align::Right => spare,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Style {
+    pub(crate) bold: bool,
+    pub(crate) underline: bool,
+    pub(crate) reverse: bool,
+    pub(crate) double_strike: bool,
+    pub(crate) font: Font,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            bold: false,
+            underline: false,
+            reverse: false,
+            double_strike: false,
+            font: Font::A,
+            width: 1,
+            height: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Run {
+    pub(crate) text: String,
+    pub(crate) style: Style,
+}
+
+impl Run {
+    pub(crate) fn width(&self) -> u32 {
+        text_width(&self.text) as u32 * self.style.font.char_width() * self.style.width
+    }
+}
+
+/// One strip of the receipt, as wide as the paper
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Block {
+    Line {
+        runs: Vec<Run>,
+        align: Align,
+    },
+    /// 1-bit rows as sent with GS v 0, most significant bit leftmost
+    Raster {
+        bytes_per_row: u32,
+        rows: u32,
+        data: Vec<u8>,
+    },
+    /// Black rectangles `(x, y, width, height)` from the top left corner,
+    /// for QR codes and barcodes
+    Shapes {
+        width: u32,
+        height: u32,
+        rects: Vec<(u32, u32, u32, u32)>,
+        align: Align,
+    },
+}
+
+impl Block {
+    pub(crate) fn width(&self) -> u32 {
+        match self {
+            Block::Line { runs, .. } => runs.iter().map(Run::width).sum(),
+            Block::Raster { bytes_per_row, .. } => bytes_per_row * 8,
+            Block::Shapes { width, .. } => *width,
+        }
+    }
+
+    pub(crate) fn height(&self) -> u32 {
+        match self {
+            Block::Line { runs, .. } => LINE_HEIGHT * line_scale(runs),
+            Block::Raster { rows, .. } => *rows,
+            Block::Shapes { height, .. } => *height,
+        }
+    }
+}
+
+/// The tallest character size on a line
+pub(crate) fn line_scale(runs: &[Run]) -> u32 {
+    runs.iter().map(|run| run.style.height).max().unwrap_or(1)
+}
+
+/// Places print commands on pages the way a printer places them on paper
+#[derive(Debug, Default)]
+struct Layout {
+    pages: Vec<Vec<Block>>,
+    page: Vec<Block>,
+    page_height: u32,
+    /// Text waiting for the end of its line
+    line: Vec<Run>,
+    style: Style,
+    align: Align,
+    /// The QR code `GS ( k` commands are setting up
+    qr: QrSymbol,
+    barcode_settings: BarcodeSettings,
+}
+
+/// `GS h`, `GS w` and `GS H`, for the next `GS k` barcode
+#[derive(Debug, Default)]
+struct BarcodeSettings {
+    height: Option<u8>,
+    width: Option<u8>,
+    hri: Option<HriPosition>,
+}
+
+/// Settings and data of an encoded QR code, until it is printed
+#[derive(Debug, Default)]
+struct QrSymbol {
+    size: Option<u8>,
+    error_correction: QrErrorCorrection,
+    data: Vec<u8>,
+}
+
+impl Layout {
+    fn command(&mut self, command: &PrintCommand) {
+        match command {
+            PrintCommand::Init => self.init(),
+            PrintCommand::Write(text) => self.text(text),
+            PrintCommand::WriteLine(text) => {
+                self.text(text);
+                self.newline();
+            }
+            PrintCommand::Feed(lines) => {
+                for _ in 0..*lines {
+                    self.newline();
+                }
+            }
+            PrintCommand::Cut => self.cut(),
+            PrintCommand::Bold(on) => self.style.bold = *on,
+            PrintCommand::Underline(on) => self.style.underline = *on,
+            PrintCommand::Reverse(on) => self.style.reverse = *on,
+            PrintCommand::DoubleStrike(on) => self.style.double_strike = *on,
+            PrintCommand::Font(font) => self.style.font = *font,
+            PrintCommand::Size(width, height) => {
+                self.style.width = (*width).clamp(1, 8) as u32;
+                self.style.height = (*height).clamp(1, 8) as u32;
+            }
+            PrintCommand::Align(align) => self.align = Align::parse(align),
+            PrintCommand::QRCode {
+                content,
+                size,
+                error_correction,
+            } => self.qr_code(content, *size, *error_correction),
+            PrintCommand::Barcode {
+                content,
+                height,
+                width,
+                hri,
+                ..
+            } => self.barcode(content, *height, *width, *hri),
+            PrintCommand::Image(bytes) => self.escpos(bytes),
+            // Nothing to show on paper
+            PrintCommand::Buzzer { .. } => {}
+            // The image is only in the printer's memory
+            PrintCommand::NvLogo(slot) => {
+                self.text(&format!("[NV logo {}]", slot));
+                self.newline();
+            }
+            // The page is laid out as the receipt reads, the right way up
+            PrintCommand::UpsideDown(_) => {}
+            // The page is as wide as the template
+            PrintCommand::PrintArea { .. } => {}
+        }
+    }
+
+    /// Interpret encoded ESC/POS. Commands this layout has no use for are
+    /// skipped along with their first parameter byte.
+    fn escpos(&mut self, bytes: &[u8]) {
+        let mut text: Vec<u8> = Vec::new();
+        let mut rest = bytes;
+        while let Some(&byte) = rest.first() {
+            if !matches!(byte, 0x1B | 0x1D | 0x10 | b'\n' | b'\r') {
+                text.push(byte);
+                rest = &rest[1..];
+                continue;
+            }
+            let line = String::from_utf8_lossy(&std::mem::take(&mut text)).into_owned();
+            self.text(&line);
+
+            let used = match *rest {
+                [0x1B, 0x40, ..] => {
+                    self.init();
+                    2
+                }
+                [0x1B, 0x45, n, ..] => {
+                    self.style.bold = n & 1 != 0;
+                    3
+                }
+                [0x1B, 0x2D, n, ..] => {
+                    self.style.underline = n & 3 != 0;
+                    3
+                }
+                [0x1B, 0x47, n, ..] => {
+                    self.style.double_strike = n & 1 != 0;
+                    3
+                }
+                [0x1B, 0x4D, n, ..] => {
+                    self.style.font = match n {
+                        1 | b'1' => Font::B,
+                        2 | b'2' => Font::C,
+                        _ => Font::A,
+                    };
+                    3
+                }
+                [0x1D, 0x42, n, ..] => {
+                    self.style.reverse = n & 1 != 0;
+                    3
+                }
+                [0x1D, 0x21, n, ..] => {
+                    self.style.width = (n >> 4 & 7) as u32 + 1;
+                    self.style.height = (n & 7) as u32 + 1;
+                    3
+                }
+                [0x1B, 0x61, n, ..] => {
+                    self.align = match n {
+                        1 | b'1' => Align::Center,
+                        2 | b'2' => Align::Right,
+                        _ => Align::Left,
+                    };
+                    3
+                }
+                // ESC { upside-down printing, shown the right way up
+                [0x1B, 0x7B, _, ..] => 3,
+                // GS L and GS W print area, the page is as wide as the text
+                [0x1D, 0x4C | 0x57, _, _, ..] => 4,
+                [0x1B, 0x64, n, ..] => {
+                    for _ in 0..n {
+                        self.newline();
+                    }
+                    3
+                }
+                [0x1D, 0x56, 65 | 66, _, ..] => {
+                    self.cut();
+                    4
+                }
+                [0x1D, 0x56, _, ..] => {
+                    self.cut();
+                    3
+                }
+                [0x1D, 0x68, n, ..] => {
+                    self.barcode_settings.height = Some(n);
+                    3
+                }
+                [0x1D, 0x77, n, ..] => {
+                    self.barcode_settings.width = Some(n);
+                    3
+                }
+                [0x1D, 0x48, n, ..] => {
+                    self.barcode_settings.hri = Some(match n {
+                        1 | b'1' => HriPosition::Above,
+                        2 | b'2' => HriPosition::Below,
+                        3 | b'3' => HriPosition::Both,
+                        _ => HriPosition::None,
+                    });
+                    3
+                }
+                [0x1D, 0x6B, m @ 65..=255, n, ..] => {
+                    let len = n as usize;
+                    self.encoded_barcode(m, &rest[4..(4 + len).min(rest.len())]);
+                    4 + len
+                }
+                // GS ( K print density and speed
+                [0x1D, 0x28, 0x4B, pl, ph, ..] => 5 + u16::from_le_bytes([pl, ph]) as usize,
+                [0x1D, 0x28, 0x6B, pl, ph, ..] => {
+                    let len = u16::from_le_bytes([pl, ph]) as usize;
+                    self.qr_function(&rest[5..(5 + len).min(rest.len())]);
+                    5 + len
+                }
+                [0x1D, 0x76, 0x30, _, xl, xh, yl, yh, ..] => {
+                    let bytes_per_row = u16::from_le_bytes([xl, xh]) as u32;
+                    let rows = u16::from_le_bytes([yl, yh]) as u32;
+                    let data = &rest[8..];
+                    let len = ((bytes_per_row * rows) as usize).min(data.len());
+                    self.raster(bytes_per_row, data[..len].to_vec());
+                    8 + len
+                }
+                [b'\n', ..] => {
+                    self.newline();
+                    1
+                }
+                [b'\r', ..] => 1,
+                _ => 2,
+            };
+            rest = &rest[used.min(rest.len())..];
+        }
+        let line = String::from_utf8_lossy(&text).into_owned();
+        self.text(&line);
+    }
+
+    /// One `GS ( k` QR code function: a setting, the data or printing it
+    fn qr_function(&mut self, params: &[u8]) {
+        match params {
+            [0x31, 0x43, n] => self.qr.size = Some(*n),
+            [0x31, 0x45, n] => {
+                self.qr.error_correction = match n {
+                    0x30 => QrErrorCorrection::L,
+                    0x32 => QrErrorCorrection::Q,
+                    0x33 => QrErrorCorrection::H,
+                    _ => QrErrorCorrection::M,
+                }
+            }
+            [0x31, 0x50, 0x30, data @ ..] => self.qr.data = data.to_vec(),
+            [0x31, 0x51, 0x30] => {
+                let data = std::mem::take(&mut self.qr.data);
+                let content = String::from_utf8_lossy(&data);
+                // Printers default to 3 dots a module
+                self.qr_code(
+                    &content,
+                    self.qr.size.unwrap_or(3),
+                    self.qr.error_correction,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// A `GS k` barcode of type `m` with the `GS h`, `GS w` and `GS H`
+    /// settings before it
+    fn encoded_barcode(&mut self, m: u8, data: &[u8]) {
+        let data = String::from_utf8_lossy(data);
+        // Code 128 starts with its code set, e.g. "{B", and doubles '{'
+        let content = match (m, data.strip_prefix('{')) {
+            (73, Some(rest)) => rest.get(1..).unwrap_or_default().replace("{{", "{"),
+            _ => data.into_owned(),
+        };
+        // Printers default to 162 dots high, 3 dots wide and no text
+        let height = self.barcode_settings.height.unwrap_or(162);
+        let width = self.barcode_settings.width.unwrap_or(3);
+        let hri = self.barcode_settings.hri.unwrap_or(HriPosition::None);
+        self.barcode(&content, height, width, hri);
+    }
+
+    fn init(&mut self) {
+        self.style = Style::default();
+        self.align = Align::Left;
+    }
+
+    fn text(&mut self, text: &str) {
+        let mut lines = text.split('\n');
+        if let Some(first) = lines.next() {
+            self.append(first);
+        }
+        for line in lines {
+            self.newline();
+            self.append(line);
+        }
+    }
+
+    fn append(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.line.last_mut() {
+            Some(run) if run.style == self.style => run.text.push_str(text),
+            _ => self.line.push(Run {
+                text: text.to_string(),
+                style: self.style,
+            }),
+        }
+    }
+
+    /// End the line, or feed a blank one
+    fn newline(&mut self) {
+        let runs = std::mem::take(&mut self.line);
+        self.add(Block::Line {
+            runs,
+            align: self.align,
+        });
+    }
+
+    /// Print any text still waiting, as a printer does before a cut or an
+    /// image
+    fn flush_line(&mut self) {
+        if !self.line.is_empty() {
+            self.newline();
+        }
+    }
+
+    fn add(&mut self, block: Block) {
+        let height = block.height();
+        if self.page_height + height > MAX_PAGE_HEIGHT && !self.page.is_empty() {
+            self.pages.push(std::mem::take(&mut self.page));
+            self.page_height = 0;
+        }
+        self.page_height += height;
+        self.page.push(block);
+    }
+
+    fn cut(&mut self) {
+        self.flush_line();
+        if !self.page.is_empty() {
+            self.pages.push(std::mem::take(&mut self.page));
+            self.page_height = 0;
+        }
+    }
+
+    fn raster(&mut self, bytes_per_row: u32, data: Vec<u8>) {
+        if bytes_per_row == 0 {
+            return;
+        }
+        self.flush_line();
+        let rows = data.len() as u32 / bytes_per_row;
+        self.add(Block::Raster {
+            bytes_per_row,
+            rows,
+            data,
+        });
+    }
+
+    fn qr_code(&mut self, content: &str, size: u8, error_correction: QrErrorCorrection) {
+        let code = match QrCode::with_error_correction_level(
+            content.as_bytes(),
+            error_correction.ec_level(),
+        ) {
+            Ok(code) => code,
+            Err(e) => {
+                log::warn!("Cannot draw QR code ({}), printing its text instead", e);
+                self.text(content);
+                self.newline();
+                return;
+            }
+        };
+        self.flush_line();
+
+        let module = size.clamp(1, 16) as u32;
+        let modules = code.width() as u32;
+        let colors = code.to_colors();
+        let mut rects = Vec::new();
+        for (y, row) in colors.chunks(modules as usize).enumerate() {
+            // One rectangle per run of dark modules
+            let mut x = 0;
+            while x < modules {
+                if row[x as usize] == Color::Light {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < modules && row[x as usize] == Color::Dark {
+                    x += 1;
+                }
+                rects.push((
+                    (QR_QUIET_ZONE + start) * module,
+                    (QR_QUIET_ZONE + y as u32) * module,
+                    (x - start) * module,
+                    module,
+                ));
+            }
+        }
+        let side = (modules + 2 * QR_QUIET_ZONE) * module;
+        self.add(Block::Shapes {
+            width: side,
+            height: side,
+            rects,
+            align: self.align,
+        });
+    }
+
+    /// Drawn as Code 128 whatever the format asked for, which scans to the
+    /// same content
+    fn barcode(&mut self, content: &str, height: u8, width: u8, hri: HriPosition) {
+        self.flush_line();
+        if matches!(hri, HriPosition::Above | HriPosition::Both) {
+            self.barcode_text(content);
+        }
+        let module = width.clamp(1, 6) as u32;
+        let height = height.max(1) as u32;
+        let modules = code128_modules(content);
+
+        let mut rects = Vec::new();
+        let mut x = 0;
+        while x < modules.len() {
+            if !modules[x] {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < modules.len() && modules[x] {
+                x += 1;
+            }
+            rects.push((
+                (BARCODE_QUIET_ZONE + start as u32) * module,
+                0,
+                (x - start) as u32 * module,
+                height,
+            ));
+        }
+        self.add(Block::Shapes {
+            width: (modules.len() as u32 + 2 * BARCODE_QUIET_ZONE) * module,
+            height,
+            rects,
+            align: self.align,
+        });
+
+        if matches!(hri, HriPosition::Below | HriPosition::Both) {
+            self.barcode_text(content);
+        }
+    }
+
+    /// The human readable line of a barcode, in the normal style
+    fn barcode_text(&mut self, content: &str) {
+        let style = std::mem::take(&mut self.style);
+        self.text(content);
+        self.newline();
+        self.style = style;
+    }
+
+    fn finish(mut self) -> Vec<Vec<Block>> {
+        self.cut();
+        if self.pages.is_empty() {
+            self.pages.push(Vec::new());
+        }
+        self.pages
+    }
+}
+
+// ==================== Code 128 ====================
+
+/// Bar and space widths of each Code 128 symbol, starting with a bar
+const CODE128_PATTERNS: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212",
+    "221213", "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221",
+    "223211", "221132", "221231", "213212", "223112", "312131", "311222", "321122", "321221",
+    "312212", "322112", "322211", "212123", "212321", "232121", "111323", "131123", "131321",
+    "112313", "132113", "132311", "211313", "231113", "231311", "112133", "112331", "132131",
+    "113123", "113321", "133121", "313121", "211331", "231131", "213113", "213311", "213131",
+    "311123", "311321", "331121", "312113", "312311", "332111", "314111", "221411", "431111",
+    "111224", "111422", "121124", "121421", "141122", "141221", "112214", "112412", "122114",
+    "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111", "111242",
+    "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311",
+    "113141", "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+const CODE128_START_B: usize = 104;
+const CODE128_STOP: usize = 106;
+
+/// Encode `content` with code set B, one `true` per dark module. Characters
+/// code set B doesn't have are sent as `?`.
+fn code128_modules(content: &str) -> Vec<bool> {
+    let values: Vec<usize> = content
+        .chars()
+        .map(|c| match c {
+            ' '..='\x7F' => c as usize - 32,
+            _ => '?' as usize - 32,
+        })
+        .collect();
+    let checksum = values
+        .iter()
+        .enumerate()
+        .fold(CODE128_START_B, |sum, (i, value)| sum + (i + 1) * value)
+        % 103;
+
+    let mut modules = Vec::new();
+    let symbols = std::iter::once(CODE128_START_B)
+        .chain(values)
+        .chain([checksum, CODE128_STOP]);
+    for symbol in symbols {
+        for (i, width) in CODE128_PATTERNS[symbol].bytes().enumerate() {
+            let bar = i % 2 == 0;
+            modules.resize(modules.len() + (width - b'0') as usize, bar);
+        }
+    }
+    modules
+}
+
+// ==================== PDF Writer ====================
+
+/// Objects written so far and where each one starts
+struct PdfWriter {
+    out: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl PdfWriter {
+    fn new() -> Self {
+        Self {
+            out: b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec(),
+            offsets: Vec::new(),
+        }
+    }
+
+    /// Reserve an object number, to refer to before the object is written
+    fn reserve(&mut self) -> usize {
+        self.offsets.push(0);
+        self.offsets.len()
+    }
+
+    fn object(&mut self, id: usize, body: &[u8]) {
+        self.offsets[id - 1] = self.out.len();
+        let _ = writeln!(self.out, "{} 0 obj", id);
+        self.out.extend_from_slice(body);
+        self.out.extend_from_slice(b"\nendobj\n");
+    }
+
+    fn stream(&mut self, id: usize, dictionary: &str, data: &[u8]) {
+        let mut body =
+            format!("<< {} /Length {} >>\nstream\n", dictionary, data.len()).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\nendstream");
+        self.object(id, &body);
+    }
+
+    fn finish(mut self, root: usize) -> Vec<u8> {
+        let xref = self.out.len();
+        let _ = writeln!(
+            self.out,
+            "xref\n0 {}\n0000000000 65535 f ",
+            self.offsets.len() + 1
+        );
+        for offset in &self.offsets {
+            let _ = writeln!(self.out, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            self.out,
+            "trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            root,
+            xref
+        );
+        self.out
+    }
+}
+
+/// Text in a standard font, as a PDF string in WinAnsiEncoding
+fn pdf_string(text: &str) -> Vec<u8> {
+    let mut out = vec![b'('];
+    for c in text.chars() {
+        let byte = match c {
+            '€' => 0x80,
+            ' '..='~' | '\u{A0}'..='\u{FF}' => c as u32 as u8,
+            _ => b'?',
+        };
+        if matches!(byte, b'(' | b')' | b'\\') {
+            out.push(b'\\');
+        }
+        out.push(byte);
+    }
+    out.push(b')');
+    out
+}
+
+fn write_pdf(pages: &[Vec<Block>]) -> Vec<u8> {
+    // Every page is as wide as the paper the widest block needs
+    let widest = pages.iter().flatten().map(Block::width).max().unwrap_or(0);
+    let printable = if widest <= NARROW_WIDTH {
+        NARROW_WIDTH
+    } else if widest <= WIDE_WIDTH {
+        WIDE_WIDTH
+    } else {
+        widest
+    };
+    let page_width = printable + 2 * MARGIN;
+    let points = |dots: u32| dots as f32 * 72.0 / DOTS_PER_INCH;
+
+    let mut pdf = PdfWriter::new();
+    let catalog = pdf.reserve();
+    let pages_id = pdf.reserve();
+    let regular = pdf.reserve();
+    let bold = pdf.reserve();
+    pdf.object(
+        regular,
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>",
+    );
+    pdf.object(
+        bold,
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier-Bold /Encoding /WinAnsiEncoding >>",
+    );
+
+    let mut kids = Vec::new();
+    for blocks in pages {
+        let page_height = blocks.iter().map(Block::height).sum::<u32>() + 2 * MARGIN;
+        let page = pdf.reserve();
+        let contents = pdf.reserve();
+        kids.push(page);
+
+        // Draw in dots with the origin at the bottom left
+        let mut content: Vec<u8> = Vec::new();
+        let scale = 72.0 / DOTS_PER_INCH;
+        let _ = writeln!(content, "{:.5} 0 0 {:.5} 0 0 cm", scale, scale);
+        let mut images = Vec::new();
+        let mut top = MARGIN;
+        for block in blocks {
+            let bottom = page_height - top - block.height();
+            match block {
+                Block::Line { runs, align } => {
+                    let scale = line_scale(runs);
+                    let mut x = MARGIN + align.offset(block.width(), printable);
+                    // Smaller text sits on the bottom of the tallest cells
+                    let cell_bottom = page_height - top - CHAR_HEIGHT * scale;
+                    for run in runs {
+                        draw_run(&mut content, run, x, cell_bottom);
+                        x += run.width();
+                    }
+                }
+                Block::Raster {
+                    bytes_per_row,
+                    rows,
+                    data,
+                } => {
+                    let image = pdf.reserve();
+                    let name = format!("Im{}", images.len() + 1);
+                    pdf.stream(
+                        image,
+                        &format!(
+                            "/Type /XObject /Subtype /Image /Width {} /Height {} /ImageMask true /BitsPerComponent 1 /Decode [1 0]",
+                            bytes_per_row * 8,
+                            rows
+                        ),
+                        data,
+                    );
+                    let _ = writeln!(
+                        content,
+                        "q {} 0 0 {} {} {} cm /{} Do Q",
+                        bytes_per_row * 8,
+                        rows,
+                        MARGIN,
+                        bottom,
+                        name
+                    );
+                    images.push((name, image));
+                }
+                Block::Shapes {
+                    width,
+                    height,
+                    rects,
+                    align,
+                } => {
+                    let left = MARGIN + align.offset(*width, printable);
+                    for (x, y, w, h) in rects {
+                        let _ = writeln!(
+                            content,
+                            "{} {} {} {} re",
+                            left + x,
+                            bottom + height - y - h,
+                            w,
+                            h
+                        );
+                    }
+                    if !rects.is_empty() {
+                        content.extend_from_slice(b"f\n");
+                    }
+                }
+            }
+            top += block.height();
+        }
+
+        pdf.stream(contents, "", &content);
+        let xobjects: String = images
+            .iter()
+            .map(|(name, id)| format!(" /{} {} 0 R", name, id))
+            .collect();
+        pdf.object(
+            page,
+            format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {:.2} {:.2}] /Contents {} 0 R /Resources << /Font << /F1 {} 0 R /F2 {} 0 R >> /XObject <<{} >> >> >>",
+                pages_id,
+                points(page_width),
+                points(page_height),
+                contents,
+                regular,
+                bold,
+                xobjects
+            )
+            .as_bytes(),
+        );
+    }
+
+    let kids: Vec<String> = kids.iter().map(|id| format!("{} 0 R", id)).collect();
+    pdf.object(
+        pages_id,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            kids.len()
+        )
+        .as_bytes(),
+    );
+    pdf.object(
+        catalog,
+        format!("<< /Type /Catalog /Pages {} 0 R >>", pages_id).as_bytes(),
+    );
+    pdf.finish(catalog)
+}
+
+/// Draw one run of text whose character cells sit on `cell_bottom`
+fn draw_run(content: &mut Vec<u8>, run: &Run, x: u32, cell_bottom: u32) {
+    let Style {
+        bold,
+        underline,
+        reverse,
+        double_strike,
+        font,
+        width,
+        height,
+    } = run.style;
+    let baseline = cell_bottom + DESCENT * height;
+    if reverse {
+        let _ = writeln!(
+            content,
+            "{} {} {} {} re f 1 g",
+            x,
+            cell_bottom,
+            run.width(),
+            CHAR_HEIGHT * height
+        );
+    }
+    let _ = write!(
+        content,
+        "BT /{} {} Tf {} Tz {} {} Td ",
+        // Double-strike looks bold; a narrower font is squeezed
+        if bold || double_strike { "F2" } else { "F1" },
+        CHAR_WIDTH * height * 5 / 3,
+        width * font.char_width() * 100 / (CHAR_WIDTH * height),
+        x,
+        baseline
+    );
+    content.extend_from_slice(&pdf_string(&run.text));
+    content.extend_from_slice(b" Tj ET\n");
+    if underline {
+        let _ = writeln!(
+            content,
+            "{} {} {} 2 re f",
+            x,
+            baseline.saturating_sub(3),
+            run.width()
+        );
+    }
+    if reverse {
+        content.extend_from_slice(b"0 g\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::encode_commands;
+
+    fn text(pdf: &[u8]) -> String {
+        String::from_utf8_lossy(pdf).into_owned()
+    }
+
+    #[test]
+    fn test_code128_symbols() {
+        for (value, pattern) in CODE128_PATTERNS.iter().enumerate() {
+            let modules: u32 = pattern.bytes().map(|w| (w - b'0') as u32).sum();
+            let expected = if value == CODE128_STOP { 13 } else { 11 };
+            assert_eq!(modules, expected, "symbol {}", value);
+        }
+
+        // Start B, 'H', 'i', checksum (104 + 40 * 1 + 73 * 2) % 103 = 84, stop
+        let modules = code128_modules("Hi");
+        assert_eq!(modules.len(), 4 * 11 + 13);
+        let symbol = |n: usize| -> String {
+            modules[n * 11..n * 11 + 11]
+                .iter()
+                .map(|&bar| if bar { '1' } else { '0' })
+                .collect()
+        };
+        assert_eq!(symbol(0), "11010010000");
+        assert_eq!(symbol(3), "10011110100");
+    }
+
+    #[test]
+    fn test_render_pdf_lays_out_receipt() {
+        let commands = vec![
+            PrintCommand::Init,
+            PrintCommand::Align("center".to_string()),
+            PrintCommand::Bold(true),
+            PrintCommand::WriteLine("CAFE (DOWNTOWN)".to_string()),
+            PrintCommand::Bold(false),
+            PrintCommand::Align("left".to_string()),
+            PrintCommand::WriteLine("Latte            4.50".to_string()),
+            PrintCommand::QRCode {
+                content: "https://example.com/r/42".to_string(),
+                size: 4,
+                error_correction: QrErrorCorrection::M,
+            },
+            PrintCommand::Barcode {
+                content: "42".to_string(),
+                format: "CODE128".to_string(),
+                height: 80,
+                width: 2,
+                hri: HriPosition::Below,
+            },
+            PrintCommand::Feed(2),
+            PrintCommand::Cut,
+            PrintCommand::WriteLine("Second copy".to_string()),
+            PrintCommand::Cut,
+        ];
+        let bytes = render_pdf(&commands);
+        let pdf = text(&bytes);
+
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        assert!(pdf.contains("/Count 2"));
+        // 58 mm paper: 384 dots printable plus the margins
+        assert!(pdf.contains("/MediaBox [0 0 158.90 "));
+        assert!(pdf.contains("/F2 20 Tf 100 Tz"));
+        assert!(pdf.contains("(CAFE \\(DOWNTOWN\\)) Tj"));
+        assert!(pdf.contains("(Latte            4.50) Tj"));
+        assert!(pdf.contains("(42) Tj"));
+        assert!(pdf.contains("(Second copy) Tj"));
+        // QR modules and bars are drawn, not printed as text
+        assert!(pdf.matches(" re\n").count() > 20);
+        assert!(!pdf.contains("[QR:"));
+
+        // The xref table points at each object
+        let xref = pdf.find("\nxref\n").unwrap();
+        let first = pdf[xref..].lines().nth(4).unwrap();
+        let offset: usize = first[..10].parse().unwrap();
+        assert!(bytes[offset..].starts_with(b"1 0 obj"));
+    }
+
+    #[test]
+    fn test_encoded_job_gives_same_pdf() {
+        let commands = vec![
+            PrintCommand::Init,
+            PrintCommand::Size(2, 2),
+            PrintCommand::Reverse(true),
+            PrintCommand::WriteLine("TOTAL".to_string()),
+            PrintCommand::Reverse(false),
+            PrintCommand::Size(1, 1),
+            PrintCommand::Underline(true),
+            PrintCommand::Write("Paid ".to_string()),
+            PrintCommand::Underline(false),
+            PrintCommand::WriteLine("in full".to_string()),
+            PrintCommand::Align("right".to_string()),
+            PrintCommand::QRCode {
+                content: "42".to_string(),
+                size: 6,
+                error_correction: QrErrorCorrection::H,
+            },
+            PrintCommand::Barcode {
+                content: "ORDER-42".to_string(),
+                format: "CODE39".to_string(),
+                height: 100,
+                width: 3,
+                hri: HriPosition::Both,
+            },
+            PrintCommand::Feed(3),
+            PrintCommand::Cut,
+        ];
+        assert_eq!(
+            escpos_to_pdf(&encode_commands(&commands)),
+            render_pdf(&commands)
+        );
+    }
+
+    #[test]
+    fn test_raster_image_becomes_image_mask() {
+        // 16 dots wide, 2 rows
+        let mut image = vec![0x1D, 0x76, 0x30, 0x00, 2, 0, 2, 0];
+        image.extend_from_slice(&[0xF0, 0x0F, 0xFF, 0x00]);
+        let pdf = text(&render_pdf(&[PrintCommand::Image(image)]));
+        assert!(pdf.contains("/Width 16 /Height 2 /ImageMask true"));
+        assert!(pdf.contains("q 16 0 0 2 32 "));
+        assert!(pdf.contains("/XObject << /Im1 "));
+    }
+
+    #[test]
+    fn test_backend_saves_one_pdf_per_job() {
+        let dir = std::env::temp_dir().join(format!("nexora-pdf-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let printer = PdfBackend::new(dir.to_str().unwrap()).unwrap();
+        printer.query_status().unwrap();
+        assert!(printer.takes_commands());
+        assert!(printer.open().unwrap().is_none());
+
+        printer
+            .write_commands(&[PrintCommand::WriteLine("Hello".to_string())])
+            .unwrap();
+        printer.write_all(b"\x1b@Raw\n\x1dV\x01").unwrap();
+
+        let mut saved: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                assert_eq!(path.extension().unwrap(), "pdf");
+                text(&std::fs::read(&path).unwrap())
+            })
+            .collect();
+        saved.sort();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(saved.len(), 2);
+        assert!(saved.iter().any(|pdf| pdf.contains("(Hello) Tj")));
+        assert!(saved.iter().any(|pdf| pdf.contains("(Raw) Tj")));
+        assert_eq!(
+            PdfBackend::new("").unwrap_err(),
+            "PDF output needs a folder to save jobs in"
+        );
+    }
+}
@@ -0,0 +1,192 @@
+// src/png_preview.rs
+// Print jobs drawn as PNG images, for previews that look like the receipt
+
+use std::io::Cursor;
+
+use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+
+use crate::pdf::{layout, line_scale, Block, Run, CHAR_HEIGHT, CHAR_WIDTH};
+use crate::raster_text::draw_line;
+use crate::template_render::PrintCommand;
+
+/// Blank paper around the printed area, in dots
+const MARGIN: u32 = 16;
+/// Paper shown between two receipts where they are cut apart
+const CUT_GAP: u32 = 24;
+const PAPER: Luma<u8> = Luma([255]);
+const INK: Luma<u8> = Luma([0]);
+/// The dashed line where the paper is cut
+const CUT_LINE: Luma<u8> = Luma([160]);
+
+/// Draw rendered print commands as a PNG of the receipt, one pixel per dot
+/// of a 203 dpi printer and `paper_width` font A columns wide.
+///
+/// Text is drawn with the raster text font, one cell per column, and QR
+/// codes, barcodes and images as the printer prints them. A dashed line
+/// marks each cut.
+pub fn render_png(commands: &[PrintCommand], paper_width: u32) -> Vec<u8> {
+    let pages = layout(commands);
+    let widest = pages.iter().flatten().map(Block::width).max().unwrap_or(0);
+    let printable = (paper_width * CHAR_WIDTH).max(widest);
+    let printed: u32 = pages.iter().flatten().map(Block::height).sum();
+    let cuts = pages.len().saturating_sub(1) as u32;
+    let mut image = GrayImage::from_pixel(
+        printable + 2 * MARGIN,
+        printed + cuts * CUT_GAP + 2 * MARGIN,
+        PAPER,
+    );
+
+    let mut top = MARGIN;
+    for (i, blocks) in pages.iter().enumerate() {
+        if i > 0 {
+            let y = top + CUT_GAP / 2;
+            for x in (0..image.width()).filter(|x| x % 12 < 6) {
+                image.put_pixel(x, y, CUT_LINE);
+            }
+            top += CUT_GAP;
+        }
+        for block in blocks {
+            draw_block(&mut image, block, top, printable);
+            top += block.height();
+        }
+    }
+
+    let mut png = Vec::new();
+    DynamicImage::ImageLuma8(image)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .expect("a PNG can be written to memory");
+    png
+}
+
+fn draw_block(image: &mut GrayImage, block: &Block, top: u32, printable: u32) {
+    match block {
+        Block::Line { runs, align } => {
+            let scale = line_scale(runs);
+            let mut x = MARGIN + align.offset(block.width(), printable);
+            for run in runs {
+                // Smaller text sits on the bottom of the tallest cells
+                draw_run(
+                    image,
+                    run,
+                    x,
+                    top + CHAR_HEIGHT * (scale - run.style.height),
+                );
+                x += run.width();
+            }
+        }
+        Block::Raster {
+            bytes_per_row,
+            data,
+            ..
+        } => {
+            for (x, y) in set_dots(*bytes_per_row, data) {
+                fill(image, MARGIN + x, top + y, 1, 1, INK);
+            }
+        }
+        Block::Shapes {
+            width,
+            rects,
+            align,
+            ..
+        } => {
+            let left = MARGIN + align.offset(*width, printable);
+            for &(x, y, w, h) in rects {
+                fill(image, left + x, top + y, w, h, INK);
+            }
+        }
+    }
+}
+
+/// Draw one run of text whose character cells start at `top`
+fn draw_run(image: &mut GrayImage, run: &Run, left: u32, top: u32) {
+    let style = run.style;
+    let cell_height = CHAR_HEIGHT * style.height;
+    let ink = if style.reverse {
+        fill(image, left, top, run.width(), cell_height, INK);
+        PAPER
+    } else {
+        INK
+    };
+
+    let (bytes_per_row, dots) = draw_line(&run.text, style.font.char_width());
+    // Bold and double-strike text is a dot thicker
+    let strokes = if style.bold || style.double_strike {
+        2
+    } else {
+        1
+    };
+    for (x, y) in set_dots(bytes_per_row, &dots) {
+        for stroke in 0..strokes {
+            fill(
+                image,
+                left + (x + stroke) * style.width,
+                top + y * style.height,
+                style.width,
+                style.height,
+                ink,
+            );
+        }
+    }
+    if style.underline {
+        fill(image, left, top + cell_height - 2, run.width(), 2, ink);
+    }
+}
+
+/// Where the dots are in 1-bit rows, most significant bit leftmost
+fn set_dots(bytes_per_row: u32, data: &[u8]) -> impl Iterator<Item = (u32, u32)> + '_ {
+    data.iter().enumerate().flat_map(move |(i, &byte)| {
+        let x = (i as u32 % bytes_per_row) * 8;
+        let y = i as u32 / bytes_per_row;
+        (0..8)
+            .filter(move |bit| byte & (0x80 >> bit) != 0)
+            .map(move |bit| (x + bit, y))
+    })
+}
+
+/// Paint a rectangle, cut off at the edges of the image
+fn fill(image: &mut GrayImage, x: u32, y: u32, width: u32, height: u32, color: Luma<u8>) {
+    for y in y..(y + height).min(image.height()) {
+        for x in x..(x + width).min(image.width()) {
+            image.put_pixel(x, y, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template_render::QrErrorCorrection;
+
+    #[test]
+    fn test_receipt_is_drawn_at_paper_width() {
+        let commands = [
+            PrintCommand::Init,
+            PrintCommand::Reverse(true),
+            PrintCommand::WriteLine("  ".to_string()),
+            PrintCommand::Reverse(false),
+            PrintCommand::WriteLine("Latte".to_string()),
+            PrintCommand::QRCode {
+                content: "1001".to_string(),
+                size: 4,
+                error_correction: QrErrorCorrection::M,
+            },
+            PrintCommand::Cut,
+            PrintCommand::WriteLine("Second".to_string()),
+        ];
+        let png = render_png(&commands, 32);
+        let image = image::load_from_memory(&png).unwrap().to_luma8();
+
+        // Two lines and a 21 module QR code with its quiet zone, a cut, then
+        // another line
+        assert_eq!(image.width(), 32 * 12 + 2 * MARGIN);
+        assert_eq!(image.height(), 30 + 30 + 29 * 4 + CUT_GAP + 30 + 2 * MARGIN);
+        // The reversed spaces are solid
+        assert_eq!(*image.get_pixel(MARGIN + 5, MARGIN + 5), INK);
+        assert_eq!(*image.get_pixel(MARGIN + 30, MARGIN + 5), PAPER);
+        // The word is inked
+        let inked = (MARGIN..MARGIN + 60)
+            .any(|x| (MARGIN + 30..MARGIN + 54).any(|y| *image.get_pixel(x, y) == INK));
+        assert!(inked);
+        assert!(image.pixels().any(|pixel| *pixel == CUT_LINE));
+    }
+}
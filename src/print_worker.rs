@@ -0,0 +1,414 @@
+// src/print_worker.rs
+// Dedicated thread that owns all blocking printer I/O
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::spool::Spool;
+use crate::PrintOutput;
+use crossbeam_channel::RecvTimeoutError;
+use tokio::sync::oneshot;
+
+/// How long an aborted job gets to stop after the drain timeout
+pub const ABORT_GRACE: Duration = Duration::from_secs(2);
+
+struct Job {
+    output: PrintOutput,
+    reply: oneshot::Sender<Result<(), String>>,
+}
+
+enum Message {
+    Job(Job),
+    /// Sent by `shutdown`; everything queued ahead of it is spooled
+    Stop,
+}
+
+/// Serializes print jobs onto a single OS thread.
+///
+/// HTTP handlers render a job while briefly holding the manager lock, then
+/// hand the encoded bytes to the worker and await the result. The tokio
+/// runtime never blocks on the printer, and the manager lock is free for
+/// status queries while a slow job is being written.
+#[derive(Clone)]
+pub struct PrintWorker {
+    sender: crossbeam_channel::Sender<Message>,
+    /// Kept to collect jobs the worker never reached during shutdown
+    receiver: crossbeam_channel::Receiver<Message>,
+    /// Disconnects when the worker thread exits
+    stopped: crossbeam_channel::Receiver<()>,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    stopping: AtomicBool,
+    abort: AtomicBool,
+    spool: Option<Spool>,
+    spooled: AtomicUsize,
+    lost: AtomicUsize,
+}
+
+/// What happened to the jobs left when the worker was shut down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// The job being printed outlasted the drain timeout and was aborted
+    pub timed_out: bool,
+    /// The worker thread had exited by the time shutdown returned
+    pub stopped: bool,
+    /// Queued jobs saved to the spool
+    pub spooled: usize,
+    /// Queued jobs that couldn't be saved
+    pub lost: usize,
+}
+
+impl PrintWorker {
+    /// Start a worker that drops jobs still queued at shutdown
+    pub fn spawn() -> Self {
+        Self::start(None)
+    }
+
+    /// Start a worker that saves jobs still queued at shutdown to `spool`
+    pub fn spawn_with_spool(spool: Spool) -> Self {
+        Self::start(Some(spool))
+    }
+
+    fn start(spool: Option<Spool>) -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded::<Message>();
+        let (running, stopped) = crossbeam_channel::bounded::<()>(0);
+        let shared = Arc::new(Shared {
+            stopping: AtomicBool::new(false),
+            abort: AtomicBool::new(false),
+            spool,
+            spooled: AtomicUsize::new(0),
+            lost: AtomicUsize::new(0),
+        });
+
+        let jobs = receiver.clone();
+        let worker = Arc::clone(&shared);
+        std::thread::Builder::new()
+            .name("print-worker".to_string())
+            .spawn(move || {
+                for message in jobs {
+                    let job = match message {
+                        Message::Job(job) => job,
+                        Message::Stop => break,
+                    };
+                    if worker.stopping.load(Ordering::SeqCst) {
+                        worker.spool(job);
+                        continue;
+                    }
+                    let result = job.output.send_or_abort(&worker.abort);
+                    if let Err(e) = &result {
+                        log::error!("Print job failed: {}", e);
+                    }
+                    // The caller may have gone away (e.g. client disconnected)
+                    let _ = job.reply.send(result);
+                }
+                drop(running);
+                log::info!("Print worker stopped");
+            })
+            .expect("Failed to spawn print worker thread");
+
+        Self {
+            sender,
+            receiver,
+            stopped,
+            shared,
+        }
+    }
+
+    /// Where jobs still queued at shutdown are saved, if anywhere
+    pub fn spool(&self) -> Option<&Spool> {
+        self.shared.spool.as_ref()
+    }
+
+    /// False once shutdown has started
+    pub fn is_accepting(&self) -> bool {
+        !self.shared.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Queue an encoded job and wait for the printer write to complete.
+    pub async fn submit(&self, output: PrintOutput) -> Result<(), String> {
+        if !self.is_accepting() {
+            return Err("Print server is shutting down".to_string());
+        }
+        let (reply, result) = oneshot::channel();
+        self.sender
+            .send(Message::Job(Job { output, reply }))
+            .map_err(|_| "Print worker is not running".to_string())?;
+        result
+            .await
+            .map_err(|_| "Print worker stopped before finishing the job".to_string())?
+    }
+
+    /// Stop taking jobs, let the one being printed finish and spool the rest.
+    ///
+    /// A job still printing after `drain_timeout` is aborted (see
+    /// [`PrintOutput::send_or_abort`]) and given [`ABORT_GRACE`] to stop, so
+    /// this returns within `drain_timeout + ABORT_GRACE`. Blocks the calling
+    /// thread.
+    pub fn shutdown(&self, drain_timeout: Duration) -> ShutdownReport {
+        self.shared.stopping.store(true, Ordering::SeqCst);
+        let _ = self.sender.send(Message::Stop);
+
+        let mut report = ShutdownReport::default();
+        report.stopped = match self.stopped.recv_timeout(drain_timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                log::warn!(
+                    "Print job still running after {:?}, aborting it",
+                    drain_timeout
+                );
+                report.timed_out = true;
+                self.shared.abort.store(true, Ordering::SeqCst);
+                !matches!(
+                    self.stopped.recv_timeout(ABORT_GRACE),
+                    Err(RecvTimeoutError::Timeout)
+                )
+            }
+            _ => true,
+        };
+        if !report.stopped {
+            log::error!("Print worker didn't stop; leaving its job unfinished");
+        }
+
+        // Whatever the worker didn't get to
+        while let Ok(message) = self.receiver.try_recv() {
+            if let Message::Job(job) = message {
+                self.shared.spool(job);
+            }
+        }
+
+        report.spooled = self.shared.spooled.load(Ordering::SeqCst);
+        report.lost = self.shared.lost.load(Ordering::SeqCst);
+        report
+    }
+}
+
+impl Shared {
+    /// Save a job that won't be printed this run and tell its caller
+    fn spool(&self, job: Job) {
+        let saved = match &self.spool {
+            Some(spool) => job.output.into_spooled().and_then(|job| spool.save(&job)),
+            None => Err("no spool configured".to_string()),
+        };
+        let reply = match saved {
+            Ok(path) => {
+                self.spooled.fetch_add(1, Ordering::SeqCst);
+                log::info!("Saved queued print job to {}", path.display());
+                "Print server shut down before the job printed; it will print on next start"
+                    .to_string()
+            }
+            Err(e) => {
+                self.lost.fetch_add(1, Ordering::SeqCst);
+                log::error!("Dropped queued print job at shutdown: {}", e);
+                format!("Print server shut down before the job printed: {}", e)
+            }
+        };
+        let _ = job.reply.send(Err(reply));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lock::LockExt;
+    use crate::pdf::PdfBackend;
+    use crate::printer::{CLEAR_BUFFER, DEFAULT_PRINTER};
+    use crate::spool::SpooledPayload;
+    use crate::template_render::load_template;
+    use crate::{MockBackend, PrinterManager, ReceiptData};
+    use std::sync::Mutex;
+
+    fn manager_with(printer: &MockBackend) -> PrinterManager {
+        let mut manager = PrinterManager::new();
+        manager.connect_backend(DEFAULT_PRINTER, None, Arc::new(printer.clone()));
+        manager
+    }
+
+    fn test_spool(name: &str) -> Spool {
+        let dir = std::env::temp_dir().join(format!(
+            "nexora-spool-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        Spool::new(dir)
+    }
+
+    fn job(manager: &PrinterManager, order_id: &str) -> PrintOutput {
+        manager
+            .prepare_bytes(format!("order {}\n", order_id).into_bytes())
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_shutdown_drains_current_job_then_spools_queued() {
+        let printer = MockBackend::new()
+            .with_delay(Duration::from_millis(300))
+            .whole_jobs();
+        let manager = manager_with(&printer);
+        let spool = test_spool("drain");
+        let worker = PrintWorker::spawn_with_spool(spool.clone());
+
+        let jobs: Vec<_> = ["1", "2", "3"]
+            .into_iter()
+            .map(|id| {
+                let worker = worker.clone();
+                let output = job(&manager, id);
+                tokio::spawn(async move { worker.submit(output).await })
+            })
+            .collect();
+        // Let the first job reach the (slow) printer
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let stopping = worker.clone();
+        let report = tokio::task::spawn_blocking(move || stopping.shutdown(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            ShutdownReport { timed_out: false, stopped: true, spooled: 2, lost: 0 }
+        );
+
+        let mut results = Vec::new();
+        for job in jobs {
+            results.push(job.await.unwrap());
+        }
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(results
+            .iter()
+            .filter_map(|r| r.as_ref().err())
+            .all(|e| e.contains("will print on next start")));
+        assert_eq!(printer.jobs(), 1);
+        assert_eq!(spool.pending().len(), 2);
+
+        assert!(!worker.is_accepting());
+        let late = worker.submit(job(&manager, "4")).await;
+        assert_eq!(late.unwrap_err(), "Print server is shutting down");
+
+        // The next run prints what was saved, in order
+        let next_printer = MockBackend::new();
+        let next = Mutex::new(manager_with(&next_printer));
+        assert_eq!(spool.replay(&next).unwrap(), 2);
+        let mut expected = printer.written();
+        expected.extend_from_slice(&next_printer.written());
+        assert_eq!(expected, b"order 1\norder 2\norder 3\n");
+        assert!(spool.pending().is_empty());
+        let _ = std::fs::remove_dir_all(spool.dir());
+    }
+
+    fn order_template() -> crate::ReceiptTemplate {
+        load_template(
+            r#"{
+                "id": "order", "name": "Order", "version": "1.0", "paper_width": 32,
+                "layout": {"sections": [{"type": "body", "elements": [
+                    {"type": "text", "content": "order {{order_id}}"}
+                ]}]}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_spooled_jobs_replay_on_the_printer_they_were_for() {
+        let mut manager = manager_with(&MockBackend::new());
+        manager.connect_backend("kitchen", None, Arc::new(MockBackend::new()));
+        manager.set_template(order_template(), false).unwrap();
+        let data = ReceiptData {
+            order_id: "7".to_string(),
+            ..Default::default()
+        };
+        let spool = test_spool("route");
+        let snapshot = manager.snapshot_for("kitchen").unwrap();
+        let ticket = snapshot.render(&data).unwrap().into_spooled().unwrap();
+        spool.save(&ticket).unwrap();
+        let receipt = job(&manager, "8").into_spooled().unwrap();
+        spool.save(&receipt).unwrap();
+
+        // The kitchen printer isn't back yet, so only its job waits
+        let front = MockBackend::new();
+        let next = Mutex::new(manager_with(&front));
+        assert_eq!(spool.replay(&next).unwrap(), 1);
+        assert_eq!(front.written(), b"order 8\n");
+        assert_eq!(spool.pending().len(), 1);
+
+        let kitchen = MockBackend::new();
+        next.lock_or_recover()
+            .connect_backend("kitchen", None, Arc::new(kitchen.clone()));
+        assert_eq!(spool.replay(&next).unwrap(), 1);
+        assert!(String::from_utf8_lossy(&kitchen.written()).contains("order 7"));
+        assert_eq!(front.written(), b"order 8\n");
+        assert!(spool.pending().is_empty());
+        let _ = std::fs::remove_dir_all(spool.dir());
+    }
+
+    #[test]
+    fn test_spooled_jobs_for_a_pdf_printer_stay_commands() {
+        let folder = std::env::temp_dir().join(format!("nexora-spool-pdf-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        let pdf = || Arc::new(PdfBackend::new(folder.to_str().unwrap()).unwrap());
+        let mut manager = manager_with(&MockBackend::new());
+        manager.connect_backend("pdf", None, pdf());
+        manager.set_template(order_template(), false).unwrap();
+        let data = ReceiptData {
+            order_id: "7".to_string(),
+            ..Default::default()
+        };
+        let spool = test_spool("pdf");
+        let output = manager.snapshot_for("pdf").unwrap().render(&data).unwrap();
+        let saved = output.into_spooled().unwrap();
+        assert_eq!(saved.printer, "pdf");
+        assert!(matches!(saved.payload, SpooledPayload::Commands(_)));
+        spool.save(&saved).unwrap();
+
+        let mut next = manager_with(&MockBackend::new());
+        next.connect_backend("pdf", None, pdf());
+        assert_eq!(spool.replay(&Mutex::new(next)).unwrap(), 1);
+        let saved: Vec<_> = std::fs::read_dir(&folder).unwrap().collect();
+        assert_eq!(saved.len(), 1);
+        let _ = std::fs::remove_dir_all(&folder);
+        let _ = std::fs::remove_dir_all(spool.dir());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_shutdown_aborts_streamed_job_after_drain_timeout() {
+        let printer = MockBackend::new().with_delay(Duration::from_millis(50));
+        let mut manager = manager_with(&printer);
+        let template = load_template(
+            r#"{
+                "id": "report", "name": "Report", "version": "1.0", "paper_width": 48,
+                "layout": {"sections": [{"type": "body", "elements": [
+                    {"type": "table", "data_source": "rows",
+                     "columns": [{"field": "name", "width": 40}]}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+        manager.set_template(template, false).unwrap();
+        let rows: Vec<_> = (0..5000)
+            .map(|i| serde_json::json!({ "name": format!("row {}", i) }))
+            .collect();
+        let data: ReceiptData = serde_json::from_value(
+            serde_json::json!({ "order_id": "1", "timestamp": "", "rows": rows }),
+        )
+        .unwrap();
+
+        let worker = PrintWorker::spawn();
+        let output = manager.prepare_template_stream(data).unwrap();
+        let submitted = worker.clone();
+        let print = tokio::spawn(async move { submitted.submit(output).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let stopping = worker.clone();
+        let report =
+            tokio::task::spawn_blocking(move || stopping.shutdown(Duration::from_millis(200)))
+                .await
+                .unwrap();
+        assert!(report.timed_out);
+        assert!(report.stopped);
+
+        assert_eq!(print.await.unwrap().unwrap_err(), "Print job aborted for shutdown");
+        assert!(printer.written().ends_with(&CLEAR_BUFFER));
+        assert_eq!(manager.print_stats().failures.aborted, 1);
+    }
+}
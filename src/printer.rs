@@ -0,0 +1,1887 @@
+// src/printer.rs
+// ESC/POS job preparation and the receipt/barcode managers
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::backend::{self, PrintBackend, PrinterState};
+use crate::barcode_printer::{self, BarcodeLabelRequest, BarcodePrinterConfig};
+use crate::codepage;
+use crate::config::PrinterConfig;
+use crate::encoder::{CommandEncoder, PrinterEncoder, Protocol, StreamEncoder};
+use crate::image_print::{self, ImageOptions};
+use crate::label_template::{self, LabelSpec};
+use crate::logo_cache::{self, LogoCacheEntry};
+use crate::optimizer::{optimize, Optimizer};
+use crate::profiles::{self, PrinterProfile};
+use crate::reconnect::{ConnectionState, Reconnecting};
+use crate::routing::RoutingRule;
+use crate::spool::{SpooledJob, SpooledPayload};
+use crate::template_cache::{TemplateCache, TemplateReplacement, VersionConflict};
+use crate::template_compile::{
+    check_conditions, check_layout, CompiledTemplate, ConditionIssue, Strictness,
+    TemplateValidation,
+};
+use crate::telemetry::{FailureClass, PrintStats, PrintTelemetry};
+use crate::template_data::check_data;
+use crate::template_render::{
+    self, CommandSink, PrintCommand, ReceiptData, ReceiptItem, ReceiptTemplate, TemplateRenderer,
+};
+
+// ==================== Legacy Receipt Models ====================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub struct LineItem {
+    pub name: String,
+    pub quantity: u32,
+    pub price: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub struct Receipt {
+    pub order_id: String,
+    pub timestamp: String,
+    pub items: Vec<LineItem>,
+    pub subtotal: f64,
+    pub tax: f64,
+    pub total: f64,
+    pub payment_method: String,
+}
+
+// ==================== Print Snapshots ====================
+
+/// Everything a template print needs from `PrinterManager`, captured when
+/// the job starts.
+///
+/// The template, its compiled form and resolved logos are shared, not looked
+/// up again, so a `set_template`, cache eviction or change of active template
+/// after the snapshot is taken only affects later jobs. Take the snapshot
+/// under the manager lock and render from it after releasing the lock.
+pub struct PrintSnapshot {
+    template: Arc<ReceiptTemplate>,
+    compiled: Arc<CompiledTemplate>,
+    renderer: TemplateRenderer,
+    printer_id: String,
+    connection: Arc<dyn PrintBackend>,
+    encoder: PrinterEncoder,
+    profile: Option<&'static PrinterProfile>,
+    telemetry: Arc<PrintTelemetry>,
+    optimize: bool,
+    data_strictness: Strictness,
+}
+
+impl PrintSnapshot {
+    pub fn template_id(&self) -> &str {
+        &self.template.id
+    }
+
+    /// Check print data against the template's declared variables, applying
+    /// safe coercions (see [`check_data`]).
+    ///
+    /// Returns the mismatches as warnings, or as an error when
+    /// `template_validation` is strict about data.
+    pub fn check_data(&self, data: &mut ReceiptData) -> Result<Vec<String>, String> {
+        let Some(variables) = &self.template.variables else {
+            return Ok(Vec::new());
+        };
+
+        let warnings = check_data(variables, data);
+        if !warnings.is_empty() && self.data_strictness == Strictness::Reject {
+            return Err(format!(
+                "Data doesn't match the template's variables: {}",
+                warnings.join("; ")
+            ));
+        }
+        for warning in &warnings {
+            log::warn!("Order {}: {}", data.order_id, warning);
+        }
+        Ok(warnings)
+    }
+
+    /// Parts of the template left out on this printer's paper width
+    pub fn dropped(&self) -> &[String] {
+        self.compiled.dropped()
+    }
+
+    /// Render and encode the whole job up front
+    pub fn render(self, data: &ReceiptData) -> Result<PrintOutput, String> {
+        let data = match self.profile {
+            Some(profile) if !data.custom.contains_key("printer") => {
+                let mut data = data.clone();
+                with_printer(&mut data, profile);
+                Cow::Owned(data)
+            }
+            _ => Cow::Borrowed(data),
+        };
+        // A renderer bug must fail this job, not unwind through the caller
+        let render = render_guarded(|| self.renderer.render_to_commands(&self.compiled, &data));
+        if let Err(e) = &render {
+            self.telemetry.record_failure(FailureClass::Render, e, 0);
+        }
+        let mut commands = render?;
+        if self.optimize {
+            commands = optimize(commands);
+        }
+        Ok(PrintOutput {
+            printer_id: self.printer_id,
+            payload: PrintPayload::for_connection(
+                self.connection.as_ref(),
+                &self.encoder,
+                commands,
+            ),
+            connection: self.connection,
+            telemetry: self.telemetry,
+        })
+    }
+
+    /// Render while the job is written to the printer, so a job with
+    /// thousands of rows is never held in memory as commands or bytes.
+    ///
+    /// Nothing is rendered until the output is sent. A render error then
+    /// stops the job part way, so prefer [`PrintSnapshot::render`] unless the
+    /// data exceeds [`STREAMING_ROW_THRESHOLD`] rows.
+    pub fn stream(self, mut data: ReceiptData) -> PrintOutput {
+        if let Some(profile) = self.profile {
+            with_printer(&mut data, profile);
+        }
+        PrintOutput {
+            printer_id: self.printer_id,
+            connection: self.connection,
+            telemetry: self.telemetry,
+            payload: PrintPayload::Stream(Box::new(StreamingRender {
+                template: self.compiled,
+                renderer: self.renderer,
+                data,
+                encoder: self.encoder,
+                optimize: self.optimize,
+            })),
+        }
+    }
+}
+
+/// A printer state query bound to the connection it asks
+pub struct StateQuery {
+    connection: Arc<dyn PrintBackend>,
+}
+
+impl StateQuery {
+    /// `None` if the printer has no way to tell or didn't answer. This
+    /// performs blocking I/O.
+    pub fn run(self) -> Result<Option<PrinterState>, String> {
+        self.connection.printer_state()
+    }
+}
+
+/// Let the template read the printer's profile as `printer.*`, unless the
+/// data brings its own `printer`
+fn with_printer(data: &mut ReceiptData, profile: &PrinterProfile) {
+    data.custom
+        .entry("printer".to_string())
+        .or_insert_with(|| profile.template_value());
+}
+
+// ==================== Printer Manager ====================
+
+/// Jobs whose data sources hold more rows than this are rendered while they
+/// are written to the printer instead of being encoded up front
+pub const STREAMING_ROW_THRESHOLD: usize = 1000;
+
+/// A print job bound to the connection it should be written to.
+///
+/// Produced while holding the `PrinterManager` lock, then sent without it so
+/// slow printer I/O never blocks status queries or other callers.
+pub struct PrintOutput {
+    printer_id: String,
+    connection: Arc<dyn PrintBackend>,
+    telemetry: Arc<PrintTelemetry>,
+    payload: PrintPayload,
+}
+
+enum PrintPayload {
+    /// Fully encoded bytes in the printer's protocol
+    Bytes(Vec<u8>),
+    /// Rendered but not encoded, for a backend that lays jobs out itself
+    Commands(Vec<PrintCommand>),
+    /// Rendered and encoded as it is written, for jobs too large to buffer
+    Stream(Box<StreamingRender>),
+}
+
+struct StreamingRender {
+    template: Arc<CompiledTemplate>,
+    renderer: TemplateRenderer,
+    data: ReceiptData,
+    encoder: PrinterEncoder,
+    optimize: bool,
+}
+
+impl PrintPayload {
+    fn for_connection(
+        connection: &dyn PrintBackend,
+        encoder: &PrinterEncoder,
+        commands: Vec<PrintCommand>,
+    ) -> Self {
+        if connection.takes_commands() {
+            PrintPayload::Commands(commands)
+        } else {
+            PrintPayload::Bytes(encoder.encode_all(&commands))
+        }
+    }
+}
+
+/// ESC @: drops whatever is left in the printer's buffer and resets its modes
+pub const CLEAR_BUFFER: [u8; 2] = [0x1B, 0x40];
+
+impl PrintOutput {
+    /// Write the job to the printer. This performs blocking I/O.
+    pub fn send(self) -> Result<(), String> {
+        self.send_or_abort(&AtomicBool::new(false))
+    }
+
+    /// Write the job, giving up once `abort` is set.
+    ///
+    /// Only streamed jobs can stop part way. They stop between the chunks
+    /// they are written in, which always end on a whole command, and are
+    /// followed by [`CLEAR_BUFFER`] so the printer isn't left mid-receipt.
+    /// A job written in one piece always runs to the end.
+    pub fn send_or_abort(self, abort: &AtomicBool) -> Result<(), String> {
+        let written = Arc::new(AtomicU64::new(0));
+        let result = match self.payload {
+            PrintPayload::Bytes(bytes) => self
+                .connection
+                .write_all(&bytes)
+                .map(|()| written.store(bytes.len() as u64, Ordering::Relaxed))
+                .map_err(|e| (FailureClass::Write, e)),
+            // Nothing goes over a printer connection, so no bytes are counted
+            PrintPayload::Commands(commands) => self
+                .connection
+                .write_commands(&commands)
+                .map_err(|e| (FailureClass::Write, e)),
+            PrintPayload::Stream(job) => job.send(self.connection.as_ref(), &written, abort),
+        };
+
+        let written = written.load(Ordering::Relaxed);
+        match result {
+            Ok(()) => {
+                self.telemetry.record_success(written);
+                Ok(())
+            }
+            Err((class, e)) => {
+                self.telemetry.record_failure(class, &e, written);
+                Err(e)
+            }
+        }
+    }
+
+    /// The job and the printer it's for, for keeping it to print later.
+    /// Streamed jobs are rendered in full.
+    pub fn into_spooled(self) -> Result<SpooledJob, String> {
+        let payload = match self.payload {
+            PrintPayload::Bytes(bytes) => SpooledPayload::Bytes(bytes),
+            PrintPayload::Commands(commands) => SpooledPayload::Commands(commands),
+            PrintPayload::Stream(job) if self.connection.takes_commands() => {
+                SpooledPayload::Commands(job.render_commands()?)
+            }
+            PrintPayload::Stream(job) => SpooledPayload::Bytes(job.render_bytes()?),
+        };
+        Ok(SpooledJob {
+            printer: self.printer_id,
+            payload,
+        })
+    }
+}
+
+/// Counts the bytes a streamed job gets through to the printer
+struct CountingWriter<W> {
+    inner: W,
+    written: Arc<AtomicU64>,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl StreamingRender {
+    fn send(
+        self,
+        connection: &dyn PrintBackend,
+        written: &Arc<AtomicU64>,
+        abort: &AtomicBool,
+    ) -> Result<(), (FailureClass, String)> {
+        let writer = connection
+            .open()
+            .map_err(|e| (FailureClass::Connection, e))?;
+        let Some(writer) = writer else {
+            // This output takes the job in one piece, so fall back to buffering it
+            if connection.takes_commands() {
+                let commands = self
+                    .render_commands()
+                    .map_err(|e| (FailureClass::Render, e))?;
+                return connection
+                    .write_commands(&commands)
+                    .map_err(|e| (FailureClass::Write, e));
+            }
+            let bytes = self
+                .render_bytes()
+                .map_err(|e| (FailureClass::Render, e))?;
+            connection
+                .write_all(&bytes)
+                .map_err(|e| (FailureClass::Write, e))?;
+            written.store(bytes.len() as u64, Ordering::Relaxed);
+            return Ok(());
+        };
+
+        let encoder = StreamEncoder::new(CountingWriter {
+            inner: writer,
+            written: Arc::clone(written),
+        })
+        .with_encoder(self.encoder.clone());
+        // Anything still buffered when the render stops is dropped with the
+        // sink, along with the printer handle
+        let rendered = if self.optimize {
+            let mut sink = Optimizer::new(encoder);
+            self.render_into(&mut sink, abort).map(|()| sink.finish())
+        } else {
+            let mut sink = encoder;
+            self.render_into(&mut sink, abort).map(|()| sink)
+        };
+        let encoder = match rendered {
+            Ok(encoder) => encoder,
+            Err((FailureClass::Aborted, e)) => {
+                if let Err(clear) = connection.write_all(&CLEAR_BUFFER) {
+                    log::warn!("Failed to clear the printer after aborting a job: {}", clear);
+                }
+                return Err((FailureClass::Aborted, e));
+            }
+            Err(e) => return Err(e),
+        };
+        encoder
+            .finish()
+            .map(|_| ())
+            .map_err(|e| (FailureClass::Write, e))
+    }
+
+    fn render_commands(&self) -> Result<Vec<PrintCommand>, String> {
+        let commands =
+            render_guarded(|| self.renderer.render_to_commands(&self.template, &self.data))?;
+        Ok(if self.optimize {
+            optimize(commands)
+        } else {
+            commands
+        })
+    }
+
+    fn render_bytes(&self) -> Result<Vec<u8>, String> {
+        Ok(self.encoder.encode_all(&self.render_commands()?))
+    }
+
+    fn render_into(
+        &self,
+        sink: &mut dyn CommandSink,
+        abort: &AtomicBool,
+    ) -> Result<(), (FailureClass, String)> {
+        let mut sink = AbortableSink { inner: sink, abort };
+        let render = render_guarded(|| {
+            self.renderer
+                .render_to_sink(&self.template, &self.data, &mut sink)
+        });
+        render.map_err(|e| {
+            log::error!(
+                "Streaming print of order {} stopped part way: {}",
+                self.data.order_id,
+                e
+            );
+            // The sink only reports an error once the printer write has failed
+            let class = if abort.load(Ordering::SeqCst) {
+                FailureClass::Aborted
+            } else if sink.inner.check().is_err() {
+                FailureClass::Write
+            } else {
+                FailureClass::Render
+            };
+            (class, e)
+        })
+    }
+}
+
+/// Stops a streamed render at its next check once `abort` is set
+struct AbortableSink<'a> {
+    inner: &'a mut dyn CommandSink,
+    abort: &'a AtomicBool,
+}
+
+impl CommandSink for AbortableSink<'_> {
+    fn push(&mut self, command: template_render::PrintCommand) {
+        self.inner.push(command);
+    }
+
+    fn check(&mut self) -> Result<(), String> {
+        if self.abort.load(Ordering::SeqCst) {
+            return Err("Print job aborted for shutdown".to_string());
+        }
+        self.inner.check()
+    }
+}
+
+/// Run a render, turning a renderer panic into an error for this job only
+fn render_guarded<T>(render: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(render)).map_err(|panic| {
+        let reason = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_string());
+        log::error!("Template rendering panicked: {}", reason);
+        format!("Template rendering failed: {}", reason)
+    })?
+}
+
+/// Why `PrinterManager::set_template` refused a template
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetTemplateError {
+    /// An older version would replace a newer cached one
+    VersionConflict(VersionConflict),
+    /// Some conditions don't parse and conditions are set to reject
+    InvalidConditions {
+        template_id: String,
+        issues: Vec<ConditionIssue>,
+    },
+}
+
+impl std::fmt::Display for SetTemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SetTemplateError::VersionConflict(conflict) => conflict.fmt(f),
+            SetTemplateError::InvalidConditions { template_id, issues } => write!(
+                f,
+                "Template '{}' has {} invalid condition{}",
+                template_id,
+                issues.len(),
+                if issues.len() == 1 { "" } else { "s" }
+            ),
+        }
+    }
+}
+
+// ==================== Printer Registry ====================
+
+/// Id of the printer that the single-printer API (`connect`, `snapshot`,
+/// `prepare_test`, ...) works with
+pub const DEFAULT_PRINTER: &str = "default";
+
+/// One named printer in the manager's registry
+#[derive(Default)]
+pub(crate) struct RegisteredPrinter {
+    /// Kept after a disconnect, so the printer can be connected again
+    pub(crate) config: Option<PrinterConfig>,
+    pub(crate) connection: Option<Arc<dyn PrintBackend>>,
+    /// Set while the reconnect supervisor retries a dropped printer
+    pub(crate) reconnecting: Option<Reconnecting>,
+}
+
+impl RegisteredPrinter {
+    fn state(&self) -> ConnectionState {
+        match (&self.reconnecting, &self.connection) {
+            (Some(reconnecting), _) => reconnecting.state(),
+            (None, Some(_)) => ConnectionState::Connected,
+            (None, None) => ConnectionState::Disconnected,
+        }
+    }
+}
+
+/// A registered printer, as listed by [`PrinterManager::printers`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PrinterInfo {
+    pub id: String,
+    pub connection_type: Option<String>,
+    pub device_path: Option<String>,
+    pub roles: Vec<String>,
+    /// Id of the model profile in use, see [`PrinterManager::profile`]
+    pub profile: Option<String>,
+    pub connected: bool,
+    pub connection_state: ConnectionState,
+}
+
+fn not_connected(printer_id: &str) -> String {
+    if printer_id == DEFAULT_PRINTER {
+        "Printer not connected".to_string()
+    } else {
+        format!("Printer '{}' not connected", printer_id)
+    }
+}
+
+/// Receipt printers and everything shared between them: templates, logos,
+/// print settings and job counters.
+///
+/// Printers are registered under an id, so one instance can drive e.g. a
+/// front-desk printer and a kitchen printer. Methods without a printer id
+/// work with [`DEFAULT_PRINTER`].
+pub struct PrinterManager {
+    pub(crate) printers: BTreeMap<String, RegisteredPrinter>,
+    pub template_cache: TemplateCache,
+    pub template_validation: TemplateValidation,
+    /// Strip redundant style commands before encoding; turn off with
+    /// `--no-optimize` to see exactly what the renderer produced
+    pub optimize_commands: bool,
+    pub active_template_id: Option<String>,
+    pub logo_cache: std::collections::HashMap<String, LogoCacheEntry>,
+    pub logo_cache_path: String,
+    /// Where items of a template print also go, by printer role
+    pub routing_rules: Vec<RoutingRule>,
+    /// Connection uptime is that of the default printer
+    telemetry: Arc<PrintTelemetry>,
+}
+
+impl Default for PrinterManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrinterManager {
+    pub fn new() -> Self {
+        Self {
+            printers: BTreeMap::new(),
+            template_cache: TemplateCache::default(),
+            template_validation: TemplateValidation::default(),
+            optimize_commands: true,
+            active_template_id: None,
+            logo_cache: std::collections::HashMap::new(),
+            logo_cache_path: directories::ProjectDirs::from("com", "nexora", "printer-manager")
+                .map(|d| d.data_local_dir().join("cache").join("logos"))
+                .unwrap_or_else(|| std::path::PathBuf::from("cache").join("logos"))
+                .to_string_lossy()
+                .into_owned(),
+            routing_rules: Vec::new(),
+            telemetry: Arc::new(PrintTelemetry::new()),
+        }
+    }
+
+    pub fn connect(&mut self, config: PrinterConfig) -> Result<(), String> {
+        self.connect_printer(DEFAULT_PRINTER, config)
+    }
+
+    /// Connect the printer registered as `printer_id`, registering it if it
+    /// is new and replacing its connection if it was already connected
+    pub fn connect_printer(
+        &mut self,
+        printer_id: &str,
+        config: PrinterConfig,
+    ) -> Result<(), String> {
+        if printer_id.trim().is_empty() {
+            return Err("Printer id must not be empty".to_string());
+        }
+        log::info!(
+            "Connecting printer '{}' to {} at {}",
+            printer_id,
+            config.connection_type,
+            config.device_path
+        );
+        if let Some(profile) =
+            profiles::resolve_profile(config.profile.as_deref(), &config.device_path)?
+        {
+            log::info!("Printer '{}' uses the {} profile", printer_id, profile.name);
+        }
+
+        if let Some(pacing) = &config.write_pacing {
+            pacing.validate()?;
+        }
+        let mut connection = backend::connect(
+            &config.connection_type,
+            &config.device_path,
+            &config.serial,
+            &config.timeouts,
+            "Nexora Receipt",
+        )?;
+        if let Some(pacing) = config.write_pacing {
+            connection = backend::paced(connection, pacing);
+        }
+        self.connect_backend(printer_id, Some(config), connection);
+        log::info!("Printer '{}' connected successfully", printer_id);
+        Ok(())
+    }
+
+    /// Register `connection` as the printer `printer_id`, e.g. a backend this
+    /// crate doesn't provide
+    pub fn connect_backend(
+        &mut self,
+        printer_id: &str,
+        config: Option<PrinterConfig>,
+        connection: Arc<dyn PrintBackend>,
+    ) {
+        let printer = self.printers.entry(printer_id.to_string()).or_default();
+        printer.config = config;
+        printer.connection = Some(connection);
+        printer.reconnecting = None;
+        if printer_id == DEFAULT_PRINTER {
+            self.telemetry.connection_opened();
+        }
+    }
+
+    pub fn disconnect(&mut self) {
+        self.disconnect_printer(DEFAULT_PRINTER);
+    }
+
+    /// Close the connection to `printer_id`, keeping its settings. `false` if
+    /// no such printer is registered.
+    pub fn disconnect_printer(&mut self, printer_id: &str) -> bool {
+        if printer_id == DEFAULT_PRINTER {
+            self.telemetry.connection_closed();
+        }
+        let Some(printer) = self.printers.get_mut(printer_id) else {
+            return false;
+        };
+        if let Some(connection) = printer.connection.take() {
+            connection.close();
+        }
+        if let Some(reconnecting) = printer.reconnecting.take() {
+            reconnecting.close();
+        }
+        log::info!("Printer '{}' disconnected", printer_id);
+        true
+    }
+
+    /// Take a printer that stopped answering out of service until the
+    /// reconnect supervisor brings it back
+    pub(crate) fn drop_connection(&mut self, printer_id: &str, reconnecting: Reconnecting) {
+        if let Some(printer) = self.printers.get_mut(printer_id) {
+            printer.connection = None;
+            printer.reconnecting = Some(reconnecting);
+        }
+        if printer_id == DEFAULT_PRINTER {
+            self.telemetry.connection_closed();
+        }
+    }
+
+    pub(crate) fn restore_connection(
+        &mut self,
+        printer_id: &str,
+        connection: Arc<dyn PrintBackend>,
+    ) {
+        if let Some(printer) = self.printers.get_mut(printer_id) {
+            printer.connection = Some(connection);
+            printer.reconnecting = None;
+        }
+        if printer_id == DEFAULT_PRINTER {
+            self.telemetry.connection_opened();
+        }
+    }
+
+    /// Every registered printer, connected or not, by id
+    pub fn printers(&self) -> Vec<PrinterInfo> {
+        self.printers
+            .iter()
+            .map(|(id, printer)| PrinterInfo {
+                id: id.clone(),
+                connection_type: printer.config.as_ref().map(|c| c.connection_type.clone()),
+                device_path: printer.config.as_ref().map(|c| c.device_path.clone()),
+                roles: printer
+                    .config
+                    .as_ref()
+                    .map(|c| c.roles.clone())
+                    .unwrap_or_default(),
+                profile: self.profile(id).map(|profile| profile.id.to_string()),
+                connected: printer.connection.is_some(),
+                connection_state: printer.state(),
+            })
+            .collect()
+    }
+
+    /// Settings the default printer was last connected with
+    pub fn config(&self) -> Option<&PrinterConfig> {
+        self.printer_config(DEFAULT_PRINTER)
+    }
+
+    pub fn printer_config(&self, printer_id: &str) -> Option<&PrinterConfig> {
+        self.printers.get(printer_id)?.config.as_ref()
+    }
+
+    /// The command language `printer_id` speaks: as configured, else from
+    /// its profile, else ESC/POS
+    pub fn protocol(&self, printer_id: &str) -> Protocol {
+        self.printer_config(printer_id)
+            .and_then(|config| config.protocol)
+            .or_else(|| self.profile(printer_id).map(|profile| profile.protocol))
+            .unwrap_or_default()
+    }
+
+    /// The protocol and character sets text for `printer_id` is encoded
+    /// with. Code pages come from its config, else its profile. QR codes
+    /// are sent as images if its profile says it can't draw them. Density
+    /// and speed come from its config.
+    pub fn encoder(&self, printer_id: &str) -> PrinterEncoder {
+        let config = self.printer_config(printer_id);
+        let code_pages = match config {
+            Some(config) if !config.code_pages.is_empty() => config.code_pages.clone(),
+            _ => self
+                .profile(printer_id)
+                .map(|profile| codepage::from_names(profile.code_pages))
+                .unwrap_or_default(),
+        };
+        PrinterEncoder::new(self.protocol(printer_id), code_pages)
+            .with_cjk(config.and_then(|config| config.cjk_encoding))
+            .with_raster_text(config.is_some_and(|config| config.raster_text))
+            .with_native_qr(
+                self.profile(printer_id)
+                    .is_none_or(|profile| profile.native_qr),
+            )
+            .with_print_settings(
+                config.and_then(|config| config.density),
+                config.and_then(|config| config.print_speed),
+            )
+    }
+
+    /// The model profile `printer_id` was configured with or detected as
+    pub fn profile(&self, printer_id: &str) -> Option<&'static PrinterProfile> {
+        let config = self.printer_config(printer_id)?;
+        profiles::resolve_profile(config.profile.as_deref(), &config.device_path)
+            .ok()
+            .flatten()
+    }
+
+    /// Connected, disconnected, or waiting to retry a printer that dropped
+    pub fn connection_state(&self) -> ConnectionState {
+        self.printer_connection_state(DEFAULT_PRINTER)
+    }
+
+    pub fn printer_connection_state(&self, printer_id: &str) -> ConnectionState {
+        self.printers
+            .get(printer_id)
+            .map_or(ConnectionState::Disconnected, RegisteredPrinter::state)
+    }
+
+    /// Check that the connected printer can be reached, without printing.
+    /// This performs blocking I/O.
+    pub fn query_status(&self) -> Result<(), String> {
+        self.connection(DEFAULT_PRINTER)
+            .ok_or_else(|| not_connected(DEFAULT_PRINTER))?
+            .query_status()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.is_printer_connected(DEFAULT_PRINTER)
+    }
+
+    pub fn is_printer_connected(&self, printer_id: &str) -> bool {
+        self.connection(printer_id).is_some()
+    }
+
+    /// Ids of the connected printers tagged with `role`, ignoring case
+    pub fn printers_with_role(&self, role: &str) -> Vec<String> {
+        self.printers
+            .iter()
+            .filter(|(_, printer)| printer.connection.is_some())
+            .filter(|(_, printer)| {
+                printer
+                    .config
+                    .as_ref()
+                    .is_some_and(|c| c.roles.iter().any(|r| r.eq_ignore_ascii_case(role)))
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// The backend of a connected printer
+    pub(crate) fn connection(&self, printer_id: &str) -> Option<Arc<dyn PrintBackend>> {
+        self.printers.get(printer_id)?.connection.clone()
+    }
+
+    /// Ask a connected printer for its state, e.g. out of paper or cover
+    /// open. Take the query under the manager lock and run it without.
+    pub fn state_query(&self, printer_id: &str) -> Option<StateQuery> {
+        Some(StateQuery {
+            connection: self.connection(printer_id)?,
+        })
+    }
+
+    /// Job counters since the process started
+    pub fn print_stats(&self) -> PrintStats {
+        self.telemetry.snapshot()
+    }
+
+    /// The printer to send a job to, counting a connection failure if there is none
+    fn job_connection(&self, printer_id: &str) -> Result<Arc<dyn PrintBackend>, String> {
+        self.connection(printer_id).ok_or_else(|| {
+            let error = not_connected(printer_id);
+            self.telemetry.record_failure(FailureClass::Connection, &error, 0);
+            error
+        })
+    }
+
+    /// Cache a template and make it active.
+    ///
+    /// A template with a lower version than the cached one of the same id is
+    /// rejected unless `force` is set, so a stale client can't roll back a
+    /// newer template. Same-version templates with new content overwrite with
+    /// a warning.
+    ///
+    /// Every condition is parsed first. Ones that don't parse reject the
+    /// template, or are only logged when `template_validation` says to warn.
+    pub fn set_template(
+        &mut self,
+        template: ReceiptTemplate,
+        force: bool,
+    ) -> Result<TemplateReplacement, SetTemplateError> {
+        let id = template.id.clone();
+
+        let issues = check_conditions(&template);
+        if !issues.is_empty() {
+            for issue in &issues {
+                log::warn!("Template '{}' condition {}", id, issue);
+            }
+            if self.template_validation.conditions == Strictness::Reject {
+                log::warn!("Rejected template '{}': {} invalid condition(s)", id, issues.len());
+                return Err(SetTemplateError::InvalidConditions {
+                    template_id: id,
+                    issues,
+                });
+            }
+        }
+
+        for warning in check_layout(&template) {
+            log::warn!("Template '{}' {}", id, warning);
+        }
+
+        let replacement = self.template_cache.classify(&template);
+
+        match &replacement {
+            TemplateReplacement::Downgrade { previous_version } if !force => {
+                log::warn!(
+                    "Rejected template '{}' version {}: cached version {} is newer",
+                    id,
+                    template.version,
+                    previous_version
+                );
+                return Err(SetTemplateError::VersionConflict(VersionConflict {
+                    template_id: id,
+                    cached_version: previous_version.clone(),
+                    incoming_version: template.version,
+                }));
+            }
+            TemplateReplacement::Downgrade { previous_version } => {
+                log::warn!(
+                    "Forced downgrade of template '{}' from version {} to {}",
+                    id,
+                    previous_version,
+                    template.version
+                );
+            }
+            TemplateReplacement::Overwrite {
+                previous_hash,
+                new_hash,
+            } => {
+                log::warn!(
+                    "Template '{}' version {} replaced with different content ({} -> {})",
+                    id,
+                    template.version,
+                    previous_hash,
+                    new_hash
+                );
+            }
+            TemplateReplacement::Upgrade { previous_version } => {
+                log::info!(
+                    "Template '{}' upgraded from version {} to {}",
+                    id,
+                    previous_version,
+                    template.version
+                );
+            }
+            TemplateReplacement::New | TemplateReplacement::Unchanged => {}
+        }
+
+        self.template_cache.insert(template, Some(&id));
+        self.active_template_id = Some(id);
+        Ok(replacement)
+    }
+
+    /// Drop all cached templates and the active template selection
+    pub fn clear_templates(&mut self) {
+        self.template_cache.clear();
+        self.active_template_id = None;
+    }
+
+    pub fn print_raw(&mut self, bytes: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let connection = self.job_connection(DEFAULT_PRINTER)?;
+        match connection.write_binary(bytes, "Image") {
+            Ok(()) => self.telemetry.record_success(bytes.len() as u64),
+            Err(e) => {
+                self.telemetry.record_failure(FailureClass::Write, &e, 0);
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn print_with_template(&mut self, data: &ReceiptData) -> Result<(), String> {
+        self.prepare_template_print(data)?.send()
+    }
+
+    /// Render the active template into a `PrintOutput` without touching the printer.
+    pub fn prepare_template_print(&mut self, data: &ReceiptData) -> Result<PrintOutput, String> {
+        self.snapshot()?.render(data)
+    }
+
+    /// Prepare the active template to be rendered while it is written to the
+    /// printer (see [`PrintSnapshot::stream`]).
+    pub fn prepare_template_stream(&mut self, data: ReceiptData) -> Result<PrintOutput, String> {
+        Ok(self.snapshot()?.stream(data))
+    }
+
+    /// Capture the active template, its logos and the print settings for one
+    /// job, so it can be rendered after the manager is released.
+    pub fn snapshot(&mut self) -> Result<PrintSnapshot, String> {
+        self.snapshot_for(DEFAULT_PRINTER)
+    }
+
+    /// [`snapshot`](Self::snapshot) for a job on `printer_id`
+    pub fn snapshot_for(&mut self, printer_id: &str) -> Result<PrintSnapshot, String> {
+        let template_id = self
+            .active_template_id
+            .clone()
+            .ok_or("No active template set")?;
+        self.snapshot_template(printer_id, &template_id)
+    }
+
+    /// [`snapshot_for`](Self::snapshot_for) with a cached template other than
+    /// the active one, e.g. a kitchen ticket template
+    pub fn snapshot_template(
+        &mut self,
+        printer_id: &str,
+        template_id: &str,
+    ) -> Result<PrintSnapshot, String> {
+        let compiled = self
+            .template_cache
+            .compiled(template_id)
+            .ok_or("Template not found in cache")?;
+        let template = self
+            .template_cache
+            .get(template_id)
+            .ok_or("Template not found in cache")?;
+        let profile = self.profile(printer_id);
+
+        // A template without its own paper width fits the printer's
+        let compiled = match profile {
+            Some(profile)
+                if template.paper_width.is_none()
+                    && compiled.paper_width() != profile.chars_per_line =>
+            {
+                Arc::new(TemplateRenderer::new(profile.chars_per_line).compile(&template))
+            }
+            _ => compiled,
+        };
+
+        // Resolve any logo references using the logo cache
+        let resolved_logos = logo_cache::resolve_compiled_logos(self, &compiled);
+        // The template's print area, else the printer's, on the printer's
+        // paper (80mm when unknown)
+        let config = self.printer_config(printer_id);
+        let paper_dots = profile.map_or(48, |profile| profile.chars_per_line) * 12;
+        let print_area = template
+            .print_area
+            .or_else(|| config.and_then(|config| config.print_area))
+            .map(|area| area.resolve(compiled.paper_width() * 12, paper_dots));
+        let renderer = TemplateRenderer::new(compiled.paper_width())
+            .with_resolved_logos(resolved_logos)
+            .with_upside_down(config.is_some_and(|config| config.upside_down))
+            .with_print_area(print_area);
+
+        Ok(PrintSnapshot {
+            template,
+            compiled,
+            renderer,
+            printer_id: printer_id.to_string(),
+            connection: self.job_connection(printer_id)?,
+            encoder: self.encoder(printer_id),
+            profile,
+            telemetry: Arc::clone(&self.telemetry),
+            optimize: self.optimize_commands,
+            data_strictness: self.template_validation.data,
+        })
+    }
+
+    /// Wrap bytes already encoded for the default printer, for sending
+    pub fn prepare_bytes(&self, bytes: Vec<u8>) -> Result<PrintOutput, String> {
+        Ok(PrintOutput {
+            printer_id: DEFAULT_PRINTER.to_string(),
+            connection: self.job_connection(DEFAULT_PRINTER)?,
+            telemetry: Arc::clone(&self.telemetry),
+            payload: PrintPayload::Bytes(bytes),
+        })
+    }
+
+    /// Wrap a spooled job for sending to the printer it was saved for
+    pub fn prepare_spooled(&self, job: SpooledJob) -> Result<PrintOutput, String> {
+        let connection = self.job_connection(&job.printer)?;
+        let payload = match job.payload {
+            SpooledPayload::Bytes(bytes) => PrintPayload::Bytes(bytes),
+            SpooledPayload::Commands(commands) => PrintPayload::for_connection(
+                connection.as_ref(),
+                &self.encoder(&job.printer),
+                commands,
+            ),
+        };
+        Ok(PrintOutput {
+            printer_id: job.printer,
+            connection,
+            telemetry: Arc::clone(&self.telemetry),
+            payload,
+        })
+    }
+
+    fn prepare_commands(
+        &self,
+        printer_id: &str,
+        commands: Vec<template_render::PrintCommand>,
+    ) -> Result<PrintOutput, String> {
+        let connection = self.job_connection(printer_id)?;
+        let commands = if self.optimize_commands {
+            optimize(commands)
+        } else {
+            commands
+        };
+        Ok(PrintOutput {
+            printer_id: printer_id.to_string(),
+            payload: PrintPayload::for_connection(
+                connection.as_ref(),
+                &self.encoder(printer_id),
+                commands,
+            ),
+            connection,
+            telemetry: Arc::clone(&self.telemetry),
+        })
+    }
+
+    pub fn print_test(&mut self) -> Result<(), String> {
+        self.prepare_test()?.send()
+    }
+
+    /// Build the connection test page without touching the printer.
+    pub fn prepare_test(&self) -> Result<PrintOutput, String> {
+        self.prepare_test_for(DEFAULT_PRINTER)
+    }
+
+    /// [`prepare_test`](Self::prepare_test) for `printer_id`
+    pub fn prepare_test_for(&self, printer_id: &str) -> Result<PrintOutput, String> {
+        let config = self
+            .printer_config(printer_id)
+            .ok_or("No configuration found")?;
+
+        let commands = vec![
+            template_render::PrintCommand::Init,
+            template_render::PrintCommand::Align("center".to_string()),
+            template_render::PrintCommand::Size(2, 2),
+            template_render::PrintCommand::Bold(true),
+            template_render::PrintCommand::WriteLine("NEXORA POS".to_string()),
+            template_render::PrintCommand::Size(1, 1),
+            template_render::PrintCommand::Bold(false),
+            template_render::PrintCommand::WriteLine("Test Print".to_string()),
+            template_render::PrintCommand::Feed(1),
+            template_render::PrintCommand::Align("left".to_string()),
+            template_render::PrintCommand::WriteLine(
+                "================================".to_string(),
+            ),
+            template_render::PrintCommand::WriteLine(format!(
+                "Connection: {}",
+                config.connection_type
+            )),
+            template_render::PrintCommand::WriteLine(format!("Device: {}", config.device_path)),
+            template_render::PrintCommand::WriteLine(format!("Store: {}", config.store_name)),
+            template_render::PrintCommand::WriteLine(
+                "================================".to_string(),
+            ),
+            template_render::PrintCommand::Feed(1),
+            template_render::PrintCommand::WriteLine("[OK] Connection Successful".to_string()),
+            template_render::PrintCommand::WriteLine(format!(
+                "Date: {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+            )),
+            template_render::PrintCommand::Feed(1),
+            template_render::PrintCommand::WriteLine("Testing text output:".to_string()),
+            template_render::PrintCommand::WriteLine("Regular Text".to_string()),
+            template_render::PrintCommand::Bold(true),
+            template_render::PrintCommand::WriteLine("Bold Text".to_string()),
+            template_render::PrintCommand::Bold(false),
+            template_render::PrintCommand::Reverse(true),
+            template_render::PrintCommand::WriteLine("Inverted Text".to_string()),
+            template_render::PrintCommand::Reverse(false),
+            template_render::PrintCommand::Feed(1),
+            template_render::PrintCommand::WriteLine("ESC/POS Compatible [OK]".to_string()),
+            template_render::PrintCommand::Feed(3),
+            template_render::PrintCommand::Feed(1),
+            template_render::PrintCommand::Feed(1),
+            template_render::PrintCommand::Cut,
+        ];
+
+        self.prepare_commands(printer_id, commands)
+    }
+
+    /// Store a logo in `printer_id`'s non-volatile memory under `slot`, so
+    /// templates print it with `nv_slot` instead of sending the image with
+    /// every receipt. `source` is a cached logo id, base64 data, a file or
+    /// a URL.
+    pub fn prepare_nv_logo(
+        &self,
+        printer_id: &str,
+        slot: u8,
+        source: &str,
+        max_width: Option<u32>,
+        max_height: Option<u32>,
+        options: &ImageOptions,
+    ) -> Result<PrintOutput, String> {
+        if self.protocol(printer_id) != Protocol::EscPos {
+            return Err("Star printers store logos with Star's setup utility".to_string());
+        }
+        let connection = self.job_connection(printer_id)?;
+        if connection.takes_commands() {
+            return Err(format!("Printer '{}' can't store logos", printer_id));
+        }
+        let source = logo_cache::get_logo(self, source)
+            .map_or_else(|| source.to_string(), |logo| logo.base64_data);
+        let paper_width_dots = self
+            .profile(printer_id)
+            .map_or(48, |profile| profile.chars_per_line)
+            * 12;
+        let bytes = image_print::nv_logo_store(
+            &source,
+            slot,
+            paper_width_dots,
+            max_width,
+            max_height,
+            options,
+        )
+        .map_err(|e| format!("Logo conversion failed: {}", e))?;
+        Ok(PrintOutput {
+            printer_id: printer_id.to_string(),
+            connection,
+            telemetry: Arc::clone(&self.telemetry),
+            payload: PrintPayload::Bytes(bytes),
+        })
+    }
+
+    pub fn print_receipt(&mut self, receipt: &Receipt) -> Result<(), String> {
+        self.prepare_receipt(receipt)?.send()
+    }
+
+    /// Build a legacy receipt (or render it through the active template) without printing.
+    pub fn prepare_receipt(&mut self, receipt: &Receipt) -> Result<PrintOutput, String> {
+        // Convert legacy Receipt to ReceiptData and use print_with_template if possible
+        // Or just build commands manually for legacy support
+        let data = ReceiptData {
+            store_name: Some(
+                self.config().map(|c| c.store_name.clone()).unwrap_or_default(),
+            ),
+            store_address: Some(
+                self.config().map(|c| c.store_address.clone()).unwrap_or_default(),
+            ),
+            order_id: receipt.order_id.clone(),
+            timestamp: receipt.timestamp.clone(),
+            items: receipt
+                .items
+                .iter()
+                .map(|item| ReceiptItem {
+                    name: item.name.clone(),
+                    quantity: item.quantity,
+                    price: item.price,
+                    total: item.quantity as f64 * item.price,
+                    ..Default::default()
+                })
+                .collect(),
+            subtotal: receipt.subtotal,
+            tax: receipt.tax,
+            total: receipt.total,
+            payment_method: receipt.payment_method.clone(),
+            footer_message: Some(
+                self.config().map(|c| c.footer_message.clone()).unwrap_or_default(),
+            ),
+            ..Default::default()
+        };
+
+        // If we have an active template, use it. Otherwise, build a simple receipt.
+        if self.active_template_id.is_some() {
+            return self.prepare_template_print(&data);
+        }
+
+        let mut commands = vec![
+            template_render::PrintCommand::Init,
+            template_render::PrintCommand::Align("center".to_string()),
+            template_render::PrintCommand::Bold(true),
+            template_render::PrintCommand::WriteLine(data.store_name.unwrap_or_default()),
+            template_render::PrintCommand::Bold(false),
+            template_render::PrintCommand::WriteLine(data.store_address.unwrap_or_default()),
+            template_render::PrintCommand::Feed(1),
+            template_render::PrintCommand::Align("left".to_string()),
+            template_render::PrintCommand::WriteLine(format!("Order #{}", data.order_id)),
+            template_render::PrintCommand::WriteLine(data.timestamp),
+            template_render::PrintCommand::WriteLine("-".repeat(32)),
+        ];
+
+        for item in &data.items {
+            commands.push(template_render::PrintCommand::WriteLine(item.name.clone()));
+            let qty_price = format!("{}x ${:.2}", item.quantity, item.price);
+            let total = format!("${:.2}", item.total);
+            let spaces = 32_usize.saturating_sub(qty_price.len() + total.len());
+            commands.push(template_render::PrintCommand::WriteLine(format!(
+                "{}{}{}",
+                qty_price,
+                " ".repeat(spaces),
+                total
+            )));
+        }
+
+        commands.extend_from_slice(&[
+            template_render::PrintCommand::WriteLine("-".repeat(32)),
+            template_render::PrintCommand::WriteLine(format!(
+                "Subtotal:                ${:.2}",
+                data.subtotal
+            )),
+            template_render::PrintCommand::WriteLine(format!(
+                "Tax:                     ${:.2}",
+                data.tax
+            )),
+            template_render::PrintCommand::Bold(true),
+            template_render::PrintCommand::WriteLine(format!(
+                "TOTAL:                   ${:.2}",
+                data.total
+            )),
+            template_render::PrintCommand::Bold(false),
+            template_render::PrintCommand::WriteLine("-".repeat(32)),
+            template_render::PrintCommand::WriteLine(format!("Payment: {}", data.payment_method)),
+            template_render::PrintCommand::Feed(1),
+            template_render::PrintCommand::Align("center".to_string()),
+            template_render::PrintCommand::WriteLine(data.footer_message.unwrap_or_default()),
+            template_render::PrintCommand::WriteLine("Powered by Nexora POS".to_string()),
+            template_render::PrintCommand::Feed(3),
+            template_render::PrintCommand::Cut,
+        ]);
+
+        self.prepare_commands(DEFAULT_PRINTER, commands)
+    }
+}
+
+// ==================== Barcode Printer Manager ====================
+
+pub struct BarcodePrinterManager {
+    pub(crate) connection: Option<Arc<dyn PrintBackend>>,
+    pub config: Option<BarcodePrinterConfig>,
+}
+
+impl Default for BarcodePrinterManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BarcodePrinterManager {
+    pub fn new() -> Self {
+        Self {
+            connection: None,
+            config: None,
+        }
+    }
+
+    pub fn connect(&mut self, config: BarcodePrinterConfig) -> Result<(), String> {
+        log::info!(
+            "Connecting to barcode printer via {} at {}",
+            config.connection_type,
+            config.device_path
+        );
+
+        // Label printers need a real device; the console can't show a label
+        if config.connection_type == "Console" {
+            return Err(format!("Unsupported connection type: {}", config.connection_type));
+        }
+        self.connection = Some(backend::connect(
+            &config.connection_type,
+            &config.device_path,
+            &config.serial,
+            &config.timeouts,
+            "Nexora Barcode",
+        )?);
+
+        self.config = Some(config);
+        log::info!("Barcode printer connected successfully");
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            connection.close();
+        }
+        log::info!("Barcode printer disconnected");
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    pub fn print_label(&mut self, req: &BarcodeLabelRequest) -> Result<(), String> {
+        let config = self.config.as_ref().ok_or("Barcode printer not configured")?;
+        let bytes = barcode_printer::build_label(config, req);
+        self.print_raw(&bytes).map_err(|e| e.to_string())
+    }
+
+    /// Print `copies` of a label template's label for `data`
+    pub fn print_template_label(
+        &mut self,
+        label: &LabelSpec,
+        data: &ReceiptData,
+        copies: u32,
+    ) -> Result<(), String> {
+        let config = self
+            .config
+            .as_ref()
+            .ok_or("Barcode printer not configured")?;
+        let bytes = label_template::build_template_label(config, label, data, copies)?;
+        self.print_raw(&bytes).map_err(|e| e.to_string())
+    }
+
+    pub fn print_test_label(&mut self) -> Result<(), String> {
+        let config = self.config.as_ref().ok_or("Barcode printer not configured")?.clone();
+        let bytes = barcode_printer::build_test_label(&config);
+        self.print_raw(&bytes).map_err(|e| e.to_string())
+    }
+
+    pub fn print_raw(&mut self, bytes: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let connection = self.connection.as_ref().ok_or("Barcode printer not connected")?;
+        connection.write_binary(bytes, "Barcode label")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::encoder::encode_commands;
+    use crate::template_render::load_template;
+
+    fn manager_with(printer: &MockBackend) -> PrinterManager {
+        let mut manager = PrinterManager::new();
+        let config = PrinterConfig {
+            connection_type: "Console".to_string(),
+            device_path: String::new(),
+            store_name: "Test Cafe".to_string(),
+            store_address: "1 Main St".to_string(),
+            footer_message: "Thanks".to_string(),
+            serial: Default::default(),
+            timeouts: Default::default(),
+            roles: Vec::new(),
+            protocol: None,
+            profile: None,
+            code_pages: Vec::new(),
+            cjk_encoding: None,
+            raster_text: false,
+            upside_down: false,
+            density: None,
+            print_speed: None,
+            print_area: None,
+            write_pacing: None,
+        };
+        manager.connect_backend(DEFAULT_PRINTER, Some(config), Arc::new(printer.clone()));
+        manager
+    }
+
+    fn report(rows: usize) -> (ReceiptTemplate, ReceiptData) {
+        let template = load_template(
+            r#"{
+                "id": "report", "name": "Report", "version": "1.0", "paper_width": 48,
+                "layout": {"sections": [{"type": "body", "elements": [
+                    {"type": "text", "content": "Order {{order_id}}", "bold": true},
+                    {"type": "table", "data_source": "rows",
+                     "columns": [{"field": "name", "width": 20}]}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+        let rows: Vec<_> = (0..rows)
+            .map(|i| serde_json::json!({ "name": format!("row {}", i) }))
+            .collect();
+        let data = serde_json::from_value(
+            serde_json::json!({ "order_id": "42", "timestamp": "", "rows": rows }),
+        )
+        .unwrap();
+        (template, data)
+    }
+
+    #[test]
+    fn test_test_print_and_template_print_reach_backend() {
+        let printer = MockBackend::new();
+        let mut manager = manager_with(&printer);
+
+        manager.print_test().unwrap();
+        assert!(printer.written().starts_with(&[0x1B, 0x40]));
+        assert!(String::from_utf8_lossy(&printer.written()).contains("Test Cafe"));
+
+        printer.clear();
+        let (template, data) = report(3);
+        manager.set_template(template.clone(), false).unwrap();
+        manager.print_with_template(&data).unwrap();
+
+        let commands = TemplateRenderer::new(48).render_template(&template, &data).unwrap();
+        assert_eq!(printer.written(), encode_commands(&optimize(commands)));
+        assert_eq!(printer.jobs(), 2);
+    }
+
+    #[test]
+    fn test_profile_detected_from_usb_id_shapes_the_job() {
+        let printer = MockBackend::new();
+        let mut manager = manager_with(&printer);
+        let mut config = manager.config().unwrap().clone();
+        config.device_path = "usb:0416:5011".to_string();
+        manager.connect_backend(
+            DEFAULT_PRINTER,
+            Some(config.clone()),
+            Arc::new(printer.clone()),
+        );
+        assert_eq!(manager.profile(DEFAULT_PRINTER).unwrap().id, "generic-58mm");
+        assert_eq!(
+            manager.printers()[0].profile.as_deref(),
+            Some("generic-58mm")
+        );
+
+        // No paper_width, so the template takes the printer's 32 columns
+        let template = load_template(
+            r#"{
+                "id": "fit", "name": "Fit", "version": "1.0",
+                "layout": {"sections": [{"type": "body", "elements": [
+                    {"type": "text", "content": "narrow", "condition": "printer.paper_width_mm == 58"},
+                    {"type": "text", "content": "wide", "condition": "printer.paper_width_mm == 80"},
+                    {"type": "text", "content": "no cutter", "condition": "printer.cutter == false"}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+        manager.set_template(template, false).unwrap();
+        let snapshot = manager.snapshot_for(DEFAULT_PRINTER).unwrap();
+        assert_eq!(snapshot.compiled.paper_width(), 32);
+        snapshot
+            .render(&ReceiptData::default())
+            .unwrap()
+            .send()
+            .unwrap();
+        let text = String::from_utf8_lossy(&printer.written()).into_owned();
+        assert!(
+            text.contains("narrow") && !text.contains("wide"),
+            "{}",
+            text
+        );
+        assert!(text.contains("no cutter"), "{}", text);
+
+        // A named profile overrides detection and sets the protocol
+        config.profile = Some("star-tsp143".to_string());
+        manager.connect_backend(
+            DEFAULT_PRINTER,
+            Some(config.clone()),
+            Arc::new(printer.clone()),
+        );
+        assert_eq!(manager.protocol(DEFAULT_PRINTER), Protocol::Star);
+        assert_eq!(
+            manager.encoder(DEFAULT_PRINTER).code_pages,
+            codepage::from_names(profiles::profile("star-tsp143").unwrap().code_pages)
+        );
+        config.protocol = Some(Protocol::EscPos);
+        config.code_pages = vec![codepage::CodePage::Cp857];
+        manager.connect_backend(DEFAULT_PRINTER, Some(config), Arc::new(printer.clone()));
+        assert_eq!(manager.protocol(DEFAULT_PRINTER), Protocol::EscPos);
+        // The TSP143 draws no QR codes of its own
+        assert_eq!(
+            manager.encoder(DEFAULT_PRINTER),
+            PrinterEncoder::new(Protocol::EscPos, vec![codepage::CodePage::Cp857])
+                .with_native_qr(false)
+        );
+    }
+
+    #[test]
+    fn test_streamed_job_is_written_in_chunks() {
+        let (template, data) = report(2000);
+        let batch = MockBackend::new();
+        let streamed = MockBackend::new();
+        let whole = MockBackend::new().whole_jobs();
+
+        for (printer, stream) in [(&batch, false), (&streamed, true), (&whole, true)] {
+            let mut manager = manager_with(printer);
+            manager.set_template(template.clone(), false).unwrap();
+            let output = if stream {
+                manager.prepare_template_stream(data.clone()).unwrap()
+            } else {
+                manager.prepare_template_print(&data).unwrap()
+            };
+            output.send().unwrap();
+        }
+
+        assert_eq!(streamed.written(), batch.written());
+        assert!(streamed.writes() > 1, "expected chunked writes");
+        assert_eq!(streamed.jobs(), 1);
+        // A backend that only takes whole jobs gets the streamed job buffered
+        assert_eq!(whole.written(), batch.written());
+        assert_eq!(whole.writes(), 1);
+    }
+
+    #[test]
+    fn test_write_failures_fail_only_that_job() {
+        let printer = MockBackend::new();
+        let mut manager = manager_with(&printer);
+        let (template, data) = report(2000);
+        manager.set_template(template, false).unwrap();
+
+        printer.fail_next_writes(1);
+        assert_eq!(manager.print_test().unwrap_err(), "Mock printer write failed");
+        manager.print_test().unwrap();
+
+        // A streamed job stops at the failed write
+        printer.clear();
+        printer.fail_next_writes(1);
+        let output = manager.prepare_template_stream(data.clone()).unwrap();
+        assert!(output.send().is_err());
+        assert!(printer.written().is_empty());
+
+        printer.set_offline(true);
+        assert!(manager.query_status().is_err());
+        assert!(manager.print_test().is_err());
+        printer.set_offline(false);
+        manager.query_status().unwrap();
+        manager.print_test().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_usb_device_receives_escpos_bytes() {
+        let path = std::env::temp_dir().join(format!("nexora-usb-{}", std::process::id()));
+        // Stands in for the device node, which connect expects to exist
+        std::fs::write(&path, b"").unwrap();
+        let mut manager = PrinterManager::new();
+        manager
+            .connect(PrinterConfig {
+                connection_type: "USB".to_string(),
+                device_path: path.to_string_lossy().into_owned(),
+                store_name: "Test Cafe".to_string(),
+                store_address: String::new(),
+                footer_message: String::new(),
+                serial: Default::default(),
+                timeouts: Default::default(),
+                roles: Vec::new(),
+                protocol: None,
+                profile: None,
+                code_pages: Vec::new(),
+                cjk_encoding: None,
+                raster_text: false,
+                upside_down: false,
+                density: None,
+                print_speed: None,
+                print_area: None,
+                write_pacing: None,
+            })
+            .unwrap();
+
+        manager.print_test().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+        assert!(bytes.starts_with(&[0x1B, 0x40]), "init");
+        assert!(contains(&[0x1B, 0x61, 1]), "center");
+        assert!(contains(&[0x1D, 0x21, 0x11]), "double size");
+        assert!(contains(&[0x1B, 0x45, 1]), "bold");
+        assert!(contains(&[0x1D, 0x42, 1]), "reverse");
+        assert!(contains(b"Store: Test Cafe\n"));
+        assert!(bytes.ends_with(&[b'\n', 0x1D, 0x56, 0x01]), "feed then cut");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_nv_logo_is_stored_then_printed_by_slot() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let printer = MockBackend::new();
+        let mut manager = manager_with(&printer);
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(image::RgbaImage::new(16, 4))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let logo = general_purpose::STANDARD.encode(png);
+
+        manager
+            .prepare_nv_logo(DEFAULT_PRINTER, 3, &logo, None, None, &Default::default())
+            .unwrap()
+            .send()
+            .unwrap();
+        // Delete, then define slot "03"
+        assert!(printer
+            .written()
+            .starts_with(&[0x1D, 0x28, 0x4C, 4, 0, 0x30, 0x42, b'0', b'3']));
+
+        printer.clear();
+        let template = load_template(
+            r#"{
+                "id": "nv", "name": "NV", "version": "1.0", "paper_width": 48,
+                "layout": {"sections": [{"type": "header", "elements": [
+                    {"type": "logo", "nv_slot": 3}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+        manager.set_template(template, false).unwrap();
+        manager.print_with_template(&ReceiptData::default()).unwrap();
+        let print = [0x1D, 0x28, 0x4C, 6, 0, 0x30, 0x45, b'0', b'3', 1, 1];
+        assert!(printer.written().windows(print.len()).any(|w| w == print));
+    }
+
+    #[test]
+    fn test_star_printer_receives_star_bytes() {
+        let path = std::env::temp_dir().join(format!("nexora-star-{}", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        let mut manager = PrinterManager::new();
+        manager
+            .connect(PrinterConfig {
+                connection_type: "USB".to_string(),
+                device_path: path.to_string_lossy().into_owned(),
+                store_name: "Test Cafe".to_string(),
+                store_address: String::new(),
+                footer_message: String::new(),
+                serial: Default::default(),
+                timeouts: Default::default(),
+                roles: Vec::new(),
+                protocol: Some(Protocol::Star),
+                profile: None,
+                code_pages: Vec::new(),
+                cjk_encoding: None,
+                raster_text: false,
+                upside_down: false,
+                density: None,
+                print_speed: None,
+                print_area: None,
+                write_pacing: None,
+            })
+            .unwrap();
+
+        manager.print_test().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(&[0x1B, 0x1D, 0x61, 1]), "center");
+        assert!(contains(&[0x1B, 0x69, 1, 1]), "double size");
+        assert!(!contains(&[0x1D, 0x21, 0x11]), "no ESC/POS size");
+        assert!(bytes.ends_with(&[b'\n', 0x1B, 0x64, 0x01]), "feed then cut");
+    }
+
+    #[test]
+    fn test_pdf_printer_saves_rendered_jobs() {
+        let dir = std::env::temp_dir().join(format!("nexora-pdf-jobs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut manager = PrinterManager::new();
+        manager
+            .connect(PrinterConfig {
+                connection_type: "PDF".to_string(),
+                device_path: dir.to_string_lossy().into_owned(),
+                store_name: "Test Cafe".to_string(),
+                store_address: String::new(),
+                footer_message: String::new(),
+                serial: Default::default(),
+                timeouts: Default::default(),
+                roles: Vec::new(),
+                protocol: None,
+                profile: None,
+                code_pages: Vec::new(),
+                cjk_encoding: None,
+                raster_text: false,
+                upside_down: false,
+                density: None,
+                print_speed: None,
+                print_area: None,
+                write_pacing: None,
+            })
+            .unwrap();
+
+        manager.print_test().unwrap();
+        let (template, data) = report(2000);
+        manager.set_template(template, false).unwrap();
+        let job = manager.prepare_template_stream(data).unwrap();
+        job.send().unwrap();
+
+        let saved: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+            .map(|pdf| String::from_utf8_lossy(&pdf).into_owned())
+            .collect();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(saved.len(), 2);
+        let saved_with = |text: &str| saved.iter().any(|pdf| pdf.contains(text));
+        assert!(saved_with("(Store: Test Cafe) Tj"));
+        assert!(saved_with("(row 1999"));
+    }
+
+    #[test]
+    fn test_network_printer_receives_job_and_reports_errors() {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = std::thread::spawn(move || {
+            // connect checks the printer with a connection that sends nothing
+            loop {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut bytes = Vec::new();
+                stream.read_to_end(&mut bytes).unwrap();
+                if !bytes.is_empty() {
+                    return bytes;
+                }
+            }
+        });
+
+        let mut manager = PrinterManager::new();
+        let config = PrinterConfig {
+            connection_type: "Network".to_string(),
+            device_path: addr.to_string(),
+            store_name: String::new(),
+            store_address: String::new(),
+            footer_message: String::new(),
+            serial: Default::default(),
+            timeouts: Default::default(),
+            roles: Vec::new(),
+            protocol: None,
+            profile: None,
+            code_pages: Vec::new(),
+            cjk_encoding: None,
+            raster_text: false,
+            upside_down: false,
+            density: None,
+            print_speed: None,
+            print_area: None,
+            write_pacing: None,
+        };
+        manager.connect(config).unwrap();
+        let (template, data) = report(3);
+        manager.set_template(template.clone(), false).unwrap();
+        manager.print_with_template(&data).unwrap();
+
+        let commands = TemplateRenderer::new(48).render_template(&template, &data).unwrap();
+        assert_eq!(received.join().unwrap(), encode_commands(&optimize(commands)));
+
+        // The listener is gone, so nothing accepts the next job
+        let error = manager.print_with_template(&data).unwrap_err();
+        assert!(
+            error.starts_with(&format!("Cannot reach {}", addr)),
+            "unexpected error: {}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_print_stats_count_every_job() {
+        let printer = MockBackend::new();
+        let mut manager = manager_with(&printer);
+        let (template, data) = report(3);
+        manager.set_template(template, false).unwrap();
+
+        manager.print_test().unwrap();
+        manager.print_with_template(&data).unwrap();
+
+        let stats = manager.print_stats();
+        assert_eq!(stats.total_prints, 2);
+        assert_eq!(stats.bytes_written, printer.written().len() as u64);
+        assert_eq!(stats.failures.total(), 0);
+        assert!(stats.last_success_at.is_some());
+        assert_eq!(stats.last_error, None);
+
+        printer.fail_next_writes(1);
+        assert!(manager.print_test().is_err());
+        let (_, big) = report(2000);
+        printer.fail_next_writes(1);
+        assert!(manager.prepare_template_stream(big).unwrap().send().is_err());
+        manager.disconnect();
+        assert!(manager.print_test().is_err());
+
+        let stats = manager.print_stats();
+        assert_eq!(stats.total_prints, 2);
+        assert_eq!(stats.failures.write, 2);
+        assert_eq!(stats.failures.connection, 1);
+        assert_eq!(stats.last_error.as_deref(), Some("Printer not connected"));
+    }
+
+    #[test]
+    fn test_registered_printers_get_their_own_jobs() {
+        let front = MockBackend::new();
+        let kitchen = MockBackend::new();
+        let mut manager = manager_with(&front);
+        manager.connect_backend("kitchen", None, Arc::new(kitchen.clone()));
+        let (template, data) = report(3);
+        manager.set_template(template, false).unwrap();
+
+        let output = manager.snapshot_for("kitchen").unwrap().render(&data).unwrap();
+        output.send().unwrap();
+        assert_eq!((front.jobs(), kitchen.jobs()), (0, 1));
+        manager.print_with_template(&data).unwrap();
+        assert_eq!((front.jobs(), kitchen.jobs()), (1, 1));
+
+        assert_eq!(
+            manager.snapshot_for("bar").err().unwrap(),
+            "Printer 'bar' not connected"
+        );
+        assert_eq!(
+            manager.prepare_test_for("kitchen").err().unwrap(),
+            "No configuration found"
+        );
+
+        assert!(manager.disconnect_printer("kitchen"));
+        assert!(!manager.disconnect_printer("bar"));
+        assert!(kitchen.is_closed() && !front.is_closed());
+        let printers: Vec<_> = manager
+            .printers()
+            .into_iter()
+            .map(|printer| (printer.id, printer.connected))
+            .collect();
+        assert_eq!(
+            printers,
+            [("default".to_string(), true), ("kitchen".to_string(), false)]
+        );
+        assert_eq!(
+            manager.connect_printer(" ", manager.config().unwrap().clone()),
+            Err("Printer id must not be empty".to_string())
+        );
+    }
+
+    #[test]
+    fn test_disconnect_closes_backend_and_reconnect_replaces_it() {
+        let printer = MockBackend::new();
+        let mut manager = manager_with(&printer);
+
+        manager.disconnect();
+        assert!(printer.is_closed());
+        assert!(!manager.is_connected());
+        assert_eq!(manager.print_test().unwrap_err(), "Printer not connected");
+        assert_eq!(manager.query_status().unwrap_err(), "Printer not connected");
+
+        assert_eq!(manager.print_stats().connection_uptime_secs, None);
+
+        let config = manager.config().unwrap().clone();
+        manager.connect(config).unwrap();
+        assert!(manager.is_connected());
+        manager.query_status().unwrap();
+        assert_eq!(manager.print_stats().connection_uptime_secs, Some(0));
+
+        let mut barcode = BarcodePrinterManager::new();
+        let config: BarcodePrinterConfig = serde_json::from_value(serde_json::json!({
+            "connection_type": "Console", "device_path": "", "protocol": "TSPL",
+            "label_width_mm": 50, "label_height_mm": 30, "dpi": 203
+        }))
+        .unwrap();
+        assert_eq!(
+            barcode.connect(config).unwrap_err(),
+            "Unsupported connection type: Console"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+// src/profiles.rs
+// Built-in capability profiles for known receipt printer models
+
+use serde::Serialize;
+
+use crate::encoder::Protocol;
+
+/// What a printer model can do, looked up by id or USB vendor and product id
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PrinterProfile {
+    pub id: &'static str,
+    pub name: &'static str,
+    /// `vendor:product` in hex, as in a `usb:04b8:0e15` device path
+    pub usb_ids: &'static [&'static str],
+    /// 58 or 80
+    pub paper_width_mm: u32,
+    /// Characters per line in the standard font
+    pub chars_per_line: u32,
+    /// Prints QR codes from a command instead of needing an image
+    pub native_qr: bool,
+    pub cutter: bool,
+    pub code_pages: &'static [&'static str],
+    pub protocol: Protocol,
+}
+
+impl PrinterProfile {
+    /// The profile as the `printer` object templates see, e.g.
+    /// `"condition": "printer.paper_width_mm == 58"`
+    pub fn template_value(&self) -> serde_json::Value {
+        serde_json::json!({
+            "model": self.id,
+            "name": self.name,
+            "paper_width_mm": self.paper_width_mm,
+            "chars_per_line": self.chars_per_line,
+            "native_qr": self.native_qr,
+            "cutter": self.cutter,
+            "code_pages": self.code_pages,
+        })
+    }
+}
+
+const EPSON_CODE_PAGES: &[&str] = &[
+    "cp437", "katakana", "cp850", "cp860", "cp863", "cp865", "cp1252", "cp866", "cp852", "cp858",
+];
+const STAR_CODE_PAGES: &[&str] = &[
+    "cp437", "katakana", "cp850", "cp852", "cp858", "cp866", "cp1252",
+];
+const BASIC_CODE_PAGES: &[&str] = &["cp437", "cp850"];
+
+/// Known models. Where several share a USB id the first one listed wins.
+pub static PROFILES: &[PrinterProfile] = &[
+    PrinterProfile {
+        id: "epson-tm-t88v",
+        name: "Epson TM-T88V / TM-T88VI",
+        usb_ids: &["04b8:0202"],
+        paper_width_mm: 80,
+        chars_per_line: 48,
+        native_qr: true,
+        cutter: true,
+        code_pages: EPSON_CODE_PAGES,
+        protocol: Protocol::EscPos,
+    },
+    PrinterProfile {
+        id: "epson-tm-t88iv",
+        name: "Epson TM-T88III / TM-T88IV",
+        usb_ids: &[],
+        paper_width_mm: 80,
+        chars_per_line: 48,
+        native_qr: false,
+        cutter: true,
+        code_pages: EPSON_CODE_PAGES,
+        protocol: Protocol::EscPos,
+    },
+    PrinterProfile {
+        id: "epson-tm-t20",
+        name: "Epson TM-T20",
+        usb_ids: &["04b8:0e15"],
+        paper_width_mm: 80,
+        chars_per_line: 48,
+        native_qr: true,
+        cutter: true,
+        code_pages: EPSON_CODE_PAGES,
+        protocol: Protocol::EscPos,
+    },
+    PrinterProfile {
+        id: "star-tsp143",
+        name: "Star TSP143",
+        usb_ids: &["0519:0003"],
+        paper_width_mm: 80,
+        chars_per_line: 48,
+        native_qr: false,
+        cutter: true,
+        code_pages: STAR_CODE_PAGES,
+        protocol: Protocol::Star,
+    },
+    PrinterProfile {
+        id: "star-tsp654",
+        name: "Star TSP654",
+        usb_ids: &[],
+        paper_width_mm: 80,
+        chars_per_line: 48,
+        native_qr: true,
+        cutter: true,
+        code_pages: STAR_CODE_PAGES,
+        protocol: Protocol::Star,
+    },
+    PrinterProfile {
+        id: "bixolon-srp-350",
+        name: "Bixolon SRP-350",
+        usb_ids: &[],
+        paper_width_mm: 80,
+        chars_per_line: 42,
+        native_qr: false,
+        cutter: true,
+        code_pages: BASIC_CODE_PAGES,
+        protocol: Protocol::EscPos,
+    },
+    PrinterProfile {
+        id: "generic-80mm",
+        name: "Generic 80 mm ESC/POS",
+        usb_ids: &[],
+        paper_width_mm: 80,
+        chars_per_line: 48,
+        native_qr: true,
+        cutter: true,
+        code_pages: BASIC_CODE_PAGES,
+        protocol: Protocol::EscPos,
+    },
+    PrinterProfile {
+        id: "generic-58mm",
+        name: "Generic 58 mm ESC/POS",
+        usb_ids: &["0416:5011"],
+        paper_width_mm: 58,
+        chars_per_line: 32,
+        native_qr: false,
+        cutter: false,
+        code_pages: BASIC_CODE_PAGES,
+        protocol: Protocol::EscPos,
+    },
+];
+
+pub fn profile(id: &str) -> Option<&'static PrinterProfile> {
+    PROFILES
+        .iter()
+        .find(|p| p.id.eq_ignore_ascii_case(id.trim()))
+}
+
+/// The profile for a `usb:VID:PID` device path, if the model is known
+pub fn detect_profile(device_path: &str) -> Option<&'static PrinterProfile> {
+    let ids = device_path.trim().strip_prefix("usb:")?;
+    PROFILES
+        .iter()
+        .find(|p| p.usb_ids.iter().any(|id| id.eq_ignore_ascii_case(ids)))
+}
+
+/// The named profile, or the one detected from the device path when none is
+/// named
+pub fn resolve_profile(
+    id: Option<&str>,
+    device_path: &str,
+) -> Result<Option<&'static PrinterProfile>, String> {
+    match id {
+        Some(id) => profile(id)
+            .map(Some)
+            .ok_or_else(|| format!("Unknown printer profile: {}", id)),
+        None => Ok(detect_profile(device_path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_profile() {
+        assert_eq!(
+            resolve_profile(None, "usb:04B8:0E15").unwrap().unwrap().id,
+            "epson-tm-t20"
+        );
+        assert_eq!(resolve_profile(None, "COM3").unwrap(), None);
+        assert_eq!(resolve_profile(None, "usb:1234:5678").unwrap(), None);
+        // A named profile wins over the detected one
+        let star = resolve_profile(Some("Star-TSP143"), "usb:04b8:0e15")
+            .unwrap()
+            .unwrap();
+        assert_eq!(star.protocol, Protocol::Star);
+        assert_eq!(
+            resolve_profile(Some("tm-9000"), "").unwrap_err(),
+            "Unknown printer profile: tm-9000"
+        );
+    }
+
+    #[test]
+    fn test_profiles_are_consistent() {
+        for (i, p) in PROFILES.iter().enumerate() {
+            assert!(PROFILES[..i].iter().all(|q| q.id != p.id), "{}", p.id);
+            match p.paper_width_mm {
+                58 => assert_eq!(p.chars_per_line, 32, "{}", p.id),
+                80 => assert!((42..=48).contains(&p.chars_per_line), "{}", p.id),
+                _ => panic!("{}: paper width {}", p.id, p.paper_width_mm),
+            }
+            assert!(p.usb_ids.iter().all(|id| id.len() == 9), "{}", p.id);
+        }
+        let value = profile("generic-58mm").unwrap().template_value();
+        assert_eq!(value["chars_per_line"], 32);
+        assert_eq!(value["cutter"], false);
+    }
+}
@@ -0,0 +1,163 @@
+// src/raster_text.rs
+// Printing text lines as bitmaps when the printer's character sets lack them
+
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+
+use crate::template_render::char_width;
+
+/// DejaVu Sans, which covers Latin, Greek, Cyrillic, Arabic, Hebrew and
+/// many symbols
+static FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+
+/// Dots per column and per line, the size of the printer's standard font
+pub const CELL_WIDTH: u32 = 12;
+pub const CELL_HEIGHT: u32 = 24;
+
+/// An ESC/POS `GS v 0` raster of `text`, one cell per column.
+///
+/// Each character is drawn into its own cell (two for full-width ones), so
+/// the bitmap lines up with the text lines around it. Right-to-left runs
+/// are drawn right to left, but Arabic letters are not joined.
+pub fn render_line(text: &str) -> Vec<u8> {
+    let (bytes_per_row, dots) = draw_line(text, CELL_WIDTH);
+
+    let mut out = Vec::with_capacity(8 + dots.len());
+    out.extend_from_slice(&[
+        0x1D,
+        0x76,
+        0x30,
+        0x00,
+        (bytes_per_row & 0xFF) as u8,
+        (bytes_per_row >> 8) as u8,
+        CELL_HEIGHT as u8,
+        0x00,
+    ]);
+    out.extend_from_slice(&dots);
+    out
+}
+
+/// `text` drawn with cells `cell_width` dots wide (two for full-width
+/// characters) and [`CELL_HEIGHT`] tall, as the bytes in each row and 1-bit
+/// rows, most significant bit leftmost
+pub(crate) fn draw_line(text: &str, cell_width: u32) -> (u32, Vec<u8>) {
+    let font = FontRef::try_from_slice(FONT).expect("the embedded font is valid");
+    let cells = visual_order(text);
+    let columns: u32 = cells.iter().map(|&c| char_width(c) as u32).sum();
+    let bytes_per_row = (columns * cell_width).div_ceil(8).max(1);
+    let mut dots = vec![0u8; (bytes_per_row * CELL_HEIGHT) as usize];
+
+    let mut x = 0;
+    for c in cells {
+        let width = cell_width * char_width(c) as u32;
+        if width > 0 && !c.is_whitespace() {
+            draw_glyph(&font, c, x, width, bytes_per_row, &mut dots);
+        }
+        x += width;
+    }
+    (bytes_per_row, dots)
+}
+
+/// Draw `c` centred in the cell at `left`, narrowed if it is wider than
+/// the cell. Characters the font lacks are drawn as its missing-glyph box.
+fn draw_glyph(
+    font: &FontRef,
+    c: char,
+    left: u32,
+    cell_width: u32,
+    bytes_per_row: u32,
+    dots: &mut [u8],
+) {
+    let id = font.glyph_id(c);
+    let scaled = font.as_scaled(PxScale::from(CELL_HEIGHT as f32));
+    let advance = scaled.h_advance(id);
+    let x_scale = if advance > cell_width as f32 {
+        CELL_HEIGHT as f32 * cell_width as f32 / advance
+    } else {
+        CELL_HEIGHT as f32
+    };
+    let scale = PxScale {
+        x: x_scale,
+        y: CELL_HEIGHT as f32,
+    };
+    let width = font.as_scaled(scale).h_advance(id);
+    let origin = point(
+        left as f32 + (cell_width as f32 - width) / 2.0,
+        scaled.ascent(),
+    );
+    let Some(outline) = font.outline_glyph(id.with_scale_and_position(scale, origin)) else {
+        return;
+    };
+    let bounds = outline.px_bounds();
+    let cell_right = (left + cell_width) as i32;
+    outline.draw(|gx, gy, coverage| {
+        let x = bounds.min.x as i32 + gx as i32;
+        let y = bounds.min.y as i32 + gy as i32;
+        if coverage < 0.5 || x < left as i32 || x >= cell_right {
+            return;
+        }
+        if !(0..CELL_HEIGHT as i32).contains(&y) {
+            return;
+        }
+        let (x, y) = (x as u32, y as u32);
+        dots[(y * bytes_per_row + x / 8) as usize] |= 0x80 >> (x % 8);
+    });
+}
+
+/// The characters of `text` left to right as printed: runs of Arabic and
+/// Hebrew, with the spaces and digits between their words, are reversed
+fn visual_order(text: &str) -> Vec<char> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = Vec::with_capacity(chars.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_rtl(chars[i]) {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        // The run ends at the last right-to-left letter before any
+        // left-to-right text
+        let mut end = i;
+        let mut j = i;
+        while j < chars.len() && !is_ltr(chars[j]) {
+            if is_rtl(chars[j]) {
+                end = j;
+            }
+            j += 1;
+        }
+        out.extend(chars[i..=end].iter().rev());
+        i = end + 1;
+    }
+    out
+}
+
+fn is_rtl(c: char) -> bool {
+    matches!(c as u32, 0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF)
+}
+
+fn is_ltr(c: char) -> bool {
+    c.is_alphabetic() && !is_rtl(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_is_one_cell_per_column() {
+        let raster = render_line("مرحبا 5 ☕");
+        // 8 columns plus 2 for the emoji, 12 dots each, are 15 bytes a row
+        assert_eq!(&raster[..8], &[0x1D, 0x76, 0x30, 0x00, 15, 0, 24, 0]);
+        assert_eq!(raster.len(), 8 + 15 * 24);
+        assert!(raster[8..].iter().any(|&b| b != 0));
+
+        let blank = render_line("   ");
+        assert!(blank[8..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_right_to_left_runs_are_reversed() {
+        let order: String = visual_order("Total שלום עולם 12").into_iter().collect();
+        assert_eq!(order, "Total םלוע םולש 12");
+    }
+}
@@ -0,0 +1,198 @@
+// src/realtime_status.rs
+// ESC/POS real-time status requests (DLE EOT) and what the replies mean
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use crate::backend::PrinterState;
+
+/// How long the printer gets to answer each request. Printers answer within
+/// milliseconds, even mid-job; no answer means the connection only goes one
+/// way or the printer doesn't support real-time status.
+pub const STATUS_TIMEOUT: Duration = Duration::from_millis(500);
+
+const DLE: u8 = 0x10;
+const EOT: u8 = 0x04;
+
+/// A connection to the printer that can also be read from
+pub(crate) trait StatusLink {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    /// Read what the printer sent, waiting up to `timeout`. `Ok(0)` if
+    /// nothing came.
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize>;
+}
+
+impl StatusLink for TcpStream {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_all(bytes)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.set_read_timeout(Some(timeout))?;
+        match self.read(buf) {
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Ok(0),
+                _ => Err(e),
+            },
+            result => result,
+        }
+    }
+}
+
+/// Ask for the printer, offline cause, error cause and paper sensor status
+/// (DLE EOT 1 to 4). `None` if the printer doesn't answer.
+pub(crate) fn query(link: &mut dyn StatusLink) -> io::Result<Option<PrinterState>> {
+    let mut replies = [0u8; 4];
+    for (n, reply) in (1u8..=4).zip(replies.iter_mut()) {
+        link.send(&[DLE, EOT, n])?;
+        match receive_status(link)? {
+            Some(byte) => *reply = byte,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(parse_status(replies)))
+}
+
+/// The next status byte. Status bytes have bits 1 and 4 set and bits 0 and
+/// 7 clear, so XON/XOFF and other chatter are skipped.
+fn receive_status(link: &mut dyn StatusLink) -> io::Result<Option<u8>> {
+    let deadline = Instant::now() + STATUS_TIMEOUT;
+    let mut buf = [0u8; 16];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(None);
+        }
+        let n = link.receive(&mut buf, left)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        if let Some(&byte) = buf[..n].iter().find(|&&b| b & 0x93 == 0x12) {
+            return Ok(Some(byte));
+        }
+    }
+}
+
+/// Turn the replies to DLE EOT 1 to 4 into a [`PrinterState`]
+pub(crate) fn parse_status([printer, offline, error, paper]: [u8; 4]) -> PrinterState {
+    let conditions = [
+        (printer & 0x08 != 0, "offline", "Offline"),
+        (offline & 0x04 != 0, "door-open", "Cover open"),
+        (
+            offline & 0x20 != 0 || paper & 0x60 != 0,
+            "media-empty",
+            "Out of paper",
+        ),
+        (error & 0x08 != 0, "media-jam", "Cutter jammed"),
+        (error & 0x20 != 0, "service-requested", "Printer error"),
+        (paper & 0x0C != 0, "media-low", "Paper low"),
+    ];
+    let found: Vec<_> = conditions.iter().filter(|(set, ..)| *set).collect();
+
+    let reasons: Vec<String> = found
+        .iter()
+        .map(|(_, reason, _)| reason.to_string())
+        .collect();
+    let paper = if reasons.iter().any(|r| r == "media-empty") {
+        "empty"
+    } else if reasons.iter().any(|r| r == "media-low") {
+        "low"
+    } else {
+        "ok"
+    };
+    // Paper running low doesn't stop printing
+    let stopped = reasons.iter().any(|r| r != "media-low");
+    let message = found
+        .iter()
+        .map(|(_, _, message)| *message)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    PrinterState {
+        state: if stopped { "stopped" } else { "idle" }.to_string(),
+        reasons,
+        message: Some(message).filter(|m| !m.is_empty()),
+        model: None,
+        serial_number: None,
+        paper: Some(paper.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A printer answering each request with the next of `replies`, after
+    /// some noise
+    struct FakePrinter {
+        replies: Vec<u8>,
+        requests: Vec<u8>,
+    }
+
+    impl StatusLink for FakePrinter {
+        fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.requests.extend_from_slice(bytes);
+            Ok(())
+        }
+
+        fn receive(&mut self, buf: &mut [u8], _timeout: Duration) -> io::Result<usize> {
+            if self.replies.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = 0x11; // XON
+            buf[1] = self.replies.remove(0);
+            Ok(2)
+        }
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let ready = parse_status([0x16, 0x12, 0x12, 0x12]);
+        assert_eq!(ready.state, "idle");
+        assert!(ready.reasons.is_empty());
+        assert_eq!((ready.message, ready.paper.as_deref()), (None, Some("ok")));
+
+        let near_end = parse_status([0x12, 0x12, 0x12, 0x1E]);
+        assert_eq!(near_end.state, "idle");
+        assert_eq!(near_end.paper.as_deref(), Some("low"));
+
+        // Cover open and out of paper, so offline
+        let stuck = parse_status([0x1A, 0x36, 0x12, 0x72]);
+        assert_eq!(stuck.state, "stopped");
+        assert_eq!(stuck.reasons, ["offline", "door-open", "media-empty"]);
+        assert_eq!(
+            stuck.message.as_deref(),
+            Some("Offline, Cover open, Out of paper")
+        );
+        assert_eq!(stuck.paper.as_deref(), Some("empty"));
+
+        let jammed = parse_status([0x1A, 0x52, 0x3A, 0x12]);
+        assert_eq!(
+            jammed.reasons,
+            ["offline", "media-jam", "service-requested"]
+        );
+    }
+
+    #[test]
+    fn test_query_sends_dle_eot_1_to_4() {
+        let mut printer = FakePrinter {
+            replies: vec![0x16, 0x12, 0x12, 0x1E],
+            requests: Vec::new(),
+        };
+        let state = query(&mut printer).unwrap().unwrap();
+        assert_eq!(
+            printer.requests,
+            [0x10, 0x04, 1, 0x10, 0x04, 2, 0x10, 0x04, 3, 0x10, 0x04, 4]
+        );
+        assert_eq!(state.paper.as_deref(), Some("low"));
+
+        // A printer that doesn't answer has no state to report
+        let mut silent = FakePrinter {
+            replies: Vec::new(),
+            requests: Vec::new(),
+        };
+        assert_eq!(query(&mut silent).unwrap(), None);
+    }
+}
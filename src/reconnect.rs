@@ -0,0 +1,453 @@
+// src/reconnect.rs
+// Notice when the receipt printer drops off and bring it back with backoff
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::backend::PrintBackend;
+use crate::lock::LockExt;
+use crate::printer::PrinterManager;
+
+/// Whether the receipt printer can be reached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ConnectionState {
+    Disconnected,
+    Connected,
+    /// The printer stopped answering and is being retried
+    Reconnecting {
+        /// Retries made so far, counting the one being waited for
+        attempt: u32,
+        retry_in_ms: u64,
+        /// Why the last check failed
+        error: String,
+    },
+}
+
+/// How often to check the printer and how quickly to retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Time between status checks while the printer answers
+    pub check_interval: Duration,
+    /// Wait before the first retry, doubled after every failed one
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_secs(5),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Wait before retry number `attempt` (1-based)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+/// A dropped connection waiting to be retried
+pub(crate) struct Reconnecting {
+    /// Backends open the printer for each job, so reconnecting is a matter
+    /// of the same backend answering its status check again
+    backend: Arc<dyn PrintBackend>,
+    attempt: u32,
+    next_attempt: Instant,
+    error: String,
+}
+
+impl Reconnecting {
+    pub(crate) fn state(&self) -> ConnectionState {
+        ConnectionState::Reconnecting {
+            attempt: self.attempt,
+            retry_in_ms: self
+                .next_attempt
+                .saturating_duration_since(Instant::now())
+                .as_millis() as u64,
+            error: self.error.clone(),
+        }
+    }
+
+    pub(crate) fn close(&self) {
+        self.backend.close();
+    }
+}
+
+/// Called with a printer's id and new state whenever its connection drops
+/// or comes back
+pub type StateListener = Box<dyn Fn(&str, ConnectionState) + Send>;
+
+/// Sent to the supervisor thread
+enum Signal {
+    /// Check every printer now and retry dropped ones without waiting
+    Check,
+    Stop,
+}
+
+/// Asks a [`ReconnectSupervisor`] to check its printers straight away, e.g.
+/// when a USB device was plugged in or removed
+#[derive(Clone)]
+pub struct CheckTrigger(mpsc::Sender<Signal>);
+
+impl CheckTrigger {
+    pub fn check_now(&self) {
+        let _ = self.0.send(Signal::Check);
+    }
+}
+
+/// Background thread that checks the registered printers and reconnects
+/// them.
+///
+/// Connected printers are asked for their status every `check_interval`.
+/// When a printer fails the check the manager stops reporting it connected,
+/// so jobs fail fast instead of waiting on a dead printer, and the check is
+/// retried with exponential backoff until the printer answers again. A
+/// manual connect or disconnect cancels the retries, and a
+/// [`CheckTrigger`] skips the wait.
+///
+/// The thread stops when the supervisor is dropped.
+pub struct ReconnectSupervisor {
+    signals: mpsc::Sender<Signal>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ReconnectSupervisor {
+    pub fn spawn(
+        manager: Arc<Mutex<PrinterManager>>,
+        policy: ReconnectPolicy,
+        on_change: StateListener,
+    ) -> Self {
+        let (sender, signals) = mpsc::channel::<Signal>();
+        let thread = std::thread::Builder::new()
+            .name("printer-reconnect".to_string())
+            .spawn(move || {
+                let mut next_check = Instant::now() + policy.check_interval;
+                loop {
+                    let wake = manager
+                        .lock_or_recover()
+                        .printers
+                        .values()
+                        .filter_map(|printer| printer.reconnecting.as_ref())
+                        .map(|reconnecting| reconnecting.next_attempt)
+                        .fold(next_check, Instant::min);
+                    let triggered = match signals
+                        .recv_timeout(wake.saturating_duration_since(Instant::now()))
+                    {
+                        Ok(Signal::Check) => {
+                            retry_now(&manager);
+                            true
+                        }
+                        Err(RecvTimeoutError::Timeout) => false,
+                        Ok(Signal::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                    };
+
+                    let check_connected = triggered || Instant::now() >= next_check;
+                    if check_connected {
+                        next_check = Instant::now() + policy.check_interval;
+                    }
+                    for (printer_id, state) in step(&manager, &policy, check_connected) {
+                        on_change(&printer_id, state);
+                    }
+                }
+            })
+            .expect("failed to spawn reconnect thread");
+        Self {
+            signals: sender,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn trigger(&self) -> CheckTrigger {
+        CheckTrigger(self.signals.clone())
+    }
+
+    /// Stop checking and wait for the thread to finish. A check in progress
+    /// is allowed to complete.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            // Triggers hold the channel open, so ask the thread to stop
+            let _ = self.signals.send(Signal::Stop);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ReconnectSupervisor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Make every dropped printer due for a retry
+fn retry_now(manager: &Mutex<PrinterManager>) {
+    let now = Instant::now();
+    for printer in manager.lock_or_recover().printers.values_mut() {
+        if let Some(reconnecting) = printer.reconnecting.as_mut() {
+            reconnecting.next_attempt = now;
+        }
+    }
+}
+
+/// Retry the dropped printers that are due and, with `check_connected`,
+/// check the connected ones, returning the printers whose state changed.
+/// Status checks run without the manager lock held.
+fn step(
+    manager: &Mutex<PrinterManager>,
+    policy: &ReconnectPolicy,
+    check_connected: bool,
+) -> Vec<(String, ConnectionState)> {
+    let now = Instant::now();
+    let due: Vec<(String, Arc<dyn PrintBackend>, bool)> = manager
+        .lock_or_recover()
+        .printers
+        .iter()
+        .filter_map(
+            |(id, printer)| match (&printer.reconnecting, &printer.connection) {
+                (Some(reconnecting), _) if reconnecting.next_attempt <= now => {
+                    Some((id.clone(), Arc::clone(&reconnecting.backend), true))
+                }
+                (None, Some(connection)) if check_connected => {
+                    Some((id.clone(), Arc::clone(connection), false))
+                }
+                _ => None,
+            },
+        )
+        .collect();
+
+    due.into_iter()
+        .filter_map(|(printer_id, backend, retrying)| {
+            let result = backend.query_status();
+            let mut manager = manager.lock_or_recover();
+            let state = if retrying {
+                retried(&mut manager, &printer_id, backend, result, policy)
+            } else {
+                checked(&mut manager, &printer_id, backend, result, policy)
+            }?;
+            Some((printer_id, state))
+        })
+        .collect()
+}
+
+/// Record the outcome of a retry
+fn retried(
+    manager: &mut PrinterManager,
+    printer_id: &str,
+    backend: Arc<dyn PrintBackend>,
+    result: Result<(), String>,
+    policy: &ReconnectPolicy,
+) -> Option<ConnectionState> {
+    // Connected or disconnected by hand in the meantime
+    let reconnecting = manager
+        .printers
+        .get_mut(printer_id)?
+        .reconnecting
+        .as_mut()
+        .filter(|reconnecting| Arc::ptr_eq(&reconnecting.backend, &backend))?;
+    match result {
+        Ok(()) => {
+            let attempts = reconnecting.attempt;
+            manager.restore_connection(printer_id, backend);
+            log::info!(
+                "Printer '{}' reconnected after {} attempt(s)",
+                printer_id,
+                attempts
+            );
+            Some(ConnectionState::Connected)
+        }
+        Err(e) => {
+            reconnecting.attempt += 1;
+            reconnecting.next_attempt = Instant::now() + policy.delay(reconnecting.attempt);
+            reconnecting.error = e;
+            log::debug!(
+                "Reconnect attempt {} for printer '{}' failed",
+                reconnecting.attempt - 1,
+                printer_id
+            );
+            Some(reconnecting.state())
+        }
+    }
+}
+
+/// Record the outcome of a connected printer's status check
+fn checked(
+    manager: &mut PrinterManager,
+    printer_id: &str,
+    backend: Arc<dyn PrintBackend>,
+    result: Result<(), String>,
+    policy: &ReconnectPolicy,
+) -> Option<ConnectionState> {
+    let error = result.err()?;
+    let current = manager.printers.get(printer_id)?.connection.as_ref();
+    if !current.is_some_and(|connection| Arc::ptr_eq(connection, &backend)) {
+        return None;
+    }
+    log::warn!("Printer '{}' connection lost: {}", printer_id, error);
+    let reconnecting = Reconnecting {
+        backend,
+        attempt: 1,
+        next_attempt: Instant::now() + policy.delay(1),
+        error,
+    };
+    let state = reconnecting.state();
+    manager.drop_connection(printer_id, reconnecting);
+    Some(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::printer::DEFAULT_PRINTER;
+
+    fn manager_with(printer: &MockBackend) -> PrinterManager {
+        let mut manager = PrinterManager::new();
+        manager.connect_backend(DEFAULT_PRINTER, None, Arc::new(printer.clone()));
+        manager
+    }
+
+    #[test]
+    fn test_delay_doubles_up_to_max() {
+        let policy = ReconnectPolicy::default();
+        let delays: Vec<u64> = (1..=8).map(|n| policy.delay(n).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(policy.delay(200), policy.max_delay);
+    }
+
+    #[test]
+    fn test_step_drops_and_restores_connection() {
+        let printer = MockBackend::new();
+        let manager = Mutex::new(manager_with(&printer));
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::ZERO,
+            ..ReconnectPolicy::default()
+        };
+
+        assert_eq!(step(&manager, &policy, true), []);
+
+        printer.set_offline(true);
+        let changes = step(&manager, &policy, true);
+        assert!(matches!(
+            changes[..],
+            [(_, ConnectionState::Reconnecting { attempt: 1, .. })]
+        ));
+        assert!(!manager.lock().unwrap().is_connected());
+
+        // Still down
+        let changes = step(&manager, &policy, false);
+        assert!(matches!(
+            changes[..],
+            [(_, ConnectionState::Reconnecting { attempt: 2, .. })]
+        ));
+
+        printer.set_offline(false);
+        assert_eq!(
+            step(&manager, &policy, false),
+            [(DEFAULT_PRINTER.to_string(), ConnectionState::Connected)]
+        );
+        let manager = manager.lock().unwrap();
+        assert!(manager.is_connected());
+        assert_eq!(manager.connection_state(), ConnectionState::Connected);
+    }
+
+    #[test]
+    fn test_disconnect_cancels_reconnect() {
+        let printer = MockBackend::new();
+        printer.set_offline(true);
+        let manager = Mutex::new(manager_with(&printer));
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::ZERO,
+            ..ReconnectPolicy::default()
+        };
+
+        assert_eq!(step(&manager, &policy, true).len(), 1);
+        manager.lock().unwrap().disconnect();
+        printer.set_offline(false);
+        assert_eq!(step(&manager, &policy, true), []);
+        assert_eq!(
+            manager.lock().unwrap().connection_state(),
+            ConnectionState::Disconnected
+        );
+    }
+
+    #[test]
+    fn test_supervisor_reports_changes() {
+        let printer = MockBackend::new();
+        let manager = Arc::new(Mutex::new(manager_with(&printer)));
+        let policy = ReconnectPolicy {
+            check_interval: Duration::from_millis(10),
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(20),
+        };
+        let (sender, changes) = mpsc::channel();
+        let supervisor = ReconnectSupervisor::spawn(
+            Arc::clone(&manager),
+            policy,
+            Box::new(move |_, state| {
+                let _ = sender.send(state);
+            }),
+        );
+
+        printer.set_offline(true);
+        let timeout = Duration::from_secs(5);
+        let state = changes.recv_timeout(timeout).unwrap();
+        assert!(matches!(state, ConnectionState::Reconnecting { .. }));
+        printer.set_offline(false);
+        while changes.recv_timeout(timeout).unwrap() != ConnectionState::Connected {}
+        supervisor.stop();
+        assert!(manager.lock().unwrap().is_connected());
+    }
+
+    #[test]
+    fn test_trigger_checks_without_waiting() {
+        let printer = MockBackend::new();
+        let manager = Arc::new(Mutex::new(manager_with(&printer)));
+        let policy = ReconnectPolicy {
+            check_interval: Duration::from_secs(3600),
+            initial_delay: Duration::from_secs(3600),
+            max_delay: Duration::from_secs(3600),
+        };
+        let (sender, changes) = mpsc::channel();
+        let supervisor = ReconnectSupervisor::spawn(
+            Arc::clone(&manager),
+            policy,
+            Box::new(move |_, state| {
+                let _ = sender.send(state);
+            }),
+        );
+        let trigger = supervisor.trigger();
+
+        let timeout = Duration::from_secs(5);
+        printer.set_offline(true);
+        trigger.check_now();
+        let state = changes.recv_timeout(timeout).unwrap();
+        assert!(matches!(state, ConnectionState::Reconnecting { .. }));
+
+        printer.set_offline(false);
+        trigger.check_now();
+        assert_eq!(
+            changes.recv_timeout(timeout).unwrap(),
+            ConnectionState::Connected
+        );
+        // A trigger left behind doesn't keep the thread alive
+        supervisor.stop();
+        trigger.check_now();
+    }
+}
@@ -0,0 +1,160 @@
+// src/routing.rs
+// Split an order's items into tickets for printers by role (kitchen, bar, ...)
+
+use serde::{Deserialize, Serialize};
+
+use crate::template_render::{ReceiptData, ReceiptItem};
+
+/// The item field a [`RoutingRule`] looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingField {
+    Category,
+    Sku,
+    Name,
+    Unit,
+}
+
+impl RoutingField {
+    fn value(self, item: &ReceiptItem) -> Option<&str> {
+        match self {
+            RoutingField::Category => item.category.as_deref(),
+            RoutingField::Sku => item.sku.as_deref(),
+            RoutingField::Name => Some(&item.name),
+            RoutingField::Unit => item.unit.as_deref(),
+        }
+    }
+}
+
+/// Send items whose `field` equals `equals` (ignoring case) to every
+/// connected printer with `role`, e.g. category "drinks" to the bar
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingRule {
+    pub field: RoutingField,
+    pub equals: String,
+    pub role: String,
+    /// Cached template to print the ticket with; the active template if unset
+    #[serde(default)]
+    pub template_id: Option<String>,
+}
+
+impl RoutingRule {
+    pub fn matches(&self, item: &ReceiptItem) -> bool {
+        self.field
+            .value(item)
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case(self.equals.trim()))
+    }
+}
+
+/// Contents of `routing.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoutingConfig {
+    #[serde(default)]
+    pub rules: Vec<RoutingRule>,
+}
+
+/// The part of an order that goes to the printers with one role
+#[derive(Debug, Clone)]
+pub struct RoutedTicket {
+    pub role: String,
+    pub template_id: Option<String>,
+    /// The order with only the items routed here
+    pub data: ReceiptData,
+}
+
+/// Check every item against `rules` in order; the first rule that matches
+/// decides where the item goes, and items no rule matches aren't routed.
+/// Tickets come out in the order of the first rule that sent them an item.
+pub fn route_items(data: &ReceiptData, rules: &[RoutingRule]) -> Vec<RoutedTicket> {
+    let mut tickets: Vec<RoutedTicket> = Vec::new();
+    for item in &data.items {
+        let Some(rule) = rules.iter().find(|rule| rule.matches(item)) else {
+            continue;
+        };
+        let ticket = match tickets
+            .iter()
+            .position(|t| t.role == rule.role && t.template_id == rule.template_id)
+        {
+            Some(index) => &mut tickets[index],
+            None => {
+                tickets.push(RoutedTicket {
+                    role: rule.role.clone(),
+                    template_id: rule.template_id.clone(),
+                    data: ReceiptData {
+                        items: Vec::new(),
+                        ..data.clone()
+                    },
+                });
+                tickets.last_mut().unwrap()
+            }
+        };
+        ticket.data.items.push(item.clone());
+    }
+    tickets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(name: &str, category: &str) -> ReceiptItem {
+        serde_json::from_value(serde_json::json!({ "name": name, "category": category })).unwrap()
+    }
+
+    fn rule(equals: &str, role: &str) -> RoutingRule {
+        RoutingRule {
+            field: RoutingField::Category,
+            equals: equals.to_string(),
+            role: role.to_string(),
+            template_id: None,
+        }
+    }
+
+    #[test]
+    fn test_route_items_groups_by_role() {
+        let data = ReceiptData {
+            order_id: "42".to_string(),
+            items: vec![
+                item("Burger", "Food"),
+                item("Cola", "drinks"),
+                item("Fries", "food"),
+                item("Gift card", "other"),
+            ],
+            ..Default::default()
+        };
+        let rules = [rule("drinks", "bar"), rule("food", "kitchen")];
+
+        let tickets = route_items(&data, &rules);
+        let routed: Vec<(&str, Vec<&str>)> = tickets
+            .iter()
+            .map(|ticket| {
+                let names = ticket.data.items.iter().map(|i| i.name.as_str()).collect();
+                (ticket.role.as_str(), names)
+            })
+            .collect();
+        assert_eq!(
+            routed,
+            [("kitchen", vec!["Burger", "Fries"]), ("bar", vec!["Cola"])]
+        );
+        assert_eq!(tickets[0].data.order_id, "42");
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let data = ReceiptData {
+            items: vec![item("Espresso", "drinks")],
+            ..Default::default()
+        };
+        let by_name = RoutingRule {
+            field: RoutingField::Name,
+            equals: "espresso".to_string(),
+            role: "barista".to_string(),
+            template_id: Some("coffee".to_string()),
+        };
+        let tickets = route_items(&data, &[by_name, rule("drinks", "bar")]);
+        assert_eq!(tickets.len(), 1);
+        assert_eq!(tickets[0].role, "barista");
+        assert_eq!(tickets[0].template_id.as_deref(), Some("coffee"));
+        assert!(route_items(&data, &[]).is_empty());
+    }
+}
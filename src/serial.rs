@@ -0,0 +1,247 @@
+// src/serial.rs
+// Apply baud rate, framing and flow control to an opened serial port
+
+use std::fs::File;
+
+use crate::config::SerialSettings;
+#[cfg(target_os = "windows")]
+use crate::config::Timeouts;
+
+/// Configure `file` if it is a serial port; anything else (a USB printer
+/// port, a parallel port, a plain file) is left alone
+pub(crate) fn configure(file: &File, settings: &SerialSettings) -> Result<(), String> {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::io::AsRawHandle;
+        configure_handle(file.as_raw_handle() as _, settings)
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        configure_fd(file.as_raw_fd(), settings)
+    }
+    #[cfg(not(any(unix, target_os = "windows")))]
+    {
+        let _ = (file, settings);
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn configure_handle(
+    handle: windows_sys::Win32::Foundation::HANDLE,
+    settings: &SerialSettings,
+) -> Result<(), String> {
+    use windows_sys::Win32::Devices::Communication::{
+        GetCommState, SetCommState, DCB, EVENPARITY, NOPARITY, ODDPARITY, ONESTOPBIT, TWOSTOPBITS,
+    };
+    use windows_sys::Win32::Foundation::GetLastError;
+
+    // DCB flag bits
+    const F_BINARY: u32 = 1 << 0;
+    const F_PARITY: u32 = 1 << 1;
+    const F_OUTX_CTS_FLOW: u32 = 1 << 2;
+    const F_OUTX_DSR_FLOW: u32 = 1 << 3;
+    const F_DTR_CONTROL_MASK: u32 = 0b11 << 4;
+    const F_DTR_CONTROL_ENABLE: u32 = 1 << 4;
+    const F_DTR_CONTROL_HANDSHAKE: u32 = 2 << 4;
+    const F_OUTX: u32 = 1 << 8;
+    const F_INX: u32 = 1 << 9;
+    const F_RTS_CONTROL_MASK: u32 = 0b11 << 12;
+    const F_RTS_CONTROL_ENABLE: u32 = 1 << 12;
+    const F_RTS_CONTROL_HANDSHAKE: u32 = 2 << 12;
+
+    let mut dcb: DCB = unsafe { std::mem::zeroed() };
+    dcb.DCBlength = std::mem::size_of::<DCB>() as u32;
+    if unsafe { GetCommState(handle, &mut dcb) } == 0 {
+        // Not a COM port
+        return Ok(());
+    }
+
+    dcb.BaudRate = settings.baud_rate;
+    dcb.ByteSize = settings.data_bits;
+    dcb.Parity = match settings.parity.as_str() {
+        "odd" => ODDPARITY,
+        "even" => EVENPARITY,
+        _ => NOPARITY,
+    };
+    dcb.StopBits = if settings.stop_bits == 2 {
+        TWOSTOPBITS
+    } else {
+        ONESTOPBIT
+    };
+
+    let mut flags = (dcb._bitfield | F_BINARY)
+        & !(F_PARITY
+            | F_OUTX_CTS_FLOW
+            | F_OUTX_DSR_FLOW
+            | F_DTR_CONTROL_MASK
+            | F_OUTX
+            | F_INX
+            | F_RTS_CONTROL_MASK);
+    if settings.parity != "none" {
+        flags |= F_PARITY;
+    }
+    flags |= match settings.flow_control.as_str() {
+        "hardware" => F_OUTX_CTS_FLOW | F_RTS_CONTROL_HANDSHAKE | F_DTR_CONTROL_ENABLE,
+        "software" => F_OUTX | F_INX | F_RTS_CONTROL_ENABLE | F_DTR_CONTROL_ENABLE,
+        "dsr" => F_OUTX_DSR_FLOW | F_DTR_CONTROL_HANDSHAKE | F_RTS_CONTROL_ENABLE,
+        _ => F_RTS_CONTROL_ENABLE | F_DTR_CONTROL_ENABLE,
+    };
+    dcb._bitfield = flags;
+    dcb.XonChar = 0x11;
+    dcb.XoffChar = 0x13;
+
+    if unsafe { SetCommState(handle, &dcb) } == 0 {
+        let err = unsafe { GetLastError() };
+        return Err(format!(
+            "Cannot configure serial port: Windows error code {}",
+            err
+        ));
+    }
+    Ok(())
+}
+
+/// Make reads and writes on a COM or LPT port give up after `timeouts`.
+/// Fails on handles that aren't ports.
+#[cfg(target_os = "windows")]
+pub(crate) fn set_timeouts_handle(
+    handle: windows_sys::Win32::Foundation::HANDLE,
+    timeouts: &Timeouts,
+) -> Result<(), String> {
+    use windows_sys::Win32::Devices::Communication::{SetCommTimeouts, COMMTIMEOUTS};
+
+    let millis = |ms: u64| ms.min(u32::MAX as u64) as u32;
+    let timeouts = COMMTIMEOUTS {
+        ReadIntervalTimeout: 0,
+        ReadTotalTimeoutMultiplier: 0,
+        ReadTotalTimeoutConstant: millis(timeouts.read_ms),
+        WriteTotalTimeoutMultiplier: 0,
+        WriteTotalTimeoutConstant: millis(timeouts.write_ms),
+    };
+    if unsafe { SetCommTimeouts(handle, &timeouts) } == 0 {
+        return Err(format!(
+            "Cannot set port timeouts: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn configure_fd(fd: std::os::unix::io::RawFd, settings: &SerialSettings) -> Result<(), String> {
+    if unsafe { libc::isatty(fd) } != 1 {
+        return Ok(());
+    }
+    let error = || {
+        format!(
+            "Cannot configure serial port: {}",
+            std::io::Error::last_os_error()
+        )
+    };
+
+    let mut tty: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut tty) } != 0 {
+        return Err(error());
+    }
+    // Pass every byte through untouched
+    unsafe { libc::cfmakeraw(&mut tty) };
+
+    let speed = match settings.baud_rate {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        other => return Err(format!("Unsupported baud rate: {}", other)),
+    };
+    if unsafe { libc::cfsetispeed(&mut tty, speed) } != 0
+        || unsafe { libc::cfsetospeed(&mut tty, speed) } != 0
+    {
+        return Err(error());
+    }
+
+    tty.c_cflag &= !(libc::CSIZE | libc::PARENB | libc::PARODD | libc::CSTOPB | libc::CRTSCTS);
+    tty.c_cflag |= libc::CLOCAL
+        | libc::CREAD
+        | match settings.data_bits {
+            5 => libc::CS5,
+            6 => libc::CS6,
+            7 => libc::CS7,
+            _ => libc::CS8,
+        };
+    match settings.parity.as_str() {
+        "odd" => tty.c_cflag |= libc::PARENB | libc::PARODD,
+        "even" => tty.c_cflag |= libc::PARENB,
+        _ => {}
+    }
+    if settings.stop_bits == 2 {
+        tty.c_cflag |= libc::CSTOPB;
+    }
+
+    tty.c_iflag &= !(libc::IXON | libc::IXOFF | libc::IXANY);
+    match settings.flow_control.as_str() {
+        "hardware" => tty.c_cflag |= libc::CRTSCTS,
+        "software" => tty.c_iflag |= libc::IXON | libc::IXOFF,
+        _ => {}
+    }
+
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &tty) } != 0 {
+        return Err(error());
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    /// A pseudo-terminal stands in for the serial port
+    fn pty() -> (File, File) {
+        let (mut master, mut slave) = (0, 0);
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, 0, "openpty failed");
+        unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) }
+    }
+
+    #[test]
+    fn test_configure_sets_line_settings() {
+        let (_master, port) = pty();
+        let settings = SerialSettings {
+            baud_rate: 38400,
+            data_bits: 7,
+            parity: "even".to_string(),
+            stop_bits: 2,
+            flow_control: "software".to_string(),
+        };
+        configure(&port, &settings).unwrap();
+
+        let mut tty: libc::termios = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::tcgetattr(port.as_raw_fd(), &mut tty) }, 0);
+        assert_eq!(unsafe { libc::cfgetospeed(&tty) }, libc::B38400);
+        // A pty keeps 8 data bits and no parity whatever is asked for
+        assert_ne!(tty.c_cflag & libc::CSTOPB, 0);
+        assert_ne!(tty.c_iflag & libc::IXON, 0);
+    }
+
+    #[test]
+    fn test_configure_leaves_other_files_alone() {
+        let path = std::env::temp_dir().join(format!("nexora-serial-{}", std::process::id()));
+        let file = File::create(&path).unwrap();
+        configure(&file, &SerialSettings::default()).unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
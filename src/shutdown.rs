@@ -0,0 +1,101 @@
+// src/shutdown.rs
+// Orderly exit: drain print jobs, spool the rest and release the printers
+
+use std::sync::Mutex;
+
+use crate::config::ShutdownSettings;
+use crate::lock::LockExt;
+use crate::print_worker::{PrintWorker, ShutdownReport};
+use crate::{BarcodePrinterManager, PrinterManager};
+
+/// Wind down printing before the process exits.
+///
+/// The worker stops taking jobs (the HTTP server answers 503 from here on),
+/// the job being printed finishes or is aborted after the drain timeout,
+/// queued jobs are saved to the spool, and both printers are disconnected so
+/// serial ports aren't left locked.
+pub fn shutdown(
+    worker: &PrintWorker,
+    printer_manager: &Mutex<PrinterManager>,
+    barcode_manager: &Mutex<BarcodePrinterManager>,
+    settings: &ShutdownSettings,
+) -> ShutdownReport {
+    log::info!(
+        "Shutting down, waiting up to {}s for the current print job",
+        settings.drain_timeout_secs
+    );
+    let report = worker.shutdown(settings.drain_timeout());
+    log::info!(
+        "Print worker drained: {} job(s) spooled, {} lost{}",
+        report.spooled,
+        report.lost,
+        if report.timed_out { ", current job aborted" } else { "" }
+    );
+
+    {
+        let mut manager = printer_manager.lock_or_recover();
+        let stats = manager.print_stats();
+        log::info!(
+            "Printed {} job(s), {} failed, {} bytes since {}",
+            stats.total_prints,
+            stats.failures.total(),
+            stats.bytes_written,
+            stats.started_at
+        );
+        manager.disconnect();
+    }
+    barcode_manager.lock_or_recover().disconnect();
+
+    log::logger().flush();
+    report
+}
+
+/// Resolves on Ctrl-C, SIGTERM, or a Windows close or system shutdown event
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(windows)]
+    let terminate = async {
+        use tokio::signal::windows::{ctrl_close, ctrl_shutdown};
+        match (ctrl_close(), ctrl_shutdown()) {
+            (Ok(mut close), Ok(mut shutdown)) => {
+                tokio::select! {
+                    _ = close.recv() => {}
+                    _ = shutdown.recv() => {}
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                log::error!("Failed to listen for close events: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(any(unix, windows)))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    log::info!("Shutdown signal received");
+}
@@ -0,0 +1,429 @@
+// src/snmp.rs
+// Minimal SNMPv2c GET client for the standard Printer and Host Resources MIBs
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+
+use crate::backend::PrinterState;
+
+pub const DEFAULT_PORT: u16 = 161;
+const COMMUNITY: &str = "public";
+
+/// How long a printer gets to answer; most receipt printers without an SNMP
+/// agent simply never do
+pub const SNMP_TIMEOUT: Duration = Duration::from_millis(700);
+
+// HOST-RESOURCES-MIB and Printer-MIB objects for the first device
+const HR_DEVICE_DESCR: &str = "1.3.6.1.2.1.25.3.2.1.3.1";
+const HR_PRINTER_STATUS: &str = "1.3.6.1.2.1.25.3.5.1.1.1";
+const HR_PRINTER_ERROR_STATE: &str = "1.3.6.1.2.1.25.3.5.1.2.1";
+const PRT_SERIAL_NUMBER: &str = "1.3.6.1.2.1.43.5.1.1.17.1";
+const SYS_DESCR: &str = "1.3.6.1.2.1.1.1.0";
+
+// BER tags
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_GET_REQUEST: u8 = 0xA0;
+const TAG_RESPONSE: u8 = 0xA2;
+
+static REQUEST_ID: AtomicI32 = AtomicI32::new(1);
+
+/// Ask the printer at `host` (an address, optionally with `:port` of the
+/// print service, which is ignored) for its model, serial number and state.
+///
+/// `Ok(None)` if nothing answers, which is the usual case for printers
+/// without SNMP.
+pub fn query_printer(host: &str, timeout: Duration) -> Result<Option<PrinterState>, String> {
+    let host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+    let addr = (host, DEFAULT_PORT)
+        .to_socket_addrs()
+        .map_err(|e| format!("Cannot resolve {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("Cannot resolve {}", host))?;
+    query(addr, timeout)
+}
+
+fn query(addr: SocketAddr, timeout: Duration) -> Result<Option<PrinterState>, String> {
+    let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })
+        .and_then(|socket| socket.connect(addr).map(|()| socket))
+        .and_then(|socket| socket.set_read_timeout(Some(timeout)).map(|()| socket))
+        .map_err(|e| format!("Cannot open SNMP socket: {}", e))?;
+
+    let oids = [
+        HR_DEVICE_DESCR,
+        SYS_DESCR,
+        PRT_SERIAL_NUMBER,
+        HR_PRINTER_STATUS,
+        HR_PRINTER_ERROR_STATE,
+    ];
+    let request_id = REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    socket
+        .send(&get_request(request_id, &oids))
+        .map_err(|e| format!("Cannot send SNMP request to {}: {}", addr, e))?;
+
+    let mut buf = [0u8; 2048];
+    let values = loop {
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None);
+            }
+            // ICMP port unreachable: no agent on this host
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => return Ok(None),
+            Err(e) => return Err(format!("SNMP request to {} failed: {}", addr, e)),
+        };
+        match parse_response(&buf[..len])? {
+            // A late answer to an earlier query
+            (id, _) if id != request_id => continue,
+            (_, values) => break values,
+        }
+    };
+    Ok(Some(printer_state(&values)))
+}
+
+/// Turn the answered objects into a [`PrinterState`]
+fn printer_state(values: &[(String, Value)]) -> PrinterState {
+    let get = |oid: &str| values.iter().find(|(o, _)| o == oid).map(|(_, v)| v);
+    let text = |oid: &str| match get(oid) {
+        Some(Value::Text(bytes)) => Some(String::from_utf8_lossy(bytes).trim().to_string())
+            .filter(|s| !s.is_empty()),
+        _ => None,
+    };
+
+    // hrPrinterDetectedErrorState is a bit string, most significant bit first
+    let errors = match get(HR_PRINTER_ERROR_STATE) {
+        Some(Value::Text(bytes)) => bytes.clone(),
+        _ => Vec::new(),
+    };
+    let bit = |n: usize| errors.get(n / 8).is_some_and(|byte| byte & (0x80 >> (n % 8)) != 0);
+    let mut reasons: Vec<String> = [
+        (0, "media-low"),
+        (1, "media-empty"),
+        (2, "toner-low"),
+        (3, "toner-empty"),
+        (4, "door-open"),
+        (5, "media-jam"),
+        (6, "offline"),
+        (7, "service-requested"),
+        (13, "media-empty"), // inputTrayEmpty
+    ]
+    .iter()
+    .filter(|(n, _)| bit(*n))
+    .map(|(_, reason)| reason.to_string())
+    .collect();
+    reasons.dedup();
+
+    let paper = if reasons.iter().any(|r| r == "media-empty") {
+        "empty"
+    } else if reasons.iter().any(|r| r == "media-low") {
+        "low"
+    } else {
+        "ok"
+    };
+    let stopped = reasons
+        .iter()
+        .any(|r| matches!(r.as_str(), "media-empty" | "door-open" | "media-jam" | "offline"));
+    let state = match get(HR_PRINTER_STATUS) {
+        _ if stopped => "stopped",
+        Some(Value::Integer(3)) => "idle",
+        Some(Value::Integer(4 | 5)) => "processing",
+        _ => "unknown",
+    };
+
+    PrinterState {
+        state: state.to_string(),
+        reasons,
+        message: None,
+        model: text(HR_DEVICE_DESCR).or_else(|| text(SYS_DESCR)),
+        serial_number: text(PRT_SERIAL_NUMBER),
+        // Without the error state there's nothing to say about paper
+        paper: get(HR_PRINTER_ERROR_STATE).map(|_| paper.to_string()),
+    }
+}
+
+// ==================== Encoding ====================
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&b| b == 0)
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend_from_slice(&bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn integer(value: i32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // Minimal two's complement: drop leading bytes that only repeat the sign
+    let mut start = 0;
+    while start < 3
+        && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    tlv(TAG_INTEGER, &bytes[start..])
+}
+
+fn oid(dotted: &str) -> Vec<u8> {
+    let arcs: Vec<u32> = dotted.split('.').filter_map(|arc| arc.parse().ok()).collect();
+    let mut content = vec![(arcs[0] * 40 + arcs[1]) as u8];
+    for &arc in &arcs[2..] {
+        let mut chunk = vec![(arc & 0x7F) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            chunk.push((rest & 0x7F) as u8 | 0x80);
+            rest >>= 7;
+        }
+        content.extend(chunk.iter().rev());
+    }
+    tlv(TAG_OID, &content)
+}
+
+/// An SNMPv2c GetRequest for `oids`
+fn get_request(request_id: i32, oids: &[&str]) -> Vec<u8> {
+    let bindings: Vec<u8> = oids
+        .iter()
+        .flat_map(|name| tlv(TAG_SEQUENCE, &[oid(name), tlv(TAG_NULL, &[])].concat()))
+        .collect();
+    let pdu = [
+        integer(request_id),
+        integer(0), // error-status
+        integer(0), // error-index
+        tlv(TAG_SEQUENCE, &bindings),
+    ]
+    .concat();
+    let message = [
+        integer(1), // version: v2c
+        tlv(TAG_OCTET_STRING, COMMUNITY.as_bytes()),
+        tlv(TAG_GET_REQUEST, &pdu),
+    ]
+    .concat();
+    tlv(TAG_SEQUENCE, &message)
+}
+
+// ==================== Decoding ====================
+
+/// A variable's value, as far as printers need
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Integer(i64),
+    Text(Vec<u8>),
+    /// noSuchObject, noSuchInstance and anything else
+    Other,
+}
+
+/// Read one TLV at `*pos`, returning its tag and content
+fn read_tlv<'a>(buf: &'a [u8], pos: &mut usize) -> Result<(u8, &'a [u8]), String> {
+    let malformed = || "Malformed SNMP response".to_string();
+    let tag = *buf.get(*pos).ok_or_else(malformed)?;
+    let first = *buf.get(*pos + 1).ok_or_else(malformed)? as usize;
+    *pos += 2;
+    let len = if first < 0x80 {
+        first
+    } else {
+        let count = first & 0x7F;
+        let bytes = buf.get(*pos..*pos + count).ok_or_else(malformed)?;
+        if count > 4 {
+            return Err(malformed());
+        }
+        *pos += count;
+        bytes.iter().fold(0usize, |len, &b| (len << 8) | b as usize)
+    };
+    let content = buf.get(*pos..*pos + len).ok_or_else(malformed)?;
+    *pos += len;
+    Ok((tag, content))
+}
+
+fn decode_integer(content: &[u8]) -> i64 {
+    let initial = if content.first().is_some_and(|b| b & 0x80 != 0) { -1 } else { 0 };
+    content.iter().fold(initial, |value, &b| (value << 8) | b as i64)
+}
+
+fn decode_oid(content: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    if let Some(&first) = content.first() {
+        arcs.push((first / 40) as u32);
+        arcs.push((first % 40) as u32);
+    }
+    let mut arc = 0u32;
+    for &b in content.iter().skip(1) {
+        arc = (arc << 7) | (b & 0x7F) as u32;
+        if b & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    arcs.iter().map(u32::to_string).collect::<Vec<_>>().join(".")
+}
+
+/// The request id and variable bindings of a GetResponse
+fn parse_response(buf: &[u8]) -> Result<(i32, Vec<(String, Value)>), String> {
+    let malformed = || "Malformed SNMP response".to_string();
+    let (tag, message) = read_tlv(buf, &mut 0)?;
+    if tag != TAG_SEQUENCE {
+        return Err(malformed());
+    }
+    let mut pos = 0;
+    read_tlv(message, &mut pos)?; // version
+    read_tlv(message, &mut pos)?; // community
+    let (tag, pdu) = read_tlv(message, &mut pos)?;
+    if tag != TAG_RESPONSE {
+        return Err(malformed());
+    }
+
+    let mut pos = 0;
+    let (_, id) = read_tlv(pdu, &mut pos)?;
+    let (_, error_status) = read_tlv(pdu, &mut pos)?;
+    read_tlv(pdu, &mut pos)?; // error-index
+    let error_status = decode_integer(error_status);
+    if error_status != 0 {
+        return Err(format!("SNMP error status {}", error_status));
+    }
+
+    let (_, bindings) = read_tlv(pdu, &mut pos)?;
+    let mut values = Vec::new();
+    let mut pos = 0;
+    while pos < bindings.len() {
+        let (_, binding) = read_tlv(bindings, &mut pos)?;
+        let mut inner = 0;
+        let (_, name) = read_tlv(binding, &mut inner)?;
+        let (tag, value) = read_tlv(binding, &mut inner)?;
+        let value = match tag {
+            TAG_INTEGER => Value::Integer(decode_integer(value)),
+            TAG_OCTET_STRING => Value::Text(value.to_vec()),
+            _ => Value::Other,
+        };
+        values.push((decode_oid(name), value));
+    }
+    Ok((decode_integer(id) as i32, values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a printer's agent would send back
+    fn response(request_id: i32, values: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let bindings: Vec<u8> = values
+            .iter()
+            .flat_map(|(name, value)| tlv(TAG_SEQUENCE, &[oid(name), value.clone()].concat()))
+            .collect();
+        let pdu = [
+            integer(request_id),
+            integer(0),
+            integer(0),
+            tlv(TAG_SEQUENCE, &bindings),
+        ]
+        .concat();
+        let message = [
+            integer(1),
+            tlv(TAG_OCTET_STRING, b"public"),
+            tlv(TAG_RESPONSE, &pdu),
+        ]
+        .concat();
+        tlv(TAG_SEQUENCE, &message)
+    }
+
+    #[test]
+    fn test_get_request_encoding() {
+        // snmpget -v2c -c public host 1.3.6.1.2.1.1.1.0, request id 1
+        let expected = [
+            0x30, 0x26, 0x02, 0x01, 0x01, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xA0,
+            0x19, 0x02, 0x01, 0x01, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0E, 0x30, 0x0C,
+            0x06, 0x08, 0x2B, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+        ];
+        assert_eq!(get_request(1, &[SYS_DESCR]), expected);
+        assert_eq!(integer(128), [0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(integer(-1), [0x02, 0x01, 0xFF]);
+        assert_eq!(decode_oid(&oid(PRT_SERIAL_NUMBER)[2..]), PRT_SERIAL_NUMBER);
+    }
+
+    #[test]
+    fn test_printer_state_from_response() {
+        let long_name = "TM-T88VI ".repeat(20);
+        let buf = response(
+            9,
+            &[
+                (HR_DEVICE_DESCR, tlv(TAG_OCTET_STRING, long_name.as_bytes())),
+                (SYS_DESCR, tlv(TAG_OCTET_STRING, b"EPSON Built-in")),
+                (PRT_SERIAL_NUMBER, tlv(0x80, &[])), // noSuchObject
+                (HR_PRINTER_STATUS, integer(3)),
+                // lowPaper and doorOpen
+                (HR_PRINTER_ERROR_STATE, tlv(TAG_OCTET_STRING, &[0x88])),
+            ],
+        );
+        let (id, values) = parse_response(&buf).unwrap();
+        assert_eq!(id, 9);
+
+        let state = printer_state(&values);
+        assert_eq!(state.state, "stopped");
+        assert_eq!(state.reasons, ["media-low", "door-open"]);
+        assert_eq!(state.paper.as_deref(), Some("low"));
+        assert_eq!(state.model.as_deref(), Some(long_name.trim()));
+        assert_eq!(state.serial_number, None);
+
+        let idle = printer_state(&[(HR_PRINTER_STATUS.to_string(), Value::Integer(3))]);
+        assert_eq!((idle.state.as_str(), idle.paper), ("idle", None));
+        assert!(parse_response(&buf[..buf.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn test_query_printer_against_fake_agent() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = agent.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, from) = agent.recv_from(&mut buf).unwrap();
+            let (_, message) = read_tlv(&buf[..len], &mut 0).unwrap();
+            let mut pos = 0;
+            read_tlv(message, &mut pos).unwrap();
+            read_tlv(message, &mut pos).unwrap();
+            let (_, pdu) = read_tlv(message, &mut pos).unwrap();
+            let (_, id) = read_tlv(pdu, &mut 0).unwrap();
+            let id = decode_integer(id) as i32;
+
+            // A stale reply first, which must be skipped
+            agent.send_to(&response(id - 1, &[]), from).unwrap();
+            let reply = response(
+                id,
+                &[
+                    (SYS_DESCR, tlv(TAG_OCTET_STRING, b"POS-80 Printer")),
+                    (PRT_SERIAL_NUMBER, tlv(TAG_OCTET_STRING, b"X3KT012345")),
+                    (HR_PRINTER_STATUS, integer(4)),
+                    (HR_PRINTER_ERROR_STATE, tlv(TAG_OCTET_STRING, &[0x00, 0x00])),
+                ],
+            );
+            agent.send_to(&reply, from).unwrap();
+        });
+
+        let state = query(addr, SNMP_TIMEOUT).unwrap().unwrap();
+        server.join().unwrap();
+        assert_eq!(state.model.as_deref(), Some("POS-80 Printer"));
+        assert_eq!(state.serial_number.as_deref(), Some("X3KT012345"));
+        assert_eq!((state.state.as_str(), state.paper.as_deref()), ("processing", Some("ok")));
+
+        // Nothing answering
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = silent.local_addr().unwrap();
+        assert_eq!(query(addr, Duration::from_millis(50)).unwrap(), None);
+    }
+}
@@ -0,0 +1,151 @@
+// src/spool.rs
+// On-disk queue for jobs accepted but not printed before the app exited
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::get_config_path;
+use crate::lock::LockExt;
+use crate::printer::PrinterManager;
+use crate::template_render::PrintCommand;
+
+/// Keeps files saved within the same millisecond in order
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+/// A job saved to print later, on the printer it was sent to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpooledJob {
+    /// Id of the printer in the manager's registry
+    pub printer: String,
+    pub payload: SpooledPayload,
+}
+
+/// What a spooled job prints
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpooledPayload {
+    /// Encoded in the printer's protocol
+    Bytes(#[serde(with = "base64_bytes")] Vec<u8>),
+    /// Rendered but not encoded, for a printer that lays jobs out itself
+    Commands(Vec<PrintCommand>),
+}
+
+/// Bytes as base64 in a spooled job's JSON
+mod base64_bytes {
+    use base64::{engine::general_purpose, Engine as _};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&general_purpose::STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        general_purpose::STANDARD
+            .decode(text)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// A directory of jobs, each printed on its own printer in the order they
+/// were saved.
+///
+/// Jobs still queued at shutdown are saved here and replayed once their
+/// printer is connected on the next start.
+#[derive(Debug, Clone)]
+pub struct Spool {
+    dir: PathBuf,
+    /// Held while replaying, so two replays never print a job twice
+    replaying: Arc<Mutex<()>>,
+}
+
+impl Spool {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            replaying: Arc::default(),
+        }
+    }
+
+    /// `spool/` next to `config.json`
+    pub fn default_dir() -> Result<PathBuf, String> {
+        Ok(get_config_path()?.with_file_name("spool"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Save a job, returning the file it was written to
+    pub fn save(&self, job: &SpooledJob) -> Result<PathBuf, String> {
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create spool directory: {}", e))?;
+
+        let name = format!(
+            "{}-{:06}",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%3f"),
+            SEQUENCE.fetch_add(1, Ordering::SeqCst) % 1_000_000
+        );
+        // Write under a temporary name so a crash never leaves half a job to replay
+        let partial = self.dir.join(format!("{}.tmp", name));
+        let path = self.dir.join(format!("{}.json", name));
+        let json = serde_json::to_vec(job).map_err(|e| format!("Failed to encode job: {}", e))?;
+        std::fs::write(&partial, json)
+            .and_then(|()| std::fs::rename(&partial, &path))
+            .map_err(|e| format!("Failed to write spooled job: {}", e))?;
+        Ok(path)
+    }
+
+    /// Saved jobs, oldest first
+    pub fn pending(&self) -> Vec<PathBuf> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut jobs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        jobs.sort();
+        jobs
+    }
+
+    /// Print saved jobs oldest first on the printers they were sent to,
+    /// removing each once it has printed.
+    ///
+    /// Jobs for a printer that isn't connected stay for a later replay, as
+    /// do that printer's newer jobs. Stops at the first job that fails so
+    /// the rest keep their order for the next attempt. Returns how many jobs
+    /// were printed.
+    pub fn replay(&self, manager: &Mutex<PrinterManager>) -> Result<usize, String> {
+        let _replaying = self.replaying.lock_or_recover();
+        let mut printed = 0;
+        let mut waiting = HashSet::new();
+        for path in self.pending() {
+            let json = std::fs::read(&path)
+                .map_err(|e| format!("Failed to read spooled job {}: {}", path.display(), e))?;
+            let job: SpooledJob = serde_json::from_slice(&json)
+                .map_err(|e| format!("Failed to read spooled job {}: {}", path.display(), e))?;
+            if waiting.contains(&job.printer) {
+                continue;
+            }
+            // Release the manager before writing, like every other job
+            let output = {
+                let manager = manager.lock_or_recover();
+                if !manager.is_printer_connected(&job.printer) {
+                    waiting.insert(job.printer);
+                    continue;
+                }
+                manager.prepare_spooled(job)?
+            };
+            output.send()?;
+            std::fs::remove_file(&path)
+                .map_err(|e| format!("Failed to remove spooled job {}: {}", path.display(), e))?;
+            printed += 1;
+        }
+        Ok(printed)
+    }
+}
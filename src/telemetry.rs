@@ -0,0 +1,134 @@
+// src/telemetry.rs
+// Print counters shared by the printer manager and the jobs it prepares
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Mutex;
+
+use crate::lock::LockExt;
+
+/// Where a print job went wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClass {
+    /// No printer connected, or the printer could not be opened
+    Connection,
+    /// The template failed to render
+    Render,
+    /// The printer stopped accepting data part way
+    Write,
+    /// Stopped part way because the app was shutting down
+    Aborted,
+}
+
+/// Failed jobs by [`FailureClass`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FailureCounts {
+    pub connection: u64,
+    pub render: u64,
+    pub write: u64,
+    pub aborted: u64,
+}
+
+impl FailureCounts {
+    pub fn total(&self) -> u64 {
+        self.connection + self.render + self.write + self.aborted
+    }
+}
+
+/// Counters for every job a `PrinterManager` prints.
+///
+/// Shared with each `PrintOutput` so jobs written by the print worker, the
+/// UI or a direct `send` are all counted. Nothing is persisted; the counters
+/// start from zero with the process, and `started_at` lets a reader turn
+/// them into rates.
+#[derive(Debug)]
+pub struct PrintTelemetry {
+    started_at: DateTime<Utc>,
+    counters: Mutex<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    total_prints: u64,
+    failures: FailureCounts,
+    bytes_written: u64,
+    last_success: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    connected_since: Option<DateTime<Utc>>,
+}
+
+/// A point-in-time copy of [`PrintTelemetry`], as reported by `/status`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PrintStats {
+    /// When the counters started (process start), RFC 3339
+    pub started_at: String,
+    pub uptime_secs: u64,
+    /// Jobs written to the printer in full
+    pub total_prints: u64,
+    pub failures: FailureCounts,
+    /// Bytes sent to the printer, including those of jobs that failed part way
+    pub bytes_written: u64,
+    pub last_success_at: Option<String>,
+    pub last_error: Option<String>,
+    /// How long the current printer connection has been open
+    pub connection_uptime_secs: Option<u64>,
+}
+
+impl Default for PrintTelemetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrintTelemetry {
+    pub fn new() -> Self {
+        Self {
+            started_at: Utc::now(),
+            counters: Mutex::new(Counters::default()),
+        }
+    }
+
+    pub fn record_success(&self, bytes: u64) {
+        let mut counters = self.counters.lock_or_recover();
+        counters.total_prints += 1;
+        counters.bytes_written += bytes;
+        counters.last_success = Some(Utc::now());
+    }
+
+    /// Count a failed job; `bytes` is what reached the printer before it failed
+    pub fn record_failure(&self, class: FailureClass, error: &str, bytes: u64) {
+        let mut counters = self.counters.lock_or_recover();
+        match class {
+            FailureClass::Connection => counters.failures.connection += 1,
+            FailureClass::Render => counters.failures.render += 1,
+            FailureClass::Write => counters.failures.write += 1,
+            FailureClass::Aborted => counters.failures.aborted += 1,
+        }
+        counters.bytes_written += bytes;
+        counters.last_error = Some(error.to_string());
+    }
+
+    pub fn connection_opened(&self) {
+        self.counters.lock_or_recover().connected_since = Some(Utc::now());
+    }
+
+    pub fn connection_closed(&self) {
+        self.counters.lock_or_recover().connected_since = None;
+    }
+
+    pub fn snapshot(&self) -> PrintStats {
+        let now = Utc::now();
+        let secs_since = |time: DateTime<Utc>| (now - time).num_seconds().max(0) as u64;
+        let counters = self.counters.lock_or_recover();
+        PrintStats {
+            started_at: self.started_at.to_rfc3339(),
+            uptime_secs: secs_since(self.started_at),
+            total_prints: counters.total_prints,
+            failures: counters.failures,
+            bytes_written: counters.bytes_written,
+            last_success_at: counters.last_success.map(|t| t.to_rfc3339()),
+            last_error: counters.last_error.clone(),
+            connection_uptime_secs: counters.connected_since.map(secs_since),
+        }
+    }
+}
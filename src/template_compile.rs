@@ -0,0 +1,1131 @@
+// src/template_compile.rs
+// Compiled form of a ReceiptTemplate, prepared once and rendered many times
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+use crate::template_expr::Expr;
+use crate::template_filter::{split_pipes, Filter};
+use crate::template_markup::{parse_markup, MarkupSpan};
+use crate::template_gift::gift_receipt;
+use crate::template_migrate::{css_bold, css_invert, migrate};
+use crate::template_render::{
+    BarChartElement, BarcodeElement, Bilingual, BoxElement, BreakdownElement, Buzzer, CouponCode,
+    CouponElement, Direction, Element, GridElement, ImageElement, KitchenItemsElement,
+    LeaderboardElement, LogoElement, MinWidth, QRElement, ReceiptData, ReceiptTemplate,
+    RepeatElement, RowElement, Section, TableElement, TaxSummaryElement, TemplateKind,
+    TemplateRenderer, TextElement, TextStyle, TicketSplit, TipSuggestionsElement, PAPER_SIZES,
+};
+
+/// A template with its conditions parsed, placeholders split and fixed
+/// layout (dividers, borders, table column widths) resolved for one paper
+/// width.
+///
+/// Templates change rarely but are printed constantly, so `PrinterManager`
+/// compiles each template once in `set_template` and renders the cached
+/// form on every print. Build one with [`TemplateRenderer::compile`].
+#[derive(Debug, Clone)]
+pub struct CompiledTemplate {
+    pub(crate) paper_width: u32,
+    pub(crate) sections: Vec<CompiledSection>,
+    pub(crate) dropped: Vec<String>,
+    pub(crate) buzzer: Option<Buzzer>,
+    /// Label tables keyed by lowercased language
+    pub(crate) translations: HashMap<String, HashMap<String, String>>,
+    pub(crate) default_language: Option<String>,
+    pub(crate) bilingual: Option<Bilingual>,
+    pub(crate) constants: HashMap<String, serde_json::Value>,
+    /// Computed values, each after those it reads
+    pub(crate) computed: Vec<(String, Expr)>,
+    /// Tickets an order prints as
+    pub(crate) tickets: TicketSplit,
+    /// Labels of the copies printed, none for a single unlabelled print
+    pub(crate) copies: Vec<String>,
+    /// The sections printed for a gift receipt; receipt templates only
+    pub(crate) gift_sections: Option<Vec<CompiledSection>>,
+    /// Whether prints are gift receipts unless the data says otherwise
+    pub(crate) gift_receipt: bool,
+}
+
+impl CompiledTemplate {
+    /// Paper width (in characters) the layout was resolved for
+    pub fn paper_width(&self) -> u32 {
+        self.paper_width
+    }
+
+    /// Sections and elements left out or replaced by their fallback because
+    /// the paper is narrower than their `min_width`, one note each
+    pub fn dropped(&self) -> &[String] {
+        &self.dropped
+    }
+
+    /// The labels `{{t.<key>}}` reads: those of `language`, or of its base
+    /// language ("pt" for "pt-BR"), over those of the default language.
+    /// `None` if the template has no translations.
+    pub(crate) fn labels(&self, language: Option<&str>) -> Option<serde_json::Value> {
+        if self.translations.is_empty() {
+            return None;
+        }
+        let languages = [self.default_language.as_deref(), language];
+        let mut labels = serde_json::Map::new();
+        for language in languages.into_iter().flatten() {
+            for (key, label) in self.translation(language).into_iter().flatten() {
+                labels.insert(key.clone(), serde_json::Value::String(label.clone()));
+            }
+        }
+        Some(serde_json::Value::Object(labels))
+    }
+
+    /// The labels and item names of the second language `data` prints on a
+    /// bilingual receipt, without the default language's filling in
+    pub(crate) fn second_language(
+        &self,
+        data: &ReceiptData,
+    ) -> Option<(&Bilingual, &HashMap<String, String>)> {
+        let bilingual = self.bilingual.as_ref()?;
+        let language = data
+            .secondary_language
+            .as_deref()
+            .or(bilingual.language.as_deref())?;
+        Some((bilingual, self.translation(language)?))
+    }
+
+    /// The labels of `language`, or of its base language ("pt" for "pt-BR")
+    fn translation(&self, language: &str) -> Option<&HashMap<String, String>> {
+        let language = language.to_lowercase();
+        self.translations.get(&language).or_else(|| {
+            let base = language.split(['-', '_']).next()?;
+            self.translations.get(base)
+        })
+    }
+
+    /// The sections `data` prints: the gift receipt's when it, or else the
+    /// template, asks for one
+    pub(crate) fn sections_for(&self, data: &ReceiptData) -> &[CompiledSection] {
+        match &self.gift_sections {
+            Some(gift) if data.gift_receipt.unwrap_or(self.gift_receipt) => gift,
+            _ => &self.sections,
+        }
+    }
+
+    /// Top-level logo elements, in render order
+    pub fn logos(&self) -> impl Iterator<Item = &LogoElement> {
+        self.sections
+            .iter()
+            .flat_map(|section| &section.elements)
+            .filter_map(|node| match &node.element {
+                CompiledElement::Logo(e) => Some(e),
+                _ => None,
+            })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledSection {
+    pub condition: Option<Condition>,
+    pub before: Option<u32>,
+    pub after: Option<u32>,
+    pub elements: Vec<CompiledNode>,
+    /// Printed when the condition is false
+    pub otherwise: Vec<CompiledNode>,
+    pub buzzer: Option<Buzzer>,
+}
+
+/// An element together with its parsed render condition
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledNode {
+    pub condition: Option<Condition>,
+    pub element: CompiledElement,
+    /// Its own direction or the one it's inside
+    pub direction: Direction,
+    /// Printed when the condition is false
+    pub otherwise: Vec<CompiledNode>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum CompiledElement {
+    Text {
+        element: TextElement,
+        content: TextPattern,
+        /// The content split at its markup, if the element reads markup
+        markup: Option<Vec<MarkupSpan>>,
+    },
+    Logo(LogoElement),
+    Image {
+        element: ImageElement,
+        source: TextPattern,
+    },
+    Divider {
+        line: String,
+        align: String,
+    },
+    Row {
+        element: RowElement,
+        left: Option<TextPattern>,
+        right: Option<TextPattern>,
+    },
+    QR {
+        element: QRElement,
+        content: TextPattern,
+    },
+    Barcode {
+        element: BarcodeElement,
+        content: TextPattern,
+    },
+    Table {
+        element: TableElement,
+        column_widths: Vec<usize>,
+        /// More than one line when a wrapping column's header is too long
+        header: Vec<String>,
+        /// One per `row_details` entry, checked against each row
+        detail_conditions: Vec<Option<Condition>>,
+    },
+    Space(u32),
+    Box {
+        element: BoxElement,
+        border_line: String,
+        children: Vec<CompiledNode>,
+    },
+    Grid {
+        element: GridElement,
+        values: Vec<TextPattern>,
+        /// `None` when the grid has no columns, which is reported at render time
+        col_width: Option<usize>,
+    },
+    BarChart(BarChartElement),
+    Leaderboard(LeaderboardElement),
+    Repeat {
+        element: RepeatElement,
+        children: Vec<CompiledNode>,
+    },
+    Switch {
+        on: String,
+        /// Each case's value, trimmed, with its elements
+        cases: Vec<(String, Vec<CompiledNode>)>,
+        default: Vec<CompiledNode>,
+    },
+    KitchenItems(KitchenItemsElement),
+    TaxSummary(TaxSummaryElement),
+    TipSuggestions(TipSuggestionsElement),
+    Breakdown(BreakdownElement),
+    Coupon {
+        element: CouponElement,
+        content: TextPattern,
+        /// A barcode or QR element for the coupon's code
+        code: Option<Box<CompiledElement>>,
+        expiry: Option<TextPattern>,
+    },
+}
+
+// ==================== Conditions ====================
+
+/// Parsed form of a template condition string, an expression such as
+/// `subtotal - discount > 10 && !is_vip` that holds unless it is empty,
+/// `false` or zero.
+///
+/// Rendering is lenient: a condition that doesn't parse always renders.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Condition {
+    Test(Expr),
+    Always,
+}
+
+/// Computed values that parse, each after the others it reads. Those that
+/// read themselves, directly or through others, are left out.
+pub(crate) fn computed_in_order(computed: &BTreeMap<String, String>) -> Vec<(String, Expr)> {
+    let mut pending: Vec<(String, Expr)> = computed
+        .iter()
+        .filter_map(|(name, expr)| Some((name.clone(), Expr::parse(expr).ok()?)))
+        .collect();
+    let mut ordered = Vec::with_capacity(pending.len());
+    loop {
+        let ready = pending.iter().position(|(_, expr)| {
+            let mut names = Vec::new();
+            expr.variables(&mut names);
+            names.iter().all(|name| {
+                let root = name.split('.').next().unwrap_or(name);
+                !pending.iter().any(|(other, _)| other == root)
+            })
+        });
+        match ready {
+            Some(i) => ordered.push(pending.remove(i)),
+            None => return ordered,
+        }
+    }
+}
+
+impl Condition {
+    /// Parse leniently for rendering: a condition that doesn't parse renders
+    pub(crate) fn parse(condition: &str) -> Self {
+        Self::try_parse(condition).unwrap_or(Condition::Always)
+    }
+
+    /// Parse a table `row_details` condition, where a field name on its
+    /// own tests that the row has a value for it
+    pub(crate) fn parse_row(condition: &str) -> Self {
+        if is_field_name(condition) {
+            Self::parse(&format!("{} != null", condition.trim()))
+        } else {
+            Self::parse(condition)
+        }
+    }
+
+    /// Parse strictly, explaining why a condition would be ignored
+    pub(crate) fn try_parse(condition: &str) -> Result<Self, String> {
+        Expr::parse(condition).map(Condition::Test)
+    }
+
+    fn compile(condition: &Option<String>) -> Option<Self> {
+        condition.as_deref().map(Self::parse)
+    }
+}
+
+// ==================== Text Patterns ====================
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Segment {
+    Literal(String),
+    Variable(String),
+    /// An expression such as `{{subtotal - discount}}`
+    Expr(Expr),
+    /// A variable or expression and the filters it goes through, as in
+    /// `{{name | upper | truncate:20}}`
+    Filtered {
+        value: Box<Segment>,
+        filters: Vec<Filter>,
+    },
+}
+
+/// A content string split into literal text and `{{ }}` placeholders.
+/// A placeholder that isn't a valid expression, or has a filter that
+/// doesn't parse, stays as literal text.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TextPattern {
+    pub segments: Vec<Segment>,
+}
+
+impl Segment {
+    /// Variables the segment reads, in order
+    pub(crate) fn variables<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Segment::Literal(_) => {}
+            Segment::Variable(name) => names.push(name),
+            Segment::Expr(expr) => expr.variables(names),
+            Segment::Filtered { value, .. } => value.variables(names),
+        }
+    }
+}
+
+fn placeholder_regex() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{\{(.+?)\}\}").unwrap())
+}
+
+impl TextPattern {
+    pub(crate) fn parse(text: &str) -> Self {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut last = 0;
+        for caps in placeholder_regex().captures_iter(text) {
+            let whole = caps.get(0).unwrap();
+            let Ok(segment) = Self::parse_placeholder(&caps[1]) else {
+                continue;
+            };
+            literal.push_str(&text[last..whole.start()]);
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(segment);
+            last = whole.end();
+        }
+        literal.push_str(&text[last..]);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Self { segments }
+    }
+
+    /// Each `{{ }}` placeholder in `text`, parsed, or with the reason it
+    /// stays literal text
+    pub(crate) fn placeholders(text: &str) -> Vec<(&str, Result<Segment, String>)> {
+        placeholder_regex()
+            .captures_iter(text)
+            .map(|caps| {
+                let whole = caps.get(0).unwrap().as_str();
+                (whole, Self::parse_placeholder(&whole[2..whole.len() - 2]))
+            })
+            .collect()
+    }
+
+    /// The inside of one placeholder: a variable or expression, then filters
+    fn parse_placeholder(inner: &str) -> Result<Segment, String> {
+        static VARIABLE: OnceLock<Regex> = OnceLock::new();
+        let variable = VARIABLE
+            .get_or_init(|| Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*(?:\.[a-zA-Z0-9_]+)*$").unwrap());
+
+        let mut parts = split_pipes(inner).into_iter();
+        let inner = parts.next().unwrap_or_default().trim();
+        let value = if variable.is_match(inner) {
+            Segment::Variable(inner.to_string())
+        } else {
+            Segment::Expr(Expr::parse(inner)?)
+        };
+        let filters = parts.map(Filter::parse).collect::<Result<Vec<_>, _>>()?;
+        Ok(if filters.is_empty() {
+            value
+        } else {
+            Segment::Filtered {
+                value: Box::new(value),
+                filters,
+            }
+        })
+    }
+}
+
+// ==================== Compilation ====================
+
+impl TemplateRenderer {
+    /// Compile a template for rendering at this renderer's paper width
+    pub fn compile(&self, template: &ReceiptTemplate) -> CompiledTemplate {
+        let migrated = migrate(template);
+        let template = migrated.as_ref().unwrap_or(template);
+        let mut dropped = Vec::new();
+        let sections = self.compile_sections(template, &mut dropped);
+        let kind = template.kind.unwrap_or_default();
+        // Its notes are the receipt's, so they aren't kept twice
+        let gift_sections = (kind == TemplateKind::Receipt)
+            .then(|| self.compile_sections(&gift_receipt(template), &mut Vec::new()));
+
+        CompiledTemplate {
+            paper_width: self.paper_width(),
+            sections,
+            dropped,
+            buzzer: template.buzzer,
+            translations: template
+                .translations
+                .iter()
+                .flatten()
+                .map(|(language, labels)| (language.to_lowercase(), labels.clone()))
+                .collect(),
+            default_language: template.default_language.clone(),
+            bilingual: template.bilingual.clone(),
+            constants: template.constants.clone().unwrap_or_default(),
+            computed: template
+                .computed
+                .as_ref()
+                .map(computed_in_order)
+                .unwrap_or_default(),
+            tickets: match kind {
+                TemplateKind::Kitchen => template.ticket_per.unwrap_or_default(),
+                TemplateKind::Receipt | TemplateKind::Label => TicketSplit::Order,
+            },
+            copies: template.copies.clone().unwrap_or_default(),
+            gift_sections,
+            gift_receipt: template
+                .gift_receipt
+                .as_ref()
+                .is_some_and(|gift| gift.enabled),
+        }
+    }
+
+    /// The template's sections, leaving out those the paper is too narrow
+    /// for
+    fn compile_sections(
+        &self,
+        template: &ReceiptTemplate,
+        dropped: &mut Vec<String>,
+    ) -> Vec<CompiledSection> {
+        let direction = template.direction.unwrap_or_default();
+        let no_styles = HashMap::new();
+        let styles = template.styles.as_ref().unwrap_or(&no_styles);
+        let mut sections = Vec::new();
+        for (i, section) in template.layout.sections.iter().enumerate() {
+            let path = format!("layout.sections[{}]", i);
+            let min_width = section.min_width.as_ref();
+            match min_width.filter(|min| self.too_narrow_for(min)) {
+                Some(min) => dropped.push(self.dropped_note(&path, "left out", min)),
+                None => {
+                    sections.push(self.compile_section(&path, section, direction, styles, dropped))
+                }
+            }
+        }
+        sections
+    }
+
+    fn compile_section(
+        &self,
+        path: &str,
+        section: &Section,
+        direction: Direction,
+        styles: &HashMap<String, TextStyle>,
+        dropped: &mut Vec<String>,
+    ) -> CompiledSection {
+        let spacing = section.spacing.as_ref();
+        CompiledSection {
+            condition: Condition::compile(&section.condition),
+            before: spacing.and_then(|s| s.before),
+            after: spacing.and_then(|s| s.after),
+            elements: self.compile_elements(path, &section.elements, direction, styles, dropped),
+            otherwise: self.compile_list(
+                &format!("{}.else_elements", path),
+                section.else_elements.as_deref().unwrap_or_default(),
+                direction,
+                styles,
+                dropped,
+            ),
+            buzzer: section.buzzer,
+        }
+    }
+
+    fn compile_elements(
+        &self,
+        parent: &str,
+        elements: &[Element],
+        direction: Direction,
+        styles: &HashMap<String, TextStyle>,
+        dropped: &mut Vec<String>,
+    ) -> Vec<CompiledNode> {
+        let list = format!("{}.elements", parent);
+        self.compile_list(&list, elements, direction, styles, dropped)
+    }
+
+    /// Compile the elements of the list at `list`, such as
+    /// `layout.sections[0].else_elements`
+    fn compile_list(
+        &self,
+        list: &str,
+        elements: &[Element],
+        direction: Direction,
+        styles: &HashMap<String, TextStyle>,
+        dropped: &mut Vec<String>,
+    ) -> Vec<CompiledNode> {
+        elements
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| {
+                let path = format!("{}[{}]", list, i);
+                self.compile_gated(&path, e, direction, styles, dropped)
+            })
+            .collect()
+    }
+
+    /// Compile an element, or its fallback if the paper is narrower than
+    /// its `min_width`; `None` if neither prints
+    fn compile_gated(
+        &self,
+        path: &str,
+        element: &Element,
+        direction: Direction,
+        styles: &HashMap<String, TextStyle>,
+        dropped: &mut Vec<String>,
+    ) -> Option<CompiledNode> {
+        let Some(min) = element.min_width().filter(|min| self.too_narrow_for(min)) else {
+            return Some(self.compile_element(path, element, direction, styles, dropped));
+        };
+        match element.fallback() {
+            Some(fallback) => {
+                dropped.push(self.dropped_note(path, "replaced by its fallback", min));
+                self.compile_gated(
+                    &format!("{}.fallback", path),
+                    fallback,
+                    direction,
+                    styles,
+                    dropped,
+                )
+            }
+            None => {
+                dropped.push(self.dropped_note(path, "left out", min));
+                None
+            }
+        }
+    }
+
+    /// Unknown paper sizes don't gate anything; validation reports them
+    fn too_narrow_for(&self, min: &MinWidth) -> bool {
+        min.columns().is_some_and(|columns| self.paper_width() < columns)
+    }
+
+    fn dropped_note(&self, path: &str, what: &str, min: &MinWidth) -> String {
+        format!(
+            "{} {}: needs {}, paper is {} columns",
+            path,
+            what,
+            min,
+            self.paper_width()
+        )
+    }
+
+    fn compile_element(
+        &self,
+        path: &str,
+        element: &Element,
+        inherited: Direction,
+        styles: &HashMap<String, TextStyle>,
+        dropped: &mut Vec<String>,
+    ) -> CompiledNode {
+        let direction = element.direction().unwrap_or(inherited);
+        let else_path = format!("{}.else_elements", path);
+        let otherwise = self.compile_list(
+            &else_path,
+            element.else_elements(),
+            direction,
+            styles,
+            dropped,
+        );
+        let (condition, element) = match element {
+            Element::Text(e) => {
+                let content = TextPattern::parse(&e.content);
+                let markup = e.markup.unwrap_or(false).then(|| parse_markup(&content));
+                let mut styled = e.clone();
+                styled.bold = styled.bold.or(css_bold(e.font_weight.as_deref()));
+                styled.invert = styled.invert.or(css_invert(e.background.as_deref()));
+                if let Some(style) = named_style(styles, &e.style) {
+                    style.style_text(&mut styled);
+                }
+                (
+                    &e.condition,
+                    CompiledElement::Text {
+                        content,
+                        markup,
+                        element: styled,
+                    },
+                )
+            }
+            Element::Logo(e) => (&e.condition, CompiledElement::Logo(e.clone())),
+            Element::Image(e) => (
+                &e.condition,
+                CompiledElement::Image {
+                    source: TextPattern::parse(&e.source),
+                    element: e.clone(),
+                },
+            ),
+            Element::Divider(e) => (
+                &e.condition,
+                CompiledElement::Divider {
+                    line: self.divider_line(e),
+                    align: e.align.clone().unwrap_or_else(|| "left".to_string()),
+                },
+            ),
+            Element::Row(e) => {
+                let mut styled = e.clone();
+                styled.bold = styled.bold.or(css_bold(e.font_weight.as_deref()));
+                styled.invert = styled.invert.or(css_invert(e.background.as_deref()));
+                if let Some(style) = named_style(styles, &e.style) {
+                    style.style_row(&mut styled);
+                }
+                (
+                    &e.condition,
+                    CompiledElement::Row {
+                        left: e.left.as_deref().map(TextPattern::parse),
+                        right: e.right.as_deref().map(TextPattern::parse),
+                        element: styled,
+                    },
+                )
+            }
+            Element::QR(e) => (
+                &e.condition,
+                CompiledElement::QR {
+                    content: TextPattern::parse(&e.content),
+                    element: e.clone(),
+                },
+            ),
+            Element::Barcode(e) => (
+                &e.condition,
+                CompiledElement::Barcode {
+                    content: TextPattern::parse(&e.content),
+                    element: e.clone(),
+                },
+            ),
+            Element::Table(e) => {
+                let column_widths = self.table_column_widths(&e.columns);
+                (
+                    &e.condition,
+                    CompiledElement::Table {
+                        header: self.format_table_row(&e.columns, &column_widths, None),
+                        column_widths,
+                        detail_conditions: e
+                            .row_details
+                            .iter()
+                            .flatten()
+                            .map(|detail| detail.condition.as_deref().map(Condition::parse_row))
+                            .collect(),
+                        element: e.clone(),
+                    },
+                )
+            }
+            Element::Space(e) => (&e.condition, CompiledElement::Space(e.lines.unwrap_or(1))),
+            Element::Box(e) => (
+                &e.condition,
+                CompiledElement::Box {
+                    border_line: "=".repeat(self.content_width()),
+                    children: self.compile_elements(path, &e.elements, direction, styles, dropped),
+                    element: e.clone(),
+                },
+            ),
+            Element::Grid(e) => (
+                &e.condition,
+                CompiledElement::Grid {
+                    values: e.data.iter().map(|item| TextPattern::parse(&item.value)).collect(),
+                    col_width: self.grid_column_width(e),
+                    element: e.clone(),
+                },
+            ),
+            Element::BarChart(e) => (&e.condition, CompiledElement::BarChart(e.clone())),
+            Element::Leaderboard(e) => (&e.condition, CompiledElement::Leaderboard(e.clone())),
+            Element::Repeat(e) => (
+                &e.condition,
+                CompiledElement::Repeat {
+                    children: self.compile_elements(path, &e.elements, direction, styles, dropped),
+                    element: e.clone(),
+                },
+            ),
+            Element::Switch(e) => (
+                &e.condition,
+                CompiledElement::Switch {
+                    on: e.on.clone(),
+                    cases: e
+                        .cases
+                        .iter()
+                        .map(|(value, elements)| {
+                            let list = format!("{}.cases.{}", path, value);
+                            let children =
+                                self.compile_list(&list, elements, direction, styles, dropped);
+                            (value.trim().to_string(), children)
+                        })
+                        .collect(),
+                    default: self.compile_list(
+                        &format!("{}.default", path),
+                        e.default.as_deref().unwrap_or_default(),
+                        direction,
+                        styles,
+                        dropped,
+                    ),
+                },
+            ),
+            Element::KitchenItems(e) => (&e.condition, CompiledElement::KitchenItems(e.clone())),
+            Element::TaxSummary(e) => (&e.condition, CompiledElement::TaxSummary(e.clone())),
+            Element::TipSuggestions(e) => {
+                (&e.condition, CompiledElement::TipSuggestions(e.clone()))
+            }
+            Element::Breakdown(e) => (&e.condition, CompiledElement::Breakdown(e.clone())),
+            Element::Coupon(e) => (
+                &e.condition,
+                CompiledElement::Coupon {
+                    content: TextPattern::parse(&e.content),
+                    code: e.code.as_deref().map(|code| Box::new(coupon_code(e, code))),
+                    expiry: e.expiry.as_deref().map(TextPattern::parse),
+                    element: e.clone(),
+                },
+            ),
+        };
+
+        CompiledNode {
+            condition: Condition::compile(condition),
+            element,
+            direction,
+            otherwise,
+        }
+    }
+}
+
+/// The style an element names; unknown names are reported by validation
+fn named_style<'a>(
+    styles: &'a HashMap<String, TextStyle>,
+    name: &Option<String>,
+) -> Option<&'a TextStyle> {
+    styles.get(name.as_deref()?)
+}
+
+/// The barcode or QR element that prints a coupon's `code`
+fn coupon_code(coupon: &CouponElement, code: &str) -> CompiledElement {
+    let content = TextPattern::parse(code);
+    match coupon.code_type.unwrap_or_default() {
+        CouponCode::Barcode => CompiledElement::Barcode {
+            element: BarcodeElement {
+                content: code.to_string(),
+                format: coupon.format.clone(),
+                height: Some(80),
+                width: None,
+                show_text: None,
+                hri_position: None,
+                align: None,
+                condition: None,
+                else_elements: None,
+                min_width: None,
+                fallback: None,
+            },
+            content,
+        },
+        CouponCode::Qr => CompiledElement::QR {
+            element: QRElement {
+                content: code.to_string(),
+                size: Some(5),
+                error_correction: None,
+                align: None,
+                condition: None,
+                else_elements: None,
+                min_width: None,
+                fallback: None,
+            },
+            content,
+        },
+    }
+}
+
+// ==================== Validation ====================
+
+/// A template condition that doesn't parse. At render time it would be
+/// ignored and its element always printed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConditionIssue {
+    /// Where the condition is in the template, e.g. `layout.sections[1].elements[0].condition`
+    pub path: String,
+    pub condition: String,
+    pub error: String,
+}
+
+impl std::fmt::Display for ConditionIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: `{}`: {}", self.path, self.condition, self.error)
+    }
+}
+
+/// What to do when a template check finds problems
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strictness {
+    /// Refuse the template (or print job) and report every problem
+    Reject,
+    /// Go ahead and report every problem as a warning
+    Warn,
+}
+
+/// Template checks, loaded from `template_validation.json`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TemplateValidation {
+    /// Conditions that don't parse, checked in `set_template`
+    #[serde(default = "default_condition_strictness")]
+    pub conditions: Strictness,
+    /// Print data that doesn't match the template's declared variables
+    #[serde(default = "default_data_strictness")]
+    pub data: Strictness,
+}
+
+fn default_condition_strictness() -> Strictness {
+    Strictness::Reject
+}
+
+fn default_data_strictness() -> Strictness {
+    Strictness::Warn
+}
+
+impl Default for TemplateValidation {
+    fn default() -> Self {
+        Self {
+            conditions: default_condition_strictness(),
+            data: default_data_strictness(),
+        }
+    }
+}
+
+/// Parse every condition in a template (sections, elements, elements
+/// nested in boxes, repeats and `else_elements`, and table row details) and report all that don't parse.
+pub fn check_conditions(template: &ReceiptTemplate) -> Vec<ConditionIssue> {
+    let mut issues = Vec::new();
+    for (i, section) in template.layout.sections.iter().enumerate() {
+        let path = format!("layout.sections[{}]", i);
+        check_condition(&path, section.condition.as_deref(), &mut issues);
+        check_elements(&path, &section.elements, &mut issues);
+        let otherwise = section.else_elements.as_deref().unwrap_or_default();
+        check_list(&format!("{}.else_elements", path), otherwise, &mut issues);
+    }
+    issues
+}
+
+fn check_elements(parent: &str, elements: &[Element], issues: &mut Vec<ConditionIssue>) {
+    check_list(&format!("{}.elements", parent), elements, issues);
+}
+
+fn check_list(list: &str, elements: &[Element], issues: &mut Vec<ConditionIssue>) {
+    for (i, element) in elements.iter().enumerate() {
+        check_element(&format!("{}[{}]", list, i), element, issues);
+    }
+}
+
+fn check_element(path: &str, element: &Element, issues: &mut Vec<ConditionIssue>) {
+    check_condition(path, element.condition(), issues);
+    match element {
+        Element::Box(e) => check_elements(path, &e.elements, issues),
+        Element::Repeat(e) => check_elements(path, &e.elements, issues),
+        Element::Switch(e) => {
+            for (value, elements) in &e.cases {
+                check_list(&format!("{}.cases.{}", path, value), elements, issues);
+            }
+            let default = e.default.as_deref().unwrap_or_default();
+            check_list(&format!("{}.default", path), default, issues);
+        }
+        Element::Table(e) => {
+            for (j, detail) in e.row_details.iter().flatten().enumerate() {
+                // Naming a field on its own tests that it has a value
+                let condition = detail.condition.as_deref().filter(|c| !is_field_name(c));
+                check_condition(&format!("{}.row_details[{}]", path, j), condition, issues);
+            }
+        }
+        _ => {}
+    }
+    let otherwise = element.else_elements();
+    check_list(&format!("{}.else_elements", path), otherwise, issues);
+}
+
+fn check_condition(path: &str, condition: Option<&str>, issues: &mut Vec<ConditionIssue>) {
+    if let Some(condition) = condition {
+        if let Err(error) = Condition::try_parse(condition) {
+            issues.push(ConditionIssue {
+                path: format!("{}.condition", path),
+                condition: condition.to_string(),
+                error,
+            });
+        }
+    }
+}
+
+/// Blank lines above which section spacing or a space element is probably
+/// a mistake
+pub const SPACING_WARN_LINES: u32 = 50;
+
+/// Report layout values that print but are probably mistakes: section
+/// spacing and space elements of more than [`SPACING_WARN_LINES`] lines, and
+/// `min_width` values no supported paper meets. These are warnings rather
+/// than issues.
+pub fn check_layout(template: &ReceiptTemplate) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for (i, section) in template.layout.sections.iter().enumerate() {
+        let path = format!("layout.sections[{}]", i);
+        if let Some(spacing) = &section.spacing {
+            check_lines(&format!("{}.spacing.before", path), spacing.before, &mut warnings);
+            check_lines(&format!("{}.spacing.after", path), spacing.after, &mut warnings);
+        }
+        check_min_width(&path, section.min_width.as_ref(), &mut warnings);
+        check_layout_elements(&path, &section.elements, &mut warnings);
+        let otherwise = section.else_elements.as_deref().unwrap_or_default();
+        check_layout_list(&format!("{}.else_elements", path), otherwise, &mut warnings);
+    }
+    warnings
+}
+
+fn check_layout_elements(parent: &str, elements: &[Element], warnings: &mut Vec<String>) {
+    check_layout_list(&format!("{}.elements", parent), elements, warnings);
+}
+
+fn check_layout_list(list: &str, elements: &[Element], warnings: &mut Vec<String>) {
+    for (i, element) in elements.iter().enumerate() {
+        check_layout_element(&format!("{}[{}]", list, i), element, warnings);
+    }
+}
+
+fn check_layout_element(path: &str, element: &Element, warnings: &mut Vec<String>) {
+    check_min_width(path, element.min_width(), warnings);
+    match element {
+        Element::Space(e) => check_lines(&format!("{}.lines", path), e.lines, warnings),
+        Element::Box(e) => check_layout_elements(path, &e.elements, warnings),
+        Element::Repeat(e) => check_layout_elements(path, &e.elements, warnings),
+        Element::Switch(e) => {
+            for (value, elements) in &e.cases {
+                check_layout_list(&format!("{}.cases.{}", path, value), elements, warnings);
+            }
+            let default = e.default.as_deref().unwrap_or_default();
+            check_layout_list(&format!("{}.default", path), default, warnings);
+        }
+        _ => {}
+    }
+    let otherwise = element.else_elements();
+    check_layout_list(&format!("{}.else_elements", path), otherwise, warnings);
+    if let Some(fallback) = element.fallback() {
+        check_layout_element(&format!("{}.fallback", path), fallback, warnings);
+    }
+}
+
+fn check_lines(path: &str, lines: Option<u32>, warnings: &mut Vec<String>) {
+    if let Some(lines) = lines.filter(|&lines| lines > SPACING_WARN_LINES) {
+        warnings.push(format!(
+            "{} is {} blank lines; more than {} is probably a mistake",
+            path, lines, SPACING_WARN_LINES
+        ));
+    }
+}
+
+fn check_min_width(path: &str, min_width: Option<&MinWidth>, warnings: &mut Vec<String>) {
+    let Some(min_width) = min_width else {
+        return;
+    };
+    let (widest, widest_columns) = PAPER_SIZES[PAPER_SIZES.len() - 1];
+    match (min_width, min_width.columns()) {
+        (MinWidth::Paper(size), None) => {
+            let sizes: Vec<&str> = PAPER_SIZES.iter().map(|(name, _)| *name).collect();
+            warnings.push(format!(
+                "{}.min_width \"{}\" is not a paper size; use {} or a number of characters \
+                 (it is ignored until then)",
+                path,
+                size,
+                sizes.join(", ")
+            ));
+        }
+        (_, Some(columns)) if columns > widest_columns => warnings.push(format!(
+            "{}.min_width needs {} but the widest supported paper ({}) has {}, so it never prints",
+            path, min_width, widest, widest_columns
+        )),
+        _ => {}
+    }
+}
+
+fn is_field_name(condition: &str) -> bool {
+    let condition = condition.trim();
+    !condition.is_empty()
+        && condition
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_condition_parsing() {
+        assert_eq!(
+            Condition::parse("tip >= 1"),
+            Condition::Test(Expr::parse("tip >= 1").unwrap())
+        );
+        assert_eq!(Condition::parse("tip >="), Condition::Always);
+        assert_eq!(
+            Condition::parse_row("modifiers"),
+            Condition::parse("modifiers != null")
+        );
+        assert_eq!(
+            Condition::parse_row("modifiers == 'none'"),
+            Condition::parse("modifiers == 'none'")
+        );
+    }
+
+    #[test]
+    fn test_try_parse_explains_rejections() {
+        assert!(Condition::try_parse("status = 'paid'").unwrap_err().contains("`==`"));
+        assert!(Condition::try_parse("(total > 1").unwrap_err().contains("missing `)`"));
+        assert!(Condition::try_parse("!= null").unwrap_err().contains("found `!=`"));
+        assert!(Condition::try_parse("items.length > 0 && !is_vip").is_ok());
+        // The lenient parser still renders whatever doesn't parse
+        assert_eq!(Condition::parse("!= null"), Condition::Always);
+    }
+
+    #[test]
+    fn test_check_conditions_reports_every_bad_condition() {
+        let template = crate::load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1.0",
+            "layout": { "sections": [
+                { "type": "header", "condition": "items.length > 0", "elements": [
+                    { "type": "text", "content": "Hi", "condition": "total >= 10 &&" }
+                ] },
+                { "type": "body", "elements": [
+                    { "type": "box", "elements": [
+                        { "type": "space" },
+                        { "type": "text", "content": "Paid", "condition": "status = 'paid'" }
+                    ] },
+                    { "type": "table", "data_source": "items",
+                      "columns": [{ "field": "name" }],
+                      "row_details": [
+                        { "field": "modifiers", "condition": "modifiers" },
+                        { "field": "notes", "condition": "notes <> 1" }
+                      ] }
+                ] }
+            ] }
+        }"#,
+        )
+        .unwrap();
+
+        let issues = check_conditions(&template);
+        let paths: Vec<&str> = issues.iter().map(|issue| issue.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "layout.sections[0].elements[0].condition",
+                "layout.sections[1].elements[0].elements[1].condition",
+                "layout.sections[1].elements[1].row_details[1].condition",
+            ]
+        );
+        assert_eq!(issues[1].condition, "status = 'paid'");
+        assert!(issues[1].error.contains("=="));
+    }
+
+    #[test]
+    fn test_check_layout_flags_impossible_min_width() {
+        let template = crate::load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1.0",
+            "layout": { "sections": [
+                { "type": "header", "min_width": "80mm", "elements": [
+                    { "type": "text", "content": "A", "min_width": 64 },
+                    { "type": "text", "content": "B", "min_width": 32,
+                      "fallback": { "type": "text", "content": "b", "min_width": "70mm" } }
+                ] }
+            ] }
+        }"#,
+        )
+        .unwrap();
+
+        let warnings = check_layout(&template);
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings[0].starts_with("layout.sections[0].elements[0].min_width needs 64 columns"));
+        assert!(warnings[0].ends_with("(80mm) has 48, so it never prints"));
+        assert!(warnings[1]
+            .starts_with("layout.sections[0].elements[1].fallback.min_width \"70mm\" is not"));
+    }
+
+    #[test]
+    fn test_check_layout_flags_absurd_spacing() {
+        let template = crate::load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1.0",
+            "layout": { "sections": [
+                { "type": "header", "spacing": { "before": 50, "after": 300 }, "elements": [
+                    { "type": "space", "lines": 3 },
+                    { "type": "box", "elements": [{ "type": "space", "lines": 51 }] }
+                ] }
+            ] }
+        }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            check_layout(&template),
+            vec![
+                "layout.sections[0].spacing.after is 300 blank lines; \
+                 more than 50 is probably a mistake"
+                    .to_string(),
+                "layout.sections[0].elements[1].elements[0].lines is 51 blank lines; \
+                 more than 50 is probably a mistake"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_pattern_splits_placeholders() {
+        let pattern =
+            TextPattern::parse("Order #{{order_id}}{{ item.name }} - {{ bad + }}{{total * 2}}!");
+        assert_eq!(
+            pattern.segments,
+            vec![
+                Segment::Literal("Order #".to_string()),
+                Segment::Variable("order_id".to_string()),
+                Segment::Variable("item.name".to_string()),
+                Segment::Literal(" - {{ bad + }}".to_string()),
+                Segment::Expr(Expr::parse("total * 2").unwrap()),
+                Segment::Literal("!".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+// src/template_data.rs
+// Checks receipt data against the variables a template declares
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::template_render::{ReceiptData, VariableDefinition};
+
+/// Declared variable types, by the name used in `VariableDefinition::var_type`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VarType {
+    Text,
+    Number,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl VarType {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "string" | "text" | "date" => Some(VarType::Text),
+            "number" | "currency" => Some(VarType::Number),
+            "boolean" | "bool" => Some(VarType::Boolean),
+            "array" => Some(VarType::Array),
+            "object" => Some(VarType::Object),
+            _ => None,
+        }
+    }
+}
+
+/// Check `data` against a template's declared variables and return a
+/// warning for every value that doesn't fit its declared type.
+///
+/// Safe coercions are applied to custom fields along the way, so
+/// conditions and rendering see the declared type afterward:
+/// - numbers and booleans declared as text become strings
+/// - numeric strings declared as number or currency become numbers, when
+///   that doesn't change how they print (`"12"` does, `"12.50"` stays text)
+/// - `"true"`/`"false"` declared as boolean become booleans
+///
+/// Built-in fields are already typed by `ReceiptData`, so for them only the
+/// declaration itself can be wrong.
+pub fn check_data(
+    variables: &HashMap<String, VariableDefinition>,
+    data: &mut ReceiptData,
+) -> Vec<String> {
+    let mut names: Vec<&String> = variables.keys().collect();
+    names.sort();
+
+    let mut warnings = Vec::new();
+    for name in names {
+        let definition = &variables[name];
+        let Some(var_type) = VarType::parse(&definition.var_type) else {
+            warnings.push(format!(
+                "`{}` is declared with unknown type \"{}\"",
+                name, definition.var_type
+            ));
+            continue;
+        };
+
+        let missing = definition
+            .required
+            .then(|| "is required but missing".to_string());
+        let problem = match builtin_value(name, data) {
+            Some(Builtin::Items) if var_type == VarType::Array => None,
+            Some(Builtin::Items) => Some(format!(
+                "is declared as {} but is the items list",
+                definition.var_type
+            )),
+            Some(Builtin::Unset) => missing,
+            Some(Builtin::Value(mut value)) => {
+                coerce(var_type, &definition.var_type, &mut value).err()
+            }
+            None => match data.custom.get_mut(name.as_str()) {
+                Some(value) if !value.is_null() => {
+                    coerce(var_type, &definition.var_type, value).err()
+                }
+                _ => missing,
+            },
+        };
+
+        if let Some(problem) = problem {
+            warnings.push(format!("`{}` {}", name, problem));
+        }
+    }
+    warnings
+}
+
+/// Convert `value` to the declared type if that is safe, or explain why it
+/// doesn't fit
+fn coerce(var_type: VarType, declared: &str, value: &mut Value) -> Result<(), String> {
+    let mismatch = |value: &Value| {
+        format!("is declared as {} but is {}", declared, describe(value))
+    };
+
+    match (var_type, &*value) {
+        (VarType::Text, Value::String(_))
+        | (VarType::Number, Value::Number(_))
+        | (VarType::Boolean, Value::Bool(_))
+        | (VarType::Object, Value::Object(_)) => Ok(()),
+        (VarType::Text, Value::Number(n)) => {
+            *value = Value::String(n.to_string());
+            Ok(())
+        }
+        (VarType::Text, Value::Bool(b)) => {
+            *value = Value::String(b.to_string());
+            Ok(())
+        }
+        (VarType::Number, Value::String(s)) => {
+            let text = s.trim();
+            if text.parse::<f64>().is_err() {
+                return Err(mismatch(value));
+            }
+            // Only convert when the number prints exactly like the text did
+            let number = match text.parse::<i64>() {
+                Ok(i) => Some(serde_json::Number::from(i)),
+                Err(_) => text.parse::<f64>().ok().and_then(serde_json::Number::from_f64),
+            };
+            if let Some(number) = number.filter(|n| n.to_string() == *s) {
+                *value = Value::Number(number);
+            }
+            Ok(())
+        }
+        (VarType::Boolean, Value::String(s)) if s == "true" || s == "false" => {
+            *value = Value::Bool(s == "true");
+            Ok(())
+        }
+        (VarType::Array, Value::Array(rows)) => {
+            match rows.iter().position(|row| !row.is_object()) {
+                Some(index) => Err(format!(
+                    "can't be used as a data source: row {} is {}, not an object",
+                    index,
+                    describe(&rows[index])
+                )),
+                None => Ok(()),
+            }
+        }
+        _ => Err(mismatch(value)),
+    }
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => format!("the boolean {}", b),
+        Value::Number(n) => format!("the number {}", n),
+        Value::String(s) => format!("the string \"{}\"", s),
+        Value::Array(_) => "an array".to_string(),
+        Value::Object(_) => "an object".to_string(),
+    }
+}
+
+enum Builtin {
+    Items,
+    Unset,
+    Value(Value),
+}
+
+/// The value of a built-in `ReceiptData` field, or `None` for custom fields
+fn builtin_value(name: &str, data: &ReceiptData) -> Option<Builtin> {
+    let text = |value: &Option<String>| match value {
+        Some(s) => Builtin::Value(Value::String(s.clone())),
+        None => Builtin::Unset,
+    };
+    let number = |value: Option<f64>| match value.and_then(serde_json::Number::from_f64) {
+        Some(n) => Builtin::Value(Value::Number(n)),
+        None => Builtin::Unset,
+    };
+
+    Some(match name {
+        "items" => Builtin::Items,
+        "store_name" => text(&data.store_name),
+        "store_address" => text(&data.store_address),
+        "store_phone" => text(&data.store_phone),
+        "store_website" => text(&data.store_website),
+        "date" => text(&data.date),
+        "time" => text(&data.time),
+        "cashier_name" => text(&data.cashier_name),
+        "server_name" => text(&data.server_name),
+        "table_number" => text(&data.table_number),
+        "order_type" => text(&data.order_type),
+        "course" => text(&data.course),
+        "seat" => text(&data.seat),
+        "original_order_id" => text(&data.original_order_id),
+        "copy_label" => text(&data.copy_label),
+        "footer_message" => text(&data.footer_message),
+        "farewell_message" => text(&data.farewell_message),
+        "receipt_url" => text(&data.receipt_url),
+        "order_id" => Builtin::Value(Value::String(data.order_id.clone())),
+        "timestamp" => Builtin::Value(Value::String(data.timestamp.clone())),
+        "receipt_type" => Builtin::Value(Value::String(data.receipt_type.as_str().to_string())),
+        "payment_method" => Builtin::Value(Value::String(data.payment_method.clone())),
+        "established_year" => match data.established_year {
+            Some(year) => Builtin::Value(Value::Number(year.into())),
+            None => Builtin::Unset,
+        },
+        "subtotal" => number(Some(data.subtotal)),
+        "tax" => number(Some(data.tax)),
+        "total" => number(Some(data.total)),
+        "tax_rate" => number(data.tax_rate),
+        "discount" => number(data.discount),
+        "tip" => number(data.tip),
+        "service_charge" => number(data.service_charge),
+        "service_rate" => number(data.service_rate),
+        "change" => number(data.change),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn declare(var_type: &str) -> HashMap<String, VariableDefinition> {
+        let definition = VariableDefinition {
+            var_type: var_type.to_string(),
+            required: false,
+            default: None,
+        };
+        HashMap::from([("value".to_string(), definition)])
+    }
+
+    /// Check one custom value declared as `var_type`; returns the value
+    /// afterward and the warnings
+    fn check(var_type: &str, value: Value) -> (Value, Vec<String>) {
+        let mut data: ReceiptData =
+            serde_json::from_value(json!({ "order_id": "1", "timestamp": "", "value": value }))
+                .unwrap();
+        let warnings = check_data(&declare(var_type), &mut data);
+        (data.custom["value"].clone(), warnings)
+    }
+
+    #[test]
+    fn test_matching_values_pass_unchanged() {
+        for (var_type, value) in [
+            ("string", json!("Table 4")),
+            ("number", json!(3)),
+            ("currency", json!(12.5)),
+            ("boolean", json!(true)),
+            ("array", json!([{ "name": "A" }])),
+            ("object", json!({ "a": 1 })),
+        ] {
+            assert_eq!(check(var_type, value.clone()), (value, vec![]), "{}", var_type);
+        }
+    }
+
+    #[test]
+    fn test_safe_coercions_applied() {
+        assert_eq!(check("string", json!(42)).0, json!("42"));
+        assert_eq!(check("string", json!(false)).0, json!("false"));
+        assert_eq!(check("number", json!("12")).0, json!(12));
+        assert_eq!(check("number", json!("0.5")).0, json!(0.5));
+        assert_eq!(check("boolean", json!("true")).0, json!(true));
+
+        // Numeric, so no warning, but converting would print "12.5"
+        assert_eq!(check("currency", json!("12.50")), (json!("12.50"), vec![]));
+    }
+
+    #[test]
+    fn test_incompatible_values_warn() {
+        for (var_type, value, expected) in [
+            ("string", json!([1]), "declared as string but is an array"),
+            ("number", json!("abc"), "declared as number but is the string \"abc\""),
+            ("currency", json!(true), "declared as currency but is the boolean true"),
+            ("boolean", json!(1), "declared as boolean but is the number 1"),
+            ("array", json!("x"), "declared as array but is the string \"x\""),
+            ("array", json!([{ "a": 1 }, 2]), "row 1 is the number 2, not an object"),
+            ("object", json!([]), "declared as object but is an array"),
+        ] {
+            let (after, warnings) = check(var_type, value.clone());
+            assert_eq!(after, value, "{} should be left as is", var_type);
+            assert_eq!(warnings.len(), 1, "{}", var_type);
+            assert!(warnings[0].contains(expected), "{}", warnings[0]);
+        }
+    }
+
+    #[test]
+    fn test_builtin_fields_and_missing_values() {
+        let mut variables = declare("nubmer");
+        variables.insert(
+            "store_name".to_string(),
+            VariableDefinition { var_type: "number".to_string(), required: false, default: None },
+        );
+        variables.insert(
+            "items".to_string(),
+            VariableDefinition { var_type: "array".to_string(), required: true, default: None },
+        );
+        variables.insert(
+            "loyalty_id".to_string(),
+            VariableDefinition { var_type: "string".to_string(), required: true, default: None },
+        );
+        let mut data: ReceiptData = serde_json::from_value(
+            json!({ "order_id": "1", "timestamp": "", "store_name": "Cafe", "value": 1 }),
+        )
+        .unwrap();
+
+        assert_eq!(
+            check_data(&variables, &mut data),
+            vec![
+                "`loyalty_id` is required but missing".to_string(),
+                "`store_name` is declared as number but is the string \"Cafe\"".to_string(),
+                "`value` is declared with unknown type \"nubmer\"".to_string(),
+            ]
+        );
+    }
+}
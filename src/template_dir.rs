@@ -0,0 +1,258 @@
+// src/template_dir.rs
+// Templates kept as JSON files in a folder, reloaded when the files change
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use crate::lock::LockExt;
+use crate::printer::PrinterManager;
+use crate::template_cache::TemplateCache;
+use crate::template_render::{load_template, ReceiptTemplate};
+
+/// How often the folder is checked for changed files
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A template file's state when it was last read
+#[derive(Debug, Clone, PartialEq)]
+struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+/// A change to the templates in the folder
+#[derive(Debug)]
+pub enum TemplateFileChange {
+    /// A file was added or changed
+    Loaded(Box<ReceiptTemplate>),
+    /// The file holding this template id was removed, or now holds another
+    Removed(String),
+}
+
+/// The `*.json` templates in a folder, one per file. A file that doesn't
+/// load is reported once per change and its last good template is kept,
+/// so a half-saved edit doesn't take a template away.
+pub struct TemplateDir {
+    dir: PathBuf,
+    /// Each file read so far, with the id of the template it last loaded
+    files: HashMap<PathBuf, (FileStamp, Option<String>)>,
+}
+
+impl TemplateDir {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Read the files added or changed since the last scan. A missing
+    /// folder holds no templates.
+    pub fn scan(&mut self) -> Vec<TemplateFileChange> {
+        let current = match json_files(&self.dir) {
+            Ok(files) => files,
+            Err(e) => {
+                log::warn!("Cannot read template folder {}: {}", self.dir.display(), e);
+                return Vec::new();
+            }
+        };
+
+        let mut changes = Vec::new();
+        self.files.retain(|path, (_, id)| {
+            if current.contains_key(path) {
+                return true;
+            }
+            log::info!("Template file {} removed", path.display());
+            changes.extend(id.take().map(TemplateFileChange::Removed));
+            false
+        });
+        for (path, stamp) in current {
+            let previous = self.files.get(&path);
+            if previous.is_some_and(|(seen, _)| *seen == stamp) {
+                continue;
+            }
+            let mut id = previous.and_then(|(_, id)| id.clone());
+            match read_template(&path) {
+                Ok(template) => {
+                    if let Some(old) = id.take().filter(|old| *old != template.id) {
+                        changes.push(TemplateFileChange::Removed(old));
+                    }
+                    log::info!("Template '{}' loaded from {}", template.id, path.display());
+                    id = Some(template.id.clone());
+                    changes.push(TemplateFileChange::Loaded(Box::new(template)));
+                }
+                Err(e) => log::warn!("Template file {} not loaded: {}", path.display(), e),
+            }
+            self.files.insert(path, (stamp, id));
+        }
+        changes
+    }
+}
+
+/// Apply changes from [`TemplateDir::scan`] to a cache. Templates from files
+/// are never evicted or cleared; `active` is kept when others are evicted
+/// to make room.
+pub fn apply_changes(
+    cache: &mut TemplateCache,
+    changes: Vec<TemplateFileChange>,
+    active: Option<&str>,
+) {
+    for change in changes {
+        match change {
+            TemplateFileChange::Loaded(template) => {
+                cache.insert_read_only(*template, active);
+            }
+            TemplateFileChange::Removed(id) => {
+                cache.remove(&id);
+            }
+        }
+    }
+}
+
+/// Loads a [`TemplateDir`] into the printer manager's template cache and
+/// keeps it in step with the files, checking every [`POLL_INTERVAL`].
+///
+/// The thread stops when the watcher is dropped.
+pub struct TemplateDirWatcher {
+    /// Dropping it ends the thread
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TemplateDirWatcher {
+    /// Load the folder's templates now, then watch it
+    pub fn spawn(
+        mut dir: TemplateDir,
+        manager: Arc<Mutex<PrinterManager>>,
+    ) -> Result<Self, String> {
+        sync(&mut dir, &manager);
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("template-dir".to_string())
+            .spawn(move || loop {
+                match stopped.recv_timeout(POLL_INTERVAL) {
+                    Err(RecvTimeoutError::Timeout) => sync(&mut dir, &manager),
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                }
+            })
+            .map_err(|e| format!("Cannot start template folder thread: {}", e))?;
+        Ok(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for TemplateDirWatcher {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Files are read before the manager is locked, so a slow disk doesn't hold
+/// up printing
+fn sync(dir: &mut TemplateDir, manager: &Mutex<PrinterManager>) {
+    let changes = dir.scan();
+    if changes.is_empty() {
+        return;
+    }
+    let mut manager = manager.lock_or_recover();
+    let active = manager.active_template_id.clone();
+    apply_changes(&mut manager.template_cache, changes, active.as_deref());
+}
+
+fn json_files(dir: &Path) -> std::io::Result<HashMap<PathBuf, FileStamp>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = HashMap::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        // Files removed between listing and reading are picked up next scan
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        if metadata.is_file() {
+            let stamp = FileStamp {
+                modified: metadata.modified()?,
+                len: metadata.len(),
+            };
+            files.insert(path, stamp);
+        }
+    }
+    Ok(files)
+}
+
+fn read_template(path: &Path) -> Result<ReceiptTemplate, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    load_template(&json).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(id: &str, name: &str) -> String {
+        format!(
+            r#"{{"id": "{}", "name": "{}", "version": "1", "layout": {{"sections": []}}}}"#,
+            id, name
+        )
+    }
+
+    #[test]
+    fn test_scan_follows_added_changed_and_removed_files() {
+        let dir = std::env::temp_dir().join(format!("nexora-templates-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut templates = TemplateDir::new(&dir);
+        let mut cache = TemplateCache::default();
+        let mut reload = |templates: &mut TemplateDir| {
+            let changes = templates.scan();
+            apply_changes(&mut cache, changes, None);
+            let mut cached: Vec<_> = cache
+                .iter()
+                .map(|(id, template)| format!("{}:{}", id, template.name))
+                .collect();
+            cached.sort();
+            cached
+        };
+
+        // No folder yet
+        assert!(reload(&mut templates).is_empty());
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("receipt.json"), template("receipt", "Receipt")).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a template").unwrap();
+        assert_eq!(reload(&mut templates), ["receipt:Receipt"]);
+        // Unchanged files aren't read again
+        assert!(templates.scan().is_empty());
+
+        std::fs::write(dir.join("receipt.json"), template("receipt", "Receipt v2")).unwrap();
+        std::fs::write(dir.join("kitchen.json"), template("kitchen", "Kitchen")).unwrap();
+        let both = ["kitchen:Kitchen", "receipt:Receipt v2"];
+        assert_eq!(reload(&mut templates), both);
+
+        // A broken edit keeps the last good template
+        std::fs::write(dir.join("kitchen.json"), r#"{"id": "kitchen""#).unwrap();
+        assert_eq!(reload(&mut templates), both);
+
+        std::fs::remove_file(dir.join("kitchen.json")).unwrap();
+        assert_eq!(reload(&mut templates), ["receipt:Receipt v2"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
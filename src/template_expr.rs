@@ -0,0 +1,582 @@
+// src/template_expr.rs
+// Expressions in template conditions and {{ }} placeholders
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::template_render::TableAggregate;
+
+/// A value an expression works with. Variables arrive as text and are read
+/// as numbers wherever an operator needs one.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    /// A number and the decimal places it prints with
+    Number(f64, usize),
+    Text(String),
+}
+
+impl Value {
+    fn from_text(text: String) -> Self {
+        if text.is_empty() {
+            Value::Null
+        } else {
+            Value::Text(text)
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        match self {
+            Value::Null => true,
+            Value::Text(text) => text.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// The value as a number, with its decimal places: `"12.50"` is 12.5
+    /// to two places
+    fn number(&self) -> Option<(f64, usize)> {
+        match self {
+            Value::Number(n, places) => Some((*n, *places)),
+            Value::Text(text) => {
+                let text = text.trim();
+                let n = text.parse::<f64>().ok().filter(|n| n.is_finite())?;
+                Some((n, decimal_places(text)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Text that doesn't read as a number
+    fn is_text(&self) -> bool {
+        matches!(self, Value::Text(_)) && self.number().is_none()
+    }
+
+    /// Whether a condition with this value holds: anything but empty,
+    /// `false` and zero
+    pub(crate) fn is_true(&self) -> bool {
+        if let Some((n, _)) = self.number() {
+            return n != 0.0;
+        }
+        match self {
+            Value::Bool(b) => *b,
+            Value::Text(text) => !text.is_empty() && text != "false",
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            // Adding 0.0 prints -0 as 0
+            Value::Number(n, places) => write!(f, "{:.*}", *places, n + 0.0),
+            Value::Text(text) => f.write_str(text),
+        }
+    }
+}
+
+pub(crate) fn decimal_places(number: &str) -> usize {
+    number
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+/// A parsed expression, such as `subtotal - discount > 10 && !is_vip`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Literal(Value),
+    /// A variable or dotted path, read from the receipt data
+    Variable(String),
+    /// A bare word on the right of `==` or `!=`: the variable if the data
+    /// has it, otherwise the word itself, as in `order_type == dine_in`
+    Word(String),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    /// A function over a data source, such as `sum(items.total)`, with the
+    /// data source and field as written
+    Aggregate(TableAggregate, String),
+}
+
+/// Where an expression reads its values
+pub(crate) trait Scope {
+    /// A variable's value, empty if there is none
+    fn variable(&self, name: &str) -> String;
+
+    /// `function` over the rows of a data source, `path` naming the data
+    /// source and usually one of its fields; empty if it can't be worked out
+    fn aggregate(&self, function: TableAggregate, path: &str) -> String;
+}
+
+/// Variables read through a function, with no data sources to aggregate
+impl<F: Fn(&str) -> String> Scope for F {
+    fn variable(&self, name: &str) -> String {
+        self(name)
+    }
+
+    fn aggregate(&self, _function: TableAggregate, _path: &str) -> String {
+        String::new()
+    }
+}
+
+impl Expr {
+    /// Parse an expression, explaining what is wrong if it doesn't parse
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let tokens = tokenize(text)?;
+        if tokens.is_empty() {
+            return Err("expression is empty".to_string());
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {}", token)),
+        }
+    }
+
+    /// Variables the expression reads, in order, with the paths functions
+    /// read. Bare words on the right of `==` and `!=` aren't counted, as
+    /// they may be meant as text.
+    pub(crate) fn variables<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Expr::Variable(name) | Expr::Aggregate(_, name) => names.push(name),
+            Expr::Literal(_) | Expr::Word(_) => {}
+            Expr::Not(inner) | Expr::Negate(inner) => inner.variables(names),
+            Expr::Binary(_, left, right) => {
+                left.variables(names);
+                right.variables(names);
+            }
+        }
+    }
+
+    /// Evaluate, reading variables from `scope`; missing ones are empty
+    /// text
+    pub(crate) fn eval(&self, scope: &dyn Scope) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Variable(name) => Value::from_text(scope.variable(name)),
+            Expr::Aggregate(function, path) => Value::from_text(scope.aggregate(*function, path)),
+            Expr::Word(word) => match scope.variable(word) {
+                value if value.is_empty() => Value::Text(word.clone()),
+                value => Value::Text(value),
+            },
+            Expr::Not(expr) => Value::Bool(!expr.eval(scope).is_true()),
+            Expr::Negate(expr) => match expr.eval(scope).number() {
+                Some((n, places)) => Value::Number(-n, places),
+                None => Value::Null,
+            },
+            Expr::Binary(Op::And, left, right) => {
+                Value::Bool(left.eval(scope).is_true() && right.eval(scope).is_true())
+            }
+            Expr::Binary(Op::Or, left, right) => {
+                Value::Bool(left.eval(scope).is_true() || right.eval(scope).is_true())
+            }
+            Expr::Binary(op, left, right) => apply(*op, left.eval(scope), right.eval(scope)),
+        }
+    }
+}
+
+/// Comparisons and arithmetic. `+` joins text; the other arithmetic
+/// operators need numbers and give `Null` otherwise, as does dividing by
+/// zero. Results keep the most decimal places of their operands, and
+/// division at least two.
+fn apply(op: Op, left: Value, right: Value) -> Value {
+    match op {
+        Op::Equal => Value::Bool(equal(&left, &right)),
+        Op::NotEqual => Value::Bool(!equal(&left, &right)),
+        Op::Less | Op::LessEqual | Op::Greater | Op::GreaterEqual => {
+            Value::Bool(compare(&left, &right).is_some_and(|ordering| match op {
+                Op::Less => ordering.is_lt(),
+                Op::LessEqual => ordering.is_le(),
+                Op::Greater => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }))
+        }
+        Op::Add if left.is_text() || right.is_text() => Value::Text(format!("{}{}", left, right)),
+        _ => {
+            let (Some((a, a_places)), Some((b, b_places))) = (left.number(), right.number()) else {
+                return Value::Null;
+            };
+            let places = a_places.max(b_places);
+            match op {
+                Op::Add => Value::Number(a + b, places),
+                Op::Subtract => Value::Number(a - b, places),
+                Op::Multiply => Value::Number(a * b, places),
+                Op::Divide if b != 0.0 => Value::Number(a / b, places.max(2)),
+                Op::Remainder if b != 0.0 => Value::Number(a % b, places),
+                _ => Value::Null,
+            }
+        }
+    }
+}
+
+/// `true` and `false` match by truth, `null` matches empty values, and
+/// numbers match numerically (`"12.50" == 12.5`)
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Bool(b), other) | (other, Value::Bool(b)) => other.is_true() == *b,
+        _ if left.is_null() || right.is_null() => left.is_null() && right.is_null(),
+        _ => match (left.number(), right.number()) {
+            (Some((a, _)), Some((b, _))) => (a - b).abs() < 1e-9,
+            _ => left.to_string() == right.to_string(),
+        },
+    }
+}
+
+/// Numbers compare numerically and text alphabetically; anything else,
+/// such as a missing value, doesn't compare
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left.number(), right.number()) {
+        (Some((a, _)), Some((b, _))) => a.partial_cmp(&b),
+        _ if left.is_text() && right.is_text() => Some(left.to_string().cmp(&right.to_string())),
+        _ => None,
+    }
+}
+
+// ==================== Parsing ====================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64, usize),
+    Text(String),
+    Name(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n, places) => write!(f, "`{:.*}`", *places, n),
+            Token::Text(text) => write!(f, "'{}'", text),
+            Token::Name(name) => write!(f, "`{}`", name),
+            Token::Symbol(symbol) => write!(f, "`{}`", symbol),
+        }
+    }
+}
+
+/// Two-character symbols come first so `<=` isn't read as `<`
+const SYMBOLS: [&str; 16] = [
+    "||", "&&", "==", "!=", "<=", ">=", "!", "<", ">", "+", "-", "*", "/", "%", "(", ")",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let number = &rest[..len];
+            let n = number
+                .parse::<f64>()
+                .map_err(|_| format!("`{}` is not a number", number))?;
+            tokens.push(Token::Number(n, decimal_places(number)));
+            len
+        } else if c.is_alphabetic() || c == '_' {
+            // Names include dotted paths such as `items.0.name`
+            let len = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_' && c != '.')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            len
+        } else if c == '\'' || c == '"' {
+            let end = rest[1..]
+                .find(c)
+                .ok_or_else(|| format!("text starting {} has no closing quote", c))?;
+            tokens.push(Token::Text(rest[1..end + 1].to_string()));
+            end + 2
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        } else if c == '=' {
+            return Err("use `==` to compare values".to_string());
+        } else if c == '&' || c == '|' {
+            return Err(format!("use `{0}{0}`", c));
+        } else {
+            return Err(format!("unexpected `{}`", c));
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+const OR: [(&str, Op); 1] = [("||", Op::Or)];
+const AND: [(&str, Op); 1] = [("&&", Op::And)];
+const COMPARISON: [(&str, Op); 6] = [
+    ("==", Op::Equal),
+    ("!=", Op::NotEqual),
+    ("<", Op::Less),
+    ("<=", Op::LessEqual),
+    (">", Op::Greater),
+    (">=", Op::GreaterEqual),
+];
+const SUM: [(&str, Op); 2] = [("+", Op::Add), ("-", Op::Subtract)];
+const PRODUCT: [(&str, Op); 3] = [("*", Op::Multiply), ("/", Op::Divide), ("%", Op::Remainder)];
+
+/// Recursive descent, loosest binding first: `||`, `&&`, comparisons,
+/// `+ -`, `* / %`, then `!` and `-` in front of a value
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while let Some(op) = self.op(&OR) {
+            expr = binary(op, expr, self.and()?);
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.comparison()?;
+        while let Some(op) = self.op(&AND) {
+            expr = binary(op, expr, self.comparison()?);
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.sum()?;
+        let Some(op) = self.op(&COMPARISON) else {
+            return Ok(left);
+        };
+        let right = match self.sum()? {
+            Expr::Variable(name) if matches!(op, Op::Equal | Op::NotEqual) => Expr::Word(name),
+            right => right,
+        };
+        if self.op(&COMPARISON).is_some() {
+            return Err("comparisons can't be chained; join them with `&&`".to_string());
+        }
+        Ok(binary(op, left, right))
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.op(&SUM) {
+            expr = binary(op, expr, self.product()?);
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.op(&PRODUCT) {
+            expr = binary(op, expr, self.unary()?);
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.symbol("!") {
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else if self.symbol("-") {
+            Ok(Expr::Negate(Box::new(self.unary()?)))
+        } else {
+            self.value()
+        }
+    }
+
+    fn value(&mut self) -> Result<Expr, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| "a value is missing at the end".to_string())?;
+        self.pos += 1;
+        Ok(match token {
+            Token::Number(n, places) => Expr::Literal(Value::Number(n, places)),
+            Token::Text(text) => Expr::Literal(Value::Text(text)),
+            Token::Name(name) if self.symbol("(") => self.call(&name)?,
+            Token::Name(name) => match name.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "null" => Expr::Literal(Value::Null),
+                _ => Expr::Variable(name),
+            },
+            Token::Symbol("(") => {
+                let expr = self.or()?;
+                if !self.symbol(")") {
+                    return Err("missing `)`".to_string());
+                }
+                expr
+            }
+            token => return Err(format!("expected a value, found {}", token)),
+        })
+    }
+
+    /// The rest of a function call after its `(`: a data source path,
+    /// then `)`
+    fn call(&mut self, function: &str) -> Result<Expr, String> {
+        let function = match function {
+            "sum" => TableAggregate::Sum,
+            "count" => TableAggregate::Count,
+            "avg" | "average" => TableAggregate::Average,
+            "min" => TableAggregate::Min,
+            "max" => TableAggregate::Max,
+            _ => {
+                return Err(format!(
+                    "unknown function `{}`; use sum, count, avg, min or max",
+                    function
+                ))
+            }
+        };
+        let Some(Token::Name(path)) = self.tokens.get(self.pos).cloned() else {
+            return Err("functions take a data source, as in `sum(items.total)`".to_string());
+        };
+        self.pos += 1;
+        if !self.symbol(")") {
+            return Err("missing `)`".to_string());
+        }
+        Ok(Expr::Aggregate(function, path))
+    }
+
+    /// Take the next token if it is one of `ops`
+    fn op(&mut self, ops: &[(&str, Op)]) -> Option<Op> {
+        let Some(Token::Symbol(symbol)) = self.tokens.get(self.pos) else {
+            return None;
+        };
+        let (_, op) = ops.iter().find(|(s, _)| s == symbol)?;
+        self.pos += 1;
+        Some(*op)
+    }
+
+    /// Take the next token if it is `symbol`
+    fn symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+}
+
+fn binary(op: Op, left: Expr, right: Expr) -> Expr {
+    Expr::Binary(op, Box::new(left), Box::new(right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expr: &str) -> String {
+        let variable = |name: &str| match name {
+            "subtotal" => "20.25".to_string(),
+            "discount" => "2.00".to_string(),
+            "quantity" => "3".to_string(),
+            "is_vip" => "true".to_string(),
+            "order_type" => "takeaway".to_string(),
+            "table_number" => "12".to_string(),
+            _ => String::new(),
+        };
+        Expr::parse(expr).unwrap().eval(&variable).to_string()
+    }
+
+    /// Aggregates over a data source of 2.50, 4.00 and 1.25
+    struct Totals;
+
+    impl Scope for Totals {
+        fn variable(&self, name: &str) -> String {
+            match name {
+                "discount" => "1.00".to_string(),
+                _ => String::new(),
+            }
+        }
+
+        fn aggregate(&self, function: TableAggregate, path: &str) -> String {
+            match (function, path) {
+                (TableAggregate::Sum, "items.total") => "7.75".to_string(),
+                (TableAggregate::Count, "items") => "3".to_string(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_arithmetic_and_text() {
+        assert_eq!(eval("subtotal - discount"), "18.25");
+        assert_eq!(eval("(subtotal - discount) * 2"), "36.50");
+        assert_eq!(eval("quantity * 2 + 1"), "7");
+        assert_eq!(eval("quantity / 2"), "1.50");
+        assert_eq!(eval("-quantity % 2"), "-1");
+        assert_eq!(eval("'Table ' + table_number"), "Table 12");
+        assert_eq!(eval("quantity / 0"), "");
+        assert_eq!(eval("missing * 2"), "");
+    }
+
+    #[test]
+    fn test_conditions() {
+        assert_eq!(eval("subtotal > 20 && is_vip"), "true");
+        assert_eq!(eval("subtotal - discount >= 18.25"), "true");
+        assert_eq!(eval("!(is_vip || quantity < 1)"), "false");
+        assert_eq!(eval("subtotal == 20.250"), "true");
+        assert_eq!(eval("is_vip == true && missing == false"), "true");
+        assert_eq!(eval("missing != null || missing > 0"), "false");
+        assert_eq!(eval("order_type == 'takeaway'"), "true");
+        // A bare word the data doesn't have is compared as text
+        assert_eq!(eval("order_type != dine_in"), "true");
+        assert_eq!(eval("order_type < 'zz'"), "true");
+    }
+
+    #[test]
+    fn test_aggregates_read_data_sources() {
+        let eval = |expr: &str| Expr::parse(expr).unwrap().eval(&Totals).to_string();
+        assert_eq!(eval("sum(items.total) - discount"), "6.75");
+        assert_eq!(eval("sum(items.total) / count(items)"), "2.58");
+        assert_eq!(eval("count(items) > 2"), "true");
+        assert_eq!(eval("max(items.total)"), "");
+
+        let expr = Expr::parse("avg(hourly.sales) > discount").unwrap();
+        let mut names = Vec::new();
+        expr.variables(&mut names);
+        assert_eq!(names, ["hourly.sales", "discount"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |expr: &str| Expr::parse(expr).unwrap_err();
+        assert_eq!(error("status = 'paid'"), "use `==` to compare values");
+        assert_eq!(error("a & b"), "use `&&`");
+        assert_eq!(error("(total > 1"), "missing `)`");
+        assert_eq!(error("total >"), "a value is missing at the end");
+        assert_eq!(error("!= null"), "expected a value, found `!=`");
+        assert_eq!(
+            error("1 < total < 5"),
+            "comparisons can't be chained; join them with `&&`"
+        );
+        assert_eq!(error("total 5"), "unexpected `5`");
+        assert_eq!(error("'open"), "text starting ' has no closing quote");
+        assert_eq!(
+            error("total(items)"),
+            "unknown function `total`; use sum, count, avg, min or max"
+        );
+        assert_eq!(
+            error("sum(2)"),
+            "functions take a data source, as in `sum(items.total)`"
+        );
+        assert_eq!(error("  "), "expression is empty");
+    }
+}
@@ -0,0 +1,303 @@
+// src/template_filter.rs
+// Filters that format placeholder values, as in {{total | currency}}
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use std::fmt::Write as _;
+
+use crate::template_render::{text_width, truncate_columns};
+
+/// A filter after a `|` in a placeholder
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Filter {
+    /// `currency` or `currency:"€"`: two decimal places after the symbol,
+    /// `$` by default
+    Currency(String),
+    /// `number` or `number:2`: digits grouped in thousands, to a number of
+    /// decimal places (none by default)
+    Number(usize),
+    /// `pad:10`, `pad:10,right` or `pad:10,center`: spaces up to a width
+    /// in columns
+    Pad(usize, Pad),
+    Upper,
+    Lower,
+    /// `truncate:20`: at most this many columns
+    Truncate(usize),
+    /// `default:"n/a"`: printed instead of an empty value
+    Default(String),
+    /// `format:"%d %b %Y"`: a date or time, in strftime syntax
+    Format(String),
+}
+
+/// Where `pad` puts the text within its width
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Pad {
+    Left,
+    Right,
+    Center,
+}
+
+impl Filter {
+    /// Parse one filter, such as `truncate:20`
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let (name, args) = match text.split_once(':') {
+            Some((name, args)) => (name.trim(), split_args(args)?),
+            None => (text.trim(), Vec::new()),
+        };
+        let arg = |i: usize| args.get(i).map(String::as_str);
+        let width = || -> Result<usize, String> {
+            let arg =
+                arg(0).ok_or_else(|| format!("`{}` needs a width, e.g. `{}:20`", name, name))?;
+            arg.parse()
+                .map_err(|_| format!("`{}` needs a width, not `{}`", name, arg))
+        };
+
+        Ok(match name {
+            "currency" => Filter::Currency(arg(0).unwrap_or("$").to_string()),
+            "number" => match arg(0) {
+                Some(places) => Filter::Number(
+                    places
+                        .parse()
+                        .map_err(|_| format!("`number` needs decimal places, not `{}`", places))?,
+                ),
+                None => Filter::Number(0),
+            },
+            "pad" => {
+                let pad = match arg(1) {
+                    None | Some("left") => Pad::Left,
+                    Some("right") => Pad::Right,
+                    Some("center") => Pad::Center,
+                    Some(other) => {
+                        return Err(format!(
+                            "`pad` aligns left, right or center, not `{}`",
+                            other
+                        ))
+                    }
+                };
+                Filter::Pad(width()?, pad)
+            }
+            "upper" => Filter::Upper,
+            "lower" => Filter::Lower,
+            "truncate" => Filter::Truncate(width()?),
+            "default" => Filter::Default(arg(0).unwrap_or_default().to_string()),
+            "format" => {
+                let format =
+                    arg(0).ok_or("`format` needs a pattern, e.g. `format:\"%d %b %Y\"`")?;
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(format!("`{}` is not a date format", format));
+                }
+                Filter::Format(format.to_string())
+            }
+            _ => return Err(format!("unknown filter `{}`", name)),
+        })
+    }
+
+    pub(crate) fn apply(&self, value: String) -> String {
+        match self {
+            Filter::Currency(symbol) => match number(&value) {
+                Some(n) => format_currency(symbol, n),
+                None => value,
+            },
+            Filter::Number(places) => match number(&value) {
+                Some(n) => group_thousands(&format!("{:.*}", places, n)),
+                None => value,
+            },
+            Filter::Pad(width, pad) => {
+                let space = width.saturating_sub(text_width(&value));
+                let before = match pad {
+                    Pad::Left => 0,
+                    Pad::Right => space,
+                    Pad::Center => space / 2,
+                };
+                format!(
+                    "{}{}{}",
+                    " ".repeat(before),
+                    value,
+                    " ".repeat(space - before)
+                )
+            }
+            Filter::Upper => value.to_uppercase(),
+            Filter::Lower => value.to_lowercase(),
+            Filter::Truncate(width) => truncate_columns(&value, *width).0.to_string(),
+            Filter::Default(default) if value.is_empty() => default.clone(),
+            Filter::Default(_) => value,
+            Filter::Format(format) => format_date(&value, format).unwrap_or(value),
+        }
+    }
+}
+
+/// `amount` to two decimal places after `symbol`, with the minus sign
+/// before the symbol. Amounts that round to zero print without one.
+pub(crate) fn format_currency(symbol: &str, amount: f64) -> String {
+    let amount = format!("{:.2}", amount);
+    match amount.strip_prefix('-') {
+        Some(digits) if digits.bytes().any(|b| matches!(b, b'1'..=b'9')) => {
+            format!("-{}{}", symbol, digits)
+        }
+        Some(digits) => format!("{}{}", symbol, digits),
+        None => format!("{}{}", symbol, amount),
+    }
+}
+
+/// Split a placeholder at the `|` before each filter, leaving `||` and
+/// quoted text alone
+pub(crate) fn split_pipes(text: &str) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match (quote, bytes[i]) {
+            (Some(q), b) if b == q => quote = None,
+            (Some(_), _) => {}
+            (None, b'\'' | b'"') => quote = Some(bytes[i]),
+            (None, b'|') if bytes.get(i + 1) == Some(&b'|') => i += 1,
+            (None, b'|') => {
+                parts.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Comma separated arguments, each optionally quoted
+fn split_args(args: &str) -> Result<Vec<String>, String> {
+    let mut out = Vec::new();
+    let mut rest = args.trim();
+    while !rest.is_empty() {
+        let (arg, after) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let end = rest[1..]
+                    .find(quote)
+                    .ok_or_else(|| format!("{} has no closing quote", rest))?;
+                (&rest[1..end + 1], &rest[end + 2..])
+            }
+            _ => match rest.find(',') {
+                Some(comma) => (rest[..comma].trim(), &rest[comma..]),
+                None => (rest, ""),
+            },
+        };
+        out.push(arg.to_string());
+        let after = after.trim_start();
+        rest = match after.strip_prefix(',') {
+            Some(next) => next.trim_start(),
+            None if after.is_empty() => after,
+            None => return Err(format!("expected `,` before `{}`", after)),
+        };
+    }
+    Ok(out)
+}
+
+fn number(value: &str) -> Option<f64> {
+    value.trim().parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+/// `1234567.5` as `1,234,567.5`
+fn group_thousands(number: &str) -> String {
+    let (sign, digits) = match number.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", number),
+    };
+    let (whole, fraction) = match digits.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (digits, None),
+    };
+    let mut out = sign.to_string();
+    for (i, c) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    if let Some(fraction) = fraction {
+        out.push('.');
+        out.push_str(fraction);
+    }
+    out
+}
+
+/// A date or time in one of the forms POS payloads use, formatted with
+/// `format`. `None` if the value isn't a date or the format needs a part
+/// it doesn't have.
+fn format_date(value: &str, format: &str) -> Option<String> {
+    let value = value.trim();
+    let mut out = String::new();
+    let written = if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        write!(out, "{}", time.format(format))
+    } else {
+        let time = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+            .iter()
+            .find_map(|pattern| NaiveDateTime::parse_from_str(value, pattern).ok())
+            .or_else(|| {
+                NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                    .ok()
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+            })?;
+        write!(out, "{}", time.format(format))
+    };
+    written.ok().map(|()| out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(filter: &str, value: &str) -> String {
+        Filter::parse(filter).unwrap().apply(value.to_string())
+    }
+
+    #[test]
+    fn test_filters_format_values() {
+        assert_eq!(apply("currency", "12.5"), "$12.50");
+        assert_eq!(apply("currency:\"€\"", "-3"), "-€3.00");
+        assert_eq!(apply("currency", "-0.001"), "$0.00");
+        assert_eq!(apply("currency", "n/a"), "n/a");
+        assert_eq!(apply("number", "1234567.5"), "1,234,568");
+        assert_eq!(apply("number:2", "-1234.5"), "-1,234.50");
+        assert_eq!(apply("pad:6,right", "42"), "    42");
+        assert_eq!(apply("pad:6,center", "42"), "  42  ");
+        assert_eq!(apply("pad:2", "long"), "long");
+        assert_eq!(apply("upper", "Flat white"), "FLAT WHITE");
+        assert_eq!(apply("truncate:5", "Croque monsieur"), "Croqu");
+        assert_eq!(apply("default:'n/a'", ""), "n/a");
+        assert_eq!(apply("default:'n/a'", "12"), "12");
+        assert_eq!(
+            apply("format:\"%d %b %Y\"", "2024-01-15 14:30:00"),
+            "15 Jan 2024"
+        );
+        assert_eq!(
+            apply("format:'%H:%M'", "2024-01-15T09:05:00+01:00"),
+            "09:05"
+        );
+        assert_eq!(apply("format:'%d/%m'", "tomorrow"), "tomorrow");
+    }
+
+    #[test]
+    fn test_filter_parse_errors() {
+        let error = |filter: &str| Filter::parse(filter).unwrap_err();
+        assert_eq!(error("shout"), "unknown filter `shout`");
+        assert_eq!(
+            error("truncate"),
+            "`truncate` needs a width, e.g. `truncate:20`"
+        );
+        assert_eq!(
+            error("pad:10,middle"),
+            "`pad` aligns left, right or center, not `middle`"
+        );
+        assert_eq!(error("format:'%Q'"), "`%Q` is not a date format");
+        assert_eq!(error("default:'open"), "'open has no closing quote");
+    }
+
+    #[test]
+    fn test_split_pipes_skips_or_and_quotes() {
+        assert_eq!(
+            split_pipes("a || b | default:'x|y' | upper"),
+            vec!["a || b ", " default:'x|y' ", " upper"]
+        );
+    }
+}
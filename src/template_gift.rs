@@ -0,0 +1,237 @@
+// src/template_gift.rs
+// Gift receipts: the same template with prices left out and an exchange
+// barcode added
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::template_compile::{Segment, TextPattern};
+use crate::template_filter::Filter;
+use crate::template_migrate::{else_elements, fallback};
+use crate::template_render::{
+    BarcodeElement, Element, ReceiptTemplate, Section, TableElement, TextElement,
+};
+use crate::template_validate::PRICE_FIELDS;
+
+/// How a template prints as a gift receipt: without prices or totals, and
+/// with a barcode the store scans to exchange the gift
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct GiftReceipt {
+    /// Print every receipt from the template as a gift receipt. Receipt
+    /// data's `gift_receipt` decides for one print.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Printed above the barcode, "GIFT RECEIPT" by default
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Barcode content, `{{order_id}}` by default
+    #[serde(default)]
+    pub barcode: Option<String>,
+    /// CODE128 (default) or another barcode element format
+    #[serde(default)]
+    pub barcode_format: Option<String>,
+}
+
+/// `template` as a gift receipt: elements, table columns and grid items
+/// that print prices are left out, and a section with the title and the
+/// exchange barcode goes before the footer
+pub fn gift_receipt(template: &ReceiptTemplate) -> ReceiptTemplate {
+    let settings = template.gift_receipt.clone().unwrap_or_default();
+    let mut gift = template.clone();
+    for section in &mut gift.layout.sections {
+        without_prices([&mut section.elements]);
+        without_prices(section.else_elements.iter_mut());
+    }
+
+    let title = TextElement {
+        content: settings.title.unwrap_or_else(|| "GIFT RECEIPT".to_string()),
+        align: Some("center".to_string()),
+        bold: Some(true),
+        font_size: Some(2),
+        ..Default::default()
+    };
+    let barcode = BarcodeElement {
+        content: settings
+            .barcode
+            .unwrap_or_else(|| "{{order_id}}".to_string()),
+        format: settings.barcode_format,
+        align: Some("center".to_string()),
+        ..Default::default()
+    };
+    let section = Section {
+        section_type: "gift".to_string(),
+        elements: vec![Element::Text(title), Element::Barcode(barcode)],
+        ..Default::default()
+    };
+    let sections = &mut gift.layout.sections;
+    let at = sections
+        .iter()
+        .position(|section| section.section_type == "footer")
+        .unwrap_or(sections.len());
+    sections.insert(at, section);
+    gift
+}
+
+fn without_prices<'a>(elements: impl IntoIterator<Item = &'a mut Vec<Element>>) {
+    for elements in elements {
+        elements.retain_mut(strip_prices);
+    }
+}
+
+/// Leave out the parts of `element` that print prices, and whether there's
+/// anything left of it to print
+fn strip_prices(element: &mut Element) -> bool {
+    match element {
+        Element::TaxSummary(_) | Element::TipSuggestions(_) => return false,
+        Element::Text(e) if has_price(&e.content) => return false,
+        Element::Row(e)
+            if [&e.left, &e.right, &e.center]
+                .into_iter()
+                .flatten()
+                .any(|text| has_price(text)) =>
+        {
+            return false
+        }
+        Element::Table(table) => {
+            let had_columns = !table.columns.is_empty();
+            strip_table(table);
+            if had_columns && table.columns.is_empty() {
+                return false;
+            }
+        }
+        Element::Grid(grid) => grid
+            .data
+            .retain(|item| !has_price(&item.label) && !has_price(&item.value)),
+        Element::Box(e) => without_prices([&mut e.elements]),
+        Element::Repeat(e) => without_prices([&mut e.elements]),
+        Element::Switch(e) => {
+            without_prices(e.cases.values_mut());
+            without_prices(e.default.iter_mut());
+        }
+        _ => {}
+    }
+    without_prices(else_elements(element).iter_mut());
+    let fallback = fallback(element);
+    if fallback
+        .as_deref_mut()
+        .is_some_and(|inner| !strip_prices(inner))
+    {
+        *fallback = None;
+    }
+    true
+}
+
+/// Drop price columns, and the footer values and running total that add
+/// them up
+fn strip_table(table: &mut TableElement) {
+    let is_price = |field: &str, format: Option<&str>| {
+        format == Some("currency") || PRICE_FIELDS.contains(&field)
+    };
+    table
+        .columns
+        .retain(|column| !is_price(&column.field, column.format.as_deref()));
+    if table
+        .running_total
+        .as_deref()
+        .is_some_and(|field| is_price(field, None))
+    {
+        table.running_total = None;
+    }
+
+    let columns: Vec<&str> = table.columns.iter().map(|c| c.field.as_str()).collect();
+    let footers = table.footer_rows.iter_mut().flatten();
+    for row in footers.chain(table.group_subtotal.as_mut()) {
+        row.values
+            .retain(|field, _| columns.contains(&field.as_str()));
+    }
+    if let Some(rows) = &mut table.footer_rows {
+        rows.retain(|row| !row.values.is_empty());
+    }
+    if table
+        .group_subtotal
+        .as_ref()
+        .is_some_and(|row| row.values.is_empty())
+    {
+        table.group_subtotal = None;
+    }
+}
+
+/// Whether `text` has a placeholder reading a price field or formatting a
+/// value as currency
+fn has_price(text: &str) -> bool {
+    TextPattern::placeholders(text)
+        .into_iter()
+        .filter_map(|(_, parsed)| parsed.ok())
+        .any(|segment| segment_has_price(&segment))
+}
+
+fn segment_has_price(segment: &Segment) -> bool {
+    if let Segment::Filtered { value, filters } = segment {
+        if filters.iter().any(|f| matches!(f, Filter::Currency(_))) {
+            return true;
+        }
+        return segment_has_price(value);
+    }
+    let mut names = Vec::new();
+    segment.variables(&mut names);
+    names.iter().any(|name| {
+        let field = name.rsplit('.').next().unwrap_or(name);
+        PRICE_FIELDS.contains(&field)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_template;
+
+    #[test]
+    fn test_prices_are_left_out_and_a_barcode_added() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1",
+            "gift_receipt": { "barcode": "GIFT-{{order_id}}" },
+            "layout": { "sections": [
+                { "type": "items", "elements": [
+                    { "type": "text", "content": "Order {{order_id}}" },
+                    { "type": "text", "content": "Paid {{amount_paid | currency}}" },
+                    { "type": "table", "data_source": "items", "columns": [
+                        { "field": "name", "width": 20 },
+                        { "field": "unit_cost", "width": 8, "format": "currency" },
+                        { "field": "total", "width": 8 }
+                    ], "footer_rows": [{ "label": "Total", "values": { "total": "sum" } }] },
+                    { "type": "box", "elements": [
+                        { "type": "row", "left": "Total", "right": "{{subtotal + tax}}" },
+                        { "type": "row", "left": "Items", "right": "{{count(items)}}" }
+                    ]},
+                    { "type": "tax_summary" }
+                ]},
+                { "type": "footer", "elements": [{ "type": "text", "content": "Thanks" }] }
+            ]}
+        }"#,
+        )
+        .unwrap();
+        let gift = gift_receipt(&template);
+
+        let sections = &gift.layout.sections;
+        let types: Vec<&str> = sections.iter().map(|s| s.section_type.as_str()).collect();
+        assert_eq!(types, ["items", "gift", "footer"]);
+        let items = &sections[0].elements;
+        assert_eq!(items.len(), 3);
+        let Element::Table(table) = &items[1] else {
+            panic!("not a table")
+        };
+        let fields: Vec<&str> = table.columns.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, ["name"]);
+        assert!(table.footer_rows.as_ref().unwrap().is_empty());
+        let Element::Box(inner) = &items[2] else {
+            panic!("not a box")
+        };
+        assert_eq!(inner.elements.len(), 1);
+
+        let Element::Barcode(barcode) = &sections[1].elements[1] else {
+            panic!("not a barcode")
+        };
+        assert_eq!(barcode.content, "GIFT-{{order_id}}");
+    }
+}
@@ -0,0 +1,370 @@
+// src/template_import.rs
+// TypeScript template exports from the web codebase, converted to JSON
+
+use serde_json::{Map, Number, Value};
+
+/// The template stored under `template_id` in a TypeScript export, such as
+/// `"luxury-spa": { id: 'luxury-spa', ... }` in `pro-template.ts`, as JSON
+/// that [`load_template`](crate::load_template) accepts.
+///
+/// Keys may be unquoted, strings may use single quotes or backticks, and
+/// trailing commas, comments and `as const` are dropped. Values must be
+/// literals: a variable, spread or `${...}` in a template literal is an
+/// error naming the line and column.
+pub fn parse_template_export(content: &str, template_id: &str) -> Result<String, String> {
+    let key = regex::escape(template_id);
+    let pattern = format!(r#"(?:"{0}"|'{0}'|\b{0})\s*:\s*\{{"#, key);
+    let re = regex::Regex::new(&pattern).map_err(|e| e.to_string())?;
+    let found = re
+        .find(content)
+        .ok_or_else(|| format!("Template '{}' is not in the export", template_id))?;
+
+    let mut reader = Reader {
+        source: content,
+        pos: found.end() - 1,
+    };
+    let template = reader.value()?;
+    serde_json::to_string_pretty(&template).map_err(|e| e.to_string())
+}
+
+/// JSON for a TypeScript object or array literal, with the same leniency as
+/// [`parse_template_export`]
+pub fn ts_to_json(source: &str) -> Result<String, String> {
+    let mut reader = Reader { source, pos: 0 };
+    let value = reader.value()?;
+    reader.skip_blank()?;
+    reader.eat(';');
+    reader.skip_blank()?;
+    if reader.pos < source.len() {
+        return Err(reader.error("unexpected text after the value"));
+    }
+    serde_json::to_string_pretty(&value).map_err(|e| e.to_string())
+}
+
+struct Reader<'a> {
+    source: &'a str,
+    /// Byte offset of the next character
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += c.len_utf8();
+        }
+        found
+    }
+
+    fn error(&self, message: &str) -> String {
+        let before = &self.source[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        format!("{} (line {}, column {})", message, line, column)
+    }
+
+    /// Whitespace and comments
+    fn skip_blank(&mut self) -> Result<(), String> {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                let end = trimmed
+                    .find("*/")
+                    .ok_or_else(|| self.error("unterminated comment"))?;
+                self.pos += end + 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_blank()?;
+        let value = match self.peek() {
+            Some('{') => self.object()?,
+            Some('[') => self.array()?,
+            Some(quote @ ('"' | '\'' | '`')) => Value::String(self.string(quote)?),
+            Some(c) if c.is_ascii_digit() || matches!(c, '-' | '+' | '.') => self.number()?,
+            Some(c) if is_ident_start(c) => match self.ident() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" | "undefined" => Value::Null,
+                name => {
+                    let message = format!("`{}` is a variable; only literal values convert", name);
+                    self.pos -= name.len();
+                    return Err(self.error(&message));
+                }
+            },
+            Some(c) => return Err(self.error(&format!("unexpected `{}`", c))),
+            None => return Err(self.error("unexpected end of the export")),
+        };
+        self.skip_casts()?;
+        Ok(value)
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut object = Map::new();
+        loop {
+            self.skip_blank()?;
+            if self.eat('}') {
+                return Ok(Value::Object(object));
+            }
+            let key = match self.peek() {
+                Some(quote @ ('"' | '\'' | '`')) => self.string(quote)?,
+                Some(c) if is_ident_start(c) || c.is_ascii_digit() => self.ident().to_string(),
+                Some('.') => return Err(self.error("spread `...` can't be converted")),
+                _ => return Err(self.error("expected a property name")),
+            };
+            self.skip_blank()?;
+            if !self.eat(':') {
+                return Err(self.error(&format!("expected `:` after `{}`", key)));
+            }
+            let value = self.value()?;
+            object.insert(key, value);
+
+            self.skip_blank()?;
+            if !self.eat(',') && self.peek() != Some('}') {
+                return Err(self.error("expected `,` or `}`"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut array = Vec::new();
+        loop {
+            self.skip_blank()?;
+            if self.eat(']') {
+                return Ok(Value::Array(array));
+            }
+            if self.rest().starts_with("...") {
+                return Err(self.error("spread `...` can't be converted"));
+            }
+            array.push(self.value()?);
+
+            self.skip_blank()?;
+            if !self.eat(',') && self.peek() != Some(']') {
+                return Err(self.error("expected `,` or `]`"));
+            }
+        }
+    }
+
+    /// A quoted string or a template literal without placeholders
+    fn string(&mut self, quote: char) -> Result<String, String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut text = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => {
+                    self.pos = start;
+                    return Err(self.error("unterminated string"));
+                }
+            };
+            self.pos += c.len_utf8();
+            match c {
+                _ if c == quote => return Ok(text),
+                '$' if quote == '`' && self.peek() == Some('{') => {
+                    self.pos -= 1;
+                    return Err(self.error("`${...}` in a template literal can't be converted"));
+                }
+                '\\' => self.escape(&mut text)?,
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self, text: &mut String) -> Result<(), String> {
+        let c = self
+            .peek()
+            .ok_or_else(|| self.error("unterminated string"))?;
+        self.pos += c.len_utf8();
+        match c {
+            'n' => text.push('\n'),
+            't' => text.push('\t'),
+            'r' => text.push('\r'),
+            'b' => text.push('\u{8}'),
+            'f' => text.push('\u{c}'),
+            'v' => text.push('\u{b}'),
+            '0' => text.push('\0'),
+            // A backslash at the end of a line continues the string
+            '\n' => {}
+            'x' => text.push(self.code_point(2)?),
+            'u' if self.eat('{') => {
+                let end = self
+                    .rest()
+                    .find('}')
+                    .ok_or_else(|| self.error("unterminated `\\u{...}`"))?;
+                let c = self.code_point(end)?;
+                self.pos += 1;
+                text.push(c);
+            }
+            'u' => text.push(self.code_point(4)?),
+            c => text.push(c),
+        }
+        Ok(())
+    }
+
+    /// The character whose hex code is the next `digits` characters
+    fn code_point(&mut self, digits: usize) -> Result<char, String> {
+        let hex = self.rest().get(..digits).unwrap_or("");
+        let c = u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error(&format!("bad escape code `{}`", hex)))?;
+        self.pos += digits;
+        Ok(c)
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        let mut previous = None;
+        while let Some(c) = self.peek() {
+            let sign = matches!(c, '-' | '+')
+                && (self.pos == start || matches!(previous, Some('e' | 'E')));
+            if !(c.is_ascii_alphanumeric() || matches!(c, '.' | '_') || sign) {
+                break;
+            }
+            previous = Some(c);
+            self.pos += 1;
+        }
+
+        let text = self.source[start..self.pos].replace('_', "");
+        let text = text.strip_prefix('+').unwrap_or(&text);
+        let number = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(hex) => i64::from_str_radix(hex, 16).ok().map(Number::from),
+            None => match text.parse::<i64>() {
+                Ok(n) => Some(Number::from(n)),
+                Err(_) => text.parse::<f64>().ok().and_then(Number::from_f64),
+            },
+        };
+        match number {
+            Some(number) => Ok(Value::Number(number)),
+            None => {
+                self.pos = start;
+                Err(self.error(&format!("`{}` is not a number", text)))
+            }
+        }
+    }
+
+    fn ident(&mut self) -> &'a str {
+        let start = self.pos;
+        let len = self
+            .rest()
+            .find(|c: char| !is_ident_char(c))
+            .unwrap_or(self.rest().len());
+        self.pos += len;
+        &self.source[start..self.pos]
+    }
+
+    /// `as const`, `as Template` and `satisfies Template` after a value
+    fn skip_casts(&mut self) -> Result<(), String> {
+        loop {
+            let before = self.pos;
+            self.skip_blank()?;
+            let keyword = match self.peek() {
+                Some(c) if is_ident_start(c) => self.ident(),
+                _ => "",
+            };
+            if keyword != "as" && keyword != "satisfies" {
+                self.pos = before;
+                return Ok(());
+            }
+            self.skip_blank()?;
+            self.ident();
+            while self.rest().starts_with("[]") {
+                self.pos += 2;
+            }
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == '$'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_template;
+
+    const EXPORT: &str = r#"
+import type { ReceiptTemplate } from './types';
+
+export const templates: Record<string, ReceiptTemplate> = {
+    // Shown on the spa's receipts
+    "luxury-spa": {
+        id: 'luxury-spa',
+        name: "Luxury Spa & Wellness",
+        version: '1.0.0',
+        paper_width: 48,
+        layout: {
+            sections: [
+                {
+                    type: 'header',
+                    elements: [
+                        { type: 'text', content: `WELLNESS
+& BEAUTY`, align: 'center', font_size: 1, },
+                        /* a gradient line */
+                        { type: 'divider', style: 'gradient' as const },
+                        { type: 'space', lines: 0x2 },
+                    ],
+                },
+            ],
+        },
+    },
+    'kitchen': { id: 'kitchen', name: 'It\'s hot', version: '1', layout: { sections: [] } },
+};
+"#;
+
+    #[test]
+    fn test_export_converts_to_a_template() {
+        let json = parse_template_export(EXPORT, "luxury-spa").unwrap();
+        let template = load_template(&json).unwrap();
+        assert_eq!(template.name, "Luxury Spa & Wellness");
+        let elements = &template.layout.sections[0].elements;
+        assert_eq!(elements.len(), 3);
+        let value: Value = serde_json::from_str(&json).unwrap();
+        let text = &value["layout"]["sections"][0]["elements"][0];
+        assert_eq!(text["content"], "WELLNESS\n& BEAUTY");
+        assert_eq!(value["layout"]["sections"][0]["elements"][2]["lines"], 2);
+
+        let kitchen = load_template(&parse_template_export(EXPORT, "kitchen").unwrap()).unwrap();
+        assert_eq!(kitchen.name, "It's hot");
+        assert!(parse_template_export(EXPORT, "missing").is_err());
+    }
+
+    #[test]
+    fn test_non_literal_values_are_reported_where_they_are() {
+        assert_eq!(
+            ts_to_json("{\n  name: `Hi ${name}`\n}").unwrap_err(),
+            "`${...}` in a template literal can't be converted (line 2, column 13)"
+        );
+        assert_eq!(
+            ts_to_json("{ width: WIDTH }").unwrap_err(),
+            "`WIDTH` is a variable; only literal values convert (line 1, column 10)"
+        );
+        assert_eq!(
+            ts_to_json("[1, 2.5, -3e2, true, null,]").unwrap(),
+            "[\n  1,\n  2.5,\n  -300.0,\n  true,\n  null\n]"
+        );
+    }
+}
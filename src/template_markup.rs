@@ -0,0 +1,140 @@
+// src/template_markup.rs
+// Inline styles in text content: **bold**, __underline__, {big} and {invert}
+
+use crate::template_compile::{Segment, TextPattern};
+
+/// Styles markup has turned on, over those of the element
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct MarkupStyle {
+    pub bold: bool,
+    pub underline: bool,
+    /// Twice the element's width and height
+    pub big: bool,
+    pub invert: bool,
+}
+
+/// A stretch of content printed in one style
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MarkupSpan {
+    pub style: MarkupStyle,
+    pub content: TextPattern,
+}
+
+/// Split content at its markup. `**` and `__` turn bold and underline on
+/// and off; `{big}` and `{invert}` last until `{/big}` and `{/invert}`.
+/// Markup left open runs to the end of the text, and only the template's
+/// own text is read, never the values of placeholders.
+pub(crate) fn parse_markup(pattern: &TextPattern) -> Vec<MarkupSpan> {
+    let mut spans = Vec::new();
+    let mut style = MarkupStyle::default();
+    let mut segments = Vec::new();
+    for segment in &pattern.segments {
+        let Segment::Literal(text) = segment else {
+            segments.push(segment.clone());
+            continue;
+        };
+
+        let mut rest = text.as_str();
+        while !rest.is_empty() {
+            let Some((start, marker)) = next_marker(rest) else {
+                segments.push(Segment::Literal(rest.to_string()));
+                break;
+            };
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            if !segments.is_empty() {
+                spans.push(MarkupSpan {
+                    style,
+                    content: TextPattern {
+                        segments: std::mem::take(&mut segments),
+                    },
+                });
+            }
+            match marker {
+                "**" => style.bold = !style.bold,
+                "__" => style.underline = !style.underline,
+                "{big}" => style.big = true,
+                "{/big}" => style.big = false,
+                "{invert}" => style.invert = true,
+                _ => style.invert = false,
+            }
+            rest = &rest[start + marker.len()..];
+        }
+    }
+    if !segments.is_empty() {
+        spans.push(MarkupSpan {
+            style,
+            content: TextPattern { segments },
+        });
+    }
+    spans
+}
+
+const MARKERS: [&str; 6] = ["**", "__", "{big}", "{/big}", "{invert}", "{/invert}"];
+
+/// Where the first marker in `text` starts, and which it is
+fn next_marker(text: &str) -> Option<(usize, &'static str)> {
+    MARKERS
+        .iter()
+        .filter_map(|&marker| text.find(marker).map(|start| (start, marker)))
+        .min_by_key(|&(start, _)| start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(text: &str) -> TextPattern {
+        TextPattern {
+            segments: vec![Segment::Literal(text.to_string())],
+        }
+    }
+
+    #[test]
+    fn test_markers_split_content_into_spans() {
+        let spans = parse_markup(&TextPattern::parse(
+            "Total: **{{total}}** {big}__PAID__{/big}",
+        ));
+        let bold = MarkupStyle {
+            bold: true,
+            ..Default::default()
+        };
+        let big_underline = MarkupStyle {
+            big: true,
+            underline: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            spans,
+            vec![
+                MarkupSpan {
+                    style: MarkupStyle::default(),
+                    content: literal("Total: "),
+                },
+                MarkupSpan {
+                    style: bold,
+                    content: TextPattern {
+                        segments: vec![Segment::Variable("total".to_string())],
+                    },
+                },
+                MarkupSpan {
+                    style: MarkupStyle::default(),
+                    content: literal(" "),
+                },
+                MarkupSpan {
+                    style: big_underline,
+                    content: literal("PAID"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_open_markup_runs_to_the_end() {
+        let spans = parse_markup(&literal("{invert} VOID "));
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].style.invert);
+        assert_eq!(spans[0].content, literal(" VOID "));
+    }
+}
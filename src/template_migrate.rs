@@ -0,0 +1,236 @@
+// src/template_migrate.rs
+// Schema versions of the template format and upgrades of older templates
+
+use schemars::JsonSchema;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::template_render::{Element, ReceiptTemplate};
+
+/// Template format this build reads. Templates without a `schema_version`
+/// are version 1.
+pub const TEMPLATE_SCHEMA_VERSION: u32 = 2;
+
+/// The template format a template is written in. Versions newer than
+/// [`TEMPLATE_SCHEMA_VERSION`] don't load, since they may use settings
+/// this build would print wrongly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct SchemaVersion(pub u32);
+
+impl<'de> Deserialize<'de> for SchemaVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = u32::deserialize(deserializer)?;
+        if version > TEMPLATE_SCHEMA_VERSION {
+            return Err(D::Error::custom(format!(
+                "schema version {} is newer than this printer manager reads ({}); update it",
+                version, TEMPLATE_SCHEMA_VERSION
+            )));
+        }
+        Ok(SchemaVersion(version))
+    }
+}
+
+/// `template` upgraded to [`TEMPLATE_SCHEMA_VERSION`], or `None` when it's
+/// current
+pub fn migrate(template: &ReceiptTemplate) -> Option<ReceiptTemplate> {
+    let version = template.schema_version.map_or(1, |version| version.0);
+    if version >= TEMPLATE_SCHEMA_VERSION {
+        return None;
+    }
+
+    let mut migrated = template.clone();
+    if version < 2 {
+        for_each_element(&mut migrated, drop_css_styles);
+    }
+    migrated.schema_version = Some(SchemaVersion(TEMPLATE_SCHEMA_VERSION));
+    Some(migrated)
+}
+
+/// Version 1 printed the web editor's `font_weight` and `background`
+/// plain, which version 2 reads; they're dropped so the template prints
+/// as it did
+fn drop_css_styles(element: &mut Element) {
+    match element {
+        Element::Text(e) => (e.font_weight, e.background) = (None, None),
+        Element::Row(e) => (e.font_weight, e.background) = (None, None),
+        _ => {}
+    }
+}
+
+/// `bold` as a CSS `font_weight` sets it, from schema version 2
+pub(crate) fn css_bold(font_weight: Option<&str>) -> Option<bool> {
+    let heavy = matches!(
+        font_weight.map(str::trim),
+        Some("bold" | "bolder" | "600" | "700" | "800" | "900")
+    );
+    heavy.then_some(true)
+}
+
+/// `invert` as a CSS `background` sets it, from schema version 2
+pub(crate) fn css_invert(background: Option<&str>) -> Option<bool> {
+    let dark = matches!(
+        background.map(str::trim),
+        Some("black" | "#000" | "#000000")
+    );
+    dark.then_some(true)
+}
+
+/// Run `f` on every element of the template, including those inside
+/// boxes, repeats, switches, `else_elements` and fallbacks
+fn for_each_element(template: &mut ReceiptTemplate, f: fn(&mut Element)) {
+    for section in &mut template.layout.sections {
+        each(&mut section.elements, f);
+        each(section.else_elements.iter_mut().flatten(), f);
+    }
+}
+
+fn each<'a>(elements: impl IntoIterator<Item = &'a mut Element>, f: fn(&mut Element)) {
+    for element in elements {
+        f(element);
+        match element {
+            Element::Box(e) => each(&mut e.elements, f),
+            Element::Repeat(e) => each(&mut e.elements, f),
+            Element::Switch(e) => {
+                each(e.cases.values_mut().flatten(), f);
+                each(e.default.iter_mut().flatten(), f);
+            }
+            _ => {}
+        }
+        each(else_elements(element).iter_mut().flatten(), f);
+        if let Some(fallback) = fallback(element).as_deref_mut() {
+            each([fallback], f);
+        }
+    }
+}
+
+/// The elements printed when `element`'s condition is false
+pub(crate) fn else_elements(element: &mut Element) -> &mut Option<Vec<Element>> {
+    match element {
+        Element::Text(e) => &mut e.else_elements,
+        Element::Logo(e) => &mut e.else_elements,
+        Element::Image(e) => &mut e.else_elements,
+        Element::Divider(e) => &mut e.else_elements,
+        Element::Row(e) => &mut e.else_elements,
+        Element::QR(e) => &mut e.else_elements,
+        Element::Barcode(e) => &mut e.else_elements,
+        Element::Table(e) => &mut e.else_elements,
+        Element::Space(e) => &mut e.else_elements,
+        Element::Box(e) => &mut e.else_elements,
+        Element::Grid(e) => &mut e.else_elements,
+        Element::BarChart(e) => &mut e.else_elements,
+        Element::Leaderboard(e) => &mut e.else_elements,
+        Element::Breakdown(e) => &mut e.else_elements,
+        Element::Coupon(e) => &mut e.else_elements,
+        Element::Repeat(e) => &mut e.else_elements,
+        Element::Switch(e) => &mut e.else_elements,
+        Element::KitchenItems(e) => &mut e.else_elements,
+        Element::TaxSummary(e) => &mut e.else_elements,
+        Element::TipSuggestions(e) => &mut e.else_elements,
+    }
+}
+
+/// The element printed in place of `element` on paper narrower than its
+/// `min_width`
+pub(crate) fn fallback(element: &mut Element) -> &mut Option<Box<Element>> {
+    match element {
+        Element::Text(e) => &mut e.fallback,
+        Element::Logo(e) => &mut e.fallback,
+        Element::Image(e) => &mut e.fallback,
+        Element::Divider(e) => &mut e.fallback,
+        Element::Row(e) => &mut e.fallback,
+        Element::QR(e) => &mut e.fallback,
+        Element::Barcode(e) => &mut e.fallback,
+        Element::Table(e) => &mut e.fallback,
+        Element::Space(e) => &mut e.fallback,
+        Element::Box(e) => &mut e.fallback,
+        Element::Grid(e) => &mut e.fallback,
+        Element::BarChart(e) => &mut e.fallback,
+        Element::Leaderboard(e) => &mut e.fallback,
+        Element::Breakdown(e) => &mut e.fallback,
+        Element::Coupon(e) => &mut e.fallback,
+        Element::Repeat(e) => &mut e.fallback,
+        Element::Switch(e) => &mut e.fallback,
+        Element::KitchenItems(e) => &mut e.fallback,
+        Element::TaxSummary(e) => &mut e.fallback,
+        Element::TipSuggestions(e) => &mut e.fallback,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::encode_commands;
+    use crate::load_template;
+    use crate::template_compile::CompiledElement;
+    use crate::template_render::{ReceiptData, TemplateRenderer};
+
+    fn template(schema_version: &str) -> String {
+        format!(
+            r##"{{
+            "id": "t", "name": "T", "version": "1"{},
+            "layout": {{ "sections": [{{ "type": "body", "elements": [
+                {{ "type": "text", "content": "A", "font_weight": "bold" }},
+                {{ "type": "box", "elements": [
+                    {{ "type": "row", "left": "B", "background": "#000", "bold": false }}
+                ]}}
+            ]}}]}}
+        }}"##,
+            schema_version
+        )
+    }
+
+    fn render(template: &ReceiptTemplate) -> Vec<u8> {
+        let commands = TemplateRenderer::new(32)
+            .render_template(template, &ReceiptData::default())
+            .unwrap();
+        encode_commands(&commands)
+    }
+
+    #[test]
+    fn test_version_1_templates_print_as_before_the_upgrade() {
+        let old = load_template(&template("")).unwrap();
+        let migrated = migrate(&old).unwrap();
+        assert_eq!(migrated.schema_version, Some(SchemaVersion(2)));
+        let Element::Text(text) = &migrated.layout.sections[0].elements[0] else {
+            panic!("not text")
+        };
+        assert_eq!((&text.font_weight, text.bold), (&None, None));
+
+        // Version 1 printed `font_weight` and `background` plain
+        let plain = template("")
+            .replace(r#", "font_weight": "bold""#, "")
+            .replace(r##", "background": "#000""##, "");
+        let plain = render(&load_template(&plain).unwrap());
+        assert_eq!(render(&old), plain);
+        assert_eq!(render(&migrated), plain);
+    }
+
+    #[test]
+    fn test_version_2_css_styles_print_bold_and_invert() {
+        let current = load_template(&template(r#", "schema_version": 2"#)).unwrap();
+        assert!(migrate(&current).is_none());
+
+        let compiled = TemplateRenderer::new(32).compile(&current);
+        let elements = &compiled.sections[0].elements;
+        let CompiledElement::Text { element: text, .. } = &elements[0].element else {
+            panic!("not text")
+        };
+        assert_eq!(text.bold, Some(true));
+        let CompiledElement::Box { children, .. } = &elements[1].element else {
+            panic!("not a box")
+        };
+        let CompiledElement::Row { element: row, .. } = &children[0].element else {
+            panic!("not a row")
+        };
+        // Its own `bold` wins
+        assert_eq!((row.bold, row.invert), (Some(false), Some(true)));
+    }
+
+    #[test]
+    fn test_newer_versions_are_rejected() {
+        let error = load_template(&template(r#", "schema_version": 3"#)).unwrap_err();
+        assert_eq!(error.path, "schema_version");
+        assert!(error.message.starts_with("schema version 3 is newer"));
+    }
+}
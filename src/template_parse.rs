@@ -0,0 +1,543 @@
+// src/template_parse.rs
+// Template JSON parsing with errors that point at the offending value
+
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::template_migrate::TEMPLATE_SCHEMA_VERSION;
+use crate::template_render::{
+    BarChartElement, BarcodeElement, BoxElement, BreakdownElement, CouponElement, DividerElement,
+    GridElement, LeaderboardElement, LogoElement, QRElement, ReceiptTemplate, RepeatElement,
+    RowElement, SpaceElement, SwitchElement, TableElement, TextElement,
+};
+
+/// A template (or request body) that failed to parse, with the location of
+/// the problem so an editor can jump to it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TemplateParseError {
+    /// Path to the offending value, e.g. `layout.sections[3].elements[1].font_size`.
+    /// Empty when the problem is with the document as a whole.
+    pub path: String,
+    /// 1-based line of the offending value
+    pub line: usize,
+    /// 1-based column (in characters) of the offending value
+    pub column: usize,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl std::fmt::Display for TemplateParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)?;
+        } else {
+            write!(f, "{}: {}", self.path, self.message)?;
+        }
+        write!(f, " (line {}, column {})", self.line, self.column)?;
+        if let Some(hint) = &self.hint {
+            write!(f, ". {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TemplateParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Parse template JSON, or any body that embeds a template, reporting the
+/// path, line and column of the first problem.
+pub fn parse_template_json<T: DeserializeOwned>(json: &str) -> Result<T, TemplateParseError> {
+    let mut de = serde_json::Deserializer::from_str(json);
+    let error = match serde_path_to_error::deserialize::<_, T>(&mut de) {
+        Ok(value) => match de.end() {
+            Ok(()) => return Ok(value),
+            Err(e) => return Err(syntax_error(e, Vec::new())),
+        },
+        Err(e) => e,
+    };
+
+    let path = segments(error.path());
+    let inner = error.into_inner();
+    if !inner.is_data() {
+        return Err(syntax_error(inner, path));
+    }
+
+    let (path, message) = refine(json, path, bare_message(&inner));
+    let (line, column) = locate(json, &path)
+        .map(|offset| line_column(json, offset))
+        .unwrap_or((inner.line(), inner.column()));
+
+    Err(TemplateParseError {
+        hint: data_hint(&message, &path),
+        path: format_path(&path),
+        line,
+        column,
+        message,
+    })
+}
+
+/// JSON Schema of the template format, for editors to autocomplete and check
+/// templates against. Its description names the schema version and the
+/// version that serves it.
+pub fn template_schema() -> RootSchema {
+    let mut schema = schema_for!(ReceiptTemplate);
+    schema.schema.metadata().description = Some(format!(
+        "Receipt template, schema version {}, understood by nexora-printer-manager {}",
+        TEMPLATE_SCHEMA_VERSION,
+        env!("CARGO_PKG_VERSION")
+    ));
+    schema
+}
+
+fn syntax_error(error: serde_json::Error, path: Vec<PathSegment>) -> TemplateParseError {
+    let message = bare_message(&error);
+    TemplateParseError {
+        path: format_path(&path),
+        line: error.line(),
+        column: error.column(),
+        hint: syntax_hint(&message),
+        message,
+    }
+}
+
+fn segments(path: &serde_path_to_error::Path) -> Vec<PathSegment> {
+    path.iter()
+        .filter_map(|segment| match segment {
+            serde_path_to_error::Segment::Map { key } => Some(PathSegment::Key(key.clone())),
+            serde_path_to_error::Segment::Seq { index } => Some(PathSegment::Index(*index)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn format_path(path: &[PathSegment]) -> String {
+    let mut out = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Key(key) => {
+                if !out.is_empty() {
+                    out.push('.');
+                }
+                out.push_str(key);
+            }
+            PathSegment::Index(index) => out.push_str(&format!("[{}]", index)),
+        }
+    }
+    out
+}
+
+/// serde_json's message without its " at line X column Y" suffix
+fn bare_message(error: &serde_json::Error) -> String {
+    let message = error.to_string();
+    let suffix = format!(" at line {} column {}", error.line(), error.column());
+    match message.strip_suffix(&suffix) {
+        Some(bare) => bare.to_string(),
+        None => message,
+    }
+}
+
+// ==================== Element Paths ====================
+
+/// Elements are an internally tagged enum, which serde buffers before picking
+/// the variant, so errors inside an element lose their path and position.
+/// Re-run the element's own struct on its JSON to find the field at fault,
+/// descending through boxes and repeats.
+fn refine(json: &str, mut path: Vec<PathSegment>, mut message: String) -> (Vec<PathSegment>, String) {
+    let Ok(root) = serde_json::from_str::<Value>(json) else {
+        return (path, message);
+    };
+
+    while let Some(node) = element_at(&root, &path) {
+        let Some(element_type) = node.get("type").and_then(Value::as_str) else {
+            break;
+        };
+        if message.starts_with("unknown variant") {
+            path.push(PathSegment::Key("type".to_string()));
+            break;
+        }
+        let Some((inner_path, inner_message)) = probe_element(element_type, node) else {
+            break;
+        };
+        message = inner_message;
+        if inner_path.is_empty() {
+            // e.g. a missing field: the element itself is at fault
+            break;
+        }
+        path.extend(inner_path);
+    }
+
+    (path, message)
+}
+
+/// The JSON object at `path`, if the path ends at an entry of an
+/// `elements` or `else_elements` array or of a switch's cases
+fn element_at<'a>(root: &'a Value, path: &[PathSegment]) -> Option<&'a Value> {
+    match path {
+        [.., PathSegment::Key(key), PathSegment::Index(_)]
+            if key == "elements" || key == "else_elements" || key == "default" => {}
+        // A switch's case, keyed by its value
+        [.., PathSegment::Key(cases), PathSegment::Key(_), PathSegment::Index(_)]
+            if cases == "cases" => {}
+        _ => return None,
+    }
+    let mut node = root;
+    for segment in path {
+        node = match segment {
+            PathSegment::Key(key) => node.get(key)?,
+            PathSegment::Index(index) => node.get(index)?,
+        };
+    }
+    node.is_object().then_some(node)
+}
+
+fn probe_element(element_type: &str, node: &Value) -> Option<(Vec<PathSegment>, String)> {
+    match element_type {
+        "text" => probe::<TextElement>(node),
+        "logo" => probe::<LogoElement>(node),
+        "divider" => probe::<DividerElement>(node),
+        "row" => probe::<RowElement>(node),
+        "qr" => probe::<QRElement>(node),
+        "barcode" => probe::<BarcodeElement>(node),
+        "table" => probe::<TableElement>(node),
+        "space" => probe::<SpaceElement>(node),
+        "box" => probe::<BoxElement>(node),
+        "grid" => probe::<GridElement>(node),
+        "bar_chart" => probe::<BarChartElement>(node),
+        "leaderboard" => probe::<LeaderboardElement>(node),
+        "breakdown" => probe::<BreakdownElement>(node),
+        "coupon" => probe::<CouponElement>(node),
+        "repeat" => probe::<RepeatElement>(node),
+        "switch" => probe::<SwitchElement>(node),
+        _ => None,
+    }
+}
+
+fn probe<T: DeserializeOwned>(node: &Value) -> Option<(Vec<PathSegment>, String)> {
+    serde_path_to_error::deserialize::<_, T>(node)
+        .err()
+        .map(|e| (segments(e.path()), e.into_inner().to_string()))
+}
+
+// ==================== Locating ====================
+
+/// Byte offset of the value at `path` in the JSON text
+fn locate(json: &str, path: &[PathSegment]) -> Option<usize> {
+    let mut scanner = Scanner {
+        json,
+        bytes: json.as_bytes(),
+        pos: 0,
+    };
+    scanner.find(path)
+}
+
+fn line_column(json: &str, offset: usize) -> (usize, usize) {
+    let before = &json[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Just enough of a JSON walker to find a value by path in text already
+/// known to be well formed
+struct Scanner<'a> {
+    json: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Scanner<'_> {
+    fn find(&mut self, path: &[PathSegment]) -> Option<usize> {
+        self.skip_whitespace();
+        let Some((segment, rest)) = path.split_first() else {
+            return Some(self.pos);
+        };
+
+        match (self.bytes.get(self.pos)?, segment) {
+            (b'{', PathSegment::Key(wanted)) => {
+                self.pos += 1;
+                loop {
+                    self.skip_whitespace();
+                    if self.bytes.get(self.pos)? == &b'}' {
+                        return None;
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(b':')?;
+                    if &key == wanted {
+                        return self.find(rest);
+                    }
+                    self.skip_value()?;
+                    self.skip_separator();
+                }
+            }
+            (b'[', PathSegment::Index(wanted)) => {
+                self.pos += 1;
+                for index in 0.. {
+                    self.skip_whitespace();
+                    if self.bytes.get(self.pos)? == &b']' {
+                        return None;
+                    }
+                    if index == *wanted {
+                        return self.find(rest);
+                    }
+                    self.skip_value()?;
+                    self.skip_separator();
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_separator(&mut self) {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b',') {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        (self.bytes.get(self.pos)? == &byte).then(|| self.pos += 1)
+    }
+
+    /// Read a string token, unescaped
+    fn string(&mut self) -> Option<String> {
+        let start = self.pos;
+        self.skip_string()?;
+        serde_json::from_str(&self.json[start..self.pos]).ok()
+    }
+
+    fn skip_string(&mut self) -> Option<()> {
+        self.expect(b'"')?;
+        loop {
+            match self.bytes.get(self.pos)? {
+                b'\\' => self.pos += 2,
+                b'"' => {
+                    self.pos += 1;
+                    return Some(());
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    fn skip_value(&mut self) -> Option<()> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos)? {
+            b'"' => self.skip_string(),
+            open @ (b'{' | b'[') => {
+                let close = if *open == b'{' { b'}' } else { b']' };
+                self.pos += 1;
+                loop {
+                    self.skip_whitespace();
+                    if self.bytes.get(self.pos)? == &close {
+                        self.pos += 1;
+                        return Some(());
+                    }
+                    if close == b'}' {
+                        self.skip_string()?;
+                        self.skip_whitespace();
+                        self.expect(b':')?;
+                    }
+                    self.skip_value()?;
+                    self.skip_separator();
+                }
+            }
+            _ => {
+                while self
+                    .bytes
+                    .get(self.pos)
+                    .is_some_and(|b| !matches!(b, b',' | b'}' | b']') && !b.is_ascii_whitespace())
+                {
+                    self.pos += 1;
+                }
+                Some(())
+            }
+        }
+    }
+}
+
+// ==================== Hints ====================
+
+fn data_hint(message: &str, path: &[PathSegment]) -> Option<String> {
+    let field = path.iter().rev().find_map(|s| match s {
+        PathSegment::Key(key) => Some(key.as_str()),
+        PathSegment::Index(_) => None,
+    });
+
+    if let Some(rest) = message.strip_prefix("unknown variant ") {
+        let (name, expected) = rest.split_once(", expected one of ")?;
+        let what = if field == Some("type") {
+            "an element type"
+        } else {
+            "a valid value"
+        };
+        return Some(format!(
+            "{} is not {}; use one of {}",
+            name, what, expected
+        ));
+    }
+
+    if let Some(name) = message.strip_prefix("missing field ") {
+        return Some(format!("Add the required {} field", name));
+    }
+
+    let field = format!("`{}`", field.unwrap_or("value"));
+
+    if let Some(rest) = message.strip_prefix("invalid type: ") {
+        let (found, expected) = rest.split_once(", expected ")?;
+        let expected_json = json_type(expected)?;
+        let found_json = found.split([' ', '`', '"']).next().unwrap_or(found);
+        let mut hint = format!("{} must be {}, not {}", field, expected_json, found_json);
+        if found_json == "string" && expected_json != "a string" {
+            hint.push_str(" (remove the quotes)");
+        }
+        return Some(hint);
+    }
+
+    if let Some(rest) = message.strip_prefix("invalid value: ") {
+        let (_, expected) = rest.split_once(", expected ")?;
+        return Some(format!("{} must be {}", field, json_type(expected)?));
+    }
+
+    None
+}
+
+/// How a serde "expected ..." type reads to someone writing JSON
+fn json_type(expected: &str) -> Option<&'static str> {
+    let json = match expected {
+        "u8" => "a whole number from 0 to 255",
+        "u16" | "u32" | "u64" | "usize" => "a whole number",
+        "i8" | "i16" | "i32" | "i64" | "isize" => "a whole number",
+        "f32" | "f64" => "a number",
+        "a string" => "a string",
+        "a boolean" => "true or false",
+        "a sequence" => "an array",
+        _ if expected.starts_with("struct ")
+            || expected.starts_with("internally tagged enum")
+            || expected == "a map" =>
+        {
+            "an object"
+        }
+        _ => return None,
+    };
+    Some(json)
+}
+
+fn syntax_hint(message: &str) -> Option<String> {
+    let hint = if message.starts_with("trailing comma") {
+        "Remove the comma after the last entry"
+    } else if message.starts_with("expected `,` or `}`") || message.starts_with("expected `,` or `]`") {
+        "A comma is probably missing before this point"
+    } else if message.starts_with("key must be a string") {
+        "Property names must be in double quotes"
+    } else if message.starts_with("EOF while parsing") {
+        "The JSON ends early; check for an unclosed brace, bracket or string"
+    } else if message.starts_with("trailing characters") {
+        "There is extra text after the end of the template"
+    } else {
+        return None;
+    };
+    Some(hint.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(json: &str) -> TemplateParseError {
+        parse_template_json::<ReceiptTemplate>(json).unwrap_err()
+    }
+
+    #[test]
+    fn test_wrong_type_inside_element() {
+        let error = parse_error(include_str!("../testdata/broken/font_size_string.json"));
+        assert_eq!(error.path, "layout.sections[1].elements[1].font_size");
+        assert_eq!((error.line, error.column), (16, 73));
+        assert_eq!(error.message, "invalid type: string \"2\", expected u8");
+        assert_eq!(
+            error.hint.as_deref(),
+            Some("`font_size` must be a whole number from 0 to 255, not string (remove the quotes)")
+        );
+    }
+
+    #[test]
+    fn test_unknown_element_type() {
+        let error = parse_error(include_str!("../testdata/broken/unknown_element_type.json"));
+        assert_eq!(error.path, "layout.sections[0].elements[2].type");
+        assert_eq!((error.line, error.column), (12, 21));
+        let hint = error.hint.unwrap();
+        assert!(hint.starts_with("`txt` is not an element type; use one of `text`, `logo`"), "{}", hint);
+    }
+
+    #[test]
+    fn test_error_inside_nested_box() {
+        let error = parse_error(include_str!("../testdata/broken/box_missing_content.json"));
+        assert_eq!(error.path, "layout.sections[0].elements[0].elements[1]");
+        assert_eq!(error.message, "missing field `content`");
+        assert_eq!(error.hint.as_deref(), Some("Add the required `content` field"));
+
+        let error = parse_error(include_str!("../testdata/broken/table_width_negative.json"));
+        assert_eq!(error.path, "layout.sections[0].elements[0].columns[1].width");
+        assert_eq!((error.line, error.column), (15, 44));
+        assert_eq!(error.hint.as_deref(), Some("`width` must be a whole number"));
+    }
+
+    #[test]
+    fn test_syntax_errors_keep_serde_position() {
+        let error = parse_error(include_str!("../testdata/broken/trailing_comma.json"));
+        assert_eq!((error.line, error.column), (8, 5));
+        assert_eq!(error.hint.as_deref(), Some("Remove the comma after the last entry"));
+    }
+
+    #[test]
+    fn test_locates_fields_outside_elements() {
+        let error = parse_error(r#"{"id": "t", "name": "T", "version": 2, "layout": {"sections": []}}"#);
+        assert_eq!(error.path, "version");
+        assert_eq!((error.line, error.column), (1, 37));
+        assert_eq!(error.hint.as_deref(), Some("`version` must be a string, not integer"));
+    }
+
+    #[test]
+    fn test_schema_describes_elements() {
+        let schema = serde_json::to_value(template_schema()).unwrap();
+        assert_eq!(schema["title"], "ReceiptTemplate");
+        assert!(schema["description"]
+            .as_str()
+            .unwrap()
+            .ends_with(env!("CARGO_PKG_VERSION")));
+        let required = serde_json::json!(["id", "layout", "name", "version"]);
+        assert_eq!(schema["required"], required);
+
+        // Each element type is one of `Element`'s variants, named by its `type`
+        let element = &schema["definitions"]["Element"];
+        let variants = element["oneOf"].as_array().unwrap();
+        let types: Vec<&str> = variants
+            .iter()
+            .filter_map(|variant| variant["properties"]["type"]["enum"][0].as_str())
+            .collect();
+        assert!(types.contains(&"text"), "{:?}", types);
+        assert!(types.contains(&"repeat"), "{:?}", types);
+        let text = &variants[types.iter().position(|t| *t == "text").unwrap()];
+        assert_eq!(text["properties"]["font_size"]["format"], "uint8");
+    }
+}
@@ -1,19 +1,45 @@
-use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use unicode_width::UnicodeWidthChar;
+
+use crate::barcode::{HriPosition, Symbology};
+use crate::bidi::BidiSink;
+use crate::image_print::{
+    decode_image, decoded_image_to_escpos, read_image_source_cached, Dithering, ImageOptions,
+};
+use crate::label_template::LabelSpec;
+use crate::template_compile::{
+    CompiledElement, CompiledNode, CompiledSection, CompiledTemplate, Condition, Segment,
+    TextPattern,
+};
+use crate::template_expr::{decimal_places, Scope};
+use crate::template_filter::format_currency;
+use crate::template_gift::GiftReceipt;
+use crate::template_markup::MarkupSpan;
+use crate::template_migrate::SchemaVersion;
+use crate::template_parse::{parse_template_json, TemplateParseError};
+use crate::text_preview::render_text;
 
 // Note: This module uses a PrintCommand abstraction for rendering instead of
 // directly using escpos types. For direct printer integration, see main.rs.
 
 // ==================== Template Structure ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ReceiptTemplate {
     pub id: String,
     pub name: String,
     #[serde(default)]
     pub description: Option<String>,
     pub version: String,
+    /// Template format the template is written in, 1 when unset; older
+    /// formats are upgraded when the template is compiled
+    #[serde(default)]
+    pub schema_version: Option<SchemaVersion>,
     pub paper_width: Option<u32>,
     #[serde(default)]
     pub supports_logo: Option<bool>,
@@ -24,9 +50,62 @@ pub struct ReceiptTemplate {
     pub layout: TemplateLayout,
     #[serde(default)]
     pub variables: Option<HashMap<String, VariableDefinition>>,
+    /// Fixed values read by name like receipt fields, such as
+    /// `{"store_code": "NX-01", "vat_rate": 21}`
+    #[serde(default)]
+    pub constants: Option<HashMap<String, serde_json::Value>>,
+    /// Expressions worked out once per print and read by name, such as
+    /// `{"total_with_tip": "total + tip"}`. They can use constants and
+    /// each other.
+    #[serde(default)]
+    pub computed: Option<BTreeMap<String, String>>,
+    /// Sound the buzzer once the receipt is cut
+    #[serde(default)]
+    pub buzzer: Option<Buzzer>,
+    /// Where on the paper the receipt prints, overriding the printer's
+    #[serde(default)]
+    pub print_area: Option<PrintArea>,
+    /// Labels per language, such as `{"fr": {"total": "TOTAL TTC"}}`,
+    /// printed with `{{t.total}}`
+    #[serde(default)]
+    pub translations: Option<HashMap<String, HashMap<String, String>>>,
+    /// Language whose labels are used when the data names none, or for
+    /// labels its language lacks
+    #[serde(default)]
+    pub default_language: Option<String>,
+    /// A second language printed with the data's own, its labels and item
+    /// names beside or under the first
+    #[serde(default)]
+    pub bilingual: Option<Bilingual>,
+    /// Which way the receipt's text reads; elements can set their own
+    #[serde(default)]
+    pub direction: Option<Direction>,
+    /// Text settings by name, such as `{"heading": {"bold": true,
+    /// "font_size": 2, "align": "center"}}`, for text and row elements'
+    /// `style`
+    #[serde(default)]
+    pub styles: Option<HashMap<String, TextStyle>>,
+    /// What the template prints, a receipt unless set
+    #[serde(default)]
+    pub kind: Option<TemplateKind>,
+    /// How a kitchen template's order is split into tickets, one for the
+    /// whole order unless set
+    #[serde(default)]
+    pub ticket_per: Option<TicketSplit>,
+    /// Size, media and elements of a label template
+    #[serde(default)]
+    pub label: Option<LabelSpec>,
+    /// Print once per label, such as `["CUSTOMER COPY", "MERCHANT COPY"]`,
+    /// each copy cut off and showing its label as `{{copy_label}}`
+    #[serde(default)]
+    pub copies: Option<Vec<String>>,
+    /// Title and exchange barcode of the template's gift receipts, and
+    /// whether every print is one
+    #[serde(default)]
+    pub gift_receipt: Option<GiftReceipt>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct VariableDefinition {
     #[serde(rename = "type")]
     pub var_type: String,
@@ -36,12 +115,12 @@ pub struct VariableDefinition {
     pub default: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TemplateLayout {
     pub sections: Vec<Section>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct Section {
     #[serde(rename = "type")]
     pub section_type: String,
@@ -50,11 +129,20 @@ pub struct Section {
     #[serde(default)]
     pub condition: Option<String>,
     pub elements: Vec<Element>,
+    /// Printed in place of `elements` when `condition` is false
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
     #[serde(default)]
     pub spacing: Option<Spacing>,
+    /// Narrowest paper the section prints on; it is left out on narrower paper
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    /// Sound the buzzer when the section prints, e.g. for a rush order
+    #[serde(default)]
+    pub buzzer: Option<Buzzer>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Spacing {
     #[serde(default)]
     pub before: Option<u32>,
@@ -62,15 +150,62 @@ pub struct Spacing {
     pub after: Option<u32>,
 }
 
+/// Beeps from the printer's buzzer, for kitchen printers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Buzzer {
+    /// Number of beeps, 1 to 9 (default 1)
+    #[serde(default)]
+    pub times: Option<u8>,
+    /// Length of each beep, 50 to 450 ms (default 200)
+    #[serde(default)]
+    pub duration_ms: Option<u16>,
+}
+
+impl Buzzer {
+    pub fn command(&self) -> PrintCommand {
+        PrintCommand::Buzzer {
+            times: self.times.unwrap_or(1),
+            duration_ms: self.duration_ms.unwrap_or(200),
+        }
+    }
+}
+
+/// The part of the paper a receipt prints on, in dots, e.g. a 58mm
+/// template centred on 80mm paper
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PrintArea {
+    /// From the left edge of the paper; unset centres the area
+    #[serde(default)]
+    pub left_margin: Option<u16>,
+    /// Unset is the template's paper width
+    #[serde(default)]
+    pub width: Option<u16>,
+}
+
+impl PrintArea {
+    /// Left margin and width for a template `template_dots` wide on paper
+    /// `paper_dots` wide
+    pub fn resolve(&self, template_dots: u32, paper_dots: u32) -> (u16, u16) {
+        let width = self.width.map_or(template_dots, u32::from);
+        let left = self
+            .left_margin
+            .map_or(paper_dots.saturating_sub(width) / 2, u32::from);
+        let clamp = |dots: u32| u16::try_from(dots).unwrap_or(u16::MAX);
+        (clamp(left), clamp(width))
+    }
+}
+
 // ==================== Element Types ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum Element {
     #[serde(rename = "text")]
     Text(TextElement),
     #[serde(rename = "logo")]
     Logo(LogoElement),
+    #[serde(rename = "image")]
+    Image(ImageElement),
     #[serde(rename = "divider")]
     Divider(DividerElement),
     #[serde(rename = "row")]
@@ -91,11 +226,183 @@ pub enum Element {
     BarChart(BarChartElement),
     #[serde(rename = "leaderboard")]
     Leaderboard(LeaderboardElement),
+    #[serde(rename = "breakdown")]
+    Breakdown(BreakdownElement),
+    #[serde(rename = "coupon")]
+    Coupon(CouponElement),
+    #[serde(rename = "repeat")]
+    Repeat(RepeatElement),
+    #[serde(rename = "switch")]
+    Switch(SwitchElement),
+    #[serde(rename = "kitchen_items")]
+    KitchenItems(KitchenItemsElement),
+    #[serde(rename = "tax_summary")]
+    TaxSummary(TaxSummaryElement),
+    #[serde(rename = "tip_suggestions")]
+    TipSuggestions(TipSuggestionsElement),
+}
+
+impl Element {
+    /// The element's render condition, if it has one
+    pub fn condition(&self) -> Option<&str> {
+        match self {
+            Element::Text(e) => e.condition.as_deref(),
+            Element::Logo(e) => e.condition.as_deref(),
+            Element::Image(e) => e.condition.as_deref(),
+            Element::Divider(e) => e.condition.as_deref(),
+            Element::Row(e) => e.condition.as_deref(),
+            Element::QR(e) => e.condition.as_deref(),
+            Element::Barcode(e) => e.condition.as_deref(),
+            Element::Table(e) => e.condition.as_deref(),
+            Element::Space(e) => e.condition.as_deref(),
+            Element::Box(e) => e.condition.as_deref(),
+            Element::Grid(e) => e.condition.as_deref(),
+            Element::BarChart(e) => e.condition.as_deref(),
+            Element::Leaderboard(e) => e.condition.as_deref(),
+            Element::Breakdown(e) => e.condition.as_deref(),
+            Element::Coupon(e) => e.condition.as_deref(),
+            Element::Repeat(e) => e.condition.as_deref(),
+            Element::Switch(e) => e.condition.as_deref(),
+            Element::KitchenItems(e) => e.condition.as_deref(),
+            Element::TaxSummary(e) => e.condition.as_deref(),
+            Element::TipSuggestions(e) => e.condition.as_deref(),
+        }
+    }
+
+    /// What prints instead when the condition is false. These elements can
+    /// have conditions and `else_elements` of their own, for an else-if
+    /// chain.
+    pub fn else_elements(&self) -> &[Element] {
+        match self {
+            Element::Text(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Logo(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Image(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Divider(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Row(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::QR(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Barcode(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Table(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Space(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Box(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Grid(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::BarChart(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Leaderboard(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Breakdown(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Coupon(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Repeat(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::Switch(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::KitchenItems(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::TaxSummary(e) => e.else_elements.as_deref().unwrap_or_default(),
+            Element::TipSuggestions(e) => e.else_elements.as_deref().unwrap_or_default(),
+        }
+    }
+
+    /// Narrowest paper the element prints on, if it has a minimum
+    pub fn min_width(&self) -> Option<&MinWidth> {
+        match self {
+            Element::Text(e) => e.min_width.as_ref(),
+            Element::Logo(e) => e.min_width.as_ref(),
+            Element::Image(e) => e.min_width.as_ref(),
+            Element::Divider(e) => e.min_width.as_ref(),
+            Element::Row(e) => e.min_width.as_ref(),
+            Element::QR(e) => e.min_width.as_ref(),
+            Element::Barcode(e) => e.min_width.as_ref(),
+            Element::Table(e) => e.min_width.as_ref(),
+            Element::Space(e) => e.min_width.as_ref(),
+            Element::Box(e) => e.min_width.as_ref(),
+            Element::Grid(e) => e.min_width.as_ref(),
+            Element::BarChart(e) => e.min_width.as_ref(),
+            Element::Leaderboard(e) => e.min_width.as_ref(),
+            Element::Breakdown(e) => e.min_width.as_ref(),
+            Element::Coupon(e) => e.min_width.as_ref(),
+            Element::Repeat(e) => e.min_width.as_ref(),
+            Element::Switch(e) => e.min_width.as_ref(),
+            Element::KitchenItems(e) => e.min_width.as_ref(),
+            Element::TaxSummary(e) => e.min_width.as_ref(),
+            Element::TipSuggestions(e) => e.min_width.as_ref(),
+        }
+    }
+
+    /// Which way the element's text reads, if it sets it
+    pub fn direction(&self) -> Option<Direction> {
+        match self {
+            Element::Text(e) => e.direction,
+            Element::Row(e) => e.direction,
+            Element::Table(e) => e.direction,
+            _ => None,
+        }
+    }
+
+    /// What prints instead when the paper is narrower than `min_width`
+    pub fn fallback(&self) -> Option<&Element> {
+        match self {
+            Element::Text(e) => e.fallback.as_deref(),
+            Element::Logo(e) => e.fallback.as_deref(),
+            Element::Image(e) => e.fallback.as_deref(),
+            Element::Divider(e) => e.fallback.as_deref(),
+            Element::Row(e) => e.fallback.as_deref(),
+            Element::QR(e) => e.fallback.as_deref(),
+            Element::Barcode(e) => e.fallback.as_deref(),
+            Element::Table(e) => e.fallback.as_deref(),
+            Element::Space(e) => e.fallback.as_deref(),
+            Element::Box(e) => e.fallback.as_deref(),
+            Element::Grid(e) => e.fallback.as_deref(),
+            Element::BarChart(e) => e.fallback.as_deref(),
+            Element::Leaderboard(e) => e.fallback.as_deref(),
+            Element::Breakdown(e) => e.fallback.as_deref(),
+            Element::Coupon(e) => e.fallback.as_deref(),
+            Element::Repeat(e) => e.fallback.as_deref(),
+            Element::Switch(e) => e.fallback.as_deref(),
+            Element::KitchenItems(e) => e.fallback.as_deref(),
+            Element::TaxSummary(e) => e.fallback.as_deref(),
+            Element::TipSuggestions(e) => e.fallback.as_deref(),
+        }
+    }
+}
+
+// ==================== Width Gating ====================
+
+/// Paper sizes `min_width` accepts by name, with the characters per line
+/// each prints
+pub const PAPER_SIZES: [(&str, u32); 2] = [("58mm", 32), ("80mm", 48)];
+
+/// Narrowest paper an element or section prints on: a number of characters
+/// per line, or a paper size from [`PAPER_SIZES`] such as `"80mm"`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum MinWidth {
+    Columns(u32),
+    Paper(String),
+}
+
+impl MinWidth {
+    /// Characters per line this stands for; `None` for an unknown paper size
+    pub fn columns(&self) -> Option<u32> {
+        match self {
+            MinWidth::Columns(columns) => Some(*columns),
+            MinWidth::Paper(size) => {
+                let size = size.trim().to_lowercase();
+                PAPER_SIZES
+                    .iter()
+                    .find(|(name, _)| *name == size)
+                    .map(|(_, columns)| *columns)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for MinWidth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MinWidth::Columns(columns) => write!(f, "{} columns", columns),
+            MinWidth::Paper(size) => write!(f, "{} paper", size),
+        }
+    }
 }
 
 // ==================== Text Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct TextElement {
     pub content: String,
     #[serde(default)]
@@ -104,6 +411,8 @@ pub struct TextElement {
     pub font_size: Option<u8>,
     #[serde(default)]
     pub font_width: Option<u8>,
+    /// CSS weight; from schema version 2, `bold`, `bolder` and `600` to
+    /// `900` print bold unless `bold` is set
     #[serde(default)]
     pub font_weight: Option<String>,
     #[serde(default)]
@@ -118,15 +427,203 @@ pub struct TextElement {
     pub invert: Option<bool>,
     #[serde(default)]
     pub letter_spacing: Option<u8>,
+    /// CSS background; from schema version 2, black prints inverted unless
+    /// `invert` is set
     #[serde(default)]
     pub background: Option<String>,
+    /// The printer font; B fits a third more columns
+    #[serde(default)]
+    pub font: Option<Font>,
+    /// Strike each dot twice, darker than bold on faded paper
+    #[serde(default)]
+    pub double_strike: Option<bool>,
+    /// Style parts of the content with `**bold**`, `__underline__`,
+    /// `{big}...{/big}` and `{invert}...{/invert}`
+    #[serde(default)]
+    pub markup: Option<bool>,
+    /// Name of one of the template's `styles`
+    #[serde(default)]
+    pub style: Option<String>,
+    /// Overrides the template's direction
+    #[serde(default)]
+    pub direction: Option<Direction>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
+
+/// Text settings a template names in `styles` and text and row elements
+/// use with `"style": "<name>"`. An element's own settings win; rows take
+/// only `bold`, `invert`, `font_size` and `letter_spacing`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct TextStyle {
+    #[serde(default)]
+    pub align: Option<String>,
+    #[serde(default)]
+    pub font_size: Option<u8>,
+    #[serde(default)]
+    pub font_width: Option<u8>,
+    #[serde(default)]
+    pub bold: Option<bool>,
+    #[serde(default)]
+    pub underline: Option<bool>,
+    #[serde(default)]
+    pub invert: Option<bool>,
+    #[serde(default)]
+    pub letter_spacing: Option<u8>,
+    #[serde(default)]
+    pub font: Option<Font>,
+    #[serde(default)]
+    pub double_strike: Option<bool>,
+}
+
+impl TextStyle {
+    /// Fill in the settings `element` leaves unset
+    pub(crate) fn style_text(&self, element: &mut TextElement) {
+        element.align = element.align.take().or_else(|| self.align.clone());
+        element.font_size = element.font_size.or(self.font_size);
+        element.font_width = element.font_width.or(self.font_width);
+        element.bold = element.bold.or(self.bold);
+        element.underline = element.underline.or(self.underline);
+        element.invert = element.invert.or(self.invert);
+        element.letter_spacing = element.letter_spacing.or(self.letter_spacing);
+        element.font = element.font.or(self.font);
+        element.double_strike = element.double_strike.or(self.double_strike);
+    }
+
+    /// Fill in the settings `element` leaves unset
+    pub(crate) fn style_row(&self, element: &mut RowElement) {
+        element.bold = element.bold.or(self.bold);
+        element.invert = element.invert.or(self.invert);
+        element.font_size = element.font_size.or(self.font_size);
+        element.letter_spacing = element.letter_spacing.or(self.letter_spacing);
+    }
+}
+
+/// One of the printer's built-in fonts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Font {
+    /// 12 dots wide, 48 columns on 80mm paper
+    #[default]
+    #[serde(alias = "a")]
+    A,
+    /// 9 dots wide, 64 columns on 80mm paper
+    #[serde(alias = "b")]
+    B,
+    /// A third font on some models, 9 dots wide on most
+    #[serde(alias = "c")]
+    C,
+}
+
+/// The second language of a bilingual receipt and how it's laid out
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Bilingual {
+    /// Language of the second labels, read from `translations` like the
+    /// first. Receipt data's `secondary_language` overrides it.
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub layout: BilingualLayout,
+    /// Between the languages side by side, " / " by default
+    #[serde(default)]
+    pub separator: Option<String>,
+}
+
+impl Bilingual {
+    /// `first` with `second` beside or under it; `first` alone if the
+    /// second is empty or the same
+    pub(crate) fn join(&self, first: &str, second: &str) -> String {
+        if second.is_empty() || second == first {
+            return first.to_string();
+        }
+        match self.layout {
+            BilingualLayout::Side => {
+                let separator = self.separator.as_deref().unwrap_or(" / ");
+                format!("{}{}{}", first, separator, second)
+            }
+            BilingualLayout::Stacked => format!("{}\n{}", first, second),
+        }
+    }
+}
+
+/// Where a bilingual receipt prints the second language
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BilingualLayout {
+    /// After the first on the same line: "Total / Total TTC"
+    #[default]
+    Side,
+    /// On the line below the first
+    Stacked,
+}
+
+/// Which way text reads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Lines start at the left; Arabic and Hebrew in them still read right
+    /// to left
+    #[default]
+    Ltr,
+    /// Lines start at the right and rows and tables are mirrored, so
+    /// `left` and `right` mean start and end
+    Rtl,
+}
+
+/// What kitchen items are grouped by, each group under a header
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemGroup {
+    #[default]
+    Course,
+    Seat,
+}
+
+/// What a template prints
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateKind {
+    #[default]
+    Receipt,
+    /// Tickets for the kitchen or bar: no prices, and the order can be
+    /// split with `ticket_per`
+    Kitchen,
+    /// A label of fixed size for a label printer, laid out by `label`
+    Label,
+}
+
+/// The tickets a kitchen template prints for an order, each cut off
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TicketSplit {
+    #[default]
+    Order,
+    /// One ticket per item line
+    Item,
+    /// One ticket per course, with `course` set to it
+    Course,
+    /// One ticket per seat, with `seat` set to it
+    Seat,
+}
+
+impl Font {
+    /// Width of a character in dots
+    pub fn char_width(self) -> u32 {
+        match self {
+            Font::A => 12,
+            Font::B | Font::C => 9,
+        }
+    }
 }
 
 // ==================== Logo Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct LogoElement {
     #[serde(default)]
     pub source: Option<String>,
@@ -138,13 +635,97 @@ pub struct LogoElement {
     pub max_width: Option<u32>,
     #[serde(default)]
     pub max_height: Option<u32>,
+    /// "floyd_steinberg" (default), "atkinson" or "threshold"
+    #[serde(default)]
+    pub dither: Option<Dithering>,
+    /// Grey level 0-255 below which a dot prints; 128 if unset
+    #[serde(default)]
+    pub threshold: Option<u8>,
+    /// -100 to 100
+    #[serde(default)]
+    pub brightness: Option<i32>,
+    /// -100 to 100
+    #[serde(default)]
+    pub contrast: Option<i32>,
+    /// Print the logo stored in the printer under this slot (see
+    /// `/printers/{id}/nv-logo`) instead of sending the image
+    #[serde(default)]
+    pub nv_slot: Option<u8>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
+
+impl LogoElement {
+    /// How the image is turned into dots
+    pub fn image_options(&self) -> ImageOptions {
+        let defaults = ImageOptions::default();
+        ImageOptions {
+            dithering: self.dither.unwrap_or_default(),
+            threshold: self.threshold.unwrap_or(defaults.threshold),
+            brightness: self.brightness.unwrap_or(defaults.brightness),
+            contrast: self.contrast.unwrap_or(defaults.contrast),
+        }
+    }
+}
+
+// ==================== Image Element ====================
+
+/// A picture anywhere in a layout, such as a product photo on a kitchen
+/// ticket or a promotion in the footer
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImageElement {
+    /// An `http(s)://` URL, a file path or base64 data, and may use
+    /// placeholders such as `{{item.photo}}`. Images from URLs are kept for
+    /// an hour.
+    pub source: String,
+    #[serde(default)]
+    pub align: Option<String>,
+    /// In dots; the paper's width if unset
+    #[serde(default)]
+    pub max_width: Option<u32>,
+    /// In dots
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    #[serde(default)]
+    pub dither: Option<Dithering>,
+    #[serde(default)]
+    pub threshold: Option<u8>,
+    #[serde(default)]
+    pub brightness: Option<i32>,
+    #[serde(default)]
+    pub contrast: Option<i32>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
+
+impl ImageElement {
+    /// How the image is turned into dots
+    pub fn image_options(&self) -> ImageOptions {
+        let defaults = ImageOptions::default();
+        ImageOptions {
+            dithering: self.dither.unwrap_or_default(),
+            threshold: self.threshold.unwrap_or(defaults.threshold),
+            brightness: self.brightness.unwrap_or(defaults.brightness),
+            contrast: self.contrast.unwrap_or(defaults.contrast),
+        }
+    }
 }
 
 // ==================== Divider Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DividerElement {
     #[serde(default)]
     pub style: Option<String>,
@@ -162,11 +743,17 @@ pub struct DividerElement {
     pub align: Option<String>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
 }
 
 // ==================== Row Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RowElement {
     #[serde(default)]
     pub left: Option<String>,
@@ -180,6 +767,8 @@ pub struct RowElement {
     pub invert: Option<bool>,
     #[serde(default)]
     pub font_size: Option<u8>,
+    /// CSS weight; from schema version 2, `bold`, `bolder` and `600` to
+    /// `900` print bold unless `bold` is set
     #[serde(default)]
     pub font_weight: Option<String>,
     #[serde(default)]
@@ -188,49 +777,110 @@ pub struct RowElement {
     pub letter_spacing: Option<u8>,
     #[serde(default)]
     pub separator: Option<String>,
+    /// CSS background; from schema version 2, black prints inverted unless
+    /// `invert` is set
     #[serde(default)]
     pub background: Option<String>,
+    /// Name of one of the template's `styles`
+    #[serde(default)]
+    pub style: Option<String>,
+    /// Overrides the template's direction
+    #[serde(default)]
+    pub direction: Option<Direction>,
     #[serde(default)]
     pub condition: Option<String>,
     #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+    #[serde(default)]
     pub elements: Option<Vec<Element>>,
 }
 
 // ==================== QR Code Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct QRElement {
     pub content: String,
+    /// Dots per module, 1 to 16 (default 6)
     #[serde(default)]
     pub size: Option<u8>,
     #[serde(default)]
+    pub error_correction: Option<QrErrorCorrection>,
+    #[serde(default)]
     pub align: Option<String>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
+
+/// How much of a QR code can be damaged and still scan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum QrErrorCorrection {
+    /// 7%
+    L,
+    /// 15%
+    #[default]
+    M,
+    /// 25%
+    Q,
+    /// 30%
+    H,
+}
+
+impl QrErrorCorrection {
+    pub fn ec_level(self) -> qrcode::EcLevel {
+        match self {
+            QrErrorCorrection::L => qrcode::EcLevel::L,
+            QrErrorCorrection::M => qrcode::EcLevel::M,
+            QrErrorCorrection::Q => qrcode::EcLevel::Q,
+            QrErrorCorrection::H => qrcode::EcLevel::H,
+        }
+    }
 }
 
 // ==================== Barcode Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct BarcodeElement {
     pub content: String,
+    /// CODE128 (default), CODE39, CODE93, EAN13, EAN8, UPCA, UPCE, ITF or
+    /// CODABAR
     #[serde(default)]
     pub format: Option<String>,
+    /// In dots (default 100)
     #[serde(default)]
     pub height: Option<u8>,
+    /// Narrowest bar in dots, 2 to 6 (default 3)
     #[serde(default)]
     pub width: Option<u8>,
+    /// `false` is the same as `"hri_position": "none"`
     #[serde(default)]
     pub show_text: Option<bool>,
     #[serde(default)]
+    pub hri_position: Option<HriPosition>,
+    #[serde(default)]
     pub align: Option<String>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
 }
 
 // ==================== Table Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TableElement {
     pub columns: Vec<TableColumn>,
     pub data_source: String,
@@ -246,26 +896,99 @@ pub struct TableElement {
     pub row_details: Option<Vec<RowDetail>>,
     #[serde(default)]
     pub modifiers: Option<ModifierConfig>,
+    /// Numeric field that the `_running_total` row field adds up
+    #[serde(default)]
+    pub running_total: Option<String>,
+    /// Computed rows printed under the table, lined up with its columns
+    #[serde(default)]
+    pub footer_rows: Option<Vec<TableFooterRow>>,
+    /// Divider between the rows and the footer rows, on by default
+    #[serde(default)]
+    pub footer_divider: Option<bool>,
+    /// Field whose value rows are grouped by, each group under a bold
+    /// header with that value
+    #[serde(default)]
+    pub group_by: Option<String>,
+    /// Row printed at the end of each group, worked out from its rows
+    #[serde(default)]
+    pub group_subtotal: Option<TableFooterRow>,
+    /// Overrides the template's direction
+    #[serde(default)]
+    pub direction: Option<Direction>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TableColumn {
     #[serde(default)]
     pub header: Option<String>,
     pub field: String,
+    /// Share of the line, 10 if unset. In a table where any column sets
+    /// `min_width`, `max_width` or `flex`, a width in characters, and
+    /// columns that leave it out are as wide as their widest cell.
     #[serde(default)]
     pub width: Option<u32>,
+    /// Narrowest a column without a `width` gets, in characters
+    #[serde(default)]
+    pub min_width: Option<u32>,
+    /// Widest a column without a `width` gets, in characters
+    #[serde(default)]
+    pub max_width: Option<u32>,
+    /// Give the column the characters the others leave over, up to its
+    /// `max_width`
+    #[serde(default)]
+    pub flex: Option<bool>,
+    /// `left` (default), `right`, `center`, or `decimal` to line numbers up
+    /// on their decimal point with any currency symbol at the left edge
     #[serde(default)]
     pub align: Option<String>,
+    /// Decimal places a `decimal` column leaves room for, 2 by default
+    #[serde(default)]
+    pub decimals: Option<u32>,
     #[serde(default)]
     pub format: Option<String>,
     #[serde(default)]
     pub font_style: Option<String>,
+    /// Continue content too long for the column on the lines below
+    /// instead of cutting it
+    #[serde(default)]
+    pub wrap: Option<bool>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A row under a table such as a subtotal or an item count
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TableFooterRow {
+    /// Printed in the first column, unless that column has a value
+    #[serde(default)]
+    pub label: Option<String>,
+    /// What to print under each column, by the column's field
+    #[serde(default)]
+    pub values: HashMap<String, TableAggregate>,
+    #[serde(default)]
+    pub bold: Option<bool>,
+}
+
+/// A value worked out from a column over all the table's rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TableAggregate {
+    Sum,
+    /// Rows with a value in the column
+    Count,
+    #[serde(alias = "avg")]
+    Average,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RowDetail {
     pub field: String,
     #[serde(default)]
@@ -278,7 +1001,7 @@ pub struct RowDetail {
     pub condition: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ModifierConfig {
     #[serde(default)]
     pub indent: Option<u8>,
@@ -290,17 +1013,23 @@ pub struct ModifierConfig {
 
 // ==================== Space Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SpaceElement {
     #[serde(default)]
     pub lines: Option<u32>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
 }
 
 // ==================== Box Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct BoxElement {
     pub elements: Vec<Element>,
     #[serde(default)]
@@ -313,11 +1042,17 @@ pub struct BoxElement {
     pub padding: Option<u8>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
 }
 
 // ==================== Grid Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct GridElement {
     pub columns: u8,
     pub data: Vec<GridItem>,
@@ -325,9 +1060,15 @@ pub struct GridElement {
     pub gap: Option<u8>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct GridItem {
     pub label: String,
     pub value: String,
@@ -335,7 +1076,7 @@ pub struct GridItem {
 
 // ==================== Bar Chart Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct BarChartElement {
     pub data_source: String,
     pub value_field: String,
@@ -343,11 +1084,17 @@ pub struct BarChartElement {
     pub height: Option<u32>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
 }
 
 // ==================== Leaderboard Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct LeaderboardElement {
     pub data_source: String,
     pub fields: LeaderboardFields,
@@ -355,9 +1102,15 @@ pub struct LeaderboardElement {
     pub highlight_top: Option<u8>,
     #[serde(default)]
     pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct LeaderboardFields {
     pub rank: String,
     pub name: String,
@@ -369,25 +1122,227 @@ pub struct LeaderboardFields {
     pub transactions: Option<String>,
 }
 
-// ==================== Receipt Data ====================
+// ==================== Breakdown Element ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct ReceiptData {
-    // Store info
+/// Each entry's share of the total: label, value and percentage, with a bar
+/// as long as the share under them. For a payment method mix or each
+/// category's part of sales.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BreakdownElement {
+    pub data_source: String,
+    pub label_field: String,
+    pub value_field: String,
+    /// Printed before each value, such as "$"
     #[serde(default)]
-    pub store_name: Option<String>,
+    pub prefix: Option<String>,
+    /// Print the bars, on by default
     #[serde(default)]
-    pub store_address: Option<String>,
+    pub show_bars: Option<bool>,
     #[serde(default)]
-    pub store_phone: Option<String>,
+    pub condition: Option<String>,
     #[serde(default)]
-    pub store_website: Option<String>,
+    pub else_elements: Option<Vec<Element>>,
     #[serde(default)]
-    pub established_year: Option<u32>,
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
 
-    // Order info
+// ==================== Coupon Element ====================
+
+/// A coupon to tear off the receipt: a dashed tear line, the offer in a
+/// border, a code to scan and when it expires
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CouponElement {
+    /// The offer, centred in the border; `\n` starts a new line
+    pub content: String,
+    /// What the barcode or QR code holds
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub code_type: Option<CouponCode>,
+    /// Barcode format, as on `barcode` elements (default CODE128)
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Printed under the code, such as "Valid until {{coupon_expiry}}"
+    #[serde(default)]
+    pub expiry: Option<String>,
+    /// Partially cut the paper above the coupon instead of printing the
+    /// tear line
+    #[serde(default)]
+    pub cut: Option<bool>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
+
+/// How a coupon's code prints
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CouponCode {
+    #[default]
+    Barcode,
+    Qr,
+}
+
+// ==================== Repeat Element ====================
+
+/// Prints its elements once per entry of an array. While they print,
+/// `item` is the entry (`{{item.name}}`) and `index` its place, from 1.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RepeatElement {
+    pub data_source: String,
+    pub elements: Vec<Element>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
+
+// ==================== Switch Element ====================
+
+/// Prints the elements of the case matching a variable's value, such as a
+/// footer per `payment_method`, or `default` when no case does. Values
+/// match ignoring case.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SwitchElement {
+    /// The variable, such as `payment_method` or `customer.tier`
+    pub on: String,
+    /// Elements for each value, such as `{"card": [...], "cash": [...]}`
+    pub cases: BTreeMap<String, Vec<Element>>,
+    #[serde(default)]
+    pub default: Option<Vec<Element>>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
+
+// ==================== Kitchen Items Element ====================
+
+/// The order's items as the kitchen reads them: quantity and name in large
+/// print, modifiers in bold under them, and no prices
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct KitchenItemsElement {
+    /// Size of the item lines, 1 to 8 (default 2)
+    #[serde(default)]
+    pub font_size: Option<u8>,
+    /// Group items under a header per course when they have courses, on by
+    /// default; tickets printed per course have no headers
+    #[serde(default)]
+    pub show_courses: Option<bool>,
+    /// Group by `course` (default) or by `seat`
+    #[serde(default)]
+    pub group_by: Option<ItemGroup>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
+
+// ==================== Tax Summary Element ====================
+
+/// The receipt's `taxes` as a table: each rate with the amount it's charged
+/// on and the tax, and a total line when there are several
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TaxSummaryElement {
+    /// Printed before amounts, such as "$"
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Print the column headers, on by default
+    #[serde(default)]
+    pub show_header: Option<bool>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
+
+// ==================== Tip Suggestions Element ====================
+
+/// Suggested tips, each percentage with its tip and what the total comes
+/// to with it
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TipSuggestionsElement {
+    /// Percentages to suggest (default 15, 18 and 20), from 0 to 100
+    #[serde(default)]
+    pub percentages: Option<Vec<f64>>,
+    #[serde(default)]
+    pub base: Option<TipBase>,
+    /// Line above the suggestions (default "Suggested Tip")
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Printed before amounts, such as "$"
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub else_elements: Option<Vec<Element>>,
+    #[serde(default)]
+    pub min_width: Option<MinWidth>,
+    #[serde(default)]
+    pub fallback: Option<Box<Element>>,
+}
+
+/// What tips are a percentage of
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TipBase {
+    /// The subtotal, before tax
+    #[default]
+    Subtotal,
+    /// The total, after tax
+    Total,
+}
+
+// ==================== Receipt Data ====================
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ReceiptData {
+    // Store info
+    #[serde(default)]
+    pub store_name: Option<String>,
+    #[serde(default)]
+    pub store_address: Option<String>,
+    #[serde(default)]
+    pub store_phone: Option<String>,
+    #[serde(default)]
+    pub store_website: Option<String>,
+    #[serde(default)]
+    pub established_year: Option<u32>,
+
+    // Order info
     pub order_id: String,
     pub timestamp: String,
+    /// A sale, refund, void or reprint, for templates to branch on with
+    /// `receipt_type == 'refund'`
+    #[serde(default)]
+    pub receipt_type: ReceiptType,
+    /// The order a refund, void or reprint is for
+    #[serde(default)]
+    pub original_order_id: Option<String>,
     #[serde(default)]
     pub date: Option<String>,
     #[serde(default)]
@@ -398,6 +1353,27 @@ pub struct ReceiptData {
     pub server_name: Option<String>,
     #[serde(default)]
     pub table_number: Option<String>,
+    /// Such as "dine in", "takeaway" or "delivery"
+    #[serde(default)]
+    pub order_type: Option<String>,
+    /// The course a kitchen ticket is for, set on each ticket when they're
+    /// printed per course
+    #[serde(default)]
+    pub course: Option<String>,
+    /// The seat a kitchen ticket is for, set on each ticket when they're
+    /// printed per seat
+    #[serde(default)]
+    pub seat: Option<String>,
+    /// Labels of the copies to print, in place of the template's `copies`
+    #[serde(default)]
+    pub copies: Option<Vec<String>>,
+    /// The label of the copy being printed, set on each copy
+    #[serde(default)]
+    pub copy_label: Option<String>,
+    /// Print as a gift receipt, or not, whatever the template's
+    /// `gift_receipt.enabled`
+    #[serde(default)]
+    pub gift_receipt: Option<bool>,
 
     // Items
     #[serde(default)]
@@ -410,6 +1386,9 @@ pub struct ReceiptData {
     pub tax: f64,
     #[serde(default)]
     pub tax_rate: Option<f64>,
+    /// Each tax rate charged, for receipts that list them separately
+    #[serde(default)]
+    pub taxes: Vec<TaxLine>,
     #[serde(default)]
     pub discount: Option<f64>,
     #[serde(default)]
@@ -434,6 +1413,13 @@ pub struct ReceiptData {
     pub farewell_message: Option<String>,
     #[serde(default)]
     pub receipt_url: Option<String>,
+    /// Language of the template's translated labels, such as "fr" or "pt-BR"
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Language printed with `language` on a bilingual receipt, in place of
+    /// the template's `bilingual.language`
+    #[serde(default)]
+    pub secondary_language: Option<String>,
 
     // Custom fields for flexibility
     #[serde(flatten)]
@@ -451,6 +1437,134 @@ pub struct ReceiptItem {
     pub total: f64,
     #[serde(default)]
     pub modifiers: Option<Vec<String>>,
+    /// SKU or barcode of the product
+    #[serde(default)]
+    pub sku: Option<String>,
+    /// Menu or product category, also used for kitchen routing
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Discount taken off this line
+    #[serde(default)]
+    pub discount: Option<f64>,
+    /// Tax charged on this line
+    #[serde(default)]
+    pub tax: Option<f64>,
+    /// Unit the quantity is counted in, such as "kg" or "ea"
+    #[serde(default)]
+    pub unit: Option<String>,
+    /// Course the item is served in, such as "starters" or "mains"
+    #[serde(default)]
+    pub course: Option<String>,
+    /// Free text for the line, such as "no onions" or "gift wrapped"
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Seat of the guest the item is for, such as "2"
+    #[serde(default)]
+    pub seat: Option<String>,
+}
+
+/// One tax rate on the receipt
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TaxLine {
+    /// Such as "VAT" or "Reduced VAT"
+    pub name: String,
+    /// Percent, such as 21 or 5.5
+    #[serde(default)]
+    pub rate: f64,
+    /// Amount the rate is charged on
+    #[serde(default)]
+    pub base: f64,
+    #[serde(default)]
+    pub amount: f64,
+}
+
+/// What a receipt records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiptType {
+    #[default]
+    Sale,
+    /// Money paid back, with the amounts sent as negative numbers
+    Refund,
+    /// An order cancelled before it was paid
+    Void,
+    /// Another copy of a receipt printed before
+    Reprint,
+}
+
+impl ReceiptType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReceiptType::Sale => "sale",
+            ReceiptType::Refund => "refund",
+            ReceiptType::Void => "void",
+            ReceiptType::Reprint => "reprint",
+        }
+    }
+}
+
+impl ReceiptData {
+    /// Rows across `items` and every array-valued custom field, including
+    /// arrays nested in objects (`stats.hourly`), the data a table, bar
+    /// chart or leaderboard iterates over
+    pub fn row_count(&self) -> usize {
+        fn rows(value: &serde_json::Value) -> usize {
+            match value {
+                serde_json::Value::Array(rows) => rows.len(),
+                serde_json::Value::Object(obj) => obj.values().map(rows).sum(),
+                _ => 0,
+            }
+        }
+        self.items.len() + self.custom.values().map(rows).sum::<usize>()
+    }
+
+    /// The order split into kitchen tickets, each a copy with only its
+    /// items. An order without items is one ticket.
+    pub fn tickets(&self, split: TicketSplit) -> Vec<ReceiptData> {
+        let ticket =
+            |items: Vec<&ReceiptItem>, course: Option<&str>, seat: Option<&str>| ReceiptData {
+                items: items.into_iter().cloned().collect(),
+                course: course.map(str::to_string).or_else(|| self.course.clone()),
+                seat: seat.map(str::to_string).or_else(|| self.seat.clone()),
+                ..self.clone()
+            };
+        if self.items.is_empty() {
+            return vec![self.clone()];
+        }
+        match split {
+            TicketSplit::Order => vec![self.clone()],
+            TicketSplit::Item => self
+                .items
+                .iter()
+                .map(|item| ticket(vec![item], item.course.as_deref(), item.seat.as_deref()))
+                .collect(),
+            TicketSplit::Course => item_groups(&self.items, ItemGroup::Course)
+                .into_iter()
+                .map(|(course, items)| ticket(items, course, None))
+                .collect(),
+            TicketSplit::Seat => item_groups(&self.items, ItemGroup::Seat)
+                .into_iter()
+                .map(|(seat, items)| ticket(items, None, seat))
+                .collect(),
+        }
+    }
+}
+
+/// Items grouped by course or seat, groups in the order they first appear
+fn item_groups(items: &[ReceiptItem], by: ItemGroup) -> Vec<(Option<&str>, Vec<&ReceiptItem>)> {
+    let mut groups: Vec<(Option<&str>, Vec<&ReceiptItem>)> = Vec::new();
+    for item in items {
+        let key = match by {
+            ItemGroup::Course => &item.course,
+            ItemGroup::Seat => &item.seat,
+        };
+        let key = key.as_deref().filter(|key| !key.is_empty());
+        match groups.iter_mut().find(|(group, _)| *group == key) {
+            Some((_, group)) => group.push(item),
+            None => groups.push((key, vec![item])),
+        }
+    }
+    groups
 }
 
 impl Default for ReceiptItem {
@@ -461,19 +1575,102 @@ impl Default for ReceiptItem {
             price: 0.0,
             total: 0.0,
             modifiers: None,
+            sku: None,
+            category: None,
+            discount: None,
+            tax: None,
+            unit: None,
+            course: None,
+            note: None,
+            seat: None,
         }
     }
 }
 
 // ==================== Template Renderer ====================
 
+/// Most blank lines one section spacing or space element feeds; larger
+/// values are clamped with a warning
+pub const MAX_FEED_LINES: u32 = 1_000;
+
+/// Receives print commands as the renderer produces them.
+///
+/// Rendering into a `Vec` collects the whole job; other sinks (such as
+/// [`crate::encoder::StreamEncoder`]) consume commands as they arrive so very
+/// large jobs never exist in memory all at once.
+pub trait CommandSink {
+    fn push(&mut self, command: PrintCommand);
+
+    /// Called between elements and data rows; an error stops the render,
+    /// e.g. when the printer connection has failed
+    fn check(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl CommandSink for Vec<PrintCommand> {
+    fn push(&mut self, command: PrintCommand) {
+        Vec::push(self, command);
+    }
+}
+
+/// Adapts a closure for `render_streaming`
+struct FnSink<F>(F);
+
+impl<F: FnMut(PrintCommand)> CommandSink for FnSink<F> {
+    fn push(&mut self, command: PrintCommand) {
+        (self.0)(command);
+    }
+}
+
 pub struct TemplateRenderer {
     paper_width: u32,
+    /// Image data for logo elements, keyed by `logo_id` (or `source`)
+    resolved_logos: HashMap<String, String>,
+    /// Set from another thread to abandon a render between elements
+    cancel: Option<Arc<AtomicBool>>,
+    upside_down: bool,
+    /// Left margin and width in dots, see [`PrintArea::resolve`]
+    print_area: Option<(u16, u16)>,
 }
 
 impl TemplateRenderer {
     pub fn new(paper_width: u32) -> Self {
-        Self { paper_width }
+        Self {
+            paper_width,
+            resolved_logos: HashMap::new(),
+            cancel: None,
+            upside_down: false,
+            print_area: None,
+        }
+    }
+
+    /// Print for a printer mounted upside down: every line is rotated and
+    /// the lines come out last first, so the torn off receipt reads from
+    /// the header down
+    pub fn with_upside_down(mut self, upside_down: bool) -> Self {
+        self.upside_down = upside_down;
+        self
+    }
+
+    /// Set the left margin and print width, in dots, at the start of each
+    /// receipt
+    pub fn with_print_area(mut self, print_area: Option<(u16, u16)>) -> Self {
+        self.print_area = print_area;
+        self
+    }
+
+    /// Use cached image data for logos that reference the logo cache
+    pub fn with_resolved_logos(mut self, resolved_logos: HashMap<String, String>) -> Self {
+        self.resolved_logos = resolved_logos;
+        self
+    }
+
+    /// Stop rendering with an error once `cancel` is set. The flag is checked
+    /// between elements and between data rows, so a long job stops promptly.
+    pub fn with_cancellation(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
     }
 
     /// Get paper width
@@ -481,18 +1678,170 @@ impl TemplateRenderer {
         self.paper_width
     }
 
-    /// Render template with data to a vector of print commands (for testing/preview)
-    pub fn render_to_commands(
+    /// Compile and render a template in one go, for inline and preview templates
+    pub fn render_template(
         &self,
         template: &ReceiptTemplate,
         data: &ReceiptData,
     ) -> Result<Vec<PrintCommand>, String> {
-        let mut commands = vec![PrintCommand::Init];
+        self.render_to_commands(&self.compile(template), data)
+    }
+
+    /// Render a compiled template with data to a vector of print commands
+    pub fn render_to_commands(
+        &self,
+        template: &CompiledTemplate,
+        data: &ReceiptData,
+    ) -> Result<Vec<PrintCommand>, String> {
+        let mut commands = Vec::new();
+        self.render_to_sink(template, data, &mut commands)?;
+        Ok(commands)
+    }
+
+    /// Render a compiled template as a plain-text picture of the receipt,
+    /// see [`render_text`](crate::text_preview::render_text)
+    pub fn render_to_text(
+        &self,
+        template: &CompiledTemplate,
+        data: &ReceiptData,
+    ) -> Result<String, String> {
+        let commands = self.render_to_commands(template, data)?;
+        Ok(render_text(&commands, self.paper_width))
+    }
+
+    /// Render a compiled template, handing each command to `emit` as soon as
+    /// it is produced. Emits exactly the commands `render_to_commands` returns.
+    pub fn render_streaming(
+        &self,
+        template: &CompiledTemplate,
+        data: &ReceiptData,
+        emit: impl FnMut(PrintCommand),
+    ) -> Result<(), String> {
+        self.render_to_sink(template, data, &mut FnSink(emit))
+    }
+
+    /// Render a compiled template into any command sink
+    pub fn render_to_sink(
+        &self,
+        template: &CompiledTemplate,
+        data: &ReceiptData,
+        commands: &mut dyn CommandSink,
+    ) -> Result<(), String> {
+        #[cfg(test)]
+        if data.order_id == tests::PANIC_ORDER_ID {
+            panic!("forced render panic (test hook)");
+        }
+
+        debug_assert_eq!(
+            template.paper_width, self.paper_width,
+            "template was compiled for a different paper width"
+        );
+
+        // Translated labels are read as `{{t.<key>}}`, with the second
+        // language's, and item names in it, joined on bilingual receipts
+        let labelled;
+        let data = match template.labels(data.language.as_deref()) {
+            Some(mut labels) => {
+                let mut scoped = data.clone();
+                if let Some((bilingual, second)) = template.second_language(data) {
+                    for (key, label) in labels.as_object_mut().into_iter().flatten() {
+                        if let (Some(first), Some(second)) = (label.as_str(), second.get(key)) {
+                            *label = bilingual.join(first, second).into();
+                        }
+                    }
+                    for item in &mut scoped.items {
+                        if let Some(name) = second.get(&item.name) {
+                            item.name = bilingual.join(&item.name, name);
+                        }
+                    }
+                }
+                scoped.custom.insert("t".to_string(), labels);
+                labelled = scoped;
+                &labelled
+            }
+            None => data,
+        };
+
+        // Constants and computed values are read by name, the computed ones
+        // worked out once for the whole print
+        let valued;
+        let data = if template.constants.is_empty() && template.computed.is_empty() {
+            data
+        } else {
+            let mut scoped = data.clone();
+            scoped.custom.extend(template.constants.clone());
+            for (name, expr) in &template.computed {
+                let scope = self.scope(&scoped, |name| self.get_variable_value(name, &scoped));
+                // Stored as printed, like receipt fields, and read back
+                // as a number by expressions and filters
+                let value = expr.eval(&scope).to_string();
+                scoped.custom.insert(name.clone(), value.into());
+            }
+            valued = scoped;
+            &valued
+        };
+
+        let copies = data.copies.as_deref().unwrap_or(&template.copies);
+        if copies.is_empty() {
+            self.render_tickets(template, data, commands)?;
+        }
+        for label in copies {
+            let copy = ReceiptData {
+                copy_label: Some(label.clone()),
+                ..data.clone()
+            };
+            self.render_tickets(template, &copy, commands)?;
+        }
+
+        if let Some(buzzer) = &template.buzzer {
+            commands.push(buzzer.command());
+        }
+
+        Ok(())
+    }
 
-        // Render each section
-        for section in &template.layout.sections {
-            if self.should_render(&section.condition, data) {
-                self.build_section_commands(&mut commands, section, data)?;
+    /// Print the order as one ticket, or as the template splits it
+    fn render_tickets(
+        &self,
+        template: &CompiledTemplate,
+        data: &ReceiptData,
+        commands: &mut dyn CommandSink,
+    ) -> Result<(), String> {
+        match template.tickets {
+            TicketSplit::Order => self.render_ticket(template, data, commands),
+            split => {
+                for ticket in data.tickets(split) {
+                    self.render_ticket(template, &ticket, commands)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Print the template once, from the printer's reset to the cut
+    fn render_ticket(
+        &self,
+        template: &CompiledTemplate,
+        data: &ReceiptData,
+        commands: &mut dyn CommandSink,
+    ) -> Result<(), String> {
+        commands.push(PrintCommand::Init);
+        if let Some((left, width)) = self.print_area {
+            commands.push(PrintCommand::PrintArea { left, width });
+        }
+
+        // Render each section; upside down, the lines come out last first
+        if self.upside_down {
+            commands.push(PrintCommand::UpsideDown(true));
+            let mut lines = Vec::new();
+            for section in template.sections_for(data) {
+                self.build_section_commands(&mut lines, section, data)?;
+                commands.check()?;
+            }
+            push_lines_last_first(lines, commands);
+        } else {
+            for section in template.sections_for(data) {
+                self.build_section_commands(commands, section, data)?;
             }
         }
 
@@ -505,88 +1854,258 @@ impl TemplateRenderer {
         commands.push(PrintCommand::Feed(1));
         commands.push(PrintCommand::Cut);
 
-        Ok(commands)
+        Ok(())
     }
 
     /// Build commands for a section
     fn build_section_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
-        section: &Section,
+        commands: &mut dyn CommandSink,
+        section: &CompiledSection,
         data: &ReceiptData,
     ) -> Result<(), String> {
+        let shown = self.should_render(&section.condition, data);
+        let elements = match shown {
+            true => &section.elements,
+            false if section.otherwise.is_empty() => return Ok(()),
+            false => &section.otherwise,
+        };
+
         // Spacing before
-        if let Some(spacing) = &section.spacing {
-            if let Some(before) = spacing.before {
-                commands.push(PrintCommand::Feed(before as u8));
-            }
+        if let Some(before) = section.before {
+            self.push_feed(commands, before);
         }
 
         // Render elements
-        for element in &section.elements {
-            self.build_element_commands(commands, element, data)?;
+        for node in elements {
+            self.build_element_commands(commands, node, data)?;
         }
 
         // Spacing after
-        if let Some(spacing) = &section.spacing {
-            if let Some(after) = spacing.after {
-                commands.push(PrintCommand::Feed(after as u8));
-            }
+        if let Some(after) = section.after {
+            self.push_feed(commands, after);
+        }
+
+        if let Some(buzzer) = section.buzzer.as_ref().filter(|_| shown) {
+            commands.push(buzzer.command());
         }
 
         Ok(())
     }
 
+    /// Feed `lines` blank lines, clamped to [`MAX_FEED_LINES`] and split
+    /// into commands the encoder can send
+    fn push_feed(&self, commands: &mut dyn CommandSink, lines: u32) {
+        let mut lines = if lines > MAX_FEED_LINES {
+            log::warn!("Feed of {} lines clamped to {}", lines, MAX_FEED_LINES);
+            MAX_FEED_LINES
+        } else {
+            lines
+        };
+        while lines > 0 {
+            let chunk = lines.min(u8::MAX as u32);
+            commands.push(PrintCommand::Feed(chunk as u8));
+            lines -= chunk;
+        }
+    }
+
     /// Build commands for an element
     fn build_element_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
-        element: &Element,
+        commands: &mut dyn CommandSink,
+        node: &CompiledNode,
+        data: &ReceiptData,
+    ) -> Result<(), String> {
+        self.checkpoint(commands)?;
+
+        if !self.should_render(&node.condition, data) {
+            for node in &node.otherwise {
+                self.build_element_commands(commands, node, data)?;
+            }
+            return Ok(());
+        }
+
+        match &node.element {
+            // Their children are put in order one by one
+            CompiledElement::Box { .. }
+            | CompiledElement::Repeat { .. }
+            | CompiledElement::Switch { .. } => {
+                self.build_compiled_element(commands, &node.element, data)
+            }
+            element => {
+                let mut bidi = BidiSink::new(commands, node.direction);
+                self.build_compiled_element(&mut bidi, element, data)?;
+                bidi.finish();
+                Ok(())
+            }
+        }
+    }
+
+    /// Commands for an element whose condition has passed
+    fn build_compiled_element(
+        &self,
+        commands: &mut dyn CommandSink,
+        element: &CompiledElement,
         data: &ReceiptData,
     ) -> Result<(), String> {
         match element {
-            Element::Text(e) => {
-                if self.should_render(&e.condition, data) {
-                    self.build_text_commands(commands, e, data)?;
-                }
+            CompiledElement::Text {
+                element,
+                markup: Some(spans),
+                ..
+            } => {
+                self.build_markup_commands(commands, element, spans, data)?;
             }
-            Element::Divider(e) => {
-                if self.should_render(&e.condition, data) {
-                    self.build_divider_commands(commands, e)?;
-                }
+            CompiledElement::Text {
+                element, content, ..
+            } => {
+                self.build_text_commands(commands, element, content, data)?;
             }
-            Element::Row(e) => {
-                if self.should_render(&e.condition, data) {
-                    self.build_row_commands(commands, e, data)?;
-                }
+            CompiledElement::Divider { line, align } => {
+                commands.push(PrintCommand::Align(align.clone()));
+                commands.push(PrintCommand::WriteLine(line.clone()));
+                commands.push(PrintCommand::Align("left".to_string()));
             }
-            Element::QR(e) => {
-                if self.should_render(&e.condition, data) {
-                    self.build_qr_commands(commands, e, data)?;
-                }
+            CompiledElement::Row {
+                element,
+                left,
+                right,
+            } => {
+                self.build_row_commands(commands, element, left, right, data)?;
             }
-            Element::Barcode(e) => {
-                if self.should_render(&e.condition, data) {
-                    self.build_barcode_commands(commands, e, data)?;
-                }
+            CompiledElement::QR { element, content } => {
+                self.build_qr_commands(commands, element, content, data)?;
             }
-            Element::Table(e) => {
-                if self.should_render(&e.condition, data) {
-                    self.build_table_commands(commands, e, data)?;
-                }
+            CompiledElement::Barcode { element, content } => {
+                self.build_barcode_commands(commands, element, content, data)?;
             }
-            Element::Space(e) => {
-                if self.should_render(&e.condition, data) {
-                    let lines = e.lines.unwrap_or(1);
-                    commands.push(PrintCommand::Feed(lines as u8));
-                }
+            CompiledElement::Table {
+                element,
+                column_widths,
+                header,
+                detail_conditions,
+            } => {
+                self.build_table_commands(
+                    commands,
+                    element,
+                    column_widths,
+                    header,
+                    detail_conditions,
+                    data,
+                )?;
             }
-            Element::Logo(e) => {
-    if self.should_render(&e.condition, data) {
-        // source must be a base64 string stored in a custom data field,
-        // or a literal base64 string on the element itself.
-        let source = match &e.source {
-            Some(s) => {
+            CompiledElement::Space(lines) => {
+                self.push_feed(commands, *lines);
+            }
+            CompiledElement::Logo(e) => {
+                self.build_logo_commands(commands, e, data);
+            }
+            CompiledElement::Image { element, source } => {
+                self.build_image_commands(commands, element, source, data);
+            }
+            CompiledElement::Box {
+                element,
+                border_line,
+                children,
+            } => {
+                self.build_box_commands(commands, element, border_line, children, data)?;
+            }
+            CompiledElement::Grid {
+                element,
+                values,
+                col_width,
+            } => {
+                self.build_grid_commands(commands, element, values, *col_width, data)?;
+            }
+            CompiledElement::BarChart(e) => {
+                self.build_bar_chart_commands(commands, e, data)?;
+            }
+            CompiledElement::Leaderboard(e) => {
+                self.build_leaderboard_commands(commands, e, data)?;
+            }
+            CompiledElement::Breakdown(e) => {
+                self.build_breakdown_commands(commands, e, data)?;
+            }
+            CompiledElement::Coupon {
+                element,
+                content,
+                code,
+                expiry,
+            } => {
+                self.build_coupon_commands(
+                    commands,
+                    element,
+                    content,
+                    code.as_deref(),
+                    expiry,
+                    data,
+                )?;
+            }
+            CompiledElement::Repeat { element, children } => {
+                self.build_repeat_commands(commands, element, children, data)?;
+            }
+            CompiledElement::Switch { on, cases, default } => {
+                let value = self.get_variable_value(on, data);
+                let value = value.trim();
+                let chosen = cases
+                    .iter()
+                    .find(|(case, _)| case.eq_ignore_ascii_case(value))
+                    .map_or(default, |(_, children)| children);
+                for child in chosen {
+                    self.build_element_commands(commands, child, data)?;
+                }
+            }
+            CompiledElement::KitchenItems(e) => {
+                self.build_kitchen_items_commands(commands, e, data)?;
+            }
+            CompiledElement::TaxSummary(e) => {
+                self.build_tax_summary_commands(commands, e, data)?;
+            }
+            CompiledElement::TipSuggestions(e) => {
+                self.build_tip_suggestions_commands(commands, e, data)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fail the render if it was cancelled or the sink can't take more output
+    fn checkpoint(&self, commands: &mut dyn CommandSink) -> Result<(), String> {
+        if self
+            .cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+        {
+            return Err("Print job cancelled".to_string());
+        }
+        commands.check()
+    }
+
+    /// Build logo commands; problems with the image are logged, not fatal
+    fn build_logo_commands(
+        &self,
+        commands: &mut dyn CommandSink,
+        e: &LogoElement,
+        data: &ReceiptData,
+    ) {
+        let align = e.align.as_deref().unwrap_or("center");
+        if let Some(slot) = e.nv_slot {
+            commands.push(PrintCommand::Align(align.to_string()));
+            commands.push(PrintCommand::NvLogo(slot));
+            return;
+        }
+
+        let cached = e
+            .logo_id
+            .as_ref()
+            .or(e.source.as_ref())
+            .and_then(|key| self.resolved_logos.get(key));
+
+        // source is base64 data, a file path or a URL, stored in a custom data
+        // field or literally on the element itself.
+        let source = match (cached, &e.source) {
+            (Some(cached), _) => cached.clone(),
+            (None, Some(s)) => {
                 // If it looks like a variable reference {{logo}} resolve it
                 if s.starts_with("{{") && s.ends_with("}}") {
                     let var_name = s.trim_start_matches("{{").trim_end_matches("}}");
@@ -595,72 +2114,71 @@ impl TemplateRenderer {
                     s.clone()
                 }
             }
-            None => String::new(),
+            (None, None) => String::new(),
         };
- 
+
         if source.is_empty() {
             log::warn!("Logo element has no source data — skipping");
-        } else {
-            // paper_width field on TemplateRenderer is in characters (48 / 32 etc.)
-            // but image_to_escpos works in dots. Convert: dots = chars * 12 is a
-            // rough heuristic; better to pass paper_width_dots explicitly via
-            // the template's paper_width field (already in dots for image use).
-            //
-            // If your template paper_width is in characters (e.g. 48),
-            // multiply by 12 to get dots (48 * 12 = 576 for 80mm paper).
-            let paper_width_dots = self.paper_width * 12;
- 
-            // max_width from LogoElement: stored as dots, or None = full width
-            let max_width_dots = e.max_width;
- 
-            let align = e.align.as_deref().unwrap_or("center");
- 
-            match crate::image_print::image_to_escpos(
-                &source,
-                paper_width_dots,
-                max_width_dots,
-                align,
-            ) {
-                Ok(bytes) => {
-                    commands.push(PrintCommand::Image(bytes));
-                }
-                Err(err) => {
-                    log::error!("Logo image conversion failed: {}", err);
-                }
-            }
+            return;
         }
-    }
-}
-            Element::Box(e) => {
-                if self.should_render(&e.condition, data) {
-                    self.build_box_commands(commands, e, data)?;
-                }
-            }
-            Element::Grid(e) => {
-                if self.should_render(&e.condition, data) {
-                    self.build_grid_commands(commands, e, data)?;
-                }
-            }
-            Element::BarChart(e) => {
-                if self.should_render(&e.condition, data) {
-                    self.build_bar_chart_commands(commands, e, data)?;
-                }
+
+        // paper_width is in characters (48 / 32 etc.) of 12 dots each, so
+        // 48 * 12 = 576 dots for 80mm paper
+        let paper_width_dots = self.paper_width * 12;
+
+        // Sources that aren't in the logo cache are read here: base64, a file
+        // or an http(s) URL. max_width and max_height are in dots.
+        match crate::image_print::image_to_escpos(
+            &source,
+            paper_width_dots,
+            e.max_width,
+            e.max_height,
+            align,
+            &e.image_options(),
+        ) {
+            Ok(bytes) => {
+                commands.push(PrintCommand::Image(bytes));
             }
-            Element::Leaderboard(e) => {
-                if self.should_render(&e.condition, data) {
-                    self.build_leaderboard_commands(commands, e, data)?;
-                }
+            Err(err) => {
+                log::error!("Logo image conversion failed: {}", err);
             }
         }
+    }
 
-        Ok(())
+    /// Build image commands; an image that can't be read is left out
+    fn build_image_commands(
+        &self,
+        commands: &mut dyn CommandSink,
+        element: &ImageElement,
+        source: &TextPattern,
+        data: &ReceiptData,
+    ) {
+        let source = self.render_text(source, data);
+        if source.trim().is_empty() {
+            log::warn!("Image element has no source — skipping");
+            return;
+        }
+        let image = read_image_source_cached(&source)
+            .and_then(|bytes| decode_image(&bytes).map_err(|e| e.to_string()));
+        match image {
+            Ok(image) => commands.push(PrintCommand::Image(decoded_image_to_escpos(
+                &image,
+                self.paper_width * 12,
+                element.max_width,
+                element.max_height,
+                element.align.as_deref().unwrap_or("center"),
+                &element.image_options(),
+            ))),
+            Err(e) => log::error!("Image element skipped: {}", e),
+        }
     }
 
     /// Build text element commands
     fn build_text_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
+        commands: &mut dyn CommandSink,
         element: &TextElement,
+        content: &TextPattern,
         data: &ReceiptData,
     ) -> Result<(), String> {
         // Apply styles
@@ -676,6 +2194,16 @@ impl TemplateRenderer {
             commands.push(PrintCommand::Reverse(true));
         }
 
+        let double_strike = element.double_strike.unwrap_or(false);
+        if double_strike {
+            commands.push(PrintCommand::DoubleStrike(true));
+        }
+
+        let font = element.font.unwrap_or_default();
+        if font != Font::A {
+            commands.push(PrintCommand::Font(font));
+        }
+
         // Set size
         let width = element.font_width.unwrap_or(1);
         let height = element.font_size.unwrap_or(1);
@@ -688,22 +2216,98 @@ impl TemplateRenderer {
         commands.push(PrintCommand::Align(align.to_string()));
 
         // Substitute variables
-        let mut content = self.substitute_variables(&element.content, data);
+        let mut content = self.render_text(content, data);
 
         // Apply letter spacing if specified
         if let Some(spacing) = element.letter_spacing {
             if spacing > 0 {
-                content = self.apply_letter_spacing(&content, spacing);
+                content = Cow::Owned(self.apply_letter_spacing(&content, spacing));
             }
         }
 
-        // Print
-        commands.push(PrintCommand::WriteLine(content));
+        // Print; enlarged text is wrapped to the columns left at that width
+        let multiplier = width_multiplier(width);
+        if multiplier > 1 {
+            let columns = self.effective_width(multiplier) * 12 / font.char_width() as usize;
+            for line in wrap_text(&content, columns) {
+                commands.push(PrintCommand::WriteLine(line));
+            }
+        } else {
+            for line in content.split('\n') {
+                commands.push(PrintCommand::WriteLine(line.to_string()));
+            }
+        }
 
         // Reset styles
         commands.push(PrintCommand::Bold(false));
         commands.push(PrintCommand::Underline(false));
         commands.push(PrintCommand::Reverse(false));
+        if double_strike {
+            commands.push(PrintCommand::DoubleStrike(false));
+        }
+        if font != Font::A {
+            commands.push(PrintCommand::Font(Font::A));
+        }
+        commands.push(PrintCommand::Size(1, 1));
+        commands.push(PrintCommand::Align("left".to_string()));
+
+        Ok(())
+    }
+
+    /// Build commands for text that styles parts of itself with markup. The
+    /// element's styles apply throughout, with each span's on top; the line
+    /// is left for the printer to wrap.
+    fn build_markup_commands(
+        &self,
+        commands: &mut dyn CommandSink,
+        element: &TextElement,
+        spans: &[MarkupSpan],
+        data: &ReceiptData,
+    ) -> Result<(), String> {
+        let double_strike = element.double_strike.unwrap_or(false);
+        if double_strike {
+            commands.push(PrintCommand::DoubleStrike(true));
+        }
+        let font = element.font.unwrap_or_default();
+        if font != Font::A {
+            commands.push(PrintCommand::Font(font));
+        }
+        let align = element.align.as_deref().unwrap_or("left");
+        commands.push(PrintCommand::Align(align.to_string()));
+
+        let bold = element.bold.unwrap_or(false);
+        let underline = element.underline.unwrap_or(false);
+        let invert = element.invert.unwrap_or(false);
+        let width = element.font_width.unwrap_or(1).max(1);
+        let height = element.font_size.unwrap_or(1).max(1);
+        for span in spans {
+            let style = span.style;
+            let scale = if style.big { 2 } else { 1 };
+            commands.push(PrintCommand::Bold(bold || style.bold));
+            commands.push(PrintCommand::Underline(underline || style.underline));
+            commands.push(PrintCommand::Reverse(invert || style.invert));
+            commands.push(PrintCommand::Size(
+                width.saturating_mul(scale).min(8),
+                height.saturating_mul(scale).min(8),
+            ));
+
+            let mut text = self.render_text(&span.content, data);
+            if let Some(spacing) = element.letter_spacing.filter(|&spacing| spacing > 0) {
+                text = Cow::Owned(self.apply_letter_spacing(&text, spacing));
+            }
+            commands.push(PrintCommand::Write(text.into_owned()));
+        }
+        commands.push(PrintCommand::WriteLine(String::new()));
+
+        commands.push(PrintCommand::Bold(false));
+        commands.push(PrintCommand::Underline(false));
+        commands.push(PrintCommand::Reverse(false));
+        if double_strike {
+            commands.push(PrintCommand::DoubleStrike(false));
+        }
+        if font != Font::A {
+            commands.push(PrintCommand::Font(Font::A));
+        }
         commands.push(PrintCommand::Size(1, 1));
         commands.push(PrintCommand::Align("left".to_string()));
 
@@ -712,19 +2316,18 @@ impl TemplateRenderer {
 
     /// Apply letter spacing by inserting spaces between characters
     fn apply_letter_spacing(&self, text: &str, spacing: u8) -> String {
-        let spacing_str = " ".repeat(spacing as usize);
-        text.chars()
-            .map(|c| c.to_string())
-            .collect::<Vec<_>>()
-            .join(&spacing_str)
+        let mut out = String::with_capacity(text.len() * (spacing as usize + 1));
+        for (i, c) in text.chars().enumerate() {
+            if i > 0 {
+                push_spaces(&mut out, spacing as usize);
+            }
+            out.push(c);
+        }
+        out
     }
 
-    /// Build divider commands
-    fn build_divider_commands(
-        &self,
-        commands: &mut Vec<PrintCommand>,
-        element: &DividerElement,
-    ) -> Result<(), String> {
+    /// Full divider line for an element at this paper width
+    pub(crate) fn divider_line(&self, element: &DividerElement) -> String {
         let character = if let Some(pattern) = &element.pattern {
             match pattern.as_str() {
                 "diamond" => "* ",
@@ -746,31 +2349,29 @@ impl TemplateRenderer {
             }
         };
 
-        // Subtract 6 from paper width as a safety margin to prevent wrapping on some printers
-        let width = (self.paper_width as usize).saturating_sub(6);
-
-        // For patterns with spaces, adjust repetition
-        let divider = if character.contains(' ') {
-            let pattern_len = character.len();
-            let repeats = width / pattern_len;
-            character.repeat(repeats)
-        } else {
-            character.repeat(width)
-        };
+        // Patterns like "* " and full-width characters take several columns
+        let pattern_width = text_width(character).max(1);
+        character.repeat(self.content_width() / pattern_width)
+    }
 
-        let align = element.align.as_deref().unwrap_or("left");
-        commands.push(PrintCommand::Align(align.to_string()));
-        commands.push(PrintCommand::WriteLine(divider));
-        commands.push(PrintCommand::Align("left".to_string()));
+    /// Printable line width: paper width minus a 6 character safety margin,
+    /// which prevents wrapping on some printers
+    pub(crate) fn content_width(&self) -> usize {
+        (self.paper_width as usize).saturating_sub(6)
+    }
 
-        Ok(())
+    /// Columns available while characters are printed `multiplier` times wide
+    pub(crate) fn effective_width(&self, multiplier: usize) -> usize {
+        self.content_width() / multiplier.max(1)
     }
 
     /// Build row commands
     fn build_row_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
+        commands: &mut dyn CommandSink,
         element: &RowElement,
+        left: &Option<TextPattern>,
+        right: &Option<TextPattern>,
         data: &ReceiptData,
     ) -> Result<(), String> {
         // Apply styles
@@ -788,41 +2389,44 @@ impl TemplateRenderer {
             commands.push(PrintCommand::Size(font_size, font_size));
         }
 
-        let left = element
-            .left
+        let left = left
             .as_ref()
-            .map(|s| self.substitute_variables(s, data))
+            .map(|p| self.render_text(p, data))
             .unwrap_or_default();
-        let right = element
-            .right
+        let right = right
             .as_ref()
-            .map(|s| self.substitute_variables(s, data))
+            .map(|p| self.render_text(p, data))
             .unwrap_or_default();
 
-        // Use a safety margin of 6 characters (Paper Width - 6) to prevent physical wrapping
-        let base_width = (self.paper_width as usize).saturating_sub(6);
-        
         // Adjust width based on font size. If font size is 2, characters are twice as wide.
-        let font_size = element.font_size.unwrap_or(1) as usize;
-        let width = base_width / font_size;
-        
-        // Truncate left if combined is too long, or right? 
-        // Let's ensure they fit by calculating space.
-        let left_chars: Vec<char> = left.chars().collect();
-        let right_chars: Vec<char> = right.chars().collect();
-        let total_chars = left_chars.len() + right_chars.len() + 1; // +1 for minimum space
-
-        let line = if total_chars <= width {
-            let spaces = width - (left_chars.len() + right_chars.len());
-            format!("{}{}{}", left, " ".repeat(spaces), right)
-        } else {
-            // Content is too wide, truncate left part to fit
-            let available_for_left = width.saturating_sub(right_chars.len() + 1);
-            let truncated_left: String = left_chars.iter().take(available_for_left).collect();
-            format!("{} {}", truncated_left, right)
-        };
+        let width = self.effective_width(width_multiplier(font_size));
+
+        // Stacked bilingual labels put each side's lines on lines of their own
+        let left_lines: Vec<&str> = left.split('\n').collect();
+        let right_lines: Vec<&str> = right.split('\n').collect();
+        for i in 0..left_lines.len().max(right_lines.len()) {
+            let left = left_lines.get(i).copied().unwrap_or_default();
+            let right = right_lines.get(i).copied().unwrap_or_default();
+
+            // Measured in columns, so full-width characters count twice
+            let left_width = text_width(left);
+            let right_width = text_width(right);
+
+            let line = if left_width + right_width < width {
+                let spaces = width - (left_width + right_width);
+                format!("{}{}{}", left, " ".repeat(spaces), right)
+            } else if right_width < width {
+                // Content is too wide, truncate left part to fit
+                let available_for_left = width.saturating_sub(right_width + 1);
+                let (truncated_left, _) = truncate_columns(left, available_for_left);
+                format!("{} {}", truncated_left, right)
+            } else {
+                // The right part alone doesn't fit
+                truncate_columns(right, width).0.to_string()
+            };
 
-        commands.push(PrintCommand::WriteLine(line));
+            commands.push(PrintCommand::WriteLine(line));
+        }
 
         // Reset styles
         if element.bold.unwrap_or(false) {
@@ -841,16 +2445,21 @@ impl TemplateRenderer {
     /// Build QR code commands
     fn build_qr_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
+        commands: &mut dyn CommandSink,
         element: &QRElement,
+        content: &TextPattern,
         data: &ReceiptData,
     ) -> Result<(), String> {
-        let content = self.substitute_variables(&element.content, data);
+        let content = self.render_text(content, data).into_owned();
         let size = element.size.unwrap_or(6);
         let align = element.align.as_deref().unwrap_or("center");
 
         commands.push(PrintCommand::Align(align.to_string()));
-        commands.push(PrintCommand::QRCode { content, size });
+        commands.push(PrintCommand::QRCode {
+            content,
+            size,
+            error_correction: element.error_correction.unwrap_or_default(),
+        });
         commands.push(PrintCommand::Align("left".to_string()));
 
         Ok(())
@@ -859,28 +2468,48 @@ impl TemplateRenderer {
     /// Build barcode commands
     fn build_barcode_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
+        commands: &mut dyn CommandSink,
         element: &BarcodeElement,
+        content: &TextPattern,
         data: &ReceiptData,
     ) -> Result<(), String> {
-        let content = self.substitute_variables(&element.content, data);
+        let content = self.render_text(content, data).into_owned();
         let height = element.height.unwrap_or(100);
         let width = element.width.unwrap_or(3);
         let format = element
             .format
             .clone()
             .unwrap_or_else(|| "CODE128".to_string());
-        let show_text = element.show_text.unwrap_or(true);
+        let hri = match (element.hri_position, element.show_text) {
+            (Some(hri), _) => hri,
+            (None, Some(false)) => HriPosition::None,
+            (None, _) => HriPosition::Below,
+        };
         let align = element.align.as_deref().unwrap_or("center");
 
         commands.push(PrintCommand::Align(align.to_string()));
-        commands.push(PrintCommand::Barcode {
-            content,
-            format,
-            height,
-            width,
-            show_text,
-        });
+        // A barcode that wouldn't scan is printed as text instead
+        let checked = Symbology::parse(&format)
+            .ok_or_else(|| "unknown format".to_string())
+            .and_then(|symbology| symbology.check(&content));
+        match checked {
+            Ok(content) => commands.push(PrintCommand::Barcode {
+                content,
+                format,
+                height,
+                width,
+                hri,
+            }),
+            Err(e) => {
+                log::warn!(
+                    "Barcode '{}' can't be printed as {}: {}",
+                    content,
+                    format,
+                    e
+                );
+                commands.push(PrintCommand::WriteLine(content));
+            }
+        }
         commands.push(PrintCommand::Align("left".to_string()));
 
         Ok(())
@@ -889,41 +2518,106 @@ impl TemplateRenderer {
     /// Build table commands
     fn build_table_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
+        commands: &mut dyn CommandSink,
         element: &TableElement,
+        column_widths: &[usize],
+        header: &[String],
+        detail_conditions: &[Option<Condition>],
         data: &ReceiptData,
     ) -> Result<(), String> {
+        // Measured tables fit this receipt's cells
+        let measured;
+        let (column_widths, header) = if has_measured_columns(&element.columns) {
+            let widest = self.widest_cells(element, data);
+            let widths = self.fit_column_widths(&element.columns, &widest);
+            let header = self.format_table_row(&element.columns, &widths, None);
+            measured = (widths, header);
+            (measured.0.as_slice(), measured.1.as_slice())
+        } else {
+            (column_widths, header)
+        };
+
         // Print header if enabled
         if element.show_header.unwrap_or(false) {
             if element.header_bold.unwrap_or(true) {
                 commands.push(PrintCommand::Bold(true));
             }
 
-            let header_line = self.format_table_row(&element.columns, None);
-            commands.push(PrintCommand::WriteLine(header_line));
+            for line in header {
+                commands.push(PrintCommand::WriteLine(line.clone()));
+            }
 
             if element.header_bold.unwrap_or(true) {
                 commands.push(PrintCommand::Bold(false));
             }
 
             if element.header_divider.unwrap_or(true) {
-                let width = (self.paper_width as usize).saturating_sub(6);
-                let divider = "-".repeat(width);
+                let divider = "-".repeat(self.content_width());
                 commands.push(PrintCommand::WriteLine(divider));
             }
         }
 
-        // Print rows from data source
-        let rows = self.get_data_source_items(&element.data_source, data);
+        // Print rows from data source, with their place in the loop
+        let mut rows = self.data_source_rows(&element.data_source, data);
+        if let Some(field) = &element.group_by {
+            rows = Box::new(group_rows(rows, field).into_iter());
+        }
+        let mut rows = rows.peekable();
+        let mut running_total = 0.0;
+        let footer_rows = element.footer_rows.as_deref().unwrap_or_default();
+        let mut column_totals = if footer_rows.is_empty() {
+            Vec::new()
+        } else {
+            vec![ColumnTotals::default(); element.columns.len()]
+        };
+        let mut group_totals = if element.group_subtotal.is_some() {
+            vec![ColumnTotals::default(); element.columns.len()]
+        } else {
+            Vec::new()
+        };
+        let mut index = 0;
+        let mut group_starts = true;
+        while let Some(row) = rows.next() {
+            self.checkpoint(commands)?;
+
+            // Rows without a value are grouped together, without a header
+            if let Some(field) = element.group_by.as_deref().filter(|_| group_starts) {
+                if let Some(group) = group_key(row, field) {
+                    commands.push(PrintCommand::Bold(true));
+                    commands.push(PrintCommand::WriteLine(group.into_owned()));
+                    commands.push(PrintCommand::Bold(false));
+                }
+            }
+
+            if let Some(field) = &element.running_total {
+                running_total += row
+                    .get(field)
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .unwrap_or(0.0);
+            }
+            let row = LoopRow {
+                row,
+                index,
+                is_last: rows.peek().is_none(),
+                running_total: element.running_total.as_ref().map(|_| running_total),
+            };
 
-        for (index, row) in rows.iter().enumerate() {
             // Alternating row background
             if element.alternating_rows.unwrap_or(false) && index % 2 == 1 {
                 commands.push(PrintCommand::Reverse(true));
             }
 
-            let row_line = self.format_table_row(&element.columns, Some(row));
-            commands.push(PrintCommand::WriteLine(row_line));
+            for line in self.format_table_row(&element.columns, column_widths, Some(row)) {
+                commands.push(PrintCommand::WriteLine(line));
+            }
+            for (i, col) in element.columns.iter().enumerate() {
+                let value = row.get(&col.field);
+                for totals in [&mut column_totals, &mut group_totals] {
+                    if let Some(totals) = totals.get_mut(i) {
+                        totals.add(value.as_deref());
+                    }
+                }
+            }
 
             if element.alternating_rows.unwrap_or(false) && index % 2 == 1 {
                 commands.push(PrintCommand::Reverse(false));
@@ -931,12 +2625,17 @@ impl TemplateRenderer {
 
             // Print row details if configured
             if let Some(details) = &element.row_details {
-                for detail in details {
+                for (detail, condition) in details.iter().zip(detail_conditions) {
                     if let Some(value) = row.get(&detail.field) {
-                        // Check condition
-                        if detail.condition.is_some() {
-                            // Skip if condition not met (simplified)
-                            if value.is_empty() {
+                        // Row fields come first, then the receipt's variables;
+                        // `item.` names a row field explicitly
+                        if let Some(condition) = condition {
+                            let holds = self.evaluate_condition_with(condition, data, |name| {
+                                row.get(name.strip_prefix("item.").unwrap_or(name))
+                                    .map(Cow::into_owned)
+                                    .unwrap_or_else(|| self.get_variable_value(name, data))
+                            });
+                            if !holds {
                                 continue;
                             }
                         }
@@ -972,15 +2671,66 @@ impl TemplateRenderer {
                     }
                 }
             }
+
+            if let Some(field) = element.group_by.as_deref() {
+                group_starts = match rows.peek() {
+                    Some(next) => group_key(*next, field) != group_key(row.row, field),
+                    None => true,
+                };
+                if group_starts {
+                    if let Some(subtotal) = &element.group_subtotal {
+                        self.build_footer_row(
+                            commands,
+                            element,
+                            column_widths,
+                            subtotal,
+                            &group_totals,
+                        );
+                        group_totals.fill(ColumnTotals::default());
+                    }
+                }
+            }
+
+            index += 1;
+        }
+
+        if !footer_rows.is_empty() {
+            if element.footer_divider.unwrap_or(true) {
+                let divider = "-".repeat(self.content_width());
+                commands.push(PrintCommand::WriteLine(divider));
+            }
+            for footer in footer_rows {
+                self.build_footer_row(commands, element, column_widths, footer, &column_totals);
+            }
         }
 
         Ok(())
     }
 
+    /// Print a footer row or group subtotal
+    fn build_footer_row(
+        &self,
+        commands: &mut dyn CommandSink,
+        element: &TableElement,
+        column_widths: &[usize],
+        footer: &TableFooterRow,
+        totals: &[ColumnTotals],
+    ) {
+        let bold = footer.bold.unwrap_or(false);
+        if bold {
+            commands.push(PrintCommand::Bold(true));
+        }
+        let line = self.format_footer_row(&element.columns, column_widths, footer, totals);
+        commands.push(PrintCommand::WriteLine(line));
+        if bold {
+            commands.push(PrintCommand::Bold(false));
+        }
+    }
+
     /// Build modifier command
     fn build_modifier_command(
         &self,
-        commands: &mut Vec<PrintCommand>,
+        commands: &mut dyn CommandSink,
         modifier: &str,
         config: &ModifierConfig,
     ) -> Result<(), String> {
@@ -1007,8 +2757,10 @@ impl TemplateRenderer {
     /// Build box element commands
     fn build_box_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
+        commands: &mut dyn CommandSink,
         element: &BoxElement,
+        border_line: &str,
+        children: &[CompiledNode],
         data: &ReceiptData,
     ) -> Result<(), String> {
         let style = element.style.as_deref().unwrap_or("default");
@@ -1031,10 +2783,7 @@ impl TemplateRenderer {
         if border > 0 {
             let border_positions = element.border_position.as_deref().unwrap_or("all");
             if border_positions.contains("top") || border_positions == "all" {
-                // Use the same 6-character safety margin as dividers
-                let width = (self.paper_width as usize).saturating_sub(6);
-                let border_line = "=".repeat(width);
-                commands.push(PrintCommand::WriteLine(border_line));
+                commands.push(PrintCommand::WriteLine(border_line.to_string()));
             }
         }
 
@@ -1044,8 +2793,8 @@ impl TemplateRenderer {
         }
 
         // Render inner elements
-        for inner_elem in &element.elements {
-            self.build_element_commands(commands, inner_elem, data)?;
+        for child in children {
+            self.build_element_commands(commands, child, data)?;
         }
 
         // Bottom padding
@@ -1060,10 +2809,7 @@ impl TemplateRenderer {
                 || border_positions == "all"
                 || border_positions == "top-bottom"
             {
-                // Use the same 6-character safety margin as dividers
-                let width = (self.paper_width as usize).saturating_sub(6);
-                let border_line = "=".repeat(width);
-                commands.push(PrintCommand::WriteLine(border_line));
+                commands.push(PrintCommand::WriteLine(border_line.to_string()));
             }
         }
 
@@ -1075,35 +2821,262 @@ impl TemplateRenderer {
         Ok(())
     }
 
+    /// Build repeat element commands: the children once per entry of the
+    /// data source, with the entry as `item` and its place as `index`
+    fn build_repeat_commands(
+        &self,
+        commands: &mut dyn CommandSink,
+        element: &RepeatElement,
+        children: &[CompiledNode],
+        data: &ReceiptData,
+    ) -> Result<(), String> {
+        let source = element.data_source.as_str();
+        let entries: Box<dyn Iterator<Item = serde_json::Value>> = match source {
+            "items" => Box::new(data.items.iter().map(item_value)),
+            _ => match lookup_path(&data.custom, source) {
+                Ok(serde_json::Value::Array(arr)) => Box::new(arr.iter().cloned()),
+                Ok(_) => Box::new(std::iter::empty()),
+                Err(missing) => {
+                    log::warn!(
+                        "Repeat source `{}` is empty: `{}` is not in the data",
+                        source,
+                        missing
+                    );
+                    Box::new(std::iter::empty())
+                }
+            },
+        };
+
+        // The children read the entry from a copy of the data, so nested
+        // repeats see their own `item`
+        let mut scoped = data.clone();
+        for (index, entry) in entries.enumerate() {
+            self.checkpoint(commands)?;
+            scoped.custom.insert("item".to_string(), entry);
+            scoped
+                .custom
+                .insert("index".to_string(), (index + 1).into());
+            for child in children {
+                self.build_element_commands(commands, child, &scoped)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build kitchen item commands
+    fn build_kitchen_items_commands(
+        &self,
+        commands: &mut dyn CommandSink,
+        element: &KitchenItemsElement,
+        data: &ReceiptData,
+    ) -> Result<(), String> {
+        let size = element.font_size.unwrap_or(2).clamp(1, 8);
+        let item_width = self.effective_width(width_multiplier(size));
+        let modifier_width = self.effective_width(1);
+        // A ticket for one course or seat is headed by the template, if at all
+        let group_by = element.group_by.unwrap_or_default();
+        let (ticket_group, label) = match group_by {
+            ItemGroup::Course => (&data.course, ""),
+            ItemGroup::Seat => (&data.seat, "Seat "),
+        };
+        let headers = element.show_courses.unwrap_or(true) && ticket_group.is_none();
+
+        for (group, items) in item_groups(&data.items, group_by) {
+            if let Some(group) = group.filter(|_| headers) {
+                commands.push(PrintCommand::Align("center".to_string()));
+                commands.push(PrintCommand::Bold(true));
+                commands.push(PrintCommand::WriteLine(format!("-- {}{} --", label, group)));
+                commands.push(PrintCommand::Bold(false));
+                commands.push(PrintCommand::Align("left".to_string()));
+            }
+
+            for item in items {
+                self.checkpoint(commands)?;
+                let line = match item.quantity {
+                    0 => item.name.clone(),
+                    quantity => format!("{}x {}", quantity, item.name),
+                };
+                commands.push(PrintCommand::Size(size, size));
+                commands.push(PrintCommand::Bold(true));
+                for line in wrap_text(&line, item_width) {
+                    commands.push(PrintCommand::WriteLine(line));
+                }
+
+                // Modifiers and the note stay bold, at full height so they
+                // aren't missed
+                commands.push(PrintCommand::Size(1, size));
+                let modifiers = item.modifiers.iter().flatten().map(|m| ("  > ", m));
+                // Wrapped lines line up under the first, past its marker
+                for (marker, text) in modifiers.chain(item.note.iter().map(|n| ("  ! ", n))) {
+                    let lines = wrap_text(text, modifier_width.saturating_sub(4));
+                    for (i, line) in lines.into_iter().enumerate() {
+                        let marker = if i == 0 { marker } else { "    " };
+                        commands.push(PrintCommand::WriteLine(format!("{}{}", marker, line)));
+                    }
+                }
+                commands.push(PrintCommand::Bold(false));
+                commands.push(PrintCommand::Size(1, 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build tax summary commands
+    fn build_tax_summary_commands(
+        &self,
+        commands: &mut dyn CommandSink,
+        element: &TaxSummaryElement,
+        data: &ReceiptData,
+    ) -> Result<(), String> {
+        if data.taxes.is_empty() {
+            return Ok(());
+        }
+
+        let prefix = element.prefix.as_deref().unwrap_or("");
+        let money = |value: f64| format!("{}{:.2}", prefix, value);
+        let header = element.show_header.unwrap_or(true);
+        let mut rows = Vec::new();
+        if header {
+            rows.push(("Rate".to_string(), "Base".to_string(), "Tax".to_string()));
+        }
+        rows.extend(data.taxes.iter().map(|tax| {
+            let label = format!("{} {}%", tax.name, tax.rate);
+            (label, money(tax.base), money(tax.amount))
+        }));
+        if data.taxes.len() > 1 {
+            let base = data.taxes.iter().map(|tax| tax.base).sum();
+            let amount = data.taxes.iter().map(|tax| tax.amount).sum();
+            rows.push(("Total".to_string(), money(base), money(amount)));
+        }
+
+        let lines = self.amount_table(&rows);
+        let (head, body) = lines.split_at(header as usize);
+        let (body, total) = body.split_at(data.taxes.len());
+        commands.push(PrintCommand::Align("left".to_string()));
+        self.push_amount_lines(commands, head, true)?;
+        self.push_amount_lines(commands, body, false)?;
+        self.push_amount_lines(commands, total, true)
+    }
+
+    /// Build tip suggestion commands
+    fn build_tip_suggestions_commands(
+        &self,
+        commands: &mut dyn CommandSink,
+        element: &TipSuggestionsElement,
+        data: &ReceiptData,
+    ) -> Result<(), String> {
+        let base = match element.base.unwrap_or_default() {
+            TipBase::Subtotal => data.subtotal,
+            TipBase::Total => data.total,
+        };
+        if base <= 0.0 {
+            return Ok(());
+        }
+
+        let prefix = element.prefix.as_deref().unwrap_or("");
+        let money = |value: f64| format!("{}{:.2}", prefix, value);
+        let percentages = element
+            .percentages
+            .as_deref()
+            .unwrap_or(&[15.0, 18.0, 20.0]);
+        let mut rows = vec![(String::new(), "Tip".to_string(), "Total".to_string())];
+        // Percentages out of range, or tips too big to print, are left out
+        rows.extend(
+            percentages
+                .iter()
+                .filter(|percent| (0.0..=100.0).contains(*percent))
+                .map(|percent| (percent, base * percent / 100.0))
+                .filter(|(_, tip)| (data.total + tip).is_finite())
+                .map(|(percent, tip)| {
+                    (format!("{}%", percent), money(tip), money(data.total + tip))
+                }),
+        );
+        if rows.len() == 1 {
+            return Ok(());
+        }
+
+        let lines = self.amount_table(&rows);
+        let (head, body) = lines.split_at(1);
+        let title = element.title.as_deref().unwrap_or("Suggested Tip");
+        commands.push(PrintCommand::Align("center".to_string()));
+        commands.push(PrintCommand::Bold(true));
+        commands.push(PrintCommand::WriteLine(title.to_string()));
+        commands.push(PrintCommand::Bold(false));
+        commands.push(PrintCommand::Align("left".to_string()));
+        self.push_amount_lines(commands, head, true)?;
+        self.push_amount_lines(commands, body, false)
+    }
+
+    /// `rows` of a label and two amounts, the amounts right-aligned in
+    /// columns that fit the widest of them
+    fn amount_table(&self, rows: &[(String, String, String)]) -> Vec<String> {
+        let column = rows
+            .iter()
+            .flat_map(|(_, first, second)| [text_width(first), text_width(second)])
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let label_width = self.content_width().saturating_sub(2 * column);
+        rows.iter()
+            .map(|(label, first, second)| {
+                let (label, _) = truncate_columns(label, label_width);
+                format!(
+                    "{}{}{}",
+                    padded(label, label_width),
+                    padded_left(first, column),
+                    padded_left(second, column)
+                )
+            })
+            .collect()
+    }
+
+    fn push_amount_lines(
+        &self,
+        commands: &mut dyn CommandSink,
+        lines: &[String],
+        bold: bool,
+    ) -> Result<(), String> {
+        let bold = bold && !lines.is_empty();
+        if bold {
+            commands.push(PrintCommand::Bold(true));
+        }
+        for line in lines {
+            self.checkpoint(commands)?;
+            commands.push(PrintCommand::WriteLine(line.clone()));
+        }
+        if bold {
+            commands.push(PrintCommand::Bold(false));
+        }
+        Ok(())
+    }
+
     /// Build grid element commands
     fn build_grid_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
+        commands: &mut dyn CommandSink,
         element: &GridElement,
+        values: &[TextPattern],
+        col_width: Option<usize>,
         data: &ReceiptData,
     ) -> Result<(), String> {
+        let col_width =
+            col_width.ok_or_else(|| "Grid element must have at least one column".to_string())?;
         let col_count = element.columns as usize;
         let gap = element.gap.unwrap_or(0) as usize;
-        let col_width = (self.paper_width as usize - (col_count - 1) * gap) / col_count;
 
         // Process items in pairs based on column count
-        for chunk in element.data.chunks(col_count) {
+        for (chunk, chunk_values) in element.data.chunks(col_count).zip(values.chunks(col_count)) {
             let mut line = String::new();
 
-            for (i, item) in chunk.iter().enumerate() {
-                let label_value = format!(
-                    "{}: {}",
-                    item.label,
-                    self.substitute_variables(&item.value, data)
-                );
-
-                let formatted = if label_value.len() > col_width {
-                    label_value[..col_width].to_string()
-                } else {
-                    format!("{:<width$}", label_value, width = col_width)
-                };
+            for (i, (item, value)) in chunk.iter().zip(chunk_values).enumerate() {
+                let label_value = format!("{}: {}", item.label, self.render_text(value, data));
 
-                line.push_str(&formatted);
+                // Truncate by columns, not bytes, so multibyte labels can't split a char
+                let (formatted, formatted_width) = truncate_columns(&label_value, col_width);
+                line.push_str(formatted);
+                push_spaces(&mut line, col_width - formatted_width);
 
                 if i < chunk.len() - 1 {
                     line.push_str(&" ".repeat(gap));
@@ -1116,22 +3089,26 @@ impl TemplateRenderer {
         Ok(())
     }
 
+    /// Column width for a grid, or `None` if it has no columns
+    pub(crate) fn grid_column_width(&self, element: &GridElement) -> Option<usize> {
+        let col_count = element.columns as usize;
+        if col_count == 0 {
+            return None;
+        }
+        let gap = element.gap.unwrap_or(0) as usize;
+        Some((self.paper_width as usize).saturating_sub((col_count - 1) * gap) / col_count)
+    }
+
     /// Build bar chart commands (ASCII representation)
     fn build_bar_chart_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
+        commands: &mut dyn CommandSink,
         element: &BarChartElement,
         data: &ReceiptData,
     ) -> Result<(), String> {
-        let rows = self.get_data_source_items(&element.data_source, data);
-
-        if rows.is_empty() {
-            return Ok(());
-        }
-
-        // Find max value
-        let max_value: f64 = rows
-            .iter()
+        // Find max value (also covers an empty data source)
+        let max_value: f64 = self
+            .data_source_rows(&element.data_source, data)
             .filter_map(|row| row.get(&element.value_field))
             .filter_map(|v| v.parse::<f64>().ok())
             .fold(0.0, f64::max);
@@ -1142,7 +3119,9 @@ impl TemplateRenderer {
 
         let chart_width = (self.paper_width as usize).saturating_sub(15); // Leave room for labels and safety margin
 
-        for row in &rows {
+        for row in self.data_source_rows(&element.data_source, data) {
+            self.checkpoint(commands)?;
+
             if let Some(value_str) = row.get(&element.value_field) {
                 if let Ok(value) = value_str.parse::<f64>() {
                     let bar_length = ((value / max_value) * chart_width as f64) as usize;
@@ -1152,14 +3131,13 @@ impl TemplateRenderer {
                     let label = row
                         .get("hour")
                         .or_else(|| row.get("label"))
-                        .cloned()
                         .unwrap_or_default();
 
-                    let label = if label.len() > 5 { &label[0..5] } else { &label };
+                    let (label, _) = truncate_columns(&label, 5);
                     
                     // Print label and separator without newline
                      commands.push(PrintCommand::Align("left".to_string()));
-                     commands.push(PrintCommand::Write(format!("{:>5} |", label)));
+                     commands.push(PrintCommand::Write(format!("{} |", padded_left(label, 5))));
                      
                      // Print the black bar on the same line using reverse mode
                      commands.push(PrintCommand::Reverse(true));
@@ -1175,29 +3153,137 @@ impl TemplateRenderer {
         Ok(())
     }
 
-    /// Build leaderboard commands
-    fn build_leaderboard_commands(
+    /// Build breakdown commands
+    fn build_breakdown_commands(
         &self,
-        commands: &mut Vec<PrintCommand>,
-        element: &LeaderboardElement,
+        commands: &mut dyn CommandSink,
+        element: &BreakdownElement,
         data: &ReceiptData,
     ) -> Result<(), String> {
-        let rows = self.get_data_source_items(&element.data_source, data);
-        let highlight_top = element.highlight_top.unwrap_or(0);
-        
-        // Use the same 6-character safety margin as dividers
-        let width = (self.paper_width as usize).saturating_sub(6);
+        // Entries without a number are left out, negative ones count as none
+        let entries: Vec<(String, f64)> = self
+            .data_source_rows(&element.data_source, data)
+            .filter_map(|row| {
+                let value = row.get(&element.value_field)?.trim().parse::<f64>().ok()?;
+                let label = row.get(&element.label_field).unwrap_or_default();
+                Some((label.into_owned(), value.max(0.0)))
+            })
+            .collect();
+        let total: f64 = entries.iter().map(|(_, value)| value).sum();
+        if total == 0.0 {
+            return Ok(());
+        }
 
-        for (index, row) in rows.iter().enumerate() {
-            let rank = row.get(&element.fields.rank).cloned().unwrap_or_default();
-            let name = row.get(&element.fields.name).cloned().unwrap_or_default();
+        let width = self.content_width();
+        let prefix = element.prefix.as_deref().unwrap_or("");
+        commands.push(PrintCommand::Align("left".to_string()));
+        for (label, value) in entries {
+            self.checkpoint(commands)?;
+            let share = value / total;
+            let percent = format!("{:.0}%", share * 100.0);
+            let amount = format!("{}{:.2} {:>4}", prefix, value, percent);
+            let label_width = width.saturating_sub(text_width(&amount) + 1);
+            let (label, _) = truncate_columns(&label, label_width);
+            commands.push(PrintCommand::WriteLine(format!(
+                "{} {}",
+                padded(label, label_width),
+                amount
+            )));
+
+            if element.show_bars.unwrap_or(true) {
+                let bar_length = (share * width as f64).round() as usize;
+                if bar_length > 0 {
+                    commands.push(PrintCommand::Reverse(true));
+                    commands.push(PrintCommand::Write(" ".repeat(bar_length)));
+                    commands.push(PrintCommand::Reverse(false));
+                }
+                commands.push(PrintCommand::WriteLine(String::new()));
+            }
+        }
 
-            let shift = element
-                .fields
-                .shift
+        Ok(())
+    }
+
+    /// Build coupon commands
+    fn build_coupon_commands(
+        &self,
+        commands: &mut dyn CommandSink,
+        element: &CouponElement,
+        content: &TextPattern,
+        code: Option<&CompiledElement>,
+        expiry: &Option<TextPattern>,
+        data: &ReceiptData,
+    ) -> Result<(), String> {
+        let width = self.content_width();
+        commands.push(PrintCommand::Align("left".to_string()));
+        if element.cut.unwrap_or(false) {
+            // Past the cutter, which sits a few lines above the print head
+            commands.push(PrintCommand::Feed(6));
+            commands.push(PrintCommand::Cut);
+        } else {
+            let tear_line = format!("8<{}", " -".repeat(width.saturating_sub(2) / 2));
+            commands.push(PrintCommand::WriteLine(tear_line));
+        }
+        commands.push(PrintCommand::Feed(1));
+
+        // The offer, centred between side borders
+        let inner = width.saturating_sub(4);
+        let border = format!("+{}+", "-".repeat(width.saturating_sub(2)));
+        commands.push(PrintCommand::WriteLine(border.clone()));
+        commands.push(PrintCommand::Bold(true));
+        for line in self.render_text(content, data).split('\n') {
+            for line in wrap_text(line, inner) {
+                // A wide character can be wider than the box on narrow paper
+                let space = inner.saturating_sub(text_width(&line));
+                let left = space / 2;
+                let mut boxed = "| ".to_string();
+                push_spaces(&mut boxed, left);
+                boxed.push_str(&line);
+                push_spaces(&mut boxed, space - left);
+                boxed.push_str(" |");
+                commands.push(PrintCommand::WriteLine(boxed));
+            }
+        }
+        commands.push(PrintCommand::Bold(false));
+        commands.push(PrintCommand::WriteLine(border));
+
+        if let Some(code) = code {
+            commands.push(PrintCommand::Feed(1));
+            self.build_compiled_element(commands, code, data)?;
+        }
+        if let Some(expiry) = expiry {
+            commands.push(PrintCommand::Align("center".to_string()));
+            let expiry = self.render_text(expiry, data).into_owned();
+            commands.push(PrintCommand::WriteLine(expiry));
+            commands.push(PrintCommand::Align("left".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Build leaderboard commands
+    fn build_leaderboard_commands(
+        &self,
+        commands: &mut dyn CommandSink,
+        element: &LeaderboardElement,
+        data: &ReceiptData,
+    ) -> Result<(), String> {
+        let highlight_top = element.highlight_top.unwrap_or(0);
+        
+        // Use the same 6-character safety margin as dividers
+        let width = self.content_width();
+
+        for (index, row) in self.data_source_rows(&element.data_source, data).enumerate() {
+            self.checkpoint(commands)?;
+
+            let rank = row.get(&element.fields.rank).unwrap_or_default();
+            let name = row.get(&element.fields.name).unwrap_or_default();
+
+            let shift = element
+                .fields
+                .shift
                 .as_ref()
                 .and_then(|f| row.get(f))
-                .cloned()
                 .unwrap_or_default();
 
             let sales = element
@@ -1205,7 +3291,6 @@ impl TemplateRenderer {
                 .sales
                 .as_ref()
                 .and_then(|f| row.get(f))
-                .cloned()
                 .unwrap_or_default();
             
             let formatted_sales = format!("${}", sales);
@@ -1221,26 +3306,30 @@ impl TemplateRenderer {
                 // Rank. Name (flex) Sales
                 let rank_part = format!("{:>2}. ", rank);
                 let sales_part = formatted_sales;
-                let used_width = rank_part.chars().count() + sales_part.chars().count();
+                let used_width = text_width(&rank_part) + text_width(&sales_part);
                 let name_width = width.saturating_sub(used_width);
-                format!("{}{:<name_width$}{}", rank_part, name, sales_part, name_width = name_width)
+                format!("{}{}{}", rank_part, padded(&name, name_width), sales_part)
             } else {
                 // Rank. Name (flex) Shift (8) Sales
                 let rank_part = format!("{:>2}. ", rank);
-                let shift_part = format!(" {:>8} ", shift);
+                let shift_part = format!(" {} ", padded_left(&shift, 8));
                 let sales_part = formatted_sales;
-                let used_width = rank_part.chars().count() + shift_part.chars().count() + sales_part.chars().count();
+                let used_width =
+                    text_width(&rank_part) + text_width(&shift_part) + text_width(&sales_part);
                 let name_width = width.saturating_sub(used_width);
-                format!("{}{:<name_width$}{}{}", rank_part, name, shift_part, sales_part, name_width = name_width)
+                format!(
+                    "{}{}{}{}",
+                    rank_part,
+                    padded(&name, name_width),
+                    shift_part,
+                    sales_part
+                )
             };
 
             // Final safety check to ensure we don't exceed the width
-            let entry = if entry.chars().count() > width {
-                entry.chars().take(width).collect()
-            } else {
-                // Pad with spaces to ensure the reverse background covers the full line
-                format!("{:<width$}", entry, width = width)
-            };
+            // Pad with spaces to ensure the reverse background covers the full line
+            let (entry, _) = truncate_columns(&entry, width);
+            let entry = padded(entry, width);
 
             commands.push(PrintCommand::WriteLine(entry));
 
@@ -1253,31 +3342,36 @@ impl TemplateRenderer {
         Ok(())
     }
 
-    /// Format a table row
-    fn format_table_row(
-        &self,
-        columns: &[TableColumn],
-        data: Option<&HashMap<String, String>>,
-    ) -> String {
-        let mut line = String::new();
-        // Consistently use paper_width - 6 for all table elements
-        let total_width = (self.paper_width as usize).saturating_sub(6);
+    /// Resolve column widths for a table.
+    ///
+    /// Uses incremental scaling (error diffusion) so headers and rows always
+    /// use the exact same character positions. In a measured table, columns
+    /// without a width are as wide as their header until the rows are
+    /// measured.
+    pub(crate) fn table_column_widths(&self, columns: &[TableColumn]) -> Vec<usize> {
         let num_columns = columns.len();
-        
-        if num_columns == 0 { return String::new(); }
+        if num_columns == 0 {
+            return Vec::new();
+        }
+        if has_measured_columns(columns) {
+            let headers: Vec<usize> = columns
+                .iter()
+                .map(|col| text_width(col.header.as_deref().unwrap_or(&col.field)))
+                .collect();
+            return self.fit_column_widths(columns, &headers);
+        }
 
         // Spaces between columns
         let spaces_total = num_columns - 1;
-        let usable_width = total_width.saturating_sub(spaces_total);
+        let usable_width = self.content_width().saturating_sub(spaces_total);
 
-        // Use incremental scaling (Error Diffusion) to calculate column widths.
-        // This ensures headers and rows always use the exact same character positions.
         let total_units: f64 = columns.iter()
             .map(|c| c.width.unwrap_or(10) as f64)
             .sum();
 
+        let mut widths = Vec::with_capacity(num_columns);
         let mut current_pos = 0;
-        for (i, col) in columns.iter().enumerate() {
+        for i in 0..num_columns {
             // Calculate the end position for this column based on cumulative units
             let cumulative_units: f64 = columns.iter().take(i + 1).map(|c| c.width.unwrap_or(10) as f64).sum();
             let next_pos = if total_units > 0.0 {
@@ -1285,130 +3379,311 @@ impl TemplateRenderer {
             } else {
                 (i + 1) * (usable_width / num_columns)
             };
-            
-            let width = next_pos.saturating_sub(current_pos).max(1);
+
+            widths.push(next_pos.saturating_sub(current_pos).max(1));
             current_pos = next_pos;
+        }
+        widths
+    }
 
-            let content = if let Some(data) = data {
-                let raw = data.get(&col.field).cloned().unwrap_or_default();
-                // Apply format
-                if let Some(format) = &col.format {
-                    match format.as_str() {
-                        "currency" => {
-                            if let Ok(num) = raw.parse::<f64>() {
-                                format!("${:.2}", num)
-                            } else {
-                                raw
-                            }
-                        }
-                        _ => raw,
-                    }
-                } else {
-                    raw
+    /// Widths for a table with measured columns, given the widest cell of
+    /// each: fixed columns keep their width, measured ones fit their cells
+    /// within `min_width` and `max_width`, giving up characters widest
+    /// first when the line is too short, and `flex` columns take what's left
+    /// over in turn, each up to its `max_width`
+    fn fit_column_widths(&self, columns: &[TableColumn], widest: &[usize]) -> Vec<usize> {
+        let usable_width = self
+            .content_width()
+            .saturating_sub(columns.len().saturating_sub(1));
+        let min_width = |col: &TableColumn| col.min_width.unwrap_or(1).max(1) as usize;
+        let mut widths: Vec<usize> = columns
+            .iter()
+            .zip(widest)
+            .map(|(col, &widest)| match col.width {
+                Some(width) => (width as usize).max(1),
+                None => {
+                    let max_width = col.max_width.map_or(usable_width, |max| max as usize);
+                    widest.min(max_width).max(min_width(col))
                 }
-            } else {
-                col.header.clone().unwrap_or_else(|| col.field.clone())
+            })
+            .collect();
+
+        let mut total: usize = widths.iter().sum();
+        while total > usable_width {
+            let widest = (0..columns.len())
+                .filter(|&i| columns[i].width.is_none() && widths[i] > min_width(&columns[i]))
+                .max_by_key(|&i| widths[i]);
+            let Some(i) = widest else { break };
+            widths[i] -= 1;
+            total -= 1;
+        }
+        // Space no flex column can take is left blank at the end of the line
+        let mut left_over = usable_width.saturating_sub(total);
+        for (i, col) in columns.iter().enumerate() {
+            if !col.flex.unwrap_or(false) {
+                continue;
+            }
+            let room = col
+                .max_width
+                .map_or(left_over, |max| (max as usize).saturating_sub(widths[i]));
+            let extra = room.min(left_over);
+            widths[i] += extra;
+            left_over -= extra;
+        }
+        widths
+    }
+
+    /// The widest cell of each column on this receipt: its header, its
+    /// rows and its footer values
+    fn widest_cells(&self, element: &TableElement, data: &ReceiptData) -> Vec<usize> {
+        let columns = &element.columns;
+        let cell_width = |text: &str| text.split('\n').map(text_width).max().unwrap_or(0);
+        let mut widest: Vec<usize> = columns
+            .iter()
+            .map(|col| cell_width(col.header.as_deref().unwrap_or(&col.field)))
+            .collect();
+
+        let mut rows = self.data_source_rows(&element.data_source, data);
+        if let Some(field) = &element.group_by {
+            rows = Box::new(group_rows(rows, field).into_iter());
+        }
+        let mut rows = rows.peekable();
+        let mut totals = vec![ColumnTotals::default(); columns.len()];
+        let mut running_total = 0.0;
+        let mut index = 0;
+        while let Some(row) = rows.next() {
+            if let Some(field) = &element.running_total {
+                running_total += row
+                    .get(field)
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .unwrap_or(0.0);
+            }
+            let row = LoopRow {
+                row,
+                index,
+                is_last: rows.peek().is_none(),
+                running_total: element.running_total.as_ref().map(|_| running_total),
             };
+            for (i, col) in columns.iter().enumerate() {
+                let value = row.get(&col.field);
+                totals[i].add(value.as_deref());
+                let cell = format_cell(col, value.unwrap_or_default());
+                widest[i] = widest[i].max(cell_width(&cell));
+            }
+            index += 1;
+        }
 
-            // Truncate content to column width BEFORE alignment
-            let content_chars: Vec<char> = content.chars().collect();
-            let content = if content_chars.len() > width {
-                content_chars.iter().take(width).collect::<String>()
-            } else {
-                content
+        for footer in element.footer_rows.iter().flatten() {
+            for (i, col) in columns.iter().enumerate() {
+                let cell = match footer.values.get(&col.field) {
+                    Some(&aggregate) => totals[i]
+                        .get(aggregate)
+                        .map(|value| format_cell(col, Cow::Owned(value)))
+                        .unwrap_or_default(),
+                    None if i == 0 => Cow::Borrowed(footer.label.as_deref().unwrap_or("")),
+                    None => Cow::Borrowed(""),
+                };
+                widest[i] = widest[i].max(cell_width(&cell));
+            }
+        }
+        widest
+    }
+
+    /// Format a table row using widths from `table_column_widths`.
+    /// `None` formats the header row. The row is one line unless a column
+    /// wraps its content, when the lines below carry on under that column.
+    pub(crate) fn format_table_row(
+        &self,
+        columns: &[TableColumn],
+        column_widths: &[usize],
+        row: Option<LoopRow>,
+    ) -> Vec<String> {
+        if columns.is_empty() {
+            return vec![String::new()];
+        }
+
+        // Each cell's content, one entry per line it takes
+        let mut cells: Vec<Vec<String>> = Vec::with_capacity(columns.len());
+        for (col, &width) in columns.iter().zip(column_widths) {
+            let raw: Cow<str> = match row {
+                Some(row) => row.get(&col.field).unwrap_or_default(),
+                None => Cow::Borrowed(col.header.as_deref().unwrap_or(&col.field)),
+            };
+
+            let content = match row {
+                Some(_) => format_cell(col, raw),
+                None => raw,
             };
 
-            let aligned_content = match col.align.as_deref().unwrap_or("left") {
-                "right" => format!("{:>width$}", content, width = width),
-                "center" => format!("{:^width$}", content, width = width),
-                _ => format!("{:<width$}", content, width = width),
+            if col.wrap.unwrap_or(false) && text_width(&content) > width {
+                cells.push(wrap_text(&content, width));
+            } else {
+                cells.push(content.split('\n').map(str::to_string).collect());
+            }
+        }
+
+        let lines = cells.iter().map(Vec::len).max().unwrap_or(1);
+        (0..lines)
+            .map(|line| {
+                let cells = cells
+                    .iter()
+                    .map(|cell| cell.get(line).map_or("", String::as_str));
+                self.format_table_line(columns, column_widths, cells)
+            })
+            .collect()
+    }
+
+    /// A footer row's values under their columns, with its label in the
+    /// first column if that has no value
+    fn format_footer_row(
+        &self,
+        columns: &[TableColumn],
+        column_widths: &[usize],
+        footer: &TableFooterRow,
+        column_totals: &[ColumnTotals],
+    ) -> String {
+        let cells: Vec<Cow<str>> = columns
+            .iter()
+            .zip(column_totals)
+            .enumerate()
+            .map(|(i, (col, totals))| match footer.values.get(&col.field) {
+                Some(TableAggregate::Count) => Cow::Owned(totals.values.to_string()),
+                Some(&aggregate) => totals
+                    .get(aggregate)
+                    .map(|value| format_cell(col, Cow::Owned(value)))
+                    .unwrap_or_default(),
+                None if i == 0 => Cow::Borrowed(footer.label.as_deref().unwrap_or("")),
+                None => Cow::Borrowed(""),
+            })
+            .collect();
+        self.format_table_line(
+            columns,
+            column_widths,
+            cells.iter().map(|cell| cell.as_ref()),
+        )
+    }
+
+    /// Lay one line of cells out in their columns
+    fn format_table_line<'a>(
+        &self,
+        columns: &[TableColumn],
+        column_widths: &[usize],
+        cells: impl Iterator<Item = &'a str>,
+    ) -> String {
+        // Consistently use paper_width - 6 for all table elements
+        let total_width = self.content_width();
+        let num_columns = columns.len();
+
+        let mut line = String::with_capacity(total_width);
+        let mut line_chars = 0;
+
+        for (i, ((col, &width), content)) in
+            columns.iter().zip(column_widths).zip(cells).enumerate()
+        {
+            let aligned;
+            let content = match col.align.as_deref() {
+                Some("decimal") => {
+                    let places = col.decimals.unwrap_or(2) as usize;
+                    aligned = decimal_aligned(content, width, places);
+                    aligned.as_deref().unwrap_or(content)
+                }
+                _ => content,
             };
 
-            line.push_str(&aligned_content);
+            // Truncate content to column width BEFORE alignment
+            let (content, content_width) = truncate_columns(content, width);
+            let padding = width - content_width;
+
+            let (before, after) = match col.align.as_deref().unwrap_or("left") {
+                "right" | "decimal" => (padding, 0),
+                "center" => (padding / 2, padding - padding / 2),
+                _ => (0, padding),
+            };
+            push_spaces(&mut line, before);
+            line.push_str(content);
+            push_spaces(&mut line, after);
+            line_chars += width;
 
             if i < num_columns - 1 {
                 line.push(' ');
+                line_chars += 1;
             }
         }
 
         // Final safety padding to ensure the background covers the full paper_width - 6
-        let current_count = line.chars().count();
-        if current_count < total_width {
-            format!("{:<width$}", line, width = total_width)
-        } else if current_count > total_width {
-            line.chars().take(total_width).collect()
-        } else {
-            line
+        if line_chars < total_width {
+            push_spaces(&mut line, total_width - line_chars);
+        } else if line_chars > total_width {
+            let (truncated, _) = truncate_columns(&line, total_width);
+            line.truncate(truncated.len());
         }
+        line
     }
 
-    /// Get items from a data source
-    fn get_data_source_items(
+    /// Rows of a data source, read in place as they are rendered
+    fn data_source_rows<'a>(
         &self,
         source: &str,
-        data: &ReceiptData,
-    ) -> Vec<HashMap<String, String>> {
+        data: &'a ReceiptData,
+    ) -> Box<dyn Iterator<Item = DataRow<'a>> + 'a> {
         match source {
-            "items" => data
-                .items
-                .iter()
-                .map(|item| self.item_to_map(item))
-                .collect(),
-            _ => {
-                // Try to get from custom fields
-                if let Some(value) = data.custom.get(source) {
-                    if let Some(arr) = value.as_array() {
-                        arr.iter()
-                            .filter_map(|v| {
-                                if let Some(obj) = v.as_object() {
-                                    let mut map = HashMap::new();
-                                    for (k, v) in obj {
-                                        let str_value = match v {
-                                            serde_json::Value::String(s) => s.clone(),
-                                            serde_json::Value::Number(n) => n.to_string(),
-                                            serde_json::Value::Bool(b) => b.to_string(),
-                                            _ => v.to_string(),
-                                        };
-                                        map.insert(k.clone(), str_value);
-                                    }
-                                    Some(map)
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect()
-                    } else {
-                        Vec::new()
+            "items" => Box::new(data.items.iter().map(DataRow::Item)),
+            _ => match lookup_path(&data.custom, source) {
+                Ok(serde_json::Value::Array(arr)) => {
+                    Box::new(arr.iter().filter_map(|v| v.as_object().map(DataRow::Object)))
+                }
+                Ok(_) => Box::new(std::iter::empty()),
+                Err(missing) => {
+                    if source.contains('.') {
+                        log::warn!(
+                            "Data source `{}` is empty: `{}` is not in the data",
+                            source,
+                            missing
+                        );
                     }
-                } else {
-                    Vec::new()
+                    Box::new(std::iter::empty())
                 }
-            }
+            },
         }
     }
 
-    /// Convert item to hashmap for table rendering
-    fn item_to_map(&self, item: &ReceiptItem) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("name".to_string(), item.name.clone());
-        map.insert("quantity".to_string(), item.quantity.to_string());
-        map.insert("price".to_string(), format!("{:.2}", item.price));
-        map.insert("total".to_string(), format!("{:.2}", item.total));
-        if let Some(modifiers) = &item.modifiers {
-            map.insert("modifiers".to_string(), modifiers.join(","));
+    /// Substitute variables into a pre-split content string.
+    /// Content without placeholders is borrowed as is.
+    pub(crate) fn render_text<'p>(
+        &self,
+        pattern: &'p TextPattern,
+        data: &ReceiptData,
+    ) -> Cow<'p, str> {
+        match pattern.segments.as_slice() {
+            [] => Cow::Borrowed(""),
+            [Segment::Literal(text)] => Cow::Borrowed(text),
+            segments => {
+                let mut out = String::new();
+                for segment in segments {
+                    match segment {
+                        Segment::Literal(text) => out.push_str(text),
+                        segment => out.push_str(&self.segment_value(segment, data)),
+                    }
+                }
+                Cow::Owned(out)
+            }
         }
-        map
     }
 
-    /// Substitute variables in text
-    fn substitute_variables(&self, text: &str, data: &ReceiptData) -> String {
-        let re = Regex::new(r"\{\{([a-zA-Z_][a-zA-Z0-9_]*)\}\}").unwrap();
-
-        re.replace_all(text, |caps: &regex::Captures| {
-            let var_name = &caps[1];
-            self.get_variable_value(var_name, data)
-        })
-        .to_string()
+    /// The text a content segment prints, after its filters
+    fn segment_value(&self, segment: &Segment, data: &ReceiptData) -> String {
+        match segment {
+            Segment::Literal(text) => text.clone(),
+            Segment::Variable(name) => self.get_variable_value(name, data),
+            Segment::Expr(expr) => expr
+                .eval(&self.scope(data, |name| self.get_variable_value(name, data)))
+                .to_string(),
+            Segment::Filtered { value, filters } => filters
+                .iter()
+                .fold(self.segment_value(value, data), |value, filter| {
+                    filter.apply(value)
+                }),
+        }
     }
 
     /// Get variable value from data
@@ -1424,6 +3699,8 @@ impl TemplateRenderer {
                 .unwrap_or_default(),
             "order_id" => data.order_id.clone(),
             "timestamp" => data.timestamp.clone(),
+            "receipt_type" => data.receipt_type.as_str().to_string(),
+            "original_order_id" => data.original_order_id.clone().unwrap_or_default(),
             "date" => data.date.clone().unwrap_or_else(|| {
                 // Fallback: extract date from timestamp
                 data.timestamp
@@ -1443,6 +3720,10 @@ impl TemplateRenderer {
             "cashier_name" => data.cashier_name.clone().unwrap_or_default(),
             "server_name" => data.server_name.clone().unwrap_or_default(),
             "table_number" => data.table_number.clone().unwrap_or_default(),
+            "order_type" => data.order_type.clone().unwrap_or_default(),
+            "course" => data.course.clone().unwrap_or_default(),
+            "seat" => data.seat.clone().unwrap_or_default(),
+            "copy_label" => data.copy_label.clone().unwrap_or_default(),
             "subtotal" => format!("{:.2}", data.subtotal),
             "tax" => format!("{:.2}", data.tax),
             "tax_rate" => data
@@ -1474,87 +3755,576 @@ impl TemplateRenderer {
             "footer_message" => data.footer_message.clone().unwrap_or_default(),
             "farewell_message" => data.farewell_message.clone().unwrap_or_default(),
             "receipt_url" => data.receipt_url.clone().unwrap_or_default(),
+            "items.length" => data.items.len().to_string(),
+            _ if name.starts_with("items.") => item_field(data, name).unwrap_or_default(),
             _ => {
-                // Try custom fields
-                if let Some(value) = data.custom.get(name) {
-                    match value {
+                // Try custom fields, following dotted paths
+                match lookup_path(&data.custom, name) {
+                    Ok(value) => match value {
                         serde_json::Value::String(s) => s.clone(),
                         serde_json::Value::Number(n) => n.to_string(),
                         serde_json::Value::Bool(b) => b.to_string(),
                         serde_json::Value::Null => String::new(),
                         _ => value.to_string().trim_matches('"').to_string(),
-                    }
-                } else {
-                    String::new()
+                    },
+                    // `rows.length` counts an array that has no `length` field
+                    Err(_) => match name.strip_suffix(".length") {
+                        Some(array) => match lookup_path(&data.custom, array) {
+                            Ok(serde_json::Value::Array(arr)) => arr.len().to_string(),
+                            _ => String::new(),
+                        },
+                        None => String::new(),
+                    },
                 }
             }
         }
     }
 
-    /// Evaluate simple conditions
-    fn should_render(&self, condition: &Option<String>, data: &ReceiptData) -> bool {
-        if let Some(cond) = condition {
-            self.evaluate_condition(cond, data)
-        } else {
-            true
+    /// Evaluate a parsed condition; elements without one always render
+    fn should_render(&self, condition: &Option<Condition>, data: &ReceiptData) -> bool {
+        match condition {
+            Some(condition) => self.evaluate_condition(condition, data),
+            None => true,
         }
     }
 
     /// Simple condition evaluator
-    fn evaluate_condition(&self, condition: &str, data: &ReceiptData) -> bool {
-        // Handle comparison operators
-        if condition.contains(">") {
-            let parts: Vec<&str> = condition.split(">").map(|s| s.trim()).collect();
-            if parts.len() == 2 {
-                let var_value = self.get_variable_value(parts[0], data);
-                if let Ok(num) = var_value.parse::<f64>() {
-                    if let Ok(threshold) = parts[1].parse::<f64>() {
-                        return num > threshold;
-                    }
+    fn evaluate_condition(&self, condition: &Condition, data: &ReceiptData) -> bool {
+        self.evaluate_condition_with(condition, data, |name| self.get_variable_value(name, data))
+    }
+
+    /// Evaluate a condition, reading variables through `value`
+    fn evaluate_condition_with(
+        &self,
+        condition: &Condition,
+        data: &ReceiptData,
+        value: impl Fn(&str) -> String,
+    ) -> bool {
+        match condition {
+            Condition::Test(expr) => expr.eval(&self.scope(data, value)).is_true(),
+            Condition::Always => true,
+        }
+    }
+
+    /// Where expressions read variables through `variable` and aggregate
+    /// the data sources of `data`
+    fn scope<'a, F: Fn(&str) -> String>(
+        &'a self,
+        data: &'a ReceiptData,
+        variable: F,
+    ) -> DataScope<'a, F> {
+        DataScope {
+            renderer: self,
+            data,
+            variable,
+        }
+    }
+
+    /// `function` over the rows of a data source. `path` is the data source
+    /// and then the field, as in `items.total`, or only the data source for
+    /// `count`.
+    fn aggregate(&self, function: TableAggregate, path: &str, data: &ReceiptData) -> String {
+        let whole_source = path == "items"
+            || matches!(
+                lookup_path(&data.custom, path),
+                Ok(serde_json::Value::Array(_))
+            );
+        let (source, field) = match path.rsplit_once('.') {
+            Some((source, field)) if !whole_source => (source, Some(field)),
+            _ => (path, None),
+        };
+
+        let rows = self.data_source_rows(source, data);
+        match field {
+            Some(field) => {
+                let mut totals = ColumnTotals::default();
+                for row in rows {
+                    totals.add(row.get(field).as_deref());
                 }
+                totals.get(function).unwrap_or_default()
             }
-        } else if condition.contains("!=") {
-            let parts: Vec<&str> = condition.split("!=").map(|s| s.trim()).collect();
-            if parts.len() == 2 {
-                let var_value = self.get_variable_value(parts[0], data);
-                let compare_value = parts[1].trim_matches('"').trim_matches('\'');
-                if compare_value == "null" {
-                    return !var_value.is_empty();
-                }
-                return var_value != compare_value;
-            }
-        } else if condition.contains("==") {
-            let parts: Vec<&str> = condition.split("==").map(|s| s.trim()).collect();
-            if parts.len() == 2 {
-                let var_value = self.get_variable_value(parts[0], data);
-                let compare_value = parts[1].trim_matches('"').trim_matches('\'');
-                if compare_value == "true" {
-                    return var_value == "true" || var_value == "1";
-                } else if compare_value == "false" {
-                    return var_value == "false" || var_value == "0" || var_value.is_empty();
-                }
-                return var_value == compare_value;
-            }
-        } else if condition.contains(".length") {
-            // Handle array length conditions like "items.length > 0"
-            let parts: Vec<&str> = condition.split(">").map(|s| s.trim()).collect();
-            if parts.len() == 2 {
-                let array_name = parts[0].trim_end_matches(".length");
-                let items = self.get_data_source_items(array_name, data);
-                if let Ok(threshold) = parts[1].parse::<usize>() {
-                    return items.len() > threshold;
-                }
+            None if function == TableAggregate::Count => rows.count().to_string(),
+            None => String::new(),
+        }
+    }
+}
+
+/// Variables read through a function, and aggregates over a receipt's
+/// data sources
+struct DataScope<'a, F> {
+    renderer: &'a TemplateRenderer,
+    data: &'a ReceiptData,
+    variable: F,
+}
+
+impl<F: Fn(&str) -> String> Scope for DataScope<'_, F> {
+    fn variable(&self, name: &str) -> String {
+        (self.variable)(name)
+    }
+
+    fn aggregate(&self, function: TableAggregate, path: &str) -> String {
+        self.renderer.aggregate(function, path, self.data)
+    }
+}
+
+/// One row of a table, chart or leaderboard data source, read field by
+/// field without copying the row
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum DataRow<'a> {
+    Item(&'a ReceiptItem),
+    Object(&'a serde_json::Map<String, serde_json::Value>),
+}
+
+impl<'a> DataRow<'a> {
+    /// A field as display text; `None` if the row doesn't have it
+    pub(crate) fn get(&self, field: &str) -> Option<Cow<'a, str>> {
+        match self {
+            DataRow::Item(item) => match field {
+                "name" => Some(Cow::Borrowed(item.name.as_str())),
+                "quantity" => Some(Cow::Owned(item.quantity.to_string())),
+                "price" => Some(Cow::Owned(format!("{:.2}", item.price))),
+                "total" => Some(Cow::Owned(format!("{:.2}", item.total))),
+                "modifiers" => item
+                    .modifiers
+                    .as_ref()
+                    .map(|modifiers| Cow::Owned(modifiers.join(","))),
+                "sku" => item.sku.as_deref().map(Cow::Borrowed),
+                "category" => item.category.as_deref().map(Cow::Borrowed),
+                "discount" => item.discount.map(|d| Cow::Owned(format!("{:.2}", d))),
+                "tax" => item.tax.map(|t| Cow::Owned(format!("{:.2}", t))),
+                "unit" => item.unit.as_deref().map(Cow::Borrowed),
+                "course" => item.course.as_deref().map(Cow::Borrowed),
+                "note" => item.note.as_deref().map(Cow::Borrowed),
+                "seat" => item.seat.as_deref().map(Cow::Borrowed),
+                _ => None,
+            },
+            DataRow::Object(obj) => obj.get(field).map(|value| match value {
+                serde_json::Value::String(s) => Cow::Borrowed(s.as_str()),
+                serde_json::Value::Number(n) => Cow::Owned(n.to_string()),
+                serde_json::Value::Bool(b) => Cow::Owned(b.to_string()),
+                _ => Cow::Owned(value.to_string()),
+            }),
+        }
+    }
+}
+
+/// `items.<n>.<field>`: a field of a receipt item, formatted the way
+/// tables print it
+fn item_field(data: &ReceiptData, path: &str) -> Option<String> {
+    let (index, field) = path.strip_prefix("items.")?.split_once('.')?;
+    let item = data.items.get(index.parse::<usize>().ok()?)?;
+    DataRow::Item(item).get(field).map(Cow::into_owned)
+}
+
+/// A receipt item as a repeat element's `item`, its amounts formatted the
+/// way tables print them
+fn item_value(item: &ReceiptItem) -> serde_json::Value {
+    let row = DataRow::Item(item);
+    let mut fields: serde_json::Map<String, serde_json::Value> = [
+        "name", "quantity", "price", "total", "sku", "category", "discount", "tax", "unit",
+        "course", "note", "seat",
+    ]
+    .into_iter()
+    .filter_map(|field| Some((field.to_string(), row.get(field)?.into_owned().into())))
+    .collect();
+    if let Some(modifiers) = &item.modifiers {
+        fields.insert("modifiers".to_string(), modifiers.clone().into());
+    }
+    serde_json::Value::Object(fields)
+}
+
+/// Follow a dotted path such as `stats.hourly` through the custom fields.
+/// A key that itself contains dots is matched first; numeric segments index
+/// into arrays (`days.0.hourly`). On failure, returns the part of the path
+/// that isn't in the data.
+fn lookup_path<'a>(
+    custom: &'a HashMap<String, serde_json::Value>,
+    path: &str,
+) -> Result<&'a serde_json::Value, String> {
+    if let Some(value) = custom.get(path) {
+        return Ok(value);
+    }
+
+    let mut segments = path.split('.');
+    let first = segments.next().unwrap_or_default();
+    let mut value = custom.get(first).ok_or_else(|| first.to_string())?;
+    let mut end = first.len();
+    for segment in segments {
+        end += 1 + segment.len();
+        let next = match value {
+            serde_json::Value::Object(obj) => obj.get(segment),
+            serde_json::Value::Array(arr) => {
+                segment.parse::<usize>().ok().and_then(|index| arr.get(index))
+            }
+            _ => None,
+        };
+        value = next.ok_or_else(|| path[..end].to_string())?;
+    }
+    Ok(value)
+}
+
+/// A table row with its place in the loop. Besides the row's own fields it
+/// has `_index` (1-based), `_is_first`, `_is_last` and, when the table names
+/// a `running_total` field, `_running_total`. These take precedence over
+/// payload fields of the same name.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LoopRow<'a> {
+    pub(crate) row: DataRow<'a>,
+    /// 0-based position in the data source
+    pub(crate) index: usize,
+    pub(crate) is_last: bool,
+    pub(crate) running_total: Option<f64>,
+}
+
+impl<'a> LoopRow<'a> {
+    pub(crate) fn get(&self, field: &str) -> Option<Cow<'a, str>> {
+        match field {
+            "_index" => Some(Cow::Owned((self.index + 1).to_string())),
+            "_is_first" => Some(Cow::Borrowed(if self.index == 0 { "true" } else { "false" })),
+            "_is_last" => Some(Cow::Borrowed(if self.is_last { "true" } else { "false" })),
+            "_running_total" => self
+                .running_total
+                .map(|total| Cow::Owned(format!("{:.2}", total))),
+            _ => self.row.get(field),
+        }
+    }
+}
+
+/// Whether the table is sized to its cells: some column sets `min_width`,
+/// `max_width` or `flex`. Otherwise widths are shares of the line.
+fn has_measured_columns(columns: &[TableColumn]) -> bool {
+    columns
+        .iter()
+        .any(|col| col.min_width.is_some() || col.max_width.is_some() || col.flex.unwrap_or(false))
+}
+
+/// Rows grouped by the value of `field`, each group where its first row
+/// was. All rows are read before any prints.
+fn group_rows<'a>(rows: impl Iterator<Item = DataRow<'a>>, field: &str) -> Vec<DataRow<'a>> {
+    let mut groups: Vec<(Option<Cow<'a, str>>, Vec<DataRow<'a>>)> = Vec::new();
+    for row in rows {
+        let key = group_key(row, field);
+        match groups.iter_mut().find(|(group, _)| *group == key) {
+            Some((_, group)) => group.push(row),
+            None => groups.push((key, vec![row])),
+        }
+    }
+    groups.into_iter().flat_map(|(_, rows)| rows).collect()
+}
+
+/// The group a row belongs in, `None` if it has no value for `field`
+fn group_key<'a>(row: DataRow<'a>, field: &str) -> Option<Cow<'a, str>> {
+    row.get(field).filter(|value| !value.is_empty())
+}
+
+/// A cell's value in its column's `format`
+fn format_cell<'a>(col: &TableColumn, raw: Cow<'a, str>) -> Cow<'a, str> {
+    match col.format.as_deref() {
+        Some("currency") => match raw.parse::<f64>() {
+            Ok(num) => Cow::Owned(format_currency("$", num)),
+            Err(_) => raw,
+        },
+        _ => raw,
+    }
+}
+
+/// `content` filling `width` columns with its decimal point `places`
+/// columns from the right, whole numbers ending where the point would be,
+/// and a currency symbol before the number at the left edge. `None` if it
+/// has no number or doesn't fit, to be right-aligned instead.
+fn decimal_aligned(content: &str, width: usize, places: usize) -> Option<String> {
+    let start = content.find(|c: char| c.is_ascii_digit())?;
+    let (prefix, number) = content.split_at(start);
+    let whole_end = number
+        .find(|c: char| !c.is_ascii_digit() && c != ',')
+        .unwrap_or(number.len());
+    let (whole, rest) = number.split_at(whole_end);
+    let fraction_end = match rest.strip_prefix('.') {
+        Some(digits) => {
+            1 + digits
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(digits.len())
+        }
+        None => 0,
+    };
+    let (fraction, suffix) = rest.split_at(fraction_end);
+    // A minus sign stays with the digits, whichever side of the symbol
+    let (symbol, sign) = match prefix
+        .strip_prefix('-')
+        .or_else(|| prefix.strip_suffix('-'))
+    {
+        Some(symbol) => (symbol, "-"),
+        None => (prefix, ""),
+    };
+
+    let point = if places == 0 { 0 } else { places + 1 };
+    let after = point.saturating_sub(fraction.len());
+    let used =
+        text_width(symbol) + sign.len() + whole.len() + fraction.len() + after + text_width(suffix);
+    let before = width.checked_sub(used)?;
+    Some(format!(
+        "{}{}{}{}{}{}{}",
+        symbol,
+        " ".repeat(before),
+        sign,
+        whole,
+        fraction,
+        " ".repeat(after),
+        suffix
+    ))
+}
+
+/// What a table's footer rows need to know about one column, added up
+/// as the rows print
+#[derive(Debug, Clone, Copy, Default)]
+struct ColumnTotals {
+    /// Rows with a value
+    values: usize,
+    /// Rows whose value is a number, and what those add up to
+    numbers: usize,
+    sum: f64,
+    /// The most decimal places among the numbers, which results keep
+    places: usize,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl ColumnTotals {
+    fn add(&mut self, value: Option<&str>) {
+        let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+            return;
+        };
+        self.values += 1;
+        if let Some(number) = value.parse::<f64>().ok().filter(|n| n.is_finite()) {
+            self.numbers += 1;
+            self.sum += number;
+            self.places = self.places.max(decimal_places(value));
+            self.min = Some(self.min.map_or(number, |min| min.min(number)));
+            self.max = Some(self.max.map_or(number, |max| max.max(number)));
+        }
+    }
+
+    /// The aggregate of the values seen as text, or `None` if it needs
+    /// numbers and there were none. Averages show at least two decimals.
+    fn get(&self, aggregate: TableAggregate) -> Option<String> {
+        let (value, places) = match aggregate {
+            TableAggregate::Sum => (self.sum, self.places),
+            TableAggregate::Count => return Some(self.values.to_string()),
+            TableAggregate::Average if self.numbers > 0 => {
+                (self.sum / self.numbers as f64, self.places.max(2))
             }
+            TableAggregate::Average => return None,
+            TableAggregate::Min => (self.min?, self.places),
+            TableAggregate::Max => (self.max?, self.places),
+        };
+        // Adding 0.0 prints -0 as 0
+        Some(format!("{:.*}", places, value + 0.0))
+    }
+}
+
+/// Columns `c` takes on the printer: two for full-width CJK characters
+/// and emoji, none for combining marks and control characters, one for
+/// everything else
+pub(crate) fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// Columns `text` takes on the printer
+pub(crate) fn text_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// The longest start of `text` that fits in `width` columns, and how many
+/// columns that is. A full-width character that would only half fit is
+/// left out.
+pub(crate) fn truncate_columns(text: &str, width: usize) -> (&str, usize) {
+    let mut used = 0;
+    for (i, c) in text.char_indices() {
+        let c_width = char_width(c);
+        if used + c_width > width {
+            return (&text[..i], used);
+        }
+        used += c_width;
+    }
+    (text, used)
+}
+
+/// The style a line prints in, as the commands since `Init` left it
+#[derive(Debug, Clone, PartialEq)]
+struct LineStyle {
+    bold: bool,
+    underline: bool,
+    reverse: bool,
+    double_strike: bool,
+    font: Font,
+    size: (u8, u8),
+    align: String,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        Self {
+            bold: false,
+            underline: false,
+            reverse: false,
+            double_strike: false,
+            font: Font::A,
+            size: (1, 1),
+            align: "left".to_string(),
+        }
+    }
+}
+
+impl LineStyle {
+    fn apply(&mut self, command: &PrintCommand) {
+        match command {
+            PrintCommand::Init => *self = Self::default(),
+            PrintCommand::Bold(on) => self.bold = *on,
+            PrintCommand::Underline(on) => self.underline = *on,
+            PrintCommand::Reverse(on) => self.reverse = *on,
+            PrintCommand::DoubleStrike(on) => self.double_strike = *on,
+            PrintCommand::Font(font) => self.font = *font,
+            PrintCommand::Size(width, height) => self.size = (*width, *height),
+            PrintCommand::Align(align) => self.align = align.clone(),
+            _ => {}
+        }
+    }
+
+    /// Push the commands that turn this style into `other`
+    fn change_to(&mut self, other: &LineStyle, commands: &mut dyn CommandSink) {
+        if self.bold != other.bold {
+            commands.push(PrintCommand::Bold(other.bold));
+        }
+        if self.underline != other.underline {
+            commands.push(PrintCommand::Underline(other.underline));
         }
+        if self.reverse != other.reverse {
+            commands.push(PrintCommand::Reverse(other.reverse));
+        }
+        if self.double_strike != other.double_strike {
+            commands.push(PrintCommand::DoubleStrike(other.double_strike));
+        }
+        if self.font != other.font {
+            commands.push(PrintCommand::Font(other.font));
+        }
+        if self.size != other.size {
+            commands.push(PrintCommand::Size(other.size.0, other.size.1));
+        }
+        if self.align != other.align {
+            commands.push(PrintCommand::Align(other.align.clone()));
+        }
+        *self = other.clone();
+    }
+}
+
+/// Push `commands`' lines last first, each in the style it had, for a
+/// printer mounted upside down
+fn push_lines_last_first(commands: Vec<PrintCommand>, sink: &mut dyn CommandSink) {
+    let mut style = LineStyle::default();
+    let mut start = style.clone();
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    for command in commands {
+        style.apply(&command);
+        let ends_line = matches!(
+            command,
+            PrintCommand::WriteLine(_)
+                | PrintCommand::Feed(_)
+                | PrintCommand::Cut
+                | PrintCommand::QRCode { .. }
+                | PrintCommand::Barcode { .. }
+                | PrintCommand::Image(_)
+                | PrintCommand::NvLogo(_)
+        );
+        line.push(command);
+        if ends_line {
+            let line_start = std::mem::replace(&mut start, style.clone());
+            lines.push((line_start, std::mem::take(&mut line)));
+        }
+    }
+    if !line.is_empty() {
+        lines.push((start, line));
+    }
+
+    let mut printer = LineStyle::default();
+    for (start, line) in lines.into_iter().rev() {
+        printer.change_to(&start, sink);
+        for command in line {
+            printer.apply(&command);
+            sink.push(command);
+        }
+    }
+    // Later commands expect the style the lines left in order
+    printer.change_to(&style, sink);
+}
+
+/// `text` padded with spaces to at least `width` columns
+fn padded(text: &str, width: usize) -> String {
+    let mut out = text.to_string();
+    push_spaces(&mut out, width.saturating_sub(text_width(text)));
+    out
+}
+
+/// `text` after spaces up to `width` columns, right-aligned
+fn padded_left(text: &str, width: usize) -> String {
+    let mut out = String::with_capacity(width.max(text.len()));
+    push_spaces(&mut out, width.saturating_sub(text_width(text)));
+    out.push_str(text);
+    out
+}
+
+fn push_spaces(out: &mut String, count: usize) {
+    for _ in 0..count {
+        out.push(' ');
+    }
+}
+
+/// Character width multiplier the printer applies for a size value.
+/// ESC/POS supports 1x to 8x; 0 prints at normal width.
+pub(crate) fn width_multiplier(size: u8) -> usize {
+    size.clamp(1, 8) as usize
+}
 
-        true // Default to showing if condition can't be evaluated
+/// Greedy word wrap by columns; words longer than `width` are split
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    if text.contains('\n') {
+        return text
+            .split('\n')
+            .flat_map(|line| wrap_text(line, width))
+            .collect();
     }
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_len = 0;
+
+    for word in text.split(' ') {
+        if line_len > 0 && line_len + 1 + text_width(word) > width {
+            lines.push(std::mem::take(&mut line));
+            line_len = 0;
+        }
+        if line_len > 0 {
+            line.push(' ');
+            line_len += 1;
+        }
+        for c in word.chars() {
+            let c_width = char_width(c);
+            if line_len > 0 && line_len + c_width > width {
+                lines.push(std::mem::take(&mut line));
+                line_len = 0;
+            }
+            line.push(c);
+            line_len += c_width;
+        }
+    }
+    lines.push(line);
+    lines
 }
 
 // ==================== Print Commands ====================
 
 /// Print commands for building output without direct printer access
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum PrintCommand {
     Init,
     Write(String),
@@ -1564,72 +4334,58 @@ pub enum PrintCommand {
     Bold(bool),
     Underline(bool),
     Reverse(bool),
+    /// Print each dot twice
+    DoubleStrike(bool),
+    Font(Font),
     Size(u8, u8),
     Align(String),
+    /// A model 2 QR code of `size` dots per module
     QRCode {
         content: String,
         size: u8,
+        error_correction: QrErrorCorrection,
     },
     Barcode {
         content: String,
         format: String,
         height: u8,
         width: u8,
-        show_text: bool,
+        hri: HriPosition,
     },
     Image(Vec<u8>),
+    /// Beep `times` times for `duration_ms` each
+    Buzzer {
+        times: u8,
+        duration_ms: u16,
+    },
+    /// Print the logo stored in the printer's NV memory under this slot
+    NvLogo(u8),
+    /// Rotate each line 180°, for printers mounted upside down
+    UpsideDown(bool),
+    /// Print only `width` dots starting `left` dots from the paper's edge
+    PrintArea {
+        left: u16,
+        width: u16,
+    },
 }
 
 // ==================== Template Loading ====================
 
-/// Load and parse a template from JSON
-#[allow(dead_code)]
-pub fn load_template(json: &str) -> Result<ReceiptTemplate, serde_json::Error> {
-    serde_json::from_str(json)
-}
-
-/// Convert TypeScript template exports to JSON format for parsing
-#[allow(dead_code)]
-pub fn parse_template_export(content: &str, template_id: &str) -> Option<String> {
-    // Find the template object in the export
-    let pattern = format!(r#""{}":\s*\{{"#, template_id);
-    let re = regex::Regex::new(&pattern).ok()?;
-
-    if let Some(start_match) = re.find(content) {
-        let start_idx = start_match.start() + template_id.len() + 4; // Skip '"id": {'
-
-        // Count braces to find the end
-        let mut brace_count = 1;
-        let mut end_idx = start_idx;
-
-        for (i, c) in content[start_idx..].chars().enumerate() {
-            match c {
-                '{' => brace_count += 1,
-                '}' => {
-                    brace_count -= 1;
-                    if brace_count == 0 {
-                        end_idx = start_idx + i + 1;
-                        break;
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        if brace_count == 0 {
-            let template_content = &content[start_idx - 1..end_idx];
-            // This would need more processing to convert TS to valid JSON
-            // For now, return as-is (requires proper JS/TS to JSON conversion)
-            return Some(template_content.to_string());
-        }
-    }
-
-    None
+/// Load and parse a template from JSON.
+///
+/// Errors carry the path, line and column of the problem, plus a hint for
+/// common mistakes.
+pub fn load_template(json: &str) -> Result<ReceiptTemplate, TemplateParseError> {
+    parse_template_json(json)
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::encoder::{encode_commands, CommandEncoder, StarEncoder, StreamEncoder};
+
+    /// Rendering data with this order id panics, to exercise recovery paths.
+    pub(crate) const PANIC_ORDER_ID: &str = "__force_render_panic__";
 
     #[test]
     fn test_variable_substitution() {
@@ -1643,8 +4399,8 @@ mod tests {
             ..Default::default()
         };
 
-        let result =
-            renderer.substitute_variables("Order #{{order_id}} - Total: ${{total}}", &data);
+        let pattern = TextPattern::parse("Order #{{order_id}} - Total: ${{total}}");
+        let result = renderer.render_text(&pattern, &data);
         assert_eq!(result, "Order #12345 - Total: $99.99");
     }
 
@@ -1666,8 +4422,8 @@ mod tests {
             ..Default::default()
         };
 
-        assert!(renderer.evaluate_condition("discount > 0", &data));
-        assert!(!renderer.evaluate_condition("discount > 100", &data));
+        assert!(renderer.evaluate_condition(&Condition::parse("discount > 0"), &data));
+        assert!(!renderer.evaluate_condition(&Condition::parse("discount > 100"), &data));
     }
 
     #[test]
@@ -1693,4 +4449,2207 @@ mod tests {
         assert_eq!(template.name, "Test Template");
         assert_eq!(template.layout.sections.len(), 1);
     }
+
+    #[test]
+    fn test_grid_truncates_multibyte_labels() {
+        let renderer = TemplateRenderer::new(20);
+        let grid = GridElement {
+            columns: 2,
+            data: vec![GridItem {
+                label: "Café crème brûlée".to_string(),
+                value: "€4".to_string(),
+            }],
+            gap: Some(2),
+            condition: None,
+            else_elements: None,
+            min_width: None,
+            fallback: None,
+        };
+        let values = vec![TextPattern::parse("€4")];
+        let mut commands = Vec::new();
+        renderer
+            .build_grid_commands(
+                &mut commands,
+                &grid,
+                &values,
+                renderer.grid_column_width(&grid),
+                &ReceiptData::default(),
+            )
+            .unwrap();
+
+        match &commands[0] {
+            PrintCommand::WriteLine(line) => assert_eq!(line, "Café crèm"),
+            other => panic!("unexpected command {:?}", other),
+        }
+
+        let empty = GridElement { columns: 0, ..grid };
+        assert!(renderer
+            .build_grid_commands(
+                &mut commands,
+                &empty,
+                &values,
+                renderer.grid_column_width(&empty),
+                &ReceiptData::default(),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_compiled_render_matches_golden() {
+        // Golden output recorded from the renderer before templates were compiled
+        let template =
+            load_template(include_str!("../testdata/full_receipt.template.json")).unwrap();
+        let data: ReceiptData =
+            serde_json::from_str(include_str!("../testdata/full_receipt.data.json")).unwrap();
+
+        let renderer = TemplateRenderer::new(template.paper_width.unwrap_or(48));
+        let compiled = renderer.compile(&template);
+        let commands = renderer.render_to_commands(&compiled, &data).unwrap();
+        let rendered: String = commands.iter().map(|c| format!("{:?}\n", c)).collect();
+        assert_eq!(rendered, include_str!("../testdata/full_receipt.commands.txt"));
+
+        // A compiled template is reusable across prints
+        let again = renderer.render_to_commands(&compiled, &data).unwrap();
+        assert_eq!(format!("{:?}", again), format!("{:?}", commands));
+    }
+
+    #[test]
+    fn test_report_render_matches_golden() {
+        // Table cells from every JSON value type, alignment padding and truncation
+        let template =
+            load_template(include_str!("../testdata/sales_report.template.json")).unwrap();
+        let data: ReceiptData =
+            serde_json::from_str(include_str!("../testdata/sales_report.data.json")).unwrap();
+
+        let renderer = TemplateRenderer::new(48);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        let rendered: String = commands.iter().map(|c| format!("{:?}\n", c)).collect();
+        assert_eq!(rendered, include_str!("../testdata/sales_report.commands.txt"));
+    }
+
+    /// Records how the stream encoder writes: total bytes and the largest write
+    #[derive(Default)]
+    struct RecordingWriter {
+        bytes: Vec<u8>,
+        writes: usize,
+        largest_write: usize,
+    }
+
+    impl std::io::Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.bytes.extend_from_slice(buf);
+            self.writes += 1;
+            self.largest_write = self.largest_write.max(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn report_template() -> ReceiptTemplate {
+        load_template(
+            r#"{
+                "id": "sales-report", "name": "Sales", "version": "1.0", "paper_width": 48,
+                "layout": {"sections": [{"type": "body", "elements": [
+                    {"type": "text", "content": "Sales for {{date}}", "bold": true},
+                    {"type": "table", "data_source": "sales", "show_header": true,
+                     "alternating_rows": true,
+                     "columns": [
+                        {"header": "Item", "field": "name", "width": 30},
+                        {"header": "Amount", "field": "amount", "width": 10,
+                         "align": "right", "format": "currency"}]},
+                    {"type": "leaderboard", "data_source": "sales", "highlight_top": 3,
+                     "fields": {"rank": "rank", "name": "name", "sales": "amount"}},
+                    {"type": "divider"}
+                ]}]}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn report_data(rows: usize) -> ReceiptData {
+        let sales: Vec<serde_json::Value> = (0..rows)
+            .map(|i| {
+                serde_json::json!({
+                    "rank": i + 1,
+                    "name": format!("Product {}", i),
+                    "amount": (i % 97) as f64 * 1.25,
+                })
+            })
+            .collect();
+        let mut data = ReceiptData {
+            order_id: "REPORT".to_string(),
+            timestamp: "2026-10-16 09:00:00".to_string(),
+            ..Default::default()
+        };
+        data.custom.insert("sales".to_string(), serde_json::Value::Array(sales));
+        data
+    }
+
+    #[test]
+    fn test_streaming_matches_batch_for_large_data_source() {
+        let template = report_template();
+        let data = report_data(10_000);
+        assert_eq!(data.row_count(), 10_000);
+
+        let renderer = TemplateRenderer::new(48);
+        let compiled = renderer.compile(&template);
+        let batch = renderer.render_to_commands(&compiled, &data).unwrap();
+
+        let mut streamed = Vec::new();
+        renderer
+            .render_streaming(&compiled, &data, |command| streamed.push(command))
+            .unwrap();
+        assert_eq!(format!("{:?}", streamed), format!("{:?}", batch));
+
+        // Encoded incrementally, the job never sits in memory as a whole:
+        // every write is one chunk plus at most one (short) command
+        let mut encoder = StreamEncoder::with_chunk_size(RecordingWriter::default(), 1024);
+        renderer.render_to_sink(&compiled, &data, &mut encoder).unwrap();
+        let writer = encoder.finish().unwrap();
+
+        let batch_bytes = encode_commands(&batch);
+        assert!(batch_bytes.len() > 500_000);
+        assert_eq!(writer.bytes, batch_bytes);
+        assert!(writer.writes >= batch_bytes.len() / (1024 + 128));
+        assert!(writer.largest_write < 1024 + 128, "{}", writer.largest_write);
+    }
+
+    #[test]
+    fn test_streaming_render_cancels_between_rows() {
+        let template = report_template();
+        let data = report_data(10_000);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let renderer = TemplateRenderer::new(48).with_cancellation(Arc::clone(&cancel));
+        let compiled = renderer.compile(&template);
+
+        let mut emitted = 0;
+        let result = renderer.render_streaming(&compiled, &data, |_| {
+            emitted += 1;
+            if emitted == 500 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        });
+
+        assert_eq!(result.unwrap_err(), "Print job cancelled");
+        assert!(emitted < 510, "{}", emitted);
+    }
+
+    /// Report data two levels deep, as end-of-day payloads arrive
+    fn nested_report_data() -> ReceiptData {
+        serde_json::from_value(serde_json::json!({
+            "order_id": "EOD", "timestamp": "2026-10-16 23:00:00",
+            "report": {
+                "stats": {
+                    "by_category": [
+                        { "name": "Coffee", "amount": 120.5 },
+                        { "name": "Pastry", "amount": 48 }
+                    ],
+                    "hourly": []
+                },
+                "days": [{ "hourly": [{ "hour": "09", "amount": 12 }] }]
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_dotted_data_source_reads_nested_rows() {
+        let template = load_template(
+            r#"{
+                "id": "eod", "name": "EOD", "version": "1.0", "paper_width": 48,
+                "layout": {"sections": [{"type": "body", "elements": [
+                    {"type": "table", "data_source": "report.stats.by_category",
+                     "columns": [
+                        {"field": "name", "width": 20},
+                        {"field": "amount", "width": 10, "align": "right"}]},
+                    {"type": "table", "data_source": "report.days.0.hourly",
+                     "columns": [{"field": "hour", "width": 10}]},
+                    {"type": "table", "data_source": "report.totals.by_category",
+                     "columns": [{"field": "name", "width": 20}]}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+        let data = nested_report_data();
+        assert_eq!(data.row_count(), 3);
+
+        let renderer = TemplateRenderer::new(48);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        let lines = text_lines(&commands);
+        assert!(lines.iter().any(|l| l.starts_with("Coffee") && l.ends_with("120.5")));
+        assert!(lines.iter().any(|l| l.starts_with("Pastry") && l.ends_with("48")));
+        assert!(lines.iter().any(|l| l.starts_with("09")));
+
+        // A missing intermediate key is an empty source, naming what's missing
+        assert!(renderer
+            .data_source_rows("report.totals.by_category", &data)
+            .next()
+            .is_none());
+        assert_eq!(
+            lookup_path(&data.custom, "report.totals.by_category").unwrap_err(),
+            "report.totals"
+        );
+    }
+
+    #[test]
+    fn test_length_condition_on_dotted_path() {
+        let renderer = TemplateRenderer::new(48);
+        let data = nested_report_data();
+        let holds = |condition: &str| {
+            renderer.evaluate_condition(&Condition::try_parse(condition).unwrap(), &data)
+        };
+
+        assert!(holds("report.stats.by_category.length > 1"));
+        assert!(!holds("report.stats.by_category.length > 2"));
+        assert!(!holds("report.stats.hourly.length > 0"));
+        assert!(holds("report.days.0.hourly.length == 1"));
+        assert!(!holds("report.totals.by_category.length != null"));
+        assert!(!holds("items.length > 0"));
+    }
+
+    fn three_items() -> ReceiptData {
+        serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "items": [
+                { "name": "Espresso", "quantity": 2, "price": 3.5, "total": 7 },
+                { "name": "Croissant", "quantity": 1, "price": 4, "total": 4 },
+                { "name": "Juice", "quantity": 1, "price": 5.25, "total": 5.25 }
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_columns_without_width_fit_their_cells() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [{ "type": "items", "elements": [
+                { "type": "table", "data_source": "items", "show_header": true,
+                  "header_divider": false, "columns": [
+                    { "field": "quantity", "header": "Qty" },
+                    { "field": "name", "header": "Item", "max_width": 12, "flex": true },
+                    { "field": "total", "header": "Total", "align": "right" }
+                ],
+                  "footer_rows": [{ "label": "Sum", "values": { "total": "sum" } }],
+                  "footer_divider": false }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "items": [
+                { "name": "Tea", "quantity": 2, "total": 5 },
+                { "name": "Croque monsieur with extra cheese", "quantity": 1, "total": 995.5 }
+            ]
+        }))
+        .unwrap();
+
+        let commands = TemplateRenderer::new(32)
+            .render_template(&template, &data)
+            .unwrap();
+        let lines: Vec<&str> = text_lines(&commands).iter().map(|l| l.trim_end()).collect();
+        // The name column is capped, and stays capped with room to spare
+        assert_eq!(
+            lines,
+            vec![
+                "Qty Item           Total",
+                "2   Tea             5.00",
+                "1   Croque monsi  995.50",
+                "Sum              1000.50",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flex_columns_share_the_space_left_up_to_their_max_width() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [{ "type": "items", "elements": [
+                { "type": "table", "data_source": "rows", "show_header": false, "columns": [
+                    { "field": "name", "max_width": 8, "flex": true },
+                    { "field": "note", "flex": true },
+                    { "field": "total", "width": 6, "align": "right" }
+                ]}
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "rows": [{ "name": "Tea", "note": "hot", "total": "5.00" }]
+        }))
+        .unwrap();
+
+        let commands = TemplateRenderer::new(32)
+            .render_template(&template, &data)
+            .unwrap();
+        let lines: Vec<&str> = text_lines(&commands).iter().map(|l| l.trim_end()).collect();
+        // The name column stops at 8 and the note column takes the rest
+        assert_eq!(lines, vec!["Tea      hot          5.00"]);
+    }
+
+    #[test]
+    fn test_column_without_width_keeps_its_share_next_to_widths() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 48,
+            "layout": { "sections": [{ "type": "items", "elements": [
+                { "type": "table", "data_source": "items", "show_header": true,
+                  "header_divider": false, "columns": [
+                    { "field": "name", "header": "Item", "width": 60 },
+                    { "field": "quantity", "header": "Qty" },
+                    { "field": "total", "header": "Total", "width": 20, "align": "right" }
+                ]}
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "items": [{ "name": "Tea", "quantity": 2, "total": 5 }]
+        }))
+        .unwrap();
+
+        let commands = TemplateRenderer::new(48)
+            .render_template(&template, &data)
+            .unwrap();
+        let lines: Vec<&str> = text_lines(&commands).iter().map(|l| l.trim_end()).collect();
+        // Widths of 60, 10 and 20 split the line, as they did before any
+        // column could be measured
+        assert_eq!(
+            lines,
+            vec![
+                "Item                        Qty      Total",
+                "Tea                         2         5.00",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decimal_column_lines_up_points_and_symbols() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [{ "type": "items", "elements": [
+                { "type": "table", "data_source": "rows", "columns": [
+                    { "field": "name", "width": 8 },
+                    { "field": "amount", "header": "Amount", "width": 10, "align": "decimal" }
+                ]}
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "rows": [
+                { "name": "Tea", "amount": "$4.5" },
+                { "name": "Cheese", "amount": "$123.45" },
+                { "name": "Refund", "amount": "-$12.125" },
+                { "name": "Bags", "amount": "3" },
+                { "name": "Gift", "amount": "n/a" }
+            ]
+        }))
+        .unwrap();
+
+        let commands = TemplateRenderer::new(32)
+            .render_template(&template, &data)
+            .unwrap();
+        let lines: Vec<&str> = text_lines(&commands).iter().map(|l| l.trim_end()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "Tea         $         4.5",
+                "Cheese      $       123.45",
+                // More places than the column leaves room for push left
+                "Refund      $      -12.125",
+                "Bags                  3",
+                "Gift                   n/a",
+            ]
+        );
+
+        // Too wide for the column: right-aligned and cut as before
+        assert_eq!(decimal_aligned("$1234.5", 6, 2), None);
+        assert_eq!(decimal_aligned("12", 5, 0).as_deref(), Some("   12"));
+    }
+
+    #[test]
+    fn test_table_numbers_rows_with_running_total() {
+        let template = load_template(
+            r#"{
+                "id": "numbered", "name": "Numbered", "version": "1.0", "paper_width": 48,
+                "layout": {"sections": [{"type": "items", "elements": [
+                    {"type": "table", "data_source": "items", "running_total": "total",
+                     "columns": [
+                        {"field": "_index", "width": 3},
+                        {"field": "name", "width": 20},
+                        {"field": "_running_total", "width": 10, "align": "right",
+                         "format": "currency"}]}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+
+        let renderer = TemplateRenderer::new(48);
+        let commands = renderer.render_template(&template, &three_items()).unwrap();
+        let rows: Vec<String> = text_lines(&commands)
+            .iter()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect();
+        assert_eq!(
+            rows,
+            vec!["1 Espresso $7.00", "2 Croissant $11.00", "3 Juice $16.25"]
+        );
+    }
+
+    #[test]
+    fn test_wrapping_column_continues_under_itself() {
+        let template = load_template(
+            r#"{
+                "id": "wrapped", "name": "Wrapped", "version": "1.0", "paper_width": 17,
+                "layout": {"sections": [{"type": "items", "elements": [
+                    {"type": "table", "data_source": "items", "show_header": true,
+                     "header_divider": false,
+                     "columns": [
+                        {"header": "Item name", "field": "name", "width": 6, "wrap": true},
+                        {"header": "Sum", "field": "total", "width": 4, "align": "right"}]}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+        let data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "items": [
+                { "name": "Flat white with oat milk", "quantity": 1, "total": 4 },
+                { "name": "Tea", "quantity": 1, "total": 2 }
+            ]
+        }))
+        .unwrap();
+
+        let renderer = TemplateRenderer::new(17);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec![
+                "Item    Sum",
+                "name       ",
+                "Flat   4.00",
+                "white      ",
+                "with       ",
+                "oat        ",
+                "milk       ",
+                "Tea    2.00",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_footer_rows_line_up_with_columns() {
+        let template = load_template(
+            r#"{
+                "id": "footer", "name": "Footer", "version": "1.0", "paper_width": 28,
+                "layout": {"sections": [{"type": "items", "elements": [
+                    {"type": "table", "data_source": "items",
+                     "columns": [
+                        {"field": "name", "width": 10},
+                        {"field": "quantity", "width": 4, "align": "right"},
+                        {"field": "total", "width": 6, "align": "right", "format": "currency"}],
+                     "footer_rows": [
+                        {"label": "Subtotal", "values": {"quantity": "sum", "total": "sum"},
+                         "bold": true},
+                        {"values": {"name": "count", "total": "max"}}]}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+
+        let renderer = TemplateRenderer::new(28);
+        let commands = renderer.render_template(&template, &three_items()).unwrap();
+        let lines = text_lines(&commands);
+        assert_eq!(
+            lines[3..],
+            [
+                "----------------------",
+                "Subtotal      4 $16.25",
+                "3                $7.00",
+            ]
+        );
+        let bold = commands
+            .iter()
+            .position(|c| *c == PrintCommand::Bold(true))
+            .unwrap();
+        assert_eq!(
+            commands[bold + 1],
+            PrintCommand::WriteLine(lines[4].to_string())
+        );
+    }
+
+    #[test]
+    fn test_grouped_rows_print_under_headers_with_subtotals() {
+        let template = load_template(
+            r#"{
+                "id": "grouped", "name": "Grouped", "version": "1.0", "paper_width": 23,
+                "layout": {"sections": [{"type": "items", "elements": [
+                    {"type": "table", "data_source": "items", "group_by": "category",
+                     "group_subtotal": {"label": "Subtotal", "values": {"total": "sum"}},
+                     "columns": [
+                        {"field": "name", "width": 10},
+                        {"field": "total", "width": 6, "align": "right", "format": "currency"}]}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+        let data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "items": [
+                { "name": "Latte", "quantity": 1, "total": 3.5, "category": "drinks" },
+                { "name": "Bagel", "quantity": 1, "total": 4.25, "category": "food" },
+                { "name": "Water", "quantity": 1, "total": 1 },
+                { "name": "Tea", "quantity": 1, "total": 2, "category": "drinks" }
+            ]
+        }))
+        .unwrap();
+
+        let renderer = TemplateRenderer::new(23);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec![
+                "drinks",
+                "Latte       $3.50",
+                "Tea         $2.00",
+                "Subtotal    $5.50",
+                "food",
+                "Bagel       $4.25",
+                "Subtotal    $4.25",
+                "Water       $1.00",
+                "Subtotal    $1.00",
+            ]
+        );
+        let header = commands
+            .iter()
+            .position(|c| *c == PrintCommand::WriteLine("food".to_string()))
+            .unwrap();
+        assert_eq!(commands[header - 1], PrintCommand::Bold(true));
+    }
+
+    #[test]
+    fn test_row_detail_condition_on_last_row_only() {
+        let template = load_template(
+            r#"{
+                "id": "last-row", "name": "Last row", "version": "1.0", "paper_width": 48,
+                "layout": {"sections": [{"type": "items", "elements": [
+                    {"type": "table", "data_source": "report.rows",
+                     "columns": [{"field": "name", "width": 20}],
+                     "row_details": [
+                        {"field": "name", "prefix": "== end of ", "condition": "_is_last == true"},
+                        {"field": "_index", "prefix": "first #", "condition": "_is_first == true"},
+                        {"field": "note", "condition": "note"}]}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+        let data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "report": { "rows": [
+                { "name": "A", "note": "" },
+                { "name": "B", "note": "check" },
+                { "name": "C", "_is_last": "false" }
+            ]}
+        }))
+        .unwrap();
+
+        let renderer = TemplateRenderer::new(48);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        let lines: Vec<&str> = text_lines(&commands).iter().map(|l| l.trim_end()).collect();
+        // The loop's own `_is_last` wins over the payload field of that name
+        assert_eq!(lines, vec!["A", "  first #1", "B", "  check", "C", "  == end of C"]);
+    }
+
+    #[test]
+    fn test_item_optional_fields_round_trip() {
+        let plain: ReceiptItem =
+            serde_json::from_str(r#"{ "name": "Tea", "quantity": 1, "price": 2.5 }"#).unwrap();
+        assert!(plain.sku.is_none() && plain.category.is_none() && plain.unit.is_none());
+        assert!(plain.discount.is_none() && plain.tax.is_none() && plain.note.is_none());
+
+        let full = ReceiptItem {
+            name: "Apples".to_string(),
+            quantity: 2,
+            price: 3.0,
+            total: 5.5,
+            sku: Some("4011".to_string()),
+            category: Some("produce".to_string()),
+            discount: Some(0.5),
+            tax: Some(0.44),
+            unit: Some("kg".to_string()),
+            note: Some("organic".to_string()),
+            ..Default::default()
+        };
+        for item in [plain, full] {
+            let json = serde_json::to_string(&item).unwrap();
+            let back: ReceiptItem = serde_json::from_str(&json).unwrap();
+            assert_eq!(format!("{:?}", back), format!("{:?}", item));
+        }
+    }
+
+    #[test]
+    fn test_table_renders_item_fields_and_discount_detail() {
+        let template = load_template(
+            r#"{
+                "id": "grocery", "name": "Grocery", "version": "1.0", "paper_width": 48,
+                "layout": {"sections": [{"type": "items", "elements": [
+                    {"type": "table", "data_source": "items",
+                     "columns": [
+                        {"field": "sku", "width": 6},
+                        {"field": "name", "width": 10},
+                        {"field": "unit", "width": 3},
+                        {"field": "total", "width": 8, "align": "right"}],
+                     "row_details": [
+                        {"field": "discount", "prefix": "  saved $",
+                         "condition": "item.discount > 0"},
+                        {"field": "category", "prefix": "[", "suffix": "]"},
+                        {"field": "note", "prefix": "  "}]}
+                ]}]}
+            }"#,
+        )
+        .unwrap();
+        let data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "items": [
+                { "name": "Apples", "quantity": 2, "total": 5.5, "sku": "4011",
+                  "unit": "kg", "discount": 0.5, "category": "produce" },
+                { "name": "Bread", "quantity": 1, "total": 3, "discount": 0, "note": "sliced" },
+                { "name": "Milk", "quantity": 1, "total": 1.2 }
+            ]
+        }))
+        .unwrap();
+
+        let renderer = TemplateRenderer::new(48);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        let lines: Vec<String> = text_lines(&commands)
+            .iter()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "4011 Apples kg 5.50",
+                "saved $0.50",
+                "[produce]",
+                "Bread 3.00",
+                "sliced",
+                "Milk 1.20",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_feeds_split_instead_of_wrapping() {
+        let feeds = |template: &str| -> Vec<u8> {
+            let template = load_template(template).unwrap();
+            let commands = TemplateRenderer::new(48)
+                .render_template(&template, &ReceiptData::default())
+                .unwrap();
+            let mut feeds: Vec<u8> = commands
+                .iter()
+                .filter_map(|c| match c {
+                    PrintCommand::Feed(lines) => Some(*lines),
+                    _ => None,
+                })
+                .collect();
+            // Leave out the fixed feeds before the cut
+            assert_eq!(feeds.split_off(feeds.len() - 6), vec![1; 6]);
+            feeds
+        };
+
+        for (lines, expected) in [
+            (0, vec![]),
+            (255, vec![255]),
+            (256, vec![255, 1]),
+            (300, vec![255, 45]),
+            (5000, vec![255, 255, 255, 235]),
+        ] {
+            let spacing = format!(
+                r#"{{"id": "s", "name": "S", "version": "1", "paper_width": 48,
+                    "layout": {{"sections": [{{"type": "body", "elements": [],
+                        "spacing": {{"before": {}}}}}]}}}}"#,
+                lines
+            );
+            let space = format!(
+                r#"{{"id": "s", "name": "S", "version": "1", "paper_width": 48,
+                    "layout": {{"sections": [{{"type": "body", "elements": [
+                        {{"type": "space", "lines": {}}}]}}]}}}}"#,
+                lines
+            );
+            assert_eq!(feeds(&spacing), expected, "spacing of {}", lines);
+            assert_eq!(feeds(&space), expected, "space of {}", lines);
+        }
+    }
+
+    #[test]
+    fn test_buzzer_sounds_for_rush_section_and_after_cut() {
+        let template = load_template(
+            r#"{
+                "id": "kitchen", "name": "Kitchen", "version": "1.0", "paper_width": 48,
+                "buzzer": {"times": 3},
+                "layout": {"sections": [
+                    {"type": "header", "condition": "rush == true",
+                     "buzzer": {"times": 2, "duration_ms": 100},
+                     "elements": [{"type": "text", "content": "RUSH"}]},
+                    {"type": "body", "elements": [{"type": "text", "content": "1 Burger"}]}
+                ]}
+            }"#,
+        )
+        .unwrap();
+        let renderer = TemplateRenderer::new(48);
+        let compiled = renderer.compile(&template);
+        let render = |rush: bool| {
+            let data = ReceiptData {
+                custom: [("rush".to_string(), serde_json::json!(rush))].into(),
+                ..Default::default()
+            };
+            renderer.render_to_commands(&compiled, &data).unwrap()
+        };
+
+        let commands = render(true);
+        let position = |wanted: &PrintCommand| commands.iter().position(|c| c == wanted);
+        let rush = position(&PrintCommand::WriteLine("RUSH".to_string())).unwrap();
+        let beep = position(&PrintCommand::Buzzer {
+            times: 2,
+            duration_ms: 100,
+        })
+        .unwrap();
+        let body = position(&PrintCommand::WriteLine("1 Burger".to_string())).unwrap();
+        assert!(rush < beep && beep < body);
+        assert_eq!(
+            commands[commands.len() - 2..],
+            [
+                PrintCommand::Cut,
+                PrintCommand::Buzzer {
+                    times: 3,
+                    duration_ms: 200
+                }
+            ]
+        );
+
+        let commands = render(false);
+        let beeps = commands
+            .iter()
+            .filter(|c| matches!(c, PrintCommand::Buzzer { .. }))
+            .count();
+        assert_eq!(beeps, 1);
+
+        let beep = [PrintCommand::Buzzer {
+            times: 3,
+            duration_ms: 200,
+        }];
+        assert_eq!(encode_commands(&beep), [0x1B, 0x42, 3, 4]);
+        assert_eq!(
+            StarEncoder.encode_all(&beep),
+            b"\x1b\x1d\x19\x11\x01\x0a\x0a\x1b\x1d\x19\x12\x01\x03\x00"
+        );
+    }
+
+    #[test]
+    fn test_min_width_gates_sections_and_elements_on_narrow_paper() {
+        let template = load_template(
+            r#"{
+                "id": "shared", "name": "Shared", "version": "1.0",
+                "layout": {"sections": [
+                    {"type": "header", "elements": [{"type": "text", "content": "CAFE"}]},
+                    {"type": "items", "min_width": "80mm", "elements": [
+                        {"type": "text", "content": "DETAILED TABLE"}]},
+                    {"type": "footer", "elements": [
+                        {"type": "text", "content": "WIDE THANKS", "min_width": 40,
+                         "fallback": {"type": "text", "content": "THANKS"}},
+                        {"type": "text", "content": "WIDE ONLY", "min_width": 48}
+                    ]}
+                ]}
+            }"#,
+        )
+        .unwrap();
+
+        let render = |width: u32| {
+            let renderer = TemplateRenderer::new(width);
+            let compiled = renderer.compile(&template);
+            let commands = renderer
+                .render_to_commands(&compiled, &ReceiptData::default())
+                .unwrap();
+            let lines: Vec<String> =
+                text_lines(&commands).iter().map(|l| l.trim().to_string()).collect();
+            (lines, compiled.dropped().to_vec())
+        };
+
+        let (wide, dropped) = render(48);
+        assert_eq!(wide, vec!["CAFE", "DETAILED TABLE", "WIDE THANKS", "WIDE ONLY"]);
+        assert!(dropped.is_empty());
+
+        let (narrow, dropped) = render(32);
+        assert_eq!(narrow, vec!["CAFE", "THANKS"]);
+        assert_eq!(
+            dropped,
+            vec![
+                "layout.sections[1] left out: needs 80mm paper, paper is 32 columns",
+                "layout.sections[2].elements[0] replaced by its fallback: \
+                 needs 40 columns, paper is 32 columns",
+                "layout.sections[2].elements[1] left out: needs 48 columns, paper is 32 columns",
+            ]
+        );
+    }
+
+    fn text_lines(commands: &[PrintCommand]) -> Vec<&str> {
+        commands
+            .iter()
+            .filter_map(|c| match c {
+                PrintCommand::WriteLine(line) => Some(line.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_size_2_total_row_fits_effective_columns() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 48,
+            "layout": { "sections": [{ "type": "totals", "elements": [
+                { "type": "row", "left": "TOTAL", "right": "${{total}}", "font_size": 2, "bold": true },
+                { "type": "row", "left": "Grand total including service", "right": "${{total}}", "font_size": 2 },
+                { "type": "row", "left": "X", "right": "{{farewell_message}}", "font_size": 2 }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data = ReceiptData {
+            total: 1234.5,
+            farewell_message: Some("Thanks for visiting, come back soon".to_string()),
+            ..Default::default()
+        };
+        let commands = TemplateRenderer::new(48).render_template(&template, &data).unwrap();
+
+        let lines = text_lines(&commands);
+        assert_eq!(lines.len(), 3);
+        for line in &lines {
+            assert!(line.chars().count() <= 24, "{:?} is wider than 24 columns", line);
+        }
+        assert!(lines[0].starts_with("TOTAL") && lines[0].ends_with("$1234.50"));
+        assert!(lines[1].ends_with(" $1234.50"));
+
+        // Each enlarged row is followed by a reset to normal size
+        let sizes: Vec<_> = commands
+            .iter()
+            .filter_map(|c| match c {
+                PrintCommand::Size(w, h) => Some((*w, *h)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sizes, vec![(2, 2), (1, 1), (2, 2), (1, 1), (2, 2), (1, 1)]);
+    }
+
+    #[test]
+    fn test_wide_text_wraps_to_effective_columns() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1",
+            "layout": { "sections": [{ "type": "header", "elements": [
+                { "type": "text", "content": "Grand opening weekend special offers", "font_width": 2 },
+                { "type": "text", "content": "Grand opening weekend special offers" },
+                { "type": "row", "left": "Zero", "right": "size", "font_size": 0 }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let commands = TemplateRenderer::new(48)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+
+        assert_eq!(
+            text_lines(&commands),
+            vec![
+                "Grand opening weekend",
+                "special offers",
+                "Grand opening weekend special offers",
+                "Zero                                  size",
+            ]
+        );
+        assert_eq!(wrap_text("abcdefghij kl", 4), vec!["abcd", "efgh", "ij", "kl"]);
+    }
+
+    #[test]
+    fn test_full_width_characters_take_two_columns() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 22,
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "row", "left": "牛肉面", "right": "¥28.00" },
+                { "type": "row", "left": "宫保鸡丁盖饭加蛋", "right": "¥32.00" },
+                { "type": "divider", "style": "custom", "character": "＝" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let commands = TemplateRenderer::new(22)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+
+        assert_eq!(
+            text_lines(&commands),
+            vec!["牛肉面    ¥28.00", "宫保鸡丁 ¥32.00", "＝＝＝＝＝＝＝＝"]
+        );
+        assert_eq!(
+            wrap_text("한국어 메뉴판", 5),
+            vec!["한국", "어", "메뉴", "판"]
+        );
+        assert_eq!(truncate_columns("日本語", 5), ("日本", 4));
+    }
+
+    #[test]
+    fn test_emoji_and_combining_marks_are_measured_in_columns() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 22,
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "row", "left": "🍔 Burger", "right": "9.00" },
+                { "type": "row", "left": "Cafe\u0301", "right": "3.50" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let commands = TemplateRenderer::new(22)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+
+        assert_eq!(
+            text_lines(&commands),
+            vec!["🍔 Burger   9.00", "Cafe\u{301}        3.50"]
+        );
+        assert_eq!(truncate_columns("e\u{301}e\u{301}", 1), ("e\u{301}", 1));
+        assert_eq!(padded_left("☕", 4), "  ☕");
+    }
+
+    #[test]
+    fn test_barcode_check_digit_and_text_fallback() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "barcode", "content": "400638133393", "format": "EAN13", "hri_position": "both" },
+                { "type": "barcode", "content": "4006381333932", "format": "EAN13" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let commands = TemplateRenderer::new(32)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+
+        assert!(commands.iter().any(|c| matches!(
+            c,
+            PrintCommand::Barcode { content, hri: HriPosition::Both, .. } if content == "4006381333931"
+        )));
+        assert_eq!(text_lines(&commands), vec!["4006381333932"]);
+    }
+
+    #[test]
+    fn test_upside_down_prints_lines_last_first() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [
+                { "type": "header", "elements": [
+                    { "type": "text", "content": "Cafe", "bold": true, "align": "center" },
+                    { "type": "text", "content": "Main St" }
+                ]},
+                { "type": "body", "elements": [
+                    { "type": "text", "content": "1 Tea" },
+                    { "type": "text", "content": "2 Cake" }
+                ]},
+                { "type": "footer", "elements": [{ "type": "text", "content": "Thanks" }] }
+            ]}
+        }"#,
+        )
+        .unwrap();
+        let upright = TemplateRenderer::new(32)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+        let commands = TemplateRenderer::new(32)
+            .with_upside_down(true)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+
+        assert_eq!(commands[1], PrintCommand::UpsideDown(true));
+        assert_eq!(
+            text_lines(&commands),
+            vec!["Thanks", "2 Cake", "1 Tea", "Main St", "Cafe"]
+        );
+        // Each line keeps its style, whichever line printed before it
+        let styles = |commands: &[PrintCommand]| {
+            let mut style = LineStyle::default();
+            let mut styles = Vec::new();
+            for command in commands {
+                style.apply(command);
+                if let PrintCommand::WriteLine(text) = command {
+                    styles.push((text.trim().to_string(), style.clone()));
+                }
+            }
+            styles.sort_by(|a, b| a.0.cmp(&b.0));
+            styles
+        };
+        assert_eq!(styles(&commands), styles(&upright));
+    }
+
+    #[test]
+    fn test_print_area_centres_narrow_templates() {
+        // A 58mm template on 80mm paper
+        assert_eq!(PrintArea::default().resolve(384, 576), (96, 384));
+        let area = PrintArea {
+            left_margin: Some(24),
+            width: Some(512),
+        };
+        assert_eq!(area.resolve(384, 576), (24, 512));
+        assert_eq!(PrintArea::default().resolve(576, 384), (0, 576));
+
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "print_area": {},
+            "layout": { "sections": [] }
+        }"#,
+        )
+        .unwrap();
+        assert_eq!(template.print_area, Some(PrintArea::default()));
+        let commands = TemplateRenderer::new(32)
+            .with_print_area(Some((96, 384)))
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+        assert_eq!(
+            commands[..2],
+            [
+                PrintCommand::Init,
+                PrintCommand::PrintArea {
+                    left: 96,
+                    width: 384
+                }
+            ]
+        );
+        assert_eq!(
+            crate::encoder::encode_commands(&commands[1..2]),
+            [0x1D, 0x4C, 96, 0, 0x1D, 0x57, 128, 1]
+        );
+    }
+
+    #[test]
+    fn test_font_b_and_double_strike_text() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 48,
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "Hourly sales", "font": "B", "double_strike": true }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let commands = TemplateRenderer::new(48)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+        let position = |wanted: PrintCommand| commands.iter().position(|c| *c == wanted).unwrap();
+
+        let line = position(PrintCommand::WriteLine("Hourly sales".to_string()));
+        assert!(position(PrintCommand::Font(Font::B)) < line);
+        assert!(position(PrintCommand::DoubleStrike(true)) < line);
+        assert!(position(PrintCommand::Font(Font::A)) > line);
+        assert!(position(PrintCommand::DoubleStrike(false)) > line);
+        assert_eq!(
+            crate::encoder::encode_commands(&[
+                PrintCommand::Font(Font::B),
+                PrintCommand::DoubleStrike(true)
+            ]),
+            b"\x1bM\x01\x1bG\x01"
+        );
+    }
+
+    #[test]
+    fn test_repeat_prints_elements_per_entry() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 48,
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "repeat", "data_source": "orders.open", "elements": [
+                    { "type": "text", "content": "{{index}}. {{item.table}}" },
+                    { "type": "repeat", "data_source": "item.dishes", "elements": [
+                        { "type": "text", "content": "  - {{item}}" }
+                    ]},
+                    { "type": "text", "content": "  rush", "condition": "item.rush == true" }
+                ]},
+                { "type": "repeat", "data_source": "items", "elements": [
+                    { "type": "row", "left": "{{item.name}}", "right": "{{item.total}}" }
+                ]}
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let mut data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "orders": { "open": [
+                { "table": "T4", "dishes": ["Soup", "Bread"], "rush": true },
+                { "table": "T7", "dishes": [] }
+            ]}
+        }))
+        .unwrap();
+        data.items.push(ReceiptItem {
+            name: "Tea".to_string(),
+            total: 2.5,
+            ..ReceiptItem::default()
+        });
+
+        let commands = TemplateRenderer::new(48)
+            .render_template(&template, &data)
+            .unwrap();
+        let lines: Vec<&str> = text_lines(&commands).iter().map(|l| l.trim_end()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "1. T4",
+                "  - Soup",
+                "  - Bread",
+                "  rush",
+                "2. T7",
+                &format!("Tea{}2.50", " ".repeat(35)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dotted_placeholders_read_nested_values() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 48,
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "{{customer.loyalty.points}} points" },
+                { "type": "text", "content": "First: {{items.0.name}} {{items.0.price}}" },
+                { "type": "text", "content": "[{{report.days.0.hourly.0.hour}}]" },
+                { "type": "text", "content": "[{{customer.phone}}][{{items.5.name}}]" },
+                { "type": "text", "content": "Gold", "condition": "customer.loyalty.points > 100" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let mut data = nested_report_data();
+        data.custom.insert(
+            "customer".to_string(),
+            serde_json::json!({ "loyalty": { "points": 250 } }),
+        );
+        data.items.push(ReceiptItem {
+            name: "Tea".to_string(),
+            price: 2.5,
+            ..ReceiptItem::default()
+        });
+
+        let commands = TemplateRenderer::new(48)
+            .render_template(&template, &data)
+            .unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec!["250 points", "First: Tea 2.50", "[09]", "[][]", "Gold"]
+        );
+    }
+
+    #[test]
+    fn test_aggregates_read_data_sources() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 48,
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content":
+                    "{{sum(report.stats.by_category.amount)}} in {{count(report.stats.by_category)}}" },
+                { "type": "text", "content": "{{avg(report.stats.by_category.amount)}}" },
+                { "type": "text", "content": "{{max(report.stats.hourly.amount) | default:'-'}}" },
+                { "type": "text", "content": "{{sum(items.total)}} / {{count(items)}}" },
+                { "type": "text", "content": "One day", "condition": "count(report.days) == 1" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let mut data = nested_report_data();
+        for total in [2.5, 4.0] {
+            data.items.push(ReceiptItem {
+                name: "Tea".to_string(),
+                total,
+                ..ReceiptItem::default()
+            });
+        }
+
+        let commands = TemplateRenderer::new(48)
+            .render_template(&template, &data)
+            .unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec!["168.5 in 2", "84.25", "-", "6.50 / 2", "One day"]
+        );
+    }
+
+    #[test]
+    fn test_kitchen_tickets_print_per_course() {
+        let kitchen = |ticket_per: &str| {
+            load_template(&format!(
+                r#"{{
+                "id": "t", "name": "T", "version": "1", "paper_width": 48,
+                "kind": "kitchen", "ticket_per": "{}",
+                "layout": {{ "sections": [{{ "type": "body", "elements": [
+                    {{ "type": "text", "content": "{{{{order_type}}}} / {{{{table_number}}}}" }},
+                    {{ "type": "text", "content": "{{{{course}}}}", "condition": "course" }},
+                    {{ "type": "kitchen_items" }}
+                ]}}]}}
+            }}"#,
+                ticket_per
+            ))
+            .unwrap()
+        };
+        let items: Vec<ReceiptItem> = serde_json::from_value(serde_json::json!([
+            { "name": "Soup", "quantity": 2, "price": 6.0, "course": "Starters" },
+            { "name": "Steak", "quantity": 1, "price": 24.0, "course": "Mains",
+              "modifiers": ["Medium rare", "No salt"], "note": "Allergic to nuts" },
+            { "name": "Bread", "quantity": 1, "price": 3.0, "course": "Starters" }
+        ]))
+        .unwrap();
+        let data = ReceiptData {
+            order_type: Some("Dine in".to_string()),
+            table_number: Some("12".to_string()),
+            items,
+            ..Default::default()
+        };
+        let renderer = TemplateRenderer::new(48);
+
+        let commands = renderer.render_template(&kitchen("course"), &data).unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec![
+                "Dine in / 12",
+                "Starters",
+                "2x Soup",
+                "1x Bread",
+                "Dine in / 12",
+                "Mains",
+                "1x Steak",
+                "  > Medium rare",
+                "  > No salt",
+                "  ! Allergic to nuts",
+            ]
+        );
+        let cuts = commands.iter().filter(|c| **c == PrintCommand::Cut);
+        assert_eq!(cuts.count(), 2);
+        assert!(commands.contains(&PrintCommand::Size(2, 2)));
+
+        // On one ticket the courses are headed
+        let commands = renderer.render_template(&kitchen("order"), &data).unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec![
+                "Dine in / 12",
+                "-- Starters --",
+                "2x Soup",
+                "1x Bread",
+                "-- Mains --",
+                "1x Steak",
+                "  > Medium rare",
+                "  > No salt",
+                "  ! Allergic to nuts",
+            ]
+        );
+        assert_eq!(data.tickets(TicketSplit::Item).len(), 3);
+    }
+
+    #[test]
+    fn test_kitchen_items_group_by_seat() {
+        let kitchen = |ticket_per: &str, group_by: &str| {
+            load_template(&format!(
+                r#"{{
+                "id": "t", "name": "T", "version": "1", "paper_width": 48,
+                "kind": "kitchen", "ticket_per": "{}",
+                "layout": {{ "sections": [{{ "type": "body", "elements": [
+                    {{ "type": "text", "content": "Seat {{{{seat}}}}", "condition": "seat" }},
+                    {{ "type": "kitchen_items", "font_size": 1, "group_by": "{}" }}
+                ]}}]}}
+            }}"#,
+                ticket_per, group_by
+            ))
+            .unwrap()
+        };
+        let data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "items": [
+                { "name": "Soup", "quantity": 1, "course": "Starters", "seat": "1" },
+                { "name": "Salad", "quantity": 1, "course": "Starters", "seat": "2" },
+                { "name": "Steak", "quantity": 1, "course": "Mains", "seat": "1" }
+            ]
+        }))
+        .unwrap();
+        let renderer = TemplateRenderer::new(48);
+
+        let commands = renderer
+            .render_template(&kitchen("order", "seat"), &data)
+            .unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec![
+                "-- Seat 1 --",
+                "1x Soup",
+                "1x Steak",
+                "-- Seat 2 --",
+                "1x Salad"
+            ]
+        );
+
+        // A ticket per seat, its items grouped by course
+        let commands = renderer
+            .render_template(&kitchen("seat", "course"), &data)
+            .unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec![
+                "Seat 1",
+                "-- Starters --",
+                "1x Soup",
+                "-- Mains --",
+                "1x Steak",
+                "Seat 2",
+                "-- Starters --",
+                "1x Salad",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_right_to_left_template_mirrors_rows() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 20, "direction": "rtl",
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "תודה", "align": "center" },
+                { "type": "row", "left": "סה״כ", "right": "12.50" },
+                { "type": "text", "content": "Table שלום", "direction": "ltr" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+
+        let commands = TemplateRenderer::new(20)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec!["הדות", "12.50     כ״הס", "Table םולש"]
+        );
+        // The row starts at the right, and the next element at the left again
+        let row = commands
+            .iter()
+            .position(|c| *c == PrintCommand::WriteLine("12.50     כ״הס".to_string()))
+            .unwrap();
+        assert_eq!(commands[row - 1], PrintCommand::Align("right".to_string()));
+        assert_eq!(commands[row + 1], PrintCommand::Align("left".to_string()));
+    }
+
+    #[test]
+    fn test_placeholder_filters() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 48,
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content":
+                    "{{store_name | upper | truncate:6}}|{{total | currency:'€'}}|{{date | format:'%d %b %Y'}}" },
+                { "type": "text", "content":
+                    "{{missing | default:'-'}}|{{subtotal * 100 | number}}|{{total | shout}}" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data = ReceiptData {
+            store_name: Some("Corner Café".to_string()),
+            timestamp: "2024-01-15 14:30:00".to_string(),
+            subtotal: 20.25,
+            total: 23.78,
+            ..Default::default()
+        };
+
+        let commands = TemplateRenderer::new(48)
+            .render_template(&template, &data)
+            .unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec!["CORNER|€23.78|15 Jan 2024", "-|2,025|{{total | shout}}"]
+        );
+    }
+
+    #[test]
+    fn test_translated_labels_follow_data_language() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 48,
+            "default_language": "en",
+            "translations": {
+                "en": { "total": "TOTAL", "thanks": "Thank you" },
+                "PT": { "total": "Total" },
+                "pt-BR": { "thanks": "Obrigado" }
+            },
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "{{t.total}} {{total}}" },
+                { "type": "text", "content": "{{t.thanks}}{{t.missing | default:'!'}}" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let compiled = TemplateRenderer::new(48).compile(&template);
+        let lines = |language: Option<&str>| {
+            let data = ReceiptData {
+                total: 9.5,
+                language: language.map(str::to_string),
+                ..Default::default()
+            };
+            let commands = TemplateRenderer::new(48)
+                .render_to_commands(&compiled, &data)
+                .unwrap();
+            text_lines(&commands).join("\n")
+        };
+
+        assert_eq!(lines(None), "TOTAL 9.50\nThank you!");
+        assert_eq!(lines(Some("pt-BR")), "TOTAL 9.50\nObrigado!");
+        assert_eq!(lines(Some("pt_PT")), "Total 9.50\nThank you!");
+        assert_eq!(lines(Some("de")), "TOTAL 9.50\nThank you!");
+    }
+
+    #[test]
+    fn test_constants_and_computed_values() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1",
+            "constants": { "store_code": "NX-01", "tip_rate": 10 },
+            "computed": {
+                "total_with_tip": "total + suggested_tip",
+                "suggested_tip": "total * tip_rate / 100"
+            },
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "Store {{store_code}}" },
+                { "type": "text", "content": "Tip {{suggested_tip | currency}}" },
+                { "type": "text", "content": "With tip {{total_with_tip | currency}}",
+                  "condition": "total_with_tip > total" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let mut data = ReceiptData {
+            total: 20.0,
+            ..Default::default()
+        };
+        // Template values win over payload fields of the same name
+        data.custom.insert("store_code".to_string(), "XX".into());
+
+        let renderer = TemplateRenderer::new(32);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        let lines: Vec<String> = text_lines(&commands)
+            .iter()
+            .map(|line| line.trim_end().to_string())
+            .collect();
+        assert_eq!(lines, ["Store NX-01", "Tip $2.00", "With tip $22.00"]);
+    }
+
+    #[test]
+    fn test_computed_numbers_read_back_as_numbers() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1",
+            "constants": { "vat_rate": 0.21, "free_over": 20 },
+            "computed": {
+                "vat": "total * vat_rate",
+                "with_vat": "total + vat",
+                "free_delivery": "with_vat >= free_over"
+            },
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "{{vat}} {{vat | currency}} {{with_vat | number:1}}" },
+                { "type": "text", "content": "Free delivery", "condition": "free_delivery" },
+                { "type": "text", "content": "No delivery", "condition": "!free_delivery" },
+                { "type": "text", "content": "VAT 4.2", "condition": "vat == 4.2" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data = ReceiptData {
+            total: 19.99,
+            ..Default::default()
+        };
+
+        let renderer = TemplateRenderer::new(32);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        let lines: Vec<String> = text_lines(&commands)
+            .iter()
+            .map(|line| line.trim_end().to_string())
+            .collect();
+        assert_eq!(lines, ["4.20 $4.20 24.2", "Free delivery", "VAT 4.2"]);
+    }
+
+    #[test]
+    fn test_bilingual_labels_and_item_names() {
+        let template = |layout: &str| {
+            load_template(&format!(
+                r#"{{
+                "id": "t", "name": "T", "version": "1", "paper_width": 32,
+                "default_language": "en",
+                "bilingual": {{ "language": "fr", "layout": "{}" }},
+                "translations": {{
+                    "en": {{ "total": "Total", "thanks": "Thank you" }},
+                    "fr": {{ "total": "Total TTC", "Coffee": "Café" }}
+                }},
+                "layout": {{ "sections": [{{ "type": "body", "elements": [
+                    {{ "type": "table", "data_source": "items", "show_header": false,
+                      "columns": [{{ "field": "name", "width": 20 }}] }},
+                    {{ "type": "row", "left": "{{{{t.total}}}}", "right": "{{{{total}}}}" }},
+                    {{ "type": "text", "content": "{{{{t.thanks}}}}" }}
+                ]}}]}}
+            }}"#,
+                layout
+            ))
+            .unwrap()
+        };
+        let mut data = ReceiptData {
+            total: 3.5,
+            items: vec![ReceiptItem {
+                name: "Coffee".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let renderer = TemplateRenderer::new(32);
+        let lines = |template: &ReceiptTemplate, data: &ReceiptData| {
+            let commands = renderer.render_template(template, data).unwrap();
+            text_lines(&commands)
+                .iter()
+                .map(|line| line.trim_end().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // Labels the second language lacks print once
+        assert_eq!(
+            lines(&template("side"), &data),
+            vec!["Coffee / Café", "Total / Total TTC     3.50", "Thank you"]
+        );
+        assert_eq!(
+            lines(&template("stacked"), &data),
+            vec![
+                "Coffee",
+                "Café",
+                "Total                 3.50",
+                "Total TTC",
+                "Thank you"
+            ]
+        );
+
+        // The data's second language replaces the template's
+        data.secondary_language = Some("de".to_string());
+        assert_eq!(
+            lines(&template("side"), &data),
+            vec!["Coffee", "Total                 3.50", "Thank you"]
+        );
+    }
+
+    #[test]
+    fn test_image_source_reads_placeholders() {
+        use base64::Engine;
+
+        let mut png = Vec::new();
+        image::DynamicImage::ImageLuma8(image::GrayImage::new(16, 8))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1",
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "image", "source": "data:image/png;base64,{{photo}}", "max_width": 64 },
+                { "type": "image", "source": "{{missing}}" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data = ReceiptData {
+            custom: [(
+                "photo".to_string(),
+                serde_json::json!(base64::engine::general_purpose::STANDARD.encode(&png)),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let commands = TemplateRenderer::new(48)
+            .render_template(&template, &data)
+            .unwrap();
+
+        let images: Vec<_> = commands
+            .iter()
+            .filter_map(|command| match command {
+                PrintCommand::Image(bytes) => Some(bytes),
+                _ => None,
+            })
+            .collect();
+        // One image, 64 x 32 dots in a row as wide as the paper
+        assert_eq!(images.len(), 1);
+        assert_eq!(&images[0][..8], &[0x1D, 0x76, 0x30, 0x00, 72, 0, 32, 0]);
+    }
+
+    #[test]
+    fn test_markup_styles_parts_of_a_line() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1",
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "markup": true, "content": "Hi **{{cashier_name}}**, {big}thanks{/big}" },
+                { "type": "text", "content": "Sign: ____" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data = ReceiptData {
+            cashier_name: Some("**Sam**".to_string()),
+            ..Default::default()
+        };
+        let commands = TemplateRenderer::new(48)
+            .render_template(&template, &data)
+            .unwrap();
+
+        let styled: Vec<_> = commands
+            .iter()
+            .filter(|command| {
+                matches!(
+                    command,
+                    PrintCommand::Write(_) | PrintCommand::Bold(_) | PrintCommand::Size(..)
+                )
+            })
+            .take(12)
+            .cloned()
+            .collect();
+        assert_eq!(
+            styled,
+            vec![
+                PrintCommand::Bold(false),
+                PrintCommand::Size(1, 1),
+                PrintCommand::Write("Hi ".to_string()),
+                PrintCommand::Bold(true),
+                PrintCommand::Size(1, 1),
+                // Placeholder values are printed as they are
+                PrintCommand::Write("**Sam**".to_string()),
+                PrintCommand::Bold(false),
+                PrintCommand::Size(1, 1),
+                PrintCommand::Write(", ".to_string()),
+                PrintCommand::Bold(false),
+                PrintCommand::Size(2, 2),
+                PrintCommand::Write("thanks".to_string()),
+            ]
+        );
+        // Without `markup` the content is printed as written
+        assert!(commands.contains(&PrintCommand::WriteLine("Sign: ____".to_string())));
+    }
+
+    #[test]
+    fn test_breakdown_shows_each_share_of_the_total() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "breakdown", "data_source": "payments", "label_field": "method",
+                  "value_field": "amount", "prefix": "$" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data = ReceiptData {
+            custom: serde_json::from_value(serde_json::json!({
+                "payments": [
+                    { "method": "Card", "amount": 60 },
+                    { "method": "Cash", "amount": "40" },
+                    { "method": "Voucher", "amount": "n/a" }
+                ]
+            }))
+            .unwrap(),
+            ..Default::default()
+        };
+
+        let commands = TemplateRenderer::new(32)
+            .render_template(&template, &data)
+            .unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec![
+                "Card           $60.00  60%",
+                "",
+                "Cash           $40.00  40%",
+                ""
+            ]
+        );
+        let bars: Vec<usize> = commands
+            .windows(2)
+            .filter_map(|pair| match pair {
+                [PrintCommand::Reverse(true), PrintCommand::Write(bar)] => Some(bar.len()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(bars, vec![16, 10]);
+    }
+
+    #[test]
+    fn test_coupon_tears_or_cuts_off() {
+        let coupon = |cut: bool| {
+            load_template(&format!(
+                r#"{{
+                "id": "t", "name": "T", "version": "1", "paper_width": 32,
+                "layout": {{ "sections": [{{ "type": "footer", "elements": [
+                    {{ "type": "coupon", "content": "10% OFF\nyour next visit",
+                      "code": "SAVE{{{{order_id}}}}", "code_type": "qr",
+                      "expiry": "Valid until {{{{expiry_date}}}}", "cut": {} }}
+                ]}}]}}
+            }}"#,
+                cut
+            ))
+            .unwrap()
+        };
+        let data = ReceiptData {
+            order_id: "1001".to_string(),
+            custom: HashMap::from([("expiry_date".to_string(), "2026-12-31".into())]),
+            ..Default::default()
+        };
+        let renderer = TemplateRenderer::new(32);
+
+        let commands = renderer.render_template(&coupon(false), &data).unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec![
+                "8< - - - - - - - - - - - -",
+                "+------------------------+",
+                "|        10% OFF         |",
+                "|    your next visit     |",
+                "+------------------------+",
+                "Valid until 2026-12-31",
+            ]
+        );
+        assert!(commands.iter().any(|c| matches!(
+            c,
+            PrintCommand::QRCode { content, .. } if content == "SAVE1001"
+        )));
+        let cuts = commands.iter().filter(|c| **c == PrintCommand::Cut);
+        assert_eq!(cuts.count(), 1);
+
+        // A cut takes the tear line's place
+        let commands = renderer.render_template(&coupon(true), &data).unwrap();
+        assert_eq!(text_lines(&commands)[0], "+------------------------+");
+        let cut = commands
+            .iter()
+            .position(|c| *c == PrintCommand::Cut)
+            .unwrap();
+        assert_eq!(commands[cut - 1], PrintCommand::Feed(6));
+        let cuts = commands.iter().filter(|c| **c == PrintCommand::Cut);
+        assert_eq!(cuts.count(), 2);
+    }
+
+    #[test]
+    fn test_coupon_fits_wide_characters_on_narrow_paper() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 11,
+            "layout": { "sections": [{ "type": "footer", "elements": [
+                { "type": "coupon", "content": "半額" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let commands = TemplateRenderer::new(11)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+        // Each character is wider than the one column inside the border
+        assert_eq!(
+            text_lines(&commands),
+            vec!["8< -", "+---+", "| 半 |", "| 額 |", "+---+"]
+        );
+    }
+
+    #[test]
+    fn test_copies_print_with_their_labels() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "copies": ["CUSTOMER COPY", "MERCHANT COPY"],
+            "layout": { "sections": [{ "type": "header", "elements": [
+                { "type": "text", "content": "Order {{order_id}}" },
+                { "type": "text", "content": "{{copy_label}}", "condition": "copy_label" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let mut data = ReceiptData {
+            order_id: "1001".to_string(),
+            ..Default::default()
+        };
+        let renderer = TemplateRenderer::new(32);
+
+        let commands = renderer.render_template(&template, &data).unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec!["Order 1001", "CUSTOMER COPY", "Order 1001", "MERCHANT COPY"]
+        );
+        let cuts = commands.iter().filter(|c| **c == PrintCommand::Cut);
+        assert_eq!(cuts.count(), 2);
+
+        // The request's copies replace the template's
+        data.copies = Some(vec!["REPRINT".to_string()]);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        assert_eq!(text_lines(&commands), vec!["Order 1001", "REPRINT"]);
+    }
+
+    #[test]
+    fn test_refund_receipt_branches_on_its_type() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [{ "type": "header", "elements": [
+                { "type": "text", "content": "REFUND", "condition": "receipt_type == 'refund'" },
+                { "type": "text", "content": "Original order {{original_order_id}}",
+                  "condition": "original_order_id" },
+                { "type": "table", "data_source": "items", "show_header": false, "columns": [
+                    { "field": "name", "width": 20 },
+                    { "field": "total", "width": 12, "align": "right", "format": "currency" }
+                ]},
+                { "type": "text", "content": "Total {{total | currency}}" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let mut data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1002", "timestamp": "",
+            "receipt_type": "refund", "original_order_id": "1001",
+            "items": [{ "name": "Latte", "quantity": 1, "price": -4.5, "total": -4.5 }],
+            "subtotal": -4.5, "tax": 0, "total": -4.5, "payment_method": "card"
+        }))
+        .unwrap();
+        let renderer = TemplateRenderer::new(32);
+
+        let commands = renderer.render_template(&template, &data).unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec![
+                "REFUND",
+                "Original order 1001",
+                "Latte               -$4.50",
+                "Total -$4.50"
+            ]
+        );
+
+        data.receipt_type = ReceiptType::Sale;
+        data.original_order_id = None;
+        let commands = renderer.render_template(&template, &data).unwrap();
+        assert_eq!(text_lines(&commands)[0], "Latte               -$4.50");
+    }
+
+    #[test]
+    fn test_gift_receipt_leaves_out_prices() {
+        let mut template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [
+                { "type": "header", "elements": [
+                    { "type": "text", "content": "Order {{order_id}}" },
+                    { "type": "text", "content": "Total {{total | currency}}" }
+                ]},
+                { "type": "footer", "elements": [{ "type": "text", "content": "Thanks" }] }
+            ]}
+        }"#,
+        )
+        .unwrap();
+        let mut data = ReceiptData {
+            order_id: "1001".to_string(),
+            total: 12.5,
+            ..Default::default()
+        };
+        let renderer = TemplateRenderer::new(32);
+        let has_barcode = |commands: &[PrintCommand]| {
+            commands
+                .iter()
+                .any(|c| matches!(c, PrintCommand::Barcode { content, .. } if content == "1001"))
+        };
+
+        let commands = renderer.render_template(&template, &data).unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec!["Order 1001", "Total $12.50", "Thanks"]
+        );
+        assert!(!has_barcode(&commands));
+
+        data.gift_receipt = Some(true);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            vec!["Order 1001", "GIFT RECEIPT", "Thanks"]
+        );
+        assert!(has_barcode(&commands));
+
+        // The request's choice wins over the template's
+        template.gift_receipt = Some(GiftReceipt {
+            enabled: true,
+            ..Default::default()
+        });
+        data.gift_receipt = None;
+        let commands = renderer.render_template(&template, &data).unwrap();
+        assert!(has_barcode(&commands));
+        data.gift_receipt = Some(false);
+        let commands = renderer.render_template(&template, &data).unwrap();
+        assert!(!has_barcode(&commands));
+    }
+
+    #[test]
+    fn test_else_elements_print_when_the_condition_fails() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [
+                { "type": "totals", "elements": [
+                    { "type": "text", "content": "Change due", "condition": "change > 0",
+                      "else_elements": [
+                        { "type": "text", "content": "Paid by card",
+                          "condition": "payment_method == 'card'",
+                          "else_elements": [{ "type": "text", "content": "Exact payment" }] }
+                      ] }
+                ]},
+                { "type": "footer", "condition": "member", "elements": [
+                    { "type": "text", "content": "Welcome back" }
+                ], "else_elements": [
+                    { "type": "text", "content": "Join our loyalty club" }
+                ]}
+            ]}
+        }"#,
+        )
+        .unwrap();
+        let renderer = TemplateRenderer::new(32);
+        let lines = |change: Option<f64>, payment_method: &str, member: bool| {
+            let data = ReceiptData {
+                change,
+                payment_method: payment_method.to_string(),
+                custom: HashMap::from([("member".to_string(), member.into())]),
+                ..Default::default()
+            };
+            let commands = renderer.render_template(&template, &data).unwrap();
+            text_lines(&commands)
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            lines(Some(2.5), "cash", true),
+            ["Change due", "Welcome back"]
+        );
+        assert_eq!(
+            lines(None, "card", false),
+            ["Paid by card", "Join our loyalty club"]
+        );
+        assert_eq!(
+            lines(None, "cash", false),
+            ["Exact payment", "Join our loyalty club"]
+        );
+    }
+
+    #[test]
+    fn test_switch_prints_the_matching_case() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [{ "type": "footer", "elements": [
+                { "type": "switch", "on": "payment_method",
+                  "cases": {
+                    "card": [{ "type": "text", "content": "Card ending {{card_last4}}" }],
+                    "cash": [
+                        { "type": "text", "content": "Cash" },
+                        { "type": "text", "content": "Change due", "condition": "change > 0" }
+                    ]
+                  },
+                  "default": [{ "type": "text", "content": "Paid by {{payment_method}}" }] }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let renderer = TemplateRenderer::new(32);
+        let lines = |payment_method: &str| {
+            let data = ReceiptData {
+                payment_method: payment_method.to_string(),
+                change: Some(1.5),
+                custom: HashMap::from([("card_last4".to_string(), "4242".into())]),
+                ..Default::default()
+            };
+            let commands = renderer.render_template(&template, &data).unwrap();
+            text_lines(&commands)
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(lines("Card"), ["Card ending 4242"]);
+        assert_eq!(lines("cash"), ["Cash", "Change due"]);
+        assert_eq!(lines("voucher"), ["Paid by voucher"]);
+    }
+
+    #[test]
+    fn test_named_styles_fill_in_unset_settings() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "styles": { "heading": { "bold": true, "font_size": 2, "font_width": 2, "align": "center" } },
+            "layout": { "sections": [{ "type": "header", "elements": [
+                { "type": "text", "content": "Shop", "style": "heading" },
+                { "type": "text", "content": "Receipt", "style": "heading", "align": "right" },
+                { "type": "row", "left": "Total", "right": "9", "style": "heading" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let commands = TemplateRenderer::new(32)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+        let before = |line: &str| {
+            let at = commands
+                .iter()
+                .position(|c| matches!(c, PrintCommand::WriteLine(l) if l.starts_with(line)))
+                .unwrap();
+            commands[at.saturating_sub(3)..at].to_vec()
+        };
+
+        assert_eq!(
+            before("Shop"),
+            [
+                PrintCommand::Bold(true),
+                PrintCommand::Size(2, 2),
+                PrintCommand::Align("center".to_string()),
+            ]
+        );
+        // The element's own `align` wins
+        let align = PrintCommand::Align("right".to_string());
+        assert_eq!(before("Receipt")[2], align);
+        assert!(before("Total").contains(&PrintCommand::Bold(true)));
+    }
+
+    #[test]
+    fn test_tax_summary_lists_each_rate() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 38,
+            "layout": { "sections": [{ "type": "totals", "elements": [
+                { "type": "tax_summary", "prefix": "€" }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data: ReceiptData = serde_json::from_value(serde_json::json!({
+            "order_id": "1", "timestamp": "",
+            "taxes": [
+                { "name": "VAT", "rate": 21, "base": 100, "amount": 21 },
+                { "name": "VAT", "rate": 5.5, "base": 20, "amount": 1.1 }
+            ]
+        }))
+        .unwrap();
+        let commands = TemplateRenderer::new(38)
+            .render_template(&template, &data)
+            .unwrap();
+
+        assert_eq!(
+            text_lines(&commands),
+            [
+                "Rate                Base     Tax",
+                "VAT 21%          €100.00  €21.00",
+                "VAT 5.5%          €20.00   €1.10",
+                "Total            €120.00  €22.10",
+            ]
+        );
+
+        // Without taxes nothing prints
+        let commands = TemplateRenderer::new(38)
+            .render_template(&template, &ReceiptData::default())
+            .unwrap();
+        assert!(text_lines(&commands).is_empty());
+    }
+
+    #[test]
+    fn test_tip_suggestions_add_each_tip_to_the_total() {
+        let template = |base: &str| {
+            load_template(&format!(
+                r#"{{
+                "id": "t", "name": "T", "version": "1", "paper_width": 32,
+                "layout": {{ "sections": [{{ "type": "footer", "elements": [
+                    {{ "type": "tip_suggestions", "base": "{}", "prefix": "$",
+                      "percentages": [15, 20] }}
+                ]}}]}}
+            }}"#,
+                base
+            ))
+            .unwrap()
+        };
+        let data = ReceiptData {
+            subtotal: 40.0,
+            tax: 4.0,
+            total: 44.0,
+            ..Default::default()
+        };
+        let renderer = TemplateRenderer::new(32);
+
+        let pre_tax = template("subtotal");
+        let commands = renderer.render_template(&pre_tax, &data).unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            [
+                "Suggested Tip",
+                "                Tip  Total",
+                "15%           $6.00 $50.00",
+                "20%           $8.00 $52.00",
+            ]
+        );
+        let post_tax = template("total");
+        let commands = renderer.render_template(&post_tax, &data).unwrap();
+        assert_eq!(text_lines(&commands)[2], "15%           $6.60 $50.60");
+    }
+
+    #[test]
+    fn test_tip_suggestions_leave_out_percentages_out_of_range() {
+        let template = load_template(
+            r#"{
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "layout": { "sections": [{ "type": "footer", "elements": [
+                { "type": "tip_suggestions", "prefix": "$",
+                  "percentages": [-10, 15, 150, 1e308] }
+            ]}]}
+        }"#,
+        )
+        .unwrap();
+        let data = ReceiptData {
+            subtotal: 40.0,
+            total: 44.0,
+            ..Default::default()
+        };
+        let commands = TemplateRenderer::new(32)
+            .render_template(&template, &data)
+            .unwrap();
+        assert_eq!(
+            text_lines(&commands),
+            [
+                "Suggested Tip",
+                "                Tip  Total",
+                "15%           $6.00 $50.00",
+            ]
+        );
+
+        let data = ReceiptData {
+            subtotal: f64::MAX,
+            total: f64::MAX,
+            ..Default::default()
+        };
+        let commands = TemplateRenderer::new(32)
+            .render_template(&template, &data)
+            .unwrap();
+        assert!(text_lines(&commands).is_empty());
+    }
 }
@@ -0,0 +1,814 @@
+// src/template_validate.rs
+// Template checks that report every problem with its path, for editors
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::barcode::Symbology;
+use crate::label_template::{LabelElement, LabelSpec};
+use crate::template_compile::{
+    check_conditions, check_layout, computed_in_order, Condition, TextPattern,
+};
+use crate::template_expr::Expr;
+use crate::template_parse::parse_template_json;
+use crate::template_render::{
+    text_width, width_multiplier, BarcodeElement, CouponCode, CouponElement, Element, ReceiptData,
+    ReceiptTemplate, RowElement, TableElement, TemplateKind, TemplateRenderer,
+    TipSuggestionsElement,
+};
+
+/// Paper width checked when neither the caller nor the template sets one
+const DEFAULT_PAPER_WIDTH: u32 = 48;
+
+/// Fields, of the receipt or its items, that kitchen tickets and gift
+/// receipts don't print
+pub(crate) const PRICE_FIELDS: [&str; 10] = [
+    "price",
+    "total",
+    "subtotal",
+    "tax",
+    "tax_rate",
+    "discount",
+    "tip",
+    "service_charge",
+    "service_rate",
+    "change",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The template doesn't load, or part of it can't print as written
+    Error,
+    /// The template prints, but probably not as intended
+    Warning,
+}
+
+/// A problem found by [`validate_template`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Where the problem is, e.g. `layout.sections[1].elements[0].format`.
+    /// Empty when it is with the template as a whole.
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// Check template JSON without caching it and report every problem found:
+/// - errors: a structure that doesn't load (only the first such problem),
+///   conditions that don't parse, unknown barcode formats and fixed
+///   barcode content the format can't encode
+/// - warnings: fields no element has, variables that are neither receipt
+///   fields nor declared in `variables`, placeholders that print as they
+///   are, fixed text cut to fit, prices on kitchen tickets, label elements
+///   off the label, `else_elements` without a condition, styles the
+///   template doesn't define, and the layout checks of [`check_layout`]
+///
+/// Widths are checked for `paper_width` characters per line, or the
+/// template's own `paper_width`, or 48.
+pub fn validate_template(json: &Value, paper_width: Option<u32>) -> Vec<Diagnostic> {
+    let template: ReceiptTemplate = match parse_template_json(&json.to_string()) {
+        Ok(template) => template,
+        Err(error) => {
+            let message = match error.hint {
+                Some(hint) => format!("{}. {}", error.message, hint),
+                None => error.message,
+            };
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                path: error.path,
+                message,
+            }];
+        }
+    };
+
+    let paper_width = paper_width
+        .or(template.paper_width)
+        .unwrap_or(DEFAULT_PAPER_WIDTH);
+    let mut validator = Validator {
+        diagnostics: Vec::new(),
+        known: known_variables(&template),
+        renderer: TemplateRenderer::new(paper_width),
+        paper_width,
+        kitchen: template.kind == Some(TemplateKind::Kitchen),
+        styles: template
+            .styles
+            .iter()
+            .flat_map(|styles| styles.keys().cloned())
+            .collect(),
+    };
+    if template.ticket_per.is_some() && !validator.kitchen {
+        validator.warning(
+            "ticket_per".to_string(),
+            "only kitchen templates are split into tickets; set `\"kind\": \"kitchen\"`"
+                .to_string(),
+        );
+    }
+
+    if let Ok(known) = serde_json::to_value(&template) {
+        validator.unknown_fields(String::new(), json, &known);
+    }
+    if let Some(computed) = &template.computed {
+        validator.check_computed(computed);
+    }
+    for (i, section) in template.layout.sections.iter().enumerate() {
+        let path = format!("layout.sections[{}]", i);
+        validator.check_condition(&path, section.condition.as_deref(), &[]);
+        validator.check_elements(&path, &section.elements, &[]);
+        let otherwise = section.else_elements.as_deref().unwrap_or_default();
+        validator.check_else(&path, section.condition.as_deref(), otherwise, &[]);
+    }
+
+    if let Some(label) = &template.label {
+        validator.check_label(label);
+    } else if template.kind == Some(TemplateKind::Label) {
+        validator.warning(
+            String::new(),
+            "a label template prints nothing without `label`".to_string(),
+        );
+    }
+
+    let mut diagnostics = validator.diagnostics;
+    for issue in check_conditions(&template) {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            path: issue.path,
+            message: format!("`{}` doesn't parse: {}", issue.condition, issue.error),
+        });
+    }
+    for warning in check_layout(&template) {
+        // Each warning starts with the path it is about
+        let (path, message) = warning.split_once(' ').unwrap_or(("", &warning));
+        diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            path: path.to_string(),
+            message: message.to_string(),
+        });
+    }
+    diagnostics
+}
+
+/// Names every placeholder and condition can read: receipt fields, the
+/// template's declared variables, constants and computed values, and `t`
+/// for its translations
+fn known_variables(template: &ReceiptTemplate) -> HashSet<String> {
+    let mut known: HashSet<String> = match serde_json::to_value(ReceiptData::default()) {
+        Ok(Value::Object(fields)) => fields.into_iter().map(|(name, _)| name).collect(),
+        _ => HashSet::new(),
+    };
+    if let Some(variables) = &template.variables {
+        known.extend(variables.keys().cloned());
+    }
+    known.extend(template.constants.iter().flat_map(|c| c.keys().cloned()));
+    known.extend(template.computed.iter().flat_map(|c| c.keys().cloned()));
+    if template.translations.is_some() {
+        known.insert("t".to_string());
+    }
+    known
+}
+
+struct Validator {
+    diagnostics: Vec<Diagnostic>,
+    known: HashSet<String>,
+    renderer: TemplateRenderer,
+    paper_width: u32,
+    /// Prices are reported, see [`TemplateKind::Kitchen`]
+    kitchen: bool,
+    /// Names in the template's `styles`
+    styles: HashSet<String>,
+}
+
+impl Validator {
+    fn error(&mut self, path: String, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            path,
+            message,
+        });
+    }
+
+    fn warning(&mut self, path: String, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            path,
+            message,
+        });
+    }
+
+    /// Fields in `raw` that don't survive loading into `known`, the loaded
+    /// template written back out
+    fn unknown_fields(&mut self, path: String, raw: &Value, known: &Value) {
+        match (raw, known) {
+            (Value::Object(raw), Value::Object(known)) => {
+                for (key, value) in raw {
+                    let path = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    match known.get(key) {
+                        Some(known) => self.unknown_fields(path, value, known),
+                        None => self.warning(path, format!("unknown field `{}` is ignored", key)),
+                    }
+                }
+            }
+            (Value::Array(raw), Value::Array(known)) => {
+                for (i, (value, known)) in raw.iter().zip(known).enumerate() {
+                    self.unknown_fields(format!("{}[{}]", path, i), value, known);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `scope` holds the names a repeat adds, such as `item`
+    fn check_elements(&mut self, parent: &str, elements: &[Element], scope: &[&str]) {
+        self.check_list(&format!("{}.elements", parent), elements, scope);
+    }
+
+    fn check_list(&mut self, list: &str, elements: &[Element], scope: &[&str]) {
+        for (i, element) in elements.iter().enumerate() {
+            self.check_element(&format!("{}[{}]", list, i), element, scope);
+        }
+    }
+
+    fn check_element(&mut self, path: &str, element: &Element, scope: &[&str]) {
+        self.check_condition(path, element.condition(), scope);
+        match element {
+            Element::Text(e) => {
+                self.check_content(&format!("{}.content", path), &e.content, scope);
+                self.check_style(path, &e.style);
+            }
+            Element::Row(e) => {
+                self.check_row(path, e, scope);
+                self.check_style(path, &e.style);
+            }
+            Element::QR(e) => self.check_content(&format!("{}.content", path), &e.content, scope),
+            Element::Image(e) => self.check_content(&format!("{}.source", path), &e.source, scope),
+            Element::Barcode(e) => self.check_barcode(path, e, scope),
+            Element::Table(e) => self.check_table(path, e),
+            Element::Grid(e) => {
+                for (i, item) in e.data.iter().enumerate() {
+                    self.check_content(&format!("{}.data[{}].value", path, i), &item.value, scope);
+                }
+                if self.renderer.grid_column_width(e).unwrap_or(0) == 0 {
+                    self.warning(
+                        format!("{}.columns", path),
+                        format!(
+                            "{} columns don't fit in {} characters",
+                            e.columns, self.paper_width
+                        ),
+                    );
+                }
+            }
+            Element::Box(e) => self.check_elements(path, &e.elements, scope),
+            Element::Coupon(e) => self.check_coupon(path, e, scope),
+            Element::Repeat(e) => {
+                let mut scope = scope.to_vec();
+                scope.extend(["item", "index"]);
+                self.check_elements(path, &e.elements, &scope);
+            }
+            Element::Switch(e) => {
+                self.check_variables(&format!("{}.on", path), vec![e.on.trim()], scope);
+                for (value, elements) in &e.cases {
+                    self.check_list(&format!("{}.cases.{}", path, value), elements, scope);
+                }
+                let default = e.default.as_deref().unwrap_or_default();
+                self.check_list(&format!("{}.default", path), default, scope);
+            }
+            Element::TipSuggestions(e) => self.check_tip_suggestions(path, e),
+            Element::TaxSummary(_) if self.kitchen => self.amounts_on_kitchen_ticket(path),
+            _ => {}
+        }
+        self.check_else(path, element.condition(), element.else_elements(), scope);
+        if let Some(fallback) = element.fallback() {
+            self.check_element(&format!("{}.fallback", path), fallback, scope);
+        }
+    }
+
+    /// `else_elements` only print when there's a condition to fail
+    fn check_else(
+        &mut self,
+        path: &str,
+        condition: Option<&str>,
+        otherwise: &[Element],
+        scope: &[&str],
+    ) {
+        if otherwise.is_empty() {
+            return;
+        }
+        if condition.is_none_or(|condition| condition.trim().is_empty()) {
+            self.warning(
+                format!("{}.else_elements", path),
+                "never printed without a `condition`".to_string(),
+            );
+        }
+        self.check_list(&format!("{}.else_elements", path), otherwise, scope);
+    }
+
+    /// Computed values parse, read known names and don't read themselves
+    fn check_computed(&mut self, computed: &BTreeMap<String, String>) {
+        let ordered: Vec<String> = computed_in_order(computed)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        for (name, expr) in computed {
+            let path = format!("computed.{}", name);
+            match Expr::parse(expr) {
+                Err(error) => self.error(path, format!("`{}` doesn't parse: {}", expr, error)),
+                Ok(_) if !ordered.contains(name) => self.warning(
+                    path,
+                    "reads itself through other computed values, so it is left out".to_string(),
+                ),
+                Ok(parsed) => {
+                    let mut names = Vec::new();
+                    parsed.variables(&mut names);
+                    self.check_variables(&path, names, &[]);
+                }
+            }
+        }
+    }
+
+    /// Variables a condition reads; conditions that don't parse are
+    /// reported by [`check_conditions`]
+    fn check_condition(&mut self, path: &str, condition: Option<&str>, scope: &[&str]) {
+        if let Some(Ok(Condition::Test(expr))) = condition.map(Condition::try_parse) {
+            let mut names = Vec::new();
+            expr.variables(&mut names);
+            self.check_variables(&format!("{}.condition", path), names, scope);
+        }
+    }
+
+    fn check_content(&mut self, path: &str, content: &str, scope: &[&str]) {
+        let mut segments = Vec::new();
+        for (placeholder, parsed) in TextPattern::placeholders(content) {
+            match parsed {
+                Ok(segment) => segments.push(segment),
+                Err(error) => self.warning(
+                    path.to_string(),
+                    format!("{} is printed as it is: {}", placeholder, error),
+                ),
+            }
+        }
+        let mut names = Vec::new();
+        for segment in &segments {
+            segment.variables(&mut names);
+        }
+        self.check_variables(path, names, scope);
+    }
+
+    fn check_variables(&mut self, path: &str, names: Vec<&str>, scope: &[&str]) {
+        let mut reported = HashSet::new();
+        for name in names {
+            if !reported.insert(name) {
+                continue;
+            }
+            let field = name.rsplit('.').next().unwrap_or(name);
+            if self.kitchen && PRICE_FIELDS.contains(&field) {
+                self.warning(
+                    path.to_string(),
+                    format!("`{}` is a price, which kitchen tickets don't show", name),
+                );
+            }
+            let root = name.split('.').next().unwrap_or(name);
+            if self.known.contains(root) || scope.contains(&root) {
+                continue;
+            }
+            self.warning(
+                path.to_string(),
+                format!(
+                    "`{}` is neither a receipt field nor declared in `variables`",
+                    name
+                ),
+            );
+        }
+    }
+
+    fn check_style(&mut self, path: &str, style: &Option<String>) {
+        if let Some(style) = style {
+            if !self.styles.contains(style) {
+                self.warning(
+                    format!("{}.style", path),
+                    format!("`{}` is not in the template's `styles`", style),
+                );
+            }
+        }
+    }
+
+    fn check_row(&mut self, path: &str, row: &RowElement, scope: &[&str]) {
+        for (side, text) in [("left", &row.left), ("right", &row.right)] {
+            if let Some(text) = text {
+                self.check_content(&format!("{}.{}", path, side), text, scope);
+            }
+        }
+
+        // Only fixed text can be measured before printing
+        let left = row.left.as_deref().unwrap_or_default();
+        let right = row.right.as_deref().unwrap_or_default();
+        if !TextPattern::placeholders(left).is_empty()
+            || !TextPattern::placeholders(right).is_empty()
+        {
+            return;
+        }
+        let font_size = row.font_size.unwrap_or(1);
+        let width = self.renderer.effective_width(width_multiplier(font_size));
+        let needed = text_width(left) + 1 + text_width(right);
+        if needed > width {
+            self.warning(
+                path.to_string(),
+                format!(
+                    "needs {} columns but {} fit at font size {}, so the left side is cut",
+                    needed, width, font_size
+                ),
+            );
+        }
+    }
+
+    fn check_barcode(&mut self, path: &str, barcode: &BarcodeElement, scope: &[&str]) {
+        let content_path = format!("{}.content", path);
+        self.check_content(&content_path, &barcode.content, scope);
+
+        let format = barcode.format.as_deref().unwrap_or("CODE128");
+        let Some(symbology) = Symbology::parse(format) else {
+            self.error(
+                format!("{}.format", path),
+                format!(
+                    "unknown barcode format \"{}\"; use CODE128, CODE39, CODE93, EAN13, EAN8, \
+                     UPCA, UPCE, ITF or CODABAR",
+                    format
+                ),
+            );
+            return;
+        };
+        if TextPattern::placeholders(&barcode.content).is_empty() {
+            if let Err(error) = symbology.check(&barcode.content) {
+                self.error(
+                    content_path,
+                    format!("\"{}\" isn't valid {}: {}", barcode.content, format, error),
+                );
+            }
+        }
+    }
+
+    fn check_coupon(&mut self, path: &str, coupon: &CouponElement, scope: &[&str]) {
+        self.check_content(&format!("{}.content", path), &coupon.content, scope);
+        if let Some(expiry) = &coupon.expiry {
+            self.check_content(&format!("{}.expiry", path), expiry, scope);
+        }
+        let Some(code) = &coupon.code else {
+            return;
+        };
+        self.check_content(&format!("{}.code", path), code, scope);
+        let format = coupon.format.as_deref().unwrap_or("CODE128");
+        if coupon.code_type.unwrap_or_default() == CouponCode::Barcode
+            && Symbology::parse(format).is_none()
+        {
+            self.error(
+                format!("{}.format", path),
+                format!("unknown barcode format \"{}\"", format),
+            );
+        }
+    }
+
+    fn check_tip_suggestions(&mut self, path: &str, element: &TipSuggestionsElement) {
+        for (i, percent) in element.percentages.iter().flatten().enumerate() {
+            if !(0.0..=100.0).contains(percent) {
+                self.warning(
+                    format!("{}.percentages[{}]", path, i),
+                    format!("{}% is not from 0 to 100, so it is left out", percent),
+                );
+            }
+        }
+        if self.kitchen {
+            self.amounts_on_kitchen_ticket(path);
+        }
+    }
+
+    fn amounts_on_kitchen_ticket(&mut self, path: &str) {
+        self.warning(
+            path.to_string(),
+            "prints amounts, which kitchen tickets don't show".to_string(),
+        );
+    }
+
+    fn check_label(&mut self, label: &LabelSpec) {
+        for (i, element) in label.elements.iter().enumerate() {
+            let path = format!("label.elements[{}]", i);
+            match element {
+                LabelElement::Text { content, .. }
+                | LabelElement::Barcode { content, .. }
+                | LabelElement::Qr { content, .. } => {
+                    self.check_content(&format!("{}.content", path), content, &[]);
+                }
+                LabelElement::Box { .. } => {}
+            }
+            let (x, y) = element.origin();
+            if x >= label.width || y >= label.height {
+                self.warning(
+                    path,
+                    format!(
+                        "starts at {},{}, outside the {} by {} dot label",
+                        x, y, label.width, label.height
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_table(&mut self, path: &str, table: &TableElement) {
+        if self.kitchen {
+            for (i, column) in table.columns.iter().enumerate() {
+                if column.format.as_deref() == Some("currency")
+                    || PRICE_FIELDS.contains(&column.field.as_str())
+                {
+                    self.warning(
+                        format!("{}.columns[{}]", path, i),
+                        format!(
+                            "\"{}\" is a price, which kitchen tickets don't show",
+                            column.field
+                        ),
+                    );
+                }
+            }
+        }
+
+        let widths = self.renderer.table_column_widths(&table.columns);
+        let needed = widths.iter().sum::<usize>() + widths.len().saturating_sub(1);
+        let available = self.renderer.content_width();
+        if needed > available {
+            self.warning(
+                format!("{}.columns", path),
+                format!(
+                    "{} columns need {} characters but {} fit",
+                    widths.len(),
+                    needed,
+                    available
+                ),
+            );
+        }
+
+        let footers = table.footer_rows.iter().flatten().enumerate();
+        let footers = footers
+            .map(|(i, footer)| (format!("{}.footer_rows[{}]", path, i), footer))
+            .chain(
+                table
+                    .group_subtotal
+                    .iter()
+                    .map(|footer| (format!("{}.group_subtotal", path), footer)),
+            );
+        for (footer_path, footer) in footers {
+            let mut fields: Vec<&String> = footer.values.keys().collect();
+            fields.sort();
+            for field in fields {
+                if !table.columns.iter().any(|column| &column.field == field) {
+                    self.warning(
+                        format!("{}.values.{}", footer_path, field),
+                        format!("no column shows \"{}\", so it isn't printed", field),
+                    );
+                }
+            }
+        }
+
+        if !table.show_header.unwrap_or(false) {
+            return;
+        }
+        for (i, (column, &width)) in table.columns.iter().zip(&widths).enumerate() {
+            let header = column.header.as_deref().unwrap_or(&column.field);
+            if text_width(header) > width && !column.wrap.unwrap_or(false) {
+                self.warning(
+                    format!("{}.columns[{}].header", path, i),
+                    format!(
+                        "\"{}\" is cut to {} columns on {}-character paper",
+                        header, width, self.paper_width
+                    ),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(template: Value) -> Vec<String> {
+        validate_template(&template, None)
+            .iter()
+            .map(|d| format!("{:?} {}", d.severity, d))
+            .collect()
+    }
+
+    #[test]
+    fn test_reports_problems_with_paths() {
+        let diagnostics = validate(serde_json::json!({
+            "id": "t", "name": "T", "version": "1", "paper_width": 32,
+            "variables": { "points": { "type": "number" } },
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "{{store_name}} {{points}} {{totl}}", "colour": "red" },
+                { "type": "text", "content": "{{total +}}", "condition": "vip == true" },
+                { "type": "barcode", "content": "12345", "format": "EAN13" },
+                { "type": "barcode", "content": "{{order_id}}", "format": "PDF417" },
+                { "type": "row", "left": "A very long fixed label", "right": "$100.00" },
+                { "type": "repeat", "data_source": "items", "elements": [
+                    { "type": "text", "content": "{{index}}. {{item.name}}" }
+                ]},
+                { "type": "table", "data_source": "items", "show_header": true, "columns": [
+                    { "header": "Description", "field": "name", "width": 1 },
+                    { "header": "Qty", "field": "quantity", "width": 9 }
+                ], "footer_rows": [{ "label": "Total", "values": { "total": "sum" } }]}
+            ]}]}
+        }));
+        let element = "Warning layout.sections[0].elements";
+        assert_eq!(
+            diagnostics,
+            vec![
+                format!("{}[0].colour: unknown field `colour` is ignored", element),
+                format!(
+                    "{}[0].content: `totl` is neither a receipt field nor declared in \
+                     `variables`",
+                    element
+                ),
+                format!(
+                    "{}[1].condition: `vip` is neither a receipt field nor declared in \
+                     `variables`",
+                    element
+                ),
+                format!(
+                    "{}[1].content: {{{{total +}}}} is printed as it is: a value is \
+                     missing at the end",
+                    element
+                ),
+                "Error layout.sections[0].elements[2].content: \"12345\" isn't valid EAN13: \
+                 needs 12 digits, or 13 with the check digit, not 5"
+                    .to_string(),
+                "Error layout.sections[0].elements[3].format: unknown barcode format \
+                 \"PDF417\"; use CODE128, CODE39, CODE93, EAN13, EAN8, UPCA, UPCE, ITF or \
+                 CODABAR"
+                    .to_string(),
+                format!(
+                    "{}[4]: needs 31 columns but 26 fit at font size 1, so the left side is cut",
+                    element
+                ),
+                format!(
+                    "{}[6].footer_rows[0].values.total: no column shows \"total\", so it \
+                     isn't printed",
+                    element
+                ),
+                format!(
+                    "{}[6].columns[0].header: \"Description\" is cut to 3 columns on \
+                     32-character paper",
+                    element
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kitchen_templates_report_prices() {
+        let diagnostics = validate(serde_json::json!({
+            "id": "t", "name": "T", "version": "1", "kind": "kitchen",
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "Table {{table_number}} {{total}}" },
+                { "type": "kitchen_items" },
+                { "type": "table", "data_source": "items", "columns": [
+                    { "field": "name", "width": 30 },
+                    { "field": "price", "width": 10, "format": "currency" }
+                ]},
+                { "type": "tax_summary" }
+            ]}]}
+        }));
+        assert_eq!(
+            diagnostics,
+            vec![
+                "Warning layout.sections[0].elements[0].content: `total` is a price, which \
+                 kitchen tickets don't show",
+                "Warning layout.sections[0].elements[2].columns[1]: \"price\" is a price, \
+                 which kitchen tickets don't show",
+                "Warning layout.sections[0].elements[3]: prints amounts, which kitchen \
+                 tickets don't show",
+            ]
+        );
+
+        let receipt = validate(serde_json::json!({
+            "id": "t", "name": "T", "version": "1", "ticket_per": "course",
+            "layout": { "sections": [] }
+        }));
+        assert_eq!(
+            receipt,
+            vec![
+                "Warning ticket_per: only kitchen templates are split into tickets; set \
+                 `\"kind\": \"kitchen\"`"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_styles_are_reported() {
+        let diagnostics = validate(serde_json::json!({
+            "id": "t", "name": "T", "version": "1",
+            "styles": { "heading": { "bold": true } },
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "A", "style": "heading" },
+                { "type": "row", "left": "B", "style": "title" }
+            ]}]}
+        }));
+        assert_eq!(
+            diagnostics,
+            vec![
+                "Warning layout.sections[0].elements[1].style: `title` is not in the \
+                 template's `styles`"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_else_elements_are_checked() {
+        let diagnostics = validate(serde_json::json!({
+            "id": "t", "name": "T", "version": "1",
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "A", "else_elements": [
+                    { "type": "text", "content": "{{order_id}}" }
+                ]},
+                { "type": "text", "content": "B", "condition": "change > 0", "else_elements": [
+                    { "type": "text", "content": "C", "condition": "total >" }
+                ]}
+            ]}]}
+        }));
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0],
+            "Warning layout.sections[0].elements[0].else_elements: never printed without a \
+             `condition`"
+        );
+        assert!(diagnostics[1]
+            .starts_with("Error layout.sections[0].elements[1].else_elements[0].condition: "));
+    }
+
+    #[test]
+    fn test_computed_values_are_checked() {
+        let diagnostics = validate(serde_json::json!({
+            "id": "t", "name": "T", "version": "1",
+            "constants": { "vat_rate": 21 },
+            "computed": {
+                "a": "b + 1", "b": "a + 1",
+                "broken": "total +",
+                "vat": "total * vat_rate / 100"
+            },
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "VAT {{vat}} at {{vat_rate}}%" }
+            ]}]}
+        }));
+        assert_eq!(diagnostics.len(), 3, "{:?}", diagnostics);
+        assert_eq!(
+            diagnostics[0],
+            "Warning computed.a: reads itself through other computed values, so it is left out"
+        );
+        assert!(diagnostics[2].starts_with("Error computed.broken: "));
+    }
+
+    #[test]
+    fn test_tip_percentages_out_of_range_are_reported() {
+        let diagnostics = validate(serde_json::json!({
+            "id": "t", "name": "T", "version": "1",
+            "layout": { "sections": [{ "type": "footer", "elements": [
+                { "type": "tip_suggestions", "percentages": [-5, 18, 150] }
+            ]}]}
+        }));
+        assert_eq!(
+            diagnostics,
+            [
+                "Warning layout.sections[0].elements[0].percentages[0]: -5% is not from 0 to \
+                 100, so it is left out",
+                "Warning layout.sections[0].elements[0].percentages[2]: 150% is not from 0 to \
+                 100, so it is left out",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_structure_errors_stop_the_checks() {
+        let diagnostics = validate(serde_json::json!({
+            "id": "t", "name": "T", "version": "1",
+            "layout": { "sections": [{ "type": "body", "elements": [
+                { "type": "text", "content": "Hi", "font_size": "big" }
+            ]}]}
+        }));
+        assert_eq!(diagnostics.len(), 1);
+        assert!(
+            diagnostics[0].starts_with("Error layout.sections[0].elements[0].font_size: "),
+            "{}",
+            diagnostics[0]
+        );
+    }
+}
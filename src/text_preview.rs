@@ -0,0 +1,325 @@
+// src/text_preview.rs
+// Print jobs as plain text, for trying templates out without paper
+
+use crate::template_render::{char_width, truncate_columns, width_multiplier, Font, PrintCommand};
+
+/// Lay rendered print commands out as text, one character per column of
+/// `paper_width`, framed at the edges of the paper.
+///
+/// Double-width text is spread over its columns and reversed spaces are
+/// drawn as `█`, so inverted headers and bar charts line up as printed.
+/// Styles text can't show are listed to the right of the frame on each line
+/// that uses them. Logos, QR codes and barcodes appear as a note in
+/// brackets, and each cut closes the frame.
+pub fn render_text(commands: &[PrintCommand], paper_width: u32) -> String {
+    let mut preview = Preview::new(paper_width as usize);
+    for command in commands {
+        preview.command(command);
+    }
+    preview.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    underline: bool,
+    reverse: bool,
+    double_strike: bool,
+    font: Font,
+    width: usize,
+    height: usize,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            bold: false,
+            underline: false,
+            reverse: false,
+            double_strike: false,
+            font: Font::A,
+            width: 1,
+            height: 1,
+        }
+    }
+}
+
+impl Style {
+    /// What to note beside a line printed in this style
+    fn markers(&self) -> Vec<String> {
+        let mut markers = Vec::new();
+        for (on, marker) in [
+            (self.bold, "bold"),
+            (self.underline, "underline"),
+            (self.reverse, "reversed"),
+            (self.double_strike, "double-strike"),
+            (self.font == Font::B, "font B"),
+            (self.font == Font::C, "font C"),
+        ] {
+            if on {
+                markers.push(marker.to_string());
+            }
+        }
+        if (self.width, self.height) != (1, 1) {
+            markers.push(format!("size {}x{}", self.width, self.height));
+        }
+        markers
+    }
+}
+
+#[derive(Debug)]
+struct Preview {
+    /// Columns inside the frame
+    width: usize,
+    out: String,
+    /// Whether the frame has been opened since the last cut
+    open: bool,
+    /// Text waiting for the end of its line, and the columns it takes
+    line: String,
+    line_width: usize,
+    /// Styles used on the waiting line, in the order they appeared
+    markers: Vec<String>,
+    style: Style,
+    align: String,
+}
+
+impl Preview {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            out: String::new(),
+            open: false,
+            line: String::new(),
+            line_width: 0,
+            markers: Vec::new(),
+            style: Style::default(),
+            align: "left".to_string(),
+        }
+    }
+
+    fn command(&mut self, command: &PrintCommand) {
+        match command {
+            PrintCommand::Init => {
+                self.style = Style::default();
+                self.align = "left".to_string();
+            }
+            PrintCommand::Write(text) => self.text(text),
+            PrintCommand::WriteLine(text) => {
+                self.text(text);
+                self.newline();
+            }
+            PrintCommand::Feed(lines) => {
+                for _ in 0..*lines {
+                    self.newline();
+                }
+            }
+            PrintCommand::Cut => self.cut(),
+            PrintCommand::Bold(on) => self.style.bold = *on,
+            PrintCommand::Underline(on) => self.style.underline = *on,
+            PrintCommand::Reverse(on) => self.style.reverse = *on,
+            PrintCommand::DoubleStrike(on) => self.style.double_strike = *on,
+            PrintCommand::Font(font) => self.style.font = *font,
+            PrintCommand::Size(width, height) => {
+                self.style.width = width_multiplier(*width);
+                self.style.height = width_multiplier(*height);
+            }
+            PrintCommand::Align(align) => self.align = align.to_lowercase(),
+            PrintCommand::QRCode { content, .. } => self.note(&format!("[QR code: {}]", content)),
+            PrintCommand::Barcode {
+                content, format, ..
+            } => self.note(&format!("[{} barcode: {}]", format, content)),
+            PrintCommand::Image(bytes) => match image_size(bytes) {
+                Some((width, height)) => self.note(&format!("[image {}x{} dots]", width, height)),
+                None => self.note("[image]"),
+            },
+            PrintCommand::NvLogo(slot) => self.note(&format!("[NV logo {}]", slot)),
+            // Nothing to show on paper
+            PrintCommand::Buzzer { .. }
+            | PrintCommand::UpsideDown(_)
+            | PrintCommand::PrintArea { .. } => {}
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let mut lines = text.split('\n');
+        if let Some(first) = lines.next() {
+            self.append(first);
+        }
+        for line in lines {
+            self.newline();
+            self.append(line);
+        }
+    }
+
+    fn append(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        for marker in self.style.markers() {
+            if !self.markers.contains(&marker) {
+                self.markers.push(marker);
+            }
+        }
+        let blank = if self.style.reverse { '█' } else { ' ' };
+        for c in text.chars() {
+            self.line.push(if c == ' ' { blank } else { c });
+            // Wide text takes `width` times the columns; the rest are blank
+            let columns = char_width(c) * self.style.width;
+            for _ in char_width(c)..columns {
+                self.line.push(blank);
+            }
+            self.line_width += columns;
+        }
+    }
+
+    fn newline(&mut self) {
+        let spare = self.width.saturating_sub(self.line_width);
+        let before = match self.align.as_str() {
+            "center" => spare / 2,
+            "right" => spare,
+            _ => 0,
+        };
+        let mut row = format!(
+            "|{}{}{}|",
+            " ".repeat(before),
+            self.line,
+            " ".repeat(spare - before)
+        );
+        if !self.markers.is_empty() {
+            row.push_str("  ");
+            row.push_str(&self.markers.join(", "));
+        }
+        self.line.clear();
+        self.line_width = 0;
+        self.markers.clear();
+
+        if !self.open {
+            self.border("");
+            self.open = true;
+        }
+        self.out.push_str(&row);
+        self.out.push('\n');
+    }
+
+    /// Print any text still waiting, as a printer does before a cut or an
+    /// image
+    fn flush_line(&mut self) {
+        if !self.line.is_empty() {
+            self.newline();
+        }
+    }
+
+    /// A line of its own for something text can't show
+    fn note(&mut self, note: &str) {
+        self.flush_line();
+        let (note, width) = truncate_columns(note, self.width);
+        self.line.push_str(note);
+        self.line_width = width;
+        self.newline();
+    }
+
+    fn border(&mut self, label: &str) {
+        self.out.push('+');
+        self.out.push_str(&"-".repeat(self.width));
+        self.out.push('+');
+        self.out.push_str(label);
+        self.out.push('\n');
+    }
+
+    fn cut(&mut self) {
+        self.flush_line();
+        if self.open {
+            self.border("  cut");
+            self.open = false;
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush_line();
+        if self.open {
+            self.border("");
+        }
+        self.out
+    }
+}
+
+/// Width and height in dots of the `GS v 0` rasters in an encoded image,
+/// which may be printed in several bands
+fn image_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut size: Option<(u32, u32)> = None;
+    let mut rest = bytes;
+    while let Some(start) = rest.windows(3).position(|w| w == [0x1D, 0x76, 0x30]) {
+        let Some(&[xl, xh, yl, yh]) = rest.get(start + 4..start + 8) else {
+            break;
+        };
+        let bytes_per_row = u16::from_le_bytes([xl, xh]) as u32;
+        let rows = u16::from_le_bytes([yl, yh]) as u32;
+        let (width, height) = size.unwrap_or_default();
+        size = Some((width.max(bytes_per_row * 8), height + rows));
+        let len = start + 8 + (bytes_per_row * rows) as usize;
+        rest = &rest[len.min(rest.len())..];
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_become_framed_text() {
+        let commands = [
+            PrintCommand::Init,
+            PrintCommand::Align("center".to_string()),
+            PrintCommand::Bold(true),
+            PrintCommand::Size(2, 2),
+            PrintCommand::WriteLine("CAFÉ".to_string()),
+            PrintCommand::Size(1, 1),
+            PrintCommand::Bold(false),
+            PrintCommand::Align("left".to_string()),
+            PrintCommand::WriteLine("Latte      4.50".to_string()),
+            PrintCommand::Reverse(true),
+            PrintCommand::Write("   ".to_string()),
+            PrintCommand::Reverse(false),
+            PrintCommand::WriteLine(" 3".to_string()),
+            PrintCommand::QRCode {
+                content: "https://example.com/r/1001".to_string(),
+                size: 6,
+                error_correction: Default::default(),
+            },
+            PrintCommand::Feed(1),
+            PrintCommand::Cut,
+            PrintCommand::Buzzer {
+                times: 1,
+                duration_ms: 200,
+            },
+        ];
+
+        assert_eq!(
+            render_text(&commands, 16),
+            [
+                "+----------------+",
+                "|    C A F É     |  bold, size 2x2",
+                "|Latte      4.50 |",
+                "|███ 3           |  reversed",
+                "|[QR code: https:|",
+                "|                |",
+                "+----------------+  cut",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_image_size_adds_up_bands() {
+        let band = |rows: u8| {
+            let mut bytes = vec![0x1D, 0x76, 0x30, 0x00, 48, 0, rows, 0];
+            bytes.extend(vec![0u8; 48 * rows as usize]);
+            bytes
+        };
+        let bytes = [band(24), vec![0x1B, 0x4A, 0x00], band(8)].concat();
+        assert_eq!(image_size(&bytes), Some((384, 32)));
+        assert_eq!(image_size(b"text"), None);
+    }
+}
@@ -0,0 +1,297 @@
+// src/usb.rs
+// Printer-class USB devices written over libusb bulk transfers
+
+use std::io::Write;
+use std::time::Duration;
+
+use rusb::{Direction, GlobalContext, TransferType};
+
+use crate::backend::{PrintBackend, PrinterState};
+use crate::config::Timeouts;
+use crate::realtime_status::{self, StatusLink};
+
+/// USB interface class of printers (USB Printer Class 1.1)
+const PRINTER_CLASS: u8 = 0x07;
+
+/// How long to wait when reading a device's name during a scan
+const DESCRIPTOR_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// `usb:VID:PID` with both ids in hex, e.g. `usb:04b8:0e15`
+pub fn parse_device_path(device_path: &str) -> Result<(u16, u16), String> {
+    let invalid = || {
+        format!(
+            "Invalid USB printer: {} (expected usb:VID:PID, e.g. usb:04b8:0e15)",
+            device_path
+        )
+    };
+    let ids = device_path.strip_prefix("usb:").ok_or_else(invalid)?;
+    let (vendor, product) = ids.split_once(':').ok_or_else(invalid)?;
+    let vendor_id = u16::from_str_radix(vendor, 16).map_err(|_| invalid())?;
+    let product_id = u16::from_str_radix(product, 16).map_err(|_| invalid())?;
+    Ok((vendor_id, product_id))
+}
+
+// ==================== Backend ====================
+
+/// A printer that enumerates as a USB printer-class device rather than a
+/// serial port, written directly through its bulk OUT endpoint.
+///
+/// The interface is claimed for each job and released afterwards, so the
+/// printer stays free for other programs between jobs. On Linux the `usblp`
+/// kernel driver is detached while a job prints. On Windows the device needs
+/// a WinUSB driver (e.g. installed with Zadig).
+#[derive(Debug, Clone)]
+pub struct UsbBackend {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// Bulk transfers give up after the write timeout
+    pub timeouts: Timeouts,
+}
+
+impl UsbBackend {
+    pub fn new(device_path: &str) -> Result<Self, String> {
+        let (vendor_id, product_id) = parse_device_path(device_path)?;
+        Ok(Self {
+            vendor_id,
+            product_id,
+            timeouts: Timeouts::default(),
+        })
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    fn name(&self) -> String {
+        format!("usb:{:04x}:{:04x}", self.vendor_id, self.product_id)
+    }
+
+    /// Find the device, claim its printer interface and find where to write
+    fn open_port(&self) -> Result<UsbPort, String> {
+        let devices = rusb::devices().map_err(|e| format!("Cannot list USB devices: {}", e))?;
+        let device = devices
+            .iter()
+            .find(|device| {
+                device.device_descriptor().is_ok_and(|descriptor| {
+                    descriptor.vendor_id() == self.vendor_id
+                        && descriptor.product_id() == self.product_id
+                })
+            })
+            .ok_or_else(|| format!("USB printer {} is not connected", self.name()))?;
+
+        let endpoint = printer_endpoint(&device).ok_or_else(|| {
+            format!(
+                "USB device {} has no printer interface with a bulk OUT endpoint",
+                self.name()
+            )
+        })?;
+
+        let handle = device
+            .open()
+            .map_err(|e| format!("Cannot open USB printer {}: {}", self.name(), e))?;
+        // Unsupported off Linux, where there is no kernel driver to detach
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        handle
+            .claim_interface(endpoint.interface)
+            .map_err(|e| format!("Cannot claim USB printer {}: {}", self.name(), e))?;
+        if endpoint.setting != 0 {
+            handle
+                .set_alternate_setting(endpoint.interface, endpoint.setting)
+                .map_err(|e| format!("Cannot configure USB printer {}: {}", self.name(), e))?;
+        }
+
+        Ok(UsbPort {
+            handle,
+            interface: endpoint.interface,
+            address: endpoint.address,
+            status_address: endpoint.status_address,
+            timeout: self.timeouts.write(),
+        })
+    }
+}
+
+impl PrintBackend for UsbBackend {
+    fn open(&self) -> Result<Option<Box<dyn Write + Send>>, String> {
+        Ok(Some(Box::new(self.open_port()?)))
+    }
+
+    fn write_all(&self, bytes: &[u8]) -> Result<(), String> {
+        let mut port = self.open_port()?;
+        port
+            .write_all(bytes)
+            .map_err(|e| format!("Failed to write to {}: {}", self.name(), e))
+    }
+
+    fn query_status(&self) -> Result<(), String> {
+        self.open_port().map(|_| ())
+    }
+
+    /// Real-time status (DLE EOT), read back through the bulk IN endpoint.
+    /// A job holds the interface while it prints, so this fails then.
+    fn printer_state(&self) -> Result<Option<PrinterState>, String> {
+        let mut port = self.open_port()?;
+        if port.status_address.is_none() {
+            return Ok(None);
+        }
+        realtime_status::query(&mut port)
+            .map_err(|e| format!("Failed to read status from {}: {}", self.name(), e))
+    }
+}
+
+/// Where a printer interface takes data
+struct PrinterEndpoint {
+    interface: u8,
+    setting: u8,
+    address: u8,
+    /// Bulk IN, for printers that answer status requests
+    status_address: Option<u8>,
+}
+
+/// The first printer-class interface with a bulk OUT endpoint
+fn printer_endpoint(device: &rusb::Device<GlobalContext>) -> Option<PrinterEndpoint> {
+    let config = device.active_config_descriptor().ok()?;
+    config
+        .interfaces()
+        .flat_map(|interface| interface.descriptors())
+        .filter(|setting| setting.class_code() == PRINTER_CLASS)
+        .find_map(|setting| {
+            setting
+                .endpoint_descriptors()
+                .find(|endpoint| {
+                    endpoint.direction() == Direction::Out
+                        && endpoint.transfer_type() == TransferType::Bulk
+                })
+                .map(|endpoint| PrinterEndpoint {
+                    interface: setting.interface_number(),
+                    setting: setting.setting_number(),
+                    address: endpoint.address(),
+                    status_address: setting
+                        .endpoint_descriptors()
+                        .find(|endpoint| {
+                            endpoint.direction() == Direction::In
+                                && endpoint.transfer_type() == TransferType::Bulk
+                        })
+                        .map(|endpoint| endpoint.address()),
+                })
+        })
+}
+
+/// A claimed printer interface, released on drop
+struct UsbPort {
+    handle: rusb::DeviceHandle<GlobalContext>,
+    interface: u8,
+    address: u8,
+    status_address: Option<u8>,
+    timeout: Duration,
+}
+
+impl Write for UsbPort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.handle
+            .write_bulk(self.address, buf, self.timeout)
+            .map_err(|e| match e {
+                rusb::Error::Timeout => std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "printer is not accepting data",
+                ),
+                rusb::Error::NoDevice => std::io::Error::new(
+                    std::io::ErrorKind::NotConnected,
+                    "printer was unplugged",
+                ),
+                e => std::io::Error::other(e.to_string()),
+            })
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl StatusLink for UsbPort {
+    fn send(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.write_all(bytes)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> std::io::Result<usize> {
+        let Some(address) = self.status_address else {
+            return Ok(0);
+        };
+        match self.handle.read_bulk(address, buf, timeout) {
+            Err(rusb::Error::Timeout) => Ok(0),
+            result => result.map_err(|e| std::io::Error::other(e.to_string())),
+        }
+    }
+}
+
+impl Drop for UsbPort {
+    fn drop(&mut self) {
+        let _ = self.handle.release_interface(self.interface);
+    }
+}
+
+// ==================== Discovery ====================
+
+/// A connected printer-class USB device
+#[derive(Debug, Clone)]
+pub struct UsbPrinter {
+    /// Device path to connect to with the "USB" connection type
+    pub path: String,
+    /// Manufacturer and product name, if the device reports them
+    pub name: String,
+}
+
+/// Every connected device with a printer interface
+pub fn usb_printers() -> Result<Vec<UsbPrinter>, String> {
+    let devices = rusb::devices().map_err(|e| format!("Cannot list USB devices: {}", e))?;
+    let mut printers = Vec::new();
+    for device in devices.iter() {
+        let Ok(descriptor) = device.device_descriptor() else {
+            continue;
+        };
+        if printer_endpoint(&device).is_none() {
+            continue;
+        }
+
+        let path = format!(
+            "usb:{:04x}:{:04x}",
+            descriptor.vendor_id(),
+            descriptor.product_id()
+        );
+        // Reading names needs the device opened, which may not be allowed
+        let name = device
+            .open()
+            .ok()
+            .and_then(|handle| {
+                let language = *handle.read_languages(DESCRIPTOR_TIMEOUT).ok()?.first()?;
+                let product = handle
+                    .read_product_string(language, &descriptor, DESCRIPTOR_TIMEOUT)
+                    .ok()?;
+                let manufacturer = handle
+                    .read_manufacturer_string(language, &descriptor, DESCRIPTOR_TIMEOUT)
+                    .unwrap_or_default();
+                Some(format!("{} {}", manufacturer, product).trim().to_string())
+            })
+            .unwrap_or_else(|| "USB Printer".to_string());
+
+        printers.push(UsbPrinter { path, name });
+    }
+    Ok(printers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_device_path() {
+        assert_eq!(parse_device_path("usb:04b8:0e15").unwrap(), (0x04b8, 0x0e15));
+        assert_eq!(parse_device_path("usb:0416:5011").unwrap(), (0x0416, 0x5011));
+        assert_eq!(
+            parse_device_path("usb:04b8").unwrap_err(),
+            "Invalid USB printer: usb:04b8 (expected usb:VID:PID, e.g. usb:04b8:0e15)"
+        );
+        assert!(parse_device_path("04b8:0e15").is_err());
+        assert!(parse_device_path("usb:xyz:0e15").is_err());
+    }
+}